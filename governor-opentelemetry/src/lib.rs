@@ -0,0 +1,222 @@
+//! # governor-opentelemetry - OpenTelemetry telemetry for governor rate limiters
+//!
+//! This crate provides [`OpenTelemetryMiddleware`], a [rate limiting
+//! middleware](governor::middleware) that reports every decision a
+//! [`governor`] rate limiter makes to [OpenTelemetry](https://opentelemetry.io):
+//!
+//! * It sets [attributes](attribute) describing the decision on the active span, if that span
+//!   is recording. The attributes use the names of the IETF draft for [RateLimit header
+//!   fields](https://datatracker.ietf.org/doc/draft-ietf-httpapi-ratelimit-headers/), so a
+//!   request's span shows the same limit, remaining cells and reset time as the headers in its
+//!   response.
+//! * It counts decisions and the time that denied ones asked callers to wait, which the metrics
+//!   that [`register_metrics`] sets up report whenever the meter provider collects them. The
+//!   limit, remaining cells and reset time differ between rate limiters and keys, so they are
+//!   only reported on spans.
+//!
+//! Recording a decision only updates a few atomic integers; the metrics get exported in batches
+//! by the meter provider's reader, so they don't add any exporting work to the code that checks
+//! the rate limiter.
+//!
+//! # Example
+//! ```rust
+//! # use nonzero_ext::nonzero;
+//! use governor::{Quota, RateLimiter};
+//! use governor_opentelemetry::OpenTelemetryMiddleware;
+//!
+//! governor_opentelemetry::register_metrics(&opentelemetry::global::meter("my-service"));
+//! let lim = RateLimiter::keyed(Quota::per_second(nonzero!(10u32)))
+//!     .with_middleware::<OpenTelemetryMiddleware>();
+//! assert_eq!(Ok(()), lim.check_key(&"alice"));
+//! ```
+
+// Clippy config: Deny warnings but allow unknown lint configuration (so I can use nightly)
+#![deny(warnings)]
+#![allow(unknown_lints)]
+
+use std::convert::TryInto;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use governor::clock;
+use governor::middleware::{
+    HttpHeadersMiddleware, NoOpMiddleware, RateLimitHeaders, RateLimitingMiddleware, StateSnapshot,
+};
+use opentelemetry::metrics::Meter;
+use opentelemetry::{trace, KeyValue};
+
+/// The names of the attributes that [`OpenTelemetryMiddleware`] sets on spans and metrics.
+pub mod attribute {
+    /// The decision's result: `"allowed"` or `"denied"`.
+    pub const RESULT: &str = "ratelimit.result";
+
+    /// The rate limiter's burst size, as in
+    /// [`RateLimitHeaders::limit`](governor::middleware::RateLimitHeaders::limit).
+    pub const LIMIT: &str = "ratelimit.limit";
+
+    /// The number of cells that the rate limiter would allow through right after the decision.
+    pub const REMAINING: &str = "ratelimit.remaining";
+
+    /// The number of seconds until the rate limiter has replenished its entire burst capacity.
+    pub const RESET: &str = "ratelimit.reset";
+
+    /// For denied decisions, the number of seconds until the rejected cells would be allowed
+    /// through.
+    pub const RETRY_AFTER: &str = "ratelimit.retry_after";
+
+    /// The key that the decision was made for, in the rate limiter's
+    /// [`KeyFormat`](governor::middleware::KeyFormat).
+    pub const KEY: &str = "ratelimit.key";
+}
+
+/// Middleware that reports each decision to OpenTelemetry, and otherwise behaves like the
+/// `Inner` middleware.
+///
+/// Spans get the [attributes](attribute) of the decision, and the decision is counted in the
+/// metrics that [`register_metrics`] sets up. Keys show up in spans only, in the rate limiter's
+/// [`KeyFormat`](governor::middleware::KeyFormat) (a hash of the key, by default): They would
+/// make the number of metric streams grow without bound. For the same reason, the state after
+/// each decision (its limit, remaining cells and reset time) isn't a metric: Middleware is
+/// shared by all rate limiters of a type, so a gauge could only report the state of whichever
+/// key was decided on last.
+#[derive(Debug)]
+pub struct OpenTelemetryMiddleware<Inner = NoOpMiddleware> {
+    phantom: PhantomData<Inner>,
+}
+
+impl<Inner> OpenTelemetryMiddleware<Inner> {
+    fn record<K: fmt::Display>(key: &K, headers: &RateLimitHeaders) {
+        RECORDER.record(headers);
+        trace::get_active_span(|span| {
+            if span.is_recording() {
+                span.set_attributes(span_attributes(key, headers));
+            }
+        });
+    }
+}
+
+impl<P, Inner> RateLimitingMiddleware<P> for OpenTelemetryMiddleware<Inner>
+where
+    P: clock::Reference,
+    Inner: RateLimitingMiddleware<P>,
+{
+    type PositiveOutcome = Inner::PositiveOutcome;
+
+    type NegativeOutcome = Inner::NegativeOutcome;
+
+    fn allow<K: fmt::Display>(key: &K, state: impl Into<StateSnapshot>) -> Self::PositiveOutcome {
+        let state = state.into();
+        let headers =
+            <HttpHeadersMiddleware as RateLimitingMiddleware<P>>::allow(key, state.clone());
+        Self::record(key, &headers);
+        Inner::allow(key, state)
+    }
+
+    fn disallow<K: fmt::Display>(
+        key: &K,
+        state: impl Into<StateSnapshot>,
+        start_time: P,
+    ) -> Self::NegativeOutcome {
+        let state = state.into();
+        let headers = <HttpHeadersMiddleware as RateLimitingMiddleware<P>>::disallow(
+            key,
+            state.clone(),
+            start_time,
+        );
+        Self::record(key, &headers);
+        Inner::disallow(key, state, start_time)
+    }
+}
+
+fn result(headers: &RateLimitHeaders) -> &'static str {
+    if headers.retry_after().is_some() {
+        "denied"
+    } else {
+        "allowed"
+    }
+}
+
+fn span_attributes<K: fmt::Display>(key: &K, headers: &RateLimitHeaders) -> Vec<KeyValue> {
+    let mut attributes = vec![
+        KeyValue::new(attribute::RESULT, result(headers)),
+        KeyValue::new(attribute::LIMIT, i64::from(headers.limit())),
+        KeyValue::new(attribute::REMAINING, i64::from(headers.remaining())),
+        KeyValue::new(attribute::RESET, headers.reset().as_secs_f64()),
+        KeyValue::new(attribute::KEY, key.to_string()),
+    ];
+    if let Some(retry_after) = headers.retry_after() {
+        attributes.push(KeyValue::new(
+            attribute::RETRY_AFTER,
+            retry_after.as_secs_f64(),
+        ));
+    }
+    attributes
+}
+
+/// The decisions that all [`OpenTelemetryMiddleware`]s recorded so far.
+struct Recorder {
+    allowed: AtomicU64,
+    denied: AtomicU64,
+    wait_time_ns: AtomicU64,
+}
+
+static RECORDER: Recorder = Recorder {
+    allowed: AtomicU64::new(0),
+    denied: AtomicU64::new(0),
+    wait_time_ns: AtomicU64::new(0),
+};
+
+impl Recorder {
+    fn record(&self, headers: &RateLimitHeaders) {
+        // The metrics are only read when they get collected, and don't synchronize with
+        // anything else:
+        match headers.retry_after() {
+            Some(wait) => {
+                self.denied.fetch_add(1, Ordering::Relaxed);
+                self.wait_time_ns
+                    .fetch_add(nanos(wait.as_nanos()), Ordering::Relaxed);
+            }
+            None => {
+                self.allowed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn nanos(n: u128) -> u64 {
+    n.try_into().unwrap_or(u64::MAX)
+}
+
+/// Registers the metrics that [`OpenTelemetryMiddleware`] records with `meter`.
+///
+/// The metrics cover the decisions of all rate limiters that use the middleware:
+///
+/// * `governor.decisions` counts decisions, with a [`ratelimit.result`](attribute::RESULT)
+///   attribute of `"allowed"` or `"denied"`.
+/// * `governor.wait_time` adds up the seconds that denied decisions asked callers to wait.
+///
+/// Register the metrics only once per meter provider; registering them again reports every
+/// value twice.
+pub fn register_metrics(meter: &Meter) {
+    meter
+        .u64_observable_counter("governor.decisions")
+        .with_description("The number of rate limiting decisions")
+        .with_unit("{decision}")
+        .with_callback(|observer| {
+            let allowed = RECORDER.allowed.load(Ordering::Relaxed);
+            let denied = RECORDER.denied.load(Ordering::Relaxed);
+            observer.observe(allowed, &[KeyValue::new(attribute::RESULT, "allowed")]);
+            observer.observe(denied, &[KeyValue::new(attribute::RESULT, "denied")]);
+        })
+        .build();
+    meter
+        .f64_observable_counter("governor.wait_time")
+        .with_description("The time that denied rate limiting decisions asked callers to wait")
+        .with_unit("s")
+        .with_callback(|observer| {
+            let wait = RECORDER.wait_time_ns.load(Ordering::Relaxed);
+            observer.observe(wait as f64 / 1e9, &[]);
+        })
+        .build();
+}
@@ -0,0 +1,75 @@
+use governor::{
+    clock::FakeRelativeClock, middleware::NoOpMiddleware, nanos::Nanos, Quota, RateLimiter,
+};
+use governor_opentelemetry::OpenTelemetryMiddleware;
+use nonzero_ext::nonzero;
+use opentelemetry::metrics::MeterProvider;
+use opentelemetry_sdk::metrics::{
+    data::{AggregatedMetrics, MetricData},
+    InMemoryMetricExporter, PeriodicReader, SdkMeterProvider,
+};
+use std::collections::HashMap;
+
+/// Returns the values of the exported metrics' data points, by metric name and result.
+fn collect(exporter: &InMemoryMetricExporter) -> HashMap<(String, String), f64> {
+    let mut values = HashMap::new();
+    for resource in exporter.get_finished_metrics().unwrap() {
+        for scope in resource.scope_metrics() {
+            for metric in scope.metrics() {
+                let mut insert = |attributes: Vec<String>, value: f64| {
+                    values.insert((metric.name().to_string(), attributes.join(",")), value);
+                };
+                let show = |kv: &opentelemetry::KeyValue| kv.value.to_string();
+                match metric.data() {
+                    AggregatedMetrics::U64(MetricData::Sum(sum)) => {
+                        for point in sum.data_points() {
+                            insert(point.attributes().map(show).collect(), point.value() as f64);
+                        }
+                    }
+                    AggregatedMetrics::F64(MetricData::Sum(sum)) => {
+                        for point in sum.data_points() {
+                            insert(point.attributes().map(show).collect(), point.value());
+                        }
+                    }
+                    data => panic!("unexpected metric data {:?}", data),
+                }
+            }
+        }
+    }
+    values
+}
+
+fn value(values: &HashMap<(String, String), f64>, name: &str, attributes: &str) -> f64 {
+    values[&(name.to_string(), attributes.to_string())]
+}
+
+#[test]
+fn reports_decisions() {
+    let exporter = InMemoryMetricExporter::default();
+    let provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(exporter.clone()).build())
+        .build();
+    governor_opentelemetry::register_metrics(&provider.meter("governor-test"));
+
+    provider.force_flush().unwrap();
+    let values = collect(&exporter);
+    assert_eq!(value(&values, "governor.decisions", "allowed"), 0.0);
+
+    let lim = RateLimiter::hashmap_with_clock(
+        Quota::per_minute(nonzero!(2u32)),
+        FakeRelativeClock::default(),
+    )
+    .with_middleware::<OpenTelemetryMiddleware<NoOpMiddleware<Nanos>>>();
+    assert_eq!(Ok(()), lim.check_key(&"alice"));
+    assert_eq!(Ok(()), lim.check_key(&"alice"));
+    assert!(lim.check_key(&"alice").is_err());
+    assert_eq!(Ok(()), lim.check_key(&"bob"));
+
+    exporter.reset();
+    provider.force_flush().unwrap();
+    let values = collect(&exporter);
+    assert_eq!(value(&values, "governor.decisions", "allowed"), 3.0);
+    assert_eq!(value(&values, "governor.decisions", "denied"), 1.0);
+    assert_eq!(value(&values, "governor.wait_time", ""), 30.0);
+    assert_eq!(values.len(), 3);
+}
@@ -0,0 +1,75 @@
+use governor::{
+    clock::FakeRelativeClock, middleware::NoOpMiddleware, nanos::Nanos, Quota, RateLimiter,
+};
+use governor_opentelemetry::{attribute, OpenTelemetryMiddleware};
+use nonzero_ext::nonzero;
+use opentelemetry::trace::{Tracer, TracerProvider};
+use opentelemetry::{Key, Value};
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+
+#[test]
+fn sets_span_attributes() {
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let tracer = provider.tracer("governor-test");
+
+    let lim = RateLimiter::direct_with_clock(
+        Quota::per_second(nonzero!(2u32)),
+        FakeRelativeClock::default(),
+    )
+    .with_middleware::<OpenTelemetryMiddleware<NoOpMiddleware<Nanos>>>();
+    tracer.in_span("allowed", |_| assert_eq!(Ok(()), lim.check()));
+    tracer.in_span("denied", |_| {
+        assert_eq!(Ok(()), lim.check());
+        assert!(lim.check().is_err());
+    });
+    // Decisions outside of spans aren't recorded anywhere:
+    assert!(lim.check().is_err());
+
+    let spans = exporter.get_finished_spans().unwrap();
+    assert_eq!(spans.len(), 2);
+    let attribute = |name: &str, key: &'static str| {
+        let span = spans.iter().find(|span| span.name == name).unwrap();
+        span.attributes
+            .iter()
+            .rev()
+            .find(|kv| kv.key == Key::from_static_str(key))
+            .map(|kv| kv.value.clone())
+    };
+
+    assert_eq!(
+        attribute("allowed", attribute::RESULT),
+        Some(Value::from("allowed"))
+    );
+    assert_eq!(attribute("allowed", attribute::LIMIT), Some(Value::I64(2)));
+    assert_eq!(
+        attribute("allowed", attribute::REMAINING),
+        Some(Value::I64(1))
+    );
+    assert_eq!(
+        attribute("allowed", attribute::RESET),
+        Some(Value::F64(0.5))
+    );
+    assert_eq!(
+        attribute("allowed", attribute::KEY),
+        Some(Value::from("[redacted]"))
+    );
+    assert_eq!(attribute("allowed", attribute::RETRY_AFTER), None);
+
+    // The span records the last decision made in it:
+    assert_eq!(
+        attribute("denied", attribute::RESULT),
+        Some(Value::from("denied"))
+    );
+    assert_eq!(
+        attribute("denied", attribute::REMAINING),
+        Some(Value::I64(0))
+    );
+    assert_eq!(attribute("denied", attribute::RESET), Some(Value::F64(1.0)));
+    assert_eq!(
+        attribute("denied", attribute::RETRY_AFTER),
+        Some(Value::F64(0.5))
+    );
+}
@@ -2,41 +2,41 @@
 //! The longest-running thread's time is reported. These benchmarks unfortunately measure a certain
 //! amount of overhead in thread setup and teardown.
 
-use criterion::{black_box, BenchmarkId, Criterion, Throughput};
-use governor::{clock, Quota, RateLimiter};
-use governor::{
-    middleware::NoOpMiddleware,
-    state::keyed::{DashMapStateStore, HashMapStateStore, KeyedStateStore},
-};
+use criterion::{black_box, Criterion, Throughput};
+use governor::state::keyed::{DashMapStateStore, HashMapStateStore};
+use governor::{bench, clock, state::direct::StripedDirectLimiter, state::InMemoryState, Quota};
 use nonzero_ext::*;
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
-use tynm::type_name;
 
 pub fn bench_all(c: &mut Criterion) {
-    bench_direct(c);
-    bench_keyed::<HashMapStateStore<u32>>(c);
-    bench_keyed::<DashMapStateStore<u32>>(c);
+    let clock = clock::QuantaUpkeepClock::from_interval(Duration::from_micros(10))
+        .expect("Could not spawn upkeep thread");
+    bench::multi_threaded_direct::<InMemoryState, _>(c, &clock);
+    bench_direct_striped(c, &clock);
+    bench::multi_threaded_keyed::<HashMapStateStore<u32>, _>(c, &clock);
+    bench::multi_threaded_keyed::<DashMapStateStore<u32>, _>(c, &clock);
+    bench::multi_threaded_direct_peek::<InMemoryState, _>(c, &clock);
+    bench::multi_threaded_keyed_peek::<HashMapStateStore<u32>, _>(c, &clock);
+    bench::multi_threaded_keyed_peek::<DashMapStateStore<u32>, _>(c, &clock);
+    #[cfg(feature = "skiplist")]
+    bench::multi_threaded_keyed::<governor::state::keyed::SkipListStateStore<u32>, _>(c, &clock);
 }
 
-const THREADS: u32 = 20;
-
-fn bench_direct(c: &mut Criterion) {
+fn bench_direct_striped(c: &mut Criterion, clock: &clock::QuantaUpkeepClock) {
     let mut group = c.benchmark_group("multi_threaded");
     group.throughput(Throughput::Elements(1));
-    group.bench_function("direct", |b| {
-        let clock = clock::QuantaUpkeepClock::from_interval(Duration::from_micros(10))
-            .expect("Could not spawn upkeep thread");
-
+    group.bench_function("direct_striped", |b| {
         b.iter_custom(|iters| {
-            let lim = Arc::new(RateLimiter::direct_with_clock(
+            let lim: Arc<StripedDirectLimiter<_>> = Arc::new(StripedDirectLimiter::with_clock(
                 Quota::per_second(nonzero!(50u32)),
+                nonzero!(8usize),
                 clock.clone(),
             ));
             let mut children = vec![];
             let start = Instant::now();
-            for _i in 0..THREADS {
+            for _i in 0..bench::THREADS {
                 let lim = Arc::clone(&lim);
                 children.push(thread::spawn(move || {
                     for _i in 0..iters {
@@ -52,42 +52,3 @@ fn bench_direct(c: &mut Criterion) {
     });
     group.finish();
 }
-
-fn bench_keyed<M: KeyedStateStore<u32> + Default + Send + Sync + 'static>(c: &mut Criterion) {
-    let mut group = c.benchmark_group("multi_threaded");
-
-    // We perform 3 checks per thread per iter:
-    group.throughput(Throughput::Elements(3));
-
-    group.bench_function(BenchmarkId::new("keyed", type_name::<M>()), |b| {
-        let clock = clock::QuantaUpkeepClock::from_interval(Duration::from_micros(10))
-            .expect("Could not spawn upkeep thread");
-
-        b.iter_custom(|iters| {
-            let state: M = Default::default();
-            let lim: Arc<RateLimiter<_, _, _, NoOpMiddleware>> = Arc::new(RateLimiter::new(
-                Quota::per_second(nonzero!(50u32)),
-                state,
-                clock.clone(),
-            ));
-
-            let mut children = vec![];
-            let start = Instant::now();
-            for _i in 0..THREADS {
-                let lim = Arc::clone(&lim);
-                children.push(thread::spawn(move || {
-                    for _i in 0..iters {
-                        black_box(lim.check_key(&1u32).is_ok());
-                        black_box(lim.check_key(&2u32).is_ok());
-                        black_box(lim.check_key(&3u32).is_ok());
-                    }
-                }));
-            }
-            for child in children {
-                child.join().unwrap()
-            }
-            start.elapsed()
-        })
-    });
-    group.finish();
-}
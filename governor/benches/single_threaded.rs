@@ -1,64 +1,71 @@
-use criterion::{black_box, BatchSize, BenchmarkId, Criterion, Throughput};
-use governor::{clock, Quota, RateLimiter};
+use criterion::{black_box, BatchSize, Criterion, Throughput};
 use governor::{
-    middleware::NoOpMiddleware,
-    state::keyed::{DashMapStateStore, HashMapStateStore, KeyedStateStore},
+    bench,
+    clock::FakeRelativeClock,
+    state::{
+        keyed::{AnyKey, DashMapStateStore, HashMapStateStore},
+        InMemoryState,
+    },
+    Quota, RateLimiter,
 };
-use nonzero_ext::*;
+use nonzero_ext::nonzero;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
 use std::time::Duration;
-use tynm::type_name;
 
 pub fn bench_all(c: &mut Criterion) {
-    bench_direct(c);
-    bench_keyed::<HashMapStateStore<u32>>(c);
-    bench_keyed::<DashMapStateStore<u32>>(c);
+    bench::single_threaded_direct::<InMemoryState>(c);
+    bench::single_threaded_keyed::<HashMapStateStore<u32>>(c);
+    bench::single_threaded_keyed::<DashMapStateStore<u32>>(c);
+    bench_any_key(c);
 }
 
-fn bench_direct(c: &mut Criterion) {
+/// The identities that the `any_key` benchmarks check, one of each kind in turn.
+const IDENTITIES: usize = 3;
+
+/// Compares one rate limiter for user IDs, API keys and IP addresses, keyed by [`AnyKey`], to
+/// a separate rate limiter for each of them.
+fn bench_any_key(c: &mut Criterion) {
+    let quota = Quota::per_second(nonzero!(50u32));
+    let step = Duration::from_millis(20);
+    let user: u64 = 42;
+    let api_key: Arc<str> = "0123456789abcdef0123456789abcdef".into();
+    let ip = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+
     let mut group = c.benchmark_group("single_threaded");
-    group.throughput(Throughput::Elements(1));
-    group.bench_function("direct", |b| {
-        let clock = clock::FakeRelativeClock::default();
-        let step = Duration::from_millis(20);
-        let rl = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(50u32)), clock.clone());
+    group.throughput(Throughput::Elements(IDENTITIES as u64));
+    group.bench_function("any_key/separate", |b| {
+        let clock = FakeRelativeClock::default();
+        let users = RateLimiter::hashmap_with_clock(quota, clock.clone());
+        let api_keys = RateLimiter::hashmap_with_clock(quota, clock.clone());
+        let ips = RateLimiter::hashmap_with_clock(quota, clock.clone());
         b.iter_batched(
             || {
                 clock.advance(step);
             },
             |()| {
-                black_box(rl.check().is_ok());
+                black_box(users.check_key(&user).is_ok());
+                black_box(api_keys.check_key(&api_key).is_ok());
+                black_box(ips.check_key(&ip).is_ok());
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    group.bench_function("any_key/combined", |b| {
+        let clock = FakeRelativeClock::default();
+        let lim = RateLimiter::hashmap_with_clock(quota, clock.clone());
+        let keys: [AnyKey; IDENTITIES] = [user.into(), api_key.clone().into(), ip.into()];
+        b.iter_batched(
+            || {
+                clock.advance(step);
+            },
+            |()| {
+                for key in &keys {
+                    black_box(lim.check_key(key).is_ok());
+                }
             },
             BatchSize::SmallInput,
         );
     });
-    group.finish();
-}
-
-fn bench_keyed<M: KeyedStateStore<u32> + Default + Send + Sync + 'static>(c: &mut Criterion) {
-    let mut group = c.benchmark_group("single_threaded");
-    group.throughput(Throughput::Elements(3));
-
-    group
-        .bench_function(BenchmarkId::new("keyed", type_name::<M>()), |b| {
-            let state: M = Default::default();
-            let clock = clock::FakeRelativeClock::default();
-            let step = Duration::from_millis(20);
-            let rl: RateLimiter<
-                _,
-                _,
-                _,
-                NoOpMiddleware<<clock::FakeRelativeClock as clock::Clock>::Instant>,
-            > = RateLimiter::new(Quota::per_second(nonzero!(50u32)), state, clock.clone());
-            b.iter_batched(
-                || {
-                    clock.advance(step);
-                },
-                |()| {
-                    black_box(rl.check_key(&1u32).is_ok());
-                },
-                BatchSize::SmallInput,
-            );
-        })
-        .throughput(Throughput::Elements(1));
     group.finish();
 }
@@ -107,6 +107,28 @@
 //! implementation of the keyed state, you will still have to fall
 //! back to the regular type.
 //!
+//! # Waiting for rate limiters
+//!
+//! With the `std` feature, rate limiters on real-time clocks can be
+//! waited on asynchronously, until they allow cells through. The
+//! return type of each wait method depends on whether its cells could
+//! ever fail to fit into the rate limiter's burst size:
+//!
+//! | Waits for                  | Direct                                   | Keyed                                        | Resolves to                                   |
+//! |----------------------------|------------------------------------------|----------------------------------------------|-----------------------------------------------|
+//! | one cell                   | `until_ready`                            | `until_key_ready`                            | `MW::PositiveOutcome`                         |
+//! | `n` cells                  | `until_n_ready`                          | `until_key_n_ready`                          | `Result<MW::PositiveOutcome, InsufficientCapacity>` |
+//! | up to `n` cells            | `until_n_ready_clamped`                  | `until_key_n_ready_clamped`                  | `Clamped<MW::PositiveOutcome>`                |
+//! | one cell, if soon enough   | `until_ready_or_reject_if_wait_exceeds`  | `until_key_ready_or_reject_if_wait_exceeds`  | `Result<MW::PositiveOutcome, MW::NegativeOutcome>` |
+//!
+//! A single cell always fits, so waiting for one can't fail. A batch
+//! of `n` cells can exceed the burst size, which `until_n_ready`
+//! reports as [`InsufficientCapacity`][crate::InsufficientCapacity],
+//! and which the `_clamped` methods avoid by waiting for at most the
+//! burst size's worth of cells. All methods but the threshold ones
+//! have a `_with_jitter` variant, which waits for an additional random
+//! amount of time (see [`Jitter`][crate::Jitter]).
+//!
 //! # Data ownership and references to rate limiters
 //!
 //! `governor`'s rate limiter state is not hidden behind an [interior
@@ -0,0 +1,270 @@
+//! Rate limiting bytes instead of cells.
+//!
+//! Network traffic is usually shaped to a bandwidth, like "10 Mbps", and measured in bytes. A
+//! [`Bandwidth`] converts to a [`Quota`] whose cells stand for a fixed number of bytes each, and
+//! a [`BandwidthLimiter`] converts the number of bytes to send into the number of cells to check,
+//! without overflowing. With the `std` feature, [`RateLimitedWriter`] and [`RateLimitedReader`]
+//! shape the throughput of IO to a bandwidth limiter's bandwidth.
+
+use std::prelude::v1::*;
+
+use std::convert::TryFrom;
+use std::num::{NonZeroU32, NonZeroU64};
+use std::time::Duration;
+
+use nonzero_ext::nonzero;
+
+#[cfg(feature = "std")]
+mod io;
+#[cfg(feature = "std")]
+pub use io::*;
+
+use crate::{
+    clock,
+    errors::InsufficientCapacity,
+    middleware::{NoOpMiddleware, RateLimitingMiddleware},
+    state::{InMemoryState, NotKeyed},
+    Quota, RateLimiter,
+};
+
+/// The most cells per second that a bandwidth uses by default.
+///
+/// This keeps the replenishment interval of a cell at or above a microsecond, far above the
+/// rate limiters' nanosecond resolution.
+const MAX_DEFAULT_CELLS_PER_SECOND: u64 = 1_000_000;
+
+/// A number of bytes per second, along with the number of bytes in a rate limiter's cell.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::bandwidth::Bandwidth;
+///
+/// let bandwidth = Bandwidth::mbps(nonzero!(10u32));
+/// assert_eq!(bandwidth.bytes_per_second().get(), 1_250_000);
+/// // Every cell stands for 2 bytes, so a burst of one second is 625,000 cells:
+/// assert_eq!(bandwidth.cell_size().get(), 2);
+/// assert_eq!(bandwidth.quota().burst_size().get(), 625_000);
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Bandwidth {
+    bytes_per_second: NonZeroU64,
+    cell_size: NonZeroU32,
+    burst_bytes: NonZeroU64,
+}
+
+/// Constructors for bandwidths
+impl Bandwidth {
+    /// Construct a bandwidth of the given number of bytes per second.
+    ///
+    /// The bandwidth allows a burst of one second's worth of bytes, and uses the smallest power
+    /// of two as its cell size that results in at most a million cells per second.
+    pub fn per_second(bytes_per_second: NonZeroU64) -> Bandwidth {
+        let min_cell_size = bytes_per_second
+            .get()
+            .div_ceil(MAX_DEFAULT_CELLS_PER_SECOND);
+        let cell_size = u32::try_from(min_cell_size.next_power_of_two()).unwrap_or(1 << 31);
+        Bandwidth {
+            bytes_per_second,
+            cell_size: NonZeroU32::new(cell_size).unwrap_or(nonzero!(1u32)),
+            burst_bytes: bytes_per_second,
+        }
+    }
+
+    /// Construct a bandwidth of the given number of kilobits (1000 bits) per second.
+    pub fn kbps(kilobits: NonZeroU32) -> Bandwidth {
+        Self::bits_per_second(u64::from(kilobits.get()) * 1_000)
+    }
+
+    /// Construct a bandwidth of the given number of megabits (1000 kilobits) per second.
+    pub fn mbps(megabits: NonZeroU32) -> Bandwidth {
+        Self::bits_per_second(u64::from(megabits.get()) * 1_000_000)
+    }
+
+    /// Construct a bandwidth of the given number of gigabits (1000 megabits) per second.
+    pub fn gbps(gigabits: NonZeroU32) -> Bandwidth {
+        Self::bits_per_second(u64::from(gigabits.get()) * 1_000_000_000)
+    }
+
+    fn bits_per_second(bits: u64) -> Bandwidth {
+        // All callers pass at least 1000 bits, so there is at least one byte:
+        Self::per_second(NonZeroU64::new(bits / 8).unwrap_or(nonzero!(1u64)))
+    }
+
+    /// Adjusts the number of bytes that each cell of the rate limiter stands for.
+    ///
+    /// Larger cells make for cheaper checks of large amounts of bytes, but round up the size of
+    /// small amounts more.
+    pub const fn with_cell_size(self, cell_size: NonZeroU32) -> Bandwidth {
+        Bandwidth { cell_size, ..self }
+    }
+
+    /// Adjusts the maximum number of bytes that can be let through at once.
+    ///
+    /// The burst is rounded down to whole cells, but is at least one cell.
+    pub const fn allow_burst(self, burst_bytes: NonZeroU64) -> Bandwidth {
+        Bandwidth {
+            burst_bytes,
+            ..self
+        }
+    }
+}
+
+/// Retrieving information about a bandwidth
+impl Bandwidth {
+    /// The number of bytes that the bandwidth allows each second.
+    pub const fn bytes_per_second(&self) -> NonZeroU64 {
+        self.bytes_per_second
+    }
+
+    /// The number of bytes that each cell stands for.
+    pub const fn cell_size(&self) -> NonZeroU32 {
+        self.cell_size
+    }
+
+    /// The maximum number of bytes that can be let through at once.
+    pub const fn burst_bytes(&self) -> NonZeroU64 {
+        self.burst_bytes
+    }
+
+    /// Returns the quota for rate limiting the bandwidth's cells.
+    ///
+    /// The replenishment interval of a cell is rounded up to the next nanosecond, so the quota
+    /// never lets through more bytes than the bandwidth allows.
+    pub fn quota(&self) -> Quota {
+        let cell_size = u64::from(self.cell_size.get());
+        let interval_ns = (u128::from(cell_size) * 1_000_000_000)
+            .div_ceil(u128::from(self.bytes_per_second.get()));
+        let interval_ns = u64::try_from(interval_ns).unwrap_or(u64::MAX);
+        let burst = u32::try_from(self.burst_bytes.get() / cell_size).unwrap_or(u32::MAX);
+        Quota {
+            max_burst: NonZeroU32::new(burst).unwrap_or(NonZeroU32::MIN),
+            replenish_1_per: Duration::from_nanos(interval_ns),
+            cooldown: Duration::ZERO,
+            recharge_delay: Duration::ZERO,
+        }
+    }
+
+    /// Returns the number of cells that `n_bytes` bytes take up, rounded up to whole cells.
+    ///
+    /// Returns `InsufficientCapacity` if the number of cells doesn't fit into a `u32`, as no
+    /// quota could ever accommodate them.
+    pub fn cells_for_bytes(&self, n_bytes: NonZeroU64) -> Result<NonZeroU32, InsufficientCapacity> {
+        let cells = n_bytes.get().div_ceil(u64::from(self.cell_size.get()));
+        u32::try_from(cells)
+            .ok()
+            .and_then(NonZeroU32::new)
+            .ok_or_else(|| InsufficientCapacity(self.quota().burst_size().get()))
+    }
+}
+
+impl From<Bandwidth> for Quota {
+    fn from(bandwidth: Bandwidth) -> Quota {
+        bandwidth.quota()
+    }
+}
+
+/// A direct rate limiter that checks numbers of bytes against a [`Bandwidth`].
+///
+/// # Example
+/// ```rust
+/// # #[cfg(feature = "std")]
+/// # fn main () {
+/// # use nonzero_ext::nonzero;
+/// use governor::bandwidth::{Bandwidth, BandwidthLimiter};
+///
+/// let lim = BandwidthLimiter::new(Bandwidth::kbps(nonzero!(8u32)));
+/// // 8 kbps is 1000 bytes per second:
+/// assert_eq!(Ok(Ok(())), lim.check_bytes(nonzero!(600u64)));
+/// assert_eq!(Ok(Ok(())), lim.check_bytes(nonzero!(400u64)));
+/// assert!(lim.check_bytes(nonzero!(1u64)).unwrap().is_err());
+/// // More than a second's worth of bytes can never be let through:
+/// assert!(lim.check_bytes(nonzero!(1001u64)).is_err());
+/// # }
+/// # #[cfg(not(feature = "std"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct BandwidthLimiter<
+    C: clock::Clock = clock::DefaultClock,
+    MW: RateLimitingMiddleware<C::Instant> = NoOpMiddleware<<C as clock::Clock>::Instant>,
+> {
+    bandwidth: Bandwidth,
+    limiter: RateLimiter<NotKeyed, InMemoryState, C, MW>,
+}
+
+#[cfg(feature = "std")]
+impl BandwidthLimiter {
+    /// Constructs a bandwidth limiter with the default real-time clock.
+    pub fn new(bandwidth: Bandwidth) -> Self {
+        Self::with_clock(bandwidth, clock::DefaultClock::default())
+    }
+}
+
+impl<C: clock::Clock> BandwidthLimiter<C, NoOpMiddleware<C::Instant>> {
+    /// Constructs a bandwidth limiter with a custom clock.
+    pub fn with_clock(bandwidth: Bandwidth, clock: C) -> Self {
+        BandwidthLimiter {
+            bandwidth,
+            limiter: RateLimiter::direct_with_clock(bandwidth.quota(), clock),
+        }
+    }
+}
+
+impl<C, MW> BandwidthLimiter<C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Convert the bandwidth limiter's middleware type to another.
+    pub fn with_middleware<Outer: RateLimitingMiddleware<C::Instant>>(
+        self,
+    ) -> BandwidthLimiter<C, Outer> {
+        BandwidthLimiter {
+            bandwidth: self.bandwidth,
+            limiter: self.limiter.with_middleware(),
+        }
+    }
+
+    /// Returns the bandwidth that the limiter enforces.
+    pub fn bandwidth(&self) -> &Bandwidth {
+        &self.bandwidth
+    }
+
+    /// Returns the rate limiter that checks the bandwidth's cells.
+    pub fn limiter(&self) -> &RateLimiter<NotKeyed, InMemoryState, C, MW> {
+        &self.limiter
+    }
+
+    /// Allow *only all* of `n_bytes` bytes through the rate limiter.
+    ///
+    /// The bytes are rounded up to whole cells and checked like
+    /// [`RateLimiter::check_n`]. Returns `InsufficientCapacity` if the bytes exceed the
+    /// bandwidth's burst, in cells.
+    pub fn check_bytes(
+        &self,
+        n_bytes: NonZeroU64,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        let cells = self.bandwidth.cells_for_bytes(n_bytes)?;
+        self.limiter.check_n(cells)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C, MW> BandwidthLimiter<C, MW>
+where
+    C: clock::ReasonablyRealtime,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = crate::NotUntil<C::Instant>>,
+{
+    /// Asynchronously resolves as soon as the rate limiter allows `n_bytes` bytes through.
+    ///
+    /// Returns `InsufficientCapacity` if the bytes exceed the bandwidth's burst, in cells; see
+    /// [`RateLimiter::until_n_ready`].
+    pub async fn until_bytes_ready(
+        &self,
+        n_bytes: NonZeroU64,
+    ) -> Result<MW::PositiveOutcome, InsufficientCapacity> {
+        let cells = self.bandwidth.cells_for_bytes(n_bytes)?;
+        self.limiter.until_n_ready(cells).await
+    }
+}
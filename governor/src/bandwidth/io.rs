@@ -0,0 +1,393 @@
+use std::prelude::v1::*;
+
+use std::cmp;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::num::NonZeroU32;
+use std::thread;
+
+#[cfg(feature = "futures-io")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use nonzero_ext::nonzero;
+
+use super::BandwidthLimiter;
+use crate::{clock, middleware::RateLimitingMiddleware, NotUntil};
+
+#[cfg(feature = "futures-io")]
+use crate::timer::Delay;
+
+/// Creating rate-limited IO adapters
+impl<C, MW> BandwidthLimiter<C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Wraps `inner` in a writer that only writes as many bytes as the bandwidth allows.
+    ///
+    /// See [`RateLimitedWriter`].
+    pub fn writer<W>(&self, inner: W) -> RateLimitedWriter<'_, W, C, MW> {
+        RateLimitedWriter {
+            inner,
+            throttle: Throttle::new(self),
+        }
+    }
+
+    /// Wraps `inner` in a reader that only reads as many bytes as the bandwidth allows.
+    ///
+    /// See [`RateLimitedReader`].
+    pub fn reader<R>(&self, inner: R) -> RateLimitedReader<'_, R, C, MW> {
+        RateLimitedReader {
+            inner,
+            throttle: Throttle::new(self),
+        }
+    }
+}
+
+/// Hands out the bytes that a bandwidth limiter lets through to an IO adapter.
+///
+/// The limiter gets checked for whole cells, so the adapter keeps the bytes of a cell that it
+/// didn't transfer yet as credit for the next transfer.
+struct Throttle<'a, C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    limiter: &'a BandwidthLimiter<C, MW>,
+    nonblocking: bool,
+    credit: usize,
+    #[cfg(feature = "futures-io")]
+    delay: Option<Delay>,
+}
+
+impl<'a, C, MW> Throttle<'a, C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    fn new(limiter: &'a BandwidthLimiter<C, MW>) -> Self {
+        Throttle {
+            limiter,
+            nonblocking: false,
+            credit: 0,
+            #[cfg(feature = "futures-io")]
+            delay: None,
+        }
+    }
+
+    /// Records that `n` of the bytes handed out were transferred.
+    fn spend(&mut self, n: usize) {
+        self.credit = self.credit.saturating_sub(n);
+    }
+}
+
+impl<C, MW> Throttle<'_, C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+{
+    /// Checks the limiter for as many cells as `len` bytes take up (at least one, and at most
+    /// the burst size), and adds the bytes it lets through to the credit.
+    fn check(&mut self, len: usize) -> io::Result<Result<(), NotUntil<C::Instant>>> {
+        let bandwidth = self.limiter.bandwidth();
+        let cell_size = u64::from(bandwidth.cell_size().get());
+        let cells = u64::try_from(len).unwrap_or(u64::MAX).div_ceil(cell_size);
+        let cells =
+            NonZeroU32::new(u32::try_from(cells).unwrap_or(u32::MAX)).unwrap_or(nonzero!(1u32));
+        let decision = self
+            .limiter
+            .limiter()
+            .check_at_least_n(nonzero!(1u32), cells)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(decision.map(|clamped| {
+            let bytes = u64::from(clamped.admitted().get()) * cell_size;
+            self.credit = usize::try_from(bytes).unwrap_or(usize::MAX);
+        }))
+    }
+
+    /// Returns how many of `len` bytes may be transferred, blocking the current thread until
+    /// the limiter lets at least one through.
+    fn take(&mut self, len: usize) -> io::Result<usize>
+    where
+        C: clock::ReasonablyRealtime,
+    {
+        while self.credit == 0 {
+            if let Err(negative) = self.check(len)? {
+                if self.nonblocking {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+                thread::sleep(negative.wait_time_from(self.limiter.limiter().clock().now()));
+            }
+        }
+        Ok(cmp::min(len, self.credit))
+    }
+
+    /// Returns how many of `len` bytes may be transferred, once the limiter lets at least one
+    /// through.
+    #[cfg(feature = "futures-io")]
+    fn poll_take(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<io::Result<usize>>
+    where
+        C: clock::ReasonablyRealtime,
+    {
+        loop {
+            if let Some(delay) = &mut self.delay {
+                ready!(Pin::new(delay).poll(cx));
+                self.delay = None;
+            }
+            if self.credit > 0 {
+                return Poll::Ready(Ok(cmp::min(len, self.credit)));
+            }
+            if let Err(negative) = self.check(len)? {
+                let wait = negative.wait_time_from(self.limiter.limiter().clock().now());
+                self.delay = Some(Delay::new(wait));
+            }
+        }
+    }
+}
+
+/// A [`Write`]r that shapes the throughput of another writer to a [`BandwidthLimiter`]'s
+/// bandwidth.
+///
+/// Each write checks the bandwidth limiter for the bytes to write, and writes only as many of
+/// them as it lets through right now. If it lets none through, the write blocks until it does
+/// (or, with [`nonblocking`](#method.nonblocking), fails with
+/// [`WouldBlock`](io::ErrorKind::WouldBlock)). [`write_all`](Write::write_all) thus writes a
+/// whole buffer at the bandwidth's pace.
+///
+/// The bandwidth limiter gets checked for whole cells. Bytes of a cell that the inner writer
+/// didn't accept aren't lost: they count toward the next write.
+///
+/// With the `futures-io` feature, the writer also implements
+/// [`AsyncWrite`] for inner writers that do, and waits on governor's timer instead of blocking.
+///
+/// [`AsyncWrite`]: https://docs.rs/futures-io/0.3/futures_io/trait.AsyncWrite.html
+///
+/// This is produced by [`BandwidthLimiter::writer`].
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::bandwidth::{Bandwidth, BandwidthLimiter};
+/// use std::io::Write;
+///
+/// let lim = BandwidthLimiter::new(Bandwidth::per_second(nonzero!(100_000u64)));
+/// let mut writer = lim.writer(vec![]);
+/// writer.write_all(&[0; 1000]).unwrap();
+/// assert_eq!(writer.into_inner().len(), 1000);
+/// ```
+pub struct RateLimitedWriter<'a, W, C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    inner: W,
+    throttle: Throttle<'a, C, MW>,
+}
+
+impl<'a, W, C, MW> RateLimitedWriter<'a, W, C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Makes writes that the bandwidth limiter lets no bytes through for fail with
+    /// [`WouldBlock`](io::ErrorKind::WouldBlock), instead of blocking.
+    ///
+    /// This has no effect on asynchronous writes.
+    pub fn nonblocking(mut self) -> Self {
+        self.throttle.nonblocking = true;
+        self
+    }
+
+    /// Returns the bandwidth limiter that this writer checks.
+    pub fn limiter(&self) -> &'a BandwidthLimiter<C, MW> {
+        self.throttle.limiter
+    }
+
+    /// Acquires a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Acquires a mutable reference to the underlying writer.
+    ///
+    /// Bytes written to it directly don't get checked against the bandwidth.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Consumes this writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W, C, MW> Write for RateLimitedWriter<'_, W, C, MW>
+where
+    W: Write,
+    C: clock::ReasonablyRealtime,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.write(buf);
+        }
+        let n = self.throttle.take(buf.len())?;
+        let written = self.inner.write(&buf[..n])?;
+        self.throttle.spend(written);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<W, C, MW> futures_io::AsyncWrite for RateLimitedWriter<'_, W, C, MW>
+where
+    W: futures_io::AsyncWrite + Unpin,
+    C: clock::ReasonablyRealtime,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        let n = ready!(this.throttle.poll_take(cx, buf.len()))?;
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..n]))?;
+        this.throttle.spend(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
+
+/// A [`Read`]er that shapes the throughput of another reader to a [`BandwidthLimiter`]'s
+/// bandwidth.
+///
+/// Each read checks the bandwidth limiter for the bytes that fit into the buffer, and reads only
+/// as many of them as it lets through right now. If it lets none through, the read blocks until
+/// it does (or, with [`nonblocking`](#method.nonblocking), fails with
+/// [`WouldBlock`](io::ErrorKind::WouldBlock)).
+///
+/// The bandwidth limiter gets checked for whole cells. Bytes of a cell that the inner reader
+/// didn't fill in aren't lost: they count toward the next read.
+///
+/// With the `futures-io` feature, the reader also implements
+/// [`AsyncRead`] for inner readers that do, and waits on governor's timer instead of blocking.
+///
+/// [`AsyncRead`]: https://docs.rs/futures-io/0.3/futures_io/trait.AsyncRead.html
+///
+/// This is produced by [`BandwidthLimiter::reader`].
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::bandwidth::{Bandwidth, BandwidthLimiter};
+/// use std::io::Read;
+///
+/// let lim = BandwidthLimiter::new(Bandwidth::per_second(nonzero!(100_000u64)));
+/// let mut contents = vec![];
+/// lim.reader(&[0u8; 1000][..]).read_to_end(&mut contents).unwrap();
+/// assert_eq!(contents.len(), 1000);
+/// ```
+pub struct RateLimitedReader<'a, R, C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    inner: R,
+    throttle: Throttle<'a, C, MW>,
+}
+
+impl<'a, R, C, MW> RateLimitedReader<'a, R, C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Makes reads that the bandwidth limiter lets no bytes through for fail with
+    /// [`WouldBlock`](io::ErrorKind::WouldBlock), instead of blocking.
+    ///
+    /// This has no effect on asynchronous reads.
+    pub fn nonblocking(mut self) -> Self {
+        self.throttle.nonblocking = true;
+        self
+    }
+
+    /// Returns the bandwidth limiter that this reader checks.
+    pub fn limiter(&self) -> &'a BandwidthLimiter<C, MW> {
+        self.throttle.limiter
+    }
+
+    /// Acquires a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Acquires a mutable reference to the underlying reader.
+    ///
+    /// Bytes read from it directly don't get checked against the bandwidth.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Consumes this reader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R, C, MW> Read for RateLimitedReader<'_, R, C, MW>
+where
+    R: Read,
+    C: clock::ReasonablyRealtime,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return self.inner.read(buf);
+        }
+        let n = self.throttle.take(buf.len())?;
+        let read = self.inner.read(&mut buf[..n])?;
+        self.throttle.spend(read);
+        Ok(read)
+    }
+}
+
+#[cfg(feature = "futures-io")]
+impl<R, C, MW> futures_io::AsyncRead for RateLimitedReader<'_, R, C, MW>
+where
+    R: futures_io::AsyncRead + Unpin,
+    C: clock::ReasonablyRealtime,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+        let n = ready!(this.throttle.poll_take(cx, buf.len()))?;
+        let read = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf[..n]))?;
+        this.throttle.spend(read);
+        Poll::Ready(Ok(read))
+    }
+}
@@ -0,0 +1,369 @@
+//! Governor's benchmarks, for use with custom state stores and clocks.
+//!
+//! This module is available with the `criterion` feature. It exports the
+//! [criterion](https://docs.rs/criterion) benchmarks that governor runs against its own state
+//! stores and clocks, parameterized by the state store and clock type, so the authors of custom
+//! [`StateStore`](crate::state::StateStore)s and [`Clock`]s can measure theirs against the
+//! built-in ones with the same methodology:
+//!
+//! * [`single_threaded_direct`] and [`single_threaded_keyed`] measure one thread checking a
+//!   rate limiter that allows every cell. Time is simulated with a [`FakeRelativeClock`] that
+//!   advances between checks, so these measure only the state store.
+//! * [`multi_threaded_direct`] and [`multi_threaded_keyed`] measure how long it takes
+//!   [`THREADS`] threads to check a shared rate limiter `iters` times each. The
+//!   longest-running thread's time is reported; it includes some overhead from setting up and
+//!   tearing down the threads.
+//! * [`multi_threaded_direct_peek`] and [`multi_threaded_keyed_peek`] measure read-mostly
+//!   workloads the same way: [`THREADS`] threads peek at a shared rate limiter with
+//!   `check_only`, alone and while every other thread checks it.
+//! * [`realtime_clock`] measures checks against a real-time clock, through a rate limiter that
+//!   mostly allows and one that mostly denies.
+//!
+//! Benchmarks are named like governor's own, with the state store type (or, for
+//! [`realtime_clock`], the clock type) as their parameter, so their reports sit next to the
+//! built-ins' in criterion's output. Benchmarks of the default [`InMemoryState`] have no
+//! parameter, as governor's own always had. The multi-threaded benchmarks' names don't include
+//! the clock, so compare state stores that were measured with the same clock:
+//!
+//! ```rust,no_run
+//! use criterion::{criterion_group, criterion_main, Criterion};
+//! use governor::{bench, clock::QuantaUpkeepClock, state::keyed::HashMapStateStore};
+//! use std::time::Duration;
+//!
+//! # type MyStateStore = HashMapStateStore<u32>;
+//! fn my_store(c: &mut Criterion) {
+//!     let clock = QuantaUpkeepClock::from_interval(Duration::from_micros(10)).unwrap();
+//!     bench::single_threaded_keyed::<MyStateStore>(c);
+//!     bench::multi_threaded_keyed::<MyStateStore, _>(c, &clock);
+//!     bench::single_threaded_keyed::<HashMapStateStore<u32>>(c);
+//!     bench::multi_threaded_keyed::<HashMapStateStore<u32>, _>(c, &clock);
+//! }
+//!
+//! criterion_group!(benches, my_store);
+//! criterion_main!(benches);
+//! ```
+
+use std::prelude::v1::*;
+
+use std::any::TypeId;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use criterion::{
+    black_box, measurement::WallTime, BatchSize, Bencher, BenchmarkGroup, BenchmarkId, Criterion,
+    Throughput,
+};
+use nonzero_ext::nonzero;
+use tynm::type_name;
+
+use crate::{
+    clock::{Clock, FakeRelativeClock},
+    middleware::NoOpMiddleware,
+    nanos::Nanos,
+    state::{keyed::KeyedStateStore, DirectStateStore, InMemoryState},
+    Quota, RateLimiter,
+};
+
+/// The number of threads that the multi-threaded benchmarks check a rate limiter from.
+pub const THREADS: u32 = 20;
+
+/// Benchmarks `check` and `check_bare` on a direct rate limiter with the state store `S`, in
+/// the `single_threaded` group.
+pub fn single_threaded_direct<S>(c: &mut Criterion)
+where
+    S: DirectStateStore + Default + 'static,
+{
+    let mut group = c.benchmark_group("single_threaded");
+    group.throughput(Throughput::Elements(1));
+    bench_store::<S, _>(&mut group, "direct", |b| {
+        let clock = FakeRelativeClock::default();
+        let step = Duration::from_millis(20);
+        let rl: RateLimiter<_, S, _, NoOpMiddleware<Nanos>> = RateLimiter::new(
+            Quota::per_second(nonzero!(50u32)),
+            S::default(),
+            clock.clone(),
+        );
+        b.iter_batched(
+            || {
+                clock.advance(step);
+            },
+            |()| {
+                black_box(rl.check().is_ok());
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    bench_store::<S, _>(&mut group, "direct_bare", |b| {
+        let clock = FakeRelativeClock::default();
+        let step = Duration::from_millis(20);
+        let rl: RateLimiter<_, S, _, NoOpMiddleware<Nanos>> = RateLimiter::new(
+            Quota::per_second(nonzero!(50u32)),
+            S::default(),
+            clock.clone(),
+        );
+        b.iter_batched(
+            || {
+                clock.advance(step);
+            },
+            |()| {
+                black_box(rl.check_bare().is_ok());
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    group.finish();
+}
+
+/// Benchmarks `check_key` on a keyed rate limiter with the state store `S`, in the
+/// `single_threaded` group.
+///
+/// `keyed` checks the same key over and over; `keyed_new_key` checks a key that the rate limiter
+/// hasn't seen yet.
+pub fn single_threaded_keyed<S>(c: &mut Criterion)
+where
+    S: KeyedStateStore<u32> + Default + 'static,
+{
+    let mut group = c.benchmark_group("single_threaded");
+    group.throughput(Throughput::Elements(1));
+    bench_store::<S, _>(&mut group, "keyed", |b| {
+        let clock = FakeRelativeClock::default();
+        let step = Duration::from_millis(20);
+        let rl: RateLimiter<_, S, _, NoOpMiddleware<Nanos>> = RateLimiter::new(
+            Quota::per_second(nonzero!(50u32)),
+            S::default(),
+            clock.clone(),
+        );
+        b.iter_batched(
+            || {
+                clock.advance(step);
+            },
+            |()| {
+                black_box(rl.check_key(&1u32).is_ok());
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    bench_store::<S, _>(&mut group, "keyed_new_key", |b| {
+        let quota = Quota::per_second(nonzero!(50u32));
+        b.iter_batched(
+            || {
+                RateLimiter::<_, S, _, NoOpMiddleware<Nanos>>::new(
+                    quota,
+                    S::default(),
+                    FakeRelativeClock::default(),
+                )
+            },
+            |rl| {
+                black_box(rl.check_key(&1u32).is_ok());
+                // Dropping the rate limiter isn't part of the measurement:
+                rl
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    group.finish();
+}
+
+/// Benchmarks [`THREADS`] threads calling `check` on a direct rate limiter with the state store
+/// `S` and `clock`, in the `multi_threaded` group.
+pub fn multi_threaded_direct<S, C>(c: &mut Criterion, clock: &C)
+where
+    S: DirectStateStore + Default + Send + Sync + 'static,
+    C: Clock + Clone + Send + Sync + 'static,
+{
+    let mut group = c.benchmark_group("multi_threaded");
+    group.throughput(Throughput::Elements(1));
+    bench_store::<S, _>(&mut group, "direct", |b| {
+        b.iter_custom(|iters| {
+            let lim: RateLimiter<_, S, _, NoOpMiddleware<C::Instant>> = RateLimiter::new(
+                Quota::per_second(nonzero!(50u32)),
+                S::default(),
+                clock.clone(),
+            );
+            race(Arc::new(lim), iters, |lim, _| {
+                black_box(lim.check().is_ok());
+            })
+        })
+    });
+    group.finish();
+}
+
+/// Benchmarks [`THREADS`] threads calling `check_key` on a keyed rate limiter with the state
+/// store `S` and `clock`, in the `multi_threaded` group.
+///
+/// Each thread checks three keys per iteration.
+pub fn multi_threaded_keyed<S, C>(c: &mut Criterion, clock: &C)
+where
+    S: KeyedStateStore<u32> + Default + Send + Sync + 'static,
+    C: Clock + Clone + Send + Sync + 'static,
+{
+    let mut group = c.benchmark_group("multi_threaded");
+    group.throughput(Throughput::Elements(3));
+    bench_store::<S, _>(&mut group, "keyed", |b| {
+        b.iter_custom(|iters| {
+            let lim: RateLimiter<_, S, _, NoOpMiddleware<C::Instant>> = RateLimiter::new(
+                Quota::per_second(nonzero!(50u32)),
+                S::default(),
+                clock.clone(),
+            );
+            race(Arc::new(lim), iters, |lim, _| {
+                black_box(lim.check_key(&1u32).is_ok());
+                black_box(lim.check_key(&2u32).is_ok());
+                black_box(lim.check_key(&3u32).is_ok());
+            })
+        })
+    });
+    group.finish();
+}
+
+/// Benchmarks [`THREADS`] threads peeking at a direct rate limiter with the state store `S` and
+/// `clock`, in the `multi_threaded` group.
+///
+/// In `direct_peek`, all threads call `check_only`; in `direct_peek_contended`, every other
+/// thread calls `check` instead, so the peeks read a state that keeps changing.
+pub fn multi_threaded_direct_peek<S, C>(c: &mut Criterion, clock: &C)
+where
+    S: DirectStateStore + Default + Send + Sync + 'static,
+    C: Clock + Clone + Send + Sync + 'static,
+{
+    let mut group = c.benchmark_group("multi_threaded");
+    group.throughput(Throughput::Elements(1));
+    for (name, contended) in [("direct_peek", false), ("direct_peek_contended", true)] {
+        bench_store::<S, _>(&mut group, name, |b| {
+            b.iter_custom(|iters| {
+                let lim: RateLimiter<_, S, _, NoOpMiddleware<C::Instant>> = RateLimiter::new(
+                    Quota::per_second(nonzero!(50u32)),
+                    S::default(),
+                    clock.clone(),
+                );
+                race(Arc::new(lim), iters, move |lim, thread| {
+                    if contended && thread % 2 == 1 {
+                        black_box(lim.check().is_ok());
+                    } else {
+                        black_box(lim.check_only().is_ok());
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Benchmarks [`THREADS`] threads peeking at a keyed rate limiter with the state store `S` and
+/// `clock`, in the `multi_threaded` group.
+///
+/// Each thread peeks at (or checks) three keys per iteration. In `keyed_peek`, all threads call
+/// `check_key_only`; in `keyed_peek_contended`, every other thread calls `check_key` instead.
+pub fn multi_threaded_keyed_peek<S, C>(c: &mut Criterion, clock: &C)
+where
+    S: KeyedStateStore<u32> + Default + Send + Sync + 'static,
+    C: Clock + Clone + Send + Sync + 'static,
+{
+    let mut group = c.benchmark_group("multi_threaded");
+    group.throughput(Throughput::Elements(3));
+    for (name, contended) in [("keyed_peek", false), ("keyed_peek_contended", true)] {
+        bench_store::<S, _>(&mut group, name, |b| {
+            b.iter_custom(|iters| {
+                let lim: RateLimiter<_, S, _, NoOpMiddleware<C::Instant>> = RateLimiter::new(
+                    Quota::per_second(nonzero!(50u32)),
+                    S::default(),
+                    clock.clone(),
+                );
+                race(Arc::new(lim), iters, move |lim, thread| {
+                    for key in 1u32..=3 {
+                        if contended && thread % 2 == 1 {
+                            black_box(lim.check_key(&key).is_ok());
+                        } else {
+                            black_box(lim.check_key_only(&key).is_ok());
+                        }
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Benchmarks a direct rate limiter's checks against `clock`, in the `realtime_clock` group.
+///
+/// `mostly_allow` and `mostly_allow_bare` check a rate limiter that allows `u32::MAX` cells per
+/// nanosecond with `check` and `check_bare`; `mostly_deny`, `mostly_deny_bare` and
+/// `mostly_deny_fast` check one that allows one cell per hour with `check`, `check_bare` and
+/// `check_fast`.
+pub fn realtime_clock<C: Clock + Clone>(c: &mut Criterion, clock: &C) {
+    #[allow(deprecated)]
+    let allowing = Quota::new(nonzero!(u32::MAX), Duration::from_nanos(1)).unwrap();
+    let denying = Quota::per_hour(nonzero!(1u32));
+    let id = |name| BenchmarkId::new(name, type_name::<C>());
+
+    let mut group = c.benchmark_group("realtime_clock");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function(id("mostly_allow"), |b| {
+        let rl = RateLimiter::direct_with_clock(allowing, clock.clone());
+        b.iter(|| {
+            black_box(rl.check().is_ok());
+        });
+    });
+    group.bench_function(id("mostly_allow_bare"), |b| {
+        let rl = RateLimiter::direct_with_clock(allowing, clock.clone());
+        b.iter(|| {
+            black_box(rl.check_bare().is_ok());
+        });
+    });
+    group.bench_function(id("mostly_deny"), |b| {
+        let rl = RateLimiter::direct_with_clock(denying, clock.clone());
+        b.iter(|| {
+            black_box(rl.check().is_ok());
+        });
+    });
+    group.bench_function(id("mostly_deny_bare"), |b| {
+        let rl = RateLimiter::direct_with_clock(denying, clock.clone());
+        b.iter(|| {
+            black_box(rl.check_bare().is_ok());
+        });
+    });
+    group.bench_function(id("mostly_deny_fast"), |b| {
+        let rl = RateLimiter::direct_with_clock(denying, clock.clone());
+        b.iter(|| {
+            black_box(rl.check_fast());
+        });
+    });
+    group.finish();
+}
+
+/// Adds the benchmark `name` of the state store `S` to `group`, with the type of `S` as its
+/// parameter, unless it is the default [`InMemoryState`].
+fn bench_store<S: 'static, F>(group: &mut BenchmarkGroup<'_, WallTime>, name: &str, f: F)
+where
+    F: FnMut(&mut Bencher<'_, WallTime>),
+{
+    if TypeId::of::<S>() == TypeId::of::<InMemoryState>() {
+        group.bench_function(name, f);
+    } else {
+        group.bench_function(BenchmarkId::new(name, type_name::<S>()), f);
+    }
+}
+
+/// Runs `check` `iters` times on each of [`THREADS`] threads, and returns how long it took
+/// until the last thread finished. `check` gets the index of the thread it runs on.
+fn race<L, F>(lim: Arc<L>, iters: u64, check: F) -> Duration
+where
+    L: Send + Sync + 'static,
+    F: Fn(&L, u32) + Copy + Send + 'static,
+{
+    let mut children = vec![];
+    let start = Instant::now();
+    for index in 0..THREADS {
+        let lim = Arc::clone(&lim);
+        children.push(thread::spawn(move || {
+            for _i in 0..iters {
+                check(&lim, index);
+            }
+        }));
+    }
+    for child in children {
+        child.join().unwrap()
+    }
+    start.elapsed()
+}
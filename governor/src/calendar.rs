@@ -0,0 +1,279 @@
+//! Rate limiters whose quota is tied to calendar days in a time zone.
+//!
+//! A GCRA [`Quota`][crate::Quota] of 100 cells per day replenishes
+//! continuously: one cell becomes available again every 14.4 minutes,
+//! and the limit applies to any rolling 24-hour window. Some quotas
+//! are instead defined as "100 per calendar day": The full allowance
+//! becomes available at local midnight, no matter how it was used up
+//! on the previous day.
+//!
+//! The [`CalendarDayRateLimiter`] implements these quotas as a fixed
+//! window that starts at midnight in a [`chrono::TimeZone`]. Days are
+//! determined by the local date, so days that are 23 or 25 hours long
+//! (because of daylight saving time transitions) are handled
+//! correctly, as are time zones where midnight does not exist on the
+//! day a transition happens.
+//!
+//! ```rust
+//! # use nonzero_ext::nonzero;
+//! use chrono::FixedOffset;
+//! use governor::calendar::CalendarDayRateLimiter;
+//!
+//! let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+//! let lim = CalendarDayRateLimiter::new(nonzero!(2u32), tz);
+//! assert!(lim.check().is_ok());
+//! assert!(lim.check().is_ok());
+//! // Anything further has to wait until midnight (UTC+2):
+//! assert!(lim.check().is_err());
+//! ```
+
+use std::prelude::v1::*;
+
+use std::fmt;
+use std::num::NonZeroU32;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Datelike, LocalResult, NaiveDate, NaiveTime, TimeZone, Utc};
+use portable_atomic::AtomicU64;
+
+use crate::clock::{Clock, SystemClock};
+use crate::errors::InsufficientCapacity;
+
+/// A negative decision from a [`CalendarDayRateLimiter`].
+///
+/// Indicates the start of the next calendar day, when the quota is
+/// available again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalendarNotUntil<Tz: TimeZone> {
+    earliest: DateTime<Tz>,
+}
+
+impl<Tz: TimeZone> CalendarNotUntil<Tz> {
+    /// Returns the start of the calendar day on which a decision can
+    /// be conforming again, in the rate limiter's time zone.
+    pub fn earliest_possible(&self) -> DateTime<Tz> {
+        self.earliest.clone()
+    }
+
+    /// Returns the minimum amount of time from `from` that must pass
+    /// before a decision can be conforming.
+    ///
+    /// If the start of the next calendar day is in the past, returns
+    /// a zero `Duration`.
+    pub fn wait_time_from(&self, from: SystemTime) -> Duration {
+        SystemTime::from(self.earliest.with_timezone(&Utc))
+            .duration_since(from)
+            .unwrap_or_default()
+    }
+}
+
+impl<Tz: TimeZone> fmt::Display for CalendarNotUntil<Tz>
+where
+    Tz::Offset: fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rate-limited until {}", self.earliest)
+    }
+}
+
+/// A rate limiter that allows a number of cells per calendar day in a
+/// given time zone.
+///
+/// The allowance is restored in full at each local midnight. See the
+/// [module documentation][self] for how this differs from a regular
+/// [`RateLimiter`][crate::RateLimiter].
+///
+/// The time zone can be any [`chrono::TimeZone`]: a
+/// [`FixedOffset`][chrono::FixedOffset], or one from a time zone
+/// database like `chrono-tz`, which takes daylight saving time into
+/// account.
+pub struct CalendarDayRateLimiter<Tz: TimeZone, C: Clock<Instant = SystemTime> = SystemClock> {
+    max_per_day: NonZeroU32,
+    tz: Tz,
+    clock: C,
+
+    /// The calendar day (as days since the common era) in the upper
+    /// 32 bits, the number of cells admitted on that day in the lower.
+    state: AtomicU64,
+}
+
+impl<Tz: TimeZone> CalendarDayRateLimiter<Tz, SystemClock> {
+    /// Constructs a rate limiter that allows `max_per_day` cells per
+    /// calendar day in the time zone `tz`, using the system clock.
+    pub fn new(max_per_day: NonZeroU32, tz: Tz) -> Self {
+        Self::with_clock(max_per_day, tz, SystemClock)
+    }
+}
+
+impl<Tz: TimeZone, C: Clock<Instant = SystemTime>> CalendarDayRateLimiter<Tz, C> {
+    /// Constructs a rate limiter that allows `max_per_day` cells per
+    /// calendar day in the time zone `tz`, with a custom wall clock.
+    pub fn with_clock(max_per_day: NonZeroU32, tz: Tz, clock: C) -> Self {
+        CalendarDayRateLimiter {
+            max_per_day,
+            tz,
+            clock,
+            state: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the time zone whose calendar days the rate limiter uses.
+    pub fn time_zone(&self) -> &Tz {
+        &self.tz
+    }
+
+    /// Returns the number of cells allowed per calendar day.
+    pub fn max_per_day(&self) -> NonZeroU32 {
+        self.max_per_day
+    }
+
+    /// Allow a single cell through the rate limiter.
+    ///
+    /// If the day's allowance is used up, `check` returns the start
+    /// of the next calendar day.
+    pub fn check(&self) -> Result<(), CalendarNotUntil<Tz>> {
+        self.admit(1)
+    }
+
+    /// Allow *only all* `n` cells through the rate limiter.
+    ///
+    /// This method can succeed in only one way and fail in two ways:
+    /// * Success: If all `n` cells fit into the remaining allowance
+    ///   for the day, it returns `Ok(Ok(()))`.
+    /// * Failure (but ok): Not all cells fit into the remaining
+    ///   allowance. The result is `Ok(Err(CalendarNotUntil))`.
+    /// * Failure (the batch can never go through): `n` is larger
+    ///   than the daily allowance. The result is
+    ///   `Err(InsufficientCapacity)`.
+    pub fn check_n(
+        &self,
+        n: NonZeroU32,
+    ) -> Result<Result<(), CalendarNotUntil<Tz>>, InsufficientCapacity> {
+        let max = self.max_per_day.get();
+        if n.get() > max {
+            return Err(InsufficientCapacity(max));
+        }
+        Ok(self.admit(n.get()))
+    }
+
+    /// Returns the number of cells that can still be let through on
+    /// the current calendar day.
+    pub fn remaining(&self) -> u32 {
+        let (day, used) = unpack(self.state.load(Ordering::Acquire));
+        if day >= self.today() {
+            self.max_per_day.get() - used
+        } else {
+            self.max_per_day.get()
+        }
+    }
+
+    /// Admits `n` cells (at most the daily allowance) if they fit
+    /// into the current day's remaining allowance.
+    fn admit(&self, n: u32) -> Result<(), CalendarNotUntil<Tz>> {
+        let max = self.max_per_day.get();
+        let today = self.today();
+        let mut prev = self.state.load(Ordering::Acquire);
+        loop {
+            let (day, used) = unpack(prev);
+            // If the clock was set back across midnight, keep counting
+            // against the later day rather than granting a new allowance:
+            let (day, used) = if day >= today {
+                (day, used)
+            } else {
+                (today, 0)
+            };
+            if max - used < n {
+                return Err(CalendarNotUntil {
+                    earliest: self.start_of_day_after(day),
+                });
+            }
+            match self.state.compare_exchange_weak(
+                prev,
+                pack(day, used + n),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(next_prev) => prev = next_prev,
+            }
+        }
+    }
+
+    /// Returns the current calendar day in the rate limiter's time zone.
+    fn today(&self) -> u32 {
+        let now = DateTime::<Utc>::from(self.clock.now()).with_timezone(&self.tz);
+        day_number(now.date_naive())
+    }
+
+    /// Returns the first instant of the calendar day following `day`.
+    fn start_of_day_after(&self, day: u32) -> DateTime<Tz> {
+        let date = NaiveDate::from_num_days_from_ce_opt((day as i32).saturating_add(1))
+            .unwrap_or(NaiveDate::MAX);
+        start_of_day(&self.tz, date)
+    }
+}
+
+impl<Tz: TimeZone, C: Clock<Instant = SystemTime>> fmt::Debug for CalendarDayRateLimiter<Tz, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (day, used) = unpack(self.state.load(Ordering::Relaxed));
+        f.debug_struct("CalendarDayRateLimiter")
+            .field("max_per_day", &self.max_per_day)
+            .field("day", &day)
+            .field("used", &used)
+            .finish()
+    }
+}
+
+/// Returns the first instant of `date` in the time zone `tz`.
+///
+/// Usually, this is midnight. If midnight falls into a daylight
+/// saving time gap, the day starts at the end of the gap; if it is
+/// ambiguous, the day starts at its earlier occurrence.
+fn start_of_day<Tz: TimeZone>(tz: &Tz, date: NaiveDate) -> DateTime<Tz> {
+    let mut local = date.and_time(NaiveTime::MIN);
+    loop {
+        match tz.from_local_datetime(&local) {
+            LocalResult::Single(t) => return t,
+            LocalResult::Ambiguous(earliest, _) => return earliest,
+            LocalResult::None => local += chrono::Duration::minutes(1),
+        }
+    }
+}
+
+fn day_number(date: NaiveDate) -> u32 {
+    // Days before the common era are clamped; nobody should be
+    // rate-limiting then.
+    date.num_days_from_ce().max(0) as u32
+}
+
+fn pack(day: u32, used: u32) -> u64 {
+    ((day as u64) << 32) | used as u64
+}
+
+fn unpack(state: u64) -> (u32, u32) {
+    ((state >> 32) as u32, state as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::FixedOffset;
+
+    #[test]
+    fn packing_roundtrips() {
+        assert_eq!(unpack(pack(739_000, 17)), (739_000, 17));
+        assert_eq!(unpack(pack(u32::MAX, u32::MAX)), (u32::MAX, u32::MAX));
+        assert_eq!(unpack(0), (0, 0));
+    }
+
+    #[test]
+    fn calendar_impls_coverage() {
+        let tz = FixedOffset::east_opt(0).unwrap();
+        let lim = CalendarDayRateLimiter::new(nonzero_ext::nonzero!(1u32), tz);
+        assert!(format!("{:?}", lim).contains("CalendarDayRateLimiter"));
+        let negative = lim.check().and_then(|_| lim.check()).unwrap_err();
+        assert!(format!("{}", negative).starts_with("rate-limited until"));
+        assert_eq!(negative.clone(), negative);
+    }
+}
@@ -46,7 +46,6 @@
 
 use std::prelude::v1::*;
 
-use std::convert::TryInto;
 use std::fmt::Debug;
 use std::ops::Add;
 use std::sync::atomic::Ordering;
@@ -88,19 +87,18 @@ impl Reference for Duration {
     /// # use std::time::Duration;
     /// # use governor::clock::Reference;
     /// let diff = Duration::from_secs(20).duration_since(Duration::from_secs(10));
-    /// assert_eq!(diff, Duration::from_secs(10).into());
+    /// assert_eq!(Duration::from(diff), Duration::from_secs(10));
     /// ```
     fn duration_since(&self, earlier: Self) -> Nanos {
-        self.checked_sub(earlier)
-            .unwrap_or_else(|| Duration::new(0, 0))
-            .into()
+        Nanos::saturating_from(Duration::saturating_sub(*self, earlier))
     }
 
     /// The internal duration between this point and another.
     /// ```rust
     /// # use std::time::Duration;
     /// # use governor::clock::Reference;
-    /// let diff = Reference::saturating_sub(&Duration::from_secs(20), Duration::from_secs(10).into());
+    /// # use governor::nanos::Nanos;
+    /// let diff = Reference::saturating_sub(&Duration::from_secs(20), Nanos::saturating_from(Duration::from_secs(10)));
     /// assert_eq!(diff, Duration::from_secs(10));
     /// ```
     fn saturating_sub(&self, duration: Nanos) -> Self {
@@ -124,28 +122,70 @@ impl Add<Nanos> for Duration {
 /// # Thread safety
 /// The mock time is represented as an atomic u64 count of nanoseconds, behind an [`Arc`].
 /// Clones of this clock will all show the same time, even if the original advances.
+///
+/// # Example
+/// ```rust
+/// # use std::time::Duration;
+/// use governor::{clock::{Clock, FakeRelativeClock}, nanos::Nanos};
+///
+/// let clock = FakeRelativeClock::default();
+/// assert_eq!(clock.advance(Duration::from_secs(1)), Nanos::from(1_000_000_000));
+/// clock.set(Duration::from_millis(10));
+/// assert_eq!(clock.now(), Nanos::from(10_000_000));
+///
+/// // Let every reading advance the clock:
+/// clock.auto_advance(Duration::from_millis(1));
+/// assert_eq!(clock.now(), Nanos::from(10_000_000));
+/// assert_eq!(clock.now(), Nanos::from(11_000_000));
+/// ```
 #[derive(Debug, Clone, Default)]
 pub struct FakeRelativeClock {
     now: Arc<AtomicU64>,
+    auto_advance: Arc<AtomicU64>,
 }
 
 impl FakeRelativeClock {
-    /// Advances the fake clock by the given amount.
-    pub fn advance(&self, by: Duration) {
-        let by: u64 = by
-            .as_nanos()
-            .try_into()
-            .expect("Can not represent times past ~584 years");
+    /// Advances the fake clock by the given amount, returning the time it advanced to.
+    ///
+    /// If several threads advance the clock at once, each of them gets the time that its own
+    /// advance resulted in.
+    pub fn advance(&self, by: Duration) -> Nanos {
+        let by = Nanos::saturating_from(by).as_u64();
+        self.advance_nanos(by).1.into()
+    }
 
+    /// Sets the fake clock to the given time.
+    ///
+    /// Unlike [`advance`](#method.advance), this can move the clock backwards. Rate limiters
+    /// treat readings that are earlier than their previous ones as if no time had passed.
+    pub fn set(&self, to: Duration) {
+        self.now
+            .store(Nanos::saturating_from(to).as_u64(), Ordering::Release);
+    }
+
+    /// Makes the fake clock advance by `step` each time it is read, starting with the next
+    /// reading after this one. A `step` of zero stops the clock from advancing by itself.
+    ///
+    /// This is useful for soak tests that run many rate limiter decisions without advancing
+    /// the clock by hand. Each reading returns the time before the step, so readings from any
+    /// number of threads are distinct.
+    pub fn auto_advance(&self, step: Duration) {
+        self.auto_advance
+            .store(Nanos::saturating_from(step).as_u64(), Ordering::Release);
+    }
+
+    /// Advances the clock by `by` nanoseconds, returning the time before and after.
+    fn advance_nanos(&self, by: u64) -> (u64, u64) {
         let mut prev = self.now.load(Ordering::Acquire);
-        let mut next = prev + by;
+        let mut next = prev.saturating_add(by);
         while let Err(next_prev) =
             self.now
                 .compare_exchange_weak(prev, next, Ordering::Release, Ordering::Relaxed)
         {
             prev = next_prev;
-            next = prev + by;
+            next = prev.saturating_add(by);
         }
+        (prev, next)
     }
 }
 
@@ -170,15 +210,36 @@ impl Clock for FakeRelativeClock {
     type Instant = Nanos;
 
     fn now(&self) -> Self::Instant {
-        self.now.load(Ordering::Relaxed).into()
+        match self.auto_advance.load(Ordering::Relaxed) {
+            0 => self.now.load(Ordering::Relaxed).into(),
+            step => self.advance_nanos(step).0.into(),
+        }
     }
 }
 
+mod manual;
+pub use manual::ManualClock;
+
 #[cfg(feature = "std")]
 mod with_std;
 #[cfg(feature = "std")]
 pub use with_std::*;
 
+#[cfg(feature = "std")]
+mod delay;
+#[cfg(feature = "std")]
+pub use delay::*;
+
+#[cfg(feature = "std")]
+mod cached;
+#[cfg(feature = "std")]
+pub use cached::CachedClock;
+
+#[cfg(feature = "wasm")]
+mod web;
+#[cfg(feature = "wasm")]
+pub use web::WebClock;
+
 #[cfg(all(feature = "std", feature = "quanta"))]
 mod quanta;
 #[cfg(all(feature = "std", feature = "quanta"))]
@@ -192,7 +253,6 @@ pub use default::*;
 mod test {
     use super::*;
     use crate::nanos::Nanos;
-    use std::iter::repeat;
     use std::sync::Arc;
     use std::thread;
     use std::time::Duration;
@@ -200,8 +260,7 @@ mod test {
     #[test]
     fn fake_clock_parallel_advances() {
         let clock = Arc::new(FakeRelativeClock::default());
-        let threads = repeat(())
-            .take(10)
+        let threads = (0..10)
             .map(move |_| {
                 let clock = Arc::clone(&clock);
                 thread::spawn(move || {
@@ -218,6 +277,29 @@ mod test {
         }
     }
 
+    #[test]
+    fn fake_clock_parallel_auto_advances() {
+        let clock = FakeRelativeClock::default();
+        clock.auto_advance(Duration::from_nanos(1));
+        let threads = (0..10)
+            .map(|_| {
+                let clock = clock.clone();
+                thread::spawn(move || (0..1000).map(|_| clock.now()).collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        let mut readings: Vec<Nanos> = threads
+            .into_iter()
+            .flat_map(|t| t.join().unwrap())
+            .collect();
+        readings.sort();
+        readings.dedup();
+        assert_eq!(readings.len(), 10 * 1000);
+
+        clock.auto_advance(Duration::ZERO);
+        assert_eq!(clock.now(), clock.now());
+        assert_eq!(clock.now(), Nanos::new(10 * 1000));
+    }
+
     #[test]
     fn duration_addition_coverage() {
         let d = Duration::from_secs(1);
@@ -0,0 +1,134 @@
+use std::prelude::v1::*;
+
+use std::fmt;
+use std::io;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use portable_atomic::AtomicU64;
+
+use super::{Clock, ReasonablyRealtime, Reference};
+use crate::nanos::Nanos;
+
+/// A clock that reads another clock in regular intervals, and returns the most recent reading.
+///
+/// Reading a clock can dominate the cost of cheap rate-limiting decisions. This clock makes
+/// reading it as cheap as an atomic load: An upkeep thread reads the wrapped clock once every
+/// `granularity` and stores the reading, which [`now`](#method.now) then returns. The upkeep
+/// thread is stopped once the last clone of the clock is dropped.
+///
+/// # Correctness
+/// The time returned by this clock lags behind the wrapped clock by up to `granularity` (and
+/// more, if the upkeep thread doesn't get scheduled in time). Rate limiters using it make
+/// their decisions as if they were made at that earlier time, which makes them a little more
+/// strict than necessary: Cells may be denied (and waits may be longer) by up to the lag.
+///
+/// The time also advances in steps. When a rate limiter's replenishment interval is shorter
+/// than `granularity`, all the cells replenished during one step become available at once, in
+/// bursts of about `granularity / interval` cells (on top of the quota's burst size). Pick a
+/// granularity well below the replenishment interval of the quotas you use.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// use governor::{clock::{CachedClock, MonotonicClock}, Quota, RateLimiter};
+///
+/// let clock = CachedClock::new(MonotonicClock, Duration::from_micros(100)).unwrap();
+/// let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(50u32)), clock);
+/// assert_eq!(Ok(()), lim.check());
+/// ```
+pub struct CachedClock<C: Clock> {
+    reference: C::Instant,
+    offset: Arc<AtomicU64>,
+    granularity: Duration,
+}
+
+impl<C> CachedClock<C>
+where
+    C: Clock + Send + 'static,
+{
+    /// Returns a clock that reads `inner` once every `granularity`, on an upkeep thread.
+    ///
+    /// Returns an error if the upkeep thread could not be started.
+    pub fn new(inner: C, granularity: Duration) -> io::Result<CachedClock<C>> {
+        let reference = inner.now();
+        let offset = Arc::new(AtomicU64::new(0));
+        let weak = Arc::downgrade(&offset);
+        thread::Builder::new()
+            .name("governor-cached-clock".to_string())
+            .spawn(move || loop {
+                thread::sleep(granularity);
+                let Some(offset) = weak.upgrade() else {
+                    return;
+                };
+                let now = inner.now().duration_since(reference).as_u64();
+                offset.fetch_max(now, Ordering::Release);
+            })?;
+        Ok(CachedClock {
+            reference,
+            offset,
+            granularity,
+        })
+    }
+}
+
+impl<C: Clock> CachedClock<C> {
+    /// Returns the interval in which the wrapped clock gets read.
+    pub fn granularity(&self) -> Duration {
+        self.granularity
+    }
+}
+
+impl<C: Clock> Clock for CachedClock<C> {
+    type Instant = C::Instant;
+
+    fn now(&self) -> Self::Instant {
+        self.reference + Nanos::from(self.offset.load(Ordering::Acquire))
+    }
+}
+
+impl<C: ReasonablyRealtime> ReasonablyRealtime for CachedClock<C> {}
+
+impl<C: Clock> Clone for CachedClock<C> {
+    fn clone(&self) -> Self {
+        CachedClock {
+            reference: self.reference,
+            offset: Arc::clone(&self.offset),
+            granularity: self.granularity,
+        }
+    }
+}
+
+impl<C: Clock> fmt::Debug for CachedClock<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedClock")
+            .field("now", &self.now())
+            .field("granularity", &self.granularity)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::FakeRelativeClock;
+
+    #[test]
+    fn lags_behind_inner_clock() {
+        let inner = FakeRelativeClock::default();
+        let clock = CachedClock::new(inner.clone(), Duration::from_millis(1)).unwrap();
+        assert_eq!(clock.now(), Nanos::from(0));
+
+        inner.advance(Duration::from_secs(1));
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while clock.now() == Nanos::from(0) && std::time::Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(clock.clone().now(), Nanos::from(1_000_000_000));
+        assert_eq!(clock.granularity(), Duration::from_millis(1));
+        assert!(format!("{:?}", clock).contains("CachedClock"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::prelude::v1::*;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use super::{Clock, FakeRelativeClock, ReasonablyRealtime};
+use crate::timer;
+
+/// Identifies clocks that asynchronous rate limiter methods can wait on.
+///
+/// Methods like [`until_ready`](crate::RateLimiter::until_ready) and
+/// [`until_key_ready`](crate::RateLimiter::until_key_ready) check the rate limiter, and wait
+/// for a delay provided by its clock whenever the check is negative. Every
+/// [`ReasonablyRealtime`] clock waits on governor's timer; [`FakeRelativeClock`] waits by
+/// advancing itself, which lets tests run the asynchronous methods deterministically, without
+/// actually waiting. (The stream and sink combinators, and the blocking iterators, still need
+/// a [`ReasonablyRealtime`] clock.)
+///
+/// ```rust
+/// # use futures_executor::block_on;
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// use governor::{clock::{Clock, FakeRelativeClock}, nanos::Nanos, Quota, RateLimiter};
+///
+/// let clock = FakeRelativeClock::default();
+/// let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+/// block_on(async {
+///     lim.until_key_ready(&"alice").await;
+///     lim.until_key_ready(&"alice").await;
+///     assert_eq!(clock.now(), Nanos::from(0));
+///     lim.until_key_ready(&"alice").await;
+///     assert_eq!(Duration::from(clock.now()), Duration::from_millis(500));
+/// });
+/// ```
+pub trait DelayProvider: Clock {
+    /// The future that waits out a delay.
+    type Delay: Future<Output = ()>;
+
+    /// Returns a future that resolves once `duration` has passed on this clock.
+    fn delay(&self, duration: Duration) -> Self::Delay;
+}
+
+impl<C: ReasonablyRealtime> DelayProvider for C {
+    type Delay = RealtimeDelay;
+
+    fn delay(&self, duration: Duration) -> RealtimeDelay {
+        RealtimeDelay(timer::Delay::new(duration))
+    }
+}
+
+/// A future that resolves after a duration has passed in real time, on governor's timer.
+///
+/// The timer is [`futures_timer::Delay`] by default, or the `async-io` reactor's timer with the
+/// `smol` or `async-std` features.
+#[derive(Debug)]
+pub struct RealtimeDelay(timer::Delay);
+
+impl Future for RealtimeDelay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl DelayProvider for FakeRelativeClock {
+    type Delay = FakeDelay;
+
+    fn delay(&self, duration: Duration) -> FakeDelay {
+        FakeDelay {
+            clock: self.clone(),
+            duration: Some(duration),
+        }
+    }
+}
+
+/// A future that advances a [`FakeRelativeClock`] by a duration, and resolves right away.
+///
+/// The clock advances when the future is first polled, so a delay that is dropped unpolled
+/// doesn't affect the clock.
+#[derive(Debug)]
+pub struct FakeDelay {
+    clock: FakeRelativeClock,
+    duration: Option<Duration>,
+}
+
+impl Future for FakeDelay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(duration) = self.duration.take() {
+            self.clock.advance(duration);
+        }
+        Poll::Ready(())
+    }
+}
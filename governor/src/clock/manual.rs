@@ -0,0 +1,113 @@
+use std::prelude::v1::*;
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use portable_atomic::AtomicU64;
+
+use super::Clock;
+use crate::nanos::Nanos;
+
+/// A clock that reports whatever time its owner last set.
+///
+/// Some hosts don't offer a clock that rate limiters could read whenever they make a decision,
+/// but tell each request the time it arrived at. Cloudflare Workers, for example, have no
+/// monotonic clock, and `Date.now()` only advances between requests. A `ManualClock` lets such
+/// code keep rate limiters across requests (e.g. in a Durable Object) anyway: Each request sets
+/// the clock to its time with [`set_now`](ManualClock::set_now) before checking any rate
+/// limiters, which then measure time from that.
+///
+/// Wall-clock times like `Date.now()` can jump backwards (e.g. when the host's clock gets
+/// adjusted). To make sure that rate limiters never see time run backwards, the clock only ever
+/// moves forward: setting it to an earlier time than it already shows keeps it where it is,
+/// as if no time had passed.
+///
+/// # Thread safety
+/// Like the [`FakeRelativeClock`](super::FakeRelativeClock), the time is an atomic u64 count of
+/// nanoseconds, behind an [`Arc`]: Clones of this clock all show the same time. Hosts that
+/// handle several requests at once should give each rate limiter's clock the latest time of
+/// any of them; a request that arrived earlier then gets checked at a slightly later time.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{
+///     clock::ManualClock, middleware::NoOpMiddleware, nanos::Nanos,
+///     state::keyed::HashMapStateStore, Quota, RateLimiter,
+/// };
+///
+/// type UserLimiter =
+///     RateLimiter<String, HashMapStateStore<String>, ManualClock, NoOpMiddleware<Nanos>>;
+///
+/// /// State that lives as long as the Durable Object, across requests.
+/// struct Limits {
+///     clock: ManualClock,
+///     per_user: UserLimiter,
+/// }
+///
+/// impl Limits {
+///     fn new() -> Self {
+///         let clock = ManualClock::default();
+///         let quota = Quota::per_second(nonzero!(2u32));
+///         let per_user = RateLimiter::hashmap_with_clock(quota, clock.clone());
+///         Limits { clock, per_user }
+///     }
+///
+///     /// Handles a request that arrived at `date_now` (milliseconds since the UNIX epoch, as
+///     /// returned by `Date.now()`).
+///     fn handle(&self, date_now: f64, user: &str) -> u16 {
+///         self.clock.set_now_millis(date_now);
+///         match self.per_user.check_key(&user.to_string()) {
+///             Ok(()) => 200,
+///             Err(_) => 429,
+///         }
+///     }
+/// }
+///
+/// let limits = Limits::new();
+/// assert_eq!(limits.handle(1_700_000_000_000.0, "alice"), 200);
+/// assert_eq!(limits.handle(1_700_000_000_001.0, "alice"), 200);
+/// assert_eq!(limits.handle(1_700_000_000_002.0, "alice"), 429);
+/// assert_eq!(limits.handle(1_700_000_000_502.0, "alice"), 200);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Returns a clock that shows `now`.
+    pub fn new(now: Nanos) -> Self {
+        ManualClock {
+            now: Arc::new(AtomicU64::new(now.as_u64())),
+        }
+    }
+
+    /// Sets the clock to `now`, unless it already shows a later time. Returns the time that the
+    /// clock shows afterwards.
+    pub fn set_now(&self, now: Nanos) -> Nanos {
+        let prev = self.now.fetch_max(now.as_u64(), Ordering::AcqRel);
+        Nanos::from(prev.max(now.as_u64()))
+    }
+
+    /// Sets the clock to `millis` milliseconds, e.g. a time since the UNIX epoch from
+    /// JavaScript's `Date.now()`. Like [`set_now`](ManualClock::set_now), this never moves the
+    /// clock backwards; negative and NaN times leave it where it is.
+    pub fn set_now_millis(&self, millis: f64) -> Nanos {
+        // Converting whole and fractional milliseconds separately keeps sub-millisecond precision
+        // for times since the epoch. Float-to-integer casts saturate, and turn NaN into zero:
+        let whole = millis as u64;
+        let fraction = ((millis - whole as f64) * 1_000_000.0) as u64;
+        self.set_now(Nanos::from(
+            whole.saturating_mul(1_000_000).saturating_add(fraction),
+        ))
+    }
+}
+
+impl Clock for ManualClock {
+    type Instant = Nanos;
+
+    fn now(&self) -> Self::Instant {
+        self.now.load(Ordering::Acquire).into()
+    }
+}
@@ -2,7 +2,10 @@ use std::prelude::v1::*;
 
 use crate::clock::{Clock, ReasonablyRealtime, Reference};
 use crate::nanos::Nanos;
+use std::fmt;
+use std::num::NonZeroU32;
 use std::ops::Add;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -68,11 +71,17 @@ impl Reference for QuantaInstant {
 /// Whether this is faster than a [`QuantaClock`] depends on the utilization of the rate limiter
 /// and the upkeep interval that you pick; you should measure and compare performance before
 /// picking one or the other.
+///
+/// If the upkeep thread doesn't get to run (e.g. when the CPU is starved, or its container gets
+/// frozen), the time this clock reports stands still, so rate limiters stop replenishing their
+/// capacity until the thread catches up, and then replenish all of it at once. To notice and
+/// bridge such stalls, see [`with_stall_detection`](QuantaUpkeepClock::with_stall_detection).
 #[derive(Debug, Clone)]
 pub struct QuantaUpkeepClock {
     clock: quanta::Clock,
     _handle: Arc<quanta::Handle>,
     reference: quanta::Instant,
+    stalls: Option<Arc<StallDetector>>,
 }
 
 impl QuantaUpkeepClock {
@@ -91,18 +100,171 @@ impl QuantaUpkeepClock {
             clock,
             _handle: handle,
             reference,
+            stalls: None,
         })
     }
+
+    /// Makes the clock detect stalls of its upkeep thread, as configured by `detection`.
+    ///
+    /// ```rust
+    /// # use std::time::Duration;
+    /// use governor::clock::{QuantaUpkeepClock, StallDetection};
+    ///
+    /// let clock = QuantaUpkeepClock::from_interval(Duration::from_millis(1))?
+    ///     .with_stall_detection(
+    ///         StallDetection::new(Duration::from_millis(100))
+    ///             .on_stall(|lag| eprintln!("upkeep thread is {:?} behind", lag)),
+    ///     );
+    /// # Ok::<(), quanta::Error>(())
+    /// ```
+    pub fn with_stall_detection(self, detection: StallDetection) -> QuantaUpkeepClock {
+        QuantaUpkeepClock {
+            stalls: Some(Arc::new(StallDetector::new(detection))),
+            ..self
+        }
+    }
+}
+
+/// How a [`QuantaUpkeepClock`] detects and bridges stalls of its upkeep thread.
+///
+/// Every so many readings, the clock compares the time that the upkeep thread last stored with a
+/// synchronous read of the time (as a [`QuantaClock`] would make). If the upkeep thread's time
+/// lags behind by more than a threshold, the upkeep thread is considered stalled: The clock
+/// reports the anomaly to the [`on_stall`](StallDetection::on_stall) callback, and (unless
+/// that is [turned off](StallDetection::fall_back_to_synchronous)) reads the time
+/// synchronously until the upkeep thread has caught up again.
+///
+/// While the upkeep thread is stalled, every reading of the clock compares both times, so the
+/// clock is slower than a [`QuantaClock`] until the stall ends. When it ends, the clock's time
+/// may step back by up to the upkeep interval, as it goes back to the time that the upkeep
+/// thread stored.
+#[derive(Clone)]
+pub struct StallDetection {
+    threshold: Duration,
+    check_every: NonZeroU32,
+    fallback: bool,
+    on_stall: Option<Arc<dyn Fn(Duration) + Send + Sync>>,
+}
+
+impl StallDetection {
+    /// Considers the upkeep thread stalled if its time lags behind by more than `threshold`.
+    ///
+    /// The threshold should be a few times as long as the upkeep interval, so that regular
+    /// delays in waking up the upkeep thread don't count as stalls. By default, the clock checks
+    /// for stalls every 64 readings and falls back to reading the time synchronously during a
+    /// stall.
+    pub fn new(threshold: Duration) -> StallDetection {
+        StallDetection {
+            threshold,
+            check_every: nonzero_ext::nonzero!(64u32),
+            fallback: true,
+            on_stall: None,
+        }
+    }
+
+    /// Checks for stalls once every `readings` readings of the clock.
+    ///
+    /// Checking more often notices stalls sooner, but makes reading the clock slower on average.
+    pub fn check_every(self, readings: NonZeroU32) -> StallDetection {
+        StallDetection {
+            check_every: readings,
+            ..self
+        }
+    }
+
+    /// Whether the clock should read the time synchronously while the upkeep thread is stalled.
+    ///
+    /// If this is `false`, the clock only reports stalls, and keeps returning the upkeep
+    /// thread's time.
+    pub fn fall_back_to_synchronous(self, fallback: bool) -> StallDetection {
+        StallDetection { fallback, ..self }
+    }
+
+    /// Calls `on_stall` whenever the clock notices that its upkeep thread stalled, with how far
+    /// the upkeep thread's time lags behind.
+    ///
+    /// The callback runs on the thread that read the clock, once per stall.
+    pub fn on_stall<F>(self, on_stall: F) -> StallDetection
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        StallDetection {
+            on_stall: Some(Arc::new(on_stall)),
+            ..self
+        }
+    }
+}
+
+impl fmt::Debug for StallDetection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StallDetection")
+            .field("threshold", &self.threshold)
+            .field("check_every", &self.check_every)
+            .field("fallback", &self.fallback)
+            .field("on_stall", &self.on_stall.is_some())
+            .finish()
+    }
+}
+
+/// The state of a [`QuantaUpkeepClock`]'s stall detection, shared by all its clones.
+#[derive(Debug)]
+struct StallDetector {
+    detection: StallDetection,
+    readings: AtomicU32,
+    stalled: AtomicBool,
+}
+
+impl StallDetector {
+    fn new(detection: StallDetection) -> Self {
+        StallDetector {
+            detection,
+            readings: AtomicU32::new(0),
+            stalled: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the time to report, given the time `recent` that the upkeep thread stored.
+    // `is_multiple_of` needs Rust 1.87:
+    #[allow(clippy::manual_is_multiple_of)]
+    fn check(&self, clock: &quanta::Clock, recent: quanta::Instant) -> quanta::Instant {
+        // Stall detection doesn't synchronize with anything, it only needs to notice eventually:
+        let stalled = self.stalled.load(Ordering::Relaxed);
+        let check_every = self.detection.check_every.get();
+        if !stalled && self.readings.fetch_add(1, Ordering::Relaxed) % check_every != 0 {
+            return recent;
+        }
+        let now = clock.now();
+        let lag = now.saturating_duration_since(recent);
+        if lag <= self.detection.threshold {
+            if stalled {
+                self.stalled.store(false, Ordering::Relaxed);
+            }
+            return recent;
+        }
+        if !self.stalled.swap(true, Ordering::Relaxed) {
+            if let Some(on_stall) = &self.detection.on_stall {
+                on_stall(lag);
+            }
+        }
+        if self.detection.fallback {
+            now
+        } else {
+            recent
+        }
+    }
 }
 
 impl Clock for QuantaUpkeepClock {
     type Instant = QuantaInstant;
 
     fn now(&self) -> Self::Instant {
-        QuantaInstant(Nanos::from(
-            self.clock
-                .recent()
-                .saturating_duration_since(self.reference),
+        let recent = self.clock.recent();
+        let recent = match &self.stalls {
+            Some(stalls) => stalls.check(&self.clock, recent),
+            None => recent,
+        };
+        QuantaInstant(Nanos::saturating_from(
+            recent.saturating_duration_since(self.reference),
         ))
     }
 }
@@ -129,23 +291,72 @@ mod test {
         assert_eq!(one_ns, Reference::duration_since(&(now + one_ns), now));
         assert_eq!(Nanos::new(0), Reference::duration_since(&now, now + one_ns));
         assert_eq!(
-            Reference::saturating_sub(&(now + Duration::from_nanos(1).into()), one_ns),
+            Reference::saturating_sub(&(now + Nanos::new(1)), one_ns),
             now
         );
     }
 
+    #[test]
+    fn detects_stalls() {
+        let (clock, mock) = quanta::Clock::mock();
+        let stalls = std::sync::Mutex::new(vec![]);
+        let stalls = Arc::new(stalls);
+        let reported = stalls.clone();
+        let detector = StallDetector::new(
+            StallDetection::new(Duration::from_millis(10))
+                .check_every(nonzero_ext::nonzero!(2u32))
+                .on_stall(move |lag| reported.lock().unwrap().push(lag)),
+        );
+        let recent = clock.now();
+        assert_eq!(detector.check(&clock, recent), recent);
+
+        // The upkeep thread's time stands still:
+        mock.increment(Duration::from_millis(50));
+        let now = clock.now();
+        // Only every other reading is checked:
+        assert_eq!(detector.check(&clock, recent), recent);
+        assert_eq!(detector.check(&clock, recent), now);
+        // Once stalled, every reading is checked, and the stall is reported only once:
+        assert_eq!(detector.check(&clock, recent), now);
+        assert_eq!(*stalls.lock().unwrap(), vec![Duration::from_millis(50)]);
+
+        // The upkeep thread caught up again:
+        assert_eq!(detector.check(&clock, now), now);
+        assert_eq!(detector.check(&clock, recent), recent);
+        mock.increment(Duration::from_millis(50));
+        assert_eq!(detector.check(&clock, recent), clock.now());
+        assert_eq!(stalls.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reports_stalls_without_fallback() {
+        let (clock, mock) = quanta::Clock::mock();
+        let detector = StallDetector::new(
+            StallDetection::new(Duration::from_millis(10))
+                .check_every(nonzero_ext::nonzero!(1u32))
+                .fall_back_to_synchronous(false),
+        );
+        let recent = clock.now();
+        mock.increment(Duration::from_millis(50));
+        assert_eq!(detector.check(&clock, recent), recent);
+        assert!(detector.stalled.load(Ordering::Relaxed));
+        assert!(format!("{:?}", detector).contains("fallback: false"));
+    }
+
     #[test]
     fn quanta_upkeep_impls_coverage_and_advances() {
         let one_ns = Nanos::new(1);
         // let _c1 =
         //     QuantaUpkeepClock::from_builder(quanta::Upkeep::new(Duration::from_secs(1))).unwrap();
-        let c = QuantaUpkeepClock::from_interval(Duration::from_millis(50)).unwrap();
+        let c = QuantaUpkeepClock::from_interval(Duration::from_millis(50))
+            .unwrap()
+            .with_stall_detection(StallDetection::new(Duration::from_secs(1)));
         let now = c.now();
         assert_ne!(now + one_ns, now);
         assert_eq!(one_ns, Reference::duration_since(&(now + one_ns), now));
         assert_eq!(Nanos::new(0), Reference::duration_since(&now, now + one_ns));
         assert_eq!(
-            Reference::saturating_sub(&(now + Duration::from_nanos(1).into()), one_ns),
+            Reference::saturating_sub(&(now + Nanos::new(1)), one_ns),
             now
         );
 
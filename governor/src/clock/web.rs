@@ -0,0 +1,89 @@
+use std::prelude::v1::*;
+
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+use super::Reference;
+use super::{Clock, ReasonablyRealtime};
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+use crate::nanos::Nanos;
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+use std::ops::Add;
+use std::time::SystemTime;
+
+/// A clock that works in browsers and other JavaScript hosts.
+///
+/// On `wasm32-unknown-unknown`, [`std::time::Instant`] panics when it is read, so this clock
+/// reports [`web_time::Instant`]s, which read `performance.now()`: a monotonic,
+/// sub-millisecond time that JavaScript hosts provide. On other targets, `web_time::Instant`
+/// is [`std::time::Instant`], and this clock behaves like the
+/// [`MonotonicClock`](super::MonotonicClock).
+///
+/// With the `wasm` feature, this is the default clock on `wasm32-unknown-unknown`.
+#[derive(Clone, Debug, Default)]
+pub struct WebClock;
+
+impl Clock for WebClock {
+    type Instant = web_time::Instant;
+
+    fn now(&self) -> Self::Instant {
+        web_time::Instant::now()
+    }
+}
+
+impl ReasonablyRealtime for WebClock {
+    fn convert_from_reference(&self, instant: Self::Instant) -> SystemTime {
+        // The host's wall-clock time is only available through `web_time`, but
+        // `SystemTime` arithmetic works everywhere:
+        let now = self.now();
+        let since_epoch = web_time::SystemTime::now()
+            .duration_since(web_time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let system_now = SystemTime::UNIX_EPOCH + since_epoch;
+        system_now + instant.saturating_duration_since(now) - now.saturating_duration_since(instant)
+    }
+}
+
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+impl Add<Nanos> for web_time::Instant {
+    type Output = web_time::Instant;
+
+    fn add(self, other: Nanos) -> web_time::Instant {
+        let other: std::time::Duration = other.into();
+        self + other
+    }
+}
+
+#[cfg(all(target_family = "wasm", target_os = "unknown"))]
+impl Reference for web_time::Instant {
+    fn duration_since(&self, earlier: Self) -> Nanos {
+        Nanos::saturating_from(self.saturating_duration_since(earlier))
+    }
+
+    fn saturating_sub(&self, duration: Nanos) -> Self {
+        self.checked_sub(duration.into()).unwrap_or(*self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::Reference;
+    use std::time::Duration;
+
+    #[test]
+    fn web_clock_impls_coverage() {
+        let c = WebClock;
+        let now = c.now();
+        assert!(c.now() >= now);
+        let later = now + Duration::from_millis(10);
+        assert_eq!(
+            Duration::from(Reference::duration_since(&later, now)),
+            Duration::from_millis(10)
+        );
+        let wall = c.convert_from_reference(later);
+        let diff = match wall.duration_since(SystemTime::now()) {
+            Ok(ahead) => ahead,
+            Err(e) => e.duration(),
+        };
+        assert!(diff < Duration::from_secs(1), "{:?}", diff);
+    }
+}
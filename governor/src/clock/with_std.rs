@@ -21,11 +21,7 @@ impl Add<Nanos> for Instant {
 
 impl Reference for Instant {
     fn duration_since(&self, earlier: Self) -> Nanos {
-        if earlier < *self {
-            (*self - earlier).into()
-        } else {
-            Nanos::from(Duration::new(0, 0))
-        }
+        Nanos::saturating_from(self.saturating_duration_since(earlier))
     }
 
     fn saturating_sub(&self, duration: Nanos) -> Self {
@@ -51,9 +47,10 @@ impl Reference for SystemTime {
     /// returns the zero duration if a negative duration would
     /// result (e.g. due to system clock adjustments).
     fn duration_since(&self, earlier: Self) -> Nanos {
-        self.duration_since(earlier)
-            .unwrap_or_else(|_| Duration::new(0, 0))
-            .into()
+        Nanos::saturating_from(
+            self.duration_since(earlier)
+                .unwrap_or_else(|_| Duration::new(0, 0)),
+        )
     }
 
     fn saturating_sub(&self, duration: Nanos) -> Self {
@@ -81,17 +78,34 @@ impl Clock for SystemClock {
 /// Identifies clocks that run similarly to the monotonic realtime clock.
 ///
 /// Clocks implementing this trait can be used with rate-limiters functions that operate
-/// asynchronously.
+/// asynchronously. They wait on governor's timer, as their [`DelayProvider`](super::DelayProvider).
 pub trait ReasonablyRealtime: Clock {
     /// Returns a reference point at the start of an operation.
     fn reference_point(&self) -> Self::Instant {
         self.now()
     }
+
+    /// Converts an instant read from this clock to wall-clock time, e.g. for logging.
+    ///
+    /// The default implementation relates `instant` to the clock's current time and applies
+    /// the difference to [`SystemTime::now`], so the result is only as precise as the two
+    /// clocks agree with each other.
+    fn convert_from_reference(&self, instant: Self::Instant) -> SystemTime {
+        let now = self.now();
+        let system_now = SystemTime::now();
+        let ahead: Duration = instant.duration_since(now).into();
+        let behind: Duration = now.duration_since(instant).into();
+        system_now + ahead - behind
+    }
 }
 
 impl ReasonablyRealtime for MonotonicClock {}
 
-impl ReasonablyRealtime for SystemClock {}
+impl ReasonablyRealtime for SystemClock {
+    fn convert_from_reference(&self, instant: SystemTime) -> SystemTime {
+        instant
+    }
+}
 
 /// Some tests to ensure that the code above gets exercised. We don't
 /// rely on them in tests (being nastily tainted by realism), so we
@@ -111,7 +125,7 @@ mod test {
             #[test]
             fn instant_impls_coverage() {
                 let one_ns = Nanos::new(1);
-                let c = MonotonicClock::default();
+                let c = MonotonicClock;
                 let now = c.now();
                 let ns_dur = Duration::from(one_ns);
                 assert_ne!(now + ns_dur, now, "{:?} + {:?}", ns_dur, now);
@@ -128,7 +142,7 @@ mod test {
     #[test]
     fn system_clock_impls_coverage() {
         let one_ns = Nanos::new(1);
-        let c = SystemClock::default();
+        let c = SystemClock;
         let now = c.now();
         assert_ne!(now + one_ns, now);
         // Thankfully, we're not comparing two system clock readings
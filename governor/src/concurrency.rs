@@ -0,0 +1,279 @@
+//! Concurrency limits: Limiting how many operations are in flight at once.
+//!
+//! Rate limits and concurrency limits often go together: A service may allow 100 requests per
+//! second from a client, but only 10 of them at the same time. The limiters in this module count
+//! *permits* that are held for the duration of an operation, with an API that mirrors the rate
+//! limiters': [`check`](ConcurrencyLimiter::check) returns a permit right away or a negative
+//! outcome, and [`until_ready`](ConcurrencyLimiter::until_ready) waits for a permit
+//! asynchronously. Permits are released when they are dropped.
+//!
+//! Waiting tasks get woken up whenever a permit is released, and race for it; permits are not
+//! handed out in the order in which tasks started waiting.
+
+use std::prelude::v1::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::poll_fn;
+use std::hash::Hash;
+use std::mem;
+use std::num::NonZeroU32;
+use std::task::{Poll, Waker};
+
+use crate::sync::Mutex;
+
+/// A negative outcome of a concurrency limiter: All permits are in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Saturated {
+    max_concurrency: NonZeroU32,
+}
+
+impl Saturated {
+    /// The number of permits that the limiter hands out at most.
+    pub fn max_concurrency(&self) -> NonZeroU32 {
+        self.max_concurrency
+    }
+}
+
+impl fmt::Display for Saturated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "all {} permits are in use", self.max_concurrency)
+    }
+}
+
+impl std::error::Error for Saturated {}
+
+/// The permits in use for a limiter (or one of its keys), and the tasks waiting for one.
+#[derive(Debug, Default)]
+struct Slot {
+    in_use: u32,
+    waiters: Vec<Waker>,
+}
+
+impl Slot {
+    fn try_acquire(&mut self, max: NonZeroU32) -> bool {
+        if self.in_use < max.get() {
+            self.in_use += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn wait(&mut self, waker: &Waker) {
+        if !self.waiters.iter().any(|w| w.will_wake(waker)) {
+            self.waiters.push(waker.clone());
+        }
+    }
+
+    /// Releases a permit, returning the waiters to wake up once the lock is released.
+    fn release(&mut self) -> Vec<Waker> {
+        self.in_use -= 1;
+        mem::take(&mut self.waiters)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.in_use == 0 && self.waiters.is_empty()
+    }
+}
+
+/// A direct concurrency limiter, handing out a fixed number of permits.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::concurrency::ConcurrencyLimiter;
+///
+/// let lim = ConcurrencyLimiter::new(nonzero!(2u32));
+/// let first = lim.check().unwrap();
+/// let _second = lim.check().unwrap();
+/// assert!(lim.check().is_err());
+///
+/// // Dropping a permit releases it:
+/// drop(first);
+/// assert!(lim.check().is_ok());
+/// ```
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    max: NonZeroU32,
+    slot: Mutex<Slot>,
+}
+
+impl ConcurrencyLimiter {
+    /// Constructs a concurrency limiter that hands out at most `max_concurrency` permits at a
+    /// time.
+    pub fn new(max_concurrency: NonZeroU32) -> Self {
+        ConcurrencyLimiter {
+            max: max_concurrency,
+            slot: Mutex::new(Slot::default()),
+        }
+    }
+
+    /// The number of permits that the limiter hands out at most.
+    pub fn max_concurrency(&self) -> NonZeroU32 {
+        self.max
+    }
+
+    /// The number of permits that are currently held.
+    pub fn in_use(&self) -> u32 {
+        self.slot.lock().in_use
+    }
+
+    /// Acquires a permit, if one is available.
+    pub fn check(&self) -> Result<Permit<'_>, Saturated> {
+        if self.slot.lock().try_acquire(self.max) {
+            Ok(Permit { limiter: self })
+        } else {
+            Err(Saturated {
+                max_concurrency: self.max,
+            })
+        }
+    }
+
+    /// Asynchronously resolves to a permit as soon as one is available.
+    pub async fn until_ready(&self) -> Permit<'_> {
+        poll_fn(|cx| {
+            let mut slot = self.slot.lock();
+            if slot.try_acquire(self.max) {
+                Poll::Ready(Permit { limiter: self })
+            } else {
+                slot.wait(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// A permit from a [`ConcurrencyLimiter`], released when dropped.
+#[must_use = "dropping the permit releases it right away"]
+#[derive(Debug)]
+pub struct Permit<'a> {
+    limiter: &'a ConcurrencyLimiter,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let waiters = self.limiter.slot.lock().release();
+        waiters.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// A keyed concurrency limiter, handing out a fixed number of permits for each key.
+///
+/// Only keys with held permits (or waiting tasks) take up memory, so the limiter does not need
+/// any housekeeping.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::concurrency::KeyedConcurrencyLimiter;
+///
+/// let lim = KeyedConcurrencyLimiter::new(nonzero!(1u32));
+/// let _alice = lim.check_key(&"alice").unwrap();
+/// assert!(lim.check_key(&"alice").is_err());
+/// assert!(lim.check_key(&"bob").is_ok());
+/// ```
+#[derive(Debug)]
+pub struct KeyedConcurrencyLimiter<K: Hash + Eq + Clone> {
+    max: NonZeroU32,
+    slots: Mutex<HashMap<K, Slot>>,
+}
+
+impl<K: Hash + Eq + Clone> KeyedConcurrencyLimiter<K> {
+    /// Constructs a keyed concurrency limiter that hands out at most `max_concurrency` permits
+    /// for each key at a time.
+    pub fn new(max_concurrency: NonZeroU32) -> Self {
+        KeyedConcurrencyLimiter {
+            max: max_concurrency,
+            slots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The number of permits that the limiter hands out at most, for each key.
+    pub fn max_concurrency(&self) -> NonZeroU32 {
+        self.max
+    }
+
+    /// The number of permits that are currently held for `key`.
+    pub fn in_use_for_key(&self, key: &K) -> u32 {
+        self.slots.lock().get(key).map_or(0, |slot| slot.in_use)
+    }
+
+    /// The number of keys with held permits or waiting tasks.
+    pub fn len(&self) -> usize {
+        self.slots.lock().len()
+    }
+
+    /// Returns `true` if no permits are held and no tasks are waiting.
+    pub fn is_empty(&self) -> bool {
+        self.slots.lock().is_empty()
+    }
+
+    /// Acquires a permit for `key`, if one is available.
+    pub fn check_key(&self, key: &K) -> Result<KeyedPermit<'_, K>, Saturated> {
+        let mut slots = self.slots.lock();
+        if slots.entry(key.clone()).or_default().try_acquire(self.max) {
+            Ok(KeyedPermit {
+                limiter: self,
+                key: key.clone(),
+            })
+        } else {
+            Err(Saturated {
+                max_concurrency: self.max,
+            })
+        }
+    }
+
+    /// Asynchronously resolves to a permit for `key` as soon as one is available.
+    pub async fn until_key_ready(&self, key: &K) -> KeyedPermit<'_, K> {
+        poll_fn(|cx| {
+            let mut slots = self.slots.lock();
+            let slot = slots.entry(key.clone()).or_default();
+            if slot.try_acquire(self.max) {
+                Poll::Ready(KeyedPermit {
+                    limiter: self,
+                    key: key.clone(),
+                })
+            } else {
+                slot.wait(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// A permit for a key from a [`KeyedConcurrencyLimiter`], released when dropped.
+#[must_use = "dropping the permit releases it right away"]
+#[derive(Debug)]
+pub struct KeyedPermit<'a, K: Hash + Eq + Clone> {
+    limiter: &'a KeyedConcurrencyLimiter<K>,
+    key: K,
+}
+
+impl<K: Hash + Eq + Clone> KeyedPermit<'_, K> {
+    /// The key that the permit was acquired for.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+}
+
+impl<K: Hash + Eq + Clone> Drop for KeyedPermit<'_, K> {
+    fn drop(&mut self) {
+        let waiters = {
+            let mut slots = self.limiter.slots.lock();
+            // Keys with held permits stay in the map.
+            let slot = match slots.get_mut(&self.key) {
+                Some(slot) => slot,
+                None => return,
+            };
+            let waiters = slot.release();
+            if slot.is_idle() {
+                slots.remove(&self.key);
+            }
+            waiters
+        };
+        waiters.into_iter().for_each(Waker::wake);
+    }
+}
@@ -22,6 +22,142 @@ impl fmt::Display for InsufficientCapacity {
 #[cfg(feature = "std")]
 impl std::error::Error for InsufficientCapacity {}
 
+/// An error indicating that a cost can not be charged against a rate
+/// limiter.
+///
+/// Returned by [`RateLimiter::check_cost`][crate::RateLimiter::check_cost]
+/// and [`RateLimiter::check_key_cost`][crate::RateLimiter::check_key_cost].
+/// Neither variant can ever have a conforming result, so retrying with
+/// the same cost is pointless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CostError {
+    /// The cost is larger than the rate limiter's burst size, which
+    /// the argument gives.
+    InsufficientCapacity(InsufficientCapacity),
+
+    /// The cost is negative or not a number.
+    InvalidCost,
+}
+
+impl From<InsufficientCapacity> for CostError {
+    fn from(err: InsufficientCapacity) -> Self {
+        CostError::InsufficientCapacity(err)
+    }
+}
+
+impl fmt::Display for CostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CostError::InsufficientCapacity(err) => err.fmt(f),
+            CostError::InvalidCost => write!(f, "cost is negative or not a number"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CostError {}
+
+/// A reason why a [`Quota`][crate::Quota] is likely to not do what
+/// its author intended.
+///
+/// Returned by [`Quota::validate_sane`][crate::Quota::validate_sane].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SuspiciousQuota {
+    /// The replenishment interval is shorter than a nanosecond, the
+    /// resolution of rate limiters: they will replenish one cell per
+    /// nanosecond instead.
+    SubNanosecondInterval,
+
+    /// The burst size is larger than a million cells, and the
+    /// replenishment interval is shorter than a microsecond. Such a
+    /// quota barely limits anything; it likely results from mixing
+    /// up the burst size and the number of cells per time unit.
+    ExcessiveBurst,
+
+    /// Replenishing the entire burst takes longer than the time
+    /// range (about 584 years) that rate limiters can track.
+    BurstTooLong,
+}
+
+impl fmt::Display for SuspiciousQuota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SuspiciousQuota::SubNanosecondInterval => {
+                write!(f, "replenishment interval is shorter than 1ns")
+            }
+            SuspiciousQuota::ExcessiveBurst => write!(
+                f,
+                "burst size exceeds a million cells with a sub-microsecond replenishment interval"
+            ),
+            SuspiciousQuota::BurstTooLong => {
+                write!(f, "replenishing the burst size takes too long to track")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SuspiciousQuota {}
+
+/// An error indicating that a [`Quota`][crate::Quota] can not be
+/// constructed from a rate or replenishment interval.
+///
+/// Returned by [`Quota::per_second_f64`][crate::Quota::per_second_f64] and the
+/// validating `try_*` constructors of [`Quota`][crate::Quota].
+///
+/// Like the other errors in this crate, it only implements
+/// [`std::error::Error`] with the `std` feature. Without it, the error is
+/// still `Debug` and `Display`, and doesn't rely on `core::error::Error`,
+/// so `no_std` code can construct quotas from rates on older toolchains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum QuotaError {
+    /// The rate is zero, negative or not a number.
+    InvalidRate,
+
+    /// The rate is so high, or the replenishment interval so short,
+    /// that the interval rounds to zero nanoseconds: rate limiters
+    /// can't track intervals shorter than one nanosecond.
+    RateTooHigh,
+
+    /// The rate is so low that the replenishment interval is longer
+    /// than the time range (about 584 years) that rate limiters can
+    /// track.
+    RateTooLow,
+
+    /// The rate is not of the form `<cells>/<unit>`, like `"10/minute"`, or
+    /// `<cells> per <period>`, like `"5000 per 100 milliseconds"`.
+    Unparseable,
+}
+
+impl fmt::Display for QuotaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaError::InvalidRate => write!(f, "rate is not a positive number"),
+            QuotaError::RateTooHigh => {
+                write!(f, "replenishment interval for the rate rounds to 0ns")
+            }
+            QuotaError::RateTooLow => {
+                write!(
+                    f,
+                    "replenishment interval for the rate is too long to track"
+                )
+            }
+            QuotaError::Unparseable => {
+                write!(
+                    f,
+                    "rate is not of the form <cells>/<unit> or <cells> per <period>"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for QuotaError {}
+
 #[cfg(all(feature = "std", test))]
 mod test {
     use super::*;
@@ -33,5 +169,22 @@ mod test {
         let debug_output = format!("{:?}", InsufficientCapacity(3));
         assert!(debug_output.contains("3"));
         assert_eq!(InsufficientCapacity(3), InsufficientCapacity(3));
+
+        let display_output = format!("{}", SuspiciousQuota::ExcessiveBurst);
+        assert!(display_output.contains("burst size"));
+
+        let display_output = format!("{}", QuotaError::RateTooLow);
+        assert!(display_output.contains("too long"));
+
+        let display_output = format!("{}", QuotaError::RateTooHigh);
+        assert!(display_output.contains("0ns"));
+
+        let display_output = format!("{}", CostError::InvalidCost);
+        assert!(display_output.contains("negative"));
+        let display_output = format!("{}", CostError::from(InsufficientCapacity(3)));
+        assert!(display_output.contains("3"));
+
+        let display_output = format!("{}", QuotaError::Unparseable);
+        assert!(display_output.contains("<cells>"));
     }
 }
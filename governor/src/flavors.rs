@@ -0,0 +1,353 @@
+//! Rate limiters that only allow one way of waiting for capacity.
+//!
+//! A [`RateLimiter`] offers both methods that return a decision right away
+//! ([`check`](RateLimiter::check) and friends), and methods that wait until the rate limiter
+//! allows a cell through ([`until_ready`](RateLimiter::until_ready) and friends). Calling
+//! `check` where `until_ready` was meant is an easy mistake to make in an `async` handler: It
+//! drops the requests that exceed the rate limit instead of pacing them.
+//!
+//! The wrappers in this module make that mistake impossible to make. They share one rate
+//! limiter (through an [`Arc`]), and each exposes only one set of its methods:
+//!
+//! * [`AsyncRateLimiter`] only has the methods that wait (asynchronously) until cells are
+//!   allowed through.
+//! * [`BlockingRateLimiter`] only has the methods that return a decision right away, for code
+//!   that has no way to wait.
+//!
+//! # Example
+//! ```rust
+//! # use nonzero_ext::nonzero;
+//! use std::sync::Arc;
+//! use governor::{flavors::{AsyncRateLimiter, BlockingRateLimiter}, Quota, RateLimiter};
+//! # futures_executor::block_on(async {
+//!
+//! let lim = Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(50u32))));
+//! let paced = AsyncRateLimiter::new(Arc::clone(&lim));
+//! let immediate = BlockingRateLimiter::new(lim);
+//!
+//! paced.until_ready().await;
+//! assert_eq!(Ok(()), immediate.check());
+//! # });
+//! ```
+
+use std::prelude::v1::*;
+
+use std::hash::Hash;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{
+    clock,
+    errors::InsufficientCapacity,
+    middleware::{NoOpMiddleware, RateLimitingMiddleware},
+    state::{keyed::KeyedStateStore, Clamped, DirectStateStore, NotKeyed, Reservation, StateStore},
+    Jitter, NotUntil, RateLimiter,
+};
+
+/// A rate limiter that only has methods that wait until cells are allowed through.
+///
+/// See [the module documentation](index.html) for why this is useful.
+#[derive(Debug)]
+pub struct AsyncRateLimiter<K, S, C, MW = NoOpMiddleware<<C as clock::Clock>::Instant>>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    limiter: Arc<RateLimiter<K, S, C, MW>>,
+}
+
+/// A rate limiter that only has methods that return a decision right away.
+///
+/// See [the module documentation](index.html) for why this is useful.
+#[derive(Debug)]
+pub struct BlockingRateLimiter<K, S, C, MW = NoOpMiddleware<<C as clock::Clock>::Instant>>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    limiter: Arc<RateLimiter<K, S, C, MW>>,
+}
+
+impl<K, S, C, MW> AsyncRateLimiter<K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Wraps a shared rate limiter.
+    pub fn new(limiter: Arc<RateLimiter<K, S, C, MW>>) -> Self {
+        AsyncRateLimiter { limiter }
+    }
+
+    /// Returns the rate limiter that this wraps.
+    pub fn limiter(&self) -> &Arc<RateLimiter<K, S, C, MW>> {
+        &self.limiter
+    }
+
+    /// Returns a [`BlockingRateLimiter`] sharing this one's rate limiter.
+    pub fn blocking(&self) -> BlockingRateLimiter<K, S, C, MW> {
+        BlockingRateLimiter::new(Arc::clone(&self.limiter))
+    }
+}
+
+impl<K, S, C, MW> Clone for AsyncRateLimiter<K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    fn clone(&self) -> Self {
+        AsyncRateLimiter::new(Arc::clone(&self.limiter))
+    }
+}
+
+impl<K, S, C, MW> From<Arc<RateLimiter<K, S, C, MW>>> for AsyncRateLimiter<K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    fn from(limiter: Arc<RateLimiter<K, S, C, MW>>) -> Self {
+        AsyncRateLimiter::new(limiter)
+    }
+}
+
+impl<K, S, C, MW> BlockingRateLimiter<K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Wraps a shared rate limiter.
+    pub fn new(limiter: Arc<RateLimiter<K, S, C, MW>>) -> Self {
+        BlockingRateLimiter { limiter }
+    }
+
+    /// Returns the rate limiter that this wraps.
+    pub fn limiter(&self) -> &Arc<RateLimiter<K, S, C, MW>> {
+        &self.limiter
+    }
+
+    /// Returns an [`AsyncRateLimiter`] sharing this one's rate limiter.
+    pub fn asynchronous(&self) -> AsyncRateLimiter<K, S, C, MW> {
+        AsyncRateLimiter::new(Arc::clone(&self.limiter))
+    }
+}
+
+impl<K, S, C, MW> Clone for BlockingRateLimiter<K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    fn clone(&self) -> Self {
+        BlockingRateLimiter::new(Arc::clone(&self.limiter))
+    }
+}
+
+impl<K, S, C, MW> From<Arc<RateLimiter<K, S, C, MW>>> for BlockingRateLimiter<K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    fn from(limiter: Arc<RateLimiter<K, S, C, MW>>) -> Self {
+        BlockingRateLimiter::new(limiter)
+    }
+}
+
+/// # Direct rate limiters
+impl<S, C, MW> AsyncRateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore,
+    C: clock::DelayProvider,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+{
+    /// See [`RateLimiter::until_ready`].
+    pub async fn until_ready(&self) -> MW::PositiveOutcome {
+        self.limiter.until_ready().await
+    }
+
+    /// See [`RateLimiter::until_ready_with_jitter`].
+    pub async fn until_ready_with_jitter(&self, jitter: Jitter) -> MW::PositiveOutcome {
+        self.limiter.until_ready_with_jitter(jitter).await
+    }
+
+    /// See [`RateLimiter::until_ready_or_reject_if_wait_exceeds`].
+    pub async fn until_ready_or_reject_if_wait_exceeds(
+        &self,
+        threshold: Duration,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.limiter
+            .until_ready_or_reject_if_wait_exceeds(threshold)
+            .await
+    }
+
+    /// See [`RateLimiter::until_n_ready`].
+    pub async fn until_n_ready(
+        &self,
+        n: NonZeroU32,
+    ) -> Result<MW::PositiveOutcome, InsufficientCapacity> {
+        self.limiter.until_n_ready(n).await
+    }
+
+    /// See [`RateLimiter::until_n_ready_with_jitter`].
+    pub async fn until_n_ready_with_jitter(
+        &self,
+        n: NonZeroU32,
+        jitter: Jitter,
+    ) -> Result<MW::PositiveOutcome, InsufficientCapacity> {
+        self.limiter.until_n_ready_with_jitter(n, jitter).await
+    }
+
+    /// See [`RateLimiter::until_n_ready_clamped`].
+    pub async fn until_n_ready_clamped(&self, n: NonZeroU32) -> Clamped<MW::PositiveOutcome> {
+        self.limiter.until_n_ready_clamped(n).await
+    }
+}
+
+/// # Keyed rate limiters
+impl<K, S, C, MW> AsyncRateLimiter<K, S, C, MW>
+where
+    K: Hash + Eq + Clone,
+    S: KeyedStateStore<K>,
+    C: clock::DelayProvider,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+{
+    /// See [`RateLimiter::until_key_ready`].
+    pub async fn until_key_ready(&self, key: &K) -> MW::PositiveOutcome {
+        self.limiter.until_key_ready(key).await
+    }
+
+    /// See [`RateLimiter::until_key_ready_with_jitter`].
+    pub async fn until_key_ready_with_jitter(
+        &self,
+        key: &K,
+        jitter: Jitter,
+    ) -> MW::PositiveOutcome {
+        self.limiter.until_key_ready_with_jitter(key, jitter).await
+    }
+
+    /// See [`RateLimiter::until_key_ready_or_reject_if_wait_exceeds`].
+    pub async fn until_key_ready_or_reject_if_wait_exceeds(
+        &self,
+        key: &K,
+        threshold: Duration,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.limiter
+            .until_key_ready_or_reject_if_wait_exceeds(key, threshold)
+            .await
+    }
+
+    /// See [`RateLimiter::until_key_n_ready`].
+    pub async fn until_key_n_ready(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Result<MW::PositiveOutcome, InsufficientCapacity> {
+        self.limiter.until_key_n_ready(key, n).await
+    }
+
+    /// See [`RateLimiter::until_key_n_ready_with_jitter`].
+    pub async fn until_key_n_ready_with_jitter(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+        jitter: Jitter,
+    ) -> Result<MW::PositiveOutcome, InsufficientCapacity> {
+        self.limiter
+            .until_key_n_ready_with_jitter(key, n, jitter)
+            .await
+    }
+
+    /// See [`RateLimiter::until_key_n_ready_clamped`].
+    pub async fn until_key_n_ready_clamped(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Clamped<MW::PositiveOutcome> {
+        self.limiter.until_key_n_ready_clamped(key, n).await
+    }
+}
+
+/// # Direct rate limiters
+impl<S, C, MW> BlockingRateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// See [`RateLimiter::check`].
+    pub fn check(&self) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.limiter.check()
+    }
+
+    /// See [`RateLimiter::check_n`].
+    pub fn check_n(
+        &self,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        self.limiter.check_n(n)
+    }
+
+    /// See [`RateLimiter::check_n_clamped`].
+    pub fn check_n_clamped(
+        &self,
+        n: NonZeroU32,
+    ) -> Result<Clamped<MW::PositiveOutcome>, MW::NegativeOutcome> {
+        self.limiter.check_n_clamped(n)
+    }
+
+    /// See [`RateLimiter::check_or_reject_if_wait_exceeds`].
+    pub fn check_or_reject_if_wait_exceeds(
+        &self,
+        threshold: Duration,
+    ) -> Result<Reservation<MW::PositiveOutcome>, MW::NegativeOutcome> {
+        self.limiter.check_or_reject_if_wait_exceeds(threshold)
+    }
+}
+
+/// # Keyed rate limiters
+impl<K, S, C, MW> BlockingRateLimiter<K, S, C, MW>
+where
+    K: Hash,
+    S: KeyedStateStore<K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// See [`RateLimiter::check_key`].
+    pub fn check_key(&self, key: &K) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.limiter.check_key(key)
+    }
+
+    /// See [`RateLimiter::check_key_n`].
+    pub fn check_key_n(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        self.limiter.check_key_n(key, n)
+    }
+
+    /// See [`RateLimiter::check_key_n_clamped`].
+    pub fn check_key_n_clamped(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Result<Clamped<MW::PositiveOutcome>, MW::NegativeOutcome> {
+        self.limiter.check_key_n_clamped(key, n)
+    }
+
+    /// See [`RateLimiter::check_key_or_reject_if_wait_exceeds`].
+    pub fn check_key_or_reject_if_wait_exceeds(
+        &self,
+        key: &K,
+        threshold: Duration,
+    ) -> Result<Reservation<MW::PositiveOutcome>, MW::NegativeOutcome> {
+        self.limiter
+            .check_key_or_reject_if_wait_exceeds(key, threshold)
+    }
+}
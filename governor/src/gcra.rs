@@ -1,8 +1,17 @@
-use crate::state::StateStore;
-use crate::InsufficientCapacity;
-use crate::{clock, middleware::StateSnapshot, Quota};
+use std::prelude::v1::*;
+
+use crate::state::{FreshState, StateStore};
+use crate::{
+    clock,
+    middleware::{KeyFormat, StateSnapshot},
+    Quota,
+};
 use crate::{middleware::RateLimitingMiddleware, nanos::Nanos};
-use std::num::NonZeroU32;
+use crate::{CostError, InsufficientCapacity};
+use nonzero_ext::nonzero;
+use std::convert::{Infallible, TryFrom};
+use std::num::{NonZeroU128, NonZeroU32, NonZeroU64};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 use std::{cmp, fmt};
 
@@ -31,8 +40,7 @@ impl<P: clock::Reference> NotUntil<P> {
     /// that are made in the meantime).
     #[inline]
     pub fn earliest_possible(&self) -> P {
-        let tat: Nanos = self.state.tat;
-        self.start + tat
+        self.start + self.state.earliest_conforming()
     }
 
     /// Returns the minimum amount of time from the time that the
@@ -47,6 +55,80 @@ impl<P: clock::Reference> NotUntil<P> {
         earliest.duration_since(earliest.min(from)).into()
     }
 
+    /// Returns the minimum amount of time that must pass after the decision was made before a
+    /// decision can be conforming.
+    ///
+    /// Unlike [`wait_time_from`](#method.wait_time_from), this does not need a reading of the
+    /// clock, which makes it convenient for logs and metrics.
+    #[inline]
+    pub fn deadline_duration(&self) -> Duration {
+        self.state
+            .earliest_conforming()
+            .saturating_sub(self.state.time_of_measurement)
+            .into()
+    }
+
+    /// Returns the earliest time at which a decision could be conforming, in milliseconds since
+    /// the UNIX epoch.
+    ///
+    /// `clock` must be the clock of the rate limiter that made the decision; see
+    /// [`ReasonablyRealtime::convert_from_reference`](clock::ReasonablyRealtime::convert_from_reference)
+    /// for how precise the conversion is.
+    #[cfg(feature = "std")]
+    pub fn earliest_possible_unix_ms<C>(&self, clock: &C) -> u64
+    where
+        C: clock::ReasonablyRealtime<Instant = P>,
+    {
+        use std::convert::TryFrom;
+
+        clock
+            .convert_from_reference(self.earliest_possible())
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+    }
+
+    /// Returns the earliest time at which a decision could be conforming, as an
+    /// [`Instant`](std::time::Instant) to sleep until, e.g. with a timer.
+    ///
+    /// `clock` must be the clock of the rate limiter that made the decision: The instant lies
+    /// as far ahead of [`Instant::now`](std::time::Instant::now) as the earliest possible time
+    /// lies ahead of the clock's current time, so it's only as precise as the two clocks agree
+    /// with each other. If that time has already passed, the instant is the current one.
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{clock::MonotonicClock, Quota, RateLimiter};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let clock = MonotonicClock;
+    /// let lim = RateLimiter::direct_with_clock(Quota::per_hour(nonzero!(1u32)), clock.clone());
+    /// lim.check().unwrap();
+    /// let not_until = lim.check().unwrap_err();
+    /// let deadline = not_until.sleep_until_instant(&clock);
+    /// assert!(deadline > Instant::now() + Duration::from_secs(3590));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn sleep_until_instant<C>(&self, clock: &C) -> std::time::Instant
+    where
+        C: clock::ReasonablyRealtime<Instant = P>,
+    {
+        std::time::Instant::now() + self.wait_time_from(clock.now())
+    }
+
+    /// Returns the earliest time at which a decision could be conforming, as a
+    /// [`tokio::time::Instant`] to pass to e.g.
+    /// [`tokio::time::sleep_until`](https://docs.rs/tokio/latest/tokio/time/fn.sleep_until.html).
+    ///
+    /// This works like [`sleep_until_instant`](#method.sleep_until_instant), but measures from
+    /// tokio's current time, which stands still while tokio's clock is paused (e.g. in tests).
+    #[cfg(feature = "tokio")]
+    pub fn sleep_until_tokio_instant<C>(&self, clock: &C) -> tokio::time::Instant
+    where
+        C: clock::ReasonablyRealtime<Instant = P>,
+    {
+        tokio::time::Instant::now() + self.wait_time_from(clock.now())
+    }
+
     /// Returns the rate limiting [`Quota`] used to reach the decision.
     #[inline]
     pub fn quota(&self) -> Quota {
@@ -55,14 +137,14 @@ impl<P: clock::Reference> NotUntil<P> {
 
     #[cfg(feature = "std")] // not used unless we use Instant-compatible clocks.
     #[inline]
-    pub(crate) fn earliest_possible_with_offset(&self, jitter: Jitter) -> P {
-        let tat = jitter + self.state.tat;
+    pub(crate) fn earliest_possible_with_offset(&self, jitter: &Jitter) -> P {
+        let tat = self.state.earliest_conforming() + jitter.get();
         self.start + tat
     }
 
     #[cfg(feature = "std")] // not used unless we use Instant-compatible clocks.
     #[inline]
-    pub(crate) fn wait_time_with_offset(&self, from: P, jitter: Jitter) -> Duration {
+    pub(crate) fn wait_time_with_offset(&self, from: P, jitter: &Jitter) -> Duration {
         let earliest = self.earliest_possible_with_offset(jitter);
         earliest.duration_since(earliest.min(from)).into()
     }
@@ -70,7 +152,7 @@ impl<P: clock::Reference> NotUntil<P> {
 
 impl<P: clock::Reference> fmt::Display for NotUntil<P> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "rate-limited until {:?}", self.start + self.state.tat)
+        write!(f, "rate-limited until {:?}", self.earliest_possible())
     }
 }
 
@@ -83,19 +165,249 @@ pub(crate) struct Gcra {
     ///
     /// The total "burst capacity" of the bucket is `t + tau`.
     tau: Nanos,
+
+    /// The extra time added to the theoretical arrival time when a decision uses up the last of
+    /// the burst capacity.
+    cooldown: Nanos,
+
+    /// How the burst capacity recharges after a quiet period, if it doesn't recharge
+    /// continuously.
+    recharge: Option<Recharge>,
+
+    /// Whether decisions are bypassed, letting every cell through.
+    disabled: Disabled,
+
+    /// How much burst capacity states start with.
+    fresh: FreshState,
+}
+
+/// Whether a [`Gcra`] is disabled, see
+/// [`RateLimiter::set_enabled`](crate::RateLimiter::set_enabled).
+///
+/// Compares by the current setting.
+#[derive(Debug, Default)]
+struct Disabled(AtomicBool);
+
+impl PartialEq for Disabled {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.load(Ordering::Relaxed) == other.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Eq for Disabled {}
+
+/// The burst recharge delay of a [`Gcra`], see [`Quota::with_burst_recharge_delay`].
+///
+/// With a burst recharge delay, the stored states keep the time of the last positive decision
+/// along with the theoretical arrival time: The theoretical arrival time is rounded down to a
+/// grid of `tat_steps` points per replenishment interval, and the distance to the next grid
+/// point holds the time from the last positive decision to it, rounded down to a grid of
+/// `since_steps` points per replenishment interval.
+///
+/// Both grids map onto themselves when a whole number of replenishment intervals is added, so
+/// letting cells through at the sustained rate doesn't accumulate rounding errors.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Recharge {
+    /// How long no cells have to be let through before the burst capacity starts to recharge.
+    delay: Nanos,
+
+    /// The replenishment interval.
+    t: NonZeroU64,
+
+    /// The resolution of the theoretical arrival time, in points per replenishment interval.
+    tat_steps: NonZeroU64,
+
+    /// The resolution of the time of the last positive decision, in points per replenishment
+    /// interval.
+    since_steps: NonZeroU64,
+
+    /// The largest number of `since_steps` between the last positive decision and the
+    /// theoretical arrival time that fits between two `tat_steps`.
+    max_since: u64,
+}
+
+impl Recharge {
+    fn new(delay: Nanos, t: Nanos, span: Nanos) -> Self {
+        // The replenishment interval is at least a nanosecond:
+        let t = NonZeroU64::new(t.as_u64()).unwrap_or(nonzero!(1u64));
+        // Split the nanoseconds per replenishment interval between the two grids, keeping the
+        // theoretical arrival time at least as precise as the last positive decision, and at
+        // most 1024 points per interval:
+        let budget = t.get() / span.as_u64().div_ceil(t.get());
+        let tat_steps = isqrt(budget).clamp(1, 1024);
+        let since_steps = cmp::max(cmp::min(tat_steps / 2, budget / tat_steps), 1);
+        Recharge {
+            delay,
+            t,
+            tat_steps: NonZeroU64::new(tat_steps).unwrap_or(nonzero!(1u64)),
+            since_steps: NonZeroU64::new(since_steps).unwrap_or(nonzero!(1u64)),
+            max_since: (t.get() / tat_steps).saturating_sub(1),
+        }
+    }
+
+    // The methods below run on every decision, so they only divide by non-zero divisors, which
+    // can't panic.
+
+    /// The `i`th point on a grid of `steps` points per replenishment interval.
+    #[inline]
+    fn point(&self, i: u128, steps: NonZeroU64) -> u128 {
+        i * u128::from(self.t.get()) / NonZeroU128::from(steps)
+    }
+
+    /// The index of the last point at or before `n` on a grid of `steps` points per
+    /// replenishment interval.
+    #[inline]
+    fn index_below(&self, n: u128, steps: NonZeroU64) -> u128 {
+        ((n + 1) * u128::from(steps.get()) - 1) / NonZeroU128::from(self.t)
+    }
+
+    /// Packs the theoretical arrival time `tat` and the time `last` of the last positive
+    /// decision into a state to store.
+    #[inline]
+    fn pack(&self, tat: Nanos, last: Nanos) -> Nanos {
+        // Rounds down to the last point on the theoretical arrival time's grid, so cells that
+        // conform at `tat` still conform after the rounding:
+        let base = self.point(
+            self.index_below(u128::from(tat.as_u64()), self.tat_steps),
+            self.tat_steps,
+        );
+        let since = base.saturating_sub(u128::from(last.as_u64()));
+        let since = cmp::min(
+            self.index_below(since, self.since_steps),
+            u128::from(self.max_since),
+        );
+        Nanos::from(u64::try_from(base + since).unwrap_or(u64::MAX))
+    }
+
+    /// Unpacks a stored state into the theoretical arrival time and the time of the last
+    /// positive decision.
+    #[inline]
+    fn unpack(&self, state: Nanos) -> (Nanos, Nanos) {
+        let state = u128::from(state.as_u64());
+        let base = self.point(self.index_below(state, self.tat_steps), self.tat_steps);
+        let since = cmp::min(state - base, u128::from(self.max_since));
+        let last = base.saturating_sub(self.point(since, self.since_steps));
+        // Both fit, as they're at most `state`:
+        let narrow = |n: u128| Nanos::from(u64::try_from(n).unwrap_or(u64::MAX));
+        (narrow(base), narrow(last))
+    }
+}
+
+/// Returns the largest integer whose square is at most `n`, computed digit by digit.
+///
+/// (`u64::isqrt` needs Rust 1.84.)
+fn isqrt(mut n: u64) -> u64 {
+    let mut root = 0;
+    let mut bit = 1 << 62;
+    while bit > n {
+        bit >>= 2;
+    }
+    while bit != 0 {
+        if n >= root + bit {
+            n -= root + bit;
+            root = (root >> 1) + bit;
+        } else {
+            root >>= 1;
+        }
+        bit >>= 2;
+    }
+    root
 }
 
 impl Gcra {
     pub(crate) fn new(quota: Quota) -> Self {
-        let t: Nanos = cmp::max(quota.replenish_1_per, Duration::from_nanos(1)).into();
-        let tau: Nanos = t * (quota.max_burst.get() - 1).into();
-        Gcra { t, tau }
+        // Quotas from the const constructors can have a zero replenishment interval; the
+        // validating `Quota::try_*` constructors reject those instead of relying on this clamp.
+        let t = Nanos::saturating_from(cmp::max(quota.replenish_1_per, Duration::from_nanos(1)));
+        let tau = Nanos::from(
+            t.as_u64()
+                .saturating_mul((quota.max_burst.get() - 1).into()),
+        );
+        let cooldown = Nanos::saturating_from(quota.cooldown);
+        let recharge = Some(Nanos::saturating_from(quota.recharge_delay))
+            .filter(|delay| *delay > Nanos::from(0))
+            .map(|delay| Recharge::new(delay, t, t + tau + cooldown));
+        Gcra {
+            t,
+            tau,
+            cooldown,
+            recharge,
+            disabled: Disabled::default(),
+            fresh: FreshState::Full,
+        }
     }
 
     pub(crate) fn t(&self) -> Nanos {
         self.t
     }
 
+    /// Returns the burst recharge delay, or zero if the burst capacity recharges continuously.
+    #[inline]
+    fn recharge_delay(&self) -> Nanos {
+        self.recharge
+            .map_or(Nanos::from(0), |recharge| recharge.delay)
+    }
+
+    /// Returns the snapshot of a decision made at `t0` that left the theoretical arrival time at
+    /// `tat`.
+    #[inline]
+    fn snapshot(&self, t0: Nanos, tat: Nanos) -> StateSnapshot {
+        StateSnapshot::new(self.t, self.tau, t0, tat)
+            .with_cooldown(self.cooldown)
+            .with_recharge_delay(self.recharge_delay())
+    }
+
+    /// Returns the snapshot of a negative decision made at `t0`, where the next cell would
+    /// conform at `earliest_conforming`.
+    #[inline]
+    fn denied_snapshot(&self, t0: Nanos, earliest_conforming: Nanos) -> StateSnapshot {
+        StateSnapshot::denied(self.t, self.tau, t0, earliest_conforming)
+            .with_cooldown(self.cooldown)
+            .with_recharge_delay(self.recharge_delay())
+    }
+
+    #[cfg(feature = "std")] // only used by the shadow rate limiter.
+    pub(crate) fn tau(&self) -> Nanos {
+        self.tau
+    }
+
+    /// Sets how much burst capacity states start with; see
+    /// [`RateLimiter::with_fresh_state`](crate::RateLimiter::with_fresh_state).
+    pub(crate) fn set_fresh_state(&mut self, fresh: FreshState) {
+        self.fresh = fresh;
+    }
+
+    pub(crate) fn fresh_state(&self) -> FreshState {
+        self.fresh
+    }
+
+    /// Enables or disables decisions; see
+    /// [`RateLimiter::set_enabled`](crate::RateLimiter::set_enabled).
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.disabled.0.store(!enabled, Ordering::Relaxed);
+    }
+
+    /// Returns whether decisions are bypassed, letting every cell through.
+    #[inline]
+    pub(crate) fn is_disabled(&self) -> bool {
+        self.disabled.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns the positive outcome of a decision at `t0` that was bypassed because the rate
+    /// limiter is disabled.
+    ///
+    /// The outcome's snapshot shows the whole burst capacity as available, as for a fresh key.
+    fn bypassed<K, P: clock::Reference, MW: RateLimitingMiddleware<P>>(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        t0: P,
+    ) -> MW::PositiveOutcome {
+        let t0 = t0.duration_since(start);
+        MW::allow(&format.show(key), self.snapshot(t0, t0))
+    }
+
     /// Tests a single cell against the rate limiter state and updates it at the given key.
     pub(crate) fn test_and_update<
         K,
@@ -106,29 +418,173 @@ impl Gcra {
         &self,
         start: P,
         key: &K,
+        format: KeyFormat<K>,
         state: &S,
         t0: P,
     ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let decision = self.decide_at(start, key, state, t0);
+        self.outcome::<K, P, MW>(start, key, format, t0, decision)
+    }
+
+    /// Tests a single cell against the rate limiter state at the given key and updates it, like
+    /// [`test_and_update`](#method.test_and_update) does, but leaves consulting the middleware
+    /// to [`outcome`](#method.outcome).
+    ///
+    /// Returns `None` if the rate limiter is disabled.
+    pub(crate) fn decide_at<K, P: clock::Reference, S: StateStore<Key = K>>(
+        &self,
+        start: P,
+        key: &K,
+        state: &S,
+        t0: P,
+    ) -> Option<Result<Nanos, Nanos>> {
+        if self.is_disabled() {
+            return None;
+        }
+        Some(self.decide_one(key, state, t0.duration_since(start)))
+    }
+
+    /// Reports a decision that [`decide_at`](#method.decide_at) made at `t0` to the middleware,
+    /// and returns its outcome.
+    pub(crate) fn outcome<K, P: clock::Reference, MW: RateLimitingMiddleware<P>>(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        t0: P,
+        decision: Option<Result<Nanos, Nanos>>,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let decision = match decision {
+            Some(decision) => decision,
+            None => return Ok(self.bypassed::<K, P, MW>(start, key, format, t0)),
+        };
+        let t0 = t0.duration_since(start);
+        match decision {
+            Ok(next) => Ok(MW::allow(&format.show(key), self.snapshot(t0, next))),
+            Err(earliest_time) => Err(MW::disallow(
+                &format.show(key),
+                self.denied_snapshot(t0, earliest_time),
+                start,
+            )),
+        }
+    }
+
+    /// Tests a single cell against the rate limiter state and updates it at the given key,
+    /// without consulting a middleware.
+    ///
+    /// This makes the same decision as [`test_and_update`](#method.test_and_update) with the
+    /// [`NoOpMiddleware`][crate::middleware::NoOpMiddleware], but only constructs a
+    /// [`StateSnapshot`] for negative decisions.
+    pub(crate) fn test_and_update_bare<K, P: clock::Reference, S: StateStore<Key = K>>(
+        &self,
+        start: P,
+        key: &K,
+        state: &S,
+        t0: P,
+    ) -> Result<(), NotUntil<P>> {
+        if self.is_disabled() {
+            return Ok(());
+        }
+        let t0 = t0.duration_since(start);
+        match self.decide_one(key, state, t0) {
+            Ok(_) => Ok(()),
+            Err(earliest_time) => Err(NotUntil::new(
+                self.denied_snapshot(t0, earliest_time),
+                start,
+            )),
+        }
+    }
+
+    /// Tests a single cell against the rate limiter state and updates it at the given key,
+    /// returning only whether the cell conforms.
+    ///
+    /// This makes the same decision as [`test_and_update_bare`](#method.test_and_update_bare),
+    /// but doesn't construct a [`StateSnapshot`] for negative decisions either.
+    pub(crate) fn test_and_update_fast<K, P: clock::Reference, S: StateStore<Key = K>>(
+        &self,
+        start: P,
+        key: &K,
+        state: &S,
+        t0: P,
+    ) -> bool {
+        if self.is_disabled() {
+            return true;
+        }
+        let t0 = t0.duration_since(start);
+        self.decide_one(key, state, t0).is_ok()
+    }
+
+    /// Tests a single cell against the rate limiter states of all `keys`, and updates them only
+    /// if all of them allow it through.
+    ///
+    /// Negative decisions are reported for the key whose state takes the longest to allow the
+    /// cell through.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn test_all_keys_and_update<
+        'k,
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        keys: &[&'k K],
+        format: KeyFormat<K>,
+        state: &S,
+        t0: P,
+    ) -> Result<Vec<MW::PositiveOutcome>, (&'k K, MW::NegativeOutcome)> {
+        if self.is_disabled() {
+            return Ok(keys
+                .iter()
+                .map(|key| self.bypassed::<K, P, MW>(start, key, format, t0))
+                .collect());
+        }
         let t0 = t0.duration_since(start);
         let tau = self.tau;
         let t = self.t;
-        state.measure_and_replace(key, |tat| {
-            let tat = tat.unwrap_or(t0);
-            let earliest_time = tat.saturating_sub(tau);
+        let earliest_time = |tat: Option<Nanos>| self.tat_at(tat, t0).saturating_sub(tau);
+        let decision = state.measure_and_replace_all(keys, |tat| {
+            let earliest_time = earliest_time(tat);
             if t0 < earliest_time {
-                Err(MW::disallow(
-                    key,
-                    StateSnapshot::new(self.t, self.tau, earliest_time, earliest_time),
-                    start,
-                ))
+                Err(earliest_time)
             } else {
-                let next = cmp::max(tat, t0) + t;
-                Ok((
-                    MW::allow(key, StateSnapshot::new(self.t, self.tau, t0, next)),
-                    next,
+                let tat = self.tat_at(tat, t0);
+                let next = self.after_update(t0, tat, cmp::max(tat, t0) + t);
+                let stored = self.stored(t0, next);
+                Ok(((next, stored), stored))
+            }
+        });
+        match decision {
+            Ok(tats) => Ok(keys
+                .iter()
+                .zip(tats)
+                .map(|(key, (tat, stored))| {
+                    state.note_admission(key, t0, stored);
+                    MW::allow(&format.show(*key), self.snapshot(t0, tat))
+                })
+                .collect()),
+            Err((denied, earliest)) => {
+                let (key, earliest) = keys.iter().fold(
+                    (keys[denied], earliest),
+                    |(most_restrictive, latest), key| {
+                        let earliest = state
+                            .measure_and_peek(key, |tat| Ok::<_, Infallible>(earliest_time(tat)))
+                            .unwrap_or_else(|never| match never {});
+                        if earliest > latest {
+                            (*key, earliest)
+                        } else {
+                            (most_restrictive, latest)
+                        }
+                    },
+                );
+                state.note_denial(key, t0);
+                Err((
+                    key,
+                    MW::disallow(&format.show(key), self.denied_snapshot(t0, earliest), start),
                 ))
             }
-        })
+        }
     }
 
     /// Tests whether all `n` cells could be accommodated and updates the rate limiter state, if so.
@@ -141,39 +597,857 @@ impl Gcra {
         &self,
         start: P,
         key: &K,
+        format: KeyFormat<K>,
         n: NonZeroU32,
         state: &S,
         t0: P,
     ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        if self.is_disabled() {
+            return Ok(Ok(self.bypassed::<K, P, MW>(start, key, format, t0)));
+        }
+        let weight = self.t + self.additional_weight(n)?;
+        Ok(self
+            .test_weighted_and_update::<K, P, S, MW>(
+                start,
+                key,
+                format,
+                Nanos::default(),
+                |_| weight,
+                state,
+                t0,
+            )
+            .map(|(_, outcome)| outcome))
+    }
+
+    /// Tests whether all `n` cells would be accommodated within `grace`, and updates the rate
+    /// limiter state as if they were let through right away, if so.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn test_n_all_with_grace_and_update<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        n: NonZeroU32,
+        grace: Nanos,
+        state: &S,
+        t0: P,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        if self.is_disabled() {
+            return Ok(Ok(self.bypassed::<K, P, MW>(start, key, format, t0)));
+        }
+        let weight = self.t + self.additional_weight(n)?;
+        Ok(self
+            .test_weighted_and_update::<K, P, S, MW>(
+                start,
+                key,
+                format,
+                grace,
+                |_| weight,
+                state,
+                t0,
+            )
+            .map(|(_, outcome)| outcome))
+    }
+
+    /// Tests whether as many of `n` cells as fit into the bucket could be accommodated, and
+    /// updates the rate limiter state, if so.
+    ///
+    /// Returns the number of cells that were tested along with the decision.
+    pub(crate) fn test_n_clamped_and_update<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        n: NonZeroU32,
+        state: &S,
+        t0: P,
+    ) -> (NonZeroU32, Result<MW::PositiveOutcome, MW::NegativeOutcome>) {
+        if self.is_disabled() {
+            return (n, Ok(self.bypassed::<K, P, MW>(start, key, format, t0)));
+        }
+        let n = cmp::min(n, self.burst_size());
+        let weight = self.t * u64::from(n.get());
+        let decision = self.test_weighted_and_update::<K, P, S, MW>(
+            start,
+            key,
+            format,
+            Nanos::default(),
+            |_| weight,
+            state,
+            t0,
+        );
+        (n, decision.map(|(_, outcome)| outcome))
+    }
+
+    /// Tests whether at least `min` of `n` cells could be accommodated, and updates the rate
+    /// limiter state for as many of the `n` cells as currently fit, if so.
+    ///
+    /// Returns the number of cells that were admitted along with the positive decision. A
+    /// negative decision indicates when `min` cells could conform.
+    #[allow(clippy::type_complexity, clippy::too_many_arguments)]
+    pub(crate) fn test_n_available_and_update<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        min: NonZeroU32,
+        n: NonZeroU32,
+        state: &S,
+        t0: P,
+    ) -> Result<Result<(NonZeroU32, MW::PositiveOutcome), MW::NegativeOutcome>, InsufficientCapacity>
+    {
+        if self.is_disabled() {
+            return Ok(Ok((n, self.bypassed::<K, P, MW>(start, key, format, t0))));
+        }
+        let min_weight = self.t + self.additional_weight(min)?;
+        let n = u64::from(cmp::min(n, self.burst_size()).get());
+        let t = self.t;
+        // Let through as many of the `n` cells as the bucket has room for, but at least `min`,
+        // which are denied if there's no room for them:
+        let weigh =
+            |room: Nanos| cmp::max(min_weight, t * cmp::min(n, (room / t).saturating_add(1)));
+        let decision = self.test_weighted_and_update::<K, P, S, MW>(
+            start,
+            key,
+            format,
+            Nanos::default(),
+            weigh,
+            state,
+            t0,
+        );
+        Ok(decision.map(|(weight, outcome)| {
+            let admitted = NonZeroU32::new((weight / t) as u32).unwrap_or(min);
+            (admitted, outcome)
+        }))
+    }
+
+    /// Tests whether cells that cost `cost` cells in total could be accommodated, and updates
+    /// the rate limiter state, if so.
+    ///
+    /// The cost is converted to the time that the cells take up in the state, rounded down to
+    /// a whole nanosecond.
+    pub(crate) fn test_cost_and_update<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        cost: f64,
+        state: &S,
+        t0: P,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, CostError> {
+        // No decision for a negative or NaN cost could ever be right:
+        if cost.is_nan() || cost < 0.0 {
+            return Err(CostError::InvalidCost);
+        }
+        if self.is_disabled() {
+            return Ok(Ok(self.bypassed::<K, P, MW>(start, key, format, t0)));
+        }
+        let capacity = self.t + self.tau;
+        let weight = cost * self.t.as_u64() as f64;
+        if weight >= (capacity.as_u64() + 1) as f64 {
+            return Err(InsufficientCapacity(self.burst_size().get()).into());
+        }
+        // The cast truncates, rounding the weight down to a whole nanosecond:
+        let weight = Nanos::from(weight as u64);
+        Ok(self
+            .test_weighted_and_update::<K, P, S, MW>(
+                start,
+                key,
+                format,
+                Nanos::default(),
+                |_| weight,
+                state,
+                t0,
+            )
+            .map(|(_, outcome)| outcome))
+    }
+
+    /// Tests cells weighing `weigh(room)` in total against the rate limiter state at the given
+    /// key, admitting them if they would conform within `grace` of `t0`, and updates the state
+    /// if so.
+    ///
+    /// See [`decide_weighted`](#method.decide_weighted) for what `weigh` gets. Returns the
+    /// weight of the cells along with the positive decision.
+    #[allow(clippy::too_many_arguments)]
+    fn test_weighted_and_update<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        grace: Nanos,
+        weigh: impl Fn(Nanos) -> Nanos,
+        state: &S,
+        t0: P,
+    ) -> Result<(Nanos, MW::PositiveOutcome), MW::NegativeOutcome> {
+        let t0 = t0.duration_since(start);
+        match self.decide_weighted(key, state, t0, grace, weigh) {
+            Ok((weight, next)) => Ok((
+                weight,
+                MW::allow(&format.show(key), self.snapshot(t0, next)),
+            )),
+            Err(earliest_time) => Err(MW::disallow(
+                &format.show(key),
+                self.denied_snapshot(t0, earliest_time),
+                start,
+            )),
+        }
+    }
+
+    /// Tests batches of cells, one after the other, against the rate limiter state at the given
+    /// key, updating it once for all of them.
+    ///
+    /// Each batch is decided like in [`test_n_all_and_update`](#method.test_n_all_and_update),
+    /// as if the batches before it were tested just before it. Returns one decision per batch.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn test_batches_and_update<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        batches: &[NonZeroU32],
+        state: &S,
+        t0: P,
+    ) -> Vec<Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity>> {
+        if self.is_disabled() {
+            return batches
+                .iter()
+                .map(|_| Ok(Ok(self.bypassed::<K, P, MW>(start, key, format, t0))))
+                .collect();
+        }
         let t0 = t0.duration_since(start);
         let tau = self.tau;
         let t = self.t;
-        let additional_weight = t * (n.get() - 1) as u64;
+        let decisions = state.measure_and_replace(key, |tat| {
+            let mut next_tat = None;
+            let decisions: Vec<_> = batches
+                .iter()
+                .map(|&n| {
+                    let additional_weight = self.additional_weight(n)?;
+                    let tat = next_tat.unwrap_or_else(|| self.tat_at(tat, t0));
+                    let earliest_time = (tat + additional_weight).saturating_sub(tau);
+                    if t0 < earliest_time {
+                        Ok(Err(MW::disallow(
+                            &format.show(key),
+                            self.denied_snapshot(t0, earliest_time),
+                            start,
+                        )))
+                    } else {
+                        let next =
+                            self.after_update(t0, tat, cmp::max(tat, t0) + t + additional_weight);
+                        next_tat = Some(next);
+                        Ok(Ok((
+                            MW::allow(&format.show(key), self.snapshot(t0, next)),
+                            self.stored(t0, next),
+                        )))
+                    }
+                })
+                .collect();
+            match next_tat {
+                Some(next) => Ok((decisions, self.stored(t0, next))),
+                None => Err(decisions),
+            }
+        });
+        decisions
+            .unwrap_or_else(|decisions| decisions)
+            .into_iter()
+            .map(|decision| decision.map(|d| Self::note_decision(key, state, t0, d)))
+            .collect()
+    }
 
-        // Check that we can allow enough cells through. Note that both `additional_weight` and
-        // `tau` represent the value of the cells *in addition* to the first cell.
-        if additional_weight > tau {
-            return Err(InsufficientCapacity(
-                1 + (self.tau.as_u64() / t.as_u64()) as u32,
-            ));
+    /// Tests a single cell against the rate limiter state, without updating it.
+    pub(crate) fn test_peek<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        state: &S,
+        t0: P,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        if self.is_disabled() {
+            return Ok(self.bypassed::<K, P, MW>(start, key, format, t0));
+        }
+        self.peek_weighted::<K, P, S, MW>(start, key, format, Nanos::default(), state, t0)
+    }
+
+    /// Returns the rate limiter state at the given key as of `t0`, without updating it.
+    ///
+    /// Returns `None` if the state store has no state for the key.
+    pub(crate) fn peek_snapshot<K, P: clock::Reference, S: StateStore<Key = K>>(
+        &self,
+        start: P,
+        key: &K,
+        state: &S,
+        t0: P,
+    ) -> Option<StateSnapshot> {
+        let t0 = t0.duration_since(start);
+        state
+            .measure_and_peek(key, |tat| {
+                Ok::<_, Infallible>(tat.map(|_| self.snapshot(t0, self.tat_at(tat, t0))))
+            })
+            .unwrap_or_else(|never| match never {})
+    }
+
+    /// Returns how long no cells have been let through for the rate limiter state at the given
+    /// key at `t0`, judging by its theoretical arrival time (or the time of the last positive
+    /// decision, which states keep with a burst recharge delay).
+    ///
+    /// A cell let through at a time `x` pushes the theoretical arrival time to at least `x + t`,
+    /// so this never reports more time than has actually passed since the last cell. States
+    /// that were never updated count as idle since the rate limiter's start.
+    pub(crate) fn idle_time<K, P: clock::Reference, S: StateStore<Key = K>>(
+        &self,
+        start: P,
+        key: &K,
+        state: &S,
+        t0: P,
+    ) -> Nanos {
+        let t0 = t0.duration_since(start);
+        state
+            .measure_and_peek(key, |tat| {
+                let last_cell = tat.map_or(Nanos::from(0), |tat| match self.recharge {
+                    None => tat.saturating_sub(self.t),
+                    Some(recharge) => recharge.unpack(tat).1,
+                });
+                Ok::<_, Infallible>(t0.saturating_sub(last_cell))
+            })
+            .unwrap_or_else(|never| match never {})
+    }
+
+    /// Tests whether all `n` cells could be accommodated, without updating the rate limiter
+    /// state.
+    pub(crate) fn test_n_all_peek<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        n: NonZeroU32,
+        state: &S,
+        t0: P,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        if self.is_disabled() {
+            return Ok(Ok(self.bypassed::<K, P, MW>(start, key, format, t0)));
+        }
+        let additional_weight = self.additional_weight(n)?;
+        Ok(self.peek_weighted::<K, P, S, MW>(start, key, format, additional_weight, state, t0))
+    }
+
+    /// Updates the rate limiter state with a single cell, regardless of whether it conforms.
+    pub(crate) fn consume<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        state: &S,
+        t0: P,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        if self.is_disabled() {
+            return Ok(self.bypassed::<K, P, MW>(start, key, format, t0));
+        }
+        self.consume_weighted::<K, P, S, MW>(start, key, format, Nanos::default(), state, t0)
+    }
+
+    /// Updates the rate limiter state with `n` cells, regardless of whether they conform.
+    pub(crate) fn consume_n<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        n: NonZeroU32,
+        state: &S,
+        t0: P,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        if self.is_disabled() {
+            return Ok(Ok(self.bypassed::<K, P, MW>(start, key, format, t0)));
         }
-        Ok(state.measure_and_replace(key, |tat| {
-            let tat = tat.unwrap_or(t0);
+        let additional_weight = self.additional_weight(n)?;
+        Ok(self.consume_weighted::<K, P, S, MW>(start, key, format, additional_weight, state, t0))
+    }
+
+    fn peek_weighted<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        additional_weight: Nanos,
+        state: &S,
+        t0: P,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let t0 = t0.duration_since(start);
+        let tau = self.tau;
+        let t = self.t;
+        state.measure_and_peek(key, |tat| {
+            let tat = self.tat_at(tat, t0);
             let earliest_time = (tat + additional_weight).saturating_sub(tau);
             if t0 < earliest_time {
                 Err(MW::disallow(
-                    key,
-                    StateSnapshot::new(self.t, self.tau, earliest_time, earliest_time),
+                    &format.show(key),
+                    self.denied_snapshot(t0, earliest_time),
                     start,
                 ))
             } else {
-                let next = cmp::max(tat, t0) + t + additional_weight;
+                let next = self.after_update(t0, tat, cmp::max(tat, t0) + t + additional_weight);
+                Ok(MW::allow(&format.show(key), self.snapshot(t0, next)))
+            }
+        })
+    }
+
+    /// Counts cells against the rate limiter state. If they don't conform, the negative
+    /// outcome indicates when the next cell could.
+    fn consume_weighted<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        additional_weight: Nanos,
+        state: &S,
+        t0: P,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let t0 = t0.duration_since(start);
+        let tau = self.tau;
+        let t = self.t;
+        let consumed = state.measure_and_replace(key, |tat| {
+            let tat = self.tat_at(tat, t0);
+            let earliest_time = (tat + additional_weight).saturating_sub(tau);
+            let next = self.after_update(t0, tat, cmp::max(tat, t0) + t + additional_weight);
+            let decision = if t0 < earliest_time {
+                let next_conforming = next.saturating_sub(tau);
+                Err(MW::disallow(
+                    &format.show(key),
+                    self.denied_snapshot(t0, next_conforming),
+                    start,
+                ))
+            } else {
+                Ok(MW::allow(&format.show(key), self.snapshot(t0, next)))
+            };
+            // The cells count against the state whether they conform or not:
+            let stored = self.stored(t0, next);
+            Ok::<_, Infallible>((decision.map(|outcome| (outcome, stored)), stored))
+        });
+        match consumed {
+            Ok(decision) => Self::note_decision(key, state, t0, decision),
+            Err(never) => match never {},
+        }
+    }
+
+    /// Reserves capacity for a single cell at the earliest time it would conform, if that is at
+    /// most `max_wait` away. Returns how long the caller has to wait until then.
+    pub(crate) fn reserve_within<
+        K,
+        P: clock::Reference,
+        S: StateStore<Key = K>,
+        MW: RateLimitingMiddleware<P>,
+    >(
+        &self,
+        start: P,
+        key: &K,
+        format: KeyFormat<K>,
+        max_wait: Nanos,
+        state: &S,
+        t0: P,
+    ) -> Result<(Nanos, MW::PositiveOutcome), MW::NegativeOutcome> {
+        if self.is_disabled() {
+            return Ok((
+                Nanos::default(),
+                self.bypassed::<K, P, MW>(start, key, format, t0),
+            ));
+        }
+        let t0 = t0.duration_since(start);
+        let tau = self.tau;
+        let t = self.t;
+        let decision = state.measure_and_replace(key, |tat| {
+            let tat = self.tat_at(tat, t0);
+            let earliest_time = tat.saturating_sub(tau);
+            let wait = earliest_time.saturating_sub(t0);
+            if wait > max_wait {
+                Err(MW::disallow(
+                    &format.show(key),
+                    self.denied_snapshot(t0, earliest_time),
+                    start,
+                ))
+            } else {
+                let arrival = cmp::max(earliest_time, t0);
+                let next = self.after_update(arrival, tat, cmp::max(tat, t0) + t);
+                let stored = self.stored(arrival, next);
                 Ok((
-                    MW::allow(key, StateSnapshot::new(self.t, self.tau, t0, next)),
-                    next,
+                    (
+                        (
+                            wait,
+                            MW::allow(&format.show(key), self.snapshot(arrival, next)),
+                        ),
+                        stored,
+                    ),
+                    stored,
                 ))
             }
-        }))
+        });
+        Self::note_decision(key, state, t0, decision)
+    }
+
+    /// Projects the times at which the next `n` cells would be allowed through, if nothing else
+    /// updated the rate limiter state at the given key in the meantime.
+    pub(crate) fn projected_times<K, P: clock::Reference, S: StateStore<Key = K>>(
+        &self,
+        start: P,
+        key: &K,
+        n: usize,
+        state: &S,
+        t0: P,
+    ) -> Vec<P> {
+        if self.is_disabled() {
+            return vec![t0; n];
+        }
+        let t0 = t0.duration_since(start);
+        let mut stored = state
+            .measure_and_peek(key, Ok::<_, Infallible>)
+            .unwrap_or_else(|never| match never {});
+        let mut earliest = t0;
+        (0..n)
+            .map(|_| {
+                earliest = cmp::max(
+                    earliest,
+                    self.tat_at(stored, earliest).saturating_sub(self.tau),
+                );
+                let tat = self.tat_at(stored, earliest);
+                let next = self.after_update(earliest, tat, cmp::max(tat, earliest) + self.t);
+                stored = Some(self.stored(earliest, next));
+                start + earliest
+            })
+            .collect()
+    }
+
+    /// Gives a single cell back to the rate limiter state at the given key, as if it had never
+    /// been let through.
+    ///
+    /// Does nothing if there is no state for the key.
+    pub(crate) fn refund<K, S: StateStore<Key = K>>(&self, key: &K, state: &S) {
+        self.refund_weight(key, self.t, state)
+    }
+
+    /// Gives `weight` worth of cells back to the rate limiter state at the given key.
+    ///
+    /// Does nothing if there is no state for the key, or if the rate limiter is disabled.
+    pub(crate) fn refund_weight<K, S: StateStore<Key = K>>(
+        &self,
+        key: &K,
+        weight: Nanos,
+        state: &S,
+    ) {
+        if self.is_disabled() {
+            return;
+        }
+        // There is nothing to refund if the key has no state; the error leaves the store alone.
+        let _ = state.measure_and_replace(key, |tat| match tat {
+            Some(tat) => Ok((
+                (),
+                match self.recharge {
+                    None => tat.saturating_sub(weight),
+                    Some(recharge) => {
+                        let (tat, last) = recharge.unpack(tat);
+                        recharge.pack(tat.saturating_sub(weight), last)
+                    }
+                },
+            )),
+            None => Err(()),
+        });
+    }
+
+    /// Tests a single cell against the rate limiter state at `key` at `t0`, and updates the
+    /// state if it conforms.
+    ///
+    /// Returns the theoretical arrival time after the update if the cell conforms, and the time
+    /// at which it would conform if it doesn't.
+    #[inline]
+    fn decide_one<K, S: StateStore<Key = K>>(
+        &self,
+        key: &K,
+        state: &S,
+        t0: Nanos,
+    ) -> Result<Nanos, Nanos> {
+        let tau = self.tau;
+        let t = self.t;
+        let decision = state.measure_and_replace(key, |tat| {
+            let tat = self.tat_at(tat, t0);
+            let earliest_time = tat.saturating_sub(tau);
+            if t0 < earliest_time {
+                Err(earliest_time)
+            } else {
+                let next = self.after_update(t0, tat, cmp::max(tat, t0) + t);
+                let stored = self.stored(t0, next);
+                Ok(((next, stored), stored))
+            }
+        });
+        Self::note_decision(key, state, t0, decision)
+    }
+
+    /// Tests cells against the rate limiter state at `key` at `t0`, admitting them if they would
+    /// conform within `grace` of `t0`, and updates the state if so.
+    ///
+    /// `weigh` gets the room that is left in the bucket at `t0` in addition to the first cell,
+    /// and returns the weight of the cells, at most the bucket's capacity of `t + tau`. Returns
+    /// the weight and the theoretical arrival time after the update if the cells conform, and
+    /// the time at which they would conform if they don't.
+    #[inline]
+    fn decide_weighted<K, S: StateStore<Key = K>>(
+        &self,
+        key: &K,
+        state: &S,
+        t0: Nanos,
+        grace: Nanos,
+        weigh: impl Fn(Nanos) -> Nanos,
+    ) -> Result<(Nanos, Nanos), Nanos> {
+        let capacity = self.t + self.tau;
+        let decision = state.measure_and_replace(key, |tat| {
+            let tat = self.tat_at(tat, t0);
+            let weight = weigh((t0 + self.tau).saturating_sub(tat));
+            // The cells conform as soon as their weight fits into the bucket; fractions of a
+            // cell fit in sooner than a whole one.
+            let earliest_time = tat
+                .saturating_sub(capacity.saturating_sub(weight))
+                .saturating_sub(grace);
+            if t0 < earliest_time {
+                Err(earliest_time)
+            } else {
+                let next = self.after_update(t0, tat, cmp::max(tat, t0) + weight);
+                let stored = self.stored(t0, next);
+                Ok((((weight, next), stored), stored))
+            }
+        });
+        Self::note_decision(key, state, t0, decision)
+    }
+
+    /// Tells the state store about a decision made at `t0`, returning its outcome.
+    ///
+    /// Positive decisions come with the state they stored.
+    fn note_decision<K, S: StateStore<Key = K>, T, E>(
+        key: &K,
+        state: &S,
+        t0: Nanos,
+        decision: Result<(T, Nanos), E>,
+    ) -> Result<T, E> {
+        match decision {
+            Ok((outcome, tat)) => {
+                state.note_admission(key, t0, tat);
+                Ok(outcome)
+            }
+            Err(outcome) => {
+                state.note_denial(key, t0);
+                Err(outcome)
+            }
+        }
+    }
+
+    /// Returns the theoretical arrival time to store after updating the state at `t0` from `tat`
+    /// to `next`, adding the cooldown if the update used up the last of the burst capacity.
+    #[inline]
+    fn after_update(&self, t0: Nanos, tat: Nanos, next: Nanos) -> Nanos {
+        let depleted = t0 + self.tau;
+        if tat <= depleted && next > depleted {
+            next + self.cooldown
+        } else {
+            next
+        }
+    }
+
+    /// Returns the theoretical arrival time that decisions at `t0` are made from, given the
+    /// stored state (`None` for a fresh one).
+    ///
+    /// Fresh states have their whole burst capacity available, or with [`FreshState::Empty`],
+    /// only one cell.
+    ///
+    /// Without a burst recharge delay, this is the stored theoretical arrival time. With one, the
+    /// burst capacity replenishes by at most one cell over what the last positive decision left
+    /// of it, until the delay has passed.
+    #[inline]
+    fn tat_at(&self, state: Option<Nanos>, t0: Nanos) -> Nanos {
+        match (state, self.recharge) {
+            (None, _) => match self.fresh {
+                FreshState::Full => t0,
+                FreshState::Empty => t0 + self.tau,
+            },
+            (Some(tat), None) => tat,
+            (Some(state), Some(recharge)) => {
+                let (tat, last) = recharge.unpack(state);
+                self.recharged_tat(tat, last, t0, recharge.delay)
+            }
+        }
+    }
+
+    /// Returns the theoretical arrival time at `t0` after the last positive decision at `last`
+    /// left it at `tat`, with a burst recharge delay of `delay`.
+    #[inline]
+    fn recharged_tat(&self, tat: Nanos, last: Nanos, t0: Nanos, delay: Nanos) -> Nanos {
+        // Everything that replenishes past the next cell is held back until the delay has
+        // passed:
+        let held = cmp::min(t0.saturating_sub(last), delay).saturating_sub(self.t);
+        tat + held
+    }
+
+    /// Returns the state to store after a positive decision at `t0` that left the theoretical
+    /// arrival time at `tat`.
+    #[inline]
+    fn stored(&self, t0: Nanos, tat: Nanos) -> Nanos {
+        match self.recharge {
+            None => tat,
+            Some(recharge) => recharge.pack(tat, t0),
+        }
+    }
+
+    /// Applies `f` to the times kept in the stored `state`, e.g. to move them to a new start.
+    pub(crate) fn rewrite_state<F: Fn(Nanos) -> Nanos>(&self, state: Nanos, f: F) -> Nanos {
+        match self.recharge {
+            None => f(state),
+            Some(recharge) => {
+                let (tat, last) = recharge.unpack(state);
+                recharge.pack(f(tat), f(last))
+            }
+        }
+    }
+
+    /// Returns the parameters for `quota`, keeping whether decisions are enabled and how fresh
+    /// states start.
+    pub(crate) fn with_quota(&self, quota: Quota) -> Gcra {
+        let mut gcra = Gcra::new(quota);
+        gcra.set_enabled(!self.is_disabled());
+        gcra.fresh = self.fresh;
+        gcra
+    }
+
+    /// Converts a `state` stored for these parameters into one for the parameters `to` at `t0`,
+    /// keeping the number of cells that it is in debt by.
+    ///
+    /// States without a burst recharge delay don't keep the time of the last positive decision;
+    /// it is taken to be the latest time it can have been: One replenishment interval before the
+    /// theoretical arrival time, but no later than `t0`.
+    pub(crate) fn convert_state(&self, state: Nanos, t0: Nanos, to: &Gcra) -> Nanos {
+        let (tat, last) = match self.recharge {
+            None => (state, cmp::min(state.saturating_sub(self.t), t0)),
+            Some(recharge) => recharge.unpack(state),
+        };
+        // Theoretical arrival times up to `t0` leave the whole burst capacity available under
+        // any quota; later ones get scaled to the new replenishment interval:
+        let tat = if tat > t0 {
+            let debt = u128::from(tat.saturating_sub(t0).as_u64()) * u128::from(to.t.as_u64())
+                / u128::from(self.t.as_u64());
+            t0 + Nanos::from(u64::try_from(debt).unwrap_or(u64::MAX))
+        } else {
+            tat
+        };
+        match to.recharge {
+            None => tat,
+            Some(recharge) => recharge.pack(tat, last),
+        }
+    }
+
+    /// How long after the time that its stored state indicates a state becomes
+    /// indistinguishable from a fresh one.
+    pub(crate) fn stale_after(&self) -> Nanos {
+        // The burst capacity that the delay holds back recharges by its end:
+        self.recharge
+            .map_or(self.t, |recharge| cmp::max(self.t, recharge.delay))
+    }
+
+    /// Returns the number of cells that could be let through at `t0` for the rate limiter
+    /// state at the given key, including the replenished fraction of the next cell.
+    pub(crate) fn available_cells<K, P: clock::Reference, S: StateStore<Key = K>>(
+        &self,
+        start: P,
+        key: &K,
+        state: &S,
+        t0: P,
+    ) -> f64 {
+        let t0 = t0.duration_since(start);
+        state
+            .measure_and_peek(key, |tat| {
+                let snapshot = self.snapshot(t0, self.tat_at(tat, t0));
+                Ok::<_, Infallible>(snapshot.available_cells_f64(t0))
+            })
+            .unwrap_or_else(|never| match never {})
+    }
+
+    /// Returns the number of cells that could be let through at `t0`, given the stored state.
+    pub(crate) fn remaining_cells(&self, tat: Option<Nanos>, t0: Nanos) -> u32 {
+        let tat = self.tat_at(tat, t0);
+        let available = (t0 + self.tau + self.t).saturating_sub(tat);
+        (cmp::min(available, self.tau + self.t) / self.t) as u32
+    }
+
+    /// Returns the maximum number of cells that fit into the bucket.
+    #[inline]
+    fn burst_size(&self) -> NonZeroU32 {
+        Quota::from_gcra_parameters(self.t, self.tau).burst_size()
+    }
+
+    /// Returns the weight of `n` cells beyond the first one, if they can ever be accommodated.
+    #[inline]
+    fn additional_weight(&self, n: NonZeroU32) -> Result<Nanos, InsufficientCapacity> {
+        let additional_weight = self.t * (n.get() - 1) as u64;
+
+        // Check that we can allow enough cells through. Note that both `additional_weight` and
+        // `tau` represent the value of the cells *in addition* to the first cell.
+        if additional_weight > self.tau {
+            return Err(InsufficientCapacity(self.burst_size().get()));
+        }
+        Ok(additional_weight)
     }
 }
 
@@ -254,4 +1528,23 @@ mod test {
             assert_eq!(quota, back);
         })
     }
+
+    #[test]
+    fn isqrt_rounds_down() {
+        for n in [0, 1, 2, 3, 4, 15, 16, 17, u64::MAX] {
+            let root = u128::from(isqrt(n));
+            assert!(root * root <= u128::from(n), "isqrt({}) = {}", n, root);
+            assert!(
+                (root + 1) * (root + 1) > u128::from(n),
+                "isqrt({}) = {}",
+                n,
+                root
+            );
+        }
+        proptest!(ProptestConfig::default(), |(n: u64)| {
+            let root = u128::from(isqrt(n));
+            assert!(root * root <= u128::from(n));
+            assert!((root + 1) * (root + 1) > u128::from(n));
+        })
+    }
 }
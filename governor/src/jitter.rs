@@ -5,14 +5,22 @@ use crate::nanos::Nanos;
 use rand::distributions::uniform::{SampleBorrow, SampleUniform, UniformInt, UniformSampler};
 #[cfg(feature = "jitter")]
 use rand::distributions::{Distribution, Uniform};
+#[cfg(all(feature = "jitter", feature = "std"))]
+use rand::thread_rng;
 #[cfg(feature = "jitter")]
-use rand::{thread_rng, Rng};
+use rand::{Rng, RngCore};
+#[cfg(feature = "jitter")]
+use spinning_top::Spinlock;
+use std::fmt;
 use std::ops::Add;
 use std::time::Duration;
 
 #[cfg(feature = "std")]
 use std::time::Instant;
 
+#[cfg(feature = "jitter")]
+use std::sync::Arc;
+
 /// An interval specification for deviating from the nominal wait time.
 ///
 /// Jitter can be added to wait time `Duration`s to ensure that multiple tasks waiting on the same
@@ -57,10 +65,44 @@ use std::time::Instant;
 /// # }
 /// # #[cfg(any(not(feature = "jitter"), not(feature = "std")))] fn main() {}
 /// ```
-#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
+///
+/// Jitter normally draws random numbers from the thread-local RNG. To make wait times
+/// reproducible (e.g. in tests), use [`with_rng`](#method.with_rng) to draw them from a seeded
+/// RNG instead.
+///
+/// # Without `std`
+/// With the `jitter` feature, Jitter is also available in `no_std` builds, e.g. to spread out
+/// the retries of devices that share a rate limited service. There is no thread-local RNG
+/// there, so the jitter needs a source of randomness: Either an RNG given to
+/// [`with_rng`](#method.with_rng), or a seed for a small built-in generator given to
+/// [`with_seed`](#method.with_seed) (e.g. derived from a device's serial number). Jitter
+/// without either always deviates by its minimum.
+#[derive(Default, Clone)]
+// Without `std` or `jitter`, the type is neither exported nor used:
+#[cfg_attr(not(any(feature = "std", feature = "jitter")), allow(dead_code))]
 pub struct Jitter {
     min: Nanos,
     max: Nanos,
+    #[cfg(feature = "jitter")]
+    rng: Option<Arc<Spinlock<dyn RngCore + Send>>>,
+}
+
+impl PartialEq for Jitter {
+    fn eq(&self, other: &Self) -> bool {
+        self.min == other.min && self.max == other.max
+    }
+}
+
+impl Eq for Jitter {}
+
+impl fmt::Debug for Jitter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("Jitter");
+        d.field("min", &self.min).field("max", &self.max);
+        #[cfg(feature = "jitter")]
+        d.field("seeded", &self.rng.is_some());
+        d.finish()
+    }
 }
 
 impl Jitter {
@@ -69,6 +111,8 @@ impl Jitter {
     pub(crate) const NONE: Jitter = Jitter {
         min: Nanos::new(0),
         max: Nanos::new(0),
+        #[cfg(feature = "jitter")]
+        rng: None,
     };
 
     /// Constructs a new Jitter interval, waiting at most a duration of `max`.
@@ -85,20 +129,79 @@ impl Jitter {
     /// # #[cfg(not(all(feature = "jitter", not(feature = "no_std"))))]
     /// # fn main() {}
     /// ```
-    #[cfg(any(all(feature = "jitter", not(feature = "no_std")), feature = "std"))]
+    #[cfg(any(feature = "jitter", feature = "std"))]
     pub fn up_to(max: Duration) -> Jitter {
         Jitter {
             min: Nanos::from(0),
-            max: max.into(),
+            max: Nanos::saturating_from(max),
+            #[cfg(feature = "jitter")]
+            rng: None,
         }
     }
 
     /// Constructs a new Jitter interval, waiting at least `min` and at most `min+interval`.
-    #[cfg(any(all(feature = "jitter", not(feature = "no_std")), feature = "std"))]
+    #[cfg(any(feature = "jitter", feature = "std"))]
     pub fn new(min: Duration, interval: Duration) -> Jitter {
-        let min: Nanos = min.into();
-        let max: Nanos = min + Nanos::from(interval);
-        Jitter { min, max }
+        let min = Nanos::saturating_from(min);
+        let max = Nanos::from(
+            min.as_u64()
+                .saturating_add(Nanos::saturating_from(interval).as_u64()),
+        );
+        Jitter {
+            min,
+            max,
+            #[cfg(feature = "jitter")]
+            rng: None,
+        }
+    }
+
+    /// Draws the jitter's random amounts from `rng` instead of the thread-local RNG.
+    ///
+    /// Clones of the returned Jitter share the RNG, so a rate limiter's wait times are
+    /// reproducible if the RNG is seeded and the order of waits is deterministic.
+    ///
+    /// ```rust
+    /// # #[cfg(feature = "std")]
+    /// # fn main() {
+    /// # use std::time::Duration;
+    /// # use governor::Jitter;
+    /// use rand::{rngs::StdRng, SeedableRng};
+    ///
+    /// let jitter = |seed| Jitter::up_to(Duration::from_secs(20)).with_rng(StdRng::seed_from_u64(seed));
+    /// let (first, second) = (jitter(42), jitter(42));
+    /// for _ in 0..10 {
+    ///     assert_eq!(first.clone() + Duration::ZERO, second.clone() + Duration::ZERO);
+    /// }
+    /// # }
+    /// # #[cfg(not(feature = "std"))]
+    /// # fn main() {}
+    /// ```
+    #[cfg(feature = "jitter")]
+    pub fn with_rng<R: RngCore + Send + 'static>(self, rng: R) -> Jitter {
+        Jitter {
+            rng: Some(Arc::new(Spinlock::new(rng))),
+            ..self
+        }
+    }
+
+    /// Draws the jitter's random amounts from a small built-in generator, seeded with `seed`.
+    ///
+    /// The generator is fast and needs no operating system support, but it is not
+    /// cryptographically secure. This makes it a source of randomness for `no_std` builds,
+    /// where devices can each use a different seed to spread out their wait times.
+    ///
+    /// Like with [`with_rng`](#method.with_rng), clones of the returned Jitter share the
+    /// generator.
+    ///
+    /// ```rust
+    /// # use std::time::Duration;
+    /// # use governor::Jitter;
+    /// let jitter = Jitter::up_to(Duration::from_secs(20)).with_seed(0x5eed);
+    /// assert!(jitter + Duration::ZERO <= Duration::from_secs(20));
+    /// ```
+    #[cfg(feature = "jitter")]
+    pub fn with_seed(self, seed: u64) -> Jitter {
+        self.with_rng(SplitMix64(seed))
     }
 
     /// Returns a random amount of jitter within the configured interval.
@@ -108,16 +211,54 @@ impl Jitter {
             return self.min;
         }
         let uniform = Uniform::new(self.min, self.max);
-        uniform.sample(&mut thread_rng())
+        match &self.rng {
+            Some(rng) => uniform.sample(&mut *rng.lock()),
+            #[cfg(feature = "std")]
+            None => uniform.sample(&mut thread_rng()),
+            #[cfg(not(feature = "std"))]
+            None => self.min,
+        }
     }
 
     /// Returns a random amount of jitter within the configured interval.
     #[cfg(not(feature = "jitter"))]
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
     pub(crate) fn get(&self) -> Nanos {
         self.min
     }
 }
 
+/// The SplitMix64 generator: small and fast, and good enough for spreading out wait times.
+#[cfg(feature = "jitter")]
+struct SplitMix64(u64);
+
+#[cfg(feature = "jitter")]
+impl RngCore for SplitMix64 {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
 /// A random distribution of nanoseconds
 #[cfg(feature = "jitter")]
 #[derive(Clone, Copy, Debug)]
@@ -186,7 +327,7 @@ impl Add<Instant> for Jitter {
     }
 }
 
-#[cfg(all(feature = "jitter", not(feature = "no_std"), test))]
+#[cfg(all(feature = "jitter", feature = "std", test))]
 mod test {
     use super::*;
 
@@ -201,8 +342,41 @@ mod test {
     fn uniform_sampler_coverage() {
         let low = Duration::from_secs(0);
         let high = Duration::from_secs(20);
-        let sampler = UniformJitter::new_inclusive(Nanos::from(low), Nanos::from(high));
-        assert!(format!("{:?}", sampler).len() > 0);
-        assert!(format!("{:?}", sampler.clone()).len() > 0);
+        let sampler =
+            UniformJitter::new_inclusive(Nanos::saturating_from(low), Nanos::saturating_from(high));
+        assert!(!format!("{:?}", sampler).is_empty());
+        assert!(!format!("{:?}", sampler.clone()).is_empty());
+    }
+
+    #[test]
+    fn seeded_jitter_is_reproducible() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let jitter = Jitter::up_to(Duration::from_secs(20));
+        let first = jitter.clone().with_rng(StdRng::seed_from_u64(1));
+        let second = jitter.clone().with_rng(StdRng::seed_from_u64(1));
+        assert_eq!(first, jitter);
+        let first: Vec<Nanos> = (0..10).map(|_| first.get()).collect();
+        let second: Vec<Nanos> = (0..10).map(|_| second.get()).collect();
+        assert_eq!(first, second);
+        assert!(first.iter().any(|n| *n != first[0]));
+    }
+
+    #[test]
+    fn seeded_generator() {
+        let jitter = Jitter::new(Duration::from_secs(1), Duration::from_secs(1));
+        let first = jitter.clone().with_seed(0);
+        let second = jitter.with_seed(0);
+        let first: Vec<Nanos> = (0..100).map(|_| first.get()).collect();
+        let second: Vec<Nanos> = (0..100).map(|_| second.get()).collect();
+        assert_eq!(first, second);
+        assert!(first.iter().any(|n| *n != first[0]));
+        assert!(first
+            .iter()
+            .all(|n| (Nanos::from(1_000_000_000)..Nanos::from(2_000_000_000)).contains(n)));
+
+        let mut bytes = [0u8; 13];
+        SplitMix64(1).fill_bytes(&mut bytes);
+        assert!(bytes[8..].iter().any(|b| *b != 0));
     }
 }
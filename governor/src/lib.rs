@@ -38,18 +38,40 @@
 extern crate no_std_compat as std;
 
 pub mod r#_guide;
+pub mod bandwidth;
+#[cfg(feature = "criterion")]
+pub mod bench;
+#[cfg(feature = "chrono")]
+pub mod calendar;
 pub mod clock;
+#[cfg(feature = "std")]
+pub mod concurrency;
 mod errors;
+#[cfg(feature = "std")]
+pub mod flavors;
 mod gcra;
 mod jitter;
 pub mod middleware;
+pub mod mock;
 pub mod nanos;
-mod quota;
+pub mod quota;
+#[cfg(feature = "std")]
+pub mod ramp;
+#[cfg(feature = "std")]
+pub mod registry;
+pub mod retry;
+#[cfg(feature = "std")]
+pub mod shadow;
 pub mod state;
+mod sync;
+#[cfg(feature = "proptest")]
+pub mod testing;
+#[cfg(feature = "std")]
+mod timer;
 
 pub use errors::*;
 pub use gcra::NotUntil;
-#[cfg(all(feature = "std", feature = "jitter"))]
+#[cfg(feature = "jitter")]
 pub use jitter::Jitter;
 #[cfg(all(feature = "std", not(feature = "jitter")))]
 pub(crate) use jitter::Jitter;
@@ -57,13 +79,21 @@ pub use quota::Quota;
 #[doc(inline)]
 pub use state::RateLimiter;
 
+#[cfg(feature = "std")]
+pub use state::direct::DirectLimiter;
+#[cfg(feature = "std")]
+pub use state::direct::Permits;
+#[cfg(feature = "std")]
+pub use state::direct::RatelimitedIter;
 #[cfg(feature = "std")]
 pub use state::direct::RatelimitedSink;
 #[cfg(feature = "std")]
 pub use state::direct::RatelimitedStream;
 
-/// The collection of asynchronous traits exported from this crate.
+/// The collection of rate-limiting extension traits exported from this crate.
 pub mod prelude {
+    #[cfg(feature = "std")]
+    pub use crate::state::direct::IteratorRateLimitExt;
     #[cfg(feature = "std")]
     pub use crate::state::direct::SinkRateLimitExt;
     #[cfg(feature = "std")]
@@ -17,7 +17,7 @@
 //! (returning `Err`). However, you can override the values returned
 //! inside the Result for either decision.
 //!
-//! This crate ships two middlewares (named after their behavior in the
+//! This crate ships these middlewares (named after their behavior in the
 //! positive outcome):
 //!
 //! * The cheapest still-useful one, [`NoOpMiddleware`], named after its
@@ -28,6 +28,10 @@
 //!   returns `Ok(`[`StateSnapshot`]`)`, or
 //!   `Err(`[`NotUntil`]`)`.
 //!
+//! * A middleware for HTTP servers, [`HttpHeadersMiddleware`], which
+//!   returns the values of rate limiting response headers in both cases:
+//!   `Ok(`[`RateLimitHeaders`]`)`, or `Err(`[`RateLimitHeaders`]`)`.
+//!
 //! ## Using a custom middleware
 //!
 //! Middlewares are attached to the
@@ -64,7 +68,7 @@
 //!
 //! You can define your own middleware by `impl`ing [`RateLimitingMiddleware`].
 use core::fmt;
-use std::{cmp, marker::PhantomData};
+use std::{cmp, convert::TryFrom, marker::PhantomData, num::NonZeroU32};
 
 use crate::{clock, nanos::Nanos, NotUntil, Quota};
 
@@ -79,6 +83,12 @@ pub struct StateSnapshot {
     /// The total "burst capacity" of the bucket is `t + tau`.
     tau: Nanos,
 
+    /// The cooldown of the quota, see [`Quota::with_cooldown`].
+    cooldown: Nanos,
+
+    /// The burst recharge delay of the quota, see [`Quota::with_burst_recharge_delay`].
+    recharge_delay: Nanos,
+
     /// The time at which the measurement was taken.
     pub(crate) time_of_measurement: Nanos,
 
@@ -92,14 +102,82 @@ impl StateSnapshot {
         Self {
             t,
             tau,
+            cooldown: Nanos::from(0),
+            recharge_delay: Nanos::from(0),
             time_of_measurement,
             tat,
         }
     }
 
+    /// Records the cooldown of the quota that the decision was made with.
+    #[inline]
+    pub(crate) fn with_cooldown(self, cooldown: Nanos) -> Self {
+        Self { cooldown, ..self }
+    }
+
+    /// Records the burst recharge delay of the quota that the decision was made with.
+    #[inline]
+    pub(crate) fn with_recharge_delay(self, recharge_delay: Nanos) -> Self {
+        Self {
+            recharge_delay,
+            ..self
+        }
+    }
+
+    /// Constructs the snapshot for a negative decision made at `t0`, where the next cell would
+    /// conform at `earliest_conforming`.
+    #[inline]
+    pub(crate) fn denied(t: Nanos, tau: Nanos, t0: Nanos, earliest_conforming: Nanos) -> Self {
+        Self::new(t, tau, t0, earliest_conforming + tau)
+    }
+
+    /// Returns the earliest time at which a cell could be let through, measured from the rate
+    /// limiter's start.
+    #[inline]
+    pub(crate) fn earliest_conforming(&self) -> Nanos {
+        self.tat.saturating_sub(self.tau)
+    }
+
+    /// Returns the "weight" of a single cell: the time it takes the rate limiter to replenish
+    /// one cell.
+    ///
+    /// Unlike [`quota`](#method.quota)'s [`replenish_interval`](Quota::replenish_interval), this
+    /// is the exact value that the rate limiter uses for its calculations.
+    pub fn t(&self) -> Nanos {
+        self.t
+    }
+
+    /// Returns the rate limiter's "tolerance": how far ahead of the time of measurement the
+    /// theoretical arrival time may lie for a cell to still be allowed through.
+    ///
+    /// The burst capacity of the rate limiter corresponds to `t + tau`.
+    pub fn tau(&self) -> Nanos {
+        self.tau
+    }
+
+    /// Returns the theoretical arrival time of the next cell, after the decision was made.
+    ///
+    /// For negative decisions, this is the theoretical arrival time that the rejected cells
+    /// required: They could have been let through once the time of measurement reached
+    /// `tat - tau`.
+    ///
+    /// Like [`time_of_measurement`](#method.time_of_measurement), this is measured from the
+    /// rate limiter's [start](crate::RateLimiter::start).
+    pub fn tat(&self) -> Nanos {
+        self.tat
+    }
+
+    /// Returns the time at which the decision was made, measured from the rate limiter's
+    /// [start](crate::RateLimiter::start).
+    pub fn time_of_measurement(&self) -> Nanos {
+        self.time_of_measurement
+    }
+
     /// Returns the quota used to make the rate limiting decision.
     pub fn quota(&self) -> Quota {
         Quota::from_gcra_parameters(self.t, self.tau)
+            .with_cooldown(self.cooldown.into())
+            .with_burst_recharge_delay(self.recharge_delay.into())
     }
 
     /// Returns the number of cells that can be let through in
@@ -108,11 +186,90 @@ impl StateSnapshot {
     /// If this state snapshot is based on a negative rate limiting
     /// outcome, this method returns 0.
     pub fn remaining_burst_capacity(&self) -> u32 {
-        let t0 = self.time_of_measurement;
-        (cmp::min(
-            (t0 + self.tau + self.t).saturating_sub(self.tat).as_u64(),
-            (self.tau + self.t).as_u64(),
-        ) / self.t.as_u64()) as u32
+        self.capacity_at(self.time_of_measurement)
+    }
+
+    /// Returns the number of cells that the rate limiter could let through at once at time
+    /// `t`, if no other cells were let through after the decision was made.
+    ///
+    /// Like [`time_of_measurement`](#method.time_of_measurement), `t` is measured from the rate
+    /// limiter's [start](crate::RateLimiter::start); times before the measurement count as the
+    /// time of measurement. This lets planners schedule batches of work for when enough
+    /// capacity is available:
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{
+    ///     clock::FakeRelativeClock, middleware::StateInformationMiddleware, nanos::Nanos, Quota,
+    ///     RateLimiter,
+    /// };
+    ///
+    /// let lim = RateLimiter::direct_with_clock(
+    ///     Quota::per_second(nonzero!(10u32)),
+    ///     FakeRelativeClock::default(),
+    /// )
+    /// .with_middleware::<StateInformationMiddleware>();
+    /// let snapshot = lim.check_n(nonzero!(10u32)).unwrap().unwrap();
+    /// let half_a_second = Nanos::from(500_000_000);
+    /// assert_eq!(snapshot.capacity_at(snapshot.time_of_measurement()), 0);
+    /// assert_eq!(snapshot.capacity_at(snapshot.time_of_measurement() + half_a_second), 5);
+    /// assert_eq!(snapshot.time_with_capacity(nonzero!(5u32)), Some(half_a_second));
+    /// ```
+    pub fn capacity_at(&self, t: Nanos) -> u32 {
+        let t = cmp::max(t, self.time_of_measurement);
+        let capacity = cmp::min(
+            (t + self.tau + self.t).saturating_sub(self.tat),
+            self.tau + self.t,
+        );
+        u32::try_from(capacity / self.t).unwrap_or(u32::MAX)
+    }
+
+    /// Returns the number of cells that the rate limiter could let through at time `t`,
+    /// including the fraction of the next cell that has been replenished by then, if no other
+    /// cells were let through after the decision was made.
+    ///
+    /// This is measured like [`capacity_at`](#method.capacity_at), which returns the whole
+    /// cells of this number. It is meant for displaying the progress towards the next cell,
+    /// e.g. in a UI:
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{
+    ///     clock::FakeRelativeClock, middleware::StateInformationMiddleware, nanos::Nanos, Quota,
+    ///     RateLimiter,
+    /// };
+    ///
+    /// let lim = RateLimiter::direct_with_clock(
+    ///     Quota::per_second(nonzero!(10u32)),
+    ///     FakeRelativeClock::default(),
+    /// )
+    /// .with_middleware::<StateInformationMiddleware>();
+    /// let snapshot = lim.check_n(nonzero!(10u32)).unwrap().unwrap();
+    /// let later = snapshot.time_of_measurement() + Nanos::from(270_000_000);
+    /// assert_eq!(snapshot.available_cells_f64(later), 2.7);
+    /// assert_eq!(snapshot.capacity_at(later), 2);
+    /// ```
+    pub fn available_cells_f64(&self, t: Nanos) -> f64 {
+        let t = cmp::max(t, self.time_of_measurement);
+        let capacity = cmp::min(
+            (t + self.tau + self.t).saturating_sub(self.tat),
+            self.tau + self.t,
+        );
+        capacity.as_u64() as f64 / self.t.as_u64() as f64
+    }
+
+    /// Returns the earliest time at which the rate limiter could let `n` cells through at once,
+    /// if no other cells were let through after the decision was made.
+    ///
+    /// This is the inverse of [`capacity_at`](#method.capacity_at), measured the same way.
+    /// Returns `None` if `n` exceeds the burst size, so that the cells could never be let
+    /// through at once.
+    pub fn time_with_capacity(&self, n: NonZeroU32) -> Option<Nanos> {
+        if n > self.quota().burst_size() {
+            return None;
+        }
+        let available = (self.tat + self.t * (n.get() - 1) as u64).saturating_sub(self.tau);
+        Some(cmp::max(available, self.time_of_measurement))
     }
 }
 
@@ -199,15 +356,20 @@ pub trait RateLimitingMiddleware<P: clock::Reference>: fmt::Debug {
     /// was one cell left in the burst capacity before the decision
     /// was reached, the [`StateSnapshot::remaining_burst_capacity`]
     /// method will return 0.
-    fn allow<K>(key: &K, state: impl Into<StateSnapshot>) -> Self::PositiveOutcome;
+    ///
+    /// The `key` can only be displayed, in the rate limiter's
+    /// [`KeyFormat`]: By default, keyed rate limiters show a hash of
+    /// the key they made the decision for.
+    fn allow<K: fmt::Display>(key: &K, state: impl Into<StateSnapshot>) -> Self::PositiveOutcome;
 
     /// Called when a negative rate-limiting decision is made (the
     /// "not allowed but OK" case).
     ///
     /// This method returns whatever value is returned inside the
     /// `Err` variant a [`RateLimiter`][crate::RateLimiter]'s check
-    /// method returns.
-    fn disallow<K>(
+    /// method returns. As with [`allow`](#tymethod.allow), the `key`
+    /// is shown in the rate limiter's [`KeyFormat`].
+    fn disallow<K: fmt::Display>(
         key: &K,
         limiter: impl Into<StateSnapshot>,
         start_time: P,
@@ -271,6 +433,97 @@ impl<P: clock::Reference> RateLimitingMiddleware<P> for StateInformationMiddlewa
     }
 }
 
+/// Middleware that records each decision's rate limiting state on the current [`tracing`] span,
+/// and otherwise behaves like the `Inner` middleware.
+///
+/// For every decision, the middleware records these fields:
+///
+/// * `governor.allowed`: whether the decision was positive.
+/// * `governor.remaining_burst_capacity`: the number of cells that could be let through in
+///   addition to the decision's cells; zero for negative decisions.
+/// * `governor.wait_time_ms`: the number of milliseconds until the next cell could be let
+///   through; zero if there is remaining burst capacity.
+/// * `governor.key`: the key that the decision was made for, in the rate limiter's
+///   [`KeyFormat`]. By default, that is a hash of the key, so personal information in keys
+///   (like e-mail or IP addresses) doesn't end up in traces.
+///
+/// Spans only record the fields that they were created with, so request spans have to declare
+/// them up front, as [`Empty`](tracing::field::Empty) values.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{middleware::TracingMiddleware, Quota, RateLimiter};
+/// use tracing::field::Empty;
+///
+/// let lim = RateLimiter::keyed(Quota::per_second(nonzero!(10u32)))
+///     .with_middleware::<TracingMiddleware>();
+/// let span = tracing::info_span!(
+///     "request",
+///     governor.allowed = Empty,
+///     governor.remaining_burst_capacity = Empty,
+///     governor.wait_time_ms = Empty,
+///     governor.key = Empty,
+/// );
+/// let _entered = span.enter();
+/// assert_eq!(Ok(()), lim.check_key(&"alice"));
+/// ```
+#[cfg(feature = "tracing")]
+#[derive(Debug)]
+pub struct TracingMiddleware<Inner = NoOpMiddleware> {
+    phantom: PhantomData<Inner>,
+}
+
+#[cfg(feature = "tracing")]
+impl<Inner> TracingMiddleware<Inner> {
+    fn record<K: fmt::Display>(key: &K, allowed: bool, state: &StateSnapshot) {
+        let wait = state
+            .earliest_conforming()
+            .saturating_sub(state.time_of_measurement);
+        let span = tracing::Span::current();
+        span.record("governor.allowed", allowed);
+        span.record(
+            "governor.remaining_burst_capacity",
+            state.remaining_burst_capacity(),
+        );
+        span.record("governor.wait_time_ms", wait.as_u64() / 1_000_000);
+        span.record("governor.key", tracing::field::display(key));
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl<P, Inner> RateLimitingMiddleware<P> for TracingMiddleware<Inner>
+where
+    P: clock::Reference,
+    Inner: RateLimitingMiddleware<P>,
+{
+    type PositiveOutcome = Inner::PositiveOutcome;
+
+    type NegativeOutcome = Inner::NegativeOutcome;
+
+    fn allow<K: fmt::Display>(key: &K, state: impl Into<StateSnapshot>) -> Self::PositiveOutcome {
+        let state = state.into();
+        Self::record(key, true, &state);
+        Inner::allow(key, state)
+    }
+
+    fn disallow<K: fmt::Display>(
+        key: &K,
+        state: impl Into<StateSnapshot>,
+        start_time: P,
+    ) -> Self::NegativeOutcome {
+        let state = state.into();
+        Self::record(key, false, &state);
+        Inner::disallow(key, state, start_time)
+    }
+}
+
+mod http;
+pub use http::*;
+
+mod keys;
+pub use keys::*;
+
 #[cfg(all(feature = "std", test))]
 mod test {
     use std::time::Duration;
@@ -0,0 +1,174 @@
+use std::prelude::v1::*;
+
+use std::time::Duration;
+
+use super::{RateLimitingMiddleware, StateSnapshot};
+use crate::clock;
+use crate::nanos::Nanos;
+
+/// Middleware that returns the rate limiting headers for HTTP responses, for both positive and
+/// negative decisions.
+///
+/// The headers follow the IETF draft for [RateLimit header
+/// fields](https://datatracker.ietf.org/doc/draft-ietf-httpapi-ratelimit-headers/), in the form
+/// that most clients understand: `RateLimit-Limit`, `RateLimit-Remaining` and
+/// `RateLimit-Reset`, plus `Retry-After` for negative decisions. See [`RateLimitHeaders`] for
+/// the values.
+///
+/// As negative decisions return headers instead of a [`NotUntil`](crate::NotUntil), rate
+/// limiters with this middleware can't wait for cells to be allowed through (e.g. with
+/// [`until_ready`](crate::RateLimiter::until_ready)); they are meant for servers that reject
+/// requests that don't conform.
+///
+/// # Example
+/// ```rust
+/// # #[cfg(feature = "std")]
+/// # fn main () {
+/// # use nonzero_ext::nonzero;
+/// use governor::{middleware::HttpHeadersMiddleware, Quota, RateLimiter};
+///
+/// let lim = RateLimiter::keyed(Quota::per_minute(nonzero!(2u32)))
+///     .with_middleware::<HttpHeadersMiddleware>();
+///
+/// let headers = lim.check_key(&"alice").unwrap();
+/// assert_eq!(
+///     headers.to_headers(),
+///     [
+///         ("RateLimit-Limit", "2".to_string()),
+///         ("RateLimit-Remaining", "1".to_string()),
+///         ("RateLimit-Reset", "30".to_string()),
+///     ]
+/// );
+///
+/// lim.check_key(&"alice").unwrap();
+/// let headers = lim.check_key(&"alice").unwrap_err();
+/// assert_eq!(headers.remaining(), 0);
+/// assert_eq!(headers.retry_after_header(), Some("30".to_string()));
+/// # }
+/// # #[cfg(not(feature = "std"))]
+/// # fn main() {}
+/// ```
+#[derive(Debug)]
+pub struct HttpHeadersMiddleware;
+
+impl<P: clock::Reference> RateLimitingMiddleware<P> for HttpHeadersMiddleware {
+    type PositiveOutcome = RateLimitHeaders;
+
+    type NegativeOutcome = RateLimitHeaders;
+
+    fn allow<K>(_key: &K, state: impl Into<StateSnapshot>) -> Self::PositiveOutcome {
+        RateLimitHeaders::new(&state.into(), false)
+    }
+
+    fn disallow<K>(
+        _key: &K,
+        state: impl Into<StateSnapshot>,
+        _start_time: P,
+    ) -> Self::NegativeOutcome {
+        RateLimitHeaders::new(&state.into(), true)
+    }
+}
+
+/// The values of the rate limiting headers for an HTTP response, as returned by
+/// [`HttpHeadersMiddleware`].
+///
+/// Header values are given in whole seconds, rounded up, so that clients which wait for them
+/// don't come back too early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitHeaders {
+    limit: u32,
+    remaining: u32,
+    reset: Duration,
+    retry_after: Option<Duration>,
+}
+
+impl RateLimitHeaders {
+    /// The name of the header that holds the [`limit`](#method.limit).
+    pub const LIMIT: &'static str = "RateLimit-Limit";
+
+    /// The name of the header that holds the [`remaining`](#method.remaining) cells.
+    pub const REMAINING: &'static str = "RateLimit-Remaining";
+
+    /// The name of the header that holds the time until the [`reset`](#method.reset).
+    pub const RESET: &'static str = "RateLimit-Reset";
+
+    /// The name of the header that holds the time until a client should
+    /// [`retry`](#method.retry_after).
+    pub const RETRY_AFTER: &'static str = "Retry-After";
+
+    fn new(state: &StateSnapshot, denied: bool) -> Self {
+        let t0 = state.time_of_measurement();
+        let retry_after: Nanos = state.earliest_conforming().saturating_sub(t0);
+        RateLimitHeaders {
+            limit: state.quota().burst_size().get(),
+            remaining: state.remaining_burst_capacity(),
+            reset: state.tat().saturating_sub(t0).into(),
+            retry_after: if denied {
+                Some(retry_after.into())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// The maximum number of cells that the rate limiter allows through at once: its quota's
+    /// burst size.
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    /// The number of cells that the rate limiter would allow through right after the decision.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// The time until the rate limiter has replenished its entire burst capacity.
+    pub fn reset(&self) -> Duration {
+        self.reset
+    }
+
+    /// For negative decisions, the time until the rate limiter would allow the rejected cells
+    /// through.
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// Returns the value of the `RateLimit-Limit` header.
+    pub fn limit_header(&self) -> String {
+        self.limit.to_string()
+    }
+
+    /// Returns the value of the `RateLimit-Remaining` header.
+    pub fn remaining_header(&self) -> String {
+        self.remaining.to_string()
+    }
+
+    /// Returns the value of the `RateLimit-Reset` header.
+    pub fn reset_header(&self) -> String {
+        whole_seconds(self.reset).to_string()
+    }
+
+    /// Returns the value of the `Retry-After` header, for negative decisions.
+    pub fn retry_after_header(&self) -> Option<String> {
+        self.retry_after
+            .map(|retry_after| whole_seconds(retry_after).to_string())
+    }
+
+    /// Returns the names and values of all the headers, to add to a response.
+    pub fn to_headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![
+            (Self::LIMIT, self.limit_header()),
+            (Self::REMAINING, self.remaining_header()),
+            (Self::RESET, self.reset_header()),
+        ];
+        if let Some(retry_after) = self.retry_after_header() {
+            headers.push((Self::RETRY_AFTER, retry_after));
+        }
+        headers
+    }
+}
+
+/// Returns the number of seconds in `duration`, rounded up.
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
@@ -0,0 +1,275 @@
+use std::prelude::v1::*;
+
+use core::hash::{Hash, Hasher};
+use std::fmt;
+
+use siphasher::sip::SipHasher13;
+
+/// The seed that [`KeyFormat::hashed`] hashes keys with.
+const DEFAULT_SEED: [u8; 16] = *b"governor keys  \x01";
+
+/// How a rate limiter shows its keys to middleware, e.g. to put them in logs and metrics.
+///
+/// Keys often identify people (as e-mail addresses, IP addresses or account names do), so
+/// keyed rate limiters don't hand them to middleware as they are: By default, middleware sees a
+/// hash of each key, which tells keys apart without revealing them. Rate limiters can show
+/// their keys differently with
+/// [`with_key_format`](crate::RateLimiter::with_key_format), e.g. partially masked, or (where
+/// that is fine) in full.
+///
+/// Direct rate limiters have no keys to show; unless they are given a key format (e.g. one
+/// that shows the rate limiter's name), they show `[redacted]`.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{
+///     clock::Reference,
+///     middleware::{KeyFormat, RateLimitingMiddleware, StateSnapshot},
+///     Quota, RateLimiter,
+/// };
+/// use std::fmt::Display;
+///
+/// /// Returns the key of each decision as middleware sees it.
+/// #[derive(Debug)]
+/// struct ShowKey;
+///
+/// impl<P: Reference> RateLimitingMiddleware<P> for ShowKey {
+///     type PositiveOutcome = String;
+///     type NegativeOutcome = String;
+///
+///     fn allow<K: Display>(key: &K, _: impl Into<StateSnapshot>) -> String {
+///         key.to_string()
+///     }
+///
+///     fn disallow<K: Display>(key: &K, _: impl Into<StateSnapshot>, _: P) -> String {
+///         key.to_string()
+///     }
+/// }
+///
+/// let quota = Quota::per_second(nonzero!(1u32));
+/// let lim = RateLimiter::keyed(quota).with_middleware::<ShowKey>();
+/// assert_eq!(lim.check_key(&"alice@example.com").unwrap().len(), 17);
+///
+/// let lim = RateLimiter::keyed(quota)
+///     .with_key_format(KeyFormat::masked())
+///     .with_middleware::<ShowKey>();
+/// assert_eq!(lim.check_key(&"alice@example.com").unwrap(), "alic***");
+///
+/// let lim = RateLimiter::keyed(quota)
+///     .with_key_format(KeyFormat::full())
+///     .with_middleware::<ShowKey>();
+/// assert_eq!(lim.check_key(&"alice@example.com").unwrap(), "alice@example.com");
+/// ```
+pub struct KeyFormat<K: ?Sized>(Repr<K>);
+
+enum Repr<K: ?Sized> {
+    Hashed {
+        hash: fn(&K, &mut SipHasher13),
+        k0: u64,
+        k1: u64,
+    },
+    Custom(fn(&K, &mut fmt::Formatter<'_>) -> fmt::Result),
+}
+
+impl<K: ?Sized> KeyFormat<K> {
+    /// Shows a key as `#` followed by 16 hexadecimal digits of its SipHash-1-3 hash.
+    ///
+    /// This is the default for keyed rate limiters. The hashes are the same in every process,
+    /// so the events of different processes can be correlated by key. Keys from a small set
+    /// (like IPv4 addresses) can be found from their hashes by trying them all, though; to
+    /// prevent that, use [`hashed_with_seed`](#method.hashed_with_seed) with a secret seed.
+    pub fn hashed() -> Self
+    where
+        K: Hash,
+    {
+        KeyFormat::hashed_with_seed(DEFAULT_SEED)
+    }
+
+    /// Shows a key as `#` followed by 16 hexadecimal digits of its SipHash-1-3 hash, with
+    /// `seed` as the hash's key.
+    pub fn hashed_with_seed(seed: [u8; 16]) -> Self
+    where
+        K: Hash,
+    {
+        let mut k0 = [0; 8];
+        let mut k1 = [0; 8];
+        k0.copy_from_slice(&seed[..8]);
+        k1.copy_from_slice(&seed[8..]);
+        KeyFormat(Repr::Hashed {
+            hash: |key, hasher| key.hash(hasher),
+            k0: u64::from_le_bytes(k0),
+            k1: u64::from_le_bytes(k1),
+        })
+    }
+
+    /// Shows the first quarter (but at most four) of a key's characters, followed by `***`.
+    pub fn masked() -> Self
+    where
+        K: fmt::Display,
+    {
+        KeyFormat(Repr::Custom(|key, f| {
+            let mut len = CharCount(0);
+            fmt::write(&mut len, format_args!("{}", key))?;
+            let mut prefix = Prefix {
+                inner: f,
+                remaining: (len.0 / 4).min(4),
+            };
+            fmt::write(&mut prefix, format_args!("{}", key))?;
+            f.write_str("***")
+        }))
+    }
+
+    /// Shows keys as they are.
+    ///
+    /// Only use this where the keys don't identify anyone, or where the middleware's output
+    /// may contain personal information.
+    pub fn full() -> Self
+    where
+        K: fmt::Display,
+    {
+        KeyFormat(Repr::Custom(|key, f| fmt::Display::fmt(key, f)))
+    }
+
+    /// Shows every key as `[redacted]`.
+    pub fn redacted() -> Self {
+        KeyFormat(Repr::Custom(|_, f| f.write_str("[redacted]")))
+    }
+
+    /// Shows keys with the function `show`.
+    pub fn custom(show: fn(&K, &mut fmt::Formatter<'_>) -> fmt::Result) -> Self {
+        KeyFormat(Repr::Custom(show))
+    }
+
+    /// Returns `key`, to be shown in this format.
+    pub fn show<'a>(&self, key: &'a K) -> DisplayKey<'a, K> {
+        DisplayKey { key, format: *self }
+    }
+}
+
+impl<K: ?Sized> Clone for KeyFormat<K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K: ?Sized> Copy for KeyFormat<K> {}
+
+impl<K: ?Sized> Clone for Repr<K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<K: ?Sized> Copy for Repr<K> {}
+
+impl<K: ?Sized> fmt::Debug for KeyFormat<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            // The seed should be kept secret:
+            Repr::Hashed { .. } => f.write_str("KeyFormat::Hashed"),
+            Repr::Custom(_) => f.write_str("KeyFormat::Custom"),
+        }
+    }
+}
+
+/// A rate limiter's key, as middleware gets to see it: It can only be displayed, in the
+/// rate limiter's [`KeyFormat`].
+pub struct DisplayKey<'a, K: ?Sized> {
+    key: &'a K,
+    format: KeyFormat<K>,
+}
+
+impl<K: ?Sized> fmt::Display for DisplayKey<'_, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format.0 {
+            Repr::Hashed { hash, k0, k1 } => {
+                let mut hasher = SipHasher13::new_with_keys(k0, k1);
+                hash(self.key, &mut hasher);
+                write!(f, "#{:016x}", hasher.finish())
+            }
+            Repr::Custom(show) => show(self.key, f),
+        }
+    }
+}
+
+impl<K: ?Sized> fmt::Debug for DisplayKey<'_, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Counts the characters written to it.
+struct CharCount(usize);
+
+impl fmt::Write for CharCount {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.chars().count();
+        Ok(())
+    }
+}
+
+/// Writes the first `remaining` characters written to it to `inner`, and drops the rest.
+struct Prefix<'a, 'f> {
+    inner: &'a mut fmt::Formatter<'f>,
+    remaining: usize,
+}
+
+impl fmt::Write for Prefix<'_, '_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = s
+            .char_indices()
+            .nth(self.remaining)
+            .map_or(s.len(), |(i, _)| i);
+        self.remaining -= s[..end].chars().count();
+        self.inner.write_str(&s[..end])
+    }
+}
+
+#[cfg(all(feature = "std", test))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn key_formats() {
+        let key = "alice@example.com";
+        let show = |format: KeyFormat<&str>| format.show(&key).to_string();
+        assert_eq!(show(KeyFormat::full()), "alice@example.com");
+        assert_eq!(show(KeyFormat::masked()), "alic***");
+        assert_eq!(show(KeyFormat::redacted()), "[redacted]");
+        assert_eq!(
+            show(KeyFormat::custom(|key, f| write!(f, "{} chars", key.len()))),
+            "17 chars"
+        );
+
+        let hashed = show(KeyFormat::hashed());
+        assert_eq!(hashed.len(), 17);
+        assert!(hashed.starts_with('#'));
+        assert!(!hashed.contains("alice"));
+        assert_eq!(hashed, show(KeyFormat::hashed()));
+        assert_eq!(hashed, show(KeyFormat::hashed_with_seed(DEFAULT_SEED)));
+        assert_ne!(hashed, show(KeyFormat::hashed_with_seed([1; 16])));
+        assert_ne!(hashed, KeyFormat::hashed().show(&"bob").to_string());
+        assert_eq!(
+            format!("{:?}", KeyFormat::<&str>::hashed()),
+            "KeyFormat::Hashed"
+        );
+
+        // Short keys are masked entirely, long ones show at most four characters:
+        assert_eq!(KeyFormat::masked().show(&"bob").to_string(), "***");
+        assert_eq!(
+            KeyFormat::masked().show(&"192.168.1.1").to_string(),
+            "19***"
+        );
+        assert_eq!(
+            KeyFormat::masked()
+                .show(&"äöüßäöüßäöüßäöüßäöüß")
+                .to_string(),
+            "äöüß***"
+        );
+        assert_eq!(
+            KeyFormat::masked().show(&1_234_567_890u64).to_string(),
+            "12***"
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! State stores that make fixed decisions, for unit tests of code that uses rate limiters.
+//!
+//! Code that takes a [`RateLimiter`] often needs to be tested with both
+//! positive and negative decisions. Rather than exhausting a real rate limiter's burst and
+//! moving a fake clock around, tests can construct rate limiters with one of the state stores
+//! in this module:
+//!
+//! * [`AlwaysAllowStore`] lets every cell through,
+//! * [`AlwaysDenyStore`] rejects every cell, telling callers to retry after a fixed duration.
+//!
+//! Code under test needs to be generic over the rate limiter's state store to accept them; the
+//! [`AlwaysAllowLimiter`] and [`AlwaysDenyLimiter`] aliases name the resulting rate limiter
+//! types.
+//!
+//! # Example
+//! ```rust
+//! # use nonzero_ext::nonzero;
+//! # use std::time::Duration;
+//! use governor::{
+//!     clock::{Clock, FakeRelativeClock},
+//!     middleware::NoOpMiddleware,
+//!     mock::{AlwaysAllowStore, AlwaysDenyLimiter, AlwaysDenyStore},
+//!     state::{DirectStateStore, NotKeyed},
+//!     Quota, RateLimiter,
+//! };
+//!
+//! // The code under test, which works with any state store:
+//! fn fetch<S, C>(lim: &RateLimiter<NotKeyed, S, C, NoOpMiddleware<C::Instant>>) -> Result<(), Duration>
+//! where
+//!     S: DirectStateStore,
+//!     C: Clock,
+//! {
+//!     lim.check().map_err(|e| e.wait_time_from(lim.clock().now()))
+//! }
+//!
+//! let quota = Quota::per_second(nonzero!(1u32));
+//! let lim = RateLimiter::new(quota, AlwaysAllowStore::default(), FakeRelativeClock::default());
+//! assert_eq!(fetch(&lim), Ok(()));
+//! assert_eq!(fetch(&lim), Ok(()));
+//!
+//! let lim: AlwaysDenyLimiter<NotKeyed, FakeRelativeClock> = RateLimiter::new(
+//!     quota,
+//!     AlwaysDenyStore::new(Duration::from_secs(5)),
+//!     FakeRelativeClock::default(),
+//! );
+//! assert_eq!(fetch(&lim), Err(Duration::from_secs(5)));
+//! ```
+
+use std::prelude::v1::*;
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use crate::{
+    clock,
+    middleware::NoOpMiddleware,
+    nanos::Nanos,
+    state::{NotKeyed, StateStore},
+    RateLimiter,
+};
+
+/// A state store that lets every cell through, without keeping any state.
+///
+/// Every decision is made as if for a key that was never seen before, so every check of a
+/// number of cells up to the quota's burst size is positive. Larger batches still fail with
+/// [`InsufficientCapacity`](crate::InsufficientCapacity), as they would with any other store.
+///
+/// The store is direct by default; use `AlwaysAllowStore<K>` for keyed rate limiters.
+pub struct AlwaysAllowStore<K = NotKeyed> {
+    key: PhantomData<fn() -> K>,
+}
+
+impl<K> AlwaysAllowStore<K> {
+    /// Constructs a state store that lets every cell through.
+    pub const fn new() -> Self {
+        AlwaysAllowStore { key: PhantomData }
+    }
+}
+
+impl<K> Default for AlwaysAllowStore<K> {
+    fn default() -> Self {
+        AlwaysAllowStore::new()
+    }
+}
+
+impl<K> fmt::Debug for AlwaysAllowStore<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AlwaysAllowStore").finish()
+    }
+}
+
+impl<K> StateStore for AlwaysAllowStore<K> {
+    type Key = K;
+
+    fn measure_and_replace<T, F, E>(&self, _key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        f(None).map(|(result, _)| result)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, _key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        f(None)
+    }
+}
+
+/// A state store that rejects every cell, telling callers to retry after a fixed duration.
+///
+/// Negative decisions report that the cells would conform `retry_after` after the decision was
+/// made (in [`NotUntil::wait_time_from`](crate::NotUntil::wait_time_from), for example),
+/// regardless of the rate limiter's quota and clock. The store doesn't know the time at which
+/// decisions are made, so it finds a state that is that far ahead by asking the rate limiter's
+/// decision function about several states; middlewares see an outcome for each of these.
+///
+/// The store is direct by default; use `AlwaysDenyStore<K>` for keyed rate limiters.
+pub struct AlwaysDenyStore<K = NotKeyed> {
+    retry_after: Nanos,
+    key: PhantomData<fn() -> K>,
+}
+
+impl<K> AlwaysDenyStore<K> {
+    /// Constructs a state store that rejects every cell, with callers told to retry after
+    /// `retry_after`.
+    ///
+    /// Rate limiters can't tell callers to retry after less than a nanosecond, so shorter
+    /// durations are rounded up to that.
+    pub fn new(retry_after: Duration) -> Self {
+        AlwaysDenyStore {
+            retry_after: Nanos::saturating_from(retry_after).max(Nanos::from(1)),
+            key: PhantomData,
+        }
+    }
+
+    /// Returns the duration after which callers are told to retry.
+    pub fn retry_after(&self) -> Duration {
+        self.retry_after.into()
+    }
+
+    /// Returns the state that makes the decision `f` negative, with the cells conforming
+    /// `retry_after` after the decision.
+    fn denying_state<T, E>(&self, f: impl Fn(Option<Nanos>) -> Result<T, E>) -> Nanos {
+        // Decisions are positive for all states up to the time of the decision plus the
+        // tolerance for the cells, and negative after it; search for that boundary:
+        let (mut allowed, mut denied) = (0u64, u64::MAX);
+        while denied - allowed > 1 {
+            let mid = allowed + (denied - allowed) / 2;
+            if f(Some(Nanos::from(mid))).is_ok() {
+                allowed = mid;
+            } else {
+                denied = mid;
+            }
+        }
+        Nanos::from(allowed) + self.retry_after
+    }
+}
+
+impl<K> fmt::Debug for AlwaysDenyStore<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AlwaysDenyStore")
+            .field("retry_after", &self.retry_after())
+            .finish()
+    }
+}
+
+impl<K> StateStore for AlwaysDenyStore<K> {
+    type Key = K;
+
+    fn measure_and_replace<T, F, E>(&self, _key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let state = self.denying_state(&f);
+        f(Some(state)).map(|(result, _)| result)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, _key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        let state = self.denying_state(&f);
+        f(Some(state))
+    }
+}
+
+/// A rate limiter that lets every cell through; see [`AlwaysAllowStore`].
+///
+/// The rate limiter is direct and runs on the default clock, unless other parameters are given.
+pub type AlwaysAllowLimiter<
+    K = NotKeyed,
+    C = clock::DefaultClock,
+    MW = NoOpMiddleware<<C as clock::Clock>::Instant>,
+> = RateLimiter<K, AlwaysAllowStore<K>, C, MW>;
+
+/// A rate limiter that rejects every cell; see [`AlwaysDenyStore`].
+///
+/// The rate limiter is direct and runs on the default clock, unless other parameters are given.
+pub type AlwaysDenyLimiter<
+    K = NotKeyed,
+    C = clock::DefaultClock,
+    MW = NoOpMiddleware<<C as clock::Clock>::Instant>,
+> = RateLimiter<K, AlwaysDenyStore<K>, C, MW>;
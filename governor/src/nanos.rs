@@ -2,7 +2,7 @@
 
 use crate::clock;
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use std::ops::{Add, Div, Mul};
 use std::prelude::v1::*;
@@ -12,6 +12,16 @@ use std::time::Duration;
 ///
 /// Nanos can not represent durations >584 years, but hopefully that
 /// should not be a problem in real-world applications.
+///
+/// # Overflow
+/// All arithmetic on `Nanos` saturates instead of overflowing: Sums and products that would
+/// exceed [`u64::MAX`] nanoseconds (the "horizon", about 584 years after the reference point)
+/// are clamped to it, and differences that would be negative are clamped to zero. Rate
+/// limiters measure time from their creation, so their theoretical arrival times only reach
+/// the horizon if the quota's replenishment interval (times the number of cells tested) is
+/// that long, or if the rate limiter has been around for that long. A state that saturated
+/// at the horizon lets no further cells through until the clock reaches the horizon too, and
+/// the wait times reported for it are measured to the horizon.
 #[derive(PartialEq, Eq, Default, Clone, Copy, PartialOrd, Ord)]
 pub struct Nanos(u64);
 
@@ -19,6 +29,12 @@ impl Nanos {
     pub fn as_u64(self) -> u64 {
         self.0
     }
+
+    /// Converts a [`Duration`] to nanoseconds, saturating at the
+    /// largest representable duration (about 584 years).
+    pub fn saturating_from(d: Duration) -> Nanos {
+        Nanos(d.as_nanos().try_into().unwrap_or(u64::MAX))
+    }
 }
 
 /// Nanos as used by Jitter and other std-only features.
@@ -29,14 +45,28 @@ impl Nanos {
     }
 }
 
-impl From<Duration> for Nanos {
-    fn from(d: Duration) -> Self {
-        // This will panic:
-        Nanos(
-            d.as_nanos()
-                .try_into()
-                .expect("Duration is longer than 584 years"),
-        )
+/// The error returned when a [`Duration`] is too long to be
+/// represented as [`Nanos`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DurationTooLong(());
+
+impl fmt::Display for DurationTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "duration is longer than 584 years")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DurationTooLong {}
+
+impl TryFrom<Duration> for Nanos {
+    type Error = DurationTooLong;
+
+    fn try_from(d: Duration) -> Result<Self, Self::Error> {
+        d.as_nanos()
+            .try_into()
+            .map(Nanos)
+            .map_err(|_| DurationTooLong(()))
     }
 }
 
@@ -51,7 +81,7 @@ impl Add<Nanos> for Nanos {
     type Output = Nanos;
 
     fn add(self, rhs: Nanos) -> Self::Output {
-        Nanos(self.0 + rhs.0)
+        Nanos(self.0.saturating_add(rhs.0))
     }
 }
 
@@ -59,15 +89,17 @@ impl Mul<u64> for Nanos {
     type Output = Nanos;
 
     fn mul(self, rhs: u64) -> Self::Output {
-        Nanos(self.0 * rhs)
+        Nanos(self.0.saturating_mul(rhs))
     }
 }
 
+/// Dividing by zero nanoseconds saturates at `u64::MAX`, instead of panicking.
 impl Div<Nanos> for Nanos {
     type Output = u64;
 
+    #[inline]
     fn div(self, rhs: Nanos) -> Self::Output {
-        self.0 / rhs.0
+        self.0.checked_div(rhs.0).unwrap_or(u64::MAX)
     }
 }
 
@@ -112,8 +144,7 @@ impl Add<Duration> for Nanos {
     type Output = Self;
 
     fn add(self, other: Duration) -> Self {
-        let other: Nanos = other.into();
-        self + other
+        Nanos(self.0.saturating_add(Nanos::saturating_from(other).0))
     }
 }
 
@@ -128,15 +159,38 @@ mod test {
         assert_eq!("Nanos(20ns)", format!("{:?}", n));
     }
 
+    #[test]
+    fn nanos_from_duration() {
+        let d = Duration::from_secs(20);
+        assert_eq!(Nanos::try_from(d), Ok(Nanos::new(20_000_000_000)));
+        assert_eq!(Nanos::saturating_from(d), Nanos::new(20_000_000_000));
+
+        let d = Duration::from_secs(600 * 365 * 24 * 60 * 60);
+        let err = Nanos::try_from(d).unwrap_err();
+        assert_eq!(format!("{}", err), "duration is longer than 584 years");
+        assert_eq!(Nanos::saturating_from(d), Nanos::new(u64::MAX));
+        assert_eq!(Nanos::new(u64::MAX - 1) + d, Nanos::new(u64::MAX));
+    }
+
     #[test]
     fn nanos_arith_coverage() {
         let n = Nanos::new(20);
         let n_half = Nanos::new(10);
         assert_eq!(n / n_half, 2);
+        assert_eq!(n / Nanos::new(0), u64::MAX);
         assert_eq!(30, (n + Duration::from_nanos(10)).as_u64());
 
         assert_eq!(n_half.saturating_sub(n), Nanos::new(0));
         assert_eq!(n.saturating_sub(n_half), n_half);
         assert_eq!(clock::Reference::saturating_sub(&n_half, n), Nanos::new(0));
     }
+
+    #[test]
+    fn nanos_arith_saturates() {
+        let max = Nanos::new(u64::MAX);
+        assert_eq!(max + Nanos::new(1), max);
+        assert_eq!(Nanos::new(u64::MAX - 1) + Nanos::new(20), max);
+        assert_eq!(Nanos::new(u64::MAX / 2 + 1) * 2, max);
+        assert_eq!(Nanos::new(10) * 3, Nanos::new(30));
+    }
 }
@@ -1,10 +1,17 @@
 use std::prelude::v1::*;
 
 use nonzero_ext::nonzero;
+use std::cmp;
+use std::convert::TryFrom;
 use std::num::NonZeroU32;
+use std::str::FromStr;
 use std::time::Duration;
 
+use crate::gcra::Gcra;
 use crate::nanos::Nanos;
+use crate::{QuotaError, SuspiciousQuota};
+
+pub mod presets;
 
 /// A rate-limiting quota.
 ///
@@ -29,6 +36,26 @@ use crate::nanos::Nanos;
 /// In other words, the burst size is the maximum number of cells that the rate limiter will ever
 /// allow through without replenishing them.
 ///
+/// # Idle periods
+/// A rate limiter doesn't carry over credit from idle periods beyond its burst size: Once the
+/// burst size has been replenished, staying idle for longer doesn't allow any more cells through.
+/// (In GCRA terms, the theoretical arrival time never lags behind the time of a decision.)
+/// However, a rate limiter that was idle can let a full burst through and then continue at the
+/// replenishment rate, so an interval of time can see more cells than the burst size. Use
+/// [`max_cells_within`](#method.max_cells_within) to check that a quota meets a limit on the
+/// number of cells in *any* interval of a given length:
+/// ```rust
+/// # use governor::Quota;
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// // 10 cells per second, with a burst size of 10, let up to 19 cells through in a second:
+/// let q = Quota::per_second(nonzero!(10u32));
+/// assert_eq!(q.max_cells_within(Duration::from_millis(999)), 19);
+/// // To allow at most 10 cells in any second, the burst and the rate have to share them:
+/// let q = Quota::with_period(Duration::from_millis(200)).unwrap().allow_burst(nonzero!(5u32));
+/// assert_eq!(q.max_cells_within(Duration::from_millis(999)), 9);
+/// ```
+///
 /// # Examples
 ///
 /// Construct a quota that allows 50 cells per second (replenishing at a rate of one cell
@@ -63,6 +90,8 @@ use crate::nanos::Nanos;
 pub struct Quota {
     pub(crate) max_burst: NonZeroU32,
     pub(crate) replenish_1_per: Duration,
+    pub(crate) cooldown: Duration,
+    pub(crate) recharge_delay: Duration,
 }
 
 /// Constructors for Quotas
@@ -74,6 +103,8 @@ impl Quota {
         Quota {
             max_burst,
             replenish_1_per: Duration::from_nanos(replenish_interval_ns as u64),
+            cooldown: Duration::ZERO,
+            recharge_delay: Duration::ZERO,
         }
     }
 
@@ -84,6 +115,8 @@ impl Quota {
         Quota {
             max_burst,
             replenish_1_per: Duration::from_nanos(replenish_interval_ns as u64),
+            cooldown: Duration::ZERO,
+            recharge_delay: Duration::ZERO,
         }
     }
 
@@ -95,7 +128,71 @@ impl Quota {
         Quota {
             max_burst,
             replenish_1_per: Duration::from_nanos(replenish_interval_ns as u64),
+            cooldown: Duration::ZERO,
+            recharge_delay: Duration::ZERO,
+        }
+    }
+
+    /// Construct a quota for a fractional number of cells per second, e.g. `0.5` for one cell
+    /// every two seconds.
+    ///
+    /// The replenishment interval is `1 / rate` seconds, rounded to the nearest nanosecond. The
+    /// maximum burst size is the rate rounded down to a whole number of cells, but at least one
+    /// cell; use [`allow_burst`](#method.allow_burst) to change it.
+    ///
+    /// Whole-numbered rates result in exactly the same quota as
+    /// [`per_second`](#method.per_second) (which rounds the replenishment interval down), so
+    /// quotas read from configuration compare equal to the ones constructed in code.
+    ///
+    /// Returns an error if the rate is not a positive number, or if the replenishment interval
+    /// would round to zero or be too long for rate limiters to keep track of (about 584 years).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use governor::{Quota, QuotaError};
+    /// # use std::time::Duration;
+    /// let quota = Quota::per_second_f64(0.5).unwrap();
+    /// assert_eq!(quota.replenish_interval(), Duration::from_secs(2));
+    /// assert_eq!(quota.burst_size().get(), 1);
+    ///
+    /// let quota = Quota::per_second_f64(2.5).unwrap();
+    /// assert_eq!(quota.replenish_interval(), Duration::from_millis(400));
+    /// assert_eq!(quota.burst_size().get(), 2);
+    ///
+    /// assert_eq!(Quota::per_second_f64(0.0), Err(QuotaError::InvalidRate));
+    /// ```
+    ///
+    /// ```rust
+    /// # use governor::Quota;
+    /// # use nonzero_ext::nonzero;
+    /// assert_eq!(Quota::per_second_f64(7.0), Ok(Quota::per_second(nonzero!(7u32))));
+    /// ```
+    pub fn per_second_f64(rate: f64) -> Result<Quota, QuotaError> {
+        if rate.is_nan() || rate <= 0.0 {
+            return Err(QuotaError::InvalidRate);
+        }
+        let interval_ns = 1e9 / rate;
+        if interval_ns < 0.5 {
+            return Err(QuotaError::RateTooHigh);
+        }
+        if interval_ns >= u64::MAX as f64 {
+            return Err(QuotaError::RateTooLow);
         }
+        if rate <= 1e9 && (rate as u32) as f64 == rate {
+            // Whole-numbered rates round the way `per_second` does:
+            if let Some(max_burst) = NonZeroU32::new(rate as u32) {
+                return Ok(Quota::per_second(max_burst));
+            }
+        }
+        // Both casts saturate; the checks above keep them in range:
+        let interval_ns = (interval_ns + 0.5) as u64;
+        let max_burst = NonZeroU32::new(rate as u32).unwrap_or(nonzero!(1u32));
+        Ok(Quota {
+            max_burst,
+            replenish_1_per: Duration::from_nanos(interval_ns),
+            cooldown: Duration::ZERO,
+            recharge_delay: Duration::ZERO,
+        })
     }
 
     /// Construct a quota that replenishes one cell in a given
@@ -105,7 +202,8 @@ impl Quota {
     /// in cases where a longer refresh period than 1 cell/hour is
     /// necessary.
     ///
-    /// If the time interval is zero, returns `None`.
+    /// If the time interval is zero, or too long for rate limiters to
+    /// keep track of (about 584 years), returns `None`.
     ///
     /// # Example
     /// ```rust
@@ -118,22 +216,251 @@ impl Quota {
     ///     .allow_burst(nonzero!(10u32));
     /// ```
     pub fn with_period(replenish_1_per: Duration) -> Option<Quota> {
-        if replenish_1_per.as_nanos() == 0 {
+        if replenish_1_per.as_nanos() == 0 || Nanos::try_from(replenish_1_per).is_err() {
             None
         } else {
             Some(Quota {
                 max_burst: nonzero!(1u32),
                 replenish_1_per,
+                cooldown: Duration::ZERO,
+                recharge_delay: Duration::ZERO,
             })
         }
     }
 
+    /// Construct a quota for a number of cells per second, like
+    /// [`per_second`](#method.per_second), but returning an error
+    /// instead of a quota that rate limiters can't honor.
+    ///
+    /// For more than 10<sup>9</sup> cells per second, the replenishment
+    /// interval rounds down to zero. Rate limiters treat such quotas as
+    /// replenishing one cell per nanosecond, which lets fewer cells
+    /// through than requested; this constructor returns
+    /// [`QuotaError::RateTooHigh`] for them instead.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use governor::{Quota, QuotaError};
+    /// # use nonzero_ext::nonzero;
+    /// assert_eq!(
+    ///     Quota::try_per_second(nonzero!(50u32)),
+    ///     Ok(Quota::per_second(nonzero!(50u32)))
+    /// );
+    /// assert_eq!(
+    ///     Quota::try_per_second(nonzero!(2_000_000_000u32)),
+    ///     Err(QuotaError::RateTooHigh)
+    /// );
+    /// ```
+    pub fn try_per_second(max_burst: NonZeroU32) -> Result<Quota, QuotaError> {
+        Quota::per_second(max_burst).validated()
+    }
+
+    /// Construct a quota that replenishes one cell in a given
+    /// interval, like [`with_period`](#method.with_period), but
+    /// reporting why the interval can't be used.
+    ///
+    /// Returns [`QuotaError::RateTooHigh`] if the interval is zero, and
+    /// [`QuotaError::RateTooLow`] if it is too long for rate limiters
+    /// to keep track of (about 584 years).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use governor::{Quota, QuotaError};
+    /// # use std::time::Duration;
+    /// assert!(Quota::try_with_period(Duration::from_millis(20)).is_ok());
+    /// assert_eq!(
+    ///     Quota::try_with_period(Duration::ZERO),
+    ///     Err(QuotaError::RateTooHigh)
+    /// );
+    /// ```
+    pub fn try_with_period(replenish_1_per: Duration) -> Result<Quota, QuotaError> {
+        Quota {
+            max_burst: nonzero!(1u32),
+            replenish_1_per,
+            cooldown: Duration::ZERO,
+            recharge_delay: Duration::ZERO,
+        }
+        .validated()
+    }
+
+    /// Parses a rate of the form `<cells>/<unit>`, like `"10/minute"`,
+    /// into a quota.
+    ///
+    /// The unit is one of `second`, `minute`, `hour` or `day`, and the
+    /// number of cells is also the quota's maximum burst size. Rates
+    /// per second, minute and hour result in the same quotas as
+    /// [`per_second`](#method.per_second) and the like.
+    ///
+    /// This is a `const fn`, so rates given in code can be checked at
+    /// compile time (which is what
+    /// [`static_rate_limiter!`](crate::static_rate_limiter) does). For
+    /// other units and periods, like `"5000 per 100 milliseconds"`,
+    /// parse the rate with [`str::parse`] instead; see the
+    /// [`FromStr`](#impl-FromStr-for-Quota) implementation.
+    ///
+    /// Returns [`QuotaError::Unparseable`] if the rate isn't of that
+    /// form, or if the number of cells is zero or doesn't fit into a
+    /// `u32`, and [`QuotaError::RateTooHigh`] if the replenishment interval
+    /// would be shorter than 1ns.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use governor::{Quota, QuotaError};
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// const LOGIN: Quota = match Quota::parse_rate("10/minute") {
+    ///     Ok(quota) => quota,
+    ///     Err(_) => panic!("invalid quota"),
+    /// };
+    /// assert_eq!(LOGIN, Quota::per_minute(nonzero!(10u32)));
+    ///
+    /// let daily = Quota::parse_rate("2/day").unwrap();
+    /// assert_eq!(daily.replenish_interval(), Duration::from_secs(12 * 60 * 60));
+    ///
+    /// assert_eq!(Quota::parse_rate("10 per minute"), Err(QuotaError::Unparseable));
+    /// ```
+    pub const fn parse_rate(rate: &str) -> Result<Quota, QuotaError> {
+        let bytes = rate.as_bytes();
+        let mut i = 0;
+        let mut cells: u64 = 0;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            cells = cells * 10 + (bytes[i] - b'0') as u64;
+            if cells > u32::MAX as u64 {
+                return Err(QuotaError::Unparseable);
+            }
+            i += 1;
+        }
+        if i == 0 || i == bytes.len() || bytes[i] != b'/' {
+            return Err(QuotaError::Unparseable);
+        }
+        let period_secs: u64 = if ends_with_at(bytes, i + 1, b"second") {
+            1
+        } else if ends_with_at(bytes, i + 1, b"minute") {
+            60
+        } else if ends_with_at(bytes, i + 1, b"hour") {
+            60 * 60
+        } else if ends_with_at(bytes, i + 1, b"day") {
+            24 * 60 * 60
+        } else {
+            return Err(QuotaError::Unparseable);
+        };
+        let max_burst = match NonZeroU32::new(cells as u32) {
+            Some(max_burst) => max_burst,
+            None => return Err(QuotaError::Unparseable),
+        };
+        let replenish_interval_ns =
+            Duration::from_secs(period_secs).as_nanos() / (max_burst.get() as u128);
+        if replenish_interval_ns == 0 {
+            return Err(QuotaError::RateTooHigh);
+        }
+        Ok(Quota {
+            max_burst,
+            replenish_1_per: Duration::from_nanos(replenish_interval_ns as u64),
+            cooldown: Duration::ZERO,
+            recharge_delay: Duration::ZERO,
+        })
+    }
+
+    /// Checks that rate limiters can track the quota's replenishment
+    /// interval without adjusting it.
+    fn validated(self) -> Result<Quota, QuotaError> {
+        if self.replenish_1_per.as_nanos() == 0 {
+            Err(QuotaError::RateTooHigh)
+        } else if Nanos::try_from(self.replenish_1_per).is_err() {
+            Err(QuotaError::RateTooLow)
+        } else {
+            Ok(self)
+        }
+    }
+
     /// Adjusts the maximum burst size for a quota to construct a rate limiter with a capacity
     /// for at most the given number of cells.
     pub const fn allow_burst(self, max_burst: NonZeroU32) -> Quota {
         Quota { max_burst, ..self }
     }
 
+    /// Adds a cooldown to a quota: Whenever a positive decision uses up the last of the burst
+    /// capacity, the rate limiter additionally waits for `cooldown` before it lets the next cell
+    /// through.
+    ///
+    /// This penalizes clients that exhaust their burst, compared to clients that stay within it.
+    /// Note that the cooldown applies each time the burst capacity runs out, so a client that
+    /// keeps sending cells at the replenishment rate after exhausting its burst keeps running
+    /// into it.
+    ///
+    /// The default cooldown is zero, which doesn't affect decisions.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use governor::Quota;
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// // Allow 5 cells per second, but have clients that use all 5 at once wait 10s afterwards:
+    /// let q = Quota::per_second(nonzero!(5u32)).with_cooldown(Duration::from_secs(10));
+    /// assert_eq!(q.cooldown(), Duration::from_secs(10));
+    /// ```
+    pub const fn with_cooldown(self, cooldown: Duration) -> Quota {
+        Quota { cooldown, ..self }
+    }
+
+    /// Delays replenishing the burst capacity until no cells were let through for `delay`.
+    ///
+    /// Rate limiters normally replenish their burst capacity continuously, at the quota's rate.
+    /// With a burst recharge delay, a client that keeps sending cells only gets the replenishment
+    /// rate: Each cell it sends uses up the capacity that was replenished since the previous
+    /// one, and what is left of its burst capacity stays where it was. Only once the client has
+    /// been quiet for `delay` does its burst capacity start to replenish again, at the quota's
+    /// rate. Cells that get denied don't count as activity.
+    ///
+    /// The default delay is zero, which replenishes the burst capacity continuously.
+    ///
+    /// # Precision
+    /// Rate limiters keep the time of the last cell they let through in the low bits of the
+    /// theoretical arrival time that they store, so with a burst recharge delay:
+    ///
+    /// * The theoretical arrival time is rounded down to a 1024th of the replenishment
+    ///   interval, which makes decisions more lenient by at most that much. A full burst of
+    ///   cells and cells that arrive at the sustained rate always conform, at whatever time
+    ///   they arrive, and the rounding doesn't add up over them.
+    /// * The time of the last cell is rounded up to a 512th of the replenishment interval,
+    ///   which makes decisions more lenient by at most that much while the burst capacity is
+    ///   held back.
+    ///
+    /// Both get coarser for quotas that replenish a cell in less than about a millisecond
+    /// times the burst size (plus the cooldown, in cells).
+    ///
+    /// # Example
+    /// ```rust
+    /// # use governor::{clock::FakeRelativeClock, Quota, RateLimiter};
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// // Allow 2 cells per second with a burst of 5, but only recharge the burst after 10s of quiet:
+    /// let q = Quota::per_second(nonzero!(2u32))
+    ///     .allow_burst(nonzero!(5u32))
+    ///     .with_burst_recharge_delay(Duration::from_secs(10));
+    /// let clock = FakeRelativeClock::default();
+    /// let lim = RateLimiter::direct_with_clock(q, clock.clone());
+    /// assert!(lim.check_n(nonzero!(5u32)).unwrap().is_ok());
+    ///
+    /// // Sending a cell every 500ms keeps working, but the burst capacity doesn't come back:
+    /// for _ in 0..20 {
+    ///     clock.advance(Duration::from_millis(500));
+    ///     assert!(lim.check().is_ok());
+    ///     assert!(lim.check().is_err());
+    /// }
+    ///
+    /// // After 10s of quiet, the burst recharges at the usual rate:
+    /// clock.advance(Duration::from_secs(11));
+    /// assert!(lim.check_n(nonzero!(3u32)).unwrap().is_ok());
+    /// assert!(lim.check().is_err());
+    /// ```
+    pub const fn with_burst_recharge_delay(self, delay: Duration) -> Quota {
+        Quota {
+            recharge_delay: delay,
+            ..self
+        }
+    }
+
     /// Construct a quota for a given burst size, replenishing the entire burst size in that
     /// given unit of time.
     ///
@@ -159,6 +486,8 @@ impl Quota {
             Some(Quota {
                 max_burst,
                 replenish_1_per: replenish_all_per / max_burst.get(),
+                cooldown: Duration::ZERO,
+                recharge_delay: Duration::ZERO,
             })
         }
     }
@@ -166,6 +495,67 @@ impl Quota {
 
 /// Retrieving information about a quota
 impl Quota {
+    /// Checks the quota for combinations of parameters that are
+    /// unlikely to be what was intended.
+    ///
+    /// Quotas are often assembled from configuration values, where
+    /// units are easily confused: e.g., a burst size of 10 million
+    /// cells replenishing one cell every 100ns. Such quotas are
+    /// valid, so the constructors accept them; this method allows
+    /// flagging them, e.g. when loading a configuration.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use governor::{Quota, SuspiciousQuota};
+    /// # use nonzero_ext::nonzero;
+    /// assert_eq!(Quota::per_second(nonzero!(50u32)).validate_sane(), Ok(()));
+    /// assert_eq!(
+    ///     Quota::per_second(nonzero!(10_000_000u32)).allow_burst(nonzero!(10_000_000u32)).validate_sane(),
+    ///     Err(SuspiciousQuota::ExcessiveBurst),
+    /// );
+    /// ```
+    pub fn validate_sane(&self) -> Result<(), SuspiciousQuota> {
+        let interval_ns = self.replenish_1_per.as_nanos();
+        if interval_ns == 0 {
+            return Err(SuspiciousQuota::SubNanosecondInterval);
+        }
+        if interval_ns * (self.max_burst.get() as u128) > u64::MAX as u128 {
+            return Err(SuspiciousQuota::BurstTooLong);
+        }
+        if self.max_burst.get() > 1_000_000 && self.replenish_1_per < Duration::from_micros(1) {
+            return Err(SuspiciousQuota::ExcessiveBurst);
+        }
+        Ok(())
+    }
+
+    /// The time until a rate limiting state with the theoretical arrival time `tat` becomes
+    /// indistinguishable from a fresh state, as of `now`.
+    ///
+    /// Both `tat` and `now` are measured from the same reference point, like the values that
+    /// [`StateStore`](crate::state::StateStore)s keep. Keyed rate limiters'
+    /// [`retain_recent`](crate::RateLimiter::retain_recent) drops exactly those keys whose
+    /// states have no time left until they are fresh; state stores that keep their state
+    /// elsewhere (e.g. in Redis) can use this duration as the expiration time of a key's state
+    /// when they store it, to drop the same keys.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use governor::{nanos::Nanos, Quota};
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// let quota = Quota::per_second(nonzero!(2u32));
+    /// // Two cells were let through at 1s, and the state is stored at that time:
+    /// let tat = Nanos::from(2_000_000_000);
+    /// let now = Nanos::from(1_000_000_000);
+    /// assert_eq!(quota.time_until_fresh(tat, now), Duration::from_millis(1500));
+    /// ```
+    pub fn time_until_fresh(&self, tat: Nanos, now: Nanos) -> Duration {
+        let stale_after = Gcra::new(*self).stale_after();
+        Nanos::from(tat.as_u64().saturating_add(stale_after.as_u64()))
+            .saturating_sub(now)
+            .into()
+    }
+
     /// The time it takes for a rate limiter with an exhausted burst budget to replenish
     /// a single element.
     pub const fn replenish_interval(&self) -> Duration {
@@ -182,6 +572,64 @@ impl Quota {
         let fill_in_ns = self.replenish_1_per.as_nanos() * self.max_burst.get() as u128;
         Duration::from_nanos(fill_in_ns as u64)
     }
+
+    /// The extra time a rate limiter waits after its burst capacity runs out, before it lets
+    /// the next cell through. See [`with_cooldown`](#method.with_cooldown).
+    pub const fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+
+    /// How long a rate limiter lets no cells through before it starts replenishing its burst
+    /// capacity. See [`with_burst_recharge_delay`](#method.with_burst_recharge_delay).
+    pub const fn burst_recharge_delay(&self) -> Duration {
+        self.recharge_delay
+    }
+
+    /// Returns `true` if the quotas have the same burst size, cooldown and burst recharge delay,
+    /// and replenishment
+    /// intervals that differ by at most `tolerance`.
+    ///
+    /// Quotas that were constructed in different ways can describe the same rate, but differ in
+    /// how their replenishment interval got rounded to whole nanoseconds. This is useful for
+    /// comparing quotas that come from different sources, e.g. when reconciling configuration.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use governor::Quota;
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// let per_minute = Quota::per_minute(nonzero!(7u32)).allow_burst(nonzero!(1u32));
+    /// let per_period = Quota::with_period(Duration::from_nanos(8_571_428_572)).unwrap();
+    /// assert_ne!(per_minute, per_period);
+    /// assert!(per_minute.approx_eq(&per_period, Duration::from_nanos(1)));
+    /// assert!(!per_minute.approx_eq(&Quota::per_minute(nonzero!(7u32)), Duration::from_secs(1)));
+    /// ```
+    pub fn approx_eq(&self, other: &Quota, tolerance: Duration) -> bool {
+        let (a, b) = (self.replenish_1_per, other.replenish_1_per);
+        self.max_burst == other.max_burst
+            && self.cooldown == other.cooldown
+            && self.recharge_delay == other.recharge_delay
+            && cmp::max(a, b) - cmp::min(a, b) <= tolerance
+    }
+
+    /// The maximum number of cells that a rate limiter with this quota lets through in any
+    /// interval of length `interval` (counting cells at both ends of the interval).
+    ///
+    /// This is the burst size plus the number of cells replenished during the interval; how long
+    /// the rate limiter was idle before doesn't matter. See [Idle periods](#idle-periods).
+    pub fn max_cells_within(&self, interval: Duration) -> u64 {
+        let t = Gcra::new(*self).t();
+        let replenished = Nanos::saturating_from(interval) / t;
+        u64::from(self.max_burst.get()).saturating_add(replenished)
+    }
+
+    /// The number of cells that the quota replenishes per second, as a floating-point number.
+    ///
+    /// This is the inverse of [`per_second_f64`](#method.per_second_f64), up to the rounding
+    /// of the replenishment interval to whole nanoseconds.
+    pub fn rate_per_second(&self) -> f64 {
+        1.0 / self.replenish_1_per.as_secs_f64()
+    }
 }
 
 impl Quota {
@@ -190,20 +638,177 @@ impl Quota {
     /// This is useful mainly for [`crate::middleware::RateLimitingMiddleware`]
     /// where custom code may want to construct information based on
     /// the amount of burst balance remaining.
+    ///
+    /// The parameters don't include a cooldown or burst recharge delay, so the reconstructed
+    /// quota has neither; callers that know them add them back.
+    #[inline]
     pub(crate) fn from_gcra_parameters(t: Nanos, tau: Nanos) -> Quota {
-        // Safety assurance: by construction, the computed value is bounded by
-        // one at the lower.
-        //
-        // The casts may look a little sketch, but they're constructed from
-        // parameters that came from the crate exactly like that.
-        let max_burst =
-            unsafe { NonZeroU32::new_unchecked(1 + (tau.as_u64() / t.as_u64()) as u32) };
+        // The parameters come from a quota, so the burst size fits; saturate anyway, rather
+        // than trusting that.
+        let max_burst = NonZeroU32::MIN.saturating_add(u32::try_from(tau / t).unwrap_or(u32::MAX));
         let replenish_1_per = t.into();
         Quota {
             max_burst,
             replenish_1_per,
+            cooldown: Duration::ZERO,
+            recharge_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// Parses human-readable rates, like `"5000 per 100 milliseconds"`.
+///
+/// A rate is a number of cells, then `/` or `per`, then a period: an optional amount (which
+/// may have a fractional part, like `1.5`) and a unit. The units are:
+///
+/// * `ns`, `nanosecond`, `nanoseconds`
+/// * `us`, `µs`, `microsecond`, `microseconds`
+/// * `ms`, `millisecond`, `milliseconds`
+/// * `s`, `sec`, `second`, `seconds`
+/// * `min`, `minute`, `minutes`
+/// * `h`, `hour`, `hours`
+/// * `d`, `day`, `days`
+///
+/// The number of cells is also the quota's maximum burst size, and the replenishment interval
+/// is the period divided by the number of cells. It is computed from the decimal digits of the
+/// amount, without floating-point rounding, and rounded down to whole nanoseconds like the
+/// intervals of [`per_second`](Quota::per_second) and the like are; so `"10/minute"` and
+/// `"10 per 60 seconds"` both result in the same quota as `Quota::per_minute(nonzero!(10u32))`.
+///
+/// Returns [`QuotaError::Unparseable`] if the rate isn't of that form, if the number of cells
+/// is zero or doesn't fit into a `u32`, or if the period's amount has more significant digits
+/// than fit into a `u128`; [`QuotaError::RateTooHigh`] if the replenishment interval
+/// would be shorter than 1ns; and [`QuotaError::RateTooLow`] if it would be too long for rate
+/// limiters to keep track of.
+///
+/// # Example
+/// ```rust
+/// # use governor::{Quota, QuotaError};
+/// # use nonzero_ext::nonzero;
+/// # use std::convert::TryFrom;
+/// # use std::time::Duration;
+/// let quota: Quota = "5000 per 100 milliseconds".parse().unwrap();
+/// assert_eq!(quota.burst_size().get(), 5000);
+/// assert_eq!(quota.replenish_interval(), Duration::from_micros(20));
+///
+/// let quota = Quota::try_from("3 per 1.5 seconds").unwrap();
+/// assert_eq!(quota.replenish_interval(), Duration::from_millis(500));
+///
+/// assert_eq!("10/minute".parse(), Ok(Quota::per_minute(nonzero!(10u32))));
+/// assert_eq!("2000 per 1.5ns".parse::<Quota>(), Err(QuotaError::RateTooHigh));
+/// ```
+impl FromStr for Quota {
+    type Err = QuotaError;
+
+    fn from_str(rate: &str) -> Result<Quota, QuotaError> {
+        let rate = rate.trim();
+        let (cells, period) = rate
+            .split_once('/')
+            .or_else(|| rate.split_once(" per "))
+            .ok_or(QuotaError::Unparseable)?;
+        let max_burst = cells
+            .trim()
+            .parse::<u32>()
+            .ok()
+            .and_then(NonZeroU32::new)
+            .ok_or(QuotaError::Unparseable)?;
+
+        let period = period.trim();
+        let unit_start = period
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or(QuotaError::Unparseable)?;
+        let (amount, unit) = period.split_at(unit_start);
+        let unit_ns: u128 = match unit.trim_start() {
+            "ns" | "nanosecond" | "nanoseconds" => 1,
+            "us" | "µs" | "microsecond" | "microseconds" => 1_000,
+            "ms" | "millisecond" | "milliseconds" => 1_000_000,
+            "s" | "sec" | "second" | "seconds" => 1_000_000_000,
+            "min" | "minute" | "minutes" => 60 * 1_000_000_000,
+            "h" | "hour" | "hours" => 60 * 60 * 1_000_000_000,
+            "d" | "day" | "days" => 24 * 60 * 60 * 1_000_000_000,
+            _ => return Err(QuotaError::Unparseable),
+        };
+        let (mantissa, scale) = if amount.is_empty() {
+            (1, 1)
+        } else {
+            parse_decimal(amount)?
+        };
+
+        // period / cells = mantissa * unit / (scale * cells), rounded down:
+        let period_ns = mantissa
+            .checked_mul(unit_ns)
+            .ok_or(QuotaError::RateTooLow)?;
+        let divisor = scale
+            .checked_mul(u128::from(max_burst.get()))
+            .ok_or(QuotaError::RateTooHigh)?;
+        let interval_ns = period_ns / divisor;
+        if interval_ns == 0 {
+            return Err(QuotaError::RateTooHigh);
+        }
+        let interval_ns = u64::try_from(interval_ns).map_err(|_| QuotaError::RateTooLow)?;
+        Quota {
+            max_burst,
+            replenish_1_per: Duration::from_nanos(interval_ns),
+            cooldown: Duration::ZERO,
+            recharge_delay: Duration::ZERO,
+        }
+        .validated()
+    }
+}
+
+impl TryFrom<&str> for Quota {
+    type Error = QuotaError;
+
+    /// Parses a human-readable rate; see the [`FromStr`] implementation.
+    fn try_from(rate: &str) -> Result<Quota, QuotaError> {
+        rate.parse()
+    }
+}
+
+/// Parses a decimal number like `1.5` into its digits and the power of ten to divide them by:
+/// `(15, 10)`.
+fn parse_decimal(amount: &str) -> Result<(u128, u128), QuotaError> {
+    let (whole, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return Err(QuotaError::Unparseable);
+    }
+    // Trailing zeros don't change the amount, but would take up room in the mantissa:
+    let fraction = fraction.trim_end_matches('0');
+    let mut mantissa: u128 = 0;
+    let mut scale: u128 = 1;
+    for (i, digit) in whole.bytes().chain(fraction.bytes()).enumerate() {
+        if !digit.is_ascii_digit() {
+            return Err(QuotaError::Unparseable);
+        }
+        mantissa = mantissa
+            .checked_mul(10)
+            .and_then(|m| m.checked_add(u128::from(digit - b'0')))
+            .ok_or(if i < whole.len() {
+                QuotaError::RateTooLow
+            } else {
+                // The amount has more significant digits than we can keep track of:
+                QuotaError::Unparseable
+            })?;
+        if i >= whole.len() {
+            scale = scale.checked_mul(10).ok_or(QuotaError::RateTooHigh)?;
         }
     }
+    Ok((mantissa, scale))
+}
+
+/// Returns whether `bytes` is exactly `suffix` from index `start` on.
+const fn ends_with_at(bytes: &[u8], start: usize, suffix: &[u8]) -> bool {
+    if bytes.len() - start != suffix.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < suffix.len() {
+        if bytes[start + i] != suffix[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
 }
 
 #[cfg(test)]
@@ -211,6 +816,27 @@ mod test {
     use super::*;
     use nonzero_ext::nonzero;
 
+    #[test]
+    fn cooldown_defaults_to_zero() {
+        let q = Quota::per_second(nonzero!(5u32));
+        assert_eq!(q.cooldown(), Duration::ZERO);
+        let cooled = q.with_cooldown(Duration::from_secs(1));
+        assert_eq!(cooled.cooldown(), Duration::from_secs(1));
+        assert_ne!(q, cooled);
+        assert!(!q.approx_eq(&cooled, Duration::from_secs(1)));
+        assert_eq!(cooled.with_cooldown(Duration::ZERO), q);
+    }
+
+    #[test]
+    fn burst_recharge_delay_defaults_to_zero() {
+        let q = Quota::per_second(nonzero!(5u32));
+        assert_eq!(q.burst_recharge_delay(), Duration::ZERO);
+        let delayed = q.with_burst_recharge_delay(Duration::from_secs(10));
+        assert_eq!(delayed.burst_recharge_delay(), Duration::from_secs(10));
+        assert!(!q.approx_eq(&delayed, Duration::from_secs(1)));
+        assert_eq!(delayed.with_burst_recharge_delay(Duration::ZERO), q);
+    }
+
     #[test]
     fn time_multiples() {
         let hourly = Quota::per_hour(nonzero!(1u32));
@@ -227,13 +853,266 @@ mod test {
         );
     }
 
+    #[test]
+    fn sanity_checks() {
+        assert_eq!(
+            Quota::per_second(nonzero!(2_000_000_000u32)).validate_sane(),
+            Err(SuspiciousQuota::SubNanosecondInterval)
+        );
+        assert_eq!(
+            Quota::per_second(nonzero!(2_000_000u32)).validate_sane(),
+            Err(SuspiciousQuota::ExcessiveBurst)
+        );
+        assert_eq!(
+            Quota::per_second(nonzero!(1_000_000u32)).validate_sane(),
+            Ok(())
+        );
+        assert_eq!(
+            Quota::per_second(nonzero!(2_000_000u32))
+                .allow_burst(nonzero!(1u32))
+                .validate_sane(),
+            Ok(())
+        );
+        assert_eq!(
+            Quota::with_period(Duration::from_secs(365 * 24 * 3600))
+                .unwrap()
+                .allow_burst(nonzero!(1_000u32))
+                .validate_sane(),
+            Err(SuspiciousQuota::BurstTooLong)
+        );
+    }
+
+    #[test]
+    fn time_until_fresh() {
+        let quota = Quota::per_second(nonzero!(2u32));
+        let ms = |n: u64| Nanos::from(n * 1_000_000);
+        assert_eq!(
+            quota.time_until_fresh(ms(1000), ms(0)),
+            Duration::from_millis(1500)
+        );
+        assert_eq!(
+            quota.time_until_fresh(ms(1000), ms(1499)),
+            Duration::from_millis(1)
+        );
+        assert_eq!(
+            quota.time_until_fresh(ms(1000), ms(1500)),
+            Duration::from_secs(0)
+        );
+        assert_eq!(
+            quota.time_until_fresh(ms(1000), ms(5000)),
+            Duration::from_secs(0)
+        );
+    }
+
+    #[test]
+    fn max_cells_within() {
+        let quota = Quota::per_second(nonzero!(2u32));
+        assert_eq!(quota.max_cells_within(Duration::from_secs(0)), 2);
+        assert_eq!(quota.max_cells_within(Duration::from_millis(499)), 2);
+        assert_eq!(quota.max_cells_within(Duration::from_millis(500)), 3);
+        assert_eq!(
+            Quota::per_second(nonzero!(u32::MAX)).max_cells_within(Duration::MAX),
+            u64::MAX
+        );
+    }
+
+    #[test]
+    fn floating_point_rates() {
+        let quota = Quota::per_second_f64(3.0).unwrap();
+        assert_eq!(
+            quota.replenish_interval(),
+            Duration::from_nanos(333_333_333)
+        );
+        assert_eq!(quota.burst_size().get(), 3);
+        // Rounds to the nearest nanosecond:
+        let quota = Quota::per_second_f64(1.5).unwrap();
+        assert_eq!(
+            quota.replenish_interval(),
+            Duration::from_nanos(666_666_667)
+        );
+        assert_eq!(quota.burst_size().get(), 1);
+        // Whole numbers match the integer constructor exactly:
+        for rate in [3u32, 7, 1_000, 999_999_999] {
+            assert_eq!(
+                Quota::per_second_f64(f64::from(rate)),
+                Ok(Quota::per_second(NonZeroU32::new(rate).unwrap()))
+            );
+        }
+        assert_eq!(
+            Quota::per_second_f64(2e9).unwrap().replenish_interval(),
+            Duration::from_nanos(1)
+        );
+        assert_eq!(
+            Quota::per_second_f64(1.0 / 3600.0).unwrap(),
+            Quota::per_hour(nonzero!(1u32))
+        );
+        assert_eq!(
+            Quota::per_second_f64(1e9).unwrap().replenish_interval(),
+            Duration::from_nanos(1)
+        );
+
+        assert!((Quota::per_second_f64(0.25).unwrap().rate_per_second() - 0.25).abs() < 1e-12);
+        assert!((Quota::per_minute(nonzero!(3u32)).rate_per_second() - 0.05).abs() < 1e-12);
+
+        assert_eq!(Quota::per_second_f64(-1.0), Err(QuotaError::InvalidRate));
+        assert_eq!(
+            Quota::per_second_f64(f64::NAN),
+            Err(QuotaError::InvalidRate)
+        );
+        assert_eq!(Quota::per_second_f64(3e9), Err(QuotaError::RateTooHigh));
+        assert_eq!(
+            Quota::per_second_f64(f64::INFINITY),
+            Err(QuotaError::RateTooHigh)
+        );
+        assert_eq!(Quota::per_second_f64(1e-11), Err(QuotaError::RateTooLow));
+        assert_eq!(
+            Quota::per_second_f64(f64::MIN_POSITIVE),
+            Err(QuotaError::RateTooLow)
+        );
+    }
+
     #[test]
     fn period_error_cases() {
         assert!(Quota::with_period(Duration::from_secs(0)).is_none());
+        assert!(Quota::with_period(Duration::from_secs(600 * 365 * 24 * 60 * 60)).is_none());
 
         #[allow(deprecated)]
         {
             assert!(Quota::new(nonzero!(1u32), Duration::from_secs(0)).is_none());
         }
     }
+
+    #[test]
+    fn validating_constructors() {
+        assert_eq!(
+            Quota::try_per_second(nonzero!(1_000_000_000u32)),
+            Ok(Quota::per_second(nonzero!(1_000_000_000u32)))
+        );
+        assert_eq!(
+            Quota::try_per_second(nonzero!(1_000_000_001u32)),
+            Err(QuotaError::RateTooHigh)
+        );
+
+        assert_eq!(
+            Quota::try_with_period(Duration::from_nanos(1)),
+            Quota::with_period(Duration::from_nanos(1)).ok_or(QuotaError::RateTooHigh)
+        );
+        assert_eq!(
+            Quota::try_with_period(Duration::from_secs(0)),
+            Err(QuotaError::RateTooHigh)
+        );
+        assert_eq!(
+            Quota::try_with_period(Duration::from_secs(600 * 365 * 24 * 60 * 60)),
+            Err(QuotaError::RateTooLow)
+        );
+    }
+
+    #[test]
+    fn gcra_parameters_saturate() {
+        let quota = Quota::from_gcra_parameters(Nanos::from(1), Nanos::from(u64::MAX));
+        assert_eq!(quota.burst_size().get(), u32::MAX);
+        let quota = Quota::from_gcra_parameters(Nanos::from(0), Nanos::from(10));
+        assert_eq!(quota.burst_size().get(), u32::MAX);
+        let quota = Quota::from_gcra_parameters(Nanos::from(10), Nanos::from(0));
+        assert_eq!(quota.burst_size().get(), 1);
+    }
+
+    #[test]
+    fn parsing_human_readable_rates() {
+        let rate = |s: &str| s.parse::<Quota>();
+        assert_eq!(
+            rate("5000 per 100 milliseconds").map(|q| q.replenish_interval()),
+            Ok(Duration::from_micros(20))
+        );
+        assert_eq!(rate("5000 per 100ms"), rate("5000 per 100 milliseconds"));
+        assert_eq!(rate(" 10/minute "), Ok(Quota::per_minute(nonzero!(10u32))));
+        assert_eq!(
+            rate("10 per minute"),
+            Ok(Quota::per_minute(nonzero!(10u32)))
+        );
+        assert_eq!(rate("10 per 60s"), Ok(Quota::per_minute(nonzero!(10u32))));
+        assert_eq!(rate("3/s"), Ok(Quota::per_second(nonzero!(3u32))));
+        assert_eq!(rate("7 per hour"), Ok(Quota::per_hour(nonzero!(7u32))));
+        assert_eq!(
+            rate("2 per 1.5 seconds").map(|q| q.replenish_interval()),
+            Ok(Duration::from_millis(750))
+        );
+        assert_eq!(
+            rate("4 per .5µs").map(|q| q.replenish_interval()),
+            Ok(Duration::from_nanos(125))
+        );
+        assert_eq!(
+            rate("1 per 2 days").map(|q| q.replenish_interval()),
+            Ok(Duration::from_secs(2 * 24 * 60 * 60))
+        );
+        // Sub-nanosecond periods are rounded down, like in the other constructors:
+        assert_eq!(
+            rate("1 per 1.9ns").map(|q| q.replenish_interval()),
+            Ok(Duration::from_nanos(1))
+        );
+        assert_eq!(Quota::try_from("1/ms"), rate("1/ms"));
+
+        assert_eq!(rate("2 per 1.5ns"), Err(QuotaError::RateTooHigh));
+        assert_eq!(rate("1 per 0 seconds"), Err(QuotaError::RateTooHigh));
+        assert_eq!(rate("1 per 0.000000000001ns"), Err(QuotaError::RateTooHigh));
+        assert_eq!(
+            rate("1 per 1000 days"),
+            Ok(Quota::with_period(Duration::from_secs(1000 * 24 * 60 * 60)).unwrap())
+        );
+        assert_eq!(rate("1 per 1000000 days"), Err(QuotaError::RateTooLow));
+        let padded = format!("1 per 1.{}s", "0".repeat(40));
+        assert_eq!(rate(&padded), Ok(Quota::per_second(nonzero!(1u32))));
+        let precise = format!("1 per 1.{}1s", "0".repeat(40));
+        assert_eq!(rate(&precise), Err(QuotaError::Unparseable));
+        for bad in [
+            "",
+            "10",
+            "10 per",
+            "per minute",
+            "0 per minute",
+            "-1 per minute",
+            "10 per fortnight",
+            "10 per 1.5.5 seconds",
+            "10 per . seconds",
+            "10 per m",
+            "1.5 per second",
+        ] {
+            assert_eq!(rate(bad), Err(QuotaError::Unparseable), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn parsing_rates() {
+        assert_eq!(
+            Quota::parse_rate("3/second"),
+            Ok(Quota::per_second(nonzero!(3u32)))
+        );
+        assert_eq!(
+            Quota::parse_rate("7/hour"),
+            Ok(Quota::per_hour(nonzero!(7u32)))
+        );
+        for rate in [
+            "",
+            "10",
+            "/minute",
+            "10/",
+            "10/minutes",
+            "0/minute",
+            "-1/minute",
+            "10 /minute",
+            "4294967296/day",
+        ] {
+            assert_eq!(
+                Quota::parse_rate(rate),
+                Err(QuotaError::Unparseable),
+                "{:?}",
+                rate
+            );
+        }
+        assert_eq!(
+            Quota::parse_rate("4294967295/second"),
+            Err(QuotaError::RateTooHigh)
+        );
+        assert!(Quota::parse_rate("4294967295/day").is_ok());
+    }
 }
@@ -0,0 +1,191 @@
+//! Quotas matching the published rate limits of common third-party APIs.
+//!
+//! Clients of rate-limited services tend to collect the limits they
+//! observe in ad-hoc constants. The constructors in this module
+//! instead encode the limits that the services document, so code
+//! talking to the same API uses the same numbers everywhere.
+//!
+//! The limits were taken from each service's documentation (linked
+//! from each constructor); services change them from time to time,
+//! and many grant higher limits to some accounts. Where a service
+//! allows bursts, the burst size is the number of requests in the
+//! documented period.
+//!
+//! With the `serde` feature, [`Preset`] allows selecting one of these
+//! quotas by name in a configuration file.
+//!
+//! # Example
+//! ```rust
+//! use governor::{quota::presets, RateLimiter};
+//!
+//! # #[cfg(feature = "std")]
+//! # fn main() {
+//! let lim = RateLimiter::direct(presets::slack_tier2());
+//! assert!(lim.check().is_ok());
+//! # }
+//! # #[cfg(not(feature = "std"))]
+//! # fn main() {}
+//! ```
+
+use nonzero_ext::nonzero;
+
+use crate::Quota;
+
+/// The [GitHub REST API](https://docs.github.com/en/rest/using-the-rest-api/rate-limits-for-the-rest-api)
+/// limit for authenticated users: 5,000 requests per hour.
+pub const fn github_rest() -> Quota {
+    Quota::per_hour(nonzero!(5_000u32))
+}
+
+/// The [GitHub REST API](https://docs.github.com/en/rest/using-the-rest-api/rate-limits-for-the-rest-api)
+/// limit for unauthenticated requests: 60 requests per hour.
+pub const fn github_rest_unauthenticated() -> Quota {
+    Quota::per_hour(nonzero!(60u32))
+}
+
+/// The [GitHub search API](https://docs.github.com/en/rest/search/search#rate-limit) limit for
+/// authenticated users: 30 requests per minute.
+pub const fn github_search() -> Quota {
+    Quota::per_minute(nonzero!(30u32))
+}
+
+/// Slack's [Tier 1](https://api.slack.com/apis/rate-limits#tiers) Web API methods: 1 request
+/// per minute.
+pub const fn slack_tier1() -> Quota {
+    Quota::per_minute(nonzero!(1u32))
+}
+
+/// Slack's [Tier 2](https://api.slack.com/apis/rate-limits#tiers) Web API methods: 20 requests
+/// per minute.
+pub const fn slack_tier2() -> Quota {
+    Quota::per_minute(nonzero!(20u32))
+}
+
+/// Slack's [Tier 3](https://api.slack.com/apis/rate-limits#tiers) Web API methods: 50 requests
+/// per minute.
+pub const fn slack_tier3() -> Quota {
+    Quota::per_minute(nonzero!(50u32))
+}
+
+/// Slack's [Tier 4](https://api.slack.com/apis/rate-limits#tiers) Web API methods: 100 requests
+/// per minute.
+pub const fn slack_tier4() -> Quota {
+    Quota::per_minute(nonzero!(100u32))
+}
+
+/// Discord's [global rate limit](https://discord.com/developers/docs/topics/rate-limits#global-rate-limit)
+/// for bots: 50 requests per second.
+pub const fn discord_global() -> Quota {
+    Quota::per_second(nonzero!(50u32))
+}
+
+/// The [Stripe API](https://docs.stripe.com/rate-limits) limit in live mode: 100 operations per
+/// second.
+pub const fn stripe_live() -> Quota {
+    Quota::per_second(nonzero!(100u32))
+}
+
+/// The [Stripe API](https://docs.stripe.com/rate-limits) limit in test mode: 25 operations per
+/// second.
+pub const fn stripe_test() -> Quota {
+    Quota::per_second(nonzero!(25u32))
+}
+
+/// A preset quota, selectable by name.
+///
+/// With the `serde` feature, presets can be deserialized from their
+/// `snake_case` names, which are the same as those of the constructor
+/// functions in this module (e.g., `"slack_tier2"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum Preset {
+    /// See [`github_rest`].
+    GithubRest,
+    /// See [`github_rest_unauthenticated`].
+    GithubRestUnauthenticated,
+    /// See [`github_search`].
+    GithubSearch,
+    /// See [`slack_tier1`].
+    SlackTier1,
+    /// See [`slack_tier2`].
+    SlackTier2,
+    /// See [`slack_tier3`].
+    SlackTier3,
+    /// See [`slack_tier4`].
+    SlackTier4,
+    /// See [`discord_global`].
+    DiscordGlobal,
+    /// See [`stripe_live`].
+    StripeLive,
+    /// See [`stripe_test`].
+    StripeTest,
+}
+
+impl Preset {
+    /// Returns the preset's quota.
+    pub const fn quota(self) -> Quota {
+        match self {
+            Preset::GithubRest => github_rest(),
+            Preset::GithubRestUnauthenticated => github_rest_unauthenticated(),
+            Preset::GithubSearch => github_search(),
+            Preset::SlackTier1 => slack_tier1(),
+            Preset::SlackTier2 => slack_tier2(),
+            Preset::SlackTier3 => slack_tier3(),
+            Preset::SlackTier4 => slack_tier4(),
+            Preset::DiscordGlobal => discord_global(),
+            Preset::StripeLive => stripe_live(),
+            Preset::StripeTest => stripe_test(),
+        }
+    }
+}
+
+impl From<Preset> for Quota {
+    fn from(preset: Preset) -> Quota {
+        preset.quota()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn presets_are_sane() {
+        let presets = [
+            Preset::GithubRest,
+            Preset::GithubRestUnauthenticated,
+            Preset::GithubSearch,
+            Preset::SlackTier1,
+            Preset::SlackTier2,
+            Preset::SlackTier3,
+            Preset::SlackTier4,
+            Preset::DiscordGlobal,
+            Preset::StripeLive,
+            Preset::StripeTest,
+        ];
+        for preset in presets {
+            let quota = Quota::from(preset);
+            assert_eq!(quota.validate_sane(), Ok(()), "{:?}", preset);
+        }
+        assert_eq!(
+            github_rest().burst_size_replenished_in(),
+            Duration::from_secs(3600)
+        );
+        assert_eq!(slack_tier2().replenish_interval(), Duration::from_secs(3));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn presets_deserialize_by_name() {
+        use serde::de::{value::StrDeserializer, IntoDeserializer};
+        use serde::Deserialize;
+
+        let name: StrDeserializer<'_, serde::de::value::Error> = "slack_tier2".into_deserializer();
+        assert_eq!(Preset::deserialize(name), Ok(Preset::SlackTier2));
+        let name: StrDeserializer<'_, serde::de::value::Error> = "myspace".into_deserializer();
+        assert!(Preset::deserialize(name).is_err());
+    }
+}
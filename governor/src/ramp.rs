@@ -0,0 +1,322 @@
+//! Changing a rate limiter's quota gradually.
+//!
+//! Switching a busy rate limiter to a very different quota at once has visible effects: A much
+//! larger quota lets idle keys burst through its whole new capacity, and a much smaller one
+//! denies most requests until the keys have paid off the debt they ran up under the old quota.
+//! A [`QuotaRamp`] instead moves from the old quota to the new one in steps, interpolating the
+//! rate and the burst size over a period of time.
+//!
+//! A [`RampingRateLimiter`] applies a ramp to a rate limiter: It
+//! [changes the rate limiter's quota](crate::RateLimiter::set_quota) to the next step's as soon
+//! as a decision is made after the step's time has come. Code that owns a rate limiter and
+//! changes its quota itself can look up each step's quota with
+//! [`QuotaRamp::quota_at`] instead.
+//!
+//! # Example
+//! ```rust
+//! # use nonzero_ext::nonzero;
+//! # use std::time::Duration;
+//! use governor::{clock::FakeRelativeClock, ramp::{QuotaRamp, RampingRateLimiter}};
+//! use governor::{Quota, RateLimiter};
+//!
+//! let clock = FakeRelativeClock::default();
+//! let from = Quota::per_second(nonzero!(10u32));
+//! let to = Quota::per_second(nonzero!(100u32));
+//! let ramp = QuotaRamp::new(from, to, Duration::from_secs(60)).with_steps(nonzero!(6u32));
+//! let lim = RampingRateLimiter::new(RateLimiter::direct_with_clock(from, clock.clone()), ramp);
+//!
+//! assert_eq!(lim.quota(), from);
+//! clock.advance(Duration::from_secs(30));
+//! assert_eq!(lim.quota().burst_size().get(), 55);
+//! clock.advance(Duration::from_secs(30));
+//! assert_eq!(lim.quota(), to);
+//! assert!(lim.is_finished());
+//! assert_eq!(Ok(()), lim.check());
+//! ```
+
+use std::prelude::v1::*;
+
+use std::cmp;
+use std::hash::Hash;
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use nonzero_ext::nonzero;
+
+use crate::sync::RwLock;
+use crate::{
+    clock::{self, Reference},
+    errors::InsufficientCapacity,
+    middleware::{NoOpMiddleware, RateLimitingMiddleware},
+    state::{keyed::KeyedStateStore, DirectStateStore, NotKeyed, RebasableStateStore},
+    Quota, RateLimiter,
+};
+
+/// A gradual change from one quota to another, in equally long steps.
+///
+/// Each step's quota lies between the two quotas: Its rate (cells per second), burst size,
+/// cooldown and burst recharge delay are interpolated linearly. Step 0 is the quota to change
+/// from, and the last step, which starts once the whole duration has passed, is the quota to
+/// change to.
+///
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// use governor::{ramp::QuotaRamp, Quota};
+///
+/// let ramp = QuotaRamp::new(
+///     Quota::per_second(nonzero!(100u32)),
+///     Quota::per_second(nonzero!(20u32)),
+///     Duration::from_secs(40),
+/// )
+/// .with_steps(nonzero!(4u32));
+/// let bursts: Vec<u32> = ramp.schedule().map(|(_, q)| q.burst_size().get()).collect();
+/// assert_eq!(bursts, vec![100, 80, 60, 40, 20]);
+/// assert_eq!(ramp.quota_at(Duration::from_secs(25)).burst_size().get(), 60);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaRamp {
+    from: Quota,
+    to: Quota,
+    over: Duration,
+    steps: NonZeroU32,
+}
+
+impl QuotaRamp {
+    /// Returns a ramp from the quota `from` to the quota `to` that takes `over` in total, in 10
+    /// steps.
+    pub fn new(from: Quota, to: Quota, over: Duration) -> Self {
+        QuotaRamp {
+            from,
+            to,
+            over,
+            steps: nonzero!(10u32),
+        }
+    }
+
+    /// Changes the number of steps that the ramp takes to reach its final quota.
+    ///
+    /// A single step switches from one quota to the other once the whole duration has passed.
+    pub fn with_steps(self, steps: NonZeroU32) -> Self {
+        QuotaRamp { steps, ..self }
+    }
+
+    /// The quota that the ramp starts from.
+    pub fn from(&self) -> Quota {
+        self.from
+    }
+
+    /// The quota that the ramp ends with.
+    pub fn to(&self) -> Quota {
+        self.to
+    }
+
+    /// How long the ramp takes to reach its final quota.
+    pub fn duration(&self) -> Duration {
+        self.over
+    }
+
+    /// The number of steps that the ramp takes to reach its final quota.
+    pub fn steps(&self) -> NonZeroU32 {
+        self.steps
+    }
+
+    /// Returns the step that the ramp is at `elapsed` after it started.
+    pub fn step_at(&self, elapsed: Duration) -> u32 {
+        let steps = self.steps.get();
+        if elapsed >= self.over {
+            return steps;
+        }
+        // `elapsed` is shorter than `over`, so the step fits:
+        (elapsed.as_nanos() * u128::from(steps) / self.over.as_nanos()) as u32
+    }
+
+    /// Returns the quota that the ramp has reached `elapsed` after it started.
+    pub fn quota_at(&self, elapsed: Duration) -> Quota {
+        self.quota_of_step(self.step_at(elapsed))
+    }
+
+    /// Returns each step's start (from the start of the ramp) and quota, starting with the
+    /// quota to change from at zero.
+    pub fn schedule(&self) -> impl Iterator<Item = (Duration, Quota)> + '_ {
+        let steps = self.steps.get();
+        (0..=steps).map(move |step| {
+            let start = interpolate(Duration::ZERO, self.over, step, steps);
+            (start, self.quota_of_step(step))
+        })
+    }
+
+    /// Returns the quota of the given step.
+    fn quota_of_step(&self, step: u32) -> Quota {
+        let steps = self.steps.get();
+        if step == 0 {
+            return self.from;
+        } else if step >= steps {
+            return self.to;
+        }
+        let fraction = f64::from(step) / f64::from(steps);
+        let rate = |quota: &Quota| 1.0 / quota.replenish_interval().as_nanos() as f64;
+        let rate = rate(&self.from) + (rate(&self.to) - rate(&self.from)) * fraction;
+        let from_burst = f64::from(self.from.burst_size().get());
+        let to_burst = f64::from(self.to.burst_size().get());
+        // The casts saturate, and the burst sizes lie between two `u32`s:
+        let max_burst = (from_burst + (to_burst - from_burst) * fraction).round() as u32;
+        Quota {
+            max_burst: NonZeroU32::new(max_burst).unwrap_or(nonzero!(1u32)),
+            replenish_1_per: Duration::from_nanos(cmp::max((1.0 / rate).round() as u64, 1)),
+            cooldown: interpolate(self.from.cooldown, self.to.cooldown, step, steps),
+            recharge_delay: interpolate(
+                self.from.recharge_delay,
+                self.to.recharge_delay,
+                step,
+                steps,
+            ),
+        }
+    }
+}
+
+/// Returns the duration `step / steps` of the way from `from` to `to`.
+fn interpolate(from: Duration, to: Duration, step: u32, steps: u32) -> Duration {
+    let (step, steps) = (u128::from(step), u128::from(steps));
+    let nanos = if to >= from {
+        from.as_nanos() + (to - from).as_nanos() * step / steps
+    } else {
+        from.as_nanos() - (from - to).as_nanos() * step / steps
+    };
+    // The result lies between two durations' nanoseconds:
+    Duration::new(
+        (nanos / 1_000_000_000) as u64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+/// A rate limiter whose quota follows a [`QuotaRamp`].
+///
+/// The ramp starts when the wrapper is constructed. Each decision made through the wrapper
+/// first moves the rate limiter's quota to the ramp's current step, if it isn't there yet; the
+/// rate limiter keeps the states of its keys across the steps (see
+/// [`RateLimiter::set_quota`]). Moving to a step waits for the decisions in progress to finish.
+///
+/// See [the module documentation](index.html) for an example.
+#[derive(Debug)]
+pub struct RampingRateLimiter<K, S, C, MW = NoOpMiddleware<<C as clock::Clock>::Instant>>
+where
+    S: RebasableStateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    limiter: RwLock<RateLimiter<K, S, C, MW>>,
+    ramp: QuotaRamp,
+    started: C::Instant,
+    step: AtomicU32,
+}
+
+impl<K, S, C, MW> RampingRateLimiter<K, S, C, MW>
+where
+    S: RebasableStateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Wraps `limiter`, starting `ramp` now: The rate limiter's quota changes to the ramp's
+    /// first quota right away.
+    pub fn new(mut limiter: RateLimiter<K, S, C, MW>, ramp: QuotaRamp) -> Self {
+        let started = limiter.clock().now();
+        let step = ramp.step_at(Duration::ZERO);
+        limiter.set_quota(ramp.quota_of_step(step));
+        RampingRateLimiter {
+            limiter: RwLock::new(limiter),
+            ramp,
+            started,
+            step: AtomicU32::new(step),
+        }
+    }
+
+    /// Returns the ramp that the rate limiter's quota follows.
+    pub fn ramp(&self) -> &QuotaRamp {
+        &self.ramp
+    }
+
+    /// Returns the quota that decisions made now use.
+    pub fn quota(&self) -> Quota {
+        self.ramp.quota_of_step(self.current_step())
+    }
+
+    /// Returns whether the ramp has reached its final quota.
+    pub fn is_finished(&self) -> bool {
+        self.current_step() >= self.ramp.steps.get()
+    }
+
+    /// Returns the wrapped rate limiter, with the quota of the ramp's current step.
+    pub fn into_inner(self) -> RateLimiter<K, S, C, MW> {
+        let step = self.current_step();
+        let mut limiter = self.limiter.into_inner();
+        if step > self.step.into_inner() {
+            limiter.set_quota(self.ramp.quota_of_step(step));
+        }
+        limiter
+    }
+
+    fn current_step(&self) -> u32 {
+        let now = self.limiter.read().clock().now();
+        self.ramp.step_at(now.duration_since(self.started).into())
+    }
+
+    /// Moves the rate limiter's quota to the ramp's current step, and makes a decision with it.
+    fn decide<T>(&self, decide: impl FnOnce(&RateLimiter<K, S, C, MW>) -> T) -> T {
+        let step = self.current_step();
+        if step > self.step.load(Ordering::Acquire) {
+            let mut limiter = self.limiter.write();
+            // Another decision may have moved to this step (or a later one) in the meantime:
+            if step > self.step.load(Ordering::Acquire) {
+                limiter.set_quota(self.ramp.quota_of_step(step));
+                self.step.store(step, Ordering::Release);
+            }
+        }
+        decide(&self.limiter.read())
+    }
+}
+
+/// # Direct rate limiters
+impl<S, C, MW> RampingRateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore + RebasableStateStore<Key = NotKeyed>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// See [`RateLimiter::check`].
+    pub fn check(&self) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.decide(|limiter| limiter.check())
+    }
+
+    /// See [`RateLimiter::check_n`].
+    pub fn check_n(
+        &self,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        self.decide(|limiter| limiter.check_n(n))
+    }
+}
+
+/// # Keyed rate limiters
+impl<K, S, C, MW> RampingRateLimiter<K, S, C, MW>
+where
+    K: Hash,
+    S: KeyedStateStore<K> + RebasableStateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// See [`RateLimiter::check_key`].
+    pub fn check_key(&self, key: &K) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.decide(|limiter| limiter.check_key(key))
+    }
+
+    /// See [`RateLimiter::check_key_n`].
+    pub fn check_key_n(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        self.decide(|limiter| limiter.check_key_n(key, n))
+    }
+}
@@ -0,0 +1,355 @@
+//! A registry of rate limiters, looked up by name.
+//!
+//! Applications often use several rate limiters for different purposes ("login", "search"),
+//! configured from a file. A [`LimiterRegistry`] keeps them in one place, constructs them
+//! from their quotas, and replaces them when their quotas get reloaded.
+//!
+//! Small applications whose quotas are fixed can instead declare each rate limiter as a
+//! process-wide `static` with [`static_rate_limiter!`](crate::static_rate_limiter), and list
+//! the ones in use (e.g. to report metrics about them) with [`static_limiters`].
+
+use std::prelude::v1::*;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+
+use crate::sync::{Mutex, RwLock};
+
+use crate::{
+    clock,
+    middleware::{NoOpMiddleware, RateLimitingMiddleware},
+    state::{InMemoryState, StateStore},
+    Quota, RateLimiter,
+};
+
+/// A rate limiter that is shared through a [`LimiterRegistry`].
+pub type RegisteredLimiter<S, C, MW> = Arc<RateLimiter<<S as StateStore>::Key, S, C, MW>>;
+
+type Entries<S, C, MW> = HashMap<String, (Quota, RegisteredLimiter<S, C, MW>)>;
+
+/// A registry of rate limiters of the same type, looked up by name.
+///
+/// All rate limiters in a registry use the same type of state store, clock and middleware, so
+/// [`get`](#method.get) returns them with their full type. The registry constructs each rate
+/// limiter with a fresh (default) state store and a clone of the registry's clock.
+///
+/// Quotas can be changed at runtime (e.g. when reloading a configuration file) with
+/// [`insert`](#method.insert) and [`reload`](#method.reload). A rate limiter whose quota changes
+/// gets replaced with a new one, with a fresh state: Code that holds on to the previous rate
+/// limiter keeps using the previous quota, so look rate limiters up by name where they are
+/// used instead.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{registry::LimiterRegistry, Quota};
+///
+/// let registry: LimiterRegistry = LimiterRegistry::new();
+/// registry.insert("login", Quota::per_minute(nonzero!(5u32)));
+/// registry.insert("search", Quota::per_second(nonzero!(50u32)));
+///
+/// let login = registry.get("login").unwrap();
+/// assert_eq!(Ok(()), login.check());
+/// assert!(registry.get("upload").is_none());
+/// ```
+pub struct LimiterRegistry<
+    S: StateStore = InMemoryState,
+    C: clock::Clock = clock::DefaultClock,
+    MW: RateLimitingMiddleware<C::Instant> = NoOpMiddleware<<C as clock::Clock>::Instant>,
+> {
+    limiters: RwLock<Entries<S, C, MW>>,
+    clock: C,
+}
+
+impl<S, MW> LimiterRegistry<S, clock::DefaultClock, MW>
+where
+    S: StateStore + Default,
+    MW: RateLimitingMiddleware<<clock::DefaultClock as clock::Clock>::Instant>,
+{
+    /// Constructs an empty registry whose rate limiters use the default real-time clock.
+    pub fn new() -> Self {
+        Self::with_clock(clock::DefaultClock::default())
+    }
+}
+
+impl<S, MW> Default for LimiterRegistry<S, clock::DefaultClock, MW>
+where
+    S: StateStore + Default,
+    MW: RateLimitingMiddleware<<clock::DefaultClock as clock::Clock>::Instant>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S, C, MW> LimiterRegistry<S, C, MW>
+where
+    S: StateStore + Default,
+    C: clock::Clock + Clone,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Constructs an empty registry whose rate limiters use a custom clock.
+    pub fn with_clock(clock: C) -> Self {
+        LimiterRegistry {
+            limiters: RwLock::new(HashMap::new()),
+            clock,
+        }
+    }
+
+    /// Returns the rate limiter registered under `name`.
+    pub fn get(&self, name: &str) -> Option<RegisteredLimiter<S, C, MW>> {
+        self.limiters
+            .read()
+            .get(name)
+            .map(|(_, limiter)| Arc::clone(limiter))
+    }
+
+    /// Returns the quota of the rate limiter registered under `name`.
+    pub fn quota(&self, name: &str) -> Option<Quota> {
+        self.limiters.read().get(name).map(|(quota, _)| *quota)
+    }
+
+    /// Registers a rate limiter for `quota` under `name`, and returns it.
+    ///
+    /// If a rate limiter with the same quota is registered under the name already, it is kept
+    /// (along with its state) and returned. A rate limiter with a different quota gets
+    /// replaced.
+    pub fn insert(&self, name: impl Into<String>, quota: Quota) -> RegisteredLimiter<S, C, MW> {
+        let mut limiters = self.limiters.write();
+        let name = name.into();
+        match limiters.get(&name) {
+            Some((existing, limiter)) if *existing == quota => Arc::clone(limiter),
+            _ => {
+                let limiter = Arc::new(self.construct(quota));
+                limiters.insert(name, (quota, Arc::clone(&limiter)));
+                limiter
+            }
+        }
+    }
+
+    /// Removes the rate limiter registered under `name`, returning it.
+    pub fn remove(&self, name: &str) -> Option<RegisteredLimiter<S, C, MW>> {
+        self.limiters
+            .write()
+            .remove(name)
+            .map(|(_, limiter)| limiter)
+    }
+
+    /// Replaces the registry's rate limiters with ones for the given names and quotas.
+    ///
+    /// Rate limiters whose quota is unchanged are kept, like with [`insert`](#method.insert);
+    /// rate limiters whose names are not in `quotas` are removed. Lookups never observe a
+    /// partially reloaded registry.
+    pub fn reload<I, N>(&self, quotas: I)
+    where
+        I: IntoIterator<Item = (N, Quota)>,
+        N: Into<String>,
+    {
+        let quotas: Vec<(String, Quota)> = quotas
+            .into_iter()
+            .map(|(name, quota)| (name.into(), quota))
+            .collect();
+        let mut limiters = self.limiters.write();
+        let mut previous = std::mem::take(&mut *limiters);
+        for (name, quota) in quotas {
+            let limiter = match previous.remove(&name) {
+                Some((existing, limiter)) if existing == quota => limiter,
+                _ => Arc::new(self.construct(quota)),
+            };
+            limiters.insert(name, (quota, limiter));
+        }
+    }
+
+    /// Returns the names, quotas and rate limiters of all registered rate limiters, e.g. for
+    /// reporting metrics.
+    ///
+    /// The snapshot is consistent: It reflects the registry at a single point in time. It is
+    /// sorted by name.
+    pub fn snapshot(&self) -> Vec<(String, Quota, RegisteredLimiter<S, C, MW>)> {
+        let mut snapshot: Vec<_> = self
+            .limiters
+            .read()
+            .iter()
+            .map(|(name, (quota, limiter))| (name.clone(), *quota, Arc::clone(limiter)))
+            .collect();
+        snapshot.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+        snapshot
+    }
+
+    /// Returns the number of registered rate limiters.
+    pub fn len(&self) -> usize {
+        self.limiters.read().len()
+    }
+
+    /// Returns `true` if no rate limiters are registered.
+    pub fn is_empty(&self) -> bool {
+        self.limiters.read().is_empty()
+    }
+
+    fn construct(&self, quota: Quota) -> RateLimiter<S::Key, S, C, MW> {
+        RateLimiter::new(quota, S::default(), self.clock.clone())
+    }
+}
+
+impl<S, C, MW> fmt::Debug for LimiterRegistry<S, C, MW>
+where
+    S: StateStore,
+    C: clock::Clock + fmt::Debug,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<String> = self.limiters.read().keys().cloned().collect();
+        names.sort();
+        f.debug_struct("LimiterRegistry")
+            .field("names", &names)
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+/// The names and quotas of the [`StaticRateLimiter`]s that have been used so far.
+static STATIC_LIMITERS: OnceLock<Mutex<Vec<(&'static str, Quota)>>> = OnceLock::new();
+
+/// Returns the names and quotas of the rate limiters declared with
+/// [`static_rate_limiter!`](crate::static_rate_limiter) that have been used so far, in the
+/// order they were first used.
+///
+/// Static rate limiters are only constructed when they are first used, so ones that were never
+/// used aren't listed.
+pub fn static_limiters() -> Vec<(&'static str, Quota)> {
+    STATIC_LIMITERS
+        .get()
+        .map_or_else(Vec::new, |limiters| limiters.lock().clone())
+}
+
+/// A rate limiter in a `static`, constructed when it is first used.
+///
+/// These are declared with the [`static_rate_limiter!`](crate::static_rate_limiter) macro, and
+/// dereference to the rate limiter `L`.
+pub struct StaticRateLimiter<L> {
+    name: &'static str,
+    quota: Quota,
+    construct: fn(Quota) -> L,
+    limiter: OnceLock<L>,
+}
+
+impl<L> StaticRateLimiter<L> {
+    /// Declares a rate limiter that gets constructed by `construct` with `quota` when it is
+    /// first used.
+    ///
+    /// Prefer the [`static_rate_limiter!`](crate::static_rate_limiter) macro, which checks the
+    /// quota at compile time.
+    pub const fn new(name: &'static str, quota: Quota, construct: fn(Quota) -> L) -> Self {
+        StaticRateLimiter {
+            name,
+            quota,
+            construct,
+            limiter: OnceLock::new(),
+        }
+    }
+
+    /// Returns the name of the rate limiter, which is the name of its `static`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the quota that the rate limiter was declared with.
+    pub fn quota(&self) -> Quota {
+        self.quota
+    }
+
+    /// Returns the rate limiter, constructing it if this is its first use.
+    pub fn get(&self) -> &L {
+        self.limiter.get_or_init(|| {
+            STATIC_LIMITERS
+                .get_or_init(Default::default)
+                .lock()
+                .push((self.name, self.quota));
+            (self.construct)(self.quota)
+        })
+    }
+}
+
+impl<L> Deref for StaticRateLimiter<L> {
+    type Target = L;
+
+    fn deref(&self) -> &L {
+        self.get()
+    }
+}
+
+impl<L: fmt::Debug> fmt::Debug for StaticRateLimiter<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticRateLimiter")
+            .field("name", &self.name)
+            .field("quota", &self.quota)
+            .field("limiter", &self.limiter.get())
+            .finish()
+    }
+}
+
+/// Declares a process-wide rate limiter as a `static`, for applications that don't want to
+/// pass rate limiters around.
+///
+/// The quota is given as a rate like `"10/minute"` (see [`Quota::parse_rate`] for the format),
+/// which is checked at compile time. The rate limiter is a
+/// [`StaticRateLimiter`](crate::registry::StaticRateLimiter) that gets constructed when it is
+/// first used, and then listed by [`static_limiters`](crate::registry::static_limiters).
+///
+/// By default, the rate limiter is a [`DefaultKeyedRateLimiter<String>`](crate::DefaultKeyedRateLimiter);
+/// put `direct` in front of the rate for a
+/// [`DefaultDirectRateLimiter`](crate::DefaultDirectRateLimiter) instead.
+///
+/// # Example
+/// ```rust
+/// use governor::{registry::static_limiters, static_rate_limiter};
+///
+/// static_rate_limiter!(LOGIN: "10/minute");
+/// static_rate_limiter!(
+///     /// Limits the requests we make to the upstream API.
+///     pub(crate) UPSTREAM: direct "100/second"
+/// );
+///
+/// assert!(LOGIN.check_key(&"alice".to_string()).is_ok());
+/// assert!(UPSTREAM.check().is_ok());
+/// assert_eq!(LOGIN.name(), "LOGIN");
+/// assert!(static_limiters().contains(&("UPSTREAM", UPSTREAM.quota())));
+/// ```
+///
+/// Invalid rates fail to compile:
+///
+/// ```compile_fail
+/// governor::static_rate_limiter!(LOGIN: "10 per minute");
+/// ```
+#[macro_export]
+macro_rules! static_rate_limiter {
+    (@quota $rate:literal) => {
+        match $crate::Quota::parse_rate($rate) {
+            ::core::result::Result::Ok(quota) => quota,
+            ::core::result::Result::Err(_) => {
+                ::core::panic!(::core::concat!("invalid rate for a rate limiter: ", $rate))
+            }
+        }
+    };
+    ($(#[$attr:meta])* $vis:vis $name:ident : direct $rate:literal) => {
+        $(#[$attr])*
+        $vis static $name: $crate::registry::StaticRateLimiter<$crate::DefaultDirectRateLimiter> =
+            $crate::registry::StaticRateLimiter::new(
+                ::core::stringify!($name),
+                $crate::static_rate_limiter!(@quota $rate),
+                $crate::RateLimiter::direct,
+            );
+    };
+    ($(#[$attr:meta])* $vis:vis $name:ident : $rate:literal) => {
+        $(#[$attr])*
+        $vis static $name: $crate::registry::StaticRateLimiter<
+            $crate::DefaultKeyedRateLimiter<::std::string::String>,
+        > = $crate::registry::StaticRateLimiter::new(
+            ::core::stringify!($name),
+            $crate::static_rate_limiter!(@quota $rate),
+            $crate::RateLimiter::keyed,
+        );
+    };
+}
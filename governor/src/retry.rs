@@ -0,0 +1,137 @@
+//! Retry budgets: Limiting retries in proportion to the requests that are made.
+//!
+//! A client that retries failed requests can multiply the load on a
+//! struggling service. A [`RetryBudget`] (modeled after [Finagle's retry
+//! budgets](https://twitter.github.io/finagle/guide/Clients.html#retries))
+//! only allows a fixed percentage of requests to be retried, plus a small
+//! number of retries per unit of time so that clients that make few
+//! requests can still retry.
+
+use std::prelude::v1::*;
+
+use std::fmt;
+
+mod rate_limited;
+pub use rate_limited::RateLimitedBackoff;
+
+use crate::{
+    clock::{self, Reference},
+    gcra::Gcra,
+    middleware::{KeyFormat, NoOpMiddleware},
+    nanos::Nanos,
+    state::{InMemoryState, NotKeyed, StateStore},
+    NotUntil, Quota,
+};
+
+/// A token bucket that requests deposit into, and that retries withdraw from.
+///
+/// Every request that is made [deposits](#method.deposit) a fraction of a
+/// token (the percentage given at construction), and every retry
+/// [withdraws](#method.withdraw) a whole token. Independently of
+/// requests, the bucket also refills at the rate of a "reserve" [`Quota`],
+/// whose burst size is the number of tokens that the bucket can hold.
+///
+/// The bucket is a GCRA rate limiter with the reserve quota, which
+/// deposits give cells back to; so it is as cheap to use as a direct
+/// [`RateLimiter`](crate::RateLimiter).
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{clock::FakeRelativeClock, retry::RetryBudget, Quota};
+///
+/// // Allow 20% of requests to be retried, plus one retry per second:
+/// let reserve = Quota::per_second(nonzero!(1u32)).allow_burst(nonzero!(10u32));
+/// let budget = RetryBudget::with_clock(reserve, 20, FakeRelativeClock::default());
+///
+/// // The reserve's tokens are used up first:
+/// for _ in 0..10 {
+///     assert!(budget.withdraw().is_ok());
+/// }
+/// assert!(budget.withdraw().is_err());
+///
+/// // Five requests pay for another retry:
+/// for _ in 0..5 {
+///     budget.deposit();
+/// }
+/// assert!(budget.withdraw().is_ok());
+/// assert!(budget.withdraw().is_err());
+/// ```
+pub struct RetryBudget<C: clock::Clock = clock::DefaultClock> {
+    gcra: Gcra,
+    state: InMemoryState,
+    deposit: Nanos,
+    clock: C,
+    start: C::Instant,
+}
+
+#[cfg(feature = "std")]
+impl RetryBudget {
+    /// Constructs a retry budget that allows `percent_can_retry` percent of requests to be
+    /// retried, in addition to the retries that `reserve` allows.
+    pub fn new(reserve: Quota, percent_can_retry: u32) -> Self {
+        Self::with_clock(reserve, percent_can_retry, clock::DefaultClock::default())
+    }
+}
+
+impl<C: clock::Clock> RetryBudget<C> {
+    /// Constructs a retry budget with a custom clock.
+    ///
+    /// See [`new`](#method.new).
+    pub fn with_clock(reserve: Quota, percent_can_retry: u32, clock: C) -> Self {
+        let gcra = Gcra::new(reserve);
+        let deposit = Nanos::from(gcra.t().as_u64().saturating_mul(percent_can_retry.into()) / 100);
+        let start = clock.now();
+        RetryBudget {
+            gcra,
+            state: InMemoryState::default(),
+            deposit,
+            clock,
+            start,
+        }
+    }
+
+    /// Records a request, depositing a fraction of a token.
+    ///
+    /// Call this for every request, including the ones that are retries.
+    pub fn deposit(&self) {
+        self.gcra
+            .refund_weight(&NotKeyed::NonKey, self.deposit, &self.state);
+    }
+
+    /// Withdraws a token for a retry, if one is available.
+    ///
+    /// If there is none, the retry should not be made; the negative outcome tells when the
+    /// reserve quota would allow it, if no requests were made in the meantime.
+    pub fn withdraw(&self) -> Result<(), NotUntil<C::Instant>> {
+        self.gcra
+            .test_and_update::<NotKeyed, C::Instant, InMemoryState, NoOpMiddleware<C::Instant>>(
+                self.start,
+                &NotKeyed::NonKey,
+                KeyFormat::redacted(),
+                &self.state,
+                self.clock.now(),
+            )
+    }
+
+    /// Returns the number of retries that could be made right now.
+    pub fn balance(&self) -> u32 {
+        let t0 = self.clock.now().duration_since(self.start);
+        self.state
+            .measure_and_peek(&NotKeyed::NonKey, |tat| {
+                Ok::<_, std::convert::Infallible>(self.gcra.remaining_cells(tat, t0))
+            })
+            .unwrap_or_else(|never| match never {})
+    }
+}
+
+impl<C: clock::Clock + fmt::Debug> fmt::Debug for RetryBudget<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryBudget")
+            .field("gcra", &self.gcra)
+            .field("state", &self.state)
+            .field("deposit", &self.deposit)
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
@@ -0,0 +1,141 @@
+use std::prelude::v1::*;
+
+use std::ops::Deref;
+use std::time::Duration;
+
+use crate::{
+    clock,
+    middleware::RateLimitingMiddleware,
+    state::{DirectStateStore, NotKeyed},
+    RateLimiter,
+};
+
+/// A backoff policy that retries as fast as a direct rate limiter allows.
+///
+/// Each retry takes a cell from the rate limiter that `L` refers to (e.g. through a reference
+/// or an [`Arc`](std::sync::Arc)). The cell is
+/// [reserved](RateLimiter::check_or_reject_if_wait_exceeds) when the retry gets scheduled, and
+/// the delay before the retry is the time until the cell conforms: zero while the rate limiter
+/// has capacity left, and the replenishment interval between retries once it's used up.
+/// Several operations that retry with the same rate limiter share its capacity, and retries
+/// that are waiting for their turn already count against it.
+///
+/// The policy gives up once a retry would have to wait longer than its
+/// [maximum wait](#method.with_max_wait). Limit the number of retries (or the total time
+/// spent on them) with the retry library that runs the policy.
+///
+/// The policy is an [`Iterator`] of delays, which makes it a `Backoff` for
+/// [`backon`](https://docs.rs/backon). With the `backoff` feature, it implements
+/// [`backoff`](https://docs.rs/backoff)'s `Backoff` trait, and with the `tryhard` feature,
+/// [`tryhard`](https://docs.rs/tryhard)'s `BackoffStrategy` for use with `custom_backoff`.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// use governor::{clock::FakeRelativeClock, retry::RateLimitedBackoff, Quota, RateLimiter};
+///
+/// let clock = FakeRelativeClock::default();
+/// let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock);
+/// let delays: Vec<Duration> = RateLimitedBackoff::new(&lim)
+///     .with_max_wait(Duration::from_secs(1))
+///     .collect();
+/// assert_eq!(
+///     delays,
+///     vec![
+///         Duration::ZERO,
+///         Duration::ZERO,
+///         Duration::from_millis(500),
+///         Duration::from_secs(1),
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct RateLimitedBackoff<L> {
+    limiter: L,
+    max_wait: Duration,
+}
+
+impl<L> RateLimitedBackoff<L> {
+    /// Returns a policy that retries whenever `limiter` lets a cell through, however long that
+    /// takes.
+    pub fn new(limiter: L) -> Self {
+        RateLimitedBackoff {
+            limiter,
+            max_wait: Duration::MAX,
+        }
+    }
+
+    /// Makes the policy give up once a retry would have to wait longer than `max_wait`.
+    pub fn with_max_wait(self, max_wait: Duration) -> Self {
+        RateLimitedBackoff { max_wait, ..self }
+    }
+
+    /// Returns the longest time that the policy waits before a retry.
+    pub fn max_wait(&self) -> Duration {
+        self.max_wait
+    }
+}
+
+impl<L, S, C, MW> RateLimitedBackoff<L>
+where
+    L: Deref<Target = RateLimiter<NotKeyed, S, C, MW>>,
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Reserves a cell for the next retry, and returns how long to wait before making it.
+    ///
+    /// Returns `None` without reserving anything if the retry would have to wait longer than
+    /// the maximum wait.
+    pub fn next_delay(&self) -> Option<Duration> {
+        self.limiter
+            .check_or_reject_if_wait_exceeds(self.max_wait)
+            .ok()
+            .map(|reservation| reservation.wait())
+    }
+}
+
+impl<L, S, C, MW> Iterator for RateLimitedBackoff<L>
+where
+    L: Deref<Target = RateLimiter<NotKeyed, S, C, MW>>,
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        self.next_delay()
+    }
+}
+
+#[cfg(feature = "backoff")]
+impl<L, S, C, MW> backoff::backoff::Backoff for RateLimitedBackoff<L>
+where
+    L: Deref<Target = RateLimiter<NotKeyed, S, C, MW>>,
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    fn next_backoff(&mut self) -> Option<Duration> {
+        self.next_delay()
+    }
+}
+
+#[cfg(feature = "tryhard")]
+impl<'a, E, L, S, C, MW> tryhard::backoff_strategies::BackoffStrategy<'a, E>
+    for RateLimitedBackoff<L>
+where
+    L: Deref<Target = RateLimiter<NotKeyed, S, C, MW>>,
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    type Output = tryhard::RetryPolicy;
+
+    fn delay(&mut self, _attempt: u32, _error: &'a E) -> tryhard::RetryPolicy {
+        self.next_delay()
+            .map_or(tryhard::RetryPolicy::Break, tryhard::RetryPolicy::Delay)
+    }
+}
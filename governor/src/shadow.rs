@@ -0,0 +1,369 @@
+//! Verifying a rate limiter's decisions against a reference implementation.
+//!
+//! Governor's rate limiters keep a single timestamp per key (the GCRA's theoretical arrival
+//! time) instead of a log of the cells they let through. That makes them fast and small, but
+//! it also makes it hard to tell from the outside whether a surprising decision (e.g., "the
+//! rate limiter let through more cells than the quota allows") is a bug.
+//!
+//! A [`ShadowRateLimiter`] answers that question: It wraps a rate limiter, and checks each of
+//! its decisions against a brute-force reference implementation that keeps a log of all the
+//! cells let through, and reports each decision that the two disagree on to a callback as a
+//! [`Divergence`]. The reference implementation lets `n` cells through at time `t0` exactly if
+//! no window of time ending at `t0` would then contain more cells than the quota's burst size
+//! plus the cells replenished in that window.
+//!
+//! This is a debugging aid: The wrapper keeps a log for every key, and makes decisions one at a
+//! time. It only accounts for the quota's burst size and replenishment interval, so a quota's
+//! [cooldown](crate::Quota::with_cooldown) or
+//! [burst recharge delay](crate::Quota::with_burst_recharge_delay) show up as divergences.
+//!
+//! # Example
+//! ```rust
+//! # use nonzero_ext::nonzero;
+//! use governor::{clock::FakeRelativeClock, shadow::ShadowRateLimiter, Quota, RateLimiter};
+//! use std::sync::Mutex;
+//!
+//! let clock = FakeRelativeClock::default();
+//! let divergences = Mutex::new(vec![]);
+//! let lim = ShadowRateLimiter::new(
+//!     RateLimiter::direct_with_clock(Quota::per_second(nonzero!(5u32)), clock.clone()),
+//!     |divergence| divergences.lock().unwrap().push(divergence),
+//! );
+//! for _ in 0..1000 {
+//!     let _ = lim.check();
+//!     let _ = lim.check_n(nonzero!(3u32));
+//!     clock.advance(std::time::Duration::from_millis(70));
+//! }
+//! assert!(divergences.lock().unwrap().is_empty());
+//! ```
+
+use std::prelude::v1::*;
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::num::NonZeroU32;
+
+use crate::sync::Mutex;
+use crate::{
+    clock::{self, Reference},
+    errors::InsufficientCapacity,
+    middleware::RateLimitingMiddleware,
+    nanos::Nanos,
+    state::{keyed::KeyedStateStore, DirectStateStore, NotKeyed, StateStore},
+    RateLimiter,
+};
+
+/// A decision that a rate limiter and the reference implementation disagree on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence<K, P: clock::Reference> {
+    key: K,
+    cells: NonZeroU32,
+    admitted: bool,
+    at: P,
+    log: Vec<(P, NonZeroU32)>,
+}
+
+impl<K, P: clock::Reference> Divergence<K, P> {
+    /// The key that the decision was made for ([`NotKeyed::NonKey`] for direct rate limiters).
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// The number of cells that the decision was made about.
+    pub fn cells(&self) -> NonZeroU32 {
+        self.cells
+    }
+
+    /// Whether the rate limiter let the cells through, which the reference implementation
+    /// wouldn't have. If this is `false`, the rate limiter denied cells that the reference
+    /// implementation would have let through.
+    pub fn is_admitted(&self) -> bool {
+        self.admitted
+    }
+
+    /// The time right before the decision was made.
+    pub fn at(&self) -> P {
+        self.at
+    }
+
+    /// The earlier decisions for the key that the reference implementation took into account,
+    /// as the times right before they were made and the number of cells they let through,
+    /// oldest first.
+    pub fn log(&self) -> &[(P, NonZeroU32)] {
+        &self.log
+    }
+}
+
+/// A rate limiter whose decisions get checked against a reference implementation.
+///
+/// See [the module documentation](index.html).
+///
+/// All decisions have to be made through the wrapper for its logs to be complete: Decisions
+/// made with the [wrapped rate limiter](#method.limiter) directly, and methods that the wrapper
+/// doesn't offer, confuse the reference implementation.
+pub struct ShadowRateLimiter<K, S, C, MW, F>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    limiter: RateLimiter<K, S, C, MW>,
+    logs: Mutex<Logs<K>>,
+    on_divergence: F,
+}
+
+/// The cells that a [`ShadowRateLimiter`] let through.
+struct Logs<K> {
+    direct: Log,
+    keyed: HashMap<K, Log>,
+}
+
+/// The decisions that let cells through for one key, oldest first.
+///
+/// The log forgets decisions that can't make a difference to later ones anymore.
+#[derive(Default)]
+struct Log {
+    admissions: Vec<Admission>,
+    /// The number of cells let through so far, including by forgotten decisions.
+    cells: u128,
+}
+
+/// A decision that let cells through.
+///
+/// Clocks may advance while the rate limiter makes a decision, so the log keeps the times right
+/// before and after it.
+struct Admission {
+    earliest: Nanos,
+    latest: Nanos,
+    cells: NonZeroU32,
+    /// The number of cells let through before this decision.
+    preceding: u128,
+}
+
+impl Admission {
+    /// Returns the time at which the cells let through by this and all later decisions will
+    /// have been replenished, assuming that this decision was made at its `latest` time if
+    /// `strict`, or its `earliest` time otherwise.
+    fn replenished(&self, log: &Log, strict: bool, t: u128) -> u128 {
+        let at = if strict { self.latest } else { self.earliest };
+        u128::from(at.as_u64()) + t * (log.cells - self.preceding)
+    }
+}
+
+impl Log {
+    /// Returns whether `cells` more cells fit at `now`: For every logged decision, the cells let
+    /// through since then, plus `cells`, must not be more than the burst size plus the cells
+    /// replenished since then.
+    fn fits(&self, cells: NonZeroU32, now: Nanos, strict: bool, t: Nanos, burst: u64) -> bool {
+        let t = u128::from(t.as_u64());
+        let limit = u128::from(now.as_u64()) + t * u128::from(burst);
+        let cells = u128::from(cells.get());
+        cells <= u128::from(burst)
+            && self
+                .admissions
+                .iter()
+                .all(|admission| admission.replenished(self, strict, t) + t * cells <= limit)
+    }
+
+    /// Forgets the decisions whose cells, along with those of all later decisions, have been
+    /// replenished at `now`: The first decision made after `now` constrains later ones at least
+    /// as much as they would.
+    fn prune(&mut self, now: Nanos, t: Nanos) {
+        let t = u128::from(t.as_u64());
+        let now = u128::from(now.as_u64());
+        let admissions = std::mem::take(&mut self.admissions);
+        self.admissions = admissions
+            .into_iter()
+            .filter(|admission| admission.replenished(self, true, t) > now)
+            .collect();
+    }
+
+    /// Logs a decision that let `cells` through, and forgets the earlier decisions that it
+    /// constrains later ones at least as much as.
+    fn push(&mut self, earliest: Nanos, latest: Nanos, cells: NonZeroU32, t: Nanos) {
+        let t = u128::from(t.as_u64());
+        let admission = Admission {
+            earliest,
+            latest,
+            cells,
+            preceding: self.cells,
+        };
+        self.cells += u128::from(cells.get());
+        let admissions = std::mem::take(&mut self.admissions);
+        self.admissions = admissions
+            .into_iter()
+            .filter(|earlier| {
+                [true, false].iter().any(|&strict| {
+                    earlier.replenished(self, strict, t) > admission.replenished(self, strict, t)
+                })
+            })
+            .collect();
+        self.admissions.push(admission);
+    }
+}
+
+impl<K, S, C, MW, F> ShadowRateLimiter<K, S, C, MW, F>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+    F: Fn(Divergence<K, C::Instant>),
+{
+    /// Wraps `limiter`, calling `on_divergence` with each decision that it and the reference
+    /// implementation disagree on.
+    ///
+    /// `on_divergence` gets called after the decision, and may use the wrapper.
+    pub fn new(limiter: RateLimiter<K, S, C, MW>, on_divergence: F) -> Self {
+        ShadowRateLimiter {
+            limiter,
+            logs: Mutex::new(Logs {
+                direct: Log::default(),
+                keyed: HashMap::new(),
+            }),
+            on_divergence,
+        }
+    }
+
+    /// Returns the wrapped rate limiter.
+    pub fn limiter(&self) -> &RateLimiter<K, S, C, MW> {
+        &self.limiter
+    }
+
+    /// Consumes the wrapper, returning the wrapped rate limiter.
+    pub fn into_inner(self) -> RateLimiter<K, S, C, MW> {
+        self.limiter
+    }
+
+    /// Makes a decision with `decide`, and checks it against the reference implementation's
+    /// decision, given the cells let through for `key` in `log`.
+    fn shadow<T>(
+        &self,
+        log: &mut Log,
+        key: &K,
+        cells: NonZeroU32,
+        decide: impl FnOnce(&RateLimiter<K, S, C, MW>) -> (bool, T),
+    ) -> (T, Option<Divergence<K, C::Instant>>)
+    where
+        K: Clone,
+    {
+        let gcra = self.limiter.gcra();
+        let (t, burst) = (gcra.t(), gcra.tau().as_u64() / gcra.t().as_u64() + 1);
+        let clock = self.limiter.clock();
+        let start = self.limiter.start();
+        let before = clock.now().duration_since(start);
+        let (admitted, outcome) = decide(&self.limiter);
+        let after = clock.now().duration_since(start);
+
+        log.prune(before, t);
+        // Only report decisions that diverge however long the decision took:
+        let diverges = if admitted {
+            !log.fits(cells, after, false, t, burst)
+        } else {
+            log.fits(cells, before, true, t, burst)
+        };
+        let divergence = diverges.then(|| Divergence {
+            key: key.clone(),
+            cells,
+            admitted,
+            at: start + before,
+            log: log
+                .admissions
+                .iter()
+                .map(|admission| (start + admission.earliest, admission.cells))
+                .collect(),
+        });
+        if admitted {
+            log.push(before, after, cells, t);
+        }
+        (outcome, divergence)
+    }
+
+    /// Reports `divergence`, if there is one, and returns `outcome`.
+    fn report<T>(&self, (outcome, divergence): (T, Option<Divergence<K, C::Instant>>)) -> T {
+        if let Some(divergence) = divergence {
+            (self.on_divergence)(divergence);
+        }
+        outcome
+    }
+}
+
+/// # Direct rate limiters
+impl<S, C, MW, F> ShadowRateLimiter<NotKeyed, S, C, MW, F>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+    F: Fn(Divergence<NotKeyed, C::Instant>),
+{
+    /// See [`RateLimiter::check`].
+    pub fn check(&self) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let shadowed = {
+            let mut logs = self.logs.lock();
+            self.shadow(
+                &mut logs.direct,
+                &NotKeyed::NonKey,
+                NonZeroU32::MIN,
+                |limiter| {
+                    let decision = limiter.check();
+                    (decision.is_ok(), decision)
+                },
+            )
+        };
+        self.report(shadowed)
+    }
+
+    /// See [`RateLimiter::check_n`].
+    pub fn check_n(
+        &self,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        let shadowed = {
+            let mut logs = self.logs.lock();
+            self.shadow(&mut logs.direct, &NotKeyed::NonKey, n, |limiter| {
+                let decision = limiter.check_n(n);
+                (matches!(decision, Ok(Ok(_))), decision)
+            })
+        };
+        self.report(shadowed)
+    }
+}
+
+/// # Keyed rate limiters
+impl<K, S, C, MW, F> ShadowRateLimiter<K, S, C, MW, F>
+where
+    K: Hash + Eq + Clone,
+    S: KeyedStateStore<K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+    F: Fn(Divergence<K, C::Instant>),
+{
+    /// See [`RateLimiter::check_key`].
+    pub fn check_key(&self, key: &K) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let shadowed = {
+            let mut logs = self.logs.lock();
+            let log = logs.keyed.entry(key.clone()).or_default();
+            self.shadow(log, key, NonZeroU32::MIN, |limiter| {
+                let decision = limiter.check_key(key);
+                (decision.is_ok(), decision)
+            })
+        };
+        self.report(shadowed)
+    }
+
+    /// See [`RateLimiter::check_key_n`].
+    pub fn check_key_n(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        let shadowed = {
+            let mut logs = self.logs.lock();
+            let log = logs.keyed.entry(key.clone()).or_default();
+            self.shadow(log, key, n, |limiter| {
+                let decision = limiter.check_key_n(key, n);
+                (matches!(decision, Ok(Ok(_))), decision)
+            })
+        };
+        self.report(shadowed)
+    }
+}
@@ -1,19 +1,47 @@
 //! State stores for rate limiters
 
-use std::{marker::PhantomData, prelude::v1::*};
+use std::{cell::Cell, convert::Infallible, marker::PhantomData, prelude::v1::*};
 
+#[cfg(feature = "std")]
+mod chaos;
+#[cfg(feature = "std")]
+pub mod conformance;
+mod decision;
 pub mod direct;
 mod in_memory;
+#[cfg(feature = "std")]
+mod instrumented;
 pub mod keyed;
+mod refund;
+mod try_check;
+#[cfg(feature = "std")]
+mod waiters;
 
+#[cfg(feature = "std")]
+pub use self::chaos::ChaosStateStore;
+pub use self::decision::{Decision, IntoDecision};
 pub use self::in_memory::InMemoryState;
+#[cfg(feature = "std")]
+pub use self::instrumented::InstrumentedStateStore;
+pub use self::refund::Refundable;
+pub use self::try_check::TryCheck;
+#[cfg(feature = "std")]
+pub use self::waiters::WaiterCountingStateStore;
+#[cfg(feature = "std")]
+pub(crate) use self::waiters::Waiting;
 
 use crate::nanos::Nanos;
-use crate::{clock, Quota};
+#[cfg(feature = "std")]
+use crate::Jitter;
+use crate::{
+    clock::{self, Reference},
+    Quota,
+};
 use crate::{
     gcra::Gcra,
-    middleware::{NoOpMiddleware, RateLimitingMiddleware},
+    middleware::{KeyFormat, NoOpMiddleware, RateLimitingMiddleware},
 };
+use std::hash::Hash;
 
 pub use direct::*;
 
@@ -27,6 +55,10 @@ pub use direct::*;
 /// A direct state store is expressed as [`StateStore::Key`] = [`NotKeyed`].
 /// Keyed state stores have a
 /// type parameter for the key and set their key to that.
+///
+/// Implementations have to uphold a contract that goes beyond the methods' signatures (e.g.,
+/// the closures they get passed may be called more than once); the [`conformance`] module
+/// describes it, and checks stores against it.
 pub trait StateStore {
     /// The type of key that the state store can represent.
     type Key;
@@ -43,9 +75,153 @@ pub trait StateStore {
     /// It is `measure_and_replace`'s job then to safely replace the value at the key - it must
     /// only update the value if the value hasn't changed. The implementations in this
     /// crate use `AtomicU64` operations for this.
+    ///
+    /// The closure may be called more than once, and it calls the rate limiter's middleware.
+    /// Middleware that checks other keys on the same rate limiter deadlocks if the state store
+    /// holds a non-reentrant lock while calling the closure, which the `DashMapStateStore`
+    /// avoids.
     fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
     where
         F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>;
+
+    /// Called after a negative rate-limiting decision was made for a key.
+    ///
+    /// `t0` is the time at which the decision was made, as an offset from the rate limiter's
+    /// creation. State stores can use this to keep track of rate-limited keys; the default
+    /// implementation does nothing.
+    #[inline]
+    fn note_denial(&self, _key: &Self::Key, _t0: Nanos) {}
+
+    /// Called after a positive rate-limiting decision was made for a key.
+    ///
+    /// `t0` is the time at which the decision was made, and `tat` the state that it stored,
+    /// both as offsets from the rate limiter's creation. The default implementation does
+    /// nothing.
+    #[inline]
+    fn note_admission(&self, _key: &Self::Key, _t0: Nanos, _tat: Nanos) {}
+
+    /// Called when a task starts waiting for a key to allow cells through, in the `until_ready`
+    /// family of methods.
+    ///
+    /// Each call is followed by a call to [`note_wait_finished`](#method.note_wait_finished)
+    /// for the same key, once the task stops waiting. The default implementation does nothing.
+    #[inline]
+    fn note_wait_started(&self, _key: &Self::Key) {}
+
+    /// Called when a task stops waiting for a key, either because its cells were allowed
+    /// through or because it gave up waiting (e.g., its future was dropped). The default
+    /// implementation does nothing.
+    #[inline]
+    fn note_wait_finished(&self, _key: &Self::Key) {}
+
+    /// Calls `f` with the rate-limiting state stored at `key`, without updating it.
+    ///
+    /// This is how rate limiters make decisions that don't consume any capacity. The default
+    /// implementation calls [`measure_and_replace`](#tymethod.measure_and_replace) with a
+    /// closure that never produces a new state; keyed state stores may create an empty entry
+    /// for `key` that way.
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        match self.measure_and_replace(key, |tat| Err::<(Infallible, Nanos), _>(f(tat))) {
+            Ok(never) => match never {},
+            Err(result) => result,
+        }
+    }
+
+    /// Updates the rate limiting states of several keys using the given closure, all or nothing.
+    ///
+    /// Calls `f` for each of `keys` in order, like
+    /// [`measure_and_replace`](#tymethod.measure_and_replace) does for a single key (so keys
+    /// that occur more than once see the state stored for their previous occurrence). If `f`
+    /// returns `Err(E)` for any key, the states of the keys before it are restored, and the
+    /// key's index in `keys` is returned along with the error.
+    ///
+    /// The default implementation updates the keys one at a time, and
+    /// [restores](#method.restore) the states of the earlier keys afterwards if a later key is
+    /// rate-limited. It never accesses more than one key at once, but other decisions made for
+    /// the earlier keys in the meantime may see their state updated; if they updated the state
+    /// themselves, the earlier key keeps the update, as if its cell had been let through. State
+    /// stores that can lock all the keys at once should override this to make the update
+    /// atomic.
+    fn measure_and_replace_all<T, F, E>(
+        &self,
+        keys: &[&Self::Key],
+        f: F,
+    ) -> Result<Vec<T>, (usize, E)>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let mut outcomes = Vec::with_capacity(keys.len());
+        let mut replaced = Vec::with_capacity(keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            let prev = Cell::new(None);
+            let decision = self.measure_and_replace(key, |tat| {
+                prev.set(tat);
+                f(tat).map(|(outcome, next)| ((outcome, next), next))
+            });
+            match decision {
+                Ok((outcome, next)) => {
+                    outcomes.push(outcome);
+                    replaced.push((prev.get(), next));
+                }
+                Err(e) => {
+                    for (key, (prev, next)) in keys.iter().zip(replaced).rev() {
+                        self.restore(key, next, prev);
+                    }
+                    return Err((i, e));
+                }
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Takes back an update of the rate limiting state of `key`: If the state stored for `key`
+    /// is still `replaced`, it becomes `prev` again, and if `prev` is `None`, the key is left
+    /// without a state. A state that was updated again in the meantime is left alone.
+    ///
+    /// This is how [`measure_and_replace_all`](#method.measure_and_replace_all) gives back the
+    /// capacity of the keys before a rate-limited one. The default implementation restores
+    /// `Some` states with [`measure_and_replace`](#tymethod.measure_and_replace), but has no
+    /// way to remove a state: It leaves states that replaced `None` as they are, which charges
+    /// those keys for their cell. State stores that can remove a key's state (or that treat a
+    /// zero state as no state) should override this.
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        if let Some(prev) = prev {
+            let _ = self.measure_and_replace(key, |tat| match tat {
+                Some(tat) if tat == replaced => Ok(((), prev)),
+                _ => Err(()),
+            });
+        }
+    }
+}
+
+/// A state store whose rate limiting states can all be rewritten at once.
+///
+/// Rate limiters need this to [re-anchor](RateLimiter::rebase_start) the states they keep.
+pub trait RebasableStateStore: StateStore {
+    /// Replaces each rate limiting state kept in the store with the result of calling `f` on
+    /// it.
+    ///
+    /// Keys without a state (i.e., that are indistinguishable from fresh keys) are left alone.
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos;
+}
+
+/// How much burst capacity a rate limiter grants keys that it has no state for yet, see
+/// [`RateLimiter::with_fresh_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum FreshState {
+    /// New keys start with the quota's whole burst capacity available, as if they had been idle
+    /// for a long time. This is the default.
+    #[default]
+    Full,
+
+    /// New keys start with a single cell available, as if they had just used up their burst
+    /// capacity. They accumulate the rest of it at the quota's rate while they're idle.
+    Empty,
 }
 
 /// A rate limiter.
@@ -64,6 +240,9 @@ where
     gcra: Gcra,
     clock: C,
     start: C::Instant,
+    #[cfg(feature = "std")]
+    jitter: Jitter,
+    key_format: Option<KeyFormat<K>>,
     middleware: PhantomData<MW>,
 }
 
@@ -85,6 +264,9 @@ where
             clock,
             gcra,
             start,
+            #[cfg(feature = "std")]
+            jitter: Jitter::NONE,
+            key_format: None,
             middleware: PhantomData,
         }
     }
@@ -100,6 +282,142 @@ where
     pub fn clock(&self) -> &C {
         &self.clock
     }
+
+    /// Returns the reference point that the rate limiter measures time from.
+    ///
+    /// This is the time at which the rate limiter was created, unless it was
+    /// [rebased](#method.rebase_start) since.
+    pub fn start(&self) -> C::Instant {
+        self.start
+    }
+
+    /// Enables or disables rate limiting, e.g. as a kill switch during incidents.
+    ///
+    /// A disabled rate limiter lets every cell through without looking at or updating its
+    /// state: Checks return the middleware's positive outcome, whose
+    /// [snapshot](crate::middleware::StateSnapshot) shows the whole burst capacity as available,
+    /// methods that wait for capacity return right away, and refunds do nothing. Re-enabling it
+    /// resumes rate limiting from the state that it had when it was disabled.
+    ///
+    /// Rate limiters start out enabled. The setting applies to decisions that start after the
+    /// call, on any thread.
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{Quota, RateLimiter};
+    ///
+    /// let lim = RateLimiter::direct(Quota::per_hour(nonzero!(1u32)));
+    /// lim.check().unwrap();
+    /// assert!(lim.check().is_err());
+    ///
+    /// lim.set_enabled(false);
+    /// assert!(!lim.is_enabled());
+    /// assert!(lim.check().is_ok());
+    /// assert!(lim.check_n(nonzero!(1000u32)).unwrap().is_ok());
+    ///
+    /// lim.set_enabled(true);
+    /// assert!(lim.check().is_err());
+    /// ```
+    pub fn set_enabled(&self, enabled: bool) {
+        self.gcra.set_enabled(enabled);
+    }
+
+    /// Returns whether the rate limiter is enabled; see [`set_enabled`](#method.set_enabled).
+    pub fn is_enabled(&self) -> bool {
+        !self.gcra.is_disabled()
+    }
+
+    #[cfg(feature = "std")] // only used by the shadow rate limiter.
+    pub(crate) fn gcra(&self) -> &Gcra {
+        &self.gcra
+    }
+}
+
+/// # Re-anchoring rate limiters
+///
+/// Rate limiters keep their state as offsets from a reference point in time, their
+/// [start](#method.start). Clock readings from before that point all count as the start itself, which
+/// matters with clocks that can go backwards: If a [`SystemClock`](clock::SystemClock)'s time is
+/// stepped back (e.g. by NTP) past a rate limiter's start, the rate limiter can not tell the time
+/// pass until the clock catches up with the start again. Rebasing the rate limiter to an earlier
+/// start fixes that.
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: RebasableStateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Measures time from `new_start` instead of the current [start](#method.start).
+    ///
+    /// The rate limiting states in the state store get rewritten to refer to the new start, so
+    /// they keep the same theoretical arrival times; the rate limiter makes the same decisions as
+    /// before for clock readings later than both the old and the new start. States that lie
+    /// before a later `new_start` become fresh.
+    ///
+    /// This requires exclusive access to the rate limiter, so no decisions can be made while
+    /// the states are rewritten.
+    pub fn rebase_start(&mut self, new_start: C::Instant) {
+        let gcra = &self.gcra;
+        if new_start < self.start {
+            let shift = self.start.duration_since(new_start);
+            self.state.rewrite_states(|state| {
+                gcra.rewrite_state(state, |t| {
+                    Nanos::from(t.as_u64().saturating_add(shift.as_u64()))
+                })
+            });
+        } else {
+            let shift = new_start.duration_since(self.start);
+            self.state
+                .rewrite_states(|state| gcra.rewrite_state(state, |t| t.saturating_sub(shift)));
+        }
+        self.start = new_start;
+    }
+}
+
+/// # Changing quotas
+///
+/// A rate limiter's quota can be changed while it keeps the states of its keys: Each key
+/// stays in debt by as many cells as before, which it pays off at the new quota's rate. Keys
+/// that were idle for long enough get the new quota's whole burst capacity, and keys that used
+/// up more cells than the new quota's burst size get denied until they have paid off the
+/// excess.
+///
+/// Changing a quota drastically at once can still let a burst through or deny a lot of
+/// requests; a [`RampingRateLimiter`](crate::ramp::RampingRateLimiter) changes it gradually
+/// instead.
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: RebasableStateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Makes decisions according to `quota` from now on, keeping the rate limiting states.
+    ///
+    /// Like [rebasing](#method.rebase_start), this requires exclusive access to the rate
+    /// limiter, so no decisions can be made while the states are rewritten.
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{Quota, RateLimiter};
+    ///
+    /// let mut lim = RateLimiter::direct(Quota::per_hour(nonzero!(2u32)));
+    /// lim.check().unwrap();
+    /// lim.check().unwrap();
+    /// assert!(lim.check().is_err());
+    ///
+    /// // The cells let through so far still count against the larger quota:
+    /// lim.set_quota(Quota::per_hour(nonzero!(3u32)));
+    /// lim.check().unwrap();
+    /// assert!(lim.check().is_err());
+    /// ```
+    pub fn set_quota(&mut self, quota: Quota) {
+        let gcra = self.gcra.with_quota(quota);
+        let old = &self.gcra;
+        let t0 = self.clock.now().duration_since(self.start);
+        self.state
+            .rewrite_states(|state| old.convert_state(state, t0, &gcra));
+        self.gcra = gcra;
+    }
 }
 
 impl<K, S, C, MW> RateLimiter<K, S, C, MW>
@@ -118,8 +436,79 @@ where
             gcra: self.gcra,
             clock: self.clock,
             start: self.start,
+            #[cfg(feature = "std")]
+            jitter: self.jitter,
+            key_format: self.key_format,
+        }
+    }
+
+    /// Makes the rate limiter show its keys to middleware in `format`.
+    ///
+    /// Keyed rate limiters show a [hash](KeyFormat::hashed) of their keys by default, and
+    /// direct rate limiters show `[redacted]`. See [`KeyFormat`] for the alternatives.
+    pub fn with_key_format(self, format: KeyFormat<K>) -> Self {
+        RateLimiter {
+            key_format: Some(format),
+            ..self
         }
     }
+
+    /// Makes the rate limiter start keys that it has no state for in `fresh` state.
+    ///
+    /// With [`FreshState::Empty`], a key that the rate limiter hasn't seen yet gets a single
+    /// cell through right away, and further cells at the quota's rate only; keys that were
+    /// idle for a while after their first decision still get their accumulated burst. This slows
+    /// down clients that spread their requests over many new keys (e.g. by signing up new
+    /// accounts), without affecting established keys.
+    ///
+    /// Keys whose states were removed by [`retain_recent`](#method.retain_recent) (or that were
+    /// never seen at all) count as new. The same goes for the single state of a direct rate
+    /// limiter, before its first decision.
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{state::FreshState, Quota, RateLimiter};
+    ///
+    /// let lim = RateLimiter::keyed(Quota::per_minute(nonzero!(10u32)))
+    ///     .with_fresh_state(FreshState::Empty);
+    /// assert_eq!(Ok(()), lim.check_key(&"new-user"));
+    /// assert!(lim.check_key(&"new-user").is_err());
+    /// ```
+    pub fn with_fresh_state(mut self, fresh: FreshState) -> Self {
+        self.gcra.set_fresh_state(fresh);
+        self
+    }
+
+    /// Returns how much burst capacity keys that the rate limiter has no state for start with;
+    /// see [`with_fresh_state`](#method.with_fresh_state).
+    pub fn fresh_state(&self) -> FreshState {
+        self.gcra.fresh_state()
+    }
+}
+
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    K: Hash,
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// The format that keyed decisions show their keys to middleware in.
+    pub(crate) fn key_format(&self) -> KeyFormat<K> {
+        self.key_format.unwrap_or_else(KeyFormat::hashed)
+    }
+}
+
+impl<S, C, MW> RateLimiter<NotKeyed, S, C, MW>
+where
+    S: StateStore<Key = NotKeyed>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// The format that direct decisions show their (non-)key to middleware in.
+    pub(crate) fn key_format(&self) -> KeyFormat<NotKeyed> {
+        self.key_format.unwrap_or_else(KeyFormat::redacted)
+    }
 }
 
 #[cfg(feature = "std")]
@@ -134,6 +523,50 @@ where
     }
 }
 
+/// # Default jitter
+///
+/// Rate limiters can hold a default [`Jitter`], which the methods that wait for cells to be
+/// allowed through (like [`until_ready`](#method.until_ready) and
+/// [`until_key_ready`](#method.until_key_ready)) add to their wait periods. This sets one
+/// policy against thundering herds in the place where the rate limiter is built, instead of at
+/// every call site. The `*_with_jitter` methods still use the jitter they are given instead.
+#[cfg(feature = "std")]
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Makes the methods that wait for cells to be allowed through add `jitter` to their wait
+    /// periods, unless they are given a jitter of their own.
+    ///
+    /// ```rust
+    /// # #[cfg(feature = "jitter")]
+    /// # fn main () {
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// use governor::{Jitter, Quota, RateLimiter};
+    ///
+    /// let lim = RateLimiter::direct(Quota::per_second(nonzero!(50u32)))
+    ///     .with_jitter(Jitter::up_to(Duration::from_millis(10)));
+    /// assert_eq!(lim.jitter(), &Jitter::up_to(Duration::from_millis(10)));
+    /// # }
+    /// # #[cfg(not(feature = "jitter"))]
+    /// # fn main() {}
+    /// ```
+    pub fn with_jitter(self, jitter: Jitter) -> Self {
+        RateLimiter { jitter, ..self }
+    }
+
+    /// Returns the jitter that the methods that wait for cells to be allowed through add to
+    /// their wait periods by default.
+    ///
+    /// This is no jitter at all, unless set with [`with_jitter`](#method.with_jitter).
+    pub fn jitter(&self) -> &Jitter {
+        &self.jitter
+    }
+}
+
 #[cfg(all(feature = "std", test))]
 mod test {
     use super::*;
@@ -0,0 +1,210 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use std::fmt;
+use std::hash::Hash;
+use std::num::NonZeroU64;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::nanos::Nanos;
+use crate::state::keyed::ShrinkableKeyedStateStore;
+use crate::state::{RebasableStateStore, StateStore};
+
+/// A state store that misbehaves on purpose, for testing how applications cope with slow or
+/// contended state stores.
+///
+/// `ChaosStateStore` wraps any other state store and makes accesses to it worse in
+/// configurable ways:
+///
+/// * [`with_latency`](#method.with_latency) makes each access block the calling thread for a
+///   while before it reaches the wrapped store, like a state store on the other side of a
+///   network would.
+/// * [`with_spurious_retries`](#method.with_spurious_retries) makes some accesses lose a
+///   compare-and-swap race: The rate limiter's decision gets computed once more than usual, as
+///   if another thread had changed the state in the meantime.
+///
+/// Decisions are unaffected: They are still made by the wrapped store.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// use governor::{
+///     clock::DefaultClock, middleware::NoOpMiddleware,
+///     state::{ChaosStateStore, InMemoryState},
+///     Quota, RateLimiter,
+/// };
+///
+/// let store = ChaosStateStore::new(InMemoryState::default())
+///     .with_latency(Duration::from_millis(1))
+///     .with_spurious_retries(nonzero!(2u64));
+/// let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> =
+///     RateLimiter::new(Quota::per_second(nonzero!(1u32)), store, DefaultClock::default());
+/// assert!(lim.check().is_ok());
+/// assert!(lim.check().is_err());
+/// ```
+pub struct ChaosStateStore<S> {
+    inner: S,
+    latency: Duration,
+    retry_every: Option<NonZeroU64>,
+    accesses: AtomicU64,
+}
+
+impl<S> ChaosStateStore<S> {
+    /// Wraps `inner`, without making it misbehave yet.
+    pub fn new(inner: S) -> Self {
+        ChaosStateStore {
+            inner,
+            latency: Duration::ZERO,
+            retry_every: None,
+            accesses: AtomicU64::new(0),
+        }
+    }
+
+    /// Makes every access to the state store take `latency` longer.
+    pub fn with_latency(self, latency: Duration) -> Self {
+        ChaosStateStore { latency, ..self }
+    }
+
+    /// Makes every `nth` access to the state store lose a race against another thread once,
+    /// so that the decision is computed twice.
+    pub fn with_spurious_retries(self, nth: NonZeroU64) -> Self {
+        ChaosStateStore {
+            retry_every: Some(nth),
+            ..self
+        }
+    }
+
+    /// Returns the wrapped state store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the number of times the state store was accessed.
+    pub fn accesses(&self) -> u64 {
+        self.accesses.load(Ordering::Relaxed)
+    }
+
+    /// Counts an access and delays it; returns whether the access should be retried.
+    // `is_multiple_of` needs Rust 1.87:
+    #[allow(clippy::manual_is_multiple_of)]
+    fn access(&self) -> bool {
+        let n = self.accesses.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.latency > Duration::ZERO {
+            thread::sleep(self.latency);
+        }
+        self.retry_every.is_some_and(|nth| n % nth.get() == 0)
+    }
+}
+
+impl<S: StateStore> StateStore for ChaosStateStore<S> {
+    type Key = S::Key;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        if self.access() {
+            // Compute the decision as if the state got replaced before we could store ours:
+            let _ = self
+                .inner
+                .measure_and_peek(key, |tat| Ok::<_, ()>(f(tat).is_ok()));
+        }
+        self.inner.measure_and_replace(key, f)
+    }
+
+    fn note_denial(&self, key: &Self::Key, t0: Nanos) {
+        self.inner.note_denial(key, t0)
+    }
+
+    fn note_admission(&self, key: &Self::Key, t0: Nanos, tat: Nanos) {
+        self.inner.note_admission(key, t0, tat)
+    }
+
+    fn note_wait_started(&self, key: &Self::Key) {
+        self.inner.note_wait_started(key)
+    }
+
+    fn note_wait_finished(&self, key: &Self::Key) {
+        self.inner.note_wait_finished(key)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        self.access();
+        self.inner.measure_and_peek(key, f)
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        self.inner.restore(key, replaced, prev)
+    }
+}
+
+impl<S: RebasableStateStore> RebasableStateStore for ChaosStateStore<S> {
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        self.inner.rewrite_states(f)
+    }
+}
+
+impl<K, S> ShrinkableKeyedStateStore<K> for ChaosStateStore<S>
+where
+    K: Hash + Eq + Clone,
+    S: ShrinkableKeyedStateStore<K>,
+{
+    fn retain_recent(&self, drop_below: Nanos) {
+        self.inner.retain_recent(drop_below)
+    }
+
+    fn shrink_to_fit(&self) {
+        self.inner.shrink_to_fit()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        self.inner.approx_memory_bytes()
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        self.inner.pre_seed(keys)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        self.inner.export_states(drop_below)
+    }
+}
+
+impl<S: Default> Default for ChaosStateStore<S> {
+    fn default() -> Self {
+        ChaosStateStore::new(S::default())
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for ChaosStateStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChaosStateStore")
+            .field("inner", &self.inner)
+            .field("latency", &self.latency)
+            .field("retry_every", &self.retry_every)
+            .field("accesses", &self.accesses())
+            .finish()
+    }
+}
@@ -0,0 +1,193 @@
+//! A conformance test suite for custom state stores.
+//!
+//! Rate limiters rely on a contract that every [`StateStore`] has to uphold, beyond what the
+//! trait's signatures can express:
+//!
+//! * States are opaque [`Nanos`] values, which rate limiters compute as offsets from their
+//!   [start](crate::RateLimiter::start). Stores have to hand back exactly the value that was
+//!   stored last for a key; the only exception is a zero state, which rate limiters never store,
+//!   and which stores may treat as no state at all.
+//! * The closure passed to [`measure_and_replace`](StateStore::measure_and_replace) is pure: It
+//!   may be called any number of times, and each call has to see the state currently stored for
+//!   the key. A store may only keep the state that the closure returned if nothing else replaced
+//!   the state it was called with in the meantime; otherwise, it has to call the closure again.
+//! * If the closure returns an error, the store leaves the state alone and returns that error.
+//! * [`measure_and_peek`](StateStore::measure_and_peek) and the `note_*` hooks never change a
+//!   state.
+//! * [`measure_and_replace_all`](StateStore::measure_and_replace_all) updates all of its keys or
+//!   none of them, and keys that occur more than once see the state stored for their previous
+//!   occurrence.
+//! * [`restore`](StateStore::restore) only puts back a state if the state it replaced is still
+//!   stored.
+//!
+//! [`assert_state_store_conformance`] checks a store against this contract, and panics with a
+//! description of the first violation it finds. Run it from a test in the crate that implements
+//! the store:
+//!
+//! ```rust
+//! use governor::state::{conformance, keyed::HashMapStateStore, InMemoryState, NotKeyed};
+//!
+//! // In a #[test] function:
+//! conformance::assert_state_store_conformance(&InMemoryState::default(), &NotKeyed::NonKey);
+//! conformance::assert_state_store_conformance(&HashMapStateStore::<u32>::default(), &1);
+//! ```
+//!
+//! The `testing` module's property tests (available with the `proptest`
+//! feature) complement these checks with the decisions that rate limiters make on top of a
+//! store.
+
+use std::prelude::v1::*;
+
+use std::cell::RefCell;
+use std::convert::Infallible;
+use std::thread;
+
+use crate::{nanos::Nanos, state::StateStore};
+
+/// The number of threads that [`assert_state_store_conformance`] replaces states from at once.
+const THREADS: u64 = 8;
+
+/// The number of times each of those threads replaces the state.
+const REPLACEMENTS_PER_THREAD: u64 = 1000;
+
+/// Checks that `store` upholds the contract of [`StateStore`] for `key`, as described in
+/// [the module documentation](index.html).
+///
+/// `store` must not have a state for `key` yet, and nothing else may use it while the checks
+/// run. Some of the checks replace the state from several threads at once.
+///
+/// # Panics
+/// Panics if `store` violates the contract.
+pub fn assert_state_store_conformance<S>(store: &S, key: &S::Key)
+where
+    S: StateStore + Sync,
+    S::Key: Sync,
+{
+    // A fresh key has no state, and negative decisions don't give it one:
+    assert_eq!(peek(store, key), None, "a fresh key has a state");
+    assert_eq!(
+        store.measure_and_replace(key, |_| Err::<((), Nanos), _>("denied")),
+        Err("denied"),
+        "the closure's error didn't get returned"
+    );
+    assert_eq!(peek(store, key), None, "a negative decision stored a state");
+
+    // Positive decisions store exactly the state that the closure returns, whatever it is:
+    for state in [1, 1_000_000_000, 500, u64::MAX, 2] {
+        let seen = store.measure_and_replace(key, |prev| Ok::<_, ()>((prev, Nanos::from(state))));
+        assert_eq!(
+            store.measure_and_replace(key, |_| Err::<((), Nanos), _>(())),
+            Err(()),
+            "a negative decision didn't return the closure's error"
+        );
+        assert_eq!(
+            peek(store, key),
+            Some(Nanos::from(state)),
+            "storing {:?} (after {:?}) didn't keep it",
+            state,
+            seen
+        );
+    }
+
+    // Every call of the closure sees the current state, and the outcome of the call whose
+    // state got replaced is returned:
+    let seen = RefCell::new(vec![]);
+    let outcome = store.measure_and_replace(key, |prev| {
+        seen.borrow_mut().push(prev);
+        Ok::<_, ()>((prev.map(Nanos::as_u64), Nanos::from(3)))
+    });
+    assert_eq!(
+        outcome,
+        Ok(Some(2)),
+        "the closure's outcome didn't get returned"
+    );
+    assert!(
+        seen.borrow()
+            .iter()
+            .all(|prev| *prev == Some(Nanos::from(2))),
+        "the closure saw states {:?}, but 2 was stored",
+        seen.borrow()
+    );
+
+    // Hooks and peeks leave the state alone:
+    store.note_denial(key, Nanos::from(1));
+    store.note_admission(key, Nanos::from(1), Nanos::from(4));
+    store.note_wait_started(key);
+    store.note_wait_finished(key);
+    assert_eq!(
+        store.measure_and_peek(key, Ok::<_, ()>),
+        Ok(Some(Nanos::from(3))),
+        "the state changed without a positive decision"
+    );
+    assert_eq!(peek(store, key), Some(Nanos::from(3)));
+
+    assert_replace_all_conformance(store, key);
+    assert_concurrent_conformance(store, key);
+}
+
+/// Checks that updating the same key twice at once works all or nothing.
+fn assert_replace_all_conformance<S: StateStore>(store: &S, key: &S::Key) {
+    let increment = |prev: Option<Nanos>| {
+        let prev = prev.map_or(0, Nanos::as_u64);
+        Ok::<_, ()>((prev, Nanos::from(prev + 1)))
+    };
+    assert_eq!(
+        store.measure_and_replace_all(&[key, key], increment),
+        Ok(vec![3, 4]),
+        "a batch's second update of a key didn't see its first"
+    );
+    assert_eq!(peek(store, key), Some(Nanos::from(5)));
+
+    let outcome = store.measure_and_replace_all(&[key, key], |prev| match prev {
+        Some(prev) if prev > Nanos::from(5) => Err("denied"),
+        prev => Ok((
+            (),
+            prev.map_or(Nanos::from(1), |prev| prev + Nanos::from(1)),
+        )),
+    });
+    assert_eq!(outcome, Err((1, "denied")), "a batch didn't get denied");
+    assert_eq!(
+        peek(store, key),
+        Some(Nanos::from(5)),
+        "a denied batch didn't restore the state"
+    );
+
+    store.restore(key, Nanos::from(4), Some(Nanos::from(1)));
+    assert_eq!(
+        peek(store, key),
+        Some(Nanos::from(5)),
+        "restoring a state that was replaced since replaced it again"
+    );
+}
+
+/// Checks that concurrent replacements of the same state don't get lost.
+fn assert_concurrent_conformance<S>(store: &S, key: &S::Key)
+where
+    S: StateStore + Sync,
+    S::Key: Sync,
+{
+    let before = peek(store, key).map_or(0, Nanos::as_u64);
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                for _ in 0..REPLACEMENTS_PER_THREAD {
+                    let _ = store.measure_and_replace(key, |prev| {
+                        Ok::<_, ()>(((), Nanos::from(prev.map_or(0, Nanos::as_u64) + 1)))
+                    });
+                }
+            });
+        }
+    });
+    assert_eq!(
+        peek(store, key),
+        Some(Nanos::from(before + THREADS * REPLACEMENTS_PER_THREAD)),
+        "concurrent replacements got lost"
+    );
+}
+
+/// Returns the state stored for `key`.
+fn peek<S: StateStore>(store: &S, key: &S::Key) -> Option<Nanos> {
+    store
+        .measure_and_peek(key, Ok::<_, Infallible>)
+        .unwrap_or_else(|never| match never {})
+}
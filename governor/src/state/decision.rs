@@ -0,0 +1,223 @@
+use std::prelude::v1::*;
+
+use std::hash::Hash;
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use crate::middleware::RateLimitingMiddleware;
+use crate::state::keyed::KeyedStateStore;
+use crate::state::{DirectStateStore, NotKeyed};
+use crate::{clock, InsufficientCapacity, NotUntil, RateLimiter};
+
+/// The result of a rate-limiting decision, with the middleware's outcomes for both positive
+/// and negative decisions.
+///
+/// `Decision` flattens the nested `Result` that [`check_n`](RateLimiter::check_n) returns into
+/// a single enum, and is returned by the `*_decide` family of methods. Unlike [`TryCheck`],
+/// which replaces the negative outcome with the time to wait, it holds on to the
+/// middleware's negative outcome (a [`NotUntil`] by default).
+///
+/// Existing results convert into a `Decision` with [`From`], and the nested results of batch
+/// checks also with [`into_decision`](IntoDecision::into_decision):
+///
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// use governor::{
+///     clock::FakeRelativeClock,
+///     state::{Decision, IntoDecision},
+///     Quota, RateLimiter,
+/// };
+///
+/// let lim = RateLimiter::direct_with_clock(
+///     Quota::per_second(nonzero!(2u32)),
+///     FakeRelativeClock::default(),
+/// );
+/// assert_eq!(lim.check_n_decide(nonzero!(3u32)).max_capacity(), Some(2));
+/// assert!(lim.check_n(nonzero!(2u32)).into_decision().is_allowed());
+///
+/// let denied = lim.check_decide();
+/// assert_eq!(denied.retry_after(), Some(Duration::from_millis(500)));
+/// assert!(matches!(denied, Decision::Denied(_)));
+/// ```
+///
+/// [`TryCheck`]: crate::state::TryCheck
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision<T, N> {
+    /// The cells were allowed through, and their capacity was consumed.
+    ///
+    /// Holds the positive outcome of the rate limiter's middleware.
+    Allowed(T),
+
+    /// The cells could not be allowed through at this time. No capacity was consumed.
+    ///
+    /// Holds the negative outcome of the rate limiter's middleware.
+    Denied(N),
+
+    /// The cells can never be allowed through, because there are more of them than the
+    /// rate limiter's burst size.
+    InsufficientCapacity {
+        /// The largest number of cells that could ever be allowed through at once.
+        max: u32,
+    },
+}
+
+impl<T, N> Decision<T, N> {
+    /// Returns `true` if the cells were allowed through.
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Decision::Allowed(_))
+    }
+
+    /// Returns the largest number of cells that could ever be allowed through at once, if
+    /// there were more cells than that.
+    pub fn max_capacity(&self) -> Option<u32> {
+        match self {
+            Decision::InsufficientCapacity { max } => Some(*max),
+            _ => None,
+        }
+    }
+
+    /// Returns the positive outcome, if the cells were allowed through.
+    pub fn allowed(self) -> Option<T> {
+        match self {
+            Decision::Allowed(outcome) => Some(outcome),
+            _ => None,
+        }
+    }
+
+    /// Returns the negative outcome, if the cells could not be allowed through at this time.
+    pub fn denied(self) -> Option<N> {
+        match self {
+            Decision::Denied(outcome) => Some(outcome),
+            _ => None,
+        }
+    }
+
+    /// Converts the decision back into the nested `Result` that
+    /// [`check_n`](RateLimiter::check_n) returns.
+    pub fn into_result(self) -> Result<Result<T, N>, InsufficientCapacity> {
+        match self {
+            Decision::Allowed(outcome) => Ok(Ok(outcome)),
+            Decision::Denied(outcome) => Ok(Err(outcome)),
+            Decision::InsufficientCapacity { max } => Err(InsufficientCapacity(max)),
+        }
+    }
+}
+
+impl<T, P: clock::Reference> Decision<T, NotUntil<P>> {
+    /// Returns the time to wait before checking the cells again, if they could be allowed
+    /// through later.
+    ///
+    /// Like [`NotUntil::deadline_duration`], this is measured from the time that the decision
+    /// was made.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Decision::Denied(not_until) => Some(not_until.deadline_duration()),
+            _ => None,
+        }
+    }
+}
+
+impl<T, N> From<Result<T, N>> for Decision<T, N> {
+    fn from(decision: Result<T, N>) -> Self {
+        match decision {
+            Ok(outcome) => Decision::Allowed(outcome),
+            Err(outcome) => Decision::Denied(outcome),
+        }
+    }
+}
+
+impl<T, N> From<Result<Result<T, N>, InsufficientCapacity>> for Decision<T, N> {
+    fn from(decision: Result<Result<T, N>, InsufficientCapacity>) -> Self {
+        match decision {
+            Ok(decision) => decision.into(),
+            Err(insufficient) => insufficient.into(),
+        }
+    }
+}
+
+impl<T, N> From<InsufficientCapacity> for Decision<T, N> {
+    fn from(InsufficientCapacity(max): InsufficientCapacity) -> Self {
+        Decision::InsufficientCapacity { max }
+    }
+}
+
+/// Conversion of the nested results of batch checks (like [`check_n`](RateLimiter::check_n))
+/// into a [`Decision`], so that call sites can flatten them with a method call.
+///
+/// The results of single-cell checks convert into a `Decision` with [`From`].
+pub trait IntoDecision {
+    /// The middleware's positive outcome.
+    type Positive;
+
+    /// The middleware's negative outcome.
+    type Negative;
+
+    /// Converts the result into a [`Decision`].
+    fn into_decision(self) -> Decision<Self::Positive, Self::Negative>;
+}
+
+impl<T, N> IntoDecision for Result<Result<T, N>, InsufficientCapacity> {
+    type Positive = T;
+
+    type Negative = N;
+
+    fn into_decision(self) -> Decision<T, N> {
+        self.into()
+    }
+}
+
+/// # Direct rate limiters - Checking cells with a [`Decision`]
+impl<S, C, MW> RateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Allow a single cell through the rate limiter, reporting the decision as a [`Decision`].
+    ///
+    /// This makes the same decision as [`check`](#method.check).
+    pub fn check_decide(&self) -> Decision<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.check().into()
+    }
+
+    /// Allow *only all* `n` cells through the rate limiter, reporting the decision as a
+    /// [`Decision`].
+    ///
+    /// This makes the same decision as [`check_n`](#method.check_n).
+    pub fn check_n_decide(
+        &self,
+        n: NonZeroU32,
+    ) -> Decision<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.check_n(n).into()
+    }
+}
+
+/// # Keyed rate limiters - Checking cells with a [`Decision`]
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Allow a single cell through the rate limiter for the given key, reporting the decision
+    /// as a [`Decision`].
+    ///
+    /// This makes the same decision as [`check_key`](#method.check_key).
+    pub fn check_key_decide(&self, key: &K) -> Decision<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.check_key(key).into()
+    }
+
+    /// Allow *only all* `n` cells through the rate limiter for the given key, reporting the
+    /// decision as a [`Decision`].
+    ///
+    /// This makes the same decision as [`check_key_n`](#method.check_key_n).
+    pub fn check_key_n_decide(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Decision<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.check_key_n(key, n).into()
+    }
+}
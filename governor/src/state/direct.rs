@@ -6,20 +6,22 @@
 use std::prelude::v1::*;
 
 use std::num::NonZeroU32;
+use std::time::Duration;
 
 use crate::{
     clock,
-    errors::InsufficientCapacity,
+    errors::{CostError, InsufficientCapacity},
     middleware::{NoOpMiddleware, RateLimitingMiddleware},
+    nanos::Nanos,
     state::InMemoryState,
-    Quota,
+    NotUntil, Quota,
 };
 
 /// The "this state store does not use keys" key type.
 ///
 /// It's possible to use this to create a "direct" rate limiter. It explicitly does not implement
 /// [`Hash`][std::hash::Hash] so that it is possible to tell apart from "hashable" key types.
-#[derive(PartialEq, Debug, Eq)]
+#[derive(PartialEq, Debug, Eq, Clone, Copy)]
 pub enum NotKeyed {
     /// The value given to state stores' methods.
     NonKey,
@@ -32,6 +34,90 @@ pub trait DirectStateStore: StateStore<Key = NotKeyed> {}
 
 impl<T> DirectStateStore for T where T: StateStore<Key = NotKeyed> {}
 
+/// A positive decision for a batch of cells that may have been clamped to the rate limiter's
+/// burst size.
+///
+/// Returned by [`RateLimiter::check_n_clamped`] and
+/// [`RateLimiter::until_n_ready_clamped`], and their keyed counterparts, as well as
+/// [`RateLimiter::check_at_least_n`] and [`RateLimiter::until_at_least_n_ready`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clamped<T> {
+    requested: NonZeroU32,
+    admitted: NonZeroU32,
+    outcome: T,
+}
+
+impl<T> Clamped<T> {
+    pub(crate) fn new(requested: NonZeroU32, admitted: NonZeroU32, outcome: T) -> Self {
+        Clamped {
+            requested,
+            admitted,
+            outcome,
+        }
+    }
+
+    /// The number of cells that the caller asked for.
+    pub fn requested(&self) -> NonZeroU32 {
+        self.requested
+    }
+
+    /// The number of cells that were let through: the requested number, or the rate
+    /// limiter's burst size (or, for partial-capacity checks, the available capacity) if that
+    /// is smaller.
+    pub fn admitted(&self) -> NonZeroU32 {
+        self.admitted
+    }
+
+    /// Returns true if fewer cells than requested were let through.
+    pub fn is_clamped(&self) -> bool {
+        self.admitted < self.requested
+    }
+
+    /// The positive outcome of the rate-limiting decision.
+    pub fn outcome(&self) -> &T {
+        &self.outcome
+    }
+
+    /// Returns the positive outcome of the rate-limiting decision.
+    pub fn into_outcome(self) -> T {
+        self.outcome
+    }
+}
+
+/// A positive decision for a cell whose capacity was reserved ahead of time.
+///
+/// Returned by [`RateLimiter::check_or_reject_if_wait_exceeds`] and its keyed equivalent: the
+/// cell conforms to the rate limit once [`wait`](#method.wait) has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation<T> {
+    wait: Duration,
+    outcome: T,
+}
+
+impl<T> Reservation<T> {
+    pub(crate) fn new(wait: Nanos, outcome: T) -> Self {
+        Reservation {
+            wait: wait.into(),
+            outcome,
+        }
+    }
+
+    /// How long to wait before the cell may proceed. Zero if it may proceed right away.
+    pub fn wait(&self) -> Duration {
+        self.wait
+    }
+
+    /// The positive outcome of the rate-limiting decision.
+    pub fn outcome(&self) -> &T {
+        &self.outcome
+    }
+
+    /// Returns the positive outcome of the rate-limiting decision.
+    pub fn into_outcome(self) -> T {
+        self.outcome
+    }
+}
+
 /// # Direct in-memory rate limiters - Constructors
 ///
 /// Here we construct an in-memory rate limiter that makes direct (un-keyed)
@@ -76,6 +162,7 @@ where
         self.gcra.test_and_update::<NotKeyed, C::Instant, S, MW>(
             self.start,
             &NotKeyed::NonKey,
+            self.key_format(),
             &self.state,
             self.clock.now(),
         )
@@ -103,16 +190,480 @@ where
             .test_n_all_and_update::<NotKeyed, C::Instant, S, MW>(
                 self.start,
                 &NotKeyed::NonKey,
+                self.key_format(),
+                n,
+                &self.state,
+                self.clock.now(),
+            )
+    }
+
+    /// Allow *only all* `n` cells through the rate limiter, if they would all conform within
+    /// `grace`.
+    ///
+    /// Like [`check_n`](#method.check_n), but a batch that exceeds the current capacity is let
+    /// through right away if it would fit after waiting for at most `grace`; it borrows that
+    /// capacity from the next replenishments (some HTTP APIs call this a burst smoothing
+    /// window). The rate limiter's state moves ahead as if the batch had waited, so later cells
+    /// have to wait until the borrowed capacity is paid back. A negative outcome tells when the
+    /// batch would be let through, including the grace period.
+    ///
+    /// Returns `InsufficientCapacity` if `n` exceeds the rate limiter's burst size: the grace
+    /// period doesn't raise the burst size.
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// use governor::{clock::FakeRelativeClock, Quota, RateLimiter};
+    ///
+    /// let lim = RateLimiter::direct_with_clock(
+    ///     Quota::per_second(nonzero!(4u32)),
+    ///     FakeRelativeClock::default(),
+    /// );
+    /// assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(3u32)));
+    /// // Only one cell is left, but the remaining two replenish within 500ms:
+    /// let grace = Duration::from_millis(500);
+    /// assert_eq!(Ok(Ok(())), lim.check_n_with_grace(nonzero!(3u32), grace));
+    /// assert!(lim.check().is_err());
+    /// ```
+    pub fn check_n_with_grace(
+        &self,
+        n: NonZeroU32,
+        grace: Duration,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        self.gcra
+            .test_n_all_with_grace_and_update::<NotKeyed, C::Instant, S, MW>(
+                self.start,
+                &NotKeyed::NonKey,
+                self.key_format(),
+                n,
+                Nanos::saturating_from(grace),
+                &self.state,
+                self.clock.now(),
+            )
+    }
+
+    /// Allow as many of `n` cells through the rate limiter as it could ever accommodate.
+    ///
+    /// Unlike [`check_n`](#method.check_n), this method does not fail if `n` exceeds the rate
+    /// limiter's burst size: it tests for (and lets through) only as many cells as fit into
+    /// the burst size instead. The positive result reports how many cells were requested and
+    /// admitted; a negative result indicates when the clamped number of cells could conform.
+    pub fn check_n_clamped(
+        &self,
+        n: NonZeroU32,
+    ) -> Result<Clamped<MW::PositiveOutcome>, MW::NegativeOutcome> {
+        let (admitted, decision) = self
+            .gcra
+            .test_n_clamped_and_update::<NotKeyed, C::Instant, S, MW>(
+                self.start,
+                &NotKeyed::NonKey,
+                self.key_format(),
+                n,
+                &self.state,
+                self.clock.now(),
+            );
+        decision.map(|outcome| Clamped::new(n, admitted, outcome))
+    }
+
+    /// Allow as many of `n` cells through the rate limiter as it can accommodate right now, as
+    /// long as that is at least `min` cells.
+    ///
+    /// This suits callers that can make use of partial capacity, e.g. a batcher that would
+    /// rather send a smaller batch now than wait for room for a full one. The positive result
+    /// reports how many cells were requested and admitted (at least `min`, at most `n` and the
+    /// burst size); a negative result indicates when `min` cells could conform.
+    ///
+    /// Returns `InsufficientCapacity` if `min` exceeds the rate limiter's burst size. If `n` is
+    /// less than `min`, `min` cells are tested instead.
+    pub fn check_at_least_n(
+        &self,
+        min: NonZeroU32,
+        n: NonZeroU32,
+    ) -> Result<Result<Clamped<MW::PositiveOutcome>, MW::NegativeOutcome>, InsufficientCapacity>
+    {
+        let decision = self
+            .gcra
+            .test_n_available_and_update::<NotKeyed, C::Instant, S, MW>(
+                self.start,
+                &NotKeyed::NonKey,
+                self.key_format(),
+                min,
                 n,
                 &self.state,
                 self.clock.now(),
+            )?;
+        Ok(decision.map(|(admitted, outcome)| Clamped::new(n, admitted, outcome)))
+    }
+
+    /// Allow cells that cost a fractional number of cells (e.g. `0.25`) through the rate
+    /// limiter, only if the whole cost can be accommodated.
+    ///
+    /// Costs are not rounded to whole cells on each call, which would systematically
+    /// overcharge (or undercharge) callers. Instead, the cost is added to the rate limiter's
+    /// state as the fraction of a cell's replenishment interval that it stands for, so
+    /// fractional remainders accumulate in the state: four checks of `0.25` take up exactly one
+    /// cell. That fraction of the interval is rounded down to a whole nanosecond, the
+    /// resolution of the rate limiter's state: This is the only rounding, it is the same for
+    /// every call with the same cost, and it makes sure that costs adding up to the burst size
+    /// always fit into it (e.g. three checks of `1.0 / 3.0`), at the price of undercharging
+    /// each call by less than a nanosecond's worth of a cell.
+    ///
+    /// Returns [`CostError::InsufficientCapacity`] if `cost` exceeds the rate limiter's burst
+    /// size, and [`CostError::InvalidCost`] if it is negative or NaN. Neither can ever succeed,
+    /// and neither consumes any capacity.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{Quota, RateLimiter};
+    ///
+    /// let lim = RateLimiter::direct(Quota::per_minute(nonzero!(1u32)));
+    /// for _ in 0..4 {
+    ///     assert!(lim.check_cost(0.25).unwrap().is_ok());
+    /// }
+    /// assert!(lim.check_cost(0.25).unwrap().is_err());
+    /// ```
+    pub fn check_cost(
+        &self,
+        cost: f64,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, CostError> {
+        self.gcra
+            .test_cost_and_update::<NotKeyed, C::Instant, S, MW>(
+                self.start,
+                &NotKeyed::NonKey,
+                self.key_format(),
+                cost,
+                &self.state,
+                self.clock.now(),
+            )
+    }
+}
+
+/// # Direct rate limiters - Checking and consuming cells separately
+///
+/// Sometimes, the decision whether to let cells through and the accounting for them need to
+/// happen at different points: e.g., a request handler may want to know whether it should
+/// start on expensive work, but only charge the rate limiter once that work actually happened.
+/// These methods split [`check`](#method.check) into two halves:
+///
+/// * [`check_only`](#method.check_only) / [`check_n_only`](#method.check_n_only) make the same
+///   decision as `check` / `check_n` would, but leave the rate limiter's state untouched.
+/// * [`consume`](#method.consume) / [`consume_n`](#method.consume_n) always count cells against
+///   the rate limiter and report whether they conformed.
+///
+/// ### Atomicity
+/// Each of these methods is atomic on its own, but a `check_only` followed by a `consume` is
+/// not: Other threads may use up capacity between the two calls. A positive `check_only`
+/// therefore does not guarantee that the following `consume` is positive too. Since `consume`
+/// counts its cells regardless, the rate limiter can be overdrawn by the cells of the
+/// `consume` calls that raced each other; cells checked afterwards are delayed until that
+/// overdraft has been replenished. Use [`check`](#method.check) if a decision and its
+/// accounting must not be separated.
+impl<S, C, MW> RateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Tests whether a single cell would be allowed through the rate limiter, without
+    /// consuming any capacity.
+    pub fn check_only(&self) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.gcra.test_peek::<NotKeyed, C::Instant, S, MW>(
+            self.start,
+            &NotKeyed::NonKey,
+            self.key_format(),
+            &self.state,
+            self.clock.now(),
+        )
+    }
+
+    /// Tests whether *all* `n` cells would be allowed through the rate limiter, without
+    /// consuming any capacity.
+    ///
+    /// The results correspond to those of [`check_n`](#method.check_n).
+    pub fn check_n_only(
+        &self,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        self.gcra.test_n_all_peek::<NotKeyed, C::Instant, S, MW>(
+            self.start,
+            &NotKeyed::NonKey,
+            self.key_format(),
+            n,
+            &self.state,
+            self.clock.now(),
+        )
+    }
+
+    /// Counts a single cell against the rate limiter, whether or not it conforms.
+    ///
+    /// If the cell did not conform, the negative outcome indicates when the next cell could be
+    /// allowed through.
+    pub fn consume(&self) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.gcra.consume::<NotKeyed, C::Instant, S, MW>(
+            self.start,
+            &NotKeyed::NonKey,
+            self.key_format(),
+            &self.state,
+            self.clock.now(),
+        )
+    }
+
+    /// Counts `n` cells against the rate limiter, whether or not they conform.
+    ///
+    /// Returns `Err(InsufficientCapacity)` without counting anything if the quota's burst size
+    /// is too low for `n` cells to ever be allowed through. Otherwise, like
+    /// [`consume`](#method.consume), the inner result tells whether the cells conformed.
+    pub fn consume_n(
+        &self,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        self.gcra.consume_n::<NotKeyed, C::Instant, S, MW>(
+            self.start,
+            &NotKeyed::NonKey,
+            self.key_format(),
+            n,
+            &self.state,
+            self.clock.now(),
+        )
+    }
+}
+
+/// # Direct rate limiters - Rejecting long waits
+///
+/// These methods implement "queue if the wait is short, shed load if it is long" without a race
+/// between checking how long a cell would have to wait and waiting for it: A cell that has to
+/// wait no longer than a threshold gets its capacity reserved right away, so no other cell can
+/// take it in the meantime.
+impl<S, C, MW> RateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Reserves a single cell, if it would conform to the rate limit within `threshold`.
+    ///
+    /// If the cell would have to wait longer than `threshold`, it is rejected right away
+    /// without consuming any capacity, and the negative outcome tells when it would conform.
+    /// Otherwise, the returned [`Reservation`] tells how long the caller needs to wait before
+    /// it proceeds. With `std`,
+    /// [`until_ready_or_reject_if_wait_exceeds`](#method.until_ready_or_reject_if_wait_exceeds)
+    /// also waits for that long.
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// use governor::{clock::FakeRelativeClock, Quota, RateLimiter};
+    ///
+    /// let clock = FakeRelativeClock::default();
+    /// let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock);
+    /// let threshold = Duration::from_millis(600);
+    /// assert_eq!(lim.check_or_reject_if_wait_exceeds(threshold).unwrap().wait(), Duration::ZERO);
+    /// assert_eq!(lim.check_or_reject_if_wait_exceeds(threshold).unwrap().wait(), Duration::ZERO);
+    /// assert_eq!(
+    ///     lim.check_or_reject_if_wait_exceeds(threshold).unwrap().wait(),
+    ///     Duration::from_millis(500)
+    /// );
+    /// // The next cell would have to wait for a whole second:
+    /// assert!(lim.check_or_reject_if_wait_exceeds(threshold).is_err());
+    /// ```
+    pub fn check_or_reject_if_wait_exceeds(
+        &self,
+        threshold: Duration,
+    ) -> Result<Reservation<MW::PositiveOutcome>, MW::NegativeOutcome> {
+        self.gcra
+            .reserve_within::<NotKeyed, C::Instant, S, MW>(
+                self.start,
+                &NotKeyed::NonKey,
+                self.key_format(),
+                Nanos::saturating_from(threshold),
+                &self.state,
+                self.clock.now(),
             )
+            .map(|(wait, outcome)| Reservation::new(wait, outcome))
+    }
+}
+
+/// # Direct rate limiters - Planning ahead
+impl<S, C, MW> RateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the times at which each of the next `n` cells would be allowed through, if no
+    /// other cells were checked in the meantime.
+    ///
+    /// This does not consume any capacity; it is meant for planning a schedule ahead of time,
+    /// e.g. when pacing packets or emails. Cells that could be allowed through right away get
+    /// the current time.
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// use governor::{
+    ///     clock::{Clock, FakeRelativeClock, Reference},
+    ///     Quota, RateLimiter,
+    /// };
+    ///
+    /// let clock = FakeRelativeClock::default();
+    /// let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    /// let times = lim.next_allowed_times(3);
+    /// assert_eq!(times[..2], [clock.now(), clock.now()]);
+    /// assert_eq!(
+    ///     Duration::from(times[2].duration_since(clock.now())),
+    ///     Duration::from_millis(500)
+    /// );
+    /// // Nothing was consumed:
+    /// assert!(lim.check().is_ok());
+    /// ```
+    pub fn next_allowed_times(&self, n: usize) -> Vec<C::Instant> {
+        self.gcra.projected_times(
+            self.start,
+            &NotKeyed::NonKey,
+            n,
+            &self.state,
+            self.clock.now(),
+        )
+    }
+}
+
+/// # Direct rate limiters - Idle detection
+///
+/// These methods tell how long a rate limiter has gone without letting cells through, e.g. to
+/// scale down the workers that it paces. They are computed from the rate limiter's state, so
+/// they never report more idle time than has actually passed, but may report less if cells
+/// were let through in a burst. A rate limiter that never let any cells through has been idle
+/// since its [start](#method.start).
+impl<S, C, MW> RateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns how long the rate limiter has gone without letting any cells through.
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// use governor::{clock::FakeRelativeClock, Quota, RateLimiter};
+    ///
+    /// let clock = FakeRelativeClock::default();
+    /// let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    /// lim.check().unwrap();
+    /// clock.advance(Duration::from_secs(3));
+    /// assert_eq!(lim.idle_for(), Duration::from_secs(3));
+    /// assert!(lim.is_idle(Duration::from_secs(2)));
+    /// ```
+    pub fn idle_for(&self) -> Duration {
+        self.gcra
+            .idle_time(self.start, &NotKeyed::NonKey, &self.state, self.clock.now())
+            .into()
+    }
+
+    /// Returns `true` if the rate limiter hasn't let any cells through for at least `threshold`.
+    pub fn is_idle(&self, threshold: Duration) -> bool {
+        self.idle_for() >= threshold
+    }
+}
+
+/// # Direct rate limiters - Available capacity
+impl<S, C, MW> RateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the number of cells that the rate limiter could let through right now,
+    /// including the fraction of the next cell that has been replenished so far (e.g. `2.7`).
+    ///
+    /// This doesn't consume any capacity; it is meant for displaying the rate limiter's state,
+    /// e.g. in a UI. See
+    /// [`StateSnapshot::available_cells_f64`](crate::middleware::StateSnapshot::available_cells_f64).
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// # use std::time::Duration;
+    /// use governor::{clock::FakeRelativeClock, Quota, RateLimiter};
+    ///
+    /// let clock = FakeRelativeClock::default();
+    /// let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(4u32)), clock.clone());
+    /// assert_eq!(lim.available_cells_f64(), 4.0);
+    /// lim.check_n(nonzero!(4u32)).unwrap().unwrap();
+    /// clock.advance(Duration::from_millis(300));
+    /// assert_eq!(lim.available_cells_f64(), 1.2);
+    /// ```
+    pub fn available_cells_f64(&self) -> f64 {
+        self.gcra
+            .available_cells(self.start, &NotKeyed::NonKey, &self.state, self.clock.now())
+    }
+}
+
+/// # Direct rate limiters - Checking cells without middleware overhead
+impl<S, C> RateLimiter<NotKeyed, S, C, NoOpMiddleware<C::Instant>>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+{
+    /// Allow a single cell through the rate limiter, taking the shortest possible path
+    /// through the rate limiter's code.
+    ///
+    /// This returns the same results as [`check`](#method.check), but skips constructing the
+    /// state snapshot that the [`NoOpMiddleware`] would ignore on positive decisions. It can
+    /// make a measurable difference on the hottest of code paths.
+    pub fn check_bare(&self) -> Result<(), NotUntil<C::Instant>> {
+        self.gcra.test_and_update_bare::<NotKeyed, C::Instant, S>(
+            self.start,
+            &NotKeyed::NonKey,
+            &self.state,
+            self.clock.now(),
+        )
+    }
+
+    /// Allow a single cell through the rate limiter, returning only whether it was let
+    /// through.
+    ///
+    /// This makes the same decisions as [`check`](#method.check) and
+    /// [`check_bare`](#method.check_bare), but doesn't construct a [`NotUntil`] (along with the
+    /// state snapshot in it) for negative decisions either. Callers that ignore when the cell
+    /// could conform, e.g. to drop traffic that exceeds the rate limit, save that cost on
+    /// every denial.
+    pub fn check_fast(&self) -> bool {
+        self.gcra.test_and_update_fast::<NotKeyed, C::Instant, S>(
+            self.start,
+            &NotKeyed::NonKey,
+            &self.state,
+            self.clock.now(),
+        )
     }
 }
 
+mod const_quota;
+pub use const_quota::ConstQuotaRateLimiter;
+
+#[cfg(feature = "std")]
+mod dynamic;
+#[cfg(feature = "std")]
+pub use dynamic::DirectLimiter;
+
 #[cfg(feature = "std")]
 mod future;
 
+#[cfg(feature = "std")]
+mod striped;
+#[cfg(feature = "std")]
+pub use striped::StripedDirectLimiter;
+
+#[cfg(feature = "std")]
+mod iterators;
+#[cfg(feature = "std")]
+pub use iterators::*;
+
+#[cfg(feature = "std")]
+mod permits;
+#[cfg(feature = "std")]
+pub use permits::Permits;
+
 #[cfg(feature = "std")]
 mod sinks;
 #[cfg(feature = "std")]
@@ -0,0 +1,190 @@
+use std::prelude::v1::*;
+
+use std::cmp;
+use std::fmt;
+use std::marker::PhantomData;
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use crate::{
+    clock::{self, Reference},
+    errors::InsufficientCapacity,
+    middleware::{KeyFormat, NoOpMiddleware, RateLimitingMiddleware, StateSnapshot},
+    nanos::Nanos,
+    state::{InMemoryState, NotKeyed},
+    Quota,
+};
+
+/// A direct rate limiter whose quota is fixed at compile time.
+///
+/// The limiter lets through `CELLS` cells per `PERIOD_NS` nanoseconds, with a burst size of
+/// `CELLS` - just like a [direct rate limiter](crate::RateLimiter::direct) for a quota from
+/// [`Quota::per_second`], [`Quota::per_minute`] or [`Quota::with_period`] does. Since the
+/// quota is part of the type, the limiter doesn't store the GCRA parameters that a
+/// [`RateLimiter`](crate::RateLimiter) keeps around: it is only as large as its state (a
+/// single `u64`), its clock and its start time, and the compiler can fold the parameters into
+/// the code that checks cells. This is mostly useful on tiny embedded targets.
+///
+/// Quotas that can't be expressed this way (`CELLS` being zero, or the period being shorter
+/// than one nanosecond per cell) fail to compile. Quota features that need more state, like
+/// cooldowns and burst recharge delays, aren't available.
+///
+/// # Example
+/// ```rust
+/// use governor::{clock::FakeRelativeClock, state::direct::ConstQuotaRateLimiter};
+/// use std::time::Duration;
+///
+/// // 5 cells per second:
+/// let clock = FakeRelativeClock::default();
+/// let lim = ConstQuotaRateLimiter::<5, 1_000_000_000, _>::with_clock(clock.clone());
+/// for _ in 0..5 {
+///     assert!(lim.check().is_ok());
+/// }
+/// assert!(lim.check().is_err());
+///
+/// clock.advance(Duration::from_millis(200));
+/// assert!(lim.check().is_ok());
+/// ```
+///
+/// A quota that lets no cells through doesn't compile:
+/// ```rust,compile_fail
+/// use governor::{clock::FakeRelativeClock, state::direct::ConstQuotaRateLimiter};
+///
+/// let lim = ConstQuotaRateLimiter::<0, 1_000_000_000, _>::with_clock(FakeRelativeClock::default());
+/// ```
+pub struct ConstQuotaRateLimiter<
+    const CELLS: u32,
+    const PERIOD_NS: u64,
+    C: clock::Clock = clock::DefaultClock,
+    MW: RateLimitingMiddleware<C::Instant> = NoOpMiddleware<<C as clock::Clock>::Instant>,
+> {
+    state: InMemoryState,
+    clock: C,
+    start: C::Instant,
+    middleware: PhantomData<MW>,
+}
+
+impl<const CELLS: u32, const PERIOD_NS: u64, C, MW> ConstQuotaRateLimiter<CELLS, PERIOD_NS, C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// The interval at which a cell gets replenished, in nanoseconds.
+    const T: u64 = {
+        assert!(CELLS > 0, "a quota must allow at least one cell");
+        assert!(
+            PERIOD_NS >= CELLS as u64,
+            "a quota can replenish at most one cell per nanosecond"
+        );
+        PERIOD_NS / CELLS as u64
+    };
+
+    /// How far ahead of the current time the theoretical arrival time may get, in nanoseconds.
+    const TAU: u64 = Self::T * (CELLS as u64 - 1);
+
+    /// Constructs a rate limiter with a custom clock.
+    pub fn with_clock(clock: C) -> Self {
+        // Evaluating the parameters rejects invalid quotas at compile time:
+        let _ = (Self::T, Self::TAU);
+        let start = clock.now();
+        ConstQuotaRateLimiter {
+            state: InMemoryState::default(),
+            clock,
+            start,
+            middleware: PhantomData,
+        }
+    }
+
+    /// Returns the quota that the rate limiter's parameters describe.
+    pub fn quota(&self) -> Quota {
+        Quota::with_period(Duration::from_nanos(Self::T))
+            .expect("the replenishment interval is nonzero")
+            .allow_burst(NonZeroU32::new(CELLS).expect("the burst size is nonzero"))
+    }
+
+    /// Returns a reference to the clock.
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Allow a single cell through the rate limiter.
+    ///
+    /// If the rate limit is reached, `check` returns information about the earliest
+    /// time that a cell might be allowed through again.
+    pub fn check(&self) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.test_and_update(Nanos::from(0))
+    }
+
+    /// Allow *only all* `n` cells through the rate limiter.
+    ///
+    /// Like [`RateLimiter::check_n`](crate::RateLimiter::check_n), this returns
+    /// [`InsufficientCapacity`] if `n` exceeds the burst size, and otherwise whether all `n`
+    /// cells were let through.
+    pub fn check_n(
+        &self,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        if n.get() > CELLS {
+            return Err(InsufficientCapacity(CELLS));
+        }
+        Ok(self.test_and_update(Nanos::from(Self::T * u64::from(n.get() - 1))))
+    }
+
+    /// Tests cells weighing `additional_weight` in addition to the first one against the state,
+    /// updating it if they conform.
+    #[inline]
+    fn test_and_update(
+        &self,
+        additional_weight: Nanos,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let start = self.start;
+        let t0 = self.clock.now().duration_since(start);
+        let t = Nanos::from(Self::T);
+        let tau = Nanos::from(Self::TAU);
+        let key = KeyFormat::redacted().show(&NotKeyed::NonKey);
+        self.state.measure_and_replace_one(|tat| {
+            let tat = tat.unwrap_or(t0);
+            let earliest_time = (tat + additional_weight).saturating_sub(tau);
+            if t0 < earliest_time {
+                Err(MW::disallow(
+                    &key,
+                    StateSnapshot::denied(t, tau, t0, earliest_time),
+                    start,
+                ))
+            } else {
+                let next = cmp::max(tat, t0) + t + additional_weight;
+                Ok((MW::allow(&key, StateSnapshot::new(t, tau, t0, next)), next))
+            }
+        })
+    }
+}
+
+impl<const CELLS: u32, const PERIOD_NS: u64> ConstQuotaRateLimiter<CELLS, PERIOD_NS> {
+    /// Constructs a rate limiter with the default clock.
+    pub fn new() -> Self {
+        Self::with_clock(clock::DefaultClock::default())
+    }
+}
+
+impl<const CELLS: u32, const PERIOD_NS: u64> Default for ConstQuotaRateLimiter<CELLS, PERIOD_NS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CELLS: u32, const PERIOD_NS: u64, C, MW> fmt::Debug
+    for ConstQuotaRateLimiter<CELLS, PERIOD_NS, C, MW>
+where
+    C: clock::Clock + fmt::Debug,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConstQuotaRateLimiter")
+            .field("cells", &CELLS)
+            .field("period_ns", &PERIOD_NS)
+            .field("state", &self.state)
+            .field("clock", &self.clock)
+            .field("start", &self.start)
+            .finish()
+    }
+}
@@ -0,0 +1,117 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_core::future::BoxFuture;
+
+use crate::{
+    clock,
+    errors::InsufficientCapacity,
+    middleware::RateLimitingMiddleware,
+    state::{DirectStateStore, NotKeyed},
+    NotUntil, RateLimiter,
+};
+
+/// An object-safe interface to direct rate limiters.
+///
+/// [`RateLimiter`] has four type parameters, which code that merely
+/// wants to use "some rate limiter" would have to be generic over,
+/// too. This trait hides them: Any direct rate limiter running on a
+/// [real-time clock][clock::ReasonablyRealtime] whose middleware
+/// returns [`NotUntil`] on negative decisions implements it, so it can
+/// be stored as a `Box<dyn DirectLimiter>` or `Arc<dyn DirectLimiter>`.
+///
+/// Positive outcomes of the rate limiter's middleware are discarded;
+/// negative outcomes are converted to the time to wait from now.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{DirectLimiter, Quota, RateLimiter};
+///
+/// struct Plugin {
+///     limiter: Box<dyn DirectLimiter>,
+/// }
+///
+/// let plugin = Plugin {
+///     limiter: Box::new(RateLimiter::direct(Quota::per_second(nonzero!(1u32)))),
+/// };
+/// assert_eq!(Ok(()), plugin.limiter.check());
+/// assert!(plugin.limiter.check().is_err());
+/// ```
+pub trait DirectLimiter: Send + Sync {
+    /// Allow a single cell through the rate limiter.
+    ///
+    /// If the rate limit is reached, returns the minimum amount of
+    /// time to wait before a cell might be allowed through.
+    fn check(&self) -> Result<(), Duration>;
+
+    /// Allow *only all* `n` cells through the rate limiter.
+    ///
+    /// See [`RateLimiter::check_n`] for the results.
+    fn check_n(&self, n: NonZeroU32) -> Result<Result<(), Duration>, InsufficientCapacity>;
+
+    /// Asynchronously resolves as soon as the rate limiter allows a
+    /// single cell through.
+    fn until_ready(&self) -> BoxFuture<'_, ()>;
+
+    /// Asynchronously resolves as soon as the rate limiter allows `n`
+    /// cells through.
+    ///
+    /// Resolves to `InsufficientCapacity` if `n` exceeds the rate
+    /// limiter's burst size.
+    fn until_n_ready(&self, n: NonZeroU32) -> BoxFuture<'_, Result<(), InsufficientCapacity>>;
+}
+
+impl<S, C, MW> DirectLimiter for RateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore + Send + Sync,
+    C: clock::ReasonablyRealtime + Send + Sync,
+    C::Instant: Send + Sync,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>> + Send + Sync,
+    MW::PositiveOutcome: Send,
+{
+    fn check(&self) -> Result<(), Duration> {
+        RateLimiter::check(self)
+            .map(|_| ())
+            .map_err(|negative| negative.wait_time_from(self.clock.now()))
+    }
+
+    fn check_n(&self, n: NonZeroU32) -> Result<Result<(), Duration>, InsufficientCapacity> {
+        Ok(RateLimiter::check_n(self, n)?
+            .map(|_| ())
+            .map_err(|negative| negative.wait_time_from(self.clock.now())))
+    }
+
+    fn until_ready(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            RateLimiter::until_ready(self).await;
+        })
+    }
+
+    fn until_n_ready(&self, n: NonZeroU32) -> BoxFuture<'_, Result<(), InsufficientCapacity>> {
+        Box::pin(async move { RateLimiter::until_n_ready(self, n).await.map(|_| ()) })
+    }
+}
+
+impl<L: DirectLimiter + ?Sized> DirectLimiter for Arc<L> {
+    fn check(&self) -> Result<(), Duration> {
+        (**self).check()
+    }
+
+    fn check_n(&self, n: NonZeroU32) -> Result<Result<(), Duration>, InsufficientCapacity> {
+        (**self).check_n(n)
+    }
+
+    fn until_ready(&self) -> BoxFuture<'_, ()> {
+        (**self).until_ready()
+    }
+
+    fn until_n_ready(&self, n: NonZeroU32) -> BoxFuture<'_, Result<(), InsufficientCapacity>> {
+        (**self).until_n_ready(n)
+    }
+}
@@ -1,21 +1,21 @@
 use std::num::NonZeroU32;
+use std::time::Duration;
 
-use super::RateLimiter;
+use super::{Clamped, RateLimiter};
 use crate::{
     clock,
     errors::InsufficientCapacity,
     middleware::RateLimitingMiddleware,
-    state::{DirectStateStore, NotKeyed},
+    state::{DirectStateStore, NotKeyed, Waiting},
     Jitter, NotUntil,
 };
-use futures_timer::Delay;
 
 #[cfg(feature = "std")]
 /// # Direct rate limiters - `async`/`await`
 impl<S, C, MW> RateLimiter<NotKeyed, S, C, MW>
 where
     S: DirectStateStore,
-    C: clock::ReasonablyRealtime,
+    C: clock::DelayProvider,
     MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
 {
     /// Asynchronously resolves as soon as the rate limiter allows it.
@@ -25,10 +25,12 @@ where
     /// is polled again. This means that the future might resolve at some later time (depending
     /// on what other measurements are made on the rate limiter).
     ///
-    /// If multiple futures are dispatched against the rate limiter, it is advisable to use
+    /// The wait period gets the rate limiter's default [`jitter`](#method.jitter) added. If
+    /// multiple futures are dispatched against the rate limiter, it is advisable to set one
+    /// with [`with_jitter`](#method.with_jitter), or to use
     /// [`until_ready_with_jitter`](#method.until_ready_with_jitter), to avoid thundering herds.
     pub async fn until_ready(&self) -> MW::PositiveOutcome {
-        self.until_ready_with_jitter(Jitter::NONE).await
+        self.until_ready_with_jitter(self.jitter.clone()).await
     }
 
     /// Asynchronously resolves as soon as the rate limiter allows it, with a randomized wait
@@ -43,19 +45,44 @@ where
     /// which can help reduce the likelihood of thundering herd effects if multiple tasks try to
     /// wait on the same rate limiter.
     pub async fn until_ready_with_jitter(&self, jitter: Jitter) -> MW::PositiveOutcome {
+        let mut waiting = Waiting::new(&self.state, &NotKeyed::NonKey);
         loop {
             match self.check() {
                 Ok(x) => {
                     return x;
                 }
                 Err(negative) => {
-                    let delay = Delay::new(jitter + negative.wait_time_from(self.clock.now()));
+                    waiting.start();
+                    let delay = self
+                        .clock
+                        .delay(jitter.clone() + negative.wait_time_from(self.clock.now()));
                     delay.await;
                 }
             }
         }
     }
 
+    /// Asynchronously resolves as soon as the rate limiter allows it, unless that would take
+    /// longer than `threshold`.
+    ///
+    /// If a cell would have to wait longer than `threshold`, resolves right away to the
+    /// negative outcome, without consuming any capacity. Otherwise, reserves the cell's
+    /// capacity (see [`check_or_reject_if_wait_exceeds`](#method.check_or_reject_if_wait_exceeds))
+    /// and resolves once the cell conforms. Unlike [`until_ready`](#method.until_ready), this
+    /// does not need to poll the rate limiter again after waiting.
+    pub async fn until_ready_or_reject_if_wait_exceeds(
+        &self,
+        threshold: Duration,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let reservation = self.check_or_reject_if_wait_exceeds(threshold)?;
+        if reservation.wait() > Duration::ZERO {
+            let mut waiting = Waiting::new(&self.state, &NotKeyed::NonKey);
+            waiting.start();
+            self.clock.delay(reservation.wait()).await;
+        }
+        Ok(reservation.into_outcome())
+    }
+
     /// Asynchronously resolves as soon as the rate limiter allows it.
     ///
     /// This is similar to `until_ready` except it waits for an abitrary number
@@ -67,7 +94,7 @@ where
         &self,
         n: NonZeroU32,
     ) -> Result<MW::PositiveOutcome, InsufficientCapacity> {
-        self.until_n_ready_with_jitter(n, Jitter::NONE).await
+        self.until_n_ready_with_jitter(n, self.jitter.clone()).await
     }
 
     /// Asynchronously resolves as soon as the rate limiter allows it, with a
@@ -83,13 +110,100 @@ where
         n: NonZeroU32,
         jitter: Jitter,
     ) -> Result<MW::PositiveOutcome, InsufficientCapacity> {
+        let mut waiting = Waiting::new(&self.state, &NotKeyed::NonKey);
         loop {
             match self.check_n(n)? {
                 Ok(x) => {
                     return Ok(x);
                 }
                 Err(negative) => {
-                    let delay = Delay::new(jitter + negative.wait_time_from(self.clock.now()));
+                    waiting.start();
+                    let delay = self
+                        .clock
+                        .delay(jitter.clone() + negative.wait_time_from(self.clock.now()));
+                    delay.await;
+                }
+            }
+        }
+    }
+
+    /// Asynchronously resolves as soon as the rate limiter allows as many of `n` cells as
+    /// it could ever accommodate.
+    ///
+    /// This is the asynchronous counterpart to
+    /// [`check_n_clamped`](#method.check_n_clamped): Instead of returning
+    /// `InsufficientCapacity` if `n` exceeds the rate limiter's burst size, it waits until
+    /// the burst size's worth of cells is available.
+    pub async fn until_n_ready_clamped(&self, n: NonZeroU32) -> Clamped<MW::PositiveOutcome> {
+        self.until_n_ready_clamped_with_jitter(n, self.jitter.clone())
+            .await
+    }
+
+    /// Asynchronously resolves as soon as the rate limiter allows as many of `n` cells as
+    /// it could ever accommodate, with a randomized wait period.
+    ///
+    /// See [`until_n_ready_clamped`](#method.until_n_ready_clamped).
+    pub async fn until_n_ready_clamped_with_jitter(
+        &self,
+        n: NonZeroU32,
+        jitter: Jitter,
+    ) -> Clamped<MW::PositiveOutcome> {
+        let mut waiting = Waiting::new(&self.state, &NotKeyed::NonKey);
+        loop {
+            match self.check_n_clamped(n) {
+                Ok(x) => {
+                    return x;
+                }
+                Err(negative) => {
+                    waiting.start();
+                    let delay = self
+                        .clock
+                        .delay(jitter.clone() + negative.wait_time_from(self.clock.now()));
+                    delay.await;
+                }
+            }
+        }
+    }
+
+    /// Asynchronously resolves as soon as at least `min` of `n` cells are available, letting
+    /// through as many of the `n` cells as the rate limiter can accommodate at that time.
+    ///
+    /// This is the asynchronous counterpart to
+    /// [`check_at_least_n`](#method.check_at_least_n); the result's
+    /// [`admitted`](Clamped::admitted) count says how many cells were granted. Adaptive
+    /// batchers can use it to send whatever fits as soon as a minimum batch size is possible.
+    ///
+    /// Returns `InsufficientCapacity` if `min` exceeds the rate limiter's burst size.
+    pub async fn until_at_least_n_ready(
+        &self,
+        min: NonZeroU32,
+        n: NonZeroU32,
+    ) -> Result<Clamped<MW::PositiveOutcome>, InsufficientCapacity> {
+        self.until_at_least_n_ready_with_jitter(min, n, self.jitter.clone())
+            .await
+    }
+
+    /// Asynchronously resolves as soon as at least `min` of `n` cells are available, with a
+    /// randomized wait period.
+    ///
+    /// See [`until_at_least_n_ready`](#method.until_at_least_n_ready).
+    pub async fn until_at_least_n_ready_with_jitter(
+        &self,
+        min: NonZeroU32,
+        n: NonZeroU32,
+        jitter: Jitter,
+    ) -> Result<Clamped<MW::PositiveOutcome>, InsufficientCapacity> {
+        let mut waiting = Waiting::new(&self.state, &NotKeyed::NonKey);
+        loop {
+            match self.check_at_least_n(min, n)? {
+                Ok(x) => {
+                    return Ok(x);
+                }
+                Err(negative) => {
+                    waiting.start();
+                    let delay = self
+                        .clock
+                        .delay(jitter.clone() + negative.wait_time_from(self.clock.now()));
                     delay.await;
                 }
             }
@@ -104,6 +218,7 @@ mod test {
     use super::*;
 
     #[test]
+    #[allow(clippy::clone_on_copy)]
     fn insufficient_capacity_impl_coverage() {
         let i = InsufficientCapacity(1);
         assert_eq!(i.0, i.clone().0);
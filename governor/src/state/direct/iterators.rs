@@ -0,0 +1,217 @@
+use std::prelude::v1::*;
+
+use crate::{clock, Jitter, NotUntil, RateLimiter};
+use crate::{
+    middleware::RateLimitingMiddleware,
+    state::{DirectStateStore, NotKeyed},
+};
+use std::iter::FusedIterator;
+use std::sync::Arc;
+use std::thread;
+
+use super::streams::LimiterRef;
+
+/// Allows pacing an [`Iterator`] with a rate limiter, blocking the current thread.
+///
+/// This is the synchronous counterpart of
+/// [`StreamRateLimitExt`](crate::prelude::StreamRateLimitExt), for code (e.g. batch jobs or
+/// migration scripts) that has no async runtime to run a [`Stream`](futures_core::Stream) on.
+pub trait IteratorRateLimitExt<'a>: Iterator {
+    /// Limits the rate at which the iterator produces items.
+    ///
+    /// Each call to [`next`](Iterator::next) takes an item from the underlying iterator, and then
+    /// sleeps until the rate limiter allows a cell through before returning it.
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{prelude::*, Quota, RateLimiter};
+    ///
+    /// let lim = RateLimiter::direct(Quota::per_second(nonzero!(1000u32)));
+    /// let paced: Vec<_> = (0..3).ratelimit(&lim).collect();
+    /// assert_eq!(paced, [0, 1, 2]);
+    /// ```
+    fn ratelimit<
+        D: DirectStateStore,
+        C: clock::ReasonablyRealtime,
+        MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+    >(
+        self,
+        limiter: &'a RateLimiter<NotKeyed, D, C, MW>,
+    ) -> RatelimitedIter<'a, Self, D, C, MW>
+    where
+        Self: Sized;
+
+    /// Limits the rate at which the iterator produces items, with a randomized wait period.
+    fn ratelimit_with_jitter<
+        D: DirectStateStore,
+        C: clock::ReasonablyRealtime,
+        MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+    >(
+        self,
+        limiter: &'a RateLimiter<NotKeyed, D, C, MW>,
+        jitter: Jitter,
+    ) -> RatelimitedIter<'a, Self, D, C, MW>
+    where
+        Self: Sized;
+
+    /// Limits the rate at which the iterator produces items, using a shared rate limiter.
+    ///
+    /// Unlike [`ratelimit`](#tymethod.ratelimit), the resulting iterator holds on to the rate
+    /// limiter instead of borrowing it, so it can be `'static` (e.g., to be moved to another
+    /// thread).
+    fn ratelimit_owned<
+        D: DirectStateStore,
+        C: clock::ReasonablyRealtime,
+        MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+    >(
+        self,
+        limiter: Arc<RateLimiter<NotKeyed, D, C, MW>>,
+    ) -> RatelimitedIter<'static, Self, D, C, MW>
+    where
+        Self: Sized;
+}
+
+impl<'a, I: Iterator> IteratorRateLimitExt<'a> for I {
+    fn ratelimit<
+        D: DirectStateStore,
+        C: clock::ReasonablyRealtime,
+        MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+    >(
+        self,
+        limiter: &'a RateLimiter<NotKeyed, D, C, MW>,
+    ) -> RatelimitedIter<'a, Self, D, C, MW>
+    where
+        Self: Sized,
+    {
+        self.ratelimit_with_jitter(limiter, Jitter::NONE)
+    }
+
+    fn ratelimit_with_jitter<
+        D: DirectStateStore,
+        C: clock::ReasonablyRealtime,
+        MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+    >(
+        self,
+        limiter: &'a RateLimiter<NotKeyed, D, C, MW>,
+        jitter: Jitter,
+    ) -> RatelimitedIter<'a, Self, D, C, MW>
+    where
+        Self: Sized,
+    {
+        RatelimitedIter {
+            inner: self,
+            limiter: LimiterRef::Borrowed(limiter),
+            jitter,
+        }
+    }
+
+    fn ratelimit_owned<
+        D: DirectStateStore,
+        C: clock::ReasonablyRealtime,
+        MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+    >(
+        self,
+        limiter: Arc<RateLimiter<NotKeyed, D, C, MW>>,
+    ) -> RatelimitedIter<'static, Self, D, C, MW>
+    where
+        Self: Sized,
+    {
+        RatelimitedIter {
+            inner: self,
+            limiter: LimiterRef::Owned(limiter),
+            jitter: Jitter::NONE,
+        }
+    }
+}
+
+/// An [`Iterator`] combinator which limits the rate at which items are produced, by blocking
+/// the current thread.
+///
+/// This is produced by the methods of [`IteratorRateLimitExt`].
+pub struct RatelimitedIter<
+    'a,
+    I: Iterator,
+    D: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+> {
+    inner: I,
+    limiter: LimiterRef<'a, D, C, MW>,
+    jitter: Jitter,
+}
+
+impl<I, D, C, MW> RatelimitedIter<'_, I, D, C, MW>
+where
+    I: Iterator,
+    D: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the rate limiter that this combinator checks.
+    pub fn limiter(&self) -> &RateLimiter<NotKeyed, D, C, MW> {
+        &self.limiter
+    }
+
+    /// Returns the jitter that this combinator adds to its wait periods.
+    pub fn jitter(&self) -> &Jitter {
+        &self.jitter
+    }
+
+    /// Changes the jitter that this combinator adds to its wait periods.
+    ///
+    /// The new jitter applies from the next time the combinator needs to wait.
+    pub fn set_jitter(&mut self, jitter: Jitter) {
+        self.jitter = jitter;
+    }
+
+    /// Acquires a reference to the underlying iterator.
+    pub fn get_ref(&self) -> &I {
+        &self.inner
+    }
+
+    /// Acquires a mutable reference to the underlying iterator.
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.inner
+    }
+
+    /// Consumes this combinator, returning the underlying iterator.
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I, D, C, MW> Iterator for RatelimitedIter<'_, I, D, C, MW>
+where
+    I: Iterator,
+    D: DirectStateStore,
+    C: clock::ReasonablyRealtime,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next()?;
+        loop {
+            let reference = self.limiter.reference_reading();
+            match self.limiter.check() {
+                Ok(_) => return Some(item),
+                Err(negative) => {
+                    thread::sleep(negative.wait_time_with_offset(reference, &self.jitter));
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<I, D, C, MW> FusedIterator for RatelimitedIter<'_, I, D, C, MW>
+where
+    I: FusedIterator,
+    D: DirectStateStore,
+    C: clock::ReasonablyRealtime,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+{
+}
@@ -0,0 +1,121 @@
+use std::prelude::v1::*;
+
+use crate::{clock, Jitter, NotUntil, RateLimiter};
+use crate::{
+    middleware::RateLimitingMiddleware,
+    state::{DirectStateStore, NotKeyed},
+    timer::Delay,
+};
+use futures_core::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// # Direct rate limiters - Streams of permits
+impl<D, C, MW> RateLimiter<NotKeyed, D, C, MW>
+where
+    D: DirectStateStore,
+    C: clock::ReasonablyRealtime,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+{
+    /// Returns a [`Stream`] that yields a positive outcome for each cell that the rate limiter
+    /// allows through, as soon as it allows it.
+    ///
+    /// The stream never ends: Each time it is polled, it checks the rate limiter, and waits
+    /// until the rate limiter allows the next cell through if it doesn't yet. This makes it a
+    /// ticker for pacing producers, which lets bursts through at the start and then yields at
+    /// the quota's replenishment rate. Cells that the stream doesn't get polled for aren't
+    /// checked, so they don't use up any capacity. The stream's wait periods get the rate
+    /// limiter's default [`jitter`](#method.jitter) added, until it is given another one.
+    ///
+    /// ```rust
+    /// # use futures_executor::block_on;
+    /// # use futures_util::StreamExt;
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{Quota, RateLimiter};
+    ///
+    /// let lim = RateLimiter::direct(Quota::per_second(nonzero!(100u32)));
+    /// let jobs = block_on(lim.permits().take(3).count());
+    /// assert_eq!(jobs, 3);
+    /// ```
+    pub fn permits(&self) -> Permits<'_, D, C, MW> {
+        Permits {
+            limiter: self,
+            delay: Delay::new(Duration::new(0, 0)),
+            jitter: self.jitter.clone(),
+            waiting: false,
+        }
+    }
+}
+
+/// A [`Stream`] which yields a positive outcome each time its rate limiter allows a cell through.
+///
+/// This is produced by [`RateLimiter::permits`].
+pub struct Permits<'a, D: DirectStateStore, C: clock::Clock, MW: RateLimitingMiddleware<C::Instant>>
+{
+    limiter: &'a RateLimiter<NotKeyed, D, C, MW>,
+    delay: Delay,
+    jitter: Jitter,
+    waiting: bool,
+}
+
+impl<D, C, MW> Permits<'_, D, C, MW>
+where
+    D: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the rate limiter that this stream checks.
+    pub fn limiter(&self) -> &RateLimiter<NotKeyed, D, C, MW> {
+        self.limiter
+    }
+
+    /// Returns the jitter that this stream adds to its wait periods.
+    pub fn jitter(&self) -> &Jitter {
+        &self.jitter
+    }
+
+    /// Changes the jitter that this stream adds to its wait periods.
+    ///
+    /// The new jitter applies from the next time the stream needs to wait.
+    pub fn set_jitter(&mut self, jitter: Jitter) {
+        self.jitter = jitter;
+    }
+}
+
+impl<D, C, MW> Stream for Permits<'_, D, C, MW>
+where
+    D: DirectStateStore,
+    C: clock::ReasonablyRealtime,
+    MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+    Self: Unpin,
+{
+    type Item = MW::PositiveOutcome;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.waiting {
+                match Pin::new(&mut self.delay).poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    // Other users of the rate limiter may have taken the capacity we waited
+                    // for, so check again.
+                    Poll::Ready(_) => self.waiting = false,
+                }
+            }
+            let reference = self.limiter.reference_reading();
+            match self.limiter.check() {
+                Ok(outcome) => return Poll::Ready(Some(outcome)),
+                Err(negative) => {
+                    let earliest = negative.wait_time_with_offset(reference, &self.jitter);
+                    self.delay.reset(earliest);
+                    self.waiting = true;
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
@@ -4,15 +4,20 @@ use crate::{
     clock,
     middleware::RateLimitingMiddleware,
     state::{DirectStateStore, NotKeyed},
+    timer::Delay,
     Jitter, NotUntil, RateLimiter,
 };
-use futures_timer::Delay;
-use futures_util::task::{Context, Poll};
-use futures_util::{Future, Sink, Stream};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::future::Future;
 use std::marker::PhantomData;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
-/// Allows converting a [`futures_util::Sink`] combinator into a rate-limited sink.
+use super::streams::LimiterRef;
+
+/// Allows converting a [`futures_sink::Sink`] combinator into a rate-limited sink.
 pub trait SinkRateLimitExt<Item, S>: Sink<Item>
 where
     S: Sink<Item>,
@@ -43,6 +48,23 @@ where
     ) -> RatelimitedSink<'_, Item, S, D, C, MW>
     where
         Self: Sized;
+
+    /// Limits the rate at which items can be put into the current sink, using a shared rate
+    /// limiter.
+    ///
+    /// Unlike [`ratelimit_sink`](#tymethod.ratelimit_sink), the resulting combinator holds on
+    /// to the rate limiter instead of borrowing it, so it can be `'static` (e.g., to be spawned
+    /// into a task).
+    fn ratelimit_sink_owned<
+        D: DirectStateStore,
+        C: clock::ReasonablyRealtime,
+        MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+    >(
+        self,
+        limiter: Arc<RateLimiter<NotKeyed, D, C, MW>>,
+    ) -> RatelimitedSink<'static, Item, S, D, C, MW>
+    where
+        Self: Sized;
 }
 
 impl<Item, S: Sink<Item>> SinkRateLimitExt<Item, S> for S {
@@ -53,11 +75,11 @@ impl<Item, S: Sink<Item>> SinkRateLimitExt<Item, S> for S {
     >(
         self,
         limiter: &RateLimiter<NotKeyed, D, C, MW>,
-    ) -> RatelimitedSink<Item, S, D, C, MW>
+    ) -> RatelimitedSink<'_, Item, S, D, C, MW>
     where
         Self: Sized,
     {
-        RatelimitedSink::new(self, limiter, Jitter::NONE)
+        RatelimitedSink::new(self, LimiterRef::Borrowed(limiter), Jitter::NONE)
     }
 
     #[cfg(feature = "jitter")]
@@ -69,11 +91,25 @@ impl<Item, S: Sink<Item>> SinkRateLimitExt<Item, S> for S {
         self,
         limiter: &RateLimiter<NotKeyed, D, C, MW>,
         jitter: Jitter,
-    ) -> RatelimitedSink<Item, S, D, C, MW>
+    ) -> RatelimitedSink<'_, Item, S, D, C, MW>
+    where
+        Self: Sized,
+    {
+        RatelimitedSink::new(self, LimiterRef::Borrowed(limiter), jitter)
+    }
+
+    fn ratelimit_sink_owned<
+        D: DirectStateStore,
+        C: clock::ReasonablyRealtime,
+        MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
+    >(
+        self,
+        limiter: Arc<RateLimiter<NotKeyed, D, C, MW>>,
+    ) -> RatelimitedSink<'static, Item, S, D, C, MW>
     where
         Self: Sized,
     {
-        RatelimitedSink::new(self, limiter, jitter)
+        RatelimitedSink::new(self, LimiterRef::Owned(limiter), Jitter::NONE)
     }
 }
 
@@ -84,8 +120,22 @@ enum State {
     Ready,
 }
 
-/// A [`Sink`][futures_util::Sink] combinator that only allows sending elements when the rate-limiter
+/// A [`Sink`][futures_sink::Sink] combinator that only allows sending elements when the rate-limiter
 /// allows it.
+///
+/// Like [`RatelimitedStream`](crate::RatelimitedStream), the combinator can share its rate
+/// limiter with other combinators: It checks the rate limiter again after each wait.
+///
+/// # Flushing and closing
+///
+/// Items only get passed to the inner sink once the rate limiter has allowed them through, so
+/// the combinator never holds on to any items itself: Flushing and closing it flushes and
+/// closes the inner sink, and nothing that was sent is lost or bypasses the rate limit.
+///
+/// If the combinator was waiting for the rate limiter to admit the next item when it gets
+/// closed, [`poll_close`](Sink::poll_close) first completes that wait, and only then closes
+/// the inner sink. Flushing doesn't wait, as it only concerns items that were already
+/// admitted.
 pub struct RatelimitedSink<
     'a,
     Item,
@@ -96,7 +146,7 @@ pub struct RatelimitedSink<
 > {
     inner: S,
     state: State,
-    limiter: &'a RateLimiter<NotKeyed, D, C, MW>,
+    limiter: LimiterRef<'a, D, C, MW>,
     delay: Delay,
     jitter: Jitter,
     phantom: PhantomData<Item>,
@@ -112,7 +162,7 @@ impl<
         MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
     > RatelimitedSink<'a, Item, S, D, C, MW>
 {
-    fn new(inner: S, limiter: &'a RateLimiter<NotKeyed, D, C, MW>, jitter: Jitter) -> Self {
+    fn new(inner: S, limiter: LimiterRef<'a, D, C, MW>, jitter: Jitter) -> Self {
         RatelimitedSink {
             inner,
             limiter,
@@ -123,6 +173,23 @@ impl<
         }
     }
 
+    /// Returns the rate limiter that this combinator checks.
+    pub fn limiter(&self) -> &RateLimiter<NotKeyed, D, C, MW> {
+        &self.limiter
+    }
+
+    /// Returns the jitter that this combinator adds to its wait periods.
+    pub fn jitter(&self) -> &Jitter {
+        &self.jitter
+    }
+
+    /// Changes the jitter that this combinator adds to its wait periods.
+    ///
+    /// The new jitter applies from the next time the combinator needs to wait.
+    pub fn set_jitter(&mut self, jitter: Jitter) {
+        self.jitter = jitter;
+    }
+
     /// Acquires a reference to the underlying sink that this combinator is sending into.
     pub fn get_ref(&self) -> &S {
         &self.inner
@@ -170,7 +237,7 @@ where
                 State::NotReady => {
                     let reference = self.limiter.reference_reading();
                     if let Err(negative) = self.limiter.check() {
-                        let earliest = negative.wait_time_with_offset(reference, self.jitter);
+                        let earliest = negative.wait_time_with_offset(reference, &self.jitter);
                         self.delay.reset(earliest);
                         let future = Pin::new(&mut self.delay);
                         match future.poll(cx) {
@@ -191,6 +258,8 @@ where
                             return Poll::Pending;
                         }
                         Poll::Ready(_) => {
+                            // Other users of the rate limiter may have taken the capacity we
+                            // waited for, so check again.
                             self.state = State::NotReady;
                         }
                     }
@@ -204,16 +273,16 @@ where
     }
 
     fn start_send(mut self: Pin<&mut Self>, item: Item) -> Result<(), Self::Error> {
-        match self.state {
-            State::Wait | State::NotReady => {
-                unreachable!("Must not start_send before we're ready"); // !no_rcov!
-            }
-            State::Ready => {
-                self.state = State::NotReady;
-                let inner = Pin::new(&mut self.inner);
-                inner.start_send(item)
-            }
+        if let State::Wait | State::NotReady = self.state {
+            // The caller didn't wait for `poll_ready`, breaking the `Sink` contract. Rather
+            // than panicking, count the item against the rate limiter (even if that goes
+            // over the quota) and pass it on, so that it isn't lost and later items wait for
+            // its cell.
+            let _ = self.limiter.consume();
         }
+        self.state = State::NotReady;
+        let inner = Pin::new(&mut self.inner);
+        inner.start_send(item)
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
@@ -222,12 +291,21 @@ where
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if let State::Wait = self.state {
+            // Finish the wait for admission before the inner sink gets closed, so that it
+            // can't send anything (like a closing message) before the rate limiter allows it.
+            let future = Pin::new(&mut self.delay);
+            match future.poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(_) => self.state = State::NotReady,
+            }
+        }
         let inner = Pin::new(&mut self.inner);
         inner.poll_close(cx)
     }
 }
 
-/// Pass-through implementation for [`futures_util::Stream`] if the Sink also implements it.
+/// Pass-through implementation for [`futures_core::Stream`] if the Sink also implements it.
 impl<
         Item,
         S: Stream + Sink<Item>,
@@ -4,14 +4,45 @@ use crate::{clock, Jitter, NotUntil, RateLimiter};
 use crate::{
     middleware::RateLimitingMiddleware,
     state::{DirectStateStore, NotKeyed},
+    timer::Delay,
 };
-use futures_timer::Delay;
-use futures_util::task::{Context, Poll};
-use futures_util::{Future, Sink, Stream};
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::future::Future;
+use std::ops::Deref;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::Duration;
 
-/// Allows converting a [`futures_util::Stream`] combinator into a rate-limited stream.
+/// A rate limiter that a stream or sink combinator either borrows or shares ownership of.
+pub(crate) enum LimiterRef<'a, D, C, MW>
+where
+    D: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    Borrowed(&'a RateLimiter<NotKeyed, D, C, MW>),
+    Owned(Arc<RateLimiter<NotKeyed, D, C, MW>>),
+}
+
+impl<D, C, MW> Deref for LimiterRef<'_, D, C, MW>
+where
+    D: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    type Target = RateLimiter<NotKeyed, D, C, MW>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            LimiterRef::Borrowed(limiter) => limiter,
+            LimiterRef::Owned(limiter) => limiter,
+        }
+    }
+}
+
+/// Allows converting a [`futures_core::Stream`] combinator into a rate-limited stream.
 pub trait StreamRateLimitExt<'a>: Stream {
     /// Limits the rate at which the stream produces items.
     ///
@@ -49,6 +80,22 @@ pub trait StreamRateLimitExt<'a>: Stream {
     ) -> RatelimitedStream<'a, Self, D, C, MW>
     where
         Self: Sized;
+
+    /// Limits the rate at which the stream produces items, using a shared rate limiter.
+    ///
+    /// Unlike [`ratelimit_stream`](#tymethod.ratelimit_stream), the resulting combinator holds
+    /// on to the rate limiter instead of borrowing it, so it can be `'static` (e.g., to be
+    /// spawned into a task).
+    fn ratelimit_stream_owned<
+        D: DirectStateStore,
+        C: clock::ReasonablyRealtime,
+        MW: RateLimitingMiddleware<C::Instant>,
+    >(
+        self,
+        limiter: Arc<RateLimiter<NotKeyed, D, C, MW>>,
+    ) -> RatelimitedStream<'static, Self, D, C, MW>
+    where
+        Self: Sized;
 }
 
 impl<'a, S: Stream> StreamRateLimitExt<'a> for S {
@@ -78,14 +125,21 @@ impl<'a, S: Stream> StreamRateLimitExt<'a> for S {
     where
         Self: Sized,
     {
-        RatelimitedStream {
-            inner: self,
-            limiter,
-            buf: None,
-            delay: Delay::new(Duration::new(0, 0)),
-            jitter,
-            state: State::ReadInner,
-        }
+        RatelimitedStream::new(self, LimiterRef::Borrowed(limiter), jitter)
+    }
+
+    fn ratelimit_stream_owned<
+        D: DirectStateStore,
+        C: clock::ReasonablyRealtime,
+        MW: RateLimitingMiddleware<C::Instant>,
+    >(
+        self,
+        limiter: Arc<RateLimiter<NotKeyed, D, C, MW>>,
+    ) -> RatelimitedStream<'static, Self, D, C, MW>
+    where
+        Self: Sized,
+    {
+        RatelimitedStream::new(self, LimiterRef::Owned(limiter), Jitter::NONE)
     }
 }
 
@@ -95,10 +149,14 @@ enum State {
     Wait,
 }
 
-/// A [`Stream`][futures_util::Stream] combinator which will limit the rate of items being received.
+/// A [`Stream`][futures_core::Stream] combinator which will limit the rate of items being received.
 ///
 /// This is produced by the [`StreamRateLimitExt::ratelimit_stream`] and
 /// [`StreamRateLimitExt::ratelimit_stream_with_jitter`] methods.
+///
+/// Several combinators can share one rate limiter (with each other, or with any other code
+/// checking it): A combinator only lets an item through once the rate limiter allowed it, and
+/// checks again after each wait, in case somebody else used up the capacity it waited for.
 pub struct RatelimitedStream<
     'a,
     S: Stream,
@@ -107,7 +165,7 @@ pub struct RatelimitedStream<
     MW: RateLimitingMiddleware<C::Instant>,
 > {
     inner: S,
-    limiter: &'a RateLimiter<NotKeyed, D, C, MW>,
+    limiter: LimiterRef<'a, D, C, MW>,
     delay: Delay,
     buf: Option<S::Item>,
     jitter: Jitter,
@@ -115,9 +173,42 @@ pub struct RatelimitedStream<
 }
 
 /// Conversion methods for the stream combinator.
-impl<S: Stream, D: DirectStateStore, C: clock::Clock, MW: RateLimitingMiddleware<C::Instant>>
-    RatelimitedStream<'_, S, D, C, MW>
+impl<
+        'a,
+        S: Stream,
+        D: DirectStateStore,
+        C: clock::Clock,
+        MW: RateLimitingMiddleware<C::Instant>,
+    > RatelimitedStream<'a, S, D, C, MW>
 {
+    fn new(inner: S, limiter: LimiterRef<'a, D, C, MW>, jitter: Jitter) -> Self {
+        RatelimitedStream {
+            inner,
+            limiter,
+            buf: None,
+            delay: Delay::new(Duration::new(0, 0)),
+            jitter,
+            state: State::ReadInner,
+        }
+    }
+
+    /// Returns the rate limiter that this combinator checks.
+    pub fn limiter(&self) -> &RateLimiter<NotKeyed, D, C, MW> {
+        &self.limiter
+    }
+
+    /// Returns the jitter that this combinator adds to its wait periods.
+    pub fn jitter(&self) -> &Jitter {
+        &self.jitter
+    }
+
+    /// Changes the jitter that this combinator adds to its wait periods.
+    ///
+    /// The new jitter applies from the next time the combinator needs to wait.
+    pub fn set_jitter(&mut self, jitter: Jitter) {
+        self.jitter = jitter;
+    }
+
     /// Acquires a reference to the underlying stream that this combinator is pulling from.
     /// ```rust
     /// # use futures_util::{Stream, stream};
@@ -167,7 +258,7 @@ impl<S: Stream, D: DirectStateStore, C: clock::Clock, MW: RateLimitingMiddleware
     }
 }
 
-/// Implements the [`futures_util::Stream`] combinator.
+/// Implements the [`futures_core::Stream`] combinator.
 impl<S: Stream, D: DirectStateStore, C: clock::Clock, MW> Stream
     for RatelimitedStream<'_, S, D, C, MW>
 where
@@ -199,7 +290,7 @@ where
                 State::NotReady => {
                     let reference = self.limiter.reference_reading();
                     if let Err(negative) = self.limiter.check() {
-                        let earliest = negative.wait_time_with_offset(reference, self.jitter);
+                        let earliest = negative.wait_time_with_offset(reference, &self.jitter);
                         self.delay.reset(earliest);
                         let future = Pin::new(&mut self.delay);
                         match future.poll(cx) {
@@ -221,6 +312,8 @@ where
                             return Poll::Pending;
                         }
                         Poll::Ready(_) => {
+                            // Other users of the rate limiter may have taken the capacity we
+                            // waited for, so check again.
                             self.state = State::NotReady;
                         }
                     }
@@ -234,7 +327,7 @@ where
     }
 }
 
-/// Pass-through implementation for [`futures_util::Sink`] if the Stream also implements it.
+/// Pass-through implementation for [`futures_sink::Sink`] if the Stream also implements it.
 impl<
         Item,
         S: Stream + Sink<Item>,
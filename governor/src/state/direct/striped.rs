@@ -0,0 +1,265 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use nonzero_ext::nonzero;
+
+use crate::{
+    clock,
+    gcra::Gcra,
+    middleware::{KeyFormat, NoOpMiddleware, RateLimitingMiddleware},
+    state::{InMemoryState, NotKeyed},
+    Quota,
+};
+
+/// One stripe's share of the quota, on its own cache line.
+#[repr(align(128))]
+struct Stripe {
+    gcra: Gcra,
+    state: InMemoryState,
+}
+
+static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+std::thread_local! {
+    /// Threads get assigned stripes round-robin, the first time they check a striped limiter.
+    static THREAD_STRIPE: usize = NEXT_STRIPE.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A direct rate limiter that spreads its state over several cache lines.
+///
+/// All threads checking a [direct rate limiter](crate::RateLimiter::direct) update the same
+/// atomic integer, whose cache line then keeps bouncing between CPU cores. A striped limiter
+/// instead splits its quota into several stripes with a share of the burst size and
+/// replenishment rate each; threads check "their" stripe first, and only steal capacity from
+/// the other stripes when it is exhausted.
+///
+/// Since no stripe ever lets more cells through than its share of the quota, the striped
+/// limiter as a whole never lets more cells through than the quota allows. If the quota's burst
+/// size is smaller than the number of stripes, the limiter uses only as many stripes as there
+/// are cells in a burst.
+///
+/// Negative outcomes describe the state of the checking thread's own stripe: They indicate
+/// when that stripe can accommodate a cell again, and their quota is that stripe's share.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{state::direct::StripedDirectLimiter, Quota};
+///
+/// let lim = StripedDirectLimiter::new(Quota::per_second(nonzero!(8u32)), nonzero!(4usize));
+/// for _ in 0..8 {
+///     assert!(lim.check().is_ok());
+/// }
+/// assert!(lim.check().is_err());
+/// ```
+pub struct StripedDirectLimiter<
+    C: clock::Clock = clock::DefaultClock,
+    MW: RateLimitingMiddleware<C::Instant> = NoOpMiddleware<<C as clock::Clock>::Instant>,
+> {
+    stripes: Box<[Stripe]>,
+    clock: C,
+    start: C::Instant,
+    middleware: PhantomData<MW>,
+}
+
+impl StripedDirectLimiter {
+    /// Constructs a striped rate limiter for a quota, with the default real-time clock.
+    pub fn new(quota: Quota, stripes: NonZeroUsize) -> Self {
+        Self::with_clock(quota, stripes, clock::DefaultClock::default())
+    }
+}
+
+impl<C, MW> StripedDirectLimiter<C, MW>
+where
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Constructs a striped rate limiter for a quota with a custom clock.
+    pub fn with_clock(quota: Quota, stripes: NonZeroUsize, clock: C) -> Self {
+        let burst = quota.burst_size().get();
+        let count = stripes.get().min(burst as usize) as u32;
+        let stripes = (0..count)
+            .map(|i| {
+                // Spread the remainder of the burst size over the first stripes, and replenish
+                // each stripe in proportion to its share of the burst. Rounding the interval up
+                // keeps the stripes together from replenishing faster than the quota:
+                let share = burst / count + u32::from(i < burst % count);
+                let interval_ns = (quota.replenish_interval().as_nanos() * u128::from(burst))
+                    .div_ceil(u128::from(share));
+                let interval = Duration::from_nanos(u64::try_from(interval_ns).unwrap_or(u64::MAX));
+                let stripe_quota = Quota {
+                    max_burst: NonZeroU32::new(share).unwrap_or(nonzero!(1u32)),
+                    replenish_1_per: interval,
+                    ..quota
+                };
+                Stripe {
+                    gcra: Gcra::new(stripe_quota),
+                    state: InMemoryState::default(),
+                }
+            })
+            .collect();
+        let start = clock.now();
+        StripedDirectLimiter {
+            stripes,
+            clock,
+            start,
+            middleware: PhantomData,
+        }
+    }
+
+    /// Returns the number of stripes that the quota is split into.
+    pub fn stripes(&self) -> usize {
+        self.stripes.len()
+    }
+
+    /// Returns a reference to the clock.
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Allow a single cell through the rate limiter.
+    ///
+    /// Checks the calling thread's stripe first, then the other stripes in turn. If none of
+    /// them can accommodate the cell, returns the negative outcome of the calling thread's
+    /// stripe.
+    pub fn check(&self) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let count = self.stripes.len();
+        let local = THREAD_STRIPE.with(|stripe| *stripe) % count;
+        let now = self.clock.now();
+        let denied = match self.check_stripe(local, now) {
+            Ok(outcome) => return Ok(outcome),
+            Err(denied) => denied,
+        };
+        for offset in 1..count {
+            if let Ok(outcome) = self.check_stripe((local + offset) % count, now) {
+                return Ok(outcome);
+            }
+        }
+        Err(denied)
+    }
+
+    fn check_stripe(
+        &self,
+        index: usize,
+        now: C::Instant,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let stripe = &self.stripes[index];
+        stripe
+            .gcra
+            .test_and_update::<NotKeyed, C::Instant, InMemoryState, MW>(
+                self.start,
+                &NotKeyed::NonKey,
+                KeyFormat::redacted(),
+                &stripe.state,
+                now,
+            )
+    }
+}
+
+impl<C, MW> fmt::Debug for StripedDirectLimiter<C, MW>
+where
+    C: clock::Clock + fmt::Debug,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StripedDirectLimiter")
+            .field("stripes", &self.stripes.len())
+            .field("clock", &self.clock)
+            .field("start", &self.start)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::FakeRelativeClock;
+
+    #[test]
+    fn splits_quota() {
+        let clock = FakeRelativeClock::default();
+        let lim: StripedDirectLimiter<_> = StripedDirectLimiter::with_clock(
+            Quota::per_second(nonzero!(10u32)),
+            nonzero!(4usize),
+            clock.clone(),
+        );
+        assert_eq!(lim.stripes(), 4);
+        for _ in 0..10 {
+            assert_eq!(Ok(()), lim.check());
+        }
+        assert!(lim.check().is_err());
+
+        // Replenishing takes as long as for an unstriped limiter, give or take the nanoseconds
+        // that the stripes' intervals are rounded up by:
+        clock.advance(Duration::from_secs(1) + Duration::from_nanos(3));
+        for _ in 0..10 {
+            assert_eq!(Ok(()), lim.check());
+        }
+        assert!(lim.check().is_err());
+    }
+
+    #[test]
+    fn fewer_stripes_than_cells() {
+        let clock = FakeRelativeClock::default();
+        let lim: StripedDirectLimiter<_> = StripedDirectLimiter::with_clock(
+            Quota::per_second(nonzero!(2u32)),
+            nonzero!(8usize),
+            clock,
+        );
+        assert_eq!(lim.stripes(), 2);
+        assert_eq!(Ok(()), lim.check());
+        assert_eq!(Ok(()), lim.check());
+        assert!(lim.check().is_err());
+        assert!(format!("{:?}", lim).contains("stripes: 2"));
+    }
+
+    #[test]
+    fn uneven_shares_stay_within_quota() {
+        // The burst of 7 cells splits into shares of 3, 2 and 2, whose intervals of 7/3ns and
+        // 7/2ns don't come out even:
+        let clock = FakeRelativeClock::default();
+        let quota = Quota::with_period(Duration::from_nanos(1))
+            .unwrap()
+            .allow_burst(nonzero!(7u32));
+        let lim: StripedDirectLimiter<_> =
+            StripedDirectLimiter::with_clock(quota, nonzero!(3usize), clock.clone());
+        assert_eq!(lim.stripes(), 3);
+
+        let window = 700;
+        let mut admitted = 0;
+        for _ in 0..window {
+            while lim.check().is_ok() {
+                admitted += 1;
+            }
+            clock.advance(Duration::from_nanos(1));
+        }
+        assert!(admitted <= 7 + window, "admitted {} cells", admitted);
+    }
+
+    #[test]
+    fn strict_bound_across_threads() {
+        let clock = FakeRelativeClock::default();
+        let lim: std::sync::Arc<StripedDirectLimiter<_>> =
+            std::sync::Arc::new(StripedDirectLimiter::with_clock(
+                Quota::per_second(nonzero!(50u32)),
+                nonzero!(8usize),
+                clock,
+            ));
+        let children: Vec<_> = (0..20)
+            .map(|_| {
+                let lim = lim.clone();
+                std::thread::spawn(move || (0..10).filter(|_| lim.check().is_ok()).count())
+            })
+            .collect();
+        let admitted: usize = children.into_iter().map(|c| c.join().unwrap()).sum();
+        assert_eq!(admitted, 50);
+    }
+}
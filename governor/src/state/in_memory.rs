@@ -1,15 +1,32 @@
 use std::prelude::v1::*;
 
 use crate::nanos::Nanos;
-use crate::state::{NotKeyed, StateStore};
+use crate::state::{NotKeyed, RebasableStateStore, StateStore};
 use std::fmt;
 use std::fmt::Debug;
 use std::num::NonZeroU64;
 use std::sync::atomic::Ordering;
 use std::time::Duration;
 
+#[cfg(loom)]
+use loom::sync::atomic::AtomicU64;
+#[cfg(not(loom))]
 use portable_atomic::AtomicU64;
 
+cfg_if::cfg_if! {
+    if #[cfg(feature = "relaxed-atomics")] {
+        /// The ordering of loads of the state.
+        const LOAD: Ordering = Ordering::Relaxed;
+        /// The ordering of stores of the state.
+        const STORE: Ordering = Ordering::Relaxed;
+    } else {
+        /// The ordering of loads of the state.
+        const LOAD: Ordering = Ordering::Acquire;
+        /// The ordering of stores of the state.
+        const STORE: Ordering = Ordering::Release;
+    }
+}
+
 /// An in-memory representation of a GCRA's rate-limiting state.
 ///
 /// Implemented using [`AtomicU64`] operations, this state representation can be used to
@@ -18,6 +35,40 @@ use portable_atomic::AtomicU64;
 ///
 /// Internally, the number tracked here is the theoretical arrival time (a GCRA term) in number of
 /// nanoseconds since the rate limiter was created.
+///
+/// # Memory orderings
+///
+/// The rate limiter relies on one property only, which holds under any memory ordering: All
+/// atomic operations on a single state agree on the order of its values. Every update is a
+/// compare-and-swap from the state that the decision was based on, so it only succeeds if no
+/// other update came in between; otherwise, the decision is made again from the state that
+/// the failed compare-and-swap read. So two decisions on the same state never hand out the
+/// same capacity.
+///
+/// Beyond that, the orderings promise little:
+///
+/// * Loads that don't end in a compare-and-swap (like those of
+///   [`check_only`](crate::RateLimiter::check_only)) may see an outdated state, under any
+///   ordering, so their results can be stale.
+/// * Decisions about several states (e.g. several keys) update each state on its own; they
+///   don't happen in one atomic step.
+///
+/// By default, updates to the state are [`Release`](Ordering::Release) operations, and the
+/// first load of each decision is an [`Acquire`](Ordering::Acquire) load. If a decision is
+/// made from the state that this load saw, the update that wrote that state *happens before*
+/// the decision. Retries after a failed compare-and-swap read the state with
+/// [`Relaxed`](Ordering::Relaxed) ordering, and decisions made from those reads don't
+/// synchronize with anything. Nothing in this crate relies on this partial synchronization,
+/// and programs should not rely on it either.
+///
+/// The `relaxed-atomics` feature makes all these operations `Relaxed`, which saves memory
+/// barriers on weakly-ordered architectures like ARM. The rate limiter's decisions stay the
+/// same; only the synchronization described above goes away. The `loom`-based tests (run
+/// with `RUSTFLAGS="--cfg loom"`) check the rate limiter's decisions under both orderings.
+///
+/// Like all Cargo features, `relaxed-atomics` applies to the whole build: If any crate enables
+/// it, all rate limiters in the program use relaxed orderings, including those of crates that
+/// never asked for them. So libraries must not enable it; leave the choice to the application.
 #[derive(Default)]
 pub struct InMemoryState(AtomicU64);
 
@@ -26,15 +77,15 @@ impl InMemoryState {
     where
         F: FnMut(Option<Nanos>) -> Result<(T, Nanos), E>,
     {
-        let mut prev = self.0.load(Ordering::Acquire);
+        let mut prev = self.0.load(LOAD);
         let mut decision = f(NonZeroU64::new(prev).map(|n| n.get().into()));
         while let Ok((result, new_data)) = decision {
-            match self.0.compare_exchange_weak(
-                prev,
-                new_data.into(),
-                Ordering::Release,
-                Ordering::Relaxed,
-            ) {
+            // A failed exchange retries the decision with the state it read; the retry's
+            // outcome doesn't need to synchronize with anything, so that read is relaxed.
+            match self
+                .0
+                .compare_exchange_weak(prev, new_data.into(), STORE, Ordering::Relaxed)
+            {
                 Ok(_) => return Ok(result),
                 Err(next_prev) => prev = next_prev,
             }
@@ -45,9 +96,64 @@ impl InMemoryState {
         decision.map(|(result, _)| result)
     }
 
+    pub(crate) fn measure_and_peek_one<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce(Option<Nanos>) -> T,
+    {
+        f(NonZeroU64::new(self.0.load(LOAD)).map(|n| n.get().into()))
+    }
+
+    /// Replaces the state with `new`, regardless of what it was before.
+    pub(crate) fn replace(&self, new: Nanos) {
+        self.0.store(new.into(), STORE);
+    }
+
+    /// Puts back the state `prev` (no state, if it is `None`) if the state is still `replaced`.
+    /// Returns whether it did.
+    pub(crate) fn restore_one(&self, replaced: Nanos, prev: Option<Nanos>) -> bool {
+        self.0
+            .compare_exchange(
+                replaced.into(),
+                prev.map_or(0, u64::from),
+                STORE,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    /// Replaces the state with `new`, if it is still `prev`. Otherwise, returns the current state.
+    #[cfg(all(feature = "std", any(feature = "dashmap", feature = "skiplist")))]
+    pub(crate) fn compare_and_replace(
+        &self,
+        prev: Option<Nanos>,
+        new: Nanos,
+    ) -> Result<(), Option<Nanos>> {
+        let prev = prev.map_or(0, u64::from);
+        self.0
+            .compare_exchange(prev, new.into(), STORE, LOAD)
+            .map(|_| ())
+            .map_err(|actual| NonZeroU64::new(actual).map(|n| n.get().into()))
+    }
+
     pub(crate) fn is_older_than(&self, nanos: Nanos) -> bool {
         self.0.load(Ordering::Relaxed) <= nanos.into()
     }
+
+    /// Replaces the stored state (if there is one) with the result of `f`.
+    pub(crate) fn rewrite<F>(&mut self, f: F)
+    where
+        F: FnOnce(Nanos) -> Nanos,
+    {
+        let rewrite = |tat: &mut u64| {
+            if *tat != 0 {
+                *tat = f(Nanos::from(*tat)).into();
+            }
+        };
+        #[cfg(not(loom))]
+        rewrite(self.0.get_mut());
+        #[cfg(loom)]
+        self.0.with_mut(rewrite);
+    }
 }
 
 /// The InMemoryState is the canonical "direct" state store.
@@ -60,6 +166,26 @@ impl StateStore for InMemoryState {
     {
         self.measure_and_replace_one(f)
     }
+
+    fn measure_and_peek<T, F, E>(&self, _key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        self.measure_and_peek_one(f)
+    }
+
+    fn restore(&self, _key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        self.restore_one(replaced, prev);
+    }
+}
+
+impl RebasableStateStore for InMemoryState {
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        self.rewrite(f);
+    }
 }
 
 impl Debug for InMemoryState {
@@ -69,7 +195,7 @@ impl Debug for InMemoryState {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, not(loom)))]
 #[allow(clippy::needless_collect)]
 mod test {
 
@@ -139,3 +265,127 @@ mod test {
         assert_gt!(format!("{:?}", state).len(), 0);
     }
 }
+
+/// Model-checks the state's atomic operations with every interleaving of a few threads.
+///
+/// Loom's atomics only work inside `loom::model`, so these are the only tests to run with the
+/// `loom` cfg: `RUSTFLAGS="--cfg loom" cargo test --release --lib loom` (add
+/// `--features relaxed-atomics` to check the relaxed orderings).
+#[cfg(all(test, loom))]
+mod loom_test {
+    use super::*;
+    use crate::{clock::FakeRelativeClock, Quota, RateLimiter};
+    use loom::sync::Arc;
+    use loom::thread;
+    use nonzero_ext::nonzero;
+
+    /// Two threads take one cell of 10ns each, out of a bucket of 20ns, and a third thread takes
+    /// one more, which must be rejected if the others got through.
+    #[test]
+    fn loom_measure_and_replace_one() {
+        loom::model(|| {
+            let state = Arc::new(InMemoryState::default());
+            let take = |state: Arc<InMemoryState>| {
+                move || {
+                    state
+                        .measure_and_replace_one(|tat| {
+                            let next = tat.unwrap_or_else(|| Nanos::from(0)) + Nanos::from(10);
+                            if next > Nanos::from(20) {
+                                Err(())
+                            } else {
+                                Ok(((), next))
+                            }
+                        })
+                        .is_ok()
+                }
+            };
+            let threads: Vec<_> = (0..3)
+                .map(|_| thread::spawn(take(Arc::clone(&state))))
+                .collect();
+            let admitted = threads
+                .into_iter()
+                .map(|t| t.join().unwrap())
+                .filter(|admitted| *admitted)
+                .count();
+            assert_eq!(admitted, 2);
+            state.measure_and_peek_one(|tat| assert_eq!(tat, Some(Nanos::from(20))));
+        });
+    }
+
+    /// A direct rate limiter lets exactly its burst size through to concurrent threads.
+    #[test]
+    fn loom_direct_rate_limiter() {
+        loom::model(|| {
+            let clock = FakeRelativeClock::default();
+            let lim = Arc::new(RateLimiter::direct_with_clock(
+                Quota::per_second(nonzero!(2u32)),
+                clock,
+            ));
+            let threads: Vec<_> = (0..3)
+                .map(|_| {
+                    let lim = Arc::clone(&lim);
+                    thread::spawn(move || lim.check().is_ok())
+                })
+                .collect();
+            let admitted = threads
+                .into_iter()
+                .map(|t| t.join().unwrap())
+                .filter(|admitted| *admitted)
+                .count();
+            assert_eq!(admitted, 2);
+            assert!(lim.check().is_err());
+        });
+    }
+
+    /// Peeks never see the state go back to an older value, even while it is being updated.
+    #[test]
+    fn loom_measure_and_peek_one() {
+        loom::model(|| {
+            let state = Arc::new(InMemoryState::default());
+            let writer = {
+                let state = Arc::clone(&state);
+                thread::spawn(move || {
+                    for _ in 0..2 {
+                        state
+                            .measure_and_replace_one(|tat| {
+                                let tat = tat.unwrap_or_else(|| Nanos::from(0));
+                                Ok::<_, ()>(((), tat + Nanos::from(10)))
+                            })
+                            .unwrap();
+                    }
+                })
+            };
+            let first = state.measure_and_peek_one(|tat| tat);
+            let second = state.measure_and_peek_one(|tat| tat);
+            assert!(first <= second, "{:?} > {:?}", first, second);
+            writer.join().unwrap();
+            state.measure_and_peek_one(|tat| assert_eq!(tat, Some(Nanos::from(20))));
+        });
+    }
+
+    /// A positive decision happens after the one whose state it read, unless the orderings
+    /// are relaxed.
+    #[cfg(not(feature = "relaxed-atomics"))]
+    #[test]
+    fn loom_decisions_synchronize() {
+        use loom::sync::atomic::AtomicBool;
+
+        loom::model(|| {
+            let state = Arc::new(InMemoryState::default());
+            let written = Arc::new(AtomicBool::new(false));
+            let writer = {
+                let (state, written) = (Arc::clone(&state), Arc::clone(&written));
+                thread::spawn(move || {
+                    written.store(true, Ordering::Relaxed);
+                    state
+                        .measure_and_replace_one(|_| Ok::<_, ()>(((), Nanos::from(10))))
+                        .unwrap();
+                })
+            };
+            if state.measure_and_peek_one(|tat| tat.is_some()) {
+                assert!(written.load(Ordering::Relaxed));
+            }
+            writer.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,262 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use std::cell::Cell;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// `std::time::Instant` panics in JavaScript hosts:
+#[cfg(not(feature = "wasm"))]
+use std::time::Instant;
+#[cfg(feature = "wasm")]
+use web_time::Instant;
+
+use crate::nanos::Nanos;
+use crate::state::keyed::ShrinkableKeyedStateStore;
+use crate::state::{RebasableStateStore, StateStore};
+
+/// A state store that measures how the state store it wraps performs, e.g. to compare state
+/// store backends, or to find out whether a shared state store is contended.
+///
+/// `InstrumentedStateStore` wraps any other state store and keeps statistics about the
+/// accesses that rate limiters make to it:
+///
+/// * [`operations`](#method.operations) counts the accesses, and
+///   [`busy_time`](#method.busy_time) adds up how long they took.
+/// * [`retries`](#method.retries) counts how often decisions had to be computed again, because
+///   the state changed before the wrapped store could store a new one (as happens when several
+///   threads check the same key at once).
+/// * [`with_slow_operation_log`](#method.with_slow_operation_log) calls a closure for each
+///   access that takes longer than a threshold, e.g. to log it.
+///
+/// Decisions are unaffected: They are still made by the wrapped store.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// use governor::{
+///     clock::DefaultClock, middleware::NoOpMiddleware,
+///     state::{keyed::HashMapStateStore, InstrumentedStateStore},
+///     Quota, RateLimiter,
+/// };
+///
+/// let store = InstrumentedStateStore::new(HashMapStateStore::default()).with_slow_operation_log(
+///     Duration::from_millis(10),
+///     |key: &&str, took, attempts| eprintln!("slow: {key} took {took:?} ({attempts} attempts)"),
+/// );
+/// let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> =
+///     RateLimiter::new(Quota::per_second(nonzero!(1u32)), store, DefaultClock::default());
+/// assert!(lim.check_key(&"alice").is_ok());
+/// assert!(lim.check_key(&"alice").is_err());
+/// assert_eq!(lim.into_state_store().operations(), 2);
+/// ```
+pub struct InstrumentedStateStore<S: StateStore> {
+    inner: S,
+    operations: AtomicU64,
+    retries: AtomicU64,
+    busy_nanos: AtomicU64,
+    slow_operations: AtomicU64,
+    slow_log: Option<SlowOperationLog<S::Key>>,
+}
+
+/// Called with the key, duration and number of attempts of a slow access.
+type LogFn<K> = dyn Fn(&K, Duration, u32) + Send + Sync;
+
+struct SlowOperationLog<K> {
+    threshold: Duration,
+    log: Box<LogFn<K>>,
+}
+
+impl<S: StateStore> InstrumentedStateStore<S> {
+    /// Wraps `inner`, measuring the accesses to it.
+    pub fn new(inner: S) -> Self {
+        InstrumentedStateStore {
+            inner,
+            operations: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            busy_nanos: AtomicU64::new(0),
+            slow_operations: AtomicU64::new(0),
+            slow_log: None,
+        }
+    }
+
+    /// Calls `log` for every access that takes longer than `threshold`, with the key that was
+    /// accessed, how long the access took and how many times the decision was computed.
+    ///
+    /// The closure runs on the thread that made the access, after the access completed.
+    pub fn with_slow_operation_log<F>(self, threshold: Duration, log: F) -> Self
+    where
+        F: Fn(&S::Key, Duration, u32) + Send + Sync + 'static,
+    {
+        InstrumentedStateStore {
+            slow_log: Some(SlowOperationLog {
+                threshold,
+                log: Box::new(log),
+            }),
+            ..self
+        }
+    }
+
+    /// Returns the wrapped state store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the number of times the state store was accessed.
+    pub fn operations(&self) -> u64 {
+        self.operations.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times that decisions had to be computed again, because the
+    /// state changed in the meantime.
+    pub fn retries(&self) -> u64 {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Returns the total time that accesses to the state store took.
+    pub fn busy_time(&self) -> Duration {
+        Duration::from_nanos(self.busy_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Returns the number of accesses that took longer than the threshold given to
+    /// [`with_slow_operation_log`](#method.with_slow_operation_log).
+    pub fn slow_operations(&self) -> u64 {
+        self.slow_operations.load(Ordering::Relaxed)
+    }
+
+    /// Records an access to `key` that started at `start` and computed the decision `attempts`
+    /// times.
+    fn record(&self, key: &S::Key, start: Instant, attempts: u32) {
+        let took = start.elapsed();
+        self.operations.fetch_add(1, Ordering::Relaxed);
+        self.retries
+            .fetch_add(attempts.saturating_sub(1).into(), Ordering::Relaxed);
+        self.busy_nanos
+            .fetch_add(Nanos::saturating_from(took).as_u64(), Ordering::Relaxed);
+        if let Some(slow_log) = &self.slow_log {
+            if took > slow_log.threshold {
+                self.slow_operations.fetch_add(1, Ordering::Relaxed);
+                (slow_log.log)(key, took, attempts);
+            }
+        }
+    }
+}
+
+impl<S: StateStore> StateStore for InstrumentedStateStore<S> {
+    type Key = S::Key;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let attempts = Cell::new(0);
+        let start = Instant::now();
+        let result = self.inner.measure_and_replace(key, |tat| {
+            attempts.set(attempts.get() + 1);
+            f(tat)
+        });
+        self.record(key, start, attempts.get());
+        result
+    }
+
+    fn note_denial(&self, key: &Self::Key, t0: Nanos) {
+        self.inner.note_denial(key, t0)
+    }
+
+    fn note_admission(&self, key: &Self::Key, t0: Nanos, tat: Nanos) {
+        self.inner.note_admission(key, t0, tat)
+    }
+
+    fn note_wait_started(&self, key: &Self::Key) {
+        self.inner.note_wait_started(key)
+    }
+
+    fn note_wait_finished(&self, key: &Self::Key) {
+        self.inner.note_wait_finished(key)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        let start = Instant::now();
+        let result = self.inner.measure_and_peek(key, f);
+        self.record(key, start, 1);
+        result
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        let start = Instant::now();
+        self.inner.restore(key, replaced, prev);
+        self.record(key, start, 1);
+    }
+}
+
+impl<S: RebasableStateStore> RebasableStateStore for InstrumentedStateStore<S> {
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        self.inner.rewrite_states(f)
+    }
+}
+
+impl<K, S> ShrinkableKeyedStateStore<K> for InstrumentedStateStore<S>
+where
+    K: Hash + Eq + Clone,
+    S: ShrinkableKeyedStateStore<K>,
+{
+    fn retain_recent(&self, drop_below: Nanos) {
+        self.inner.retain_recent(drop_below)
+    }
+
+    fn shrink_to_fit(&self) {
+        self.inner.shrink_to_fit()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        self.inner.approx_memory_bytes()
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        self.inner.pre_seed(keys)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        self.inner.export_states(drop_below)
+    }
+}
+
+impl<S: StateStore + Default> Default for InstrumentedStateStore<S> {
+    fn default() -> Self {
+        InstrumentedStateStore::new(S::default())
+    }
+}
+
+impl<S: StateStore + fmt::Debug> fmt::Debug for InstrumentedStateStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstrumentedStateStore")
+            .field("inner", &self.inner)
+            .field("operations", &self.operations())
+            .field("retries", &self.retries())
+            .field("busy_time", &self.busy_time())
+            .field("slow_operations", &self.slow_operations())
+            .finish()
+    }
+}
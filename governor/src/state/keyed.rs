@@ -7,17 +7,21 @@
 //! Rate limiters based on these types are constructed with
 //! [the `RateLimiter` constructors](../struct.RateLimiter.html#keyed-rate-limiters---default-constructors)
 
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::hash::Hash;
+use std::mem;
 use std::num::NonZeroU32;
 use std::prelude::v1::*;
+use std::time::Duration;
 
-use crate::state::StateStore;
+use crate::state::{Clamped, InMemoryState, RebasableStateStore, Reservation, StateStore};
 use crate::{
     clock::{self, Reference},
-    errors::InsufficientCapacity,
-    middleware::RateLimitingMiddleware,
+    errors::{CostError, InsufficientCapacity},
+    middleware::{NoOpMiddleware, RateLimitingMiddleware, StateSnapshot},
     nanos::Nanos,
-    Quota, RateLimiter,
+    NotUntil, Quota, RateLimiter,
 };
 
 /// A trait for state stores with one rate limiting state per key.
@@ -46,6 +50,20 @@ where
         RateLimiter::new(quota, state, clock)
     }
 
+    /// Constructs a new keyed rate limiter backed by the [`DefaultKeyedStateStore`], with room
+    /// for at least `capacity` keys before the state store needs to grow.
+    ///
+    /// This avoids repeatedly resizing the state store when a large number of keys is expected
+    /// (e.g. when a service starts up and all its known clients make requests at once).
+    pub fn keyed_with_capacity(quota: Quota, capacity: usize) -> Self {
+        #[cfg(all(feature = "std", feature = "dashmap"))]
+        let state = DashMapStateStore::with_capacity(capacity);
+        #[cfg(any(all(feature = "std", not(feature = "dashmap")), not(feature = "std")))]
+        let state = HashMapStateStore::new(HashMap::with_capacity(capacity));
+        let clock = clock::DefaultClock::default();
+        RateLimiter::new(quota, state, clock)
+    }
+
     #[cfg(all(feature = "std", feature = "dashmap"))]
     /// Constructs a new keyed rate limiter explicitly backed by a [`DashMap`][::dashmap::DashMap].
     pub fn dashmap(quota: Quota) -> Self {
@@ -54,6 +72,15 @@ where
         RateLimiter::new(quota, state, clock)
     }
 
+    #[cfg(all(feature = "std", feature = "dashmap"))]
+    /// Constructs a new keyed rate limiter explicitly backed by a [`DashMap`][::dashmap::DashMap],
+    /// with room for at least `capacity` keys.
+    pub fn dashmap_with_capacity(quota: Quota, capacity: usize) -> Self {
+        let state = DashMapStateStore::with_capacity(capacity);
+        let clock = clock::DefaultClock::default();
+        RateLimiter::new(quota, state, clock)
+    }
+
     #[cfg(any(all(feature = "std", not(feature = "dashmap")), not(feature = "std")))]
     /// Constructs a new keyed rate limiter explicitly backed by a
     /// [`HashMap`][std::collections::HashMap].
@@ -62,6 +89,15 @@ where
         let clock = clock::DefaultClock::default();
         RateLimiter::new(quota, state, clock)
     }
+
+    #[cfg(any(all(feature = "std", not(feature = "dashmap")), not(feature = "std")))]
+    /// Constructs a new keyed rate limiter explicitly backed by a
+    /// [`HashMap`][std::collections::HashMap], with room for at least `capacity` keys.
+    pub fn hashmap_with_capacity(quota: Quota, capacity: usize) -> Self {
+        let state = HashMapStateStore::new(HashMap::with_capacity(capacity));
+        let clock = clock::DefaultClock::default();
+        RateLimiter::new(quota, state, clock)
+    }
 }
 
 #[cfg(all(feature = "std", feature = "dashmap"))]
@@ -76,6 +112,14 @@ where
         let clock = clock::DefaultClock::default();
         RateLimiter::new(quota, state, clock)
     }
+
+    /// Constructs a new keyed rate limiter explicitly backed by a
+    /// [`HashMap`], with room for at least `capacity` keys.
+    pub fn hashmap_with_capacity(quota: Quota, capacity: usize) -> Self {
+        let state = HashMapStateStore::new(HashMap::with_capacity(capacity));
+        let clock = clock::DefaultClock::default();
+        RateLimiter::new(quota, state, clock)
+    }
 }
 
 /// # Keyed rate limiters - Manually checking cells
@@ -94,6 +138,7 @@ where
         self.gcra.test_and_update::<K, C::Instant, S, MW>(
             self.start,
             key,
+            self.key_format(),
             &self.state,
             self.clock.now(),
         )
@@ -121,11 +166,427 @@ where
         self.gcra.test_n_all_and_update::<K, C::Instant, S, MW>(
             self.start,
             key,
+            self.key_format(),
+            n,
+            &self.state,
+            self.clock.now(),
+        )
+    }
+
+    /// Allow *only all* `n` cells through the rate limiter for the given key, if they would all
+    /// conform within `grace`.
+    ///
+    /// This is the keyed counterpart to [`check_n_with_grace`](#method.check_n_with_grace).
+    pub fn check_key_n_with_grace(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+        grace: Duration,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        self.gcra
+            .test_n_all_with_grace_and_update::<K, C::Instant, S, MW>(
+                self.start,
+                key,
+                self.key_format(),
+                n,
+                Nanos::saturating_from(grace),
+                &self.state,
+                self.clock.now(),
+            )
+    }
+
+    /// Allow as many of `n` cells through the rate limiter for the given key as it could ever
+    /// accommodate.
+    ///
+    /// This is the keyed counterpart to [`check_n_clamped`](#method.check_n_clamped).
+    pub fn check_key_n_clamped(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Result<Clamped<MW::PositiveOutcome>, MW::NegativeOutcome> {
+        let (admitted, decision) = self.gcra.test_n_clamped_and_update::<K, C::Instant, S, MW>(
+            self.start,
+            key,
+            self.key_format(),
+            n,
+            &self.state,
+            self.clock.now(),
+        );
+        decision.map(|outcome| Clamped::new(n, admitted, outcome))
+    }
+
+    /// Allow cells that cost a fractional number of cells through the rate limiter for the
+    /// given key, only if the whole cost can be accommodated.
+    ///
+    /// This is the keyed counterpart to [`check_cost`](#method.check_cost): fractional
+    /// remainders accumulate in the state of each key, and costs that exceed the burst size or
+    /// are negative or NaN are rejected with the same [`CostError`]s.
+    pub fn check_key_cost(
+        &self,
+        key: &K,
+        cost: f64,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, CostError> {
+        self.gcra.test_cost_and_update::<K, C::Instant, S, MW>(
+            self.start,
+            key,
+            self.key_format(),
+            cost,
+            &self.state,
+            self.clock.now(),
+        )
+    }
+
+    /// Allow a single cell through the rate limiter for each of the given keys, only if all of
+    /// them allow it through.
+    ///
+    /// This is for operations that count against several keys at once, e.g. both the sender and
+    /// the recipient of a message. If any of the keys would rate-limit the cell, no capacity is
+    /// consumed for any of them, and the negative outcome is returned for the most restrictive
+    /// key: the one that takes the longest to allow the cell through. Otherwise, the positive
+    /// outcomes are returned in the order of `keys`. Keys that occur more than once get one cell
+    /// through per occurrence.
+    ///
+    /// Whether other decisions can see some keys' capacity consumed while others are still
+    /// being checked depends on the state store (see [`StateStore::measure_and_replace_all`]):
+    /// The [`HashMapStateStore`] checks all keys under its lock, while the
+    /// [`DashMapStateStore`] checks them one at a time
+    /// and gives capacity back if a later key rate-limits the cell.
+    ///
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{Quota, RateLimiter};
+    ///
+    /// let lim = RateLimiter::keyed(Quota::per_hour(nonzero!(1u32)));
+    /// assert!(lim.check_key(&"bob").is_ok());
+    ///
+    /// let (denied, _) = lim.check_keys_all(&[&"alice", &"bob"]).unwrap_err();
+    /// assert_eq!(denied, &"bob");
+    /// // alice's capacity wasn't used up:
+    /// assert!(lim.check_keys_all(&[&"alice", &"carol"]).is_ok());
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn check_keys_all<'k>(
+        &self,
+        keys: &[&'k K],
+    ) -> Result<Vec<MW::PositiveOutcome>, (&'k K, MW::NegativeOutcome)> {
+        self.gcra.test_all_keys_and_update::<K, C::Instant, S, MW>(
+            self.start,
+            keys,
+            self.key_format(),
+            &self.state,
+            self.clock.now(),
+        )
+    }
+
+    /// Checks batches of cells for many keys at once, returning one decision per item.
+    ///
+    /// Each item is decided like [`check_key_n`](#method.check_key_n) would, in the order of
+    /// `items`, and all decisions are made at the same time. Items for the same key are
+    /// checked with a single access to the key's state, which saves hashing and locking when
+    /// ingesting large batches of events that share keys.
+    ///
+    /// # Example
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{Quota, RateLimiter};
+    ///
+    /// let lim = RateLimiter::keyed(Quota::per_second(nonzero!(5u32)));
+    /// let decisions = lim.check_keys_batch(vec![
+    ///     ("alice", nonzero!(3u32)),
+    ///     ("bob", nonzero!(1u32)),
+    ///     ("alice", nonzero!(3u32)),
+    ///     ("alice", nonzero!(2u32)),
+    ///     ("bob", nonzero!(6u32)),
+    /// ]);
+    /// assert!(matches!(decisions[0], Ok(Ok(()))));
+    /// assert!(matches!(decisions[1], Ok(Ok(()))));
+    /// assert!(matches!(decisions[2], Ok(Err(_))));
+    /// assert!(matches!(decisions[3], Ok(Ok(()))));
+    /// assert!(decisions[4].is_err()); // exceeds the burst size
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn check_keys_batch<I>(
+        &self,
+        items: I,
+    ) -> Vec<Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity>>
+    where
+        I: IntoIterator<Item = (K, NonZeroU32)>,
+        K: Eq + Clone,
+    {
+        // Group the items by key, remembering where each item's decision goes:
+        let mut group_of: HashMap<K, usize> = HashMap::new();
+        let mut groups: Vec<(K, Vec<usize>, Vec<NonZeroU32>)> = vec![];
+        let mut len = 0;
+        for (i, (key, n)) in items.into_iter().enumerate() {
+            let group = *group_of.entry(key.clone()).or_insert_with(|| {
+                groups.push((key, vec![], vec![]));
+                groups.len() - 1
+            });
+            groups[group].1.push(i);
+            groups[group].2.push(n);
+            len = i + 1;
+        }
+
+        let now = self.clock.now();
+        let mut decisions: Vec<_> = (0..len).map(|_| None).collect();
+        for (key, indexes, batches) in groups {
+            let group_decisions = self.gcra.test_batches_and_update::<K, C::Instant, S, MW>(
+                self.start,
+                &key,
+                self.key_format(),
+                &batches,
+                &self.state,
+                now,
+            );
+            for (i, decision) in indexes.into_iter().zip(group_decisions) {
+                decisions[i] = Some(decision);
+            }
+        }
+        // Every item is in exactly one group, so every item got a decision:
+        decisions.into_iter().flatten().collect()
+    }
+}
+
+/// # Keyed rate limiters - Checking and consuming cells separately
+///
+/// These methods split [`check_key`](#method.check_key) into a decision that leaves the key's
+/// state untouched ([`check_key_only`](#method.check_key_only),
+/// [`check_key_n_only`](#method.check_key_n_only)) and accounting that counts cells regardless
+/// of whether they conform ([`consume_key`](#method.consume_key),
+/// [`consume_key_n`](#method.consume_key_n)).
+///
+/// They come with the same atomicity guarantees as [their direct
+/// counterparts](#direct-rate-limiters---checking-and-consuming-cells-separately), per key: a
+/// `check_key_only` followed by a `consume_key` for the same key can race with other threads,
+/// and the key's state can be overdrawn by the cells of racing `consume_key` calls.
+///
+/// The state stores in this crate don't create an entry for a key that `check_key_only` or
+/// [`get_snapshot`](#method.get_snapshot) look at; state stores that don't override
+/// [`StateStore::measure_and_peek`] may.
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Tests whether a single cell would be allowed through the rate limiter for the given
+    /// key, without consuming any capacity.
+    pub fn check_key_only(&self, key: &K) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.gcra.test_peek::<K, C::Instant, S, MW>(
+            self.start,
+            key,
+            self.key_format(),
+            &self.state,
+            self.clock.now(),
+        )
+    }
+
+    /// Tests whether *all* `n` cells would be allowed through the rate limiter for the given
+    /// key, without consuming any capacity.
+    ///
+    /// The results correspond to those of [`check_key_n`](#method.check_key_n).
+    pub fn check_key_n_only(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        self.gcra.test_n_all_peek::<K, C::Instant, S, MW>(
+            self.start,
+            key,
+            self.key_format(),
             n,
             &self.state,
             self.clock.now(),
         )
     }
+
+    /// Returns the rate limiting state of the given key, without consuming any capacity or
+    /// creating an entry for the key.
+    ///
+    /// Returns `None` if the rate limiter has not made any decisions for the key (or if its
+    /// entry was removed by [`retain_recent`](#method.retain_recent)), i.e. if the key has its
+    /// full burst capacity available. This is useful for observability endpoints that report
+    /// on arbitrary keys.
+    pub fn get_snapshot(&self, key: &K) -> Option<StateSnapshot> {
+        self.gcra
+            .peek_snapshot(self.start, key, &self.state, self.clock.now())
+    }
+
+    /// Counts a single cell against the given key, whether or not it conforms.
+    ///
+    /// If the cell did not conform, the negative outcome indicates when the next cell could be
+    /// allowed through for that key.
+    pub fn consume_key(&self, key: &K) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        self.gcra.consume::<K, C::Instant, S, MW>(
+            self.start,
+            key,
+            self.key_format(),
+            &self.state,
+            self.clock.now(),
+        )
+    }
+
+    /// Counts `n` cells against the given key, whether or not they conform.
+    ///
+    /// Returns `Err(InsufficientCapacity)` without counting anything if the quota's burst size
+    /// is too low for `n` cells to ever be allowed through.
+    pub fn consume_key_n(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, InsufficientCapacity> {
+        self.gcra.consume_n::<K, C::Instant, S, MW>(
+            self.start,
+            key,
+            self.key_format(),
+            n,
+            &self.state,
+            self.clock.now(),
+        )
+    }
+}
+
+/// # Keyed rate limiters - Rejecting long waits
+///
+/// See [the direct equivalents](#direct-rate-limiters---rejecting-long-waits).
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Reserves a single cell for the given key, if it would conform to the rate limit within
+    /// `threshold`.
+    ///
+    /// See [`check_or_reject_if_wait_exceeds`](#method.check_or_reject_if_wait_exceeds).
+    pub fn check_key_or_reject_if_wait_exceeds(
+        &self,
+        key: &K,
+        threshold: Duration,
+    ) -> Result<Reservation<MW::PositiveOutcome>, MW::NegativeOutcome> {
+        self.gcra
+            .reserve_within::<K, C::Instant, S, MW>(
+                self.start,
+                key,
+                self.key_format(),
+                Nanos::saturating_from(threshold),
+                &self.state,
+                self.clock.now(),
+            )
+            .map(|(wait, outcome)| Reservation::new(wait, outcome))
+    }
+}
+
+/// # Keyed rate limiters - Planning ahead
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the times at which each of the next `n` cells would be allowed through for the
+    /// given key, if no other cells were checked for it in the meantime.
+    ///
+    /// Like [`check_key_only`](#method.check_key_only), this does not consume any capacity, but
+    /// may add an empty entry for the key to the state store. See
+    /// [the direct equivalent](#method.next_allowed_times) for details.
+    pub fn next_allowed_times_for_key(&self, key: &K, n: usize) -> Vec<C::Instant> {
+        self.gcra
+            .projected_times(self.start, key, n, &self.state, self.clock.now())
+    }
+}
+
+/// # Keyed rate limiters - Idle detection
+///
+/// These are the per-key counterparts to the [direct rate limiters' idle
+/// detection](#direct-rate-limiters---idle-detection). Keys that have no state (e.g. because
+/// they were never checked, or were removed by [`retain_recent`](#method.retain_recent)) count
+/// as idle since the rate limiter's start.
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns how long the rate limiter has gone without letting any cells through for the
+    /// given key.
+    ///
+    /// Like [`check_key_only`](#method.check_key_only), this may add an empty entry for the key
+    /// to state stores that don't override [`StateStore::measure_and_peek`].
+    pub fn key_idle_for(&self, key: &K) -> Duration {
+        self.gcra
+            .idle_time(self.start, key, &self.state, self.clock.now())
+            .into()
+    }
+
+    /// Returns `true` if the rate limiter hasn't let any cells through for the given key for at
+    /// least `threshold`.
+    pub fn is_key_idle(&self, key: &K, threshold: Duration) -> bool {
+        self.key_idle_for(key) >= threshold
+    }
+}
+
+/// # Keyed rate limiters - Available capacity
+///
+/// The per-key counterpart to the [direct rate limiters' available
+/// capacity](#direct-rate-limiters---available-capacity).
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the number of cells that the rate limiter could let through for the given key
+    /// right now, including the fraction of the next cell that has been replenished so far.
+    ///
+    /// Keys without a state have their whole burst capacity available. Like
+    /// [`check_key_only`](#method.check_key_only), this may add an empty entry for the key to
+    /// state stores that don't override [`StateStore::measure_and_peek`].
+    pub fn key_available_cells_f64(&self, key: &K) -> f64 {
+        self.gcra
+            .available_cells(self.start, key, &self.state, self.clock.now())
+    }
+}
+
+/// # Keyed rate limiters - Checking cells without middleware overhead
+impl<K, S, C> RateLimiter<K, S, C, NoOpMiddleware<C::Instant>>
+where
+    S: KeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+{
+    /// Allow a single cell through the rate limiter for the given key, taking the shortest
+    /// possible path through the rate limiter's code.
+    ///
+    /// This returns the same results as [`check_key`](#method.check_key), but skips
+    /// constructing the state snapshot that the [`NoOpMiddleware`] would ignore on positive
+    /// decisions.
+    pub fn check_key_bare(&self, key: &K) -> Result<(), NotUntil<C::Instant>> {
+        self.gcra.test_and_update_bare::<K, C::Instant, S>(
+            self.start,
+            key,
+            &self.state,
+            self.clock.now(),
+        )
+    }
+
+    /// Allow a single cell through the rate limiter for the given key, returning only whether
+    /// it was let through.
+    ///
+    /// This is the keyed counterpart to [`check_fast`](#method.check_fast).
+    pub fn check_key_fast(&self, key: &K) -> bool {
+        self.gcra.test_and_update_fast::<K, C::Instant, S>(
+            self.start,
+            key,
+            &self.state,
+            self.clock.now(),
+        )
+    }
 }
 
 /// Keyed rate limiters that can be "cleaned up".
@@ -158,6 +619,71 @@ pub trait ShrinkableKeyedStateStore<K: Hash>: KeyedStateStore<K> {
     /// imprecise results (indicating that the state store is empty
     /// while a concurrent rate-limiting operation is taking place).
     fn is_empty(&self) -> bool;
+
+    /// Returns an estimate of the number of bytes of memory used by the state store.
+    ///
+    /// The estimate is a lower bound: it only accounts for the in-line size of keys and rate
+    /// limiting states and for the bookkeeping overhead of the store that it can see. Memory
+    /// that keys allocate on the heap (e.g. the contents of a [`String`] key), the allocator's
+    /// own overhead, and internals that a store doesn't expose (like the per-shard tables
+    /// of a [`DashMapStateStore`]) are not included.
+    ///
+    /// The default implementation multiplies [`len`](#tymethod.len) by the size of a key
+    /// and its rate limiting state. State stores that can report more accurate numbers
+    /// (e.g. because they pre-allocate space for more keys) should override it.
+    fn approx_memory_bytes(&self) -> usize {
+        self.len() * (mem::size_of::<K>() + mem::size_of::<InMemoryState>())
+    }
+
+    /// Makes room for `keys` in the state store, without giving them a rate limiting state.
+    ///
+    /// Keys that are already present keep their state. If the state store can not hold keys
+    /// without a state, this method is a no-op.
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, _keys: I) {}
+
+    /// Returns `true` if the state store keeps an entry for `key`, without creating one.
+    ///
+    /// The default implementation checks whether [`measure_and_peek`](StateStore::measure_and_peek)
+    /// finds a rate limiting state for the key. State stores that can hold keys without a state
+    /// (e.g. [pre-seeded](#method.pre_seed) ones) should override it.
+    fn contains_key(&self, key: &K) -> bool {
+        self.measure_and_peek(key, |tat| Ok::<_, Infallible>(tat.is_some()))
+            .unwrap_or_else(|never| match never {})
+    }
+
+    /// Returns the keys with a rate limiting state newer than `drop_below`, along with their
+    /// states.
+    ///
+    /// These are exactly the keys that [`retain_recent`](#tymethod.retain_recent) would keep.
+    /// The default implementation returns no keys; state stores that can enumerate their keys
+    /// should override it.
+    fn export_states(&self, _drop_below: Nanos) -> Vec<(K, Nanos)> {
+        Vec::new()
+    }
+}
+
+/// Estimates the memory used by the table of a [`HashMap`][std::collections::HashMap] (as
+/// implemented by `hashbrown`) that can hold `capacity` entries of type `T` without
+/// reallocating.
+pub(crate) fn hash_table_bytes<T>(capacity: usize) -> usize {
+    // Group width of hashbrown's SIMD control-byte scans; an upper bound on all platforms.
+    const GROUP_WIDTH: usize = 16;
+
+    if capacity == 0 {
+        return 0;
+    }
+    // hashbrown keeps its tables at most 7/8 full, and always allocates a power of two
+    // buckets:
+    let buckets = if capacity < 4 {
+        4
+    } else if capacity < 8 {
+        8
+    } else {
+        (capacity.saturating_mul(8) / 7).next_power_of_two()
+    };
+    // Each bucket holds one entry and one control byte; the control bytes are padded by
+    // one group.
+    buckets * (mem::size_of::<T>() + 1) + GROUP_WIDTH
 }
 
 /// # Keyed rate limiters - Housekeeping
@@ -182,7 +708,9 @@ where
         // arrival time is larger than a starting state for the bucket gets to stay, everything
         // else (that's indistinguishable from a starting state) goes.
         let now = self.clock.now();
-        let drop_below = now.duration_since(self.start).saturating_sub(self.gcra.t());
+        let drop_below = now
+            .duration_since(self.start)
+            .saturating_sub(self.gcra.stale_after());
 
         self.state.retain_recent(drop_below);
     }
@@ -208,17 +736,142 @@ where
     pub fn is_empty(&self) -> bool {
         self.state.is_empty()
     }
+
+    /// Returns `true` if the rate limiter's state store has an entry for `key`.
+    ///
+    /// Unlike checking the key, this never creates an entry for it.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.state.contains_key(key)
+    }
+
+    /// Returns an estimate of the number of bytes of memory used by the rate limiter's
+    /// state store. The actual memory use may be higher.
+    ///
+    /// This is useful for alerting on an unexpected growth of the key set before it
+    /// exhausts the memory available to a process. See
+    /// [`ShrinkableKeyedStateStore::approx_memory_bytes`] for what the estimate covers.
+    pub fn approx_memory_bytes(&self) -> usize {
+        self.state.approx_memory_bytes()
+    }
+
+    /// Inserts entries for `keys` into the rate limiter's state store, without using up any of
+    /// their capacity.
+    ///
+    /// Services that know the set of keys they will see (e.g. their tenants) can call this at
+    /// startup, so that the state store doesn't grow piecemeal as the first requests come in.
+    /// Pre-seeded keys are indistinguishable from fresh ones, so
+    /// [`retain_recent`](#method.retain_recent) removes them again until they are used.
+    pub fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        self.state.pre_seed(keys);
+    }
+}
+
+/// # Keyed rate limiters - Housekeeping with re-anchoring
+///
+/// Rate limiting states are stored as nanoseconds since the rate limiter's
+/// [start](#method.start), in a `u64`. They don't lose precision as the rate limiter runs, but
+/// they do use up the headroom left before the offsets overflow (after about 584 years). Rate
+/// limiters that a long-running service has exclusive access to can move their start forward
+/// while they drop stale keys, keeping the stored offsets small.
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: ShrinkableKeyedStateStore<K> + RebasableStateStore,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Retains all keys in the rate limiter that were used recently enough, like
+    /// [`retain_recent`](#method.retain_recent), and then
+    /// [rebases](#method.rebase_start) the rate limiter onto the time before which the dropped
+    /// keys' states lie.
+    ///
+    /// The retained keys' states all lie after the new start, so the rate limiter makes the
+    /// same decisions as before.
+    pub fn retain_recent_and_rebase(&mut self) {
+        let now = self.clock.now();
+        let drop_below = now
+            .duration_since(self.start)
+            .saturating_sub(self.gcra.stale_after());
+
+        self.state.retain_recent(drop_below);
+        self.rebase_start(self.start + drop_below);
+    }
 }
 
 mod hashmap;
 
-pub use hashmap::HashMapStateStore;
+pub use hashmap::{HashMapStateStore, HashMapStateStoreWithHasher};
+
+#[cfg(feature = "spin")]
+pub use hashmap::{SpinHashMapStateStore, SpinHashMapStateStoreWithHasher};
 
 #[cfg(all(feature = "std", feature = "dashmap"))]
 mod dashmap;
 
 #[cfg(all(feature = "std", feature = "dashmap"))]
-pub use self::dashmap::DashMapStateStore;
+pub use self::dashmap::{DashMapStateStore, DashMapStateStoreWithHasher};
+
+#[cfg(all(feature = "std", feature = "dashmap"))]
+mod denials;
+
+#[cfg(all(feature = "std", feature = "dashmap"))]
+pub use self::denials::{DenialTrackingState, DenialTrackingStateStore};
+
+#[cfg(all(feature = "std", feature = "skiplist"))]
+mod skiplist;
+
+#[cfg(all(feature = "std", feature = "skiplist"))]
+pub use self::skiplist::SkipListStateStore;
+
+mod fixed;
+
+#[cfg(feature = "std")]
+mod replicated;
+
+#[cfg(feature = "std")]
+pub use self::replicated::ReplicatingStateStore;
+
+#[cfg(feature = "std")]
+mod admission_log;
+
+#[cfg(feature = "std")]
+pub use self::admission_log::{AdmissionLogStateStore, AdmissionRecord};
+
+#[cfg(feature = "std")]
+mod skewed;
+
+#[cfg(feature = "std")]
+pub use self::skewed::{ClockSkew, SkewedStateStore};
+
+#[cfg(feature = "std")]
+mod probation;
+
+#[cfg(feature = "std")]
+pub use self::probation::ProbationStateStore;
+
+#[cfg(feature = "std")]
+mod persistent;
+
+#[cfg(feature = "std")]
+pub use self::persistent::{PersistentStateStore, StateJournal};
+
+#[cfg(feature = "std")]
+mod any_key;
+
+#[cfg(feature = "std")]
+pub use self::any_key::AnyKey;
+
+#[cfg(feature = "std")]
+mod capped;
+
+#[cfg(feature = "std")]
+pub use self::capped::{CappedStateStore, WhenFull};
+
+pub use self::fixed::{DefaultHashBuilder, FixedCapacityStateStore, FnvHasher, StoreFull};
+
+mod seeded;
+
+pub use self::seeded::SeededHashBuilder;
 
 #[cfg(feature = "std")]
 mod future;
@@ -286,7 +939,20 @@ mod test {
         assert_eq!(lim.check_key(&1u32), Ok(()));
         assert!(lim.is_empty());
         assert_eq!(lim.len(), 0);
+        assert_eq!(lim.approx_memory_bytes(), 0);
         lim.retain_recent();
         lim.shrink_to_fit();
     }
+
+    #[test]
+    fn hash_table_bytes_estimates() {
+        assert_eq!(hash_table_bytes::<u64>(0), 0);
+        // Small tables have a minimum number of buckets:
+        assert_eq!(hash_table_bytes::<u64>(1), 4 * 9 + 16);
+        assert_eq!(hash_table_bytes::<u64>(3), 4 * 9 + 16);
+        assert_eq!(hash_table_bytes::<u64>(7), 8 * 9 + 16);
+        // Larger tables are kept at most 7/8 full:
+        assert_eq!(hash_table_bytes::<u64>(14), 16 * 9 + 16);
+        assert_eq!(hash_table_bytes::<u64>(15), 32 * 9 + 16);
+    }
 }
@@ -0,0 +1,297 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use crate::clock;
+use crate::middleware::RateLimitingMiddleware;
+use crate::nanos::Nanos;
+use crate::state::keyed::{KeyedStateStore, ShrinkableKeyedStateStore};
+use crate::state::{RebasableStateStore, StateStore};
+use crate::sync::Mutex;
+use crate::RateLimiter;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::num::NonZeroUsize;
+
+/// A rate-limiting decision, as recorded by an [`AdmissionLogStateStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionRecord<P: clock::Reference> {
+    at: P,
+    key_hash: u64,
+    allowed: bool,
+    remaining_burst_capacity: u32,
+}
+
+impl<P: clock::Reference> AdmissionRecord<P> {
+    /// The time at which the decision was made.
+    pub fn at(&self) -> P {
+        self.at
+    }
+
+    /// A hash of the key that the decision was made for.
+    ///
+    /// Keys are hashed with a fixed hash function; use [`is_for`](#method.is_for) to tell
+    /// whether the decision was made for a known key.
+    pub fn key_hash(&self) -> u64 {
+        self.key_hash
+    }
+
+    /// Returns `true` if the decision was made for `key` (or for a key with the same hash).
+    pub fn is_for<K: Hash>(&self, key: &K) -> bool {
+        self.key_hash == key_hash(key)
+    }
+
+    /// Whether the cells were allowed through.
+    pub fn is_allowed(&self) -> bool {
+        self.allowed
+    }
+
+    /// The number of cells that could have been let through for the key right after the
+    /// decision.
+    pub fn remaining_burst_capacity(&self) -> u32 {
+        self.remaining_burst_capacity
+    }
+}
+
+/// Hashes a key for recording it, the same way in all rate limiters.
+fn key_hash<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A decision as it gets recorded while rate limiting: cheap to construct, and independent of
+/// the rate limiter's parameters.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    t0: Nanos,
+    key_hash: u64,
+    /// The state after a positive decision, or the state a negative decision was made on.
+    tat: Option<Nanos>,
+    allowed: bool,
+}
+
+/// A keyed state store that remembers the most recent rate-limiting decisions, e.g. for
+/// investigating an incident after the fact.
+///
+/// The store keeps the last `capacity` decisions that were made with it in a ring buffer:
+/// when each decision was made, a hash of its key, whether it was positive, and the state it
+/// left behind. [`RateLimiter::recent_decisions`] returns them as [`AdmissionRecord`]s.
+///
+/// Unlike a [middleware](crate::middleware), which only knows its type and can't keep a
+/// buffer per rate limiter, this store records every decision that gets made with it, no
+/// matter how many threads make them concurrently. Recording a decision takes a short-lived
+/// lock on the buffer.
+///
+/// Only the decisions of the `check` family of methods get recorded; decisions that don't
+/// consume capacity (like [`check_key_only`](RateLimiter::check_key_only)) are not.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{
+///     clock::FakeRelativeClock, middleware::NoOpMiddleware,
+///     state::keyed::{AdmissionLogStateStore, HashMapStateStore},
+///     Quota, RateLimiter,
+/// };
+///
+/// let store = AdmissionLogStateStore::new(HashMapStateStore::default(), nonzero!(100usize));
+/// let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+///     Quota::per_second(nonzero!(2u32)),
+///     store,
+///     FakeRelativeClock::default(),
+/// );
+/// for _ in 0..3 {
+///     let _ = lim.check_key(&"alice");
+/// }
+///
+/// let log = lim.recent_decisions();
+/// let outcomes: Vec<_> = log.iter().map(|d| d.is_allowed()).collect();
+/// assert_eq!(outcomes, [true, true, false]);
+/// assert_eq!(log[0].remaining_burst_capacity(), 1);
+/// assert!(log.iter().all(|d| d.is_for(&"alice")));
+/// ```
+pub struct AdmissionLogStateStore<S> {
+    inner: S,
+    capacity: NonZeroUsize,
+    log: Mutex<VecDeque<Entry>>,
+}
+
+impl<S> AdmissionLogStateStore<S> {
+    /// Wraps `inner`, remembering the last `capacity` decisions made with it.
+    pub fn new(inner: S, capacity: NonZeroUsize) -> Self {
+        AdmissionLogStateStore {
+            inner,
+            capacity,
+            log: Mutex::new(VecDeque::with_capacity(capacity.get())),
+        }
+    }
+
+    /// Returns the wrapped state store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the number of decisions that the store remembers.
+    pub fn capacity(&self) -> NonZeroUsize {
+        self.capacity
+    }
+
+    fn record(&self, entry: Entry) {
+        let mut log = self.log.lock();
+        if log.len() == self.capacity.get() {
+            log.pop_front();
+        }
+        log.push_back(entry);
+    }
+}
+
+impl<S> StateStore for AdmissionLogStateStore<S>
+where
+    S: StateStore,
+    S::Key: Hash,
+{
+    type Key = S::Key;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        self.inner.measure_and_replace(key, f)
+    }
+
+    fn note_denial(&self, key: &Self::Key, t0: Nanos) {
+        let tat = self
+            .inner
+            .measure_and_peek(key, Ok::<_, ()>)
+            .unwrap_or_default();
+        self.record(Entry {
+            t0,
+            key_hash: key_hash(key),
+            tat,
+            allowed: false,
+        });
+        self.inner.note_denial(key, t0)
+    }
+
+    fn note_admission(&self, key: &Self::Key, t0: Nanos, tat: Nanos) {
+        self.record(Entry {
+            t0,
+            key_hash: key_hash(key),
+            tat: Some(tat),
+            allowed: true,
+        });
+        self.inner.note_admission(key, t0, tat)
+    }
+
+    fn note_wait_started(&self, key: &Self::Key) {
+        self.inner.note_wait_started(key)
+    }
+
+    fn note_wait_finished(&self, key: &Self::Key) {
+        self.inner.note_wait_finished(key)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        self.inner.measure_and_peek(key, f)
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        self.inner.restore(key, replaced, prev)
+    }
+}
+
+impl<S> RebasableStateStore for AdmissionLogStateStore<S>
+where
+    S: RebasableStateStore,
+    S::Key: Hash,
+{
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        for entry in self.log.get_mut().iter_mut() {
+            entry.t0 = f(entry.t0);
+            entry.tat = entry.tat.map(&f);
+        }
+        self.inner.rewrite_states(f)
+    }
+}
+
+impl<K, S> ShrinkableKeyedStateStore<K> for AdmissionLogStateStore<S>
+where
+    K: Hash + Eq + Clone,
+    S: ShrinkableKeyedStateStore<K>,
+{
+    fn retain_recent(&self, drop_below: Nanos) {
+        self.inner.retain_recent(drop_below)
+    }
+
+    fn shrink_to_fit(&self) {
+        self.inner.shrink_to_fit()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        self.inner.approx_memory_bytes() + self.capacity.get() * mem::size_of::<Entry>()
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        self.inner.pre_seed(keys)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        self.inner.export_states(drop_below)
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for AdmissionLogStateStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdmissionLogStateStore")
+            .field("inner", &self.inner)
+            .field("capacity", &self.capacity)
+            .field("recorded", &self.log.lock().len())
+            .finish()
+    }
+}
+
+/// # Keyed rate limiters - Admission log
+impl<K, S, C, MW> RateLimiter<K, AdmissionLogStateStore<S>, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the most recent decisions made by the rate limiter, oldest first.
+    ///
+    /// See [`AdmissionLogStateStore`] for which decisions get recorded.
+    pub fn recent_decisions(&self) -> Vec<AdmissionRecord<C::Instant>> {
+        let log = self.state.log.lock();
+        log.iter()
+            .map(|entry| AdmissionRecord {
+                at: self.start + entry.t0,
+                key_hash: entry.key_hash,
+                allowed: entry.allowed,
+                remaining_burst_capacity: self.gcra.remaining_cells(entry.tat, entry.t0),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+
+/// A key that can hold any of the identities that requests are commonly rate limited by.
+///
+/// Subsystems that rate limit requests by different kinds of identities (e.g. by user ID if a
+/// request is authenticated, by API key for services, and by IP address otherwise) can use one
+/// keyed rate limiter for all of them, instead of one per kind of identity. Keys of different
+/// kinds never collide, even if their values look alike:
+///
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{state::keyed::AnyKey, Quota, RateLimiter};
+/// use std::net::Ipv4Addr;
+///
+/// let lim = RateLimiter::keyed(Quota::per_minute(nonzero!(1u32)));
+/// assert!(lim.check_key(&AnyKey::from(42u64)).is_ok());
+/// assert!(lim.check_key(&AnyKey::from("42")).is_ok());
+/// assert!(lim.check_key(&AnyKey::from(Ipv4Addr::new(192, 0, 2, 42))).is_ok());
+/// assert!(lim.check_key(&AnyKey::from(42u64)).is_err());
+/// ```
+///
+/// All keys share the rate limiter's quota; kinds of identities that need different quotas
+/// still need rate limiters of their own.
+///
+/// # Performance
+///
+/// An `AnyKey` takes up 24 bytes, where a `u64` key takes 8, and hashing one hashes its kind
+/// along with its value. The only kind that costs more than that is [`AnyKey::Name`]: Hashing
+/// it hashes the whole name, and constructing it from a `&str` or `String` allocates, so hot
+/// paths should keep the key around (cloning it only copies a pointer). The `any_key`
+/// benchmarks in the `single_threaded` group compare a rate limiter with `AnyKey`s to separate
+/// rate limiters per kind of identity.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum AnyKey {
+    /// A numeric identity, e.g. a user ID.
+    Id(u64),
+
+    /// An IP address.
+    Ip(IpAddr),
+
+    /// A textual identity, e.g. an API key or a user name.
+    Name(Arc<str>),
+}
+
+impl fmt::Display for AnyKey {
+    /// Shows the key's kind along with its value, e.g. `id:42` or `ip:192.0.2.1`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnyKey::Id(id) => write!(f, "id:{}", id),
+            AnyKey::Ip(ip) => write!(f, "ip:{}", ip),
+            AnyKey::Name(name) => write!(f, "name:{}", name),
+        }
+    }
+}
+
+impl From<u64> for AnyKey {
+    fn from(id: u64) -> Self {
+        AnyKey::Id(id)
+    }
+}
+
+impl From<u32> for AnyKey {
+    fn from(id: u32) -> Self {
+        AnyKey::Id(id.into())
+    }
+}
+
+impl From<IpAddr> for AnyKey {
+    fn from(ip: IpAddr) -> Self {
+        AnyKey::Ip(ip)
+    }
+}
+
+impl From<Ipv4Addr> for AnyKey {
+    fn from(ip: Ipv4Addr) -> Self {
+        AnyKey::Ip(ip.into())
+    }
+}
+
+impl From<Ipv6Addr> for AnyKey {
+    fn from(ip: Ipv6Addr) -> Self {
+        AnyKey::Ip(ip.into())
+    }
+}
+
+impl From<Arc<str>> for AnyKey {
+    fn from(name: Arc<str>) -> Self {
+        AnyKey::Name(name)
+    }
+}
+
+impl From<&str> for AnyKey {
+    fn from(name: &str) -> Self {
+        AnyKey::Name(name.into())
+    }
+}
+
+impl From<String> for AnyKey {
+    fn from(name: String) -> Self {
+        AnyKey::Name(name.into())
+    }
+}
@@ -0,0 +1,404 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use crate::clock;
+use crate::middleware::RateLimitingMiddleware;
+use crate::nanos::Nanos;
+use crate::state::keyed::{hash_table_bytes, ShrinkableKeyedStateStore};
+use crate::state::{RebasableStateStore, StateStore};
+use crate::sync::Mutex;
+use crate::RateLimiter;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::num::NonZeroUsize;
+
+/// What a [`CappedStateStore`] does with a new key when it already holds as many keys as it
+/// may.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WhenFull {
+    /// Deny all cells for new keys, until keys get removed with
+    /// [`retain_recent`](ShrinkableKeyedStateStore::retain_recent).
+    ///
+    /// The keys that the store already holds keep their states, so a flood of new keys can't
+    /// reset them. The negative decisions for new keys report that the keys won't conform
+    /// before the end of the rate limiter's time horizon (see [`Nanos`]); treat them as "try
+    /// again later" rather than waiting for them.
+    Reject,
+
+    /// Remove the key whose state was least recently used for a decision, to make room for the
+    /// new key.
+    EvictLeastRecentlyUsed,
+
+    /// Remove the key whose state will be indistinguishable from a fresh one the soonest (the
+    /// one with the earliest theoretical arrival time), to make room for the new key.
+    ///
+    /// This evicts the key that loses the least rate limiting state: A key whose state is
+    /// already fresh gets evicted before one that is still limited.
+    EvictStalest,
+}
+
+/// A keyed state store that holds at most a fixed number of keys, and handles new keys on a
+/// full store according to a [`WhenFull`] policy.
+///
+/// The [`HashMap`]- and `DashMap`-backed stores grow with every key they see, so clients that
+/// can choose their keys can make them use arbitrary amounts of memory until
+/// [`retain_recent`](ShrinkableKeyedStateStore::retain_recent) runs. This store puts a hard cap
+/// on the number of keys instead, and makes the trade-off at that cap explicit: Either new keys
+/// get denied ([`WhenFull::Reject`]), or old keys lose their state
+/// ([`WhenFull::EvictLeastRecentlyUsed`], [`WhenFull::EvictStalest`]), which lets evicted keys
+/// through again with their full burst size.
+///
+/// Keys only take up room once a decision for them was positive: Negative decisions for new
+/// keys don't evict anything.
+///
+/// The keys are kept in a [`HashMap`] behind a mutex, along with the order to evict them in.
+/// Keeping that order costs a few more operations per decision than the
+/// [`HashMapStateStore`](super::HashMapStateStore) does.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{
+///     clock::DefaultClock, middleware::NoOpMiddleware,
+///     state::keyed::{CappedStateStore, WhenFull},
+///     Quota, RateLimiter,
+/// };
+///
+/// let store = CappedStateStore::new(nonzero!(2usize), WhenFull::EvictLeastRecentlyUsed);
+/// let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> =
+///     RateLimiter::new(Quota::per_hour(nonzero!(1u32)), store, DefaultClock::default());
+///
+/// assert!(lim.check_key(&"alice").is_ok());
+/// assert!(lim.check_key(&"bob").is_ok());
+/// assert!(lim.check_key(&"alice").is_err());
+///
+/// // Bob's state was used less recently than alice's, so carol's evicts it:
+/// assert!(lim.check_key(&"carol").is_ok());
+/// assert_eq!(lim.len(), 2);
+/// assert_eq!(lim.evicted_keys(), 1);
+/// assert!(lim.check_key(&"alice").is_err());
+/// assert!(lim.check_key(&"bob").is_ok());
+/// ```
+pub struct CappedStateStore<K, H = RandomState> {
+    table: Mutex<Table<K, H>>,
+    max_keys: NonZeroUsize,
+    when_full: WhenFull,
+}
+
+struct Entry<K> {
+    key: K,
+    tat: Nanos,
+    /// The entry's position in the eviction order.
+    rank: u64,
+}
+
+struct Table<K, H> {
+    index: HashMap<K, usize, H>,
+    entries: Vec<Entry<K>>,
+    /// The entries as (rank, index into `entries`), in the order to evict them in. Only kept
+    /// for the eviction policies.
+    order: BTreeSet<(u64, usize)>,
+    /// The rank of the next decision, for [`WhenFull::EvictLeastRecentlyUsed`].
+    tick: u64,
+    rejected: u64,
+    evicted: u64,
+}
+
+impl<K: Hash + Eq> CappedStateStore<K> {
+    /// Constructs a state store that holds at most `max_keys` keys, handling new keys on a full
+    /// store as `when_full` says.
+    pub fn new(max_keys: NonZeroUsize, when_full: WhenFull) -> Self {
+        CappedStateStore::with_hasher(max_keys, when_full, RandomState::new())
+    }
+}
+
+impl<K: Hash + Eq, H: BuildHasher> CappedStateStore<K, H> {
+    /// Constructs a state store that holds at most `max_keys` keys, which it hashes with
+    /// `hasher`, handling new keys on a full store as `when_full` says.
+    pub fn with_hasher(max_keys: NonZeroUsize, when_full: WhenFull, hasher: H) -> Self {
+        CappedStateStore {
+            table: Mutex::new(Table {
+                index: HashMap::with_hasher(hasher),
+                entries: Vec::new(),
+                order: BTreeSet::new(),
+                tick: 0,
+                rejected: 0,
+                evicted: 0,
+            }),
+            max_keys,
+            when_full,
+        }
+    }
+}
+
+impl<K, H> CappedStateStore<K, H> {
+    /// Returns the maximum number of keys the store holds.
+    pub fn max_keys(&self) -> NonZeroUsize {
+        self.max_keys
+    }
+
+    /// Returns what the store does with new keys when it is full.
+    pub fn when_full(&self) -> WhenFull {
+        self.when_full
+    }
+
+    /// Returns the number of decisions for new keys that were denied because the store was
+    /// full.
+    pub fn rejected_keys(&self) -> u64 {
+        self.table.lock().rejected
+    }
+
+    /// Returns the number of keys that were removed to make room for new ones.
+    pub fn evicted_keys(&self) -> u64 {
+        self.table.lock().evicted
+    }
+}
+
+impl<K: Hash + Eq, H: BuildHasher> Table<K, H> {
+    /// Moves the entry at `i` to its new place in the eviction order, after a decision.
+    fn touch(&mut self, i: usize, when_full: WhenFull) {
+        let rank = match when_full {
+            WhenFull::Reject => return,
+            WhenFull::EvictLeastRecentlyUsed => {
+                self.tick += 1;
+                self.tick
+            }
+            WhenFull::EvictStalest => self.entries[i].tat.as_u64(),
+        };
+        let entry = &mut self.entries[i];
+        self.order.remove(&(entry.rank, i));
+        entry.rank = rank;
+        self.order.insert((rank, i));
+    }
+
+    fn push(&mut self, key: K, tat: Nanos, when_full: WhenFull)
+    where
+        K: Clone,
+    {
+        let i = self.entries.len();
+        self.index.insert(key.clone(), i);
+        self.entries.push(Entry { key, tat, rank: 0 });
+        if when_full != WhenFull::Reject {
+            self.order.insert((0, i));
+            self.touch(i, when_full);
+        }
+    }
+
+    /// Removes the entry that comes first in the eviction order.
+    fn evict(&mut self) {
+        if let Some(&(_, i)) = self.order.first() {
+            self.remove(i);
+            self.evicted += 1;
+        }
+    }
+
+    /// Removes the entry at `i`, moving the last entry into its place.
+    fn remove(&mut self, i: usize) {
+        self.order.remove(&(self.entries[i].rank, i));
+        let last = self.entries.len() - 1;
+        if i != last {
+            let moved = &self.entries[last];
+            // Entries are only in the eviction order if the policy keeps one:
+            if self.order.remove(&(moved.rank, last)) {
+                self.order.insert((moved.rank, i));
+            }
+            if let Some(index) = self.index.get_mut(&moved.key) {
+                *index = i;
+            }
+        }
+        let entry = self.entries.swap_remove(i);
+        self.index.remove(&entry.key);
+    }
+
+    /// Rebuilds the index and the eviction order after entries were moved or changed.
+    fn reindex(&mut self, when_full: WhenFull)
+    where
+        K: Clone,
+    {
+        if when_full == WhenFull::EvictStalest {
+            for entry in self.entries.iter_mut() {
+                entry.rank = entry.tat.as_u64();
+            }
+        }
+        self.index.clear();
+        self.order.clear();
+        for (i, entry) in self.entries.iter().enumerate() {
+            self.index.insert(entry.key.clone(), i);
+            if when_full != WhenFull::Reject {
+                self.order.insert((entry.rank, i));
+            }
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, H: BuildHasher> StateStore for CappedStateStore<K, H> {
+    type Key = K;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let mut table = self.table.lock();
+        if let Some(&i) = table.index.get(key) {
+            let decision = f(Some(table.entries[i].tat));
+            if let Ok((_, tat)) = decision {
+                table.entries[i].tat = tat;
+            }
+            // Denials count as uses too: Evicting a key that is being denied would let it
+            // through again.
+            table.touch(i, self.when_full);
+            return decision.map(|(result, _)| result);
+        }
+
+        let full = table.entries.len() >= self.max_keys.get();
+        if full && self.when_full == WhenFull::Reject {
+            table.rejected += 1;
+            return f(Some(Nanos::new(u64::MAX))).map(|(result, _)| result);
+        }
+        let (result, tat) = f(None)?;
+        if full {
+            table.evict();
+        }
+        table.push(key.clone(), tat, self.when_full);
+        Ok(result)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        let table = self.table.lock();
+        match table.index.get(key) {
+            Some(&i) => f(Some(table.entries[i].tat)),
+            None if self.when_full == WhenFull::Reject
+                && table.entries.len() >= self.max_keys.get() =>
+            {
+                f(Some(Nanos::new(u64::MAX)))
+            }
+            None => f(None),
+        }
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        let mut table = self.table.lock();
+        let Some(&i) = table.index.get(key) else {
+            return;
+        };
+        if table.entries[i].tat != replaced {
+            return;
+        }
+        match prev {
+            Some(prev) => {
+                table.entries[i].tat = prev;
+                // Only the stalest-first order depends on the state:
+                if self.when_full == WhenFull::EvictStalest {
+                    table.touch(i, self.when_full);
+                }
+            }
+            // The key takes up no room until it gets let through after all:
+            None => table.remove(i),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, H: BuildHasher> RebasableStateStore for CappedStateStore<K, H> {
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        let table = self.table.get_mut();
+        for entry in table.entries.iter_mut() {
+            entry.tat = f(entry.tat);
+        }
+        table.reindex(self.when_full);
+    }
+}
+
+impl<K: Hash + Eq + Clone, H: BuildHasher> ShrinkableKeyedStateStore<K> for CappedStateStore<K, H> {
+    fn retain_recent(&self, drop_below: Nanos) {
+        let mut table = self.table.lock();
+        let len = table.entries.len();
+        table.entries.retain(|entry| entry.tat > drop_below);
+        if table.entries.len() != len {
+            table.reindex(self.when_full);
+        }
+    }
+
+    fn shrink_to_fit(&self) {
+        let mut table = self.table.lock();
+        table.index.shrink_to_fit();
+        table.entries.shrink_to_fit();
+    }
+
+    fn len(&self) -> usize {
+        self.table.lock().entries.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.table.lock().entries.is_empty()
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        let table = self.table.lock();
+        mem::size_of::<Self>()
+            + hash_table_bytes::<(K, usize)>(table.index.capacity())
+            + table.entries.capacity() * mem::size_of::<Entry<K>>()
+            + table.order.len() * mem::size_of::<(u64, usize)>()
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.table.lock().index.contains_key(key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        let table = self.table.lock();
+        table
+            .entries
+            .iter()
+            .filter(|entry| entry.tat > drop_below)
+            .map(|entry| (entry.key.clone(), entry.tat))
+            .collect()
+    }
+}
+
+impl<K, H> fmt::Debug for CappedStateStore<K, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let table = self.table.lock();
+        f.debug_struct("CappedStateStore")
+            .field("max_keys", &self.max_keys)
+            .field("when_full", &self.when_full)
+            .field("len", &table.entries.len())
+            .field("rejected_keys", &table.rejected)
+            .field("evicted_keys", &table.evicted)
+            .finish()
+    }
+}
+
+/// # Keyed rate limiters - Capped key counts
+impl<K, H, C, MW> RateLimiter<K, CappedStateStore<K, H>, C, MW>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the number of decisions for new keys that were denied because the state store
+    /// was full.
+    ///
+    /// See [`WhenFull::Reject`].
+    pub fn rejected_keys(&self) -> u64 {
+        self.state.rejected_keys()
+    }
+
+    /// Returns the number of keys that were removed from the state store to make room for new
+    /// ones.
+    ///
+    /// See [`WhenFull::EvictLeastRecentlyUsed`] and [`WhenFull::EvictStalest`].
+    pub fn evicted_keys(&self) -> u64 {
+        self.state.evicted_keys()
+    }
+}
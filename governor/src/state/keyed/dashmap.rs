@@ -3,29 +3,125 @@
 use std::prelude::v1::*;
 
 use crate::nanos::Nanos;
-use crate::state::{InMemoryState, StateStore};
+use crate::state::{InMemoryState, RebasableStateStore, StateStore};
 use crate::{clock, Quota, RateLimiter};
-use crate::{middleware::NoOpMiddleware, state::keyed::ShrinkableKeyedStateStore};
+use crate::{
+    middleware::NoOpMiddleware,
+    state::keyed::{hash_table_bytes, ShrinkableKeyedStateStore},
+};
 use dashmap::DashMap;
-use std::hash::Hash;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
 
 /// A concurrent, thread-safe and fairly performant hashmap based on [`DashMap`].
-pub type DashMapStateStore<K> = DashMap<K, InMemoryState>;
+///
+/// # Re-entrancy
+///
+/// DashMap guards each of its shards with a lock, which is not re-entrant: A thread that holds
+/// a shard's lock deadlocks when it tries to take it again. To make sure that rate limiting
+/// middleware (whose [`allow`](crate::middleware::RateLimitingMiddleware::allow) and
+/// [`disallow`](crate::middleware::RateLimitingMiddleware::disallow) hooks run as part of a
+/// decision) can safely check other keys on the same rate limiter, this state store never runs
+/// the closures passed to [`StateStore::measure_and_replace`] while holding a shard lock.
+/// Instead, it reads the key's state, releases the lock, computes the decision, and then
+/// briefly takes the lock again to swap in the new state, starting over if the state changed in
+/// the meantime.
+///
+/// # Checking several keys at once
+///
+/// Holding the locks of several shards at once can deadlock, when two threads take the same
+/// locks in different orders. [`RateLimiter::check_keys_all`] therefore takes one shard lock
+/// at a time: It updates the keys in the order they are given, and if a key rate-limits the
+/// cell, restores the states of the keys before it, in reverse order. This can't deadlock, no
+/// matter in which order keys are passed, but it isn't atomic: Decisions made for the earlier
+/// keys in the meantime may be denied because of capacity that ends up being given back.
+///
+/// # Hashing keys
+///
+/// By default, keys are hashed with the randomly-seeded SipHash-1-3 of [`RandomState`], which
+/// keeps adversaries from choosing keys whose hashes collide (known as HashDoS). Other hashers
+/// can be passed to [`dashmap_with_hasher`](crate::RateLimiter::dashmap_with_hasher), e.g. a
+/// faster one where keys can't be chosen by an adversary.
+pub type DashMapStateStore<K> = DashMapStateStoreWithHasher<K, RandomState>;
 
-impl<K: Hash + Eq + Clone> StateStore for DashMapStateStore<K> {
+/// A [`DashMapStateStore`] that hashes keys with `H`.
+pub type DashMapStateStoreWithHasher<K, H> = DashMap<K, InMemoryState, H>;
+
+impl<K: Hash + Eq + Clone, H: BuildHasher + Clone> StateStore
+    for DashMapStateStoreWithHasher<K, H>
+{
     type Key = K;
 
     fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
     where
         F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
     {
+        // Note that no shard lock may be held across calls to `f`:
+        let mut prev = self
+            .get(key)
+            .and_then(|v| v.measure_and_peek_one(|tat| tat));
+        loop {
+            let (result, new) = f(prev)?;
+            match replace_if_unchanged(self, key, prev, new) {
+                Ok(()) => return Ok(result),
+                Err(current) => prev = current,
+            }
+        }
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        // Only take the shard's read lock to load the state, never across the call to `f`
+        // (which may check other keys), and don't insert an entry for the key:
+        let prev = self
+            .get(key)
+            .and_then(|v| v.measure_and_peek_one(|tat| tat));
+        f(prev)
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        // A zero state reads as no state, and `retain_recent` drops its entry:
         if let Some(v) = self.get(key) {
-            // fast path: measure existing entry
-            return v.measure_and_replace_one(f);
+            v.restore_one(replaced, prev);
+        }
+    }
+}
+
+/// Replaces the state at `key` with `new` if it is still `prev`, inserting an entry for the
+/// key if there is none. Otherwise, returns the current state.
+///
+/// This looks the key up (and takes its shard's lock) once, unless the entry for a state that
+/// was read as `prev` got dropped in the meantime.
+fn replace_if_unchanged<K: Hash + Eq + Clone, H: BuildHasher + Clone>(
+    map: &DashMapStateStoreWithHasher<K, H>,
+    key: &K,
+    prev: Option<Nanos>,
+    new: Nanos,
+) -> Result<(), Option<Nanos>> {
+    if prev.is_some() {
+        // fast path: update the entry that the state was read from, under the read lock
+        if let Some(v) = map.get(key) {
+            return v.compare_and_replace(prev, new);
+        }
+    }
+    // There was no state, so there's most likely no entry either; make one and update that:
+    let entry = map.entry(key.clone()).or_default();
+    entry.compare_and_replace(prev, new)
+}
+
+impl<K: Hash + Eq + Clone, H: BuildHasher + Clone> RebasableStateStore
+    for DashMapStateStoreWithHasher<K, H>
+{
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        for mut state in self.iter_mut() {
+            state.rewrite(&f);
         }
-        // make an entry and measure that:
-        let entry = self.entry(key.clone()).or_default();
-        (*entry).measure_and_replace_one(f)
     }
 }
 
@@ -43,7 +139,9 @@ where
     }
 }
 
-impl<K: Hash + Eq + Clone> ShrinkableKeyedStateStore<K> for DashMapStateStore<K> {
+impl<K: Hash + Eq + Clone, H: BuildHasher + Clone> ShrinkableKeyedStateStore<K>
+    for DashMapStateStoreWithHasher<K, H>
+{
     fn retain_recent(&self, drop_below: Nanos) {
         self.retain(|_, v| !v.is_older_than(drop_below));
     }
@@ -59,4 +157,64 @@ impl<K: Hash + Eq + Clone> ShrinkableKeyedStateStore<K> for DashMapStateStore<K>
     fn is_empty(&self) -> bool {
         self.is_empty()
     }
+
+    fn approx_memory_bytes(&self) -> usize {
+        // DashMap spreads its entries over several shards, each with their own lock and table,
+        // and rounds each table's bucket count up separately; estimating them as one table
+        // leaves out that per-shard overhead, so this is a lower bound.
+        mem::size_of::<Self>() + hash_table_bytes::<(K, InMemoryState)>(self.capacity())
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        for key in keys {
+            self.entry(key).or_default();
+        }
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        DashMap::contains_key(self, key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        self.iter()
+            .filter_map(|entry| {
+                entry
+                    .value()
+                    .measure_and_peek_one(|tat| tat)
+                    .filter(|tat| *tat > drop_below)
+                    .map(|tat| (entry.key().clone(), tat))
+            })
+            .collect()
+    }
+}
+
+/// # Keyed rate limiters - [`DashMap`]-backed, with a custom hasher
+impl<K, H, C> RateLimiter<K, DashMapStateStoreWithHasher<K, H>, C, NoOpMiddleware<C::Instant>>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher + Clone,
+    C: clock::Clock,
+{
+    /// Constructs a new rate limiter with a custom clock, backed by a [`DashMap`] that hashes
+    /// keys with `hasher`.
+    ///
+    /// See [`DashMapStateStore`] for when to choose a hasher.
+    pub fn dashmap_with_hasher_and_clock(quota: Quota, hasher: H, clock: C) -> Self {
+        let state: DashMapStateStoreWithHasher<K, H> = DashMap::with_hasher(hasher);
+        RateLimiter::new(quota, state, clock)
+    }
+}
+
+impl<K, H> RateLimiter<K, DashMapStateStoreWithHasher<K, H>, clock::DefaultClock>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher + Clone,
+{
+    /// Constructs a new keyed rate limiter backed by a [`DashMap`] that hashes keys with
+    /// `hasher`.
+    ///
+    /// See [`DashMapStateStore`] for when to choose a hasher.
+    pub fn dashmap_with_hasher(quota: Quota, hasher: H) -> Self {
+        RateLimiter::dashmap_with_hasher_and_clock(quota, hasher, clock::DefaultClock::default())
+    }
 }
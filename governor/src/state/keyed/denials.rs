@@ -0,0 +1,294 @@
+#![cfg(all(feature = "std", feature = "dashmap"))]
+
+use std::prelude::v1::*;
+
+use crate::clock::{self, Reference};
+use crate::middleware::RateLimitingMiddleware;
+use crate::nanos::Nanos;
+use crate::state::keyed::{hash_table_bytes, ShrinkableKeyedStateStore};
+use crate::state::{InMemoryState, StateStore};
+use crate::RateLimiter;
+use dashmap::DashMap;
+use spinning_top::Spinlock;
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+use std::time::Duration;
+
+/// The number of buckets that a denial window is divided into.
+const BUCKETS: usize = 8;
+
+/// Counts of negative decisions in consecutive, equally-sized periods of time.
+#[derive(Debug, Default)]
+struct DenialBuckets {
+    /// The number of the most recent period that has a bucket.
+    latest: u64,
+
+    /// Counts of denials; the count for period `n` lives at index `n % BUCKETS`.
+    counts: [u32; BUCKETS],
+}
+
+impl DenialBuckets {
+    fn record(&mut self, period: u64) {
+        if period < self.latest.saturating_sub(BUCKETS as u64 - 1) {
+            // Too old to fit into the window; can happen if a thread that made a decision
+            // earlier gets to record it late.
+            return;
+        }
+        if period > self.latest {
+            let stale = (period - self.latest).min(BUCKETS as u64);
+            for i in 1..=stale {
+                self.counts[((self.latest + i) % BUCKETS as u64) as usize] = 0;
+            }
+            self.latest = period;
+        }
+        let count = &mut self.counts[(period % BUCKETS as u64) as usize];
+        *count = count.saturating_add(1);
+    }
+
+    /// Returns the number of denials in the `BUCKETS` periods up to and including `period`.
+    fn count(&self, period: u64) -> u32 {
+        (0..BUCKETS as u64)
+            .filter_map(|i| self.latest.checked_sub(i))
+            .filter(|&p| p + (BUCKETS as u64) > period)
+            .map(|p| self.counts[(p % BUCKETS as u64) as usize])
+            .fold(0, u32::saturating_add)
+    }
+}
+
+/// A rate-limiting state that also keeps track of recent negative decisions.
+///
+/// This is the per-key state kept by the [`DenialTrackingStateStore`].
+#[derive(Default)]
+pub struct DenialTrackingState {
+    state: InMemoryState,
+    denials: Spinlock<DenialBuckets>,
+}
+
+impl fmt::Debug for DenialTrackingState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DenialTrackingState")
+            .field("state", &self.state)
+            .field("denials", &*self.denials.lock())
+            .finish()
+    }
+}
+
+/// A keyed state store that counts each key's recent negative decisions.
+///
+/// Next to each key's rate-limiting state, this store keeps a count of the rate-limited
+/// cells over a sliding window of time (the window advances in steps of 1/8th of its
+/// duration). This allows building rules like "block clients that were denied more than 1000
+/// times in 10 minutes" without keeping a second map with the same keys.
+///
+/// The counts are available through
+/// [`RateLimiter::recent_denials`](../../struct.RateLimiter.html#method.recent_denials).
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// use governor::{
+///     clock::DefaultClock, middleware::NoOpMiddleware, state::keyed::DenialTrackingStateStore,
+///     Quota, RateLimiter,
+/// };
+///
+/// let store = DenialTrackingStateStore::new(Duration::from_secs(600));
+/// let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> =
+///     RateLimiter::new(Quota::per_hour(nonzero!(1u32)), store, DefaultClock::default());
+/// assert!(lim.check_key(&"client").is_ok());
+/// assert!(lim.check_key(&"client").is_err());
+/// assert!(lim.check_key(&"client").is_err());
+/// assert_eq!(lim.recent_denials(&"client"), 2);
+/// ```
+#[derive(Debug)]
+pub struct DenialTrackingStateStore<K: Hash + Eq> {
+    map: DashMap<K, DenialTrackingState>,
+    bucket_width: Nanos,
+}
+
+impl<K: Hash + Eq + Clone> DenialTrackingStateStore<K> {
+    /// Constructs a state store that counts each key's negative decisions in the last
+    /// `window`.
+    pub fn new(window: Duration) -> Self {
+        let bucket_width = Nanos::saturating_from(window / BUCKETS as u32)
+            .as_u64()
+            .max(1);
+        DenialTrackingStateStore {
+            map: DashMap::default(),
+            bucket_width: Nanos::from(bucket_width),
+        }
+    }
+
+    /// Returns the duration of the window over which denials are counted.
+    pub fn window(&self) -> Duration {
+        Duration::from(self.bucket_width) * BUCKETS as u32
+    }
+
+    /// Returns the number of negative decisions made for `key` in the window that ends at
+    /// `now`, an offset from the rate limiter's creation.
+    pub fn recent_denials(&self, key: &K, now: Nanos) -> u32 {
+        self.map
+            .get(key)
+            .map(|v| v.denials.lock().count(now / self.bucket_width))
+            .unwrap_or(0)
+    }
+
+    /// Returns true if a key's state can be dropped without losing information: its
+    /// rate-limiting state is older than `drop_below`, and it was not denied in the window
+    /// that ends there.
+    fn is_stale(&self, v: &DenialTrackingState, drop_below: Nanos) -> bool {
+        v.state.is_older_than(drop_below)
+            && v.denials.lock().count(drop_below / self.bucket_width) == 0
+    }
+}
+
+impl<K: Hash + Eq + Clone> StateStore for DenialTrackingStateStore<K> {
+    type Key = K;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        if let Some(v) = self.map.get(key) {
+            // fast path: measure existing entry
+            return v.state.measure_and_replace_one(f);
+        }
+        // make an entry and measure that:
+        let entry = self.map.entry(key.clone()).or_default();
+        entry.state.measure_and_replace_one(f)
+    }
+
+    fn note_denial(&self, key: &Self::Key, t0: Nanos) {
+        if let Some(v) = self.map.get(key) {
+            v.denials.lock().record(t0 / self.bucket_width);
+        }
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        let prev = self
+            .map
+            .get(key)
+            .and_then(|v| v.state.measure_and_peek_one(|tat| tat));
+        f(prev)
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        if let Some(v) = self.map.get(key) {
+            v.state.restore_one(replaced, prev);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> ShrinkableKeyedStateStore<K> for DenialTrackingStateStore<K> {
+    /// Removes the keys whose rate-limiting state is older than `drop_below` and that have not
+    /// been denied in the window before it.
+    fn retain_recent(&self, drop_below: Nanos) {
+        self.map.retain(|_, v| !self.is_stale(v, drop_below));
+    }
+
+    fn shrink_to_fit(&self) {
+        self.map.shrink_to_fit();
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        mem::size_of::<Self>() + hash_table_bytes::<(K, DenialTrackingState)>(self.map.capacity())
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        for key in keys {
+            self.map.entry(key).or_default();
+        }
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.map.contains_key(key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        self.map
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .value()
+                    .state
+                    .measure_and_peek_one(|tat| tat)
+                    .filter(|tat| *tat > drop_below)
+                    .map(|tat| (entry.key().clone(), tat))
+            })
+            .collect()
+    }
+}
+
+/// # Keyed rate limiters - Tracking negative decisions
+impl<K, C, MW> RateLimiter<K, DenialTrackingStateStore<K>, C, MW>
+where
+    K: Hash + Eq + Clone,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the number of negative decisions that were made for `key` recently.
+    ///
+    /// The length of the window in which decisions are counted is configured on the
+    /// [`DenialTrackingStateStore`].
+    pub fn recent_denials(&self, key: &K) -> u32 {
+        let now = self.clock.now().duration_since(self.start);
+        self.state.recent_denials(key, now)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn buckets_slide() {
+        let mut buckets = DenialBuckets::default();
+        buckets.record(0);
+        buckets.record(0);
+        buckets.record(3);
+        assert_eq!(buckets.count(3), 3);
+        assert_eq!(buckets.count(7), 3);
+        // Period 0 drops out of the window:
+        assert_eq!(buckets.count(8), 1);
+        assert_eq!(buckets.count(10), 1);
+        assert_eq!(buckets.count(11), 0);
+
+        // Recording far in the future resets all the buckets:
+        buckets.record(100);
+        assert_eq!(buckets.count(100), 1);
+        // ...and late recordings only count if they're still in the window:
+        buckets.record(95);
+        buckets.record(50);
+        assert_eq!(buckets.count(100), 2);
+    }
+
+    #[test]
+    fn buckets_reuse_slots() {
+        let mut buckets = DenialBuckets::default();
+        for period in 0..20 {
+            buckets.record(period);
+        }
+        assert_eq!(buckets.count(19), BUCKETS as u32);
+        assert_eq!(buckets.count(22), BUCKETS as u32 - 3);
+    }
+
+    #[test]
+    fn denial_tracking_impls_coverage() {
+        let store = DenialTrackingStateStore::<u32>::new(Duration::from_secs(8));
+        assert_eq!(store.window(), Duration::from_secs(8));
+        assert!(format!("{:?}", store).contains("DenialTrackingStateStore"));
+        assert!(format!("{:?}", DenialTrackingState::default()).contains("DenialTrackingState"));
+    }
+}
@@ -0,0 +1,508 @@
+use std::prelude::v1::*;
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::mem::{self, MaybeUninit};
+use std::sync::atomic::Ordering;
+
+use portable_atomic::{AtomicU8, AtomicUsize};
+use spinning_top::RwSpinlock;
+
+use crate::nanos::Nanos;
+use crate::state::keyed::ShrinkableKeyedStateStore;
+use crate::state::{InMemoryState, RebasableStateStore, StateStore};
+use crate::{clock, middleware::RateLimitingMiddleware, RateLimiter};
+
+/// The hasher that a [`FixedCapacityStateStore`] uses by default: an unseeded FNV-1a hash.
+///
+/// The default is the same with and without the `std` feature, so enabling `std` somewhere in
+/// a dependency graph doesn't change how keys are hashed. If keys can be chosen by an
+/// adversary, provide a seeded hasher (like a [`SeededHashBuilder`](super::SeededHashBuilder))
+/// with [`FixedCapacityStateStore::with_capacity_and_hasher`] instead.
+pub type DefaultHashBuilder = std::hash::BuildHasherDefault<FnvHasher>;
+
+/// The 64-bit FNV-1a hash function.
+#[derive(Debug, Clone, Copy)]
+pub struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl std::hash::Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// The error returned when a key can not be added to a full
+/// [`FixedCapacityStateStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreFull;
+
+impl fmt::Display for StoreFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "state store has no capacity for another key")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for StoreFull {}
+
+const EMPTY: u8 = 0;
+const INSERTING: u8 = 1;
+const OCCUPIED: u8 = 2;
+
+struct Slot<K> {
+    status: AtomicU8,
+    key: UnsafeCell<MaybeUninit<K>>,
+    state: InMemoryState,
+}
+
+impl<K> Slot<K> {
+    fn is_empty(&self) -> bool {
+        self.status.load(Ordering::Acquire) == EMPTY
+    }
+
+    /// Returns the slot's key, if the slot is occupied.
+    fn key(&self) -> Option<&K> {
+        if self.status.load(Ordering::Acquire) == OCCUPIED {
+            // Safety: Occupied slots hold an initialized key, which is only modified through
+            // exclusive references to the table.
+            Some(unsafe { (*self.key.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Drops the slot's key (if any) and resets it to an empty slot.
+    fn clear(&mut self) {
+        if *self.status.get_mut() == OCCUPIED {
+            // Safety: Occupied slots hold an initialized key.
+            unsafe { self.key.get_mut().assume_init_drop() };
+        }
+        *self.status.get_mut() = EMPTY;
+        self.state = InMemoryState::default();
+    }
+}
+
+/// A slot that a thread claimed for a new key, and the room it reserved for the key.
+///
+/// Dropping the claim gives both back. This happens if cloning the key panics; otherwise, the
+/// claim is forgotten once the key is in place. Without it, threads probing past the slot would
+/// wait for it to become occupied forever.
+struct Claim<'a, K> {
+    slot: &'a Slot<K>,
+    len: &'a AtomicUsize,
+}
+
+impl<K> Drop for Claim<'_, K> {
+    fn drop(&mut self) {
+        self.len.fetch_sub(1, Ordering::AcqRel);
+        self.slot.status.store(EMPTY, Ordering::Release);
+    }
+}
+
+impl<K> Default for Slot<K> {
+    fn default() -> Self {
+        Slot {
+            status: AtomicU8::new(EMPTY),
+            key: UnsafeCell::new(MaybeUninit::uninit()),
+            state: InMemoryState::default(),
+        }
+    }
+}
+
+/// A keyed state store that allocates room for all its keys up front.
+///
+/// The [`HashMap`][std::collections::HashMap]- and `DashMap`-backed
+/// stores allocate when they first see a key (and when they grow),
+/// which can show up as latency spikes. This store is an
+/// open-addressing hash table with a fixed number of slots, allocated
+/// when it is constructed: Checking a key never allocates, and keys
+/// are added concurrently with atomic operations; only
+/// [`retain_recent`](ShrinkableKeyedStateStore::retain_recent)
+/// needs exclusive access to the table.
+///
+/// # When the store is full
+/// Once the store holds as many keys as it was constructed for, it
+/// can not accommodate new keys until
+/// [`retain_recent`](ShrinkableKeyedStateStore::retain_recent)
+/// evicts old ones. Rate limiters handle new keys on a full store in
+/// one of two ways:
+///
+/// * [`check_key`](../../struct.RateLimiter.html#method.check_key)
+///   and the other methods that work on all keyed state stores make
+///   decisions for all keys that don't fit against one shared
+///   overflow state. This still limits them, as a group, to the
+///   quota.
+/// * [`check_key_or_full`](../../struct.RateLimiter.html#method.check_key_or_full)
+///   returns a [`StoreFull`] error instead.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{
+///     clock::DefaultClock, middleware::NoOpMiddleware, state::keyed::FixedCapacityStateStore,
+///     Quota, RateLimiter,
+/// };
+///
+/// let store = FixedCapacityStateStore::with_capacity(1);
+/// let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> =
+///     RateLimiter::new(Quota::per_second(nonzero!(1u32)), store, DefaultClock::default());
+/// assert_eq!(Ok(Ok(())), lim.check_key_or_full(&"alice"));
+/// assert!(lim.check_key_or_full(&"bob").is_err());
+/// ```
+pub struct FixedCapacityStateStore<K, H = DefaultHashBuilder> {
+    slots: RwSpinlock<Box<[Slot<K>]>>,
+    len: AtomicUsize,
+    capacity: usize,
+    overflow: InMemoryState,
+    hasher: H,
+}
+
+// Safety: Keys are only written once by the thread that claims an empty slot, and read by other
+// threads after that thread publishes them.
+unsafe impl<K: Send + Sync, H: Send + Sync> Sync for FixedCapacityStateStore<K, H> {}
+
+impl<K: Hash + Eq> FixedCapacityStateStore<K> {
+    /// Constructs a state store that can hold up to `capacity` keys.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, DefaultHashBuilder::default())
+    }
+}
+
+impl<K: Hash + Eq, H: BuildHasher> FixedCapacityStateStore<K, H> {
+    /// Constructs a state store that can hold up to `capacity` keys, which it hashes with
+    /// `hasher`.
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: H) -> Self {
+        // Keep the table at most 7/8 full, so probe sequences stay short:
+        let slots = (capacity.saturating_mul(8) / 7 + 1).next_power_of_two();
+        FixedCapacityStateStore {
+            slots: RwSpinlock::new((0..slots).map(|_| Slot::default()).collect()),
+            len: AtomicUsize::new(0),
+            capacity,
+            overflow: InMemoryState::default(),
+            hasher,
+        }
+    }
+
+    /// Returns the maximum number of keys the store can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns true if the store can not accommodate any new keys.
+    pub fn is_full(&self) -> bool {
+        self.len.load(Ordering::Relaxed) >= self.capacity
+    }
+
+    fn home(&self, slots: &[Slot<K>], key: &K) -> usize {
+        self.hasher.hash_one(key) as usize & (slots.len() - 1)
+    }
+
+    /// Returns the slot holding `key`, if it is in the table.
+    fn find<'a>(&self, slots: &'a [Slot<K>], key: &K) -> Option<&'a Slot<K>> {
+        let mask = slots.len() - 1;
+        let mut i = self.home(slots, key);
+        loop {
+            let slot = &slots[i];
+            match slot.status.load(Ordering::Acquire) {
+                EMPTY => return None,
+                INSERTING => std::hint::spin_loop(),
+                _ => {
+                    if slot.key() == Some(key) {
+                        return Some(slot);
+                    }
+                    i = (i + 1) & mask;
+                }
+            }
+        }
+    }
+
+    /// Returns the slot holding `key`, adding the key to the table if it has room.
+    fn find_or_insert<'a>(&self, slots: &'a [Slot<K>], key: &K) -> Result<&'a Slot<K>, StoreFull>
+    where
+        K: Clone,
+    {
+        let mask = slots.len() - 1;
+        let mut i = self.home(slots, key);
+        loop {
+            let slot = &slots[i];
+            match slot.status.load(Ordering::Acquire) {
+                EMPTY => {
+                    // With no deletions under a shared lock, keys are always found before the
+                    // first empty slot of their probe sequence; so the key isn't in the table.
+                    if slot
+                        .status
+                        .compare_exchange(EMPTY, INSERTING, Ordering::Acquire, Ordering::Relaxed)
+                        .is_ok()
+                    {
+                        // Only reserve room for the key once its slot is claimed: Other threads
+                        // adding the same key wait for the slot and find the key there, instead
+                        // of competing for the last bit of room.
+                        if self
+                            .len
+                            .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |len| {
+                                (len < self.capacity).then_some(len + 1)
+                            })
+                            .is_err()
+                        {
+                            slot.status.store(EMPTY, Ordering::Release);
+                            return Err(StoreFull);
+                        }
+                        let claim = Claim {
+                            slot,
+                            len: &self.len,
+                        };
+                        // Safety: This thread claimed the empty slot; nobody reads its key until
+                        // it is marked occupied.
+                        unsafe { (*slot.key.get()).write(key.clone()) };
+                        mem::forget(claim);
+                        slot.status.store(OCCUPIED, Ordering::Release);
+                        return Ok(slot);
+                    }
+                    // Another thread claimed the slot; look at it again.
+                }
+                INSERTING => std::hint::spin_loop(),
+                _ => {
+                    if slot.key() == Some(key) {
+                        return Ok(slot);
+                    }
+                    i = (i + 1) & mask;
+                }
+            }
+        }
+    }
+
+    /// Calls `f` with the state of the slot holding `key`, adding the key to the table if it
+    /// has room.
+    ///
+    /// The slot stays claimed while `f` runs: Keys only lose their slots while
+    /// [`retain_recent`](ShrinkableKeyedStateStore::retain_recent) has exclusive access to the
+    /// table. That also means that `retain_recent` waits for `f`, so it must not take long (or
+    /// call into user code).
+    pub(crate) fn with_slot<T, F>(&self, key: &K, f: F) -> Result<T, StoreFull>
+    where
+        K: Clone,
+        F: FnOnce(&SlotState<'_, K>) -> T,
+    {
+        let slots = self.slots.read();
+        let slot = self.find_or_insert(&slots, key)?;
+        Ok(f(&SlotState {
+            state: &slot.state,
+            key: PhantomData,
+        }))
+    }
+}
+
+/// The state of a key's slot, as a state store for that key.
+pub(crate) struct SlotState<'a, K> {
+    state: &'a InMemoryState,
+    key: PhantomData<fn(&K)>,
+}
+
+impl<K> StateStore for SlotState<'_, K> {
+    type Key = K;
+
+    fn measure_and_replace<T, F, E>(&self, _key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        self.state.measure_and_replace_one(f)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, _key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        self.state.measure_and_peek_one(f)
+    }
+
+    fn restore(&self, _key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        self.state.restore_one(replaced, prev);
+    }
+}
+
+impl<K: Hash + Eq + Clone, H: BuildHasher> StateStore for FixedCapacityStateStore<K, H> {
+    type Key = K;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let slots = self.slots.read();
+        match self.find_or_insert(&slots, key) {
+            Ok(slot) => slot.state.measure_and_replace_one(f),
+            Err(StoreFull) => self.overflow.measure_and_replace_one(f),
+        }
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        let slots = self.slots.read();
+        match self.find(&slots, key) {
+            Some(slot) => slot.state.measure_and_peek_one(f),
+            None if self.is_full() => self.overflow.measure_and_peek_one(f),
+            None => f(None),
+        }
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        // The key keeps its slot, with a zero state that reads as no state:
+        let slots = self.slots.read();
+        match self.find(&slots, key) {
+            Some(slot) => slot.state.restore_one(replaced, prev),
+            None => self.overflow.restore_one(replaced, prev),
+        };
+    }
+}
+
+impl<K: Hash + Eq + Clone, H: BuildHasher> RebasableStateStore for FixedCapacityStateStore<K, H> {
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        for slot in self.slots.get_mut().iter_mut() {
+            if *slot.status.get_mut() == OCCUPIED {
+                slot.state.rewrite(&f);
+            }
+        }
+        self.overflow.rewrite(&f);
+    }
+}
+
+impl<K: Hash + Eq + Clone, H: BuildHasher> ShrinkableKeyedStateStore<K>
+    for FixedCapacityStateStore<K, H>
+{
+    fn retain_recent(&self, drop_below: Nanos) {
+        let mut slots = self.slots.write();
+        let mut removed = 0;
+        for slot in slots.iter_mut() {
+            if *slot.status.get_mut() == OCCUPIED && slot.state.is_older_than(drop_below) {
+                slot.clear();
+                removed += 1;
+            }
+        }
+        if removed == 0 {
+            return;
+        }
+        self.len.fetch_sub(removed, Ordering::AcqRel);
+
+        // Move the remaining keys up to the first empty slot of their probe sequence, so that
+        // lookups find them again. Starting after an empty slot ensures that every probe
+        // sequence gets looked at in order.
+        let mask = slots.len() - 1;
+        let start = slots.iter().position(Slot::is_empty).unwrap_or(0);
+        for offset in 1..=slots.len() {
+            let i = (start + offset) & mask;
+            let home = match slots[i].key() {
+                Some(key) => self.home(&slots, key),
+                None => continue,
+            };
+            let mut j = home;
+            while j != i {
+                if slots[j].is_empty() {
+                    slots.swap(i, j);
+                    break;
+                }
+                j = (j + 1) & mask;
+            }
+        }
+    }
+
+    fn shrink_to_fit(&self) {
+        // The table's size is fixed.
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        mem::size_of::<Self>() + self.slots.read().len() * mem::size_of::<Slot<K>>()
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        let slots = self.slots.read();
+        self.find(&slots, key).is_some()
+    }
+}
+
+impl<K, H> Drop for FixedCapacityStateStore<K, H> {
+    fn drop(&mut self) {
+        for slot in self.slots.get_mut().iter_mut() {
+            slot.clear();
+        }
+    }
+}
+
+impl<K: fmt::Debug, H> fmt::Debug for FixedCapacityStateStore<K, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let slots = self.slots.read();
+        f.debug_struct("FixedCapacityStateStore")
+            .field("capacity", &self.capacity)
+            .field("entries", &DebugEntries(&slots))
+            .field("overflow", &self.overflow)
+            .finish()
+    }
+}
+
+struct DebugEntries<'a, K>(&'a [Slot<K>]);
+
+impl<K: fmt::Debug> fmt::Debug for DebugEntries<'_, K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(
+                self.0
+                    .iter()
+                    .filter_map(|slot| slot.key().map(|key| (key, &slot.state))),
+            )
+            .finish()
+    }
+}
+
+/// # Keyed rate limiters - Fixed-capacity state stores
+impl<K, H, C, MW> RateLimiter<K, FixedCapacityStateStore<K, H>, C, MW>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Allow a single cell through the rate limiter for the given key, if the state store has
+    /// room for the key.
+    ///
+    /// Unlike [`check_key`](#method.check_key), this does not fall back to the store's shared
+    /// overflow state for keys that don't fit into the store, but returns `Err(StoreFull)`.
+    pub fn check_key_or_full(
+        &self,
+        key: &K,
+    ) -> Result<Result<MW::PositiveOutcome, MW::NegativeOutcome>, StoreFull> {
+        // Decide on the claimed slot directly, so the key can't lose it before the decision, but
+        // only consult the middleware once the table's lock is released:
+        let t0 = self.clock.now();
+        let decision = self
+            .state
+            .with_slot(key, |slot| self.gcra.decide_at(self.start, key, slot, t0))?;
+        Ok(self
+            .gcra
+            .outcome::<K, C::Instant, MW>(self.start, key, self.key_format(), t0, decision))
+    }
+}
@@ -1,11 +1,13 @@
 use std::prelude::v1::*;
 
 use crate::{
-    clock, errors::InsufficientCapacity, middleware::RateLimitingMiddleware,
-    state::keyed::KeyedStateStore, Jitter, NotUntil, RateLimiter,
+    clock,
+    errors::InsufficientCapacity,
+    middleware::RateLimitingMiddleware,
+    state::{keyed::KeyedStateStore, Clamped, Waiting},
+    Jitter, NotUntil, RateLimiter,
 };
-use futures_timer::Delay;
-use std::{hash::Hash, num::NonZeroU32};
+use std::{hash::Hash, num::NonZeroU32, time::Duration};
 
 #[cfg(feature = "std")]
 /// # Keyed rate limiters - `async`/`await`
@@ -13,7 +15,7 @@ impl<K, S, C, MW> RateLimiter<K, S, C, MW>
 where
     K: Hash + Eq + Clone,
     S: KeyedStateStore<K>,
-    C: clock::ReasonablyRealtime,
+    C: clock::DelayProvider,
     MW: RateLimitingMiddleware<C::Instant, NegativeOutcome = NotUntil<C::Instant>>,
 {
     /// Asynchronously resolves as soon as the rate limiter allows it.
@@ -23,10 +25,13 @@ where
     /// is polled again. This means that the future might resolve at some later time (depending
     /// on what other measurements are made on the rate limiter).
     ///
-    /// If multiple futures are dispatched against the rate limiter, it is advisable to use
+    /// The wait period gets the rate limiter's default [`jitter`](#method.jitter) added. If
+    /// multiple futures are dispatched against the rate limiter, it is advisable to set one
+    /// with [`with_jitter`](#method.with_jitter), or to use
     /// [`until_ready_with_jitter`](#method.until_ready_with_jitter), to avoid thundering herds.
     pub async fn until_key_ready(&self, key: &K) -> MW::PositiveOutcome {
-        self.until_key_ready_with_jitter(key, Jitter::NONE).await
+        self.until_key_ready_with_jitter(key, self.jitter.clone())
+            .await
     }
 
     /// Asynchronously resolves as soon as the rate limiter allows it, with a randomized wait
@@ -45,19 +50,41 @@ where
         key: &K,
         jitter: Jitter,
     ) -> MW::PositiveOutcome {
+        let mut waiting = Waiting::new(&self.state, key);
         loop {
             match self.check_key(key) {
                 Ok(x) => {
                     return x;
                 }
                 Err(negative) => {
-                    let delay = Delay::new(jitter + negative.wait_time_from(self.clock.now()));
+                    waiting.start();
+                    let delay = self
+                        .clock
+                        .delay(jitter.clone() + negative.wait_time_from(self.clock.now()));
                     delay.await;
                 }
             }
         }
     }
 
+    /// Asynchronously resolves as soon as the rate limiter allows a cell for the given key,
+    /// unless that would take longer than `threshold`.
+    ///
+    /// See [`until_ready_or_reject_if_wait_exceeds`](#method.until_ready_or_reject_if_wait_exceeds).
+    pub async fn until_key_ready_or_reject_if_wait_exceeds(
+        &self,
+        key: &K,
+        threshold: Duration,
+    ) -> Result<MW::PositiveOutcome, MW::NegativeOutcome> {
+        let reservation = self.check_key_or_reject_if_wait_exceeds(key, threshold)?;
+        if reservation.wait() > Duration::ZERO {
+            let mut waiting = Waiting::new(&self.state, key);
+            waiting.start();
+            self.clock.delay(reservation.wait()).await;
+        }
+        Ok(reservation.into_outcome())
+    }
+
     /// Asynchronously resolves as soon as the rate limiter allows it.
     ///
     /// This is similar to `until_key_ready` except it waits for an abitrary number
@@ -70,7 +97,7 @@ where
         key: &K,
         n: NonZeroU32,
     ) -> Result<MW::PositiveOutcome, InsufficientCapacity> {
-        self.until_key_n_ready_with_jitter(key, n, Jitter::NONE)
+        self.until_key_n_ready_with_jitter(key, n, self.jitter.clone())
             .await
     }
 
@@ -88,13 +115,58 @@ where
         n: NonZeroU32,
         jitter: Jitter,
     ) -> Result<MW::PositiveOutcome, InsufficientCapacity> {
+        let mut waiting = Waiting::new(&self.state, key);
         loop {
             match self.check_key_n(key, n)? {
                 Ok(x) => {
                     return Ok(x);
                 }
                 Err(negative) => {
-                    let delay = Delay::new(jitter + negative.wait_time_from(self.clock.now()));
+                    waiting.start();
+                    let delay = self
+                        .clock
+                        .delay(jitter.clone() + negative.wait_time_from(self.clock.now()));
+                    delay.await;
+                }
+            }
+        }
+    }
+
+    /// Asynchronously resolves as soon as the rate limiter allows as many of `n` cells for the
+    /// given key as it could ever accommodate.
+    ///
+    /// This is the keyed counterpart to
+    /// [`until_n_ready_clamped`](#method.until_n_ready_clamped).
+    pub async fn until_key_n_ready_clamped(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+    ) -> Clamped<MW::PositiveOutcome> {
+        self.until_key_n_ready_clamped_with_jitter(key, n, self.jitter.clone())
+            .await
+    }
+
+    /// Asynchronously resolves as soon as the rate limiter allows as many of `n` cells for the
+    /// given key as it could ever accommodate, with a randomized wait period.
+    ///
+    /// See [`until_key_n_ready_clamped`](#method.until_key_n_ready_clamped).
+    pub async fn until_key_n_ready_clamped_with_jitter(
+        &self,
+        key: &K,
+        n: NonZeroU32,
+        jitter: Jitter,
+    ) -> Clamped<MW::PositiveOutcome> {
+        let mut waiting = Waiting::new(&self.state, key);
+        loop {
+            match self.check_key_n_clamped(key, n) {
+                Ok(x) => {
+                    return x;
+                }
+                Err(negative) => {
+                    waiting.start();
+                    let delay = self
+                        .clock
+                        .delay(jitter.clone() + negative.wait_time_from(self.clock.now()));
                     delay.await;
                 }
             }
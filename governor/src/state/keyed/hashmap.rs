@@ -4,62 +4,234 @@ use crate::nanos::Nanos;
 use crate::{clock, Quota, RateLimiter};
 use crate::{
     middleware::NoOpMiddleware,
-    state::{InMemoryState, StateStore},
+    state::{InMemoryState, RebasableStateStore, StateStore},
 };
 use std::collections::HashMap;
-use std::hash::Hash;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
 
-use crate::state::keyed::ShrinkableKeyedStateStore;
-
-#[cfg(feature = "std")]
-type Mutex<T> = parking_lot::Mutex<T>;
-
-#[cfg(not(feature = "std"))]
-type Mutex<T> = spinning_top::Spinlock<T>;
+use crate::state::keyed::{hash_table_bytes, ShrinkableKeyedStateStore};
+use crate::sync::map::{MapLock, Mutex};
 
 /// A thread-safe (but not very performant) implementation of a keyed rate limiter state
 /// store using [`HashMap`].
 ///
 /// The `HashMapStateStore` is the default state store in `std` when no other thread-safe
 /// features are enabled.
-pub type HashMapStateStore<K> = Mutex<HashMap<K, InMemoryState>>;
-
-impl<K: Hash + Eq + Clone> StateStore for HashMapStateStore<K> {
-    type Key = K;
-
-    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
-    where
-        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
-    {
-        let mut map = self.lock();
-        if let Some(v) = (*map).get(key) {
-            // fast path: a rate limiter is already present for the key.
-            return v.measure_and_replace_one(f);
+///
+/// Which mutex guards the map depends on the crate's features:
+/// * Without `std`, it is a [`spinning_top::Spinlock`](https://docs.rs/spinning_top).
+/// * Otherwise, with the `parking_lot` feature (enabled by default), it is a
+///   [`parking_lot::Mutex`](https://docs.rs/parking_lot).
+/// * Otherwise, it is a [`std::sync::Mutex`].
+///
+#[cfg_attr(
+    feature = "spin",
+    doc = "With the `spin` feature, the [`SpinHashMapStateStore`] guards the map with a spinlock in
+`std` builds, too."
+)]
+///
+/// # Hashing keys
+///
+/// By default, keys are hashed with the default hasher of [`HashMap`]. With the `std` feature,
+/// that is the randomly-seeded SipHash-1-3 of
+/// [`RandomState`](std::collections::hash_map::RandomState), which keeps adversaries from
+/// choosing keys whose hashes collide (known as HashDoS). Without `std`, the hasher's seed
+/// can't be drawn from the operating system, so it is easier to predict: If keys can be chosen
+/// by an adversary, pass a hasher with a secret seed (e.g. a
+/// [`SeededHashBuilder`](super::SeededHashBuilder)) to
+/// [`hashmap_with_hasher`](crate::RateLimiter::hashmap_with_hasher).
+pub type HashMapStateStore<K> = HashMapStateStoreWithHasher<K, DefaultMapHasher>;
+
+/// A [`HashMapStateStore`] that hashes keys with `H`.
+pub type HashMapStateStoreWithHasher<K, H> = Mutex<HashMap<K, InMemoryState, H>>;
+
+/// A [`HashMapStateStore`] that guards its map with a
+/// [`spinning_top::Spinlock`](https://docs.rs/spinning_top), regardless of the crate's other
+/// features.
+///
+/// Threads that wait for the lock spin instead of blocking, which can be faster for short
+/// critical sections like a rate limiter's, as long as there are no more threads than CPU cores
+/// contending for it. Without `std`, this is the same type as the [`HashMapStateStore`].
+///
+/// Available with the `spin` feature.
+#[cfg(feature = "spin")]
+pub type SpinHashMapStateStore<K> = SpinHashMapStateStoreWithHasher<K, DefaultMapHasher>;
+
+/// A [`SpinHashMapStateStore`] that hashes keys with `H`.
+#[cfg(feature = "spin")]
+pub type SpinHashMapStateStoreWithHasher<K, H> =
+    spinning_top::Spinlock<HashMap<K, InMemoryState, H>>;
+
+/// The default hasher of [`HashMap`].
+#[cfg(feature = "std")]
+type DefaultMapHasher = std::collections::hash_map::RandomState;
+
+/// The default hasher of [`HashMap`].
+#[cfg(not(feature = "std"))]
+type DefaultMapHasher = hashbrown::hash_map::DefaultHashBuilder;
+
+/// Implements the state store traits for a `HashMap` behind the lock of the type alias `$store`.
+macro_rules! hashmap_state_store {
+    ($store:ident) => {
+        impl<K: Hash + Eq + Clone, H: BuildHasher> StateStore for $store<K, H> {
+            type Key = K;
+
+            fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+            where
+                F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+            {
+                let mut map = self.lock_map();
+                if let Some(v) = (*map).get(key) {
+                    // fast path: a rate limiter is already present for the key.
+                    return v.measure_and_replace_one(f);
+                }
+                // not-so-fast path: make a new entry and measure it.
+                let entry = (*map).entry(key.clone()).or_default();
+                entry.measure_and_replace_one(f)
+            }
+
+            fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+            where
+                F: Fn(Option<Nanos>) -> Result<T, E>,
+            {
+                let map = self.lock_map();
+                match (*map).get(key) {
+                    Some(v) => v.measure_and_peek_one(f),
+                    None => f(None),
+                }
+            }
+
+            fn measure_and_replace_all<T, F, E>(
+                &self,
+                keys: &[&Self::Key],
+                f: F,
+            ) -> Result<Vec<T>, (usize, E)>
+            where
+                F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+            {
+                // Holding the lock for all the keys makes the update atomic: Nothing else can see the
+                // states of the earlier keys before they are restored.
+                let mut map = self.lock_map();
+                let mut outcomes = Vec::with_capacity(keys.len());
+                let mut replaced = Vec::with_capacity(keys.len());
+                for (i, key) in keys.iter().enumerate() {
+                    let prev = map
+                        .get(*key)
+                        .and_then(|v| v.measure_and_peek_one(|tat| tat));
+                    match f(prev) {
+                        Ok((outcome, next)) => {
+                            put(&mut map, key, Some(next));
+                            outcomes.push(outcome);
+                            replaced.push(prev);
+                        }
+                        Err(e) => {
+                            for (key, prev) in keys.iter().zip(replaced).rev() {
+                                put(&mut map, key, prev);
+                            }
+                            return Err((i, e));
+                        }
+                    }
+                }
+                Ok(outcomes)
+            }
+
+            fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+                let mut map = self.lock_map();
+                let unchanged =
+                    map.get(key).and_then(|v| v.measure_and_peek_one(|tat| tat)) == Some(replaced);
+                if unchanged {
+                    put(&mut map, key, prev);
+                }
+            }
         }
-        // not-so-fast path: make a new entry and measure it.
-        let entry = (*map).entry(key.clone()).or_default();
-        entry.measure_and_replace_one(f)
-    }
+
+        impl<K: Hash + Eq + Clone, H: BuildHasher> RebasableStateStore for $store<K, H> {
+            fn rewrite_states<F>(&mut self, f: F)
+            where
+                F: Fn(Nanos) -> Nanos,
+            {
+                for state in self.map_mut().values_mut() {
+                    state.rewrite(&f);
+                }
+            }
+        }
+
+        impl<K: Hash + Eq + Clone, H: BuildHasher> ShrinkableKeyedStateStore<K> for $store<K, H> {
+            fn retain_recent(&self, drop_below: Nanos) {
+                let mut map = self.lock_map();
+                map.retain(|_, v| !v.is_older_than(drop_below));
+            }
+
+            fn shrink_to_fit(&self) {
+                let mut map = self.lock_map();
+                map.shrink_to_fit();
+            }
+
+            fn len(&self) -> usize {
+                let map = self.lock_map();
+                (*map).len()
+            }
+            fn is_empty(&self) -> bool {
+                let map = self.lock_map();
+                (*map).is_empty()
+            }
+
+            fn approx_memory_bytes(&self) -> usize {
+                let map = self.lock_map();
+                mem::size_of::<Self>() + hash_table_bytes::<(K, InMemoryState)>((*map).capacity())
+            }
+
+            fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+                let keys = keys.into_iter();
+                let mut map = self.lock_map();
+                map.reserve(keys.size_hint().0);
+                for key in keys {
+                    map.entry(key).or_default();
+                }
+            }
+
+            fn contains_key(&self, key: &K) -> bool {
+                let map = self.lock_map();
+                (*map).contains_key(key)
+            }
+
+            fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+                let map = self.lock_map();
+                map.iter()
+                    .filter_map(|(k, v)| {
+                        v.measure_and_peek_one(|tat| tat)
+                            .filter(|tat| *tat > drop_below)
+                            .map(|tat| (k.clone(), tat))
+                    })
+                    .collect()
+            }
+        }
+    };
 }
 
-impl<K: Hash + Eq + Clone> ShrinkableKeyedStateStore<K> for HashMapStateStore<K> {
-    fn retain_recent(&self, drop_below: Nanos) {
-        let mut map = self.lock();
-        map.retain(|_, v| !v.is_older_than(drop_below));
-    }
+hashmap_state_store!(HashMapStateStoreWithHasher);
 
-    fn shrink_to_fit(&self) {
-        let mut map = self.lock();
-        map.shrink_to_fit();
-    }
+// Without `std`, the spinlock store is the same type as the default one:
+#[cfg(all(feature = "spin", any(loom, feature = "std")))]
+hashmap_state_store!(SpinHashMapStateStoreWithHasher);
 
-    fn len(&self) -> usize {
-        let map = self.lock();
-        (*map).len()
-    }
-    fn is_empty(&self) -> bool {
-        let map = self.lock();
-        (*map).is_empty()
+/// Stores `tat` as the state of `key`, or removes the key's state if `tat` is `None`.
+fn put<K: Hash + Eq + Clone, H: BuildHasher>(
+    map: &mut HashMap<K, InMemoryState, H>,
+    key: &K,
+    tat: Option<Nanos>,
+) {
+    match (map.get(key), tat) {
+        (Some(state), Some(tat)) => state.replace(tat),
+        (None, Some(tat)) => {
+            let state = InMemoryState::default();
+            state.replace(tat);
+            map.insert(key.clone(), state);
+        }
+        (_, None) => {
+            map.remove(key);
+        }
     }
 }
 
@@ -75,3 +247,34 @@ where
         RateLimiter::new(quota, state, clock)
     }
 }
+
+/// # Keyed rate limiters - [`HashMap`]-backed, with a custom hasher
+impl<K, H, C> RateLimiter<K, HashMapStateStoreWithHasher<K, H>, C, NoOpMiddleware<C::Instant>>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+    C: clock::Clock,
+{
+    /// Constructs a new rate limiter with a custom clock, backed by a [`HashMap`] that hashes
+    /// keys with `hasher`.
+    ///
+    /// See [`HashMapStateStore`] for when to choose a hasher.
+    pub fn hashmap_with_hasher_and_clock(quota: Quota, hasher: H, clock: C) -> Self {
+        let state: HashMapStateStoreWithHasher<K, H> = Mutex::new(HashMap::with_hasher(hasher));
+        RateLimiter::new(quota, state, clock)
+    }
+}
+
+impl<K, H> RateLimiter<K, HashMapStateStoreWithHasher<K, H>, clock::DefaultClock>
+where
+    K: Hash + Eq + Clone,
+    H: BuildHasher,
+{
+    /// Constructs a new keyed rate limiter backed by a [`HashMap`] that hashes keys with
+    /// `hasher`.
+    ///
+    /// See [`HashMapStateStore`] for when to choose a hasher.
+    pub fn hashmap_with_hasher(quota: Quota, hasher: H) -> Self {
+        RateLimiter::hashmap_with_hasher_and_clock(quota, hasher, clock::DefaultClock::default())
+    }
+}
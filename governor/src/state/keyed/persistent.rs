@@ -0,0 +1,591 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use crate::clock::{Clock, Reference, SystemClock};
+use crate::middleware::{NoOpMiddleware, RateLimitingMiddleware};
+use crate::nanos::Nanos;
+use crate::state::keyed::ShrinkableKeyedStateStore;
+use crate::state::{RebasableStateStore, StateStore};
+use crate::sync::Mutex;
+use crate::{Quota, RateLimiter};
+use nonzero_ext::nonzero;
+use siphasher::sip::SipHasher13;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The first bytes of every state journal: a magic number, followed by the format's version.
+const HEADER: [u8; 12] = *b"GOVSTATE\x01\x00\x00\x00";
+
+/// The size of a checkpoint's entry count and of its checksum.
+const WORD: usize = 8;
+
+/// The size of a checkpoint's entry: a key's hash, and its state in nanoseconds since the
+/// UNIX epoch.
+const ENTRY: usize = 16;
+
+/// How many checkpoints a journal holds before it gets compacted, by default.
+const DEFAULT_MAX_CHECKPOINTS: NonZeroUsize = nonzero!(64usize);
+
+/// The seed that keys are hashed with. It must never change, or journals written by earlier
+/// versions couldn't be recovered.
+const KEY_SEED: (u64, u64) = (0x676f_7665_726e_6f72, 0x6b65_7973_7461_7465);
+
+/// The seed that checkpoints' checksums are computed with.
+const CHECKSUM_SEED: (u64, u64) = (0x676f_7665_726e_6f72, 0x6a6f_7572_6e61_6c00);
+
+/// Hashes `key` the same way in every process, so its recovered state can be found again.
+fn key_hash<K: Hash + ?Sized>(key: &K) -> u64 {
+    let mut hasher = SipHasher13::new_with_keys(KEY_SEED.0, KEY_SEED.1);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = SipHasher13::new_with_keys(CHECKSUM_SEED.0, CHECKSUM_SEED.1);
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut word = [0; WORD];
+    word.copy_from_slice(&bytes[at..at + WORD]);
+    u64::from_le_bytes(word)
+}
+
+/// Encodes a checkpoint: its number of entries, the entries, and a checksum over both.
+fn encode_checkpoint(states: &HashMap<u64, u64>) -> Vec<u8> {
+    let mut block = Vec::with_capacity(WORD + states.len() * ENTRY + WORD);
+    block.extend_from_slice(&(states.len() as u64).to_le_bytes());
+    for (hash, tat) in states {
+        block.extend_from_slice(&hash.to_le_bytes());
+        block.extend_from_slice(&tat.to_le_bytes());
+    }
+    let sum = checksum(&block);
+    block.extend_from_slice(&sum.to_le_bytes());
+    block
+}
+
+/// The contents of a journal file, up to the first checkpoint that wasn't written completely.
+#[derive(Debug, Default)]
+struct Contents {
+    /// The entries of the last intact checkpoint.
+    states: Vec<(u64, u64)>,
+    /// The number of intact checkpoints.
+    checkpoints: usize,
+    /// The length of the file's intact prefix.
+    len: usize,
+}
+
+fn decode(bytes: &[u8]) -> io::Result<Contents> {
+    if bytes.len() < HEADER.len() {
+        // A new journal whose header may not have been written completely:
+        return if HEADER.starts_with(bytes) {
+            Ok(Contents::default())
+        } else {
+            Err(not_a_journal())
+        };
+    }
+    if bytes[..HEADER.len()] != HEADER {
+        return Err(not_a_journal());
+    }
+    let mut contents = Contents {
+        len: HEADER.len(),
+        ..Contents::default()
+    };
+    loop {
+        let at = contents.len;
+        if bytes.len() - at < WORD {
+            break;
+        }
+        let count = read_u64(bytes, at);
+        let size = usize::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(ENTRY))
+            .and_then(|entries| entries.checked_add(2 * WORD));
+        let size = match size {
+            Some(size) if size <= bytes.len() - at => size,
+            _ => break,
+        };
+        let (block, sum) = bytes[at..at + size].split_at(size - WORD);
+        if checksum(block) != read_u64(sum, 0) {
+            break;
+        }
+        contents.states = block[WORD..]
+            .chunks_exact(ENTRY)
+            .map(|entry| (read_u64(entry, 0), read_u64(entry, WORD)))
+            .collect();
+        contents.checkpoints += 1;
+        contents.len += size;
+    }
+    Ok(contents)
+}
+
+fn not_a_journal() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not a governor state journal")
+}
+
+/// An append-only file that keyed rate limiters periodically write their states to, so they
+/// can recover them after a restart or a crash.
+///
+/// Each [checkpoint](RateLimiter::checkpoint) appends the state of every key that was used
+/// recently (a 64-bit hash of the key, and its state as wall-clock time) to the journal, along
+/// with a checksum, and waits until the file's data has reached the disk. A checkpoint that
+/// was interrupted by a crash fails its checksum; when the journal is opened again, it
+/// recovers the states of the last intact checkpoint and drops everything after it.
+///
+/// Once the journal holds [`max_checkpoints`](#method.with_max_checkpoints) checkpoints, the
+/// next one gets written to a new file instead, which then atomically replaces the journal.
+///
+/// # File format
+/// A journal is a 12-byte header (`GOVSTATE`, followed by the format's version as a
+/// little-endian `u32`), followed by checkpoints. Each checkpoint is its number of entries as
+/// a little-endian `u64`, the entries (each a key's hash and its state in nanoseconds since
+/// the UNIX epoch, as little-endian `u64`s), and a SipHash-1-3 checksum of the preceding
+/// bytes.
+pub struct StateJournal {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    checkpoints: usize,
+    max_checkpoints: NonZeroUsize,
+    recovered: Vec<(u64, SystemTime)>,
+}
+
+impl StateJournal {
+    /// Opens the journal at `path`, creating it if it doesn't exist, and recovers the states
+    /// of its last intact checkpoint.
+    ///
+    /// Returns an error with [`InvalidData`](io::ErrorKind::InvalidData) if `path` is not a
+    /// state journal, rather than overwriting it.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<StateJournal> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let contents = decode(&bytes)?;
+
+        let mut len = contents.len as u64;
+        if len == 0 {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&HEADER)?;
+            len = HEADER.len() as u64;
+        } else {
+            // Drop the remains of an interrupted checkpoint, so new ones get appended to the
+            // intact ones:
+            file.set_len(len)?;
+        }
+        file.sync_data()?;
+
+        let recovered = contents
+            .states
+            .into_iter()
+            .map(|(hash, tat)| (hash, UNIX_EPOCH + Nanos::from(tat)))
+            .collect();
+        Ok(StateJournal {
+            path,
+            file,
+            len,
+            checkpoints: contents.checkpoints,
+            max_checkpoints: DEFAULT_MAX_CHECKPOINTS,
+            recovered,
+        })
+    }
+
+    /// Compacts the journal once it holds `max_checkpoints` checkpoints. The default is 64.
+    pub fn with_max_checkpoints(mut self, max_checkpoints: NonZeroUsize) -> Self {
+        self.max_checkpoints = max_checkpoints;
+        self
+    }
+
+    /// Returns the path of the journal's file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the number of keys whose states were recovered when the journal was opened.
+    pub fn recovered_len(&self) -> usize {
+        self.recovered.len()
+    }
+
+    /// Appends a checkpoint with `states`, compacting the journal if it is full.
+    fn append(&mut self, states: &HashMap<u64, u64>) -> io::Result<()> {
+        let block = encode_checkpoint(states);
+        if self.checkpoints >= self.max_checkpoints.get() {
+            return self.compact(&block);
+        }
+        let written = self
+            .file
+            .seek(SeekFrom::Start(self.len))
+            .and_then(|_| self.file.write_all(&block))
+            .and_then(|_| self.file.sync_data());
+        if let Err(e) = written {
+            // Don't leave a partial checkpoint in front of the next one:
+            let _ = self.file.set_len(self.len);
+            return Err(e);
+        }
+        self.len += block.len() as u64;
+        self.checkpoints += 1;
+        Ok(())
+    }
+
+    /// Replaces the journal with one that only holds the checkpoint `block`.
+    fn compact(&mut self, block: &[u8]) -> io::Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp)?;
+        file.write_all(&HEADER)?;
+        file.write_all(block)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        #[cfg(unix)]
+        {
+            // Make the rename itself durable:
+            if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                File::open(dir)?.sync_all()?;
+            }
+        }
+
+        self.file = file;
+        self.len = (HEADER.len() + block.len()) as u64;
+        self.checkpoints = 1;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for StateJournal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StateJournal")
+            .field("path", &self.path)
+            .field("len", &self.len)
+            .field("checkpoints", &self.checkpoints)
+            .field("max_checkpoints", &self.max_checkpoints)
+            .finish()
+    }
+}
+
+/// A keyed state store that [checkpoints](RateLimiter::checkpoint) its states to a
+/// [`StateJournal`], and picks up the states recovered from it.
+///
+/// The journal only records a hash of each key, so the recovered states can't be put into the
+/// wrapped state store right away. Instead, this store keeps them on the side, and applies a
+/// recovered state to its key at the key's next decision: The rate limiter decides as if the
+/// key had the later of its own and its recovered state. Keys whose recovered states are
+/// indistinguishable from fresh ones by the time of a checkpoint or of
+/// [`retain_recent`](ShrinkableKeyedStateStore::retain_recent) are forgotten.
+///
+/// If two keys' hashes collide, both get the later of their recovered states, so they can
+/// only ever get fewer cells through than they would have without the restart.
+///
+/// Rate limiters with this store use the [`SystemClock`], as the journal records states as
+/// wall-clock time; construct them with
+/// [`RateLimiter::persistent`](../../struct.RateLimiter.html#method.persistent).
+pub struct PersistentStateStore<S: StateStore> {
+    inner: S,
+    journal: Mutex<StateJournal>,
+    /// The recovered states of keys that haven't been decided on since, by key hash.
+    recovered: Mutex<HashMap<u64, Nanos>>,
+    recovering: AtomicBool,
+}
+
+impl<S: StateStore> PersistentStateStore<S> {
+    /// Returns the wrapped state store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the recovered state of `key`, if it hasn't been applied yet.
+    fn recovered_state(&self, key: &S::Key) -> Option<(u64, Nanos)>
+    where
+        S::Key: Hash,
+    {
+        if !self.recovering.load(Ordering::Acquire) {
+            return None;
+        }
+        let hash = key_hash(key);
+        self.recovered.lock().get(&hash).map(|tat| (hash, *tat))
+    }
+
+    /// Forgets the recovered states that match `f`.
+    fn forget_recovered<F: Fn(u64, Nanos) -> bool>(&self, f: F) {
+        if !self.recovering.load(Ordering::Acquire) {
+            return;
+        }
+        let mut recovered = self.recovered.lock();
+        recovered.retain(|hash, tat| !f(*hash, *tat));
+        if recovered.is_empty() {
+            self.recovering.store(false, Ordering::Release);
+        }
+    }
+}
+
+impl<S> StateStore for PersistentStateStore<S>
+where
+    S: StateStore,
+    S::Key: Hash,
+{
+    type Key = S::Key;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        match self.recovered_state(key) {
+            None => self.inner.measure_and_replace(key, f),
+            Some((hash, recovered)) => {
+                let result = self.inner.measure_and_replace(key, |tat| {
+                    f(Some(tat.map_or(recovered, |tat| tat.max(recovered))))
+                })?;
+                // The key's stored state has caught up with its recovered one:
+                self.forget_recovered(|h, _| h == hash);
+                Ok(result)
+            }
+        }
+    }
+
+    fn note_denial(&self, key: &Self::Key, t0: Nanos) {
+        self.inner.note_denial(key, t0)
+    }
+
+    fn note_admission(&self, key: &Self::Key, t0: Nanos, tat: Nanos) {
+        self.inner.note_admission(key, t0, tat)
+    }
+
+    fn note_wait_started(&self, key: &Self::Key) {
+        self.inner.note_wait_started(key)
+    }
+
+    fn note_wait_finished(&self, key: &Self::Key) {
+        self.inner.note_wait_finished(key)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        match self.recovered_state(key) {
+            None => self.inner.measure_and_peek(key, f),
+            Some((_, recovered)) => self.inner.measure_and_peek(key, |tat| {
+                f(Some(tat.map_or(recovered, |tat| tat.max(recovered))))
+            }),
+        }
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        self.inner.restore(key, replaced, prev)
+    }
+}
+
+impl<K, S> ShrinkableKeyedStateStore<K> for PersistentStateStore<S>
+where
+    K: Hash + Eq + Clone,
+    S: ShrinkableKeyedStateStore<K>,
+{
+    fn retain_recent(&self, drop_below: Nanos) {
+        self.forget_recovered(|_, tat| tat <= drop_below);
+        self.inner.retain_recent(drop_below)
+    }
+
+    fn shrink_to_fit(&self) {
+        self.recovered.lock().shrink_to_fit();
+        self.inner.shrink_to_fit()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        self.inner.approx_memory_bytes()
+            + self.recovered.lock().capacity() * std::mem::size_of::<(u64, Nanos)>()
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        self.inner.pre_seed(keys)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        self.inner.export_states(drop_below)
+    }
+}
+
+impl<S> RebasableStateStore for PersistentStateStore<S>
+where
+    S: RebasableStateStore,
+    S::Key: Hash,
+{
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        for tat in self.recovered.get_mut().values_mut() {
+            *tat = f(*tat);
+        }
+        self.inner.rewrite_states(f)
+    }
+}
+
+impl<S> fmt::Debug for PersistentStateStore<S>
+where
+    S: StateStore + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PersistentStateStore")
+            .field("inner", &self.inner)
+            .field("journal", &*self.journal.lock())
+            .field("recovered", &self.recovered.lock().len())
+            .finish()
+    }
+}
+
+/// # Keyed rate limiters - Persisting states to disk
+///
+/// Services that run on a single node can keep their keyed rate limiters' states in a
+/// [`StateJournal`] on disk, so that a restart (or a crash) doesn't reset long-running quotas
+/// like daily ones. The journal records states as wall-clock time, which the restarted rate
+/// limiter re-anchors to its own start.
+impl<K, S> RateLimiter<K, PersistentStateStore<S>, SystemClock, NoOpMiddleware<SystemTime>>
+where
+    K: Hash + Eq + Clone,
+    S: ShrinkableKeyedStateStore<K>,
+{
+    /// Constructs a new keyed rate limiter that keeps its states in `inner`, and checkpoints
+    /// them to `journal`.
+    ///
+    /// The states that `journal` recovered are applied to their keys as the keys get used;
+    /// see [`PersistentStateStore`].
+    ///
+    /// # Example
+    /// ```rust
+    /// use governor::{
+    ///     state::keyed::{HashMapStateStore, StateJournal},
+    ///     Quota, RateLimiter,
+    /// };
+    /// use std::time::Duration;
+    /// # let path = std::env::temp_dir().join(format!("governor-doctest-{}", std::process::id()));
+    /// # let _ = std::fs::remove_file(&path);
+    ///
+    /// let quota = Quota::with_period(Duration::from_secs(24 * 60 * 60)).unwrap();
+    /// let lim = RateLimiter::persistent(
+    ///     quota,
+    ///     HashMapStateStore::default(),
+    ///     StateJournal::open(&path)?,
+    /// );
+    /// assert!(lim.check_key(&"alice").is_ok());
+    /// lim.checkpoint()?;
+    /// drop(lim);
+    ///
+    /// // After a restart, alice's quota is still used up:
+    /// let lim = RateLimiter::persistent(
+    ///     quota,
+    ///     HashMapStateStore::default(),
+    ///     StateJournal::open(&path)?,
+    /// );
+    /// assert!(lim.check_key(&"alice").is_err());
+    /// assert!(lim.check_key(&"bob").is_ok());
+    /// # std::fs::remove_file(&path)?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn persistent(quota: Quota, inner: S, journal: StateJournal) -> Self {
+        let recovered = journal.recovered.clone();
+        let store = PersistentStateStore {
+            inner,
+            journal: Mutex::new(journal),
+            recovered: Mutex::new(HashMap::with_capacity(recovered.len())),
+            recovering: AtomicBool::new(false),
+        };
+        let lim = RateLimiter::new(quota, store, SystemClock);
+
+        // Re-anchor the recovered states to the new rate limiter's start. States from before
+        // the start are indistinguishable from fresh ones:
+        let mut pending = lim.state.recovered.lock();
+        for (hash, wall) in recovered {
+            let wall = Reference::duration_since(&wall, UNIX_EPOCH);
+            let tat = lim.gcra.rewrite_state(wall, |t| {
+                Reference::duration_since(&(UNIX_EPOCH + t), lim.start)
+            });
+            if tat.as_u64() > 0 {
+                pending.insert(hash, tat);
+            }
+        }
+        lim.state
+            .recovering
+            .store(!pending.is_empty(), Ordering::Release);
+        drop(pending);
+        lim
+    }
+}
+
+impl<K, S, MW> RateLimiter<K, PersistentStateStore<S>, SystemClock, MW>
+where
+    K: Hash + Eq + Clone,
+    S: ShrinkableKeyedStateStore<K>,
+    MW: RateLimitingMiddleware<SystemTime>,
+{
+    /// Appends the states of the keys that were used recently to the rate limiter's
+    /// [`StateJournal`], and waits until they have reached the disk.
+    ///
+    /// Call this periodically (e.g. from a background thread), and before shutting down: A
+    /// restarted rate limiter recovers the states as of the last checkpoint, so the cells that
+    /// were allowed since then are forgotten. Like
+    /// [`usage_summary`](#method.usage_summary), this leaves out keys whose states are
+    /// indistinguishable from fresh ones, so a checkpoint's size only grows with the number of
+    /// active keys.
+    pub fn checkpoint(&self) -> io::Result<()> {
+        let now = self.clock.now();
+        let drop_below =
+            Reference::duration_since(&now, self.start).saturating_sub(self.gcra.stale_after());
+        let store = &self.state;
+        store.forget_recovered(|_, tat| tat <= drop_below);
+
+        let mut states: HashMap<u64, Nanos> = store.recovered.lock().clone();
+        for (key, tat) in store.inner.export_states(drop_below) {
+            let entry = states.entry(key_hash(&key)).or_insert(tat);
+            *entry = (*entry).max(tat);
+        }
+        let states = states
+            .into_iter()
+            .map(|(hash, tat)| {
+                let wall = self.gcra.rewrite_state(tat, |t| {
+                    Reference::duration_since(&(self.start + t), UNIX_EPOCH)
+                });
+                (hash, wall.as_u64())
+            })
+            .collect();
+        store.journal.lock().append(&states)
+    }
+
+    /// Returns the number of recovered states that haven't been applied to their keys yet.
+    pub fn pending_recovered_len(&self) -> usize {
+        self.state.recovered.lock().len()
+    }
+}
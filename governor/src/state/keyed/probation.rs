@@ -0,0 +1,288 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use crate::clock;
+use crate::middleware::RateLimitingMiddleware;
+use crate::nanos::Nanos;
+use crate::state::keyed::ShrinkableKeyedStateStore;
+use crate::state::{RebasableStateStore, StateStore};
+use crate::RateLimiter;
+use nonzero_ext::nonzero;
+use std::collections::hash_map::RandomState;
+use std::convert::Infallible;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::mem;
+use std::num::{NonZeroU8, NonZeroUsize};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+/// The number of sighting counters that a [`ProbationStateStore`] has by default.
+const DEFAULT_SLOTS: usize = 1 << 16;
+
+/// A keyed state store that only keeps state for keys once it has seen them a few times, to
+/// avoid allocating state for each key of a key-spraying attack.
+///
+/// Keys that the wrapped state store has no state for are *on probation*: The rate limiter
+/// decides them as if they were new, without storing any state for them, until their
+/// `sightings`th decision. That decision gets stored, and from then on, the key is rate limited
+/// like with the wrapped store alone. Looking up a key that has no state doesn't allocate with
+/// most state stores (e.g. the [`DashMapStateStore`](super::DashMapStateStore) only takes a
+/// shard's read lock), so keys that are only seen a few times cost no memory.
+///
+/// Sightings are counted in a fixed number of counters, which the keys' hashes get spread over.
+/// Keys that share a counter leave probation early, and
+/// [`retain_recent`](ShrinkableKeyedStateStore::retain_recent) resets all the counters.
+///
+/// # Trade-offs
+/// Decisions for keys on probation are positive (as the first decision for a key always is),
+/// so each key can get up to `sightings - 1` cells more than its burst size through before
+/// it gets rate limited. Only use this store where such an allowance is acceptable, e.g. to
+/// protect a keyed rate limiter's memory where clients can choose their keys.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{
+///     clock::DefaultClock, middleware::NoOpMiddleware,
+///     state::keyed::{HashMapStateStore, ProbationStateStore},
+///     Quota, RateLimiter,
+/// };
+///
+/// let store = ProbationStateStore::new(HashMapStateStore::default(), nonzero!(3u8));
+/// let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> =
+///     RateLimiter::new(Quota::per_hour(nonzero!(1u32)), store, DefaultClock::default());
+///
+/// // The first two decisions for a key don't store any state:
+/// assert!(lim.check_key(&"alice").is_ok());
+/// assert!(lim.check_key(&"alice").is_ok());
+/// assert_eq!(lim.len(), 0);
+/// assert_eq!(lim.probation_decisions(), 2);
+///
+/// // The third one does:
+/// assert!(lim.check_key(&"alice").is_ok());
+/// assert_eq!(lim.len(), 1);
+/// assert!(lim.check_key(&"alice").is_err());
+/// ```
+pub struct ProbationStateStore<S: StateStore, H = RandomState> {
+    inner: S,
+    sightings: NonZeroU8,
+    counters: Box<[AtomicU8]>,
+    hasher: H,
+    probation_decisions: AtomicU64,
+}
+
+impl<S: StateStore> ProbationStateStore<S> {
+    /// Wraps `inner`, keeping keys on probation until their `sightings`th decision.
+    ///
+    /// With a `sightings` of 1, no key is ever on probation.
+    pub fn new(inner: S, sightings: NonZeroU8) -> Self {
+        ProbationStateStore::with_hasher(inner, sightings, RandomState::new())
+    }
+}
+
+impl<S: StateStore, H> ProbationStateStore<S, H> {
+    /// Wraps `inner`, keeping keys on probation until their `sightings`th decision, and hashing
+    /// keys to their sighting counters with `hasher`.
+    ///
+    /// The default [`RandomState`] keeps attackers from predicting which keys share a counter.
+    pub fn with_hasher(inner: S, sightings: NonZeroU8, hasher: H) -> Self {
+        ProbationStateStore {
+            inner,
+            sightings,
+            counters: (0..DEFAULT_SLOTS).map(|_| AtomicU8::new(0)).collect(),
+            hasher,
+            probation_decisions: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts sightings in `slots` counters instead of the default of 65536.
+    ///
+    /// More counters take more memory (a byte each), but make it less likely that keys leave
+    /// probation early because they share a counter with other keys.
+    pub fn with_slots(self, slots: NonZeroUsize) -> Self {
+        ProbationStateStore {
+            counters: (0..slots.get()).map(|_| AtomicU8::new(0)).collect(),
+            ..self
+        }
+    }
+
+    /// Returns the wrapped state store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the number of decisions a key needs before its state gets stored.
+    pub fn sightings(&self) -> NonZeroU8 {
+        self.sightings
+    }
+
+    /// Returns the number of decisions made for keys on probation, which didn't store any
+    /// state.
+    pub fn probation_decisions(&self) -> u64 {
+        self.probation_decisions.load(Ordering::Relaxed)
+    }
+}
+
+impl<S, H> ProbationStateStore<S, H>
+where
+    S: StateStore,
+    S::Key: Hash,
+    H: BuildHasher,
+{
+    /// Counts a sighting of `key`, returning whether the key has been seen often enough to
+    /// leave probation.
+    fn sighted(&self, key: &S::Key) -> bool {
+        let slot = self.hasher.hash_one(key) % self.counters.len() as u64;
+        let counter = &self.counters[slot as usize];
+        let seen = counter
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |seen| {
+                Some(seen.saturating_add(1))
+            })
+            .unwrap_or_else(|seen| seen);
+        seen.saturating_add(1) >= self.sightings.get()
+    }
+}
+
+impl<S, H> StateStore for ProbationStateStore<S, H>
+where
+    S: StateStore,
+    S::Key: Hash,
+    H: BuildHasher,
+{
+    type Key = S::Key;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let known = self
+            .inner
+            .measure_and_peek(key, |tat| Ok::<_, Infallible>(tat.is_some()))
+            .unwrap_or_else(|never| match never {});
+        if known || self.sighted(key) {
+            return self.inner.measure_and_replace(key, f);
+        }
+        self.probation_decisions.fetch_add(1, Ordering::Relaxed);
+        f(None).map(|(result, _)| result)
+    }
+
+    fn note_denial(&self, key: &Self::Key, t0: Nanos) {
+        self.inner.note_denial(key, t0)
+    }
+
+    fn note_admission(&self, key: &Self::Key, t0: Nanos, tat: Nanos) {
+        self.inner.note_admission(key, t0, tat)
+    }
+
+    fn note_wait_started(&self, key: &Self::Key) {
+        self.inner.note_wait_started(key)
+    }
+
+    fn note_wait_finished(&self, key: &Self::Key) {
+        self.inner.note_wait_finished(key)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        self.inner.measure_and_peek(key, f)
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        self.inner.restore(key, replaced, prev)
+    }
+}
+
+impl<S, H> RebasableStateStore for ProbationStateStore<S, H>
+where
+    S: RebasableStateStore,
+    S::Key: Hash,
+    H: BuildHasher,
+{
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        self.inner.rewrite_states(f)
+    }
+}
+
+impl<K, S, H> ShrinkableKeyedStateStore<K> for ProbationStateStore<S, H>
+where
+    K: Hash + Eq + Clone,
+    S: ShrinkableKeyedStateStore<K>,
+    H: BuildHasher,
+{
+    fn retain_recent(&self, drop_below: Nanos) {
+        self.inner.retain_recent(drop_below);
+        for counter in self.counters.iter() {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn shrink_to_fit(&self) {
+        self.inner.shrink_to_fit()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        self.inner.approx_memory_bytes() + self.counters.len() * mem::size_of::<AtomicU8>()
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        self.inner.pre_seed(keys)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        self.inner.export_states(drop_below)
+    }
+}
+
+impl<S: StateStore + Default> Default for ProbationStateStore<S> {
+    /// Wraps the default state store, keeping keys on probation until their second decision.
+    fn default() -> Self {
+        ProbationStateStore::new(S::default(), nonzero!(2u8))
+    }
+}
+
+impl<S: StateStore + fmt::Debug, H> fmt::Debug for ProbationStateStore<S, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProbationStateStore")
+            .field("inner", &self.inner)
+            .field("sightings", &self.sightings)
+            .field("slots", &self.counters.len())
+            .field("probation_decisions", &self.probation_decisions())
+            .finish()
+    }
+}
+
+/// # Keyed rate limiters - Probation for new keys
+impl<K, S, H, C, MW> RateLimiter<K, ProbationStateStore<S, H>, C, MW>
+where
+    S: StateStore<Key = K>,
+    K: Hash,
+    H: BuildHasher,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the number of decisions that were made for keys on probation, without storing
+    /// any state.
+    ///
+    /// See [`ProbationStateStore`].
+    pub fn probation_decisions(&self) -> u64 {
+        self.state.probation_decisions()
+    }
+}
@@ -0,0 +1,322 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use crate::clock::{self, Reference};
+use crate::middleware::RateLimitingMiddleware;
+use crate::nanos::Nanos;
+use crate::state::keyed::{KeyedStateStore, ShrinkableKeyedStateStore};
+use crate::state::StateStore;
+use crate::sync::Mutex;
+use crate::RateLimiter;
+use std::cell::Cell;
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+use std::num::NonZeroUsize;
+
+/// A keyed state store that reports the states it stores, e.g. to keep a standby rate limiter
+/// warm.
+///
+/// Every time the wrapped state store successfully replaces a key's state, this store records
+/// the key and its new state (its "theoretical arrival time", an offset from the rate limiter's
+/// creation). The recorded changes get handed to the `sink` closure in batches of `batch_size`,
+/// so the rate limiter's decisions only pay for appending to a buffer. Changes that haven't
+/// filled a batch yet are handed over on [`flush`](#method.flush), and when the store is
+/// dropped.
+///
+/// Negative decisions don't change any state, so they aren't reported. A standby rate limiter
+/// applies the reported states with
+/// [`RateLimiter::apply_replicated_states`](../../struct.RateLimiter.html#method.apply_replicated_states).
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use std::sync::mpsc;
+/// use governor::{
+///     clock::FakeRelativeClock, middleware::NoOpMiddleware,
+///     state::keyed::{HashMapStateStore, ReplicatingStateStore},
+///     Quota, RateLimiter,
+/// };
+///
+/// let (tx, rx) = mpsc::sync_channel(16);
+/// let store = ReplicatingStateStore::new(
+///     HashMapStateStore::default(),
+///     nonzero!(2usize),
+///     move |batch| tx.send(batch).unwrap(),
+/// );
+/// let clock = FakeRelativeClock::default();
+/// let quota = Quota::per_second(nonzero!(2u32));
+/// let primary: RateLimiter<_, _, _, NoOpMiddleware<_>> =
+///     RateLimiter::new(quota, store, clock.clone());
+/// let standby = RateLimiter::hashmap_with_clock(quota, clock);
+///
+/// assert!(primary.check_key(&"alice").is_ok());
+/// assert!(primary.check_key(&"alice").is_ok());
+/// assert!(primary.check_key(&"alice").is_err());
+///
+/// standby.apply_replicated_states(rx.recv().unwrap());
+/// assert!(standby.check_key(&"alice").is_err());
+/// ```
+pub struct ReplicatingStateStore<S: StateStore, F: Fn(Vec<(S::Key, Nanos)>)> {
+    inner: S,
+    sink: F,
+    batch_size: NonZeroUsize,
+    pending: Mutex<Vec<(S::Key, Nanos)>>,
+}
+
+impl<S, F> ReplicatingStateStore<S, F>
+where
+    S: StateStore,
+    F: Fn(Vec<(S::Key, Nanos)>),
+{
+    /// Wraps `inner`, handing its changed states to `sink` in batches of `batch_size`.
+    pub fn new(inner: S, batch_size: NonZeroUsize, sink: F) -> Self {
+        ReplicatingStateStore {
+            inner,
+            sink,
+            batch_size,
+            pending: Mutex::new(Vec::with_capacity(batch_size.get())),
+        }
+    }
+
+    /// Returns the wrapped state store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Hands all recorded changes to the sink, even if they don't fill a batch.
+    pub fn flush(&self) {
+        let batch = mem::take(&mut *self.pending.lock());
+        if !batch.is_empty() {
+            (self.sink)(batch);
+        }
+    }
+
+    fn record(&self, key: &S::Key, tat: Nanos)
+    where
+        S::Key: Clone,
+    {
+        let batch = {
+            let mut pending = self.pending.lock();
+            pending.push((key.clone(), tat));
+            if pending.len() < self.batch_size.get() {
+                return;
+            }
+            mem::replace(&mut *pending, Vec::with_capacity(self.batch_size.get()))
+        };
+        (self.sink)(batch);
+    }
+}
+
+impl<S, F> StateStore for ReplicatingStateStore<S, F>
+where
+    S: StateStore,
+    S::Key: Clone,
+    F: Fn(Vec<(S::Key, Nanos)>),
+{
+    type Key = S::Key;
+
+    fn measure_and_replace<T, G, E>(&self, key: &Self::Key, f: G) -> Result<T, E>
+    where
+        G: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        // The closure may run several times; the state from its last run is the one that
+        // got stored.
+        let new_tat = Cell::new(None);
+        let result = self.inner.measure_and_replace(key, |tat| {
+            let (result, new) = f(tat)?;
+            new_tat.set(Some(new));
+            Ok((result, new))
+        })?;
+        if let Some(tat) = new_tat.get() {
+            self.record(key, tat);
+        }
+        Ok(result)
+    }
+
+    fn note_denial(&self, key: &Self::Key, t0: Nanos) {
+        self.inner.note_denial(key, t0)
+    }
+
+    fn note_admission(&self, key: &Self::Key, t0: Nanos, tat: Nanos) {
+        self.inner.note_admission(key, t0, tat)
+    }
+
+    fn note_wait_started(&self, key: &Self::Key) {
+        self.inner.note_wait_started(key)
+    }
+
+    fn note_wait_finished(&self, key: &Self::Key) {
+        self.inner.note_wait_finished(key)
+    }
+
+    fn measure_and_peek<T, G, E>(&self, key: &Self::Key, f: G) -> Result<T, E>
+    where
+        G: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        self.inner.measure_and_peek(key, f)
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        // The replicas keep the state that got taken back, which only makes them stricter
+        // until the key's next decision gets replicated.
+        self.inner.restore(key, replaced, prev)
+    }
+}
+
+impl<K, S, F> ShrinkableKeyedStateStore<K> for ReplicatingStateStore<S, F>
+where
+    K: Hash + Eq + Clone,
+    S: ShrinkableKeyedStateStore<K>,
+    F: Fn(Vec<(K, Nanos)>),
+{
+    fn retain_recent(&self, drop_below: Nanos) {
+        self.inner.retain_recent(drop_below)
+    }
+
+    fn shrink_to_fit(&self) {
+        self.inner.shrink_to_fit()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        self.inner.approx_memory_bytes()
+            + self.batch_size.get() * mem::size_of::<(K, Nanos)>()
+            + mem::size_of::<F>()
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        self.inner.pre_seed(keys)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        self.inner.export_states(drop_below)
+    }
+}
+
+impl<S: StateStore, F: Fn(Vec<(S::Key, Nanos)>)> Drop for ReplicatingStateStore<S, F> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl<S, F> fmt::Debug for ReplicatingStateStore<S, F>
+where
+    S: StateStore + fmt::Debug,
+    F: Fn(Vec<(S::Key, Nanos)>),
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReplicatingStateStore")
+            .field("inner", &self.inner)
+            .field("batch_size", &self.batch_size)
+            .field("pending", &self.pending.lock().len())
+            .finish()
+    }
+}
+
+/// # Keyed rate limiters - Replication
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Applies states reported by a [`ReplicatingStateStore`], e.g. on a standby rate limiter.
+    ///
+    /// Each key's state is only moved forward: If this rate limiter has allowed more cells for
+    /// a key than the replicated state says, its own state is kept. The states are offsets from
+    /// the primary rate limiter's creation, so the standby needs to have the same
+    /// [`start`](#method.start) (see [`rebase_start`](#method.rebase_start)) for them to mean
+    /// the same thing.
+    pub fn apply_replicated_states<I>(&self, states: I)
+    where
+        I: IntoIterator<Item = (K, Nanos)>,
+    {
+        for (key, tat) in states {
+            let _ = self.state.measure_and_replace(&key, |prev| {
+                Ok::<_, ()>(((), prev.map_or(tat, |prev| prev.max(tat))))
+            });
+        }
+    }
+}
+
+/// # Keyed rate limiters - Reconciling replicas
+///
+/// Rate limiters on several nodes can approximate a shared limit without a central state store
+/// by periodically exchanging summaries of their keys' usage (e.g. over a gossip protocol), and
+/// merging each other's summaries. Merging is conservative: Each key ends up with the state of
+/// whichever replica allowed the most cells for it, so exchanging summaries never lets more
+/// cells through than a single replica would. As cells that replicas allowed between two
+/// exchanges aren't added up, the shared limit is only approximate.
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: ShrinkableKeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the states of the keys that were used recently, to send to other replicas.
+    ///
+    /// Like [`retain_recent`](#method.retain_recent), this leaves out keys whose states are
+    /// indistinguishable from fresh ones, so the summary only grows with the number of active
+    /// keys. Only state stores that can enumerate their keys (like all the keyed state stores in
+    /// this crate) report any; see [`ShrinkableKeyedStateStore::export_states`].
+    ///
+    /// # Example
+    /// ```rust
+    /// # use nonzero_ext::nonzero;
+    /// use governor::{clock::FakeRelativeClock, Quota, RateLimiter};
+    ///
+    /// let clock = FakeRelativeClock::default();
+    /// let quota = Quota::per_second(nonzero!(2u32));
+    /// let here = RateLimiter::hashmap_with_clock(quota, clock.clone());
+    /// let there = RateLimiter::hashmap_with_clock(quota, clock.clone());
+    ///
+    /// assert!(here.check_key(&"alice").is_ok());
+    /// assert!(there.check_key(&"alice").is_ok());
+    /// there.merge_usage_summary(here.usage_summary());
+    ///
+    /// // `there` only keeps the larger of the two states, so it still has capacity left:
+    /// assert!(there.check_key(&"alice").is_ok());
+    /// assert!(there.check_key(&"alice").is_err());
+    /// ```
+    pub fn usage_summary(&self) -> Vec<(K, Nanos)> {
+        self.state.export_states(self.fresh_below())
+    }
+
+    /// Merges a summary from another replica's [`usage_summary`](#method.usage_summary).
+    ///
+    /// Each key keeps the later of its own and the summarized state, as with
+    /// [`apply_replicated_states`](#method.apply_replicated_states); summarized states that
+    /// are indistinguishable from fresh ones by now are skipped, so they don't create entries.
+    /// Merging is idempotent, and the order in which summaries are merged doesn't matter. The
+    /// states are offsets from the rate limiters' [`start`](#method.start), so all replicas need
+    /// to have the same one.
+    pub fn merge_usage_summary<I>(&self, summary: I)
+    where
+        I: IntoIterator<Item = (K, Nanos)>,
+    {
+        let drop_below = self.fresh_below();
+        self.apply_replicated_states(summary.into_iter().filter(|(_, tat)| *tat > drop_below));
+    }
+
+    /// The state below which keys are indistinguishable from fresh ones, as of now.
+    fn fresh_below(&self) -> Nanos {
+        let now = self.clock.now();
+        now.duration_since(self.start)
+            .saturating_sub(self.gcra.stale_after())
+    }
+}
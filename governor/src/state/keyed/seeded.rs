@@ -0,0 +1,67 @@
+use std::prelude::v1::*;
+
+use std::fmt;
+use std::hash::BuildHasher;
+
+use siphasher::sip::SipHasher13;
+
+/// A hasher for keyed state stores that hashes keys with SipHash-1-3 and a secret seed.
+///
+/// Keyed state stores keep their keys in hash tables. If an adversary can choose keys whose
+/// hashes collide, each decision for them has to search through all the colliding keys, which
+/// can make a rate limiter spend more time on bookkeeping than on the requests it protects
+/// (known as HashDoS). SipHash with a seed that the adversary doesn't know prevents this.
+///
+/// With the `std` feature, the default hasher of the
+/// [`HashMapStateStore`](super::HashMapStateStore) and the
+/// [`DashMapStateStore`](super::DashMapStateStore) is already SipHash-1-3, with a seed that is
+/// randomly chosen for each process. Without `std`, there is no source of randomness to draw a
+/// seed from, so it has to be provided instead, e.g. from a hardware random number generator
+/// or from the device's configuration. A fixed seed also makes keys hash the same in different
+/// processes.
+///
+/// The seed should be kept secret: the `Debug` output doesn't include it.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{state::keyed::SeededHashBuilder, Quota, RateLimiter};
+///
+/// let seed = [0x5e; 16]; // Use a secret, random seed instead.
+/// let lim = RateLimiter::hashmap_with_hasher(
+///     Quota::per_second(nonzero!(1u32)),
+///     SeededHashBuilder::new(seed),
+/// );
+/// assert!(lim.check_key(&"alice").is_ok());
+/// assert!(lim.check_key(&"alice").is_err());
+/// ```
+#[derive(Clone)]
+pub struct SeededHashBuilder {
+    k0: u64,
+    k1: u64,
+}
+
+impl SeededHashBuilder {
+    /// Constructs a hasher with the 128-bit `seed`.
+    pub const fn new(seed: [u8; 16]) -> Self {
+        let [a0, a1, a2, a3, a4, a5, a6, a7, b0, b1, b2, b3, b4, b5, b6, b7] = seed;
+        SeededHashBuilder {
+            k0: u64::from_le_bytes([a0, a1, a2, a3, a4, a5, a6, a7]),
+            k1: u64::from_le_bytes([b0, b1, b2, b3, b4, b5, b6, b7]),
+        }
+    }
+}
+
+impl BuildHasher for SeededHashBuilder {
+    type Hasher = SipHasher13;
+
+    fn build_hasher(&self) -> SipHasher13 {
+        SipHasher13::new_with_keys(self.k0, self.k1)
+    }
+}
+
+impl fmt::Debug for SeededHashBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeededHashBuilder").finish_non_exhaustive()
+    }
+}
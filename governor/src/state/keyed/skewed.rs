@@ -0,0 +1,370 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use crate::clock;
+use crate::gcra::Gcra;
+use crate::middleware::RateLimitingMiddleware;
+use crate::nanos::Nanos;
+use crate::state::keyed::{KeyedStateStore, ShrinkableKeyedStateStore};
+use crate::state::{RebasableStateStore, StateStore};
+use crate::sync::RwLock;
+use crate::{Quota, RateLimiter};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::mem;
+use std::time::Duration;
+
+/// How far the clock that is authoritative for a key is off from the rate limiter's clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClockSkew {
+    /// The key's clock reads later than the rate limiter's clock, by the given duration.
+    Ahead(Duration),
+
+    /// The key's clock reads earlier than the rate limiter's clock, by the given duration.
+    Behind(Duration),
+}
+
+impl ClockSkew {
+    /// Translates a time on the key's clock to the rate limiter's clock.
+    fn to_local(self, t: Nanos) -> Nanos {
+        match self {
+            ClockSkew::Ahead(d) => t.saturating_sub(Nanos::saturating_from(d)),
+            ClockSkew::Behind(d) => t + Nanos::saturating_from(d),
+        }
+    }
+
+    /// Translates a time on the rate limiter's clock to the key's clock.
+    fn to_key(self, t: Nanos) -> Nanos {
+        match self {
+            ClockSkew::Ahead(d) => t + Nanos::saturating_from(d),
+            ClockSkew::Behind(d) => t.saturating_sub(Nanos::saturating_from(d)),
+        }
+    }
+}
+
+/// A keyed state store that makes decisions for some keys as if they were made on another
+/// clock.
+///
+/// When several regions each enforce a share of a global quota, each key usually has one
+/// region whose clock is authoritative for it, and whose states the other regions apply (e.g.
+/// ones reported by a [`ReplicatingStateStore`](struct.ReplicatingStateStore.html)). If the
+/// regions' clocks are off from each other, those states let cells through too early or too
+/// late by that offset. This store takes a [`ClockSkew`] for each such key, and adjusts the
+/// time at which decisions for the key are made by it. Keys without a skew are decided on the
+/// rate limiter's clock.
+///
+/// The wrapped state store keeps the states on each key's own clock. Rate limiters apply
+/// states from a key's authoritative region with
+/// [`RateLimiter::apply_authoritative_states`](../../struct.RateLimiter.html#method.apply_authoritative_states).
+/// The wait times that negative decisions report are measured on the rate limiter's clock.
+///
+/// The store needs the quota of the rate limiter that it is used with: With a
+/// [burst recharge delay](crate::Quota::with_burst_recharge_delay), states keep the time of the
+/// last positive decision along with the theoretical arrival time, and the store moves both of
+/// them to the other clock.
+///
+/// # Limitations
+/// States can't lie before the rate limiter's creation: A key whose clock is
+/// [`Behind`](ClockSkew::Behind) by more than the time that passed since the rate limiter was
+/// created is decided as if its clock had been started along with the rate limiter.
+///
+/// The store keeps translating states for the quota that it was constructed with, even if the
+/// rate limiter's quota is changed with [`set_quota`](crate::RateLimiter::set_quota).
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// use std::sync::mpsc;
+/// use governor::{
+///     clock::FakeRelativeClock, middleware::NoOpMiddleware,
+///     state::keyed::{ClockSkew, HashMapStateStore, ReplicatingStateStore, SkewedStateStore},
+///     Quota, RateLimiter,
+/// };
+///
+/// let quota = Quota::per_second(nonzero!(1u32));
+/// let (tx, rx) = mpsc::channel();
+/// let eu_clock = FakeRelativeClock::default();
+/// let eu: RateLimiter<_, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+///     quota,
+///     ReplicatingStateStore::new(HashMapStateStore::default(), nonzero!(1usize), move |b| {
+///         tx.send(b).unwrap()
+///     }),
+///     eu_clock.clone(),
+/// );
+/// let us_clock = FakeRelativeClock::default();
+/// let us: RateLimiter<_, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+///     quota,
+///     SkewedStateStore::new(HashMapStateStore::default(), quota)
+///         .with_skew("eu-tenant", ClockSkew::Ahead(Duration::from_millis(200))),
+///     us_clock.clone(),
+/// );
+///
+/// // The EU's clock runs 200ms ahead of the US's:
+/// eu_clock.advance(Duration::from_millis(200));
+/// assert!(eu.check_key(&"eu-tenant").is_ok());
+/// us.apply_authoritative_states(rx.recv().unwrap());
+///
+/// // A second after the EU's decision (on the EU's clock), the next cell conforms:
+/// us_clock.advance(Duration::from_millis(1000));
+/// assert!(us.check_key(&"eu-tenant").is_ok());
+/// ```
+pub struct SkewedStateStore<S: StateStore> {
+    inner: S,
+    skews: RwLock<HashMap<S::Key, ClockSkew>>,
+    gcra: Gcra,
+}
+
+impl<S> SkewedStateStore<S>
+where
+    S: StateStore,
+    S::Key: Hash + Eq,
+{
+    /// Wraps `inner` for a rate limiter with the given `quota`, without any skews yet.
+    pub fn new(inner: S, quota: Quota) -> Self {
+        SkewedStateStore {
+            inner,
+            skews: RwLock::new(HashMap::new()),
+            gcra: Gcra::new(quota),
+        }
+    }
+
+    /// Decides `key` as if the rate limiter's clock was off by `skew`.
+    pub fn with_skew(self, key: S::Key, skew: ClockSkew) -> Self {
+        self.skews.write().insert(key, skew);
+        self
+    }
+
+    /// Sets the skew for `key`, returning the previous one.
+    ///
+    /// Changing a key's skew takes effect on its next decision, and changes the time at which
+    /// cells for it conform by the difference between the skews.
+    pub fn set_skew(&self, key: S::Key, skew: ClockSkew) -> Option<ClockSkew> {
+        self.skews.write().insert(key, skew)
+    }
+
+    /// Removes the skew for `key`, so that it gets decided on the rate limiter's clock.
+    pub fn remove_skew(&self, key: &S::Key) -> Option<ClockSkew> {
+        self.skews.write().remove(key)
+    }
+
+    /// Returns the skew for `key`, if it has one.
+    pub fn skew(&self, key: &S::Key) -> Option<ClockSkew> {
+        self.skews.read().get(key).copied()
+    }
+
+    /// Returns the wrapped state store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Translates a state kept on the key's clock to the rate limiter's clock.
+    fn state_to_local(&self, skew: ClockSkew, state: Nanos) -> Nanos {
+        self.gcra.rewrite_state(state, |t| skew.to_local(t))
+    }
+
+    /// Translates a state computed on the rate limiter's clock to the key's clock.
+    fn state_to_key(&self, skew: ClockSkew, state: Nanos) -> Nanos {
+        self.gcra.rewrite_state(state, |t| skew.to_key(t))
+    }
+
+    /// Returns how far the clock that is furthest behind ours is behind: Keys on such clocks
+    /// keep their states on an earlier time scale.
+    fn max_behind(&self) -> Nanos {
+        self.skews
+            .read()
+            .values()
+            .filter_map(|skew| match skew {
+                ClockSkew::Behind(d) => Some(Nanos::saturating_from(*d)),
+                ClockSkew::Ahead(_) => None,
+            })
+            .max()
+            .unwrap_or_else(|| Nanos::from(0))
+    }
+}
+
+impl<S> StateStore for SkewedStateStore<S>
+where
+    S: StateStore,
+    S::Key: Hash + Eq,
+{
+    type Key = S::Key;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let Some(skew) = self.skew(key) else {
+            return self.inner.measure_and_replace(key, f);
+        };
+        self.inner.measure_and_replace(key, |tat| {
+            let (result, new) = f(tat.map(|tat| self.state_to_local(skew, tat)))?;
+            Ok((result, self.state_to_key(skew, new)))
+        })
+    }
+
+    fn note_denial(&self, key: &Self::Key, t0: Nanos) {
+        // The decision was made at `t0` on the key's clock:
+        let t0 = self.skew(key).map_or(t0, |skew| skew.to_key(t0));
+        self.inner.note_denial(key, t0)
+    }
+
+    fn note_admission(&self, key: &Self::Key, t0: Nanos, tat: Nanos) {
+        // The new state was already translated to the key's clock when it got stored:
+        let t0 = self.skew(key).map_or(t0, |skew| skew.to_key(t0));
+        self.inner.note_admission(key, t0, tat)
+    }
+
+    fn note_wait_started(&self, key: &Self::Key) {
+        self.inner.note_wait_started(key)
+    }
+
+    fn note_wait_finished(&self, key: &Self::Key) {
+        self.inner.note_wait_finished(key)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        let Some(skew) = self.skew(key) else {
+            return self.inner.measure_and_peek(key, f);
+        };
+        self.inner
+            .measure_and_peek(key, |tat| f(tat.map(|tat| self.state_to_local(skew, tat))))
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        let Some(skew) = self.skew(key) else {
+            return self.inner.restore(key, replaced, prev);
+        };
+        self.inner.restore(
+            key,
+            self.state_to_key(skew, replaced),
+            prev.map(|prev| self.state_to_key(skew, prev)),
+        )
+    }
+}
+
+impl<S> RebasableStateStore for SkewedStateStore<S>
+where
+    S: RebasableStateStore,
+    S::Key: Hash + Eq,
+{
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        // Re-anchoring moves all clocks by the same amount, which doesn't change their skews.
+        self.inner.rewrite_states(f)
+    }
+}
+
+impl<K, S> ShrinkableKeyedStateStore<K> for SkewedStateStore<S>
+where
+    K: Hash + Eq + Clone,
+    S: ShrinkableKeyedStateStore<K>,
+{
+    fn retain_recent(&self, drop_below: Nanos) {
+        self.inner
+            .retain_recent(drop_below.saturating_sub(self.max_behind()))
+    }
+
+    fn shrink_to_fit(&self) {
+        self.skews.write().shrink_to_fit();
+        self.inner.shrink_to_fit()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        self.inner.approx_memory_bytes()
+            + self.skews.read().capacity() * mem::size_of::<(K, ClockSkew)>()
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        self.inner.pre_seed(keys)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        // Translate the states back to our clock, like decisions see them:
+        self.inner
+            .export_states(drop_below.saturating_sub(self.max_behind()))
+            .into_iter()
+            .filter_map(|(key, tat)| {
+                let tat = self
+                    .skew(&key)
+                    .map_or(tat, |skew| self.state_to_local(skew, tat));
+                (tat > drop_below).then_some((key, tat))
+            })
+            .collect()
+    }
+}
+
+impl<S> fmt::Debug for SkewedStateStore<S>
+where
+    S: StateStore + fmt::Debug,
+    S::Key: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SkewedStateStore")
+            .field("inner", &self.inner)
+            .field("skews", &*self.skews.read())
+            .finish()
+    }
+}
+
+/// # Keyed rate limiters - Clock skews
+impl<K, S, C, MW> RateLimiter<K, SkewedStateStore<S>, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash + Eq,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Sets the skew for `key`, returning the previous one. See
+    /// [`SkewedStateStore::set_skew`].
+    pub fn set_clock_skew(&self, key: K, skew: ClockSkew) -> Option<ClockSkew> {
+        self.state.set_skew(key, skew)
+    }
+
+    /// Removes the skew for `key`, so that it gets decided on the rate limiter's clock.
+    pub fn remove_clock_skew(&self, key: &K) -> Option<ClockSkew> {
+        self.state.remove_skew(key)
+    }
+
+    /// Returns the skew for `key`, if it has one.
+    pub fn clock_skew(&self, key: &K) -> Option<ClockSkew> {
+        self.state.skew(key)
+    }
+
+    /// Applies states that were recorded on each key's own clock, e.g. by a
+    /// [`ReplicatingStateStore`](state/keyed/struct.ReplicatingStateStore.html) in the key's
+    /// authoritative region.
+    ///
+    /// Like [`apply_replicated_states`](#method.apply_replicated_states), this only moves each
+    /// key's state forward, and the states need to be offsets from the same
+    /// [`start`](#method.start).
+    pub fn apply_authoritative_states<I>(&self, states: I)
+    where
+        I: IntoIterator<Item = (K, Nanos)>,
+    {
+        for (key, tat) in states {
+            let _ = self.state.inner.measure_and_replace(&key, |prev| {
+                Ok::<_, ()>(((), prev.map_or(tat, |prev| prev.max(tat))))
+            });
+        }
+    }
+}
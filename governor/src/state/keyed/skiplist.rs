@@ -0,0 +1,186 @@
+#![cfg(all(feature = "std", feature = "skiplist"))]
+
+use std::prelude::v1::*;
+
+use crate::nanos::Nanos;
+use crate::state::{InMemoryState, RebasableStateStore, StateStore};
+use crate::{clock, Quota, RateLimiter};
+use crate::{middleware::NoOpMiddleware, state::keyed::ShrinkableKeyedStateStore};
+use crossbeam_skiplist::SkipMap;
+use std::hash::Hash;
+use std::mem;
+
+/// **Experimental**: A lock-free keyed state store based on crossbeam's [`SkipMap`].
+///
+/// The [`DashMapStateStore`](super::DashMapStateStore) guards each of its shards with a lock,
+/// which all threads that check keys in the shard contend for. A skip list needs no locks at
+/// all, so this state store may let very hot workloads on many keys through faster, at the
+/// cost of looking up keys in `O(log n)` comparisons instead of hashing them. Its keys have to
+/// be ordered as well as hashable.
+///
+/// This state store is available with the `skiplist` feature. It is experimental: It might
+/// change or go away, and the `multi_threaded` benchmarks measure it against the other keyed
+/// state stores to find out whether it should become a default.
+///
+/// # Removing keys
+///
+/// Removing a key from a skip list can't be made atomic with checking its state, so
+/// [`retain_recent`](ShrinkableKeyedStateStore::retain_recent) can remove a key whose state got
+/// updated at the same moment; decisions that notice that their key got removed start over,
+/// but an update that happens just before the removal may get lost. This can only make the
+/// rate limiter let through more cells for the key, as if its state had gone stale, never
+/// fewer.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{Quota, RateLimiter};
+///
+/// let lim = RateLimiter::skiplist(Quota::per_second(nonzero!(1u32)));
+/// assert!(lim.check_key(&"alice").is_ok());
+/// assert!(lim.check_key(&"alice").is_err());
+/// assert!(lim.check_key(&"bob").is_ok());
+/// ```
+pub type SkipListStateStore<K> = SkipMap<K, InMemoryState>;
+
+impl<K> StateStore for SkipListStateStore<K>
+where
+    K: Hash + Ord + Clone + Send + 'static,
+{
+    type Key = K;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let mut prev = peek(self, key);
+        loop {
+            let (result, new) = f(prev)?;
+            let entry = match self.get(key) {
+                Some(entry) => entry,
+                None => self.get_or_insert(key.clone(), InMemoryState::default()),
+            };
+            match entry.value().compare_and_replace(prev, new) {
+                // The update only sticks if the entry is still in the map:
+                Ok(()) if !entry.is_removed() => return Ok(result),
+                Ok(()) => prev = peek(self, key),
+                Err(current) => prev = current,
+            }
+        }
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        // Don't insert an entry for the key:
+        f(peek(self, key))
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        // A zero state reads as no state, and `retain_recent` drops its entry:
+        if let Some(entry) = self.get(key) {
+            entry.value().restore_one(replaced, prev);
+        }
+    }
+}
+
+/// Returns the state stored for `key`.
+fn peek<K: Ord>(map: &SkipListStateStore<K>, key: &K) -> Option<Nanos> {
+    map.get(key)
+        .and_then(|entry| entry.value().measure_and_peek_one(|tat| tat))
+}
+
+impl<K> RebasableStateStore for SkipListStateStore<K>
+where
+    K: Hash + Ord + Clone + Send + 'static,
+{
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        for entry in self.iter() {
+            let state = entry.value();
+            if let Some(tat) = state.measure_and_peek_one(|tat| tat) {
+                state.replace(f(tat));
+            }
+        }
+    }
+}
+
+impl<K> ShrinkableKeyedStateStore<K> for SkipListStateStore<K>
+where
+    K: Hash + Ord + Clone + Send + 'static,
+{
+    fn retain_recent(&self, drop_below: Nanos) {
+        for entry in self.iter() {
+            if entry.value().is_older_than(drop_below) {
+                entry.remove();
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        SkipMap::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        SkipMap::is_empty(self)
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        // Each node holds its key and state, a word with its reference count and height, and a
+        // tower of pointers to the next nodes; towers are two pointers high on average.
+        let node =
+            mem::size_of::<K>() + mem::size_of::<InMemoryState>() + 3 * mem::size_of::<usize>();
+        mem::size_of::<Self>() + SkipMap::len(self) * node
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        for key in keys {
+            self.get_or_insert(key, InMemoryState::default());
+        }
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        SkipMap::contains_key(self, key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        self.iter()
+            .filter_map(|entry| {
+                entry
+                    .value()
+                    .measure_and_peek_one(|tat| tat)
+                    .filter(|tat| *tat > drop_below)
+                    .map(|tat| (entry.key().clone(), tat))
+            })
+            .collect()
+    }
+}
+
+/// # Keyed rate limiters - [`SkipMap`]-backed
+impl<K, C> RateLimiter<K, SkipListStateStore<K>, C, NoOpMiddleware<C::Instant>>
+where
+    K: Hash + Ord + Clone + Send + 'static,
+    C: clock::Clock,
+{
+    /// Constructs a new rate limiter with a custom clock, backed by a [`SkipMap`].
+    ///
+    /// See [`SkipListStateStore`] for why this is experimental.
+    pub fn skiplist_with_clock(quota: Quota, clock: C) -> Self {
+        RateLimiter::new(quota, SkipMap::new(), clock)
+    }
+}
+
+impl<K> RateLimiter<K, SkipListStateStore<K>, clock::DefaultClock>
+where
+    K: Hash + Ord + Clone + Send + 'static,
+{
+    /// Constructs a new keyed rate limiter backed by a [`SkipMap`].
+    ///
+    /// See [`SkipListStateStore`] for why this is experimental.
+    pub fn skiplist(quota: Quota) -> Self {
+        RateLimiter::skiplist_with_clock(quota, clock::DefaultClock::default())
+    }
+}
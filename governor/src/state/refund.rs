@@ -0,0 +1,202 @@
+use std::prelude::v1::*;
+
+use std::fmt;
+use std::hash::Hash;
+
+use crate::{
+    clock,
+    middleware::RateLimitingMiddleware,
+    state::{DirectStateStore, NotKeyed, StateStore},
+    RateLimiter,
+};
+
+use super::keyed::KeyedStateStore;
+
+/// A positive rate-limiting decision whose cell is given back to the rate limiter unless it is
+/// committed.
+///
+/// Returned by [`RateLimiter::check_refundable`] and [`RateLimiter::check_key_refundable`].
+/// Dropping the guard (or calling [`refund`](#method.refund)) refunds the cell, as if it had
+/// never been let through; calling [`commit`](#method.commit) keeps it counted. This makes it
+/// possible to only count some outcomes of an operation against the quota, e.g., only failed
+/// login attempts:
+///
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// use governor::{Quota, RateLimiter};
+///
+/// let lim = RateLimiter::keyed(Quota::per_minute(nonzero!(3u32)));
+/// # let password_ok = |attempt: u32| attempt < 10;
+/// for attempt in 0..13 {
+///     let guard = lim.check_key_refundable(&"alice").unwrap();
+///     // Successful logins don't count against the quota:
+///     if !password_ok(attempt) {
+///         guard.commit();
+///     }
+/// }
+/// // Three failed attempts used up the quota:
+/// assert!(lim.check_key_refundable(&"alice").is_err());
+/// ```
+///
+/// Refunds are best applied soon after the check: A refund removes one cell's worth of time
+/// from the rate limiting state as it is at the time of the refund, so if the state has been
+/// replenished in the meantime, the refund can let additional cells through.
+#[must_use = "dropping the guard refunds the cell right away"]
+pub struct Refundable<'a, K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    outcome: MW::PositiveOutcome,
+    refund: Refund<'a, K, S, C, MW>,
+}
+
+/// Refunds a cell when it is dropped, unless it was disarmed.
+struct Refund<'a, K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    limiter: &'a RateLimiter<K, S, C, MW>,
+    key: K,
+    armed: bool,
+}
+
+impl<'a, K, S, C, MW> Refundable<'a, K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// The positive outcome of the rate limiting decision.
+    pub fn outcome(&self) -> &MW::PositiveOutcome {
+        &self.outcome
+    }
+
+    /// Keeps the cell counted against the rate limiter, returning the decision's positive
+    /// outcome.
+    pub fn commit(self) -> MW::PositiveOutcome {
+        let Refundable {
+            outcome,
+            mut refund,
+        } = self;
+        refund.armed = false;
+        outcome
+    }
+
+    /// Gives the cell back to the rate limiter, the same as dropping the guard.
+    pub fn refund(self) {}
+}
+
+impl<'a, K, S, C, MW> Drop for Refund<'a, K, S, C, MW>
+where
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    fn drop(&mut self) {
+        if self.armed {
+            self.limiter.gcra.refund(&self.key, &self.limiter.state);
+        }
+    }
+}
+
+impl<'a, K, S, C, MW> fmt::Debug for Refundable<'a, K, S, C, MW>
+where
+    K: fmt::Debug,
+    S: StateStore<Key = K>,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+    MW::PositiveOutcome: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Refundable")
+            .field("key", &self.refund.key)
+            .field("outcome", &self.outcome)
+            .finish()
+    }
+}
+
+/// # Direct rate limiters - Refunding cells
+impl<S, C, MW> RateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Allow a single cell through the rate limiter, giving it back unless the returned guard
+    /// is [committed](Refundable::commit).
+    ///
+    /// Makes the same decision as [`check`](#method.check).
+    pub fn check_refundable(
+        &self,
+    ) -> Result<Refundable<'_, NotKeyed, S, C, MW>, MW::NegativeOutcome> {
+        let outcome = self.gcra.test_and_update::<NotKeyed, C::Instant, S, MW>(
+            self.start,
+            &NotKeyed::NonKey,
+            self.key_format(),
+            &self.state,
+            self.clock.now(),
+        )?;
+        Ok(Refundable {
+            outcome,
+            refund: Refund {
+                limiter: self,
+                key: NotKeyed::NonKey,
+                armed: true,
+            },
+        })
+    }
+
+    /// Gives a single cell back to the rate limiter.
+    ///
+    /// This undoes the effect of the most recent positive decision; see [`Refundable`] for
+    /// caveats.
+    pub fn refund(&self) {
+        self.gcra.refund(&NotKeyed::NonKey, &self.state);
+    }
+}
+
+/// # Keyed rate limiters - Refunding cells
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash + Clone,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Allow a single cell through the rate limiter for the given key, giving it back unless
+    /// the returned guard is [committed](Refundable::commit).
+    ///
+    /// Makes the same decision as [`check_key`](#method.check_key).
+    pub fn check_key_refundable(
+        &self,
+        key: &K,
+    ) -> Result<Refundable<'_, K, S, C, MW>, MW::NegativeOutcome> {
+        let outcome = self.gcra.test_and_update::<K, C::Instant, S, MW>(
+            self.start,
+            key,
+            self.key_format(),
+            &self.state,
+            self.clock.now(),
+        )?;
+        Ok(Refundable {
+            outcome,
+            refund: Refund {
+                limiter: self,
+                key: key.clone(),
+                armed: true,
+            },
+        })
+    }
+
+    /// Gives a single cell back to the rate limiter for the given key.
+    ///
+    /// This undoes the effect of the key's most recent positive decision; see [`Refundable`]
+    /// for caveats. Does nothing if the rate limiter has no state for the key.
+    pub fn refund_key(&self, key: &K) {
+        self.gcra.refund(key, &self.state);
+    }
+}
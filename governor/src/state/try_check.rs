@@ -0,0 +1,224 @@
+use std::prelude::v1::*;
+
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+use crate::middleware::{RateLimitingMiddleware, StateSnapshot};
+use crate::state::keyed::KeyedStateStore;
+use crate::state::{DirectStateStore, NotKeyed};
+use crate::{clock, InsufficientCapacity, RateLimiter};
+
+/// The result of a rate-limiting decision, made by one of the `try_check` family of methods.
+///
+/// Unlike [`check_n`](RateLimiter::check_n), whose result nests two `Result`s to distinguish
+/// batches that can't go through *yet* from batches that can *never* go through, the
+/// `try_check` methods report all three possible outcomes as variants of a single enum.
+/// To keep the middleware's negative outcome instead of the time to wait, use
+/// [`Decision`](crate::state::Decision).
+///
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// # use std::time::Duration;
+/// use governor::{clock::FakeRelativeClock, state::TryCheck, Quota, RateLimiter};
+///
+/// let lim = RateLimiter::direct_with_clock(
+///     Quota::per_second(nonzero!(2u32)),
+///     FakeRelativeClock::default(),
+/// );
+/// assert_eq!(lim.try_check(), TryCheck::Allowed(()));
+/// assert_eq!(lim.try_check_n(nonzero!(3u32)), TryCheck::InsufficientCapacity { max: 2 });
+/// assert_eq!(lim.try_check(), TryCheck::Allowed(()));
+/// assert_eq!(
+///     lim.try_check(),
+///     TryCheck::Limited { retry_after: Duration::from_millis(500) }
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryCheck<T> {
+    /// The cells were allowed through, and their capacity was consumed.
+    ///
+    /// Holds the positive outcome of the rate limiter's middleware.
+    Allowed(T),
+
+    /// The cells could not be allowed through at this time. No capacity was consumed.
+    Limited {
+        /// How long to wait until the cells could be allowed through.
+        retry_after: Duration,
+    },
+
+    /// The cells can never be allowed through, because there are more of them than the
+    /// rate limiter's burst size.
+    InsufficientCapacity {
+        /// The largest number of cells that could ever be allowed through at once.
+        max: u32,
+    },
+}
+
+impl<T> TryCheck<T> {
+    /// Returns `true` if the cells were allowed through.
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, TryCheck::Allowed(_))
+    }
+
+    /// Returns the time to wait before checking the cells again, if they could be allowed
+    /// through later.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            TryCheck::Limited { retry_after } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// Returns the positive outcome, if the cells were allowed through.
+    pub fn allowed(self) -> Option<T> {
+        match self {
+            TryCheck::Allowed(outcome) => Some(outcome),
+            _ => None,
+        }
+    }
+}
+
+impl<T> From<InsufficientCapacity> for TryCheck<T> {
+    fn from(InsufficientCapacity(max): InsufficientCapacity) -> Self {
+        TryCheck::InsufficientCapacity { max }
+    }
+}
+
+impl<T> TryCheck<T> {
+    fn decided(decision: Result<T, Duration>) -> Self {
+        match decision {
+            Ok(outcome) => TryCheck::Allowed(outcome),
+            Err(retry_after) => TryCheck::Limited { retry_after },
+        }
+    }
+}
+
+/// Middleware that runs `Inner`, but replaces its negative outcome with the time to wait.
+///
+/// The inner middleware still gets notified of negative decisions, so that any side effects
+/// (like recording metrics) still happen.
+#[derive(Debug)]
+struct RetryAfter<Inner> {
+    phantom: PhantomData<Inner>,
+}
+
+impl<P, Inner> RateLimitingMiddleware<P> for RetryAfter<Inner>
+where
+    P: clock::Reference,
+    Inner: RateLimitingMiddleware<P>,
+{
+    type PositiveOutcome = Inner::PositiveOutcome;
+
+    type NegativeOutcome = Duration;
+
+    fn allow<K: fmt::Display>(key: &K, state: impl Into<StateSnapshot>) -> Self::PositiveOutcome {
+        Inner::allow(key, state)
+    }
+
+    fn disallow<K: fmt::Display>(
+        key: &K,
+        state: impl Into<StateSnapshot>,
+        start_time: P,
+    ) -> Self::NegativeOutcome {
+        let state = state.into();
+        let retry_after = state
+            .earliest_conforming()
+            .saturating_sub(state.time_of_measurement);
+        Inner::disallow(key, state, start_time);
+        retry_after.into()
+    }
+}
+
+/// # Direct rate limiters - Checking cells with a single result type
+impl<S, C, MW> RateLimiter<NotKeyed, S, C, MW>
+where
+    S: DirectStateStore,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Allow a single cell through the rate limiter, reporting the decision as a [`TryCheck`].
+    ///
+    /// This makes the same decision as [`check`](#method.check) and never blocks. The
+    /// middleware's negative outcome is replaced by the time to wait.
+    pub fn try_check(&self) -> TryCheck<MW::PositiveOutcome> {
+        TryCheck::decided(
+            self.gcra
+                .test_and_update::<NotKeyed, C::Instant, S, RetryAfter<MW>>(
+                    self.start,
+                    &NotKeyed::NonKey,
+                    self.key_format(),
+                    &self.state,
+                    self.clock.now(),
+                ),
+        )
+    }
+
+    /// Allow *only all* `n` cells through the rate limiter, reporting the decision as a
+    /// [`TryCheck`].
+    ///
+    /// This makes the same decision as [`check_n`](#method.check_n).
+    pub fn try_check_n(&self, n: NonZeroU32) -> TryCheck<MW::PositiveOutcome> {
+        match self
+            .gcra
+            .test_n_all_and_update::<NotKeyed, C::Instant, S, RetryAfter<MW>>(
+                self.start,
+                &NotKeyed::NonKey,
+                self.key_format(),
+                n,
+                &self.state,
+                self.clock.now(),
+            ) {
+            Ok(decision) => TryCheck::decided(decision),
+            Err(insufficient) => insufficient.into(),
+        }
+    }
+}
+
+/// # Keyed rate limiters - Checking cells with a single result type
+impl<K, S, C, MW> RateLimiter<K, S, C, MW>
+where
+    S: KeyedStateStore<K>,
+    K: Hash,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Allow a single cell through the rate limiter for the given key, reporting the decision
+    /// as a [`TryCheck`].
+    ///
+    /// This makes the same decision as [`check_key`](#method.check_key).
+    pub fn try_check_key(&self, key: &K) -> TryCheck<MW::PositiveOutcome> {
+        TryCheck::decided(
+            self.gcra
+                .test_and_update::<K, C::Instant, S, RetryAfter<MW>>(
+                    self.start,
+                    key,
+                    self.key_format(),
+                    &self.state,
+                    self.clock.now(),
+                ),
+        )
+    }
+
+    /// Allow *only all* `n` cells through the rate limiter for the given key, reporting the
+    /// decision as a [`TryCheck`].
+    ///
+    /// This makes the same decision as [`check_key_n`](#method.check_key_n).
+    pub fn try_check_key_n(&self, key: &K, n: NonZeroU32) -> TryCheck<MW::PositiveOutcome> {
+        match self
+            .gcra
+            .test_n_all_and_update::<K, C::Instant, S, RetryAfter<MW>>(
+                self.start,
+                key,
+                self.key_format(),
+                n,
+                &self.state,
+                self.clock.now(),
+            ) {
+            Ok(decision) => TryCheck::decided(decision),
+            Err(insufficient) => insufficient.into(),
+        }
+    }
+}
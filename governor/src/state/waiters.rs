@@ -0,0 +1,271 @@
+#![cfg(feature = "std")]
+
+use std::prelude::v1::*;
+
+use std::fmt;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::clock;
+use crate::middleware::RateLimitingMiddleware;
+use crate::nanos::Nanos;
+use crate::state::keyed::ShrinkableKeyedStateStore;
+use crate::state::{RebasableStateStore, StateStore};
+use crate::sync::Mutex;
+use crate::RateLimiter;
+
+/// A state store that counts the tasks waiting for its rate limiter, e.g. to shed load before
+/// the queue of waiting tasks gets too long.
+///
+/// Tasks are counted while they wait in the `until_ready` family of methods (like
+/// [`until_ready`](RateLimiter::until_ready) and
+/// [`until_key_ready`](RateLimiter::until_key_ready)): from the first time the rate limiter
+/// tells them to wait until their cells are allowed through, or until they give up waiting.
+/// Tasks whose cells are allowed through right away are not counted. The rate limiter reports
+/// the counts with [`pending_waiters`](RateLimiter::pending_waiters) and
+/// [`pending_waiters_for_key`](RateLimiter::pending_waiters_for_key).
+///
+/// Counting a waiting task takes an atomic operation and a short-lived lock on the counts per
+/// key. Only keys that tasks are waiting for have a count, and they are looked up linearly, so
+/// this store is meant for rate limiters whose tasks wait for a moderate number of keys at any
+/// given time.
+///
+/// # Example
+/// ```rust
+/// # use nonzero_ext::nonzero;
+/// # use futures_executor::block_on;
+/// # use futures_util::{future, FutureExt};
+/// use governor::{
+///     clock::DefaultClock, middleware::NoOpMiddleware,
+///     state::{InMemoryState, WaiterCountingStateStore},
+///     Quota, RateLimiter,
+/// };
+///
+/// let store = WaiterCountingStateStore::new(InMemoryState::default());
+/// let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> =
+///     RateLimiter::new(Quota::per_second(nonzero!(10u32)), store, DefaultClock::default());
+/// assert!(lim.check().is_ok());
+///
+/// let mut waiting = lim.until_n_ready(nonzero!(10u32)).boxed_local();
+/// assert!(block_on(future::poll_immediate(&mut waiting)).is_none());
+/// assert_eq!(lim.pending_waiters(), 1);
+/// drop(waiting);
+/// assert_eq!(lim.pending_waiters(), 0);
+/// ```
+pub struct WaiterCountingStateStore<S: StateStore> {
+    inner: S,
+    total: AtomicUsize,
+    per_key: Mutex<Vec<(S::Key, usize)>>,
+}
+
+impl<S: StateStore> WaiterCountingStateStore<S> {
+    /// Wraps `inner`, counting the tasks that wait for it.
+    pub fn new(inner: S) -> Self {
+        WaiterCountingStateStore {
+            inner,
+            total: AtomicUsize::new(0),
+            per_key: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the wrapped state store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the number of tasks that are currently waiting, for any key.
+    pub fn pending_waiters(&self) -> usize {
+        self.total.load(Ordering::Acquire)
+    }
+
+    /// Returns the number of tasks that are currently waiting for `key`.
+    pub fn pending_waiters_for_key(&self, key: &S::Key) -> usize
+    where
+        S::Key: PartialEq,
+    {
+        self.per_key
+            .lock()
+            .iter()
+            .find(|(k, _)| k == key)
+            .map_or(0, |&(_, count)| count)
+    }
+}
+
+impl<S> StateStore for WaiterCountingStateStore<S>
+where
+    S: StateStore,
+    S::Key: PartialEq + Clone,
+{
+    type Key = S::Key;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        self.inner.measure_and_replace(key, f)
+    }
+
+    fn note_denial(&self, key: &Self::Key, t0: Nanos) {
+        self.inner.note_denial(key, t0)
+    }
+
+    fn note_admission(&self, key: &Self::Key, t0: Nanos, tat: Nanos) {
+        self.inner.note_admission(key, t0, tat)
+    }
+
+    fn note_wait_started(&self, key: &Self::Key) {
+        self.total.fetch_add(1, Ordering::AcqRel);
+        {
+            let mut per_key = self.per_key.lock();
+            match per_key.iter_mut().find(|(k, _)| k == key) {
+                Some((_, count)) => *count += 1,
+                None => per_key.push((key.clone(), 1)),
+            }
+        }
+        self.inner.note_wait_started(key)
+    }
+
+    fn note_wait_finished(&self, key: &Self::Key) {
+        {
+            let mut per_key = self.per_key.lock();
+            if let Some(i) = per_key.iter().position(|(k, _)| k == key) {
+                per_key[i].1 -= 1;
+                if per_key[i].1 == 0 {
+                    per_key.swap_remove(i);
+                }
+            }
+        }
+        self.total.fetch_sub(1, Ordering::AcqRel);
+        self.inner.note_wait_finished(key)
+    }
+
+    fn measure_and_peek<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<T, E>,
+    {
+        self.inner.measure_and_peek(key, f)
+    }
+
+    fn restore(&self, key: &Self::Key, replaced: Nanos, prev: Option<Nanos>) {
+        self.inner.restore(key, replaced, prev)
+    }
+}
+
+impl<S> RebasableStateStore for WaiterCountingStateStore<S>
+where
+    S: RebasableStateStore,
+    S::Key: PartialEq + Clone,
+{
+    fn rewrite_states<F>(&mut self, f: F)
+    where
+        F: Fn(Nanos) -> Nanos,
+    {
+        self.inner.rewrite_states(f)
+    }
+}
+
+impl<K, S> ShrinkableKeyedStateStore<K> for WaiterCountingStateStore<S>
+where
+    K: Hash + Eq + Clone,
+    S: ShrinkableKeyedStateStore<K>,
+{
+    fn retain_recent(&self, drop_below: Nanos) {
+        self.inner.retain_recent(drop_below)
+    }
+
+    fn shrink_to_fit(&self) {
+        self.inner.shrink_to_fit()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn approx_memory_bytes(&self) -> usize {
+        self.inner.approx_memory_bytes()
+    }
+
+    fn pre_seed<I: IntoIterator<Item = K>>(&self, keys: I) {
+        self.inner.pre_seed(keys)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        self.inner.contains_key(key)
+    }
+
+    fn export_states(&self, drop_below: Nanos) -> Vec<(K, Nanos)> {
+        self.inner.export_states(drop_below)
+    }
+}
+
+impl<S: StateStore + fmt::Debug> fmt::Debug for WaiterCountingStateStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WaiterCountingStateStore")
+            .field("inner", &self.inner)
+            .field("pending_waiters", &self.pending_waiters())
+            .finish()
+    }
+}
+
+/// Tells a state store that a task is waiting for a key, until it gets dropped.
+///
+/// The waiting task only gets counted once [`start`](#method.start) gets called, so that tasks
+/// whose cells are allowed through right away don't get counted at all.
+pub(crate) struct Waiting<'a, S: StateStore> {
+    state: &'a S,
+    key: &'a S::Key,
+    started: bool,
+}
+
+impl<'a, S: StateStore> Waiting<'a, S> {
+    pub(crate) fn new(state: &'a S, key: &'a S::Key) -> Self {
+        Waiting {
+            state,
+            key,
+            started: false,
+        }
+    }
+
+    /// Notes that the task is waiting, unless it already is.
+    pub(crate) fn start(&mut self) {
+        if !self.started {
+            self.started = true;
+            self.state.note_wait_started(self.key);
+        }
+    }
+}
+
+impl<S: StateStore> Drop for Waiting<'_, S> {
+    fn drop(&mut self) {
+        if self.started {
+            self.state.note_wait_finished(self.key);
+        }
+    }
+}
+
+/// # Rate limiters - Waiting tasks
+impl<K, S, C, MW> RateLimiter<K, WaiterCountingStateStore<S>, C, MW>
+where
+    S: StateStore<Key = K>,
+    K: PartialEq + Clone,
+    C: clock::Clock,
+    MW: RateLimitingMiddleware<C::Instant>,
+{
+    /// Returns the number of tasks that are currently waiting for the rate limiter to allow
+    /// their cells through, for any key.
+    ///
+    /// See [`WaiterCountingStateStore`] for which tasks get counted.
+    pub fn pending_waiters(&self) -> usize {
+        self.state.pending_waiters()
+    }
+
+    /// Returns the number of tasks that are currently waiting for the rate limiter to allow
+    /// their cells through for `key`.
+    pub fn pending_waiters_for_key(&self, key: &K) -> usize {
+        self.state.pending_waiters_for_key(key)
+    }
+}
@@ -0,0 +1,142 @@
+//! The locks that governor's data structures use internally.
+//!
+//! By default, these are [`parking_lot`](https://docs.rs/parking_lot)'s
+//! locks. Without the `parking_lot` feature, governor uses the locks
+//! from [`std::sync`] instead, saving the dependency. Both offer the
+//! same interface here.
+//!
+//! Poisoned `std` locks are used as if they weren't poisoned. governor's
+//! own code doesn't panic while holding a lock, but code from the crate's
+//! users can: e.g., the `Hash`, `Eq` and `Clone` implementations of keys
+//! run while a keyed state store's lock is held. Such a panic can leave
+//! the store's bookkeeping incomplete (e.g. a key may lose its state), but
+//! it doesn't make the store unusable for every later decision.
+
+#[cfg(feature = "std")]
+cfg_if::cfg_if! {
+    if #[cfg(feature = "parking_lot")] {
+        pub(crate) use parking_lot::{Mutex, RwLock};
+    } else {
+        use std::sync::{self, MutexGuard, PoisonError, RwLockReadGuard, RwLockWriteGuard};
+
+        /// A mutual exclusion lock around [`std::sync::Mutex`].
+        #[derive(Debug, Default)]
+        pub(crate) struct Mutex<T: ?Sized>(sync::Mutex<T>);
+
+        impl<T> Mutex<T> {
+            pub(crate) fn new(value: T) -> Mutex<T> {
+                Mutex(sync::Mutex::new(value))
+            }
+        }
+
+        impl<T: ?Sized> Mutex<T> {
+            pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+                self.0.lock().unwrap_or_else(PoisonError::into_inner)
+            }
+
+            pub(crate) fn get_mut(&mut self) -> &mut T {
+                self.0.get_mut().unwrap_or_else(PoisonError::into_inner)
+            }
+        }
+
+        /// A reader-writer lock around [`std::sync::RwLock`].
+        #[derive(Debug, Default)]
+        pub(crate) struct RwLock<T: ?Sized>(sync::RwLock<T>);
+
+        impl<T> RwLock<T> {
+            pub(crate) fn new(value: T) -> RwLock<T> {
+                RwLock(sync::RwLock::new(value))
+            }
+
+            pub(crate) fn into_inner(self) -> T {
+                self.0.into_inner().unwrap_or_else(PoisonError::into_inner)
+            }
+        }
+
+        impl<T: ?Sized> RwLock<T> {
+            pub(crate) fn read(&self) -> RwLockReadGuard<'_, T> {
+                self.0.read().unwrap_or_else(PoisonError::into_inner)
+            }
+
+            pub(crate) fn write(&self) -> RwLockWriteGuard<'_, T> {
+                self.0.write().unwrap_or_else(PoisonError::into_inner)
+            }
+        }
+    }
+}
+
+/// The lock that guards the map of a
+/// [`HashMapStateStore`](crate::state::keyed::HashMapStateStore).
+///
+/// The store's type is that lock around the map, so unlike the locks above, this is the lock
+/// type itself rather than a wrapper: Code outside the crate can name it. It is also available
+/// without `std`, where it is a spinlock.
+pub(crate) mod map {
+    use std::ops::DerefMut;
+
+    /// Access to the map inside the lock of a `HashMap`-backed state store.
+    pub(crate) trait MapLock<T> {
+        /// Locks the map.
+        fn lock_map(&self) -> impl DerefMut<Target = T> + '_;
+
+        /// Returns the map, which needs no locking through an exclusive reference.
+        fn map_mut(&mut self) -> &mut T;
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(loom)] {
+            // Loom can only switch between threads at its own synchronization primitives:
+            pub(crate) type Mutex<T> = loom::sync::Mutex<T>;
+
+            impl<T> MapLock<T> for Mutex<T> {
+                fn lock_map(&self) -> impl DerefMut<Target = T> + '_ {
+                    self.lock().unwrap()
+                }
+
+                fn map_mut(&mut self) -> &mut T {
+                    self.get_mut().unwrap()
+                }
+            }
+        } else if #[cfg(not(feature = "std"))] {
+            pub(crate) type Mutex<T> = spinning_top::Spinlock<T>;
+        } else if #[cfg(feature = "parking_lot")] {
+            pub(crate) type Mutex<T> = parking_lot::Mutex<T>;
+
+            impl<T> MapLock<T> for Mutex<T> {
+                fn lock_map(&self) -> impl DerefMut<Target = T> + '_ {
+                    self.lock()
+                }
+
+                fn map_mut(&mut self) -> &mut T {
+                    self.get_mut()
+                }
+            }
+        } else {
+            use std::sync::PoisonError;
+
+            pub(crate) type Mutex<T> = std::sync::Mutex<T>;
+
+            impl<T> MapLock<T> for Mutex<T> {
+                fn lock_map(&self) -> impl DerefMut<Target = T> + '_ {
+                    self.lock().unwrap_or_else(PoisonError::into_inner)
+                }
+
+                fn map_mut(&mut self) -> &mut T {
+                    self.get_mut().unwrap_or_else(PoisonError::into_inner)
+                }
+            }
+        }
+    }
+
+    // The lock of the `SpinHashMapStateStore`, and of the `HashMapStateStore` without `std`:
+    #[cfg(any(feature = "spin", not(any(loom, feature = "std"))))]
+    impl<T> MapLock<T> for spinning_top::Spinlock<T> {
+        fn lock_map(&self) -> impl DerefMut<Target = T> + '_ {
+            self.lock()
+        }
+
+        fn map_mut(&mut self) -> &mut T {
+            self.get_mut()
+        }
+    }
+}
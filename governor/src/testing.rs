@@ -0,0 +1,233 @@
+//! Property tests for rate-limiting decisions, for use with custom state stores.
+//!
+//! This module is available with the `proptest` feature. It exports the
+//! [proptest](https://docs.rs/proptest) strategies that governor's own test suite uses, along
+//! with [`check_invariants`], which runs a sequence of operations against a state store and
+//! checks that the decisions made with it obey the GCRA's guarantees:
+//!
+//! * No more cells are allowed through in any window of time than the burst size plus the cells
+//!   replenished in that window. (At any one instant, that's the burst size; over long periods
+//!   of time, it's the quota's rate.)
+//! * The theoretical arrival time (TAT) stored for a key never moves backwards, and is always in
+//!   the future of the decision that stored it.
+//! * The state store hands the closure the state it last stored, and leaves it alone on
+//!   negative decisions.
+//! * Batches of cells are rejected as never conforming exactly when they exceed the burst size.
+//!
+//! Authors of [`StateStore`] implementations can run these checks against their own backends:
+//!
+//! ```rust
+//! use proptest::prelude::*;
+//! use governor::{state::keyed::HashMapStateStore, testing};
+//!
+//! // In a #[test] function:
+//! proptest!(|(quota in testing::quotas(), ops in testing::op_sequences(1..64))| {
+//!     let store = HashMapStateStore::<u32>::default();
+//!     testing::check_invariants(quota, &store, &1, &ops)?;
+//! });
+//! ```
+
+use std::prelude::v1::*;
+
+use std::cell::{Cell, RefCell};
+use std::num::NonZeroU32;
+use std::ops::Range;
+use std::time::Duration;
+
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+
+use crate::{
+    gcra::Gcra,
+    middleware::{KeyFormat, NoOpMiddleware},
+    nanos::Nanos,
+    state::StateStore,
+    Quota,
+};
+
+/// An operation on a rate limiter, as generated by [`op_sequences`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Check a single cell (like [`RateLimiter::check_key`](crate::RateLimiter::check_key)).
+    Check,
+
+    /// Check a batch of cells, all or nothing (like
+    /// [`RateLimiter::check_key_n`](crate::RateLimiter::check_key_n)).
+    CheckN(NonZeroU32),
+
+    /// Check as many of a batch of cells as fit into the burst size (like
+    /// [`RateLimiter::check_key_n_clamped`](crate::RateLimiter::check_key_n_clamped)).
+    CheckNClamped(NonZeroU32),
+
+    /// Advance the clock.
+    Advance(Duration),
+}
+
+/// Generates quotas with burst sizes from 1 to 100, replenishing one cell every microsecond up
+/// to every 10 seconds.
+pub fn quotas() -> impl Strategy<Value = Quota> {
+    (1..=100u32, 1_000..=10_000_000_000u64).prop_map(|(burst, interval)| {
+        Quota::with_period(Duration::from_nanos(interval))
+            .unwrap()
+            .allow_burst(NonZeroU32::new(burst).unwrap())
+    })
+}
+
+/// Generates single operations.
+///
+/// Batch sizes are chosen so that they sometimes exceed the burst sizes generated by
+/// [`quotas`]. Most clock advances are shorter than a second, but some jump ahead by hours.
+pub fn ops() -> impl Strategy<Value = Op> {
+    let batch = (1..=120u32).prop_map(|n| NonZeroU32::new(n).unwrap());
+    prop_oneof![
+        4 => Just(Op::Check),
+        2 => batch.clone().prop_map(Op::CheckN),
+        2 => batch.prop_map(Op::CheckNClamped),
+        3 => (0..1_000_000_000u64).prop_map(|ns| Op::Advance(Duration::from_nanos(ns))),
+        1 => (1..=24u64).prop_map(|h| Op::Advance(Duration::from_secs(h * 3600))),
+    ]
+}
+
+/// Generates interleaved sequences of operations, with lengths in `len`.
+pub fn op_sequences(len: Range<usize>) -> impl Strategy<Value = Vec<Op>> {
+    proptest::collection::vec(ops(), len)
+}
+
+/// Runs `ops` against the rate-limiting state that `state` keeps for `key`, checking the
+/// invariants listed in [the module documentation](index.html).
+///
+/// `state` must not have a state for `key` yet, and nothing else may change it while the
+/// operations run. The operations are timed with a simulated clock.
+pub fn check_invariants<S: StateStore>(
+    quota: Quota,
+    state: &S,
+    key: &S::Key,
+    ops: &[Op],
+) -> Result<(), TestCaseError> {
+    let gcra = Gcra::new(quota);
+    let t = gcra.t().as_u64();
+    let burst = u64::from(quota.burst_size().get());
+    let observed = Observed {
+        inner: state,
+        now: Cell::new(Nanos::from(0)),
+        last: Cell::new(None),
+        violation: RefCell::new(None),
+    };
+    let start = Nanos::from(0);
+    let keys = KeyFormat::redacted();
+    // Positive decisions as (time, cells allowed):
+    let mut allowed: Vec<(u64, u64)> = vec![];
+
+    for (i, op) in ops.iter().enumerate() {
+        let now = observed.now.get();
+        let cells = match *op {
+            Op::Advance(d) => {
+                observed.now.set(now + d);
+                continue;
+            }
+            Op::Check => gcra
+                .test_and_update::<_, Nanos, _, NoOpMiddleware<Nanos>>(
+                    start, key, keys, &observed, now,
+                )
+                .ok()
+                .map(|_| 1),
+            Op::CheckN(n) => {
+                let decision = gcra.test_n_all_and_update::<_, Nanos, _, NoOpMiddleware<Nanos>>(
+                    start, key, keys, n, &observed, now,
+                );
+                prop_assert_eq!(
+                    decision.is_err(),
+                    u64::from(n.get()) > burst,
+                    "op #{} ({:?}): insufficient capacity reported wrongly for burst size {}",
+                    i,
+                    op,
+                    burst
+                );
+                decision.ok().and_then(Result::ok).map(|_| n.get())
+            }
+            Op::CheckNClamped(n) => {
+                let (admitted, decision) = gcra
+                    .test_n_clamped_and_update::<_, Nanos, _, NoOpMiddleware<Nanos>>(
+                        start, key, keys, n, &observed, now,
+                    );
+                prop_assert_eq!(u64::from(admitted.get()), u64::from(n.get()).min(burst));
+                decision.ok().map(|_| admitted.get())
+            }
+        };
+        if let Some(violation) = observed.violation.borrow_mut().take() {
+            return Err(TestCaseError::fail(format!(
+                "op #{} ({:?}): {}",
+                i, op, violation
+            )));
+        }
+        if let Some(cells) = cells {
+            let now = now.as_u64();
+            allowed.push((now, cells.into()));
+            let mut sum = 0;
+            for &(then, cells) in allowed.iter().rev() {
+                sum += cells;
+                let bound = burst + (now - then) / t;
+                prop_assert!(
+                    sum <= bound,
+                    "op #{} ({:?}): allowed {} cells in the {}ns up to {}ns, but at most {} fit",
+                    i,
+                    op,
+                    sum,
+                    now - then,
+                    now,
+                    bound
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Wraps a state store, checking how it stores states.
+struct Observed<'a, S> {
+    inner: &'a S,
+    now: Cell<Nanos>,
+    last: Cell<Option<Nanos>>,
+    violation: RefCell<Option<String>>,
+}
+
+impl<S: StateStore> StateStore for Observed<'_, S> {
+    type Key = S::Key;
+
+    fn measure_and_replace<T, F, E>(&self, key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let stored = Cell::new(None);
+        let result = self.inner.measure_and_replace(key, |tat| {
+            if tat != self.last.get() {
+                self.violate(format!(
+                    "the store returned state {:?}, but {:?} was stored last",
+                    tat,
+                    self.last.get()
+                ));
+            }
+            let (result, new) = f(tat)?;
+            if tat.is_some_and(|tat| new < tat) || new <= self.now.get() {
+                self.violate(format!(
+                    "the TAT moved from {:?} to {:?} at {:?}",
+                    tat,
+                    new,
+                    self.now.get()
+                ));
+            }
+            stored.set(Some(new));
+            Ok((result, new))
+        });
+        if result.is_ok() {
+            self.last.set(stored.get());
+        }
+        result
+    }
+}
+
+impl<S> Observed<'_, S> {
+    fn violate(&self, violation: String) {
+        self.violation.borrow_mut().get_or_insert(violation);
+    }
+}
@@ -0,0 +1,94 @@
+//! The timer that asynchronous rate limiter methods wait on.
+//!
+//! By default, this is [`futures_timer::Delay`], which runs its own
+//! global timer thread and so works with any executor. With the
+//! `smol` or `async-std` features, governor instead uses the timers
+//! of the [`async-io`](https://docs.rs/async-io) reactor that these
+//! runtimes drive, saving the extra thread. With the `wasm` feature on
+//! `wasm32` targets, it waits on JavaScript timers (`setTimeout`).
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "wasm", target_arch = "wasm32"))] {
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+        use std::convert::TryFrom;
+        use std::time::Duration;
+
+        /// A future that resolves after a duration, on a JavaScript timer.
+        ///
+        /// JavaScript timers count whole milliseconds, so durations are rounded up to the
+        /// next millisecond: waking up early would only lead to another negative decision.
+        #[derive(Debug)]
+        pub(crate) struct Delay(futures_timer::Delay);
+
+        fn whole_millis(dur: Duration) -> Duration {
+            let millis = dur.as_nanos().div_ceil(1_000_000);
+            Duration::from_millis(u64::try_from(millis).unwrap_or(u64::MAX))
+        }
+
+        impl Delay {
+            pub(crate) fn new(dur: Duration) -> Delay {
+                Delay(futures_timer::Delay::new(whole_millis(dur)))
+            }
+
+            pub(crate) fn reset(&mut self, dur: Duration) {
+                self.0.reset(whole_millis(dur));
+            }
+        }
+
+        impl Future for Delay {
+            type Output = ();
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+                Pin::new(&mut self.0).poll(cx)
+            }
+        }
+    } else if #[cfg(any(feature = "smol", feature = "async-std"))] {
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+        use std::time::Duration;
+
+        /// A future that resolves after a duration, on the async-io reactor.
+        #[derive(Debug)]
+        pub(crate) struct Delay(async_io::Timer);
+
+        impl Delay {
+            pub(crate) fn new(dur: Duration) -> Delay {
+                Delay(async_io::Timer::after(dur))
+            }
+
+            pub(crate) fn reset(&mut self, dur: Duration) {
+                self.0.set_after(dur);
+            }
+        }
+
+        impl Future for Delay {
+            type Output = ();
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+                Pin::new(&mut self.0).poll(cx).map(|_| ())
+            }
+        }
+    } else {
+        pub(crate) use futures_timer::Delay;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Delay;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn delay_resets() {
+        let start = Instant::now();
+        let mut delay = Delay::new(Duration::from_secs(3600));
+        delay.reset(Duration::from_millis(10));
+        futures_executor::block_on(&mut delay);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(10), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(3600), "{:?}", elapsed);
+    }
+}
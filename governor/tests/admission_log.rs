@@ -0,0 +1,109 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    clock::{Clock, FakeRelativeClock},
+    middleware::NoOpMiddleware,
+    nanos::Nanos,
+    state::keyed::{AdmissionLogStateStore, HashMapStateStore},
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+type LoggingLimiter = RateLimiter<
+    u32,
+    AdmissionLogStateStore<HashMapStateStore<u32>>,
+    FakeRelativeClock,
+    NoOpMiddleware<Nanos>,
+>;
+
+fn logging_limiter(capacity: NonZeroUsize, clock: &FakeRelativeClock) -> LoggingLimiter {
+    let store = AdmissionLogStateStore::new(HashMapStateStore::default(), capacity);
+    RateLimiter::new(Quota::per_second(nonzero!(3u32)), store, clock.clone())
+}
+
+#[test]
+fn records_decisions() {
+    let clock = FakeRelativeClock::default();
+    let lim = logging_limiter(nonzero!(10usize), &clock);
+
+    assert!(lim.check_key(&1).is_ok());
+    clock.advance(Duration::from_millis(10));
+    assert_eq!(lim.check_key_n(&1, nonzero!(2u32)), Ok(Ok(())));
+    assert!(lim.check_key_n(&1, nonzero!(2u32)).unwrap().is_err());
+    assert!(lim.check_key_n(&1, nonzero!(5u32)).is_err());
+    assert!(lim.check_key_only(&2).is_ok());
+    assert!(lim.check_key(&2).is_ok());
+
+    let log = lim.recent_decisions();
+    assert_eq!(log.len(), 4);
+    let summary: Vec<_> = log
+        .iter()
+        .map(|d| (d.is_for(&1), d.is_allowed(), d.remaining_burst_capacity()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (true, true, 2),
+            (true, true, 0),
+            (true, false, 0),
+            (false, true, 2)
+        ]
+    );
+    assert_eq!(log[0].at(), Nanos::from(0));
+    assert_eq!(log[3].at(), clock.now());
+}
+
+#[test]
+fn keeps_most_recent_decisions() {
+    let clock = FakeRelativeClock::default();
+    let lim = logging_limiter(nonzero!(3usize), &clock);
+    for key in 0..5 {
+        assert!(lim.check_key(&key).is_ok());
+    }
+    let log = lim.recent_decisions();
+    assert_eq!(log.len(), 3);
+    assert!(log[0].is_for(&2));
+    assert!(log[2].is_for(&4));
+}
+
+#[test]
+fn records_all_concurrent_decisions() {
+    let clock = FakeRelativeClock::default();
+    let lim = Arc::new(logging_limiter(nonzero!(1000usize), &clock));
+    let threads: Vec<_> = (0..8)
+        .map(|key| {
+            let lim = Arc::clone(&lim);
+            thread::spawn(move || {
+                for _ in 0..50 {
+                    let _ = lim.check_key(&key);
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    let log = lim.recent_decisions();
+    assert_eq!(log.len(), 400);
+    for key in 0..8 {
+        let allowed = log
+            .iter()
+            .filter(|d| d.is_for(&key) && d.is_allowed())
+            .count();
+        assert_eq!(allowed, 3, "key {}", key);
+    }
+}
+
+#[test]
+fn records_batches_of_keys() {
+    let clock = FakeRelativeClock::default();
+    let lim = logging_limiter(nonzero!(10usize), &clock);
+    let _: Vec<_> = lim.check_keys_batch(vec![(1, nonzero!(2u32)), (1, nonzero!(2u32))]);
+    let log = lim.recent_decisions();
+    let outcomes: Vec<_> = log.iter().map(|d| d.is_allowed()).collect();
+    assert_eq!(outcomes, [true, false]);
+}
@@ -0,0 +1,44 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    middleware::KeyFormat, state::keyed::AnyKey, DefaultKeyedRateLimiter, Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+#[test]
+fn any_keys_of_different_kinds_dont_collide() {
+    let limiter: DefaultKeyedRateLimiter<AnyKey> =
+        RateLimiter::keyed(Quota::per_second(nonzero!(1u32)));
+    let keys = [
+        AnyKey::from(1u64),
+        AnyKey::from("1"),
+        AnyKey::from(Ipv4Addr::new(0, 0, 0, 1)),
+        AnyKey::from(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)),
+    ];
+    for key in &keys {
+        assert_eq!(Ok(()), limiter.check_key(key), "{}", key);
+    }
+    for key in &keys {
+        assert!(limiter.check_key(key).is_err(), "{}", key);
+    }
+    assert!(limiter.check_key(&AnyKey::from(1u32)).is_err());
+    assert!(limiter.check_key(&AnyKey::from(String::from("1"))).is_err());
+    assert_eq!(limiter.len(), 4);
+}
+
+#[test]
+fn any_key_display() {
+    assert_eq!(AnyKey::from(42u32).to_string(), "id:42");
+    assert_eq!(
+        AnyKey::from(Ipv4Addr::LOCALHOST).to_string(),
+        "ip:127.0.0.1"
+    );
+    assert_eq!(AnyKey::from(Ipv6Addr::LOCALHOST).to_string(), "ip:::1");
+    assert_eq!(AnyKey::from("alice").to_string(), "name:alice");
+    assert_eq!(
+        KeyFormat::full().show(&AnyKey::from(7u64)).to_string(),
+        "id:7"
+    );
+    assert_eq!(std::mem::size_of::<AnyKey>(), 24);
+}
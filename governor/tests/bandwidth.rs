@@ -0,0 +1,197 @@
+use governor::{
+    bandwidth::{Bandwidth, BandwidthLimiter},
+    clock::FakeRelativeClock,
+    InsufficientCapacity, Quota,
+};
+use nonzero_ext::nonzero;
+use std::time::Duration;
+
+#[test]
+fn converts_units() {
+    assert_eq!(
+        Bandwidth::kbps(nonzero!(1u32)).bytes_per_second().get(),
+        125
+    );
+    assert_eq!(
+        Bandwidth::mbps(nonzero!(10u32)).bytes_per_second().get(),
+        1_250_000
+    );
+    assert_eq!(
+        Bandwidth::gbps(nonzero!(100u32)).bytes_per_second().get(),
+        12_500_000_000
+    );
+}
+
+#[test]
+fn default_cell_size_limits_cells_per_second() {
+    for bandwidth in &[
+        Bandwidth::kbps(nonzero!(1u32)),
+        Bandwidth::mbps(nonzero!(10u32)),
+        Bandwidth::gbps(nonzero!(100u32)),
+    ] {
+        let quota = bandwidth.quota();
+        assert!(quota.replenish_interval() >= Duration::from_micros(1));
+        assert_eq!(quota.validate_sane(), Ok(()), "{:?}", bandwidth);
+    }
+    assert_eq!(Bandwidth::kbps(nonzero!(1u32)).cell_size().get(), 1);
+    assert_eq!(Bandwidth::gbps(nonzero!(100u32)).cell_size().get(), 16384);
+}
+
+#[test]
+fn quota_never_exceeds_bandwidth() {
+    // 3 bytes per second don't divide a second evenly; the interval rounds up:
+    let bandwidth = Bandwidth::per_second(nonzero!(3u64));
+    let quota: Quota = bandwidth.into();
+    assert_eq!(
+        quota.replenish_interval(),
+        Duration::from_nanos(333_333_334)
+    );
+    assert_eq!(quota.burst_size().get(), 3);
+
+    let quota = bandwidth
+        .with_cell_size(nonzero!(2u32))
+        .allow_burst(nonzero!(1u64))
+        .quota();
+    assert_eq!(
+        quota.replenish_interval(),
+        Duration::from_nanos(666_666_667)
+    );
+    assert_eq!(quota.burst_size().get(), 1);
+}
+
+#[test]
+fn rounds_bytes_up_to_cells() {
+    let bandwidth = Bandwidth::per_second(nonzero!(1000u64)).with_cell_size(nonzero!(100u32));
+    assert_eq!(
+        bandwidth.cells_for_bytes(nonzero!(1u64)),
+        Ok(nonzero!(1u32))
+    );
+    assert_eq!(
+        bandwidth.cells_for_bytes(nonzero!(100u64)),
+        Ok(nonzero!(1u32))
+    );
+    assert_eq!(
+        bandwidth.cells_for_bytes(nonzero!(101u64)),
+        Ok(nonzero!(2u32))
+    );
+    assert_eq!(
+        Bandwidth::per_second(nonzero!(1000u64)).cells_for_bytes(nonzero!(u64::MAX)),
+        Err(InsufficientCapacity(1000))
+    );
+}
+
+#[test]
+fn checks_bytes() {
+    let clock = FakeRelativeClock::default();
+    let lim = BandwidthLimiter::with_clock(
+        Bandwidth::per_second(nonzero!(1000u64)).with_cell_size(nonzero!(10u32)),
+        clock.clone(),
+    );
+    assert_eq!(lim.limiter().check_n(nonzero!(100u32)), Ok(Ok(())));
+    assert!(lim.check_bytes(nonzero!(1u64)).unwrap().is_err());
+
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(lim.check_bytes(nonzero!(495u64)), Ok(Ok(())));
+    assert!(lim.check_bytes(nonzero!(10u64)).unwrap().is_err());
+    assert_eq!(
+        lim.check_bytes(nonzero!(1001u64)),
+        Err(InsufficientCapacity(100))
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn until_bytes_ready() {
+    use futures_executor::block_on;
+    use std::time::Instant;
+
+    let lim = BandwidthLimiter::new(Bandwidth::per_second(nonzero!(1000u64)));
+    let start = Instant::now();
+    block_on(async {
+        lim.until_bytes_ready(nonzero!(1000u64)).await.unwrap();
+        lim.until_bytes_ready(nonzero!(100u64)).await.unwrap();
+    });
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert_eq!(
+        block_on(lim.until_bytes_ready(nonzero!(1001u64))),
+        Err(InsufficientCapacity(1000))
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn writer_writes_what_the_bandwidth_allows() {
+    use std::io::{ErrorKind, Write};
+    use std::time::Instant;
+
+    let lim = BandwidthLimiter::new(
+        Bandwidth::per_second(nonzero!(1000u64)).with_cell_size(nonzero!(10u32)),
+    );
+    let mut writer = lim.writer(vec![]).nonblocking();
+    // Writes are cut short to the burst, and then fail instead of blocking:
+    assert_eq!(writer.write(&[0; 1200]).unwrap(), 1000);
+    assert_eq!(
+        writer.write(&[0; 1]).unwrap_err().kind(),
+        ErrorKind::WouldBlock
+    );
+
+    // Blocking writes wait for the bandwidth:
+    let mut writer = lim.writer(writer.into_inner());
+    let start = Instant::now();
+    writer.write_all(&[0; 100]).unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(writer.get_ref().len(), 1100);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn io_adapters_keep_partial_cells() {
+    use std::io::{ErrorKind, Read, Write};
+
+    let lim = BandwidthLimiter::new(
+        Bandwidth::per_second(nonzero!(1000u64)).with_cell_size(nonzero!(10u32)),
+    );
+    let mut writer = lim.writer(vec![]).nonblocking();
+    assert_eq!(writer.write(&[0; 5]).unwrap(), 5);
+    assert_eq!(lim.limiter().check_n(nonzero!(99u32)), Ok(Ok(())));
+    // The rest of the first cell still goes through:
+    assert_eq!(writer.write(&[0; 10]).unwrap(), 5);
+    assert_eq!(
+        writer.write(&[0; 1]).unwrap_err().kind(),
+        ErrorKind::WouldBlock
+    );
+
+    let lim = BandwidthLimiter::new(
+        Bandwidth::per_second(nonzero!(1000u64)).with_cell_size(nonzero!(10u32)),
+    );
+    let mut reader = lim.reader(&[1u8; 2000][..]).nonblocking();
+    let mut buf = [0; 1005];
+    assert_eq!(reader.read(&mut buf).unwrap(), 1000);
+    assert_eq!(
+        reader.read(&mut buf).unwrap_err().kind(),
+        ErrorKind::WouldBlock
+    );
+    assert_eq!(reader.get_ref().len(), 1000);
+}
+
+#[cfg(feature = "futures-io")]
+#[test]
+fn async_io_adapters() {
+    use futures_executor::block_on;
+    use futures_util::io::{AsyncReadExt, AsyncWriteExt};
+    use std::time::Instant;
+
+    let lim = BandwidthLimiter::new(Bandwidth::per_second(nonzero!(1000u64)));
+    let start = Instant::now();
+    let mut contents = vec![];
+    block_on(async {
+        let mut writer = lim.writer(vec![]);
+        writer.write_all(&[1; 1050]).await.unwrap();
+        lim.reader(&writer.into_inner()[..])
+            .read_to_end(&mut contents)
+            .await
+            .unwrap();
+    });
+    assert!(start.elapsed() >= Duration::from_millis(1000));
+    assert_eq!(contents, vec![1; 1050]);
+}
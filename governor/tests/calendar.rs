@@ -0,0 +1,132 @@
+#![cfg(feature = "chrono")]
+
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use chrono_tz::Europe::Berlin;
+use governor::{calendar::CalendarDayRateLimiter, clock::Clock, InsufficientCapacity};
+use nonzero_ext::nonzero;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// A wall clock that only moves when told to.
+#[derive(Clone)]
+struct FakeWallClock(Arc<Mutex<SystemTime>>);
+
+impl FakeWallClock {
+    fn at<Tz: TimeZone>(t: DateTime<Tz>) -> Self {
+        FakeWallClock(Arc::new(Mutex::new(t.with_timezone(&Utc).into())))
+    }
+
+    fn set<Tz: TimeZone>(&self, t: DateTime<Tz>) {
+        *self.0.lock().unwrap() = t.with_timezone(&Utc).into();
+    }
+}
+
+impl Clock for FakeWallClock {
+    type Instant = SystemTime;
+
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[test]
+fn resets_at_local_midnight() {
+    let tz = FixedOffset::east_opt(5 * 3600).unwrap();
+    let clock = FakeWallClock::at(tz.with_ymd_and_hms(2024, 3, 1, 23, 0, 0).unwrap());
+    let lim = CalendarDayRateLimiter::with_clock(nonzero!(2u32), tz, clock.clone());
+
+    assert_eq!(Ok(()), lim.check());
+    assert_eq!(Ok(()), lim.check());
+    let negative = lim.check().unwrap_err();
+    assert_eq!(
+        negative.earliest_possible(),
+        tz.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap()
+    );
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_secs(3600)
+    );
+
+    // Still the same day a minute before midnight:
+    clock.set(tz.with_ymd_and_hms(2024, 3, 1, 23, 59, 0).unwrap());
+    assert!(lim.check().is_err());
+
+    // The full allowance is available right at midnight:
+    clock.set(tz.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap());
+    assert_eq!(lim.remaining(), 2);
+    assert_eq!(Ok(()), lim.check());
+    assert_eq!(Ok(()), lim.check());
+    assert!(lim.check().is_err());
+}
+
+#[test]
+fn check_n() {
+    let tz = FixedOffset::east_opt(0).unwrap();
+    let clock = FakeWallClock::at(tz.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap());
+    let lim = CalendarDayRateLimiter::with_clock(nonzero!(5u32), tz, clock.clone());
+
+    assert_eq!(Err(InsufficientCapacity(5)), lim.check_n(nonzero!(6u32)));
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(3u32)));
+    assert_eq!(lim.remaining(), 2);
+    assert!(lim.check_n(nonzero!(3u32)).unwrap().is_err());
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(2u32)));
+    assert_eq!(lim.remaining(), 0);
+}
+
+#[test]
+fn clock_set_back_does_not_grant_a_new_allowance() {
+    let tz = FixedOffset::east_opt(0).unwrap();
+    let clock = FakeWallClock::at(tz.with_ymd_and_hms(2024, 3, 2, 0, 1, 0).unwrap());
+    let lim = CalendarDayRateLimiter::with_clock(nonzero!(1u32), tz, clock.clone());
+
+    assert_eq!(Ok(()), lim.check());
+    clock.set(tz.with_ymd_and_hms(2024, 3, 1, 23, 59, 0).unwrap());
+    assert!(lim.check().is_err());
+}
+
+#[test]
+fn days_with_dst_transitions() {
+    // Berlin switches to summer time on 2024-03-31 (a 23-hour day)
+    // and back to winter time on 2024-10-27 (a 25-hour day).
+    let clock = FakeWallClock::at(Berlin.with_ymd_and_hms(2024, 3, 31, 0, 30, 0).unwrap());
+    let lim = CalendarDayRateLimiter::with_clock(nonzero!(1u32), Berlin, clock.clone());
+
+    assert_eq!(Ok(()), lim.check());
+    let negative = lim.check().unwrap_err();
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_secs(22 * 3600 + 30 * 60)
+    );
+
+    clock.set(Berlin.with_ymd_and_hms(2024, 10, 27, 0, 30, 0).unwrap());
+    assert_eq!(Ok(()), lim.check());
+    let negative = lim.check().unwrap_err();
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_secs(24 * 3600 + 30 * 60)
+    );
+    // Late in the long day, it's still the same calendar day:
+    clock.set(Berlin.with_ymd_and_hms(2024, 10, 27, 23, 59, 0).unwrap());
+    assert!(lim.check().is_err());
+}
+
+#[test]
+fn day_starts_after_a_gap_at_midnight() {
+    // Santiago de Chile moved clocks from 00:00 to 01:00 on 2022-09-11,
+    // so that day starts at 01:00 local time.
+    use chrono_tz::America::Santiago;
+
+    let clock = FakeWallClock::at(Santiago.with_ymd_and_hms(2022, 9, 10, 22, 0, 0).unwrap());
+    let lim = CalendarDayRateLimiter::with_clock(nonzero!(1u32), Santiago, clock.clone());
+
+    assert_eq!(Ok(()), lim.check());
+    let negative = lim.check().unwrap_err();
+    assert_eq!(
+        negative.earliest_possible(),
+        Santiago.with_ymd_and_hms(2022, 9, 11, 1, 0, 0).unwrap()
+    );
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_secs(2 * 3600)
+    );
+}
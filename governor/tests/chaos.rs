@@ -0,0 +1,68 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    clock::{DefaultClock, FakeRelativeClock},
+    middleware::NoOpMiddleware,
+    nanos::Nanos,
+    state::{keyed::HashMapStateStore, ChaosStateStore, InMemoryState, StateStore},
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+#[test]
+fn latency_slows_down_decisions() {
+    let store =
+        ChaosStateStore::new(InMemoryState::default()).with_latency(Duration::from_millis(20));
+    let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+        Quota::per_second(nonzero!(1u32)),
+        store,
+        DefaultClock::default(),
+    );
+    let started = Instant::now();
+    assert!(lim.check().is_ok());
+    assert!(lim.check().is_err());
+    assert!(started.elapsed() >= Duration::from_millis(40));
+}
+
+/// Counts how often the rate limiter computes a decision.
+#[derive(Default)]
+struct CountDecisions {
+    inner: HashMapStateStore<u32>,
+    decisions: AtomicUsize,
+}
+
+impl StateStore for CountDecisions {
+    type Key = u32;
+
+    fn measure_and_replace<T, F, E>(&self, key: &u32, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        self.inner.measure_and_replace(key, |tat| {
+            self.decisions.fetch_add(1, Ordering::Relaxed);
+            f(tat)
+        })
+    }
+}
+
+#[test]
+fn spurious_retries_recompute_decisions() {
+    let store =
+        ChaosStateStore::new(CountDecisions::default()).with_spurious_retries(nonzero!(2u64));
+    let lim: RateLimiter<u32, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+        Quota::per_second(nonzero!(2u32)),
+        store,
+        FakeRelativeClock::default(),
+    );
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_err());
+    assert!(lim.check_key(&1).is_err());
+
+    // Every second access was retried, and the decisions are the same as without chaos:
+    let store = lim.into_state_store();
+    assert_eq!(store.inner().decisions.load(Ordering::Relaxed), 6);
+    assert_eq!(store.accesses(), 4);
+}
@@ -0,0 +1,160 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    clock::FakeRelativeClock,
+    middleware::NoOpMiddleware,
+    nanos::Nanos,
+    state::{
+        keyed::{ClockSkew, HashMapStateStore, ReplicatingStateStore, SkewedStateStore},
+        FreshState,
+    },
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::time::Duration;
+
+fn skewed_limiter(
+    clock: &FakeRelativeClock,
+) -> RateLimiter<
+    u32,
+    SkewedStateStore<HashMapStateStore<u32>>,
+    FakeRelativeClock,
+    NoOpMiddleware<Nanos>,
+> {
+    let quota = Quota::per_second(nonzero!(1u32));
+    let store = SkewedStateStore::new(HashMapStateStore::default(), quota)
+        .with_skew(1, ClockSkew::Ahead(Duration::from_millis(300)))
+        .with_skew(2, ClockSkew::Behind(Duration::from_millis(300)));
+    RateLimiter::new(quota, store, clock.clone())
+}
+
+#[test]
+fn skews_apply_to_authoritative_states() {
+    let clock = FakeRelativeClock::default();
+    clock.advance(Duration::from_secs(1));
+    let lim = skewed_limiter(&clock);
+    clock.advance(Duration::from_secs(1));
+
+    // Each key's authoritative region let a cell through at 1s on its own clock:
+    lim.apply_authoritative_states((1..=3).map(|key| (key, Nanos::from(2_000_000_000))));
+
+    let retry_after = |key| lim.try_check_key(&key).retry_after();
+    assert_eq!(retry_after(1), Some(Duration::from_millis(700)));
+    assert_eq!(retry_after(2), Some(Duration::from_millis(1300)));
+    assert_eq!(retry_after(3), Some(Duration::from_secs(1)));
+
+    clock.advance(Duration::from_millis(700));
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key_only(&3).is_err());
+}
+
+#[test]
+fn skews_can_change() {
+    let clock = FakeRelativeClock::default();
+    let lim = skewed_limiter(&clock);
+    assert_eq!(
+        lim.clock_skew(&1),
+        Some(ClockSkew::Ahead(Duration::from_millis(300)))
+    );
+    assert_eq!(lim.clock_skew(&3), None);
+
+    clock.advance(Duration::from_secs(1));
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_err());
+
+    // Moving the key's clock further ahead makes its next cell conform earlier:
+    lim.set_clock_skew(1, ClockSkew::Ahead(Duration::from_secs(2)));
+    assert!(lim.check_key(&1).is_ok());
+    assert_eq!(
+        lim.remove_clock_skew(&1),
+        Some(ClockSkew::Ahead(Duration::from_secs(2)))
+    );
+    assert!(lim.check_key(&1).is_err());
+}
+
+#[test]
+fn retains_states_of_keys_behind() {
+    let clock = FakeRelativeClock::default();
+    clock.advance(Duration::from_secs(1));
+    let lim = skewed_limiter(&clock);
+    clock.advance(Duration::from_secs(1));
+    for key in 1..=3 {
+        assert!(lim.check_key(&key).is_ok());
+    }
+
+    // Key 2's state lies 300ms earlier on its own clock, but isn't stale on ours yet:
+    clock.advance(Duration::from_millis(1850));
+    lim.retain_recent();
+    assert_eq!(lim.len(), 3);
+
+    clock.advance(Duration::from_millis(800));
+    lim.retain_recent();
+    assert!(lim.is_empty());
+}
+
+#[test]
+fn denied_batches_leave_fresh_keys_fresh() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(5u32));
+    let store = SkewedStateStore::new(HashMapStateStore::default(), quota)
+        .with_skew(1, ClockSkew::Ahead(Duration::from_millis(300)));
+    let lim: RateLimiter<_, _, _, NoOpMiddleware<Nanos>> =
+        RateLimiter::new(quota, store, clock.clone()).with_fresh_state(FreshState::Empty);
+
+    assert!(lim.check_key(&2).is_ok());
+    assert_eq!(lim.check_keys_all(&[&1, &2]).unwrap_err().0, &2);
+
+    // Key 1 still starts out with a single cell:
+    assert!(!lim.contains_key(&1));
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_err());
+}
+
+#[test]
+fn skews_keep_the_time_of_the_last_positive_decision() {
+    let quota = Quota::per_second(nonzero!(1u32))
+        .allow_burst(nonzero!(5u32))
+        .with_burst_recharge_delay(Duration::from_secs(10));
+
+    // Returns how many cells the key gets after `quiet` seconds, once its authoritative region
+    // used up its burst, 100s into the rate limiters' lifetime on its own clock:
+    let admitted = |skew: Duration, quiet: u64| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let eu_clock = FakeRelativeClock::default();
+        let eu: RateLimiter<_, _, _, NoOpMiddleware<Nanos>> = RateLimiter::new(
+            quota,
+            ReplicatingStateStore::new(HashMapStateStore::default(), nonzero!(1usize), move |b| {
+                tx.send(b).unwrap()
+            }),
+            eu_clock.clone(),
+        );
+        let us_clock = FakeRelativeClock::default();
+        let store = SkewedStateStore::new(HashMapStateStore::default(), quota)
+            .with_skew(1u32, ClockSkew::Ahead(skew));
+        let us: RateLimiter<_, _, _, NoOpMiddleware<Nanos>> =
+            RateLimiter::new(quota, store, us_clock.clone());
+
+        eu_clock.advance(skew + Duration::from_secs(100));
+        us_clock.advance(Duration::from_secs(100));
+        for _ in 0..5 {
+            assert!(eu.check_key(&1).is_ok());
+        }
+        us.apply_authoritative_states(rx.try_iter().flatten());
+
+        us_clock.advance(Duration::from_secs(quiet));
+        (0..10).take_while(|_| us.check_key(&1).is_ok()).count()
+    };
+
+    for quiet in [3, 12] {
+        let control = admitted(Duration::ZERO, quiet);
+        for skew in [1, 7, 123, 333, 999] {
+            assert_eq!(
+                admitted(Duration::from_millis(skew), quiet),
+                control,
+                "skew of {}ms, after {}s",
+                skew,
+                quiet
+            );
+        }
+    }
+}
@@ -0,0 +1,82 @@
+#![cfg(feature = "std")]
+
+use futures_executor::block_on;
+use governor::concurrency::{ConcurrencyLimiter, KeyedConcurrencyLimiter};
+use nonzero_ext::nonzero;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn direct_permits_are_released_on_drop() {
+    let lim = ConcurrencyLimiter::new(nonzero!(2u32));
+    let first = lim.check().unwrap();
+    let second = lim.check().unwrap();
+    assert_eq!(lim.in_use(), 2);
+    let saturated = lim.check().unwrap_err();
+    assert_eq!(saturated.max_concurrency(), nonzero!(2u32));
+    assert_eq!(saturated.to_string(), "all 2 permits are in use");
+
+    drop(first);
+    assert_eq!(lim.in_use(), 1);
+    let _third = lim.check().unwrap();
+    drop(second);
+    assert_eq!(lim.in_use(), 1);
+}
+
+#[test]
+fn keyed_permits_are_per_key() {
+    let lim = KeyedConcurrencyLimiter::new(nonzero!(1u32));
+    let alice = lim.check_key(&"alice").unwrap();
+    assert_eq!(alice.key(), &"alice");
+    assert!(lim.check_key(&"alice").is_err());
+    let bob = lim.check_key(&"bob").unwrap();
+    assert_eq!(lim.in_use_for_key(&"alice"), 1);
+    assert_eq!(lim.len(), 2);
+
+    // Keys without permits are forgotten:
+    drop(alice);
+    assert_eq!(lim.in_use_for_key(&"alice"), 0);
+    assert_eq!(lim.len(), 1);
+    drop(bob);
+    assert!(lim.is_empty());
+}
+
+#[test]
+fn direct_waits_for_release() {
+    let lim = Arc::new(ConcurrencyLimiter::new(nonzero!(1u32)));
+    let permit = lim.check().unwrap();
+    let waiter = {
+        let lim = Arc::clone(&lim);
+        thread::spawn(move || {
+            let _permit = block_on(lim.until_ready());
+        })
+    };
+    thread::sleep(Duration::from_millis(10));
+    assert!(!waiter.is_finished());
+    drop(permit);
+    waiter.join().unwrap();
+    assert_eq!(lim.in_use(), 0);
+}
+
+#[test]
+fn keyed_waits_for_release() {
+    let lim = Arc::new(KeyedConcurrencyLimiter::new(nonzero!(1u32)));
+    let permit = lim.check_key(&1u32).unwrap();
+    let waiters: Vec<_> = (0..3)
+        .map(|_| {
+            let lim = Arc::clone(&lim);
+            thread::spawn(move || {
+                let _permit = block_on(lim.until_key_ready(&1u32));
+                thread::sleep(Duration::from_millis(1));
+            })
+        })
+        .collect();
+    // Other keys are not affected:
+    assert!(block_on(lim.until_key_ready(&2u32)).key() == &2u32);
+    drop(permit);
+    for waiter in waiters {
+        waiter.join().unwrap();
+    }
+    assert!(lim.is_empty());
+}
@@ -0,0 +1,110 @@
+#![cfg(feature = "std")]
+
+use governor::nanos::Nanos;
+use governor::state::StateStore;
+use governor::state::{
+    conformance::assert_state_store_conformance,
+    keyed::{
+        AdmissionLogStateStore, CappedStateStore, FixedCapacityStateStore, HashMapStateStore,
+        SkewedStateStore, WhenFull,
+    },
+    InMemoryState, InstrumentedStateStore, NotKeyed,
+};
+use governor::Quota;
+use nonzero_ext::nonzero;
+use std::sync::Mutex;
+
+#[test]
+fn in_memory_state() {
+    assert_state_store_conformance(&InMemoryState::default(), &NotKeyed::NonKey);
+}
+
+#[test]
+fn hashmap() {
+    let store = HashMapStateStore::<u32>::default();
+    assert_state_store_conformance(&store, &1);
+    assert_state_store_conformance(&store, &2);
+}
+
+#[cfg(feature = "spin")]
+#[test]
+fn spin_hashmap() {
+    let store = governor::state::keyed::SpinHashMapStateStore::<u32>::default();
+    assert_state_store_conformance(&store, &1);
+    assert_state_store_conformance(&store, &2);
+}
+
+#[cfg(feature = "dashmap")]
+#[test]
+fn dashmap() {
+    let store = governor::state::keyed::DashMapStateStore::<u32>::default();
+    assert_state_store_conformance(&store, &1);
+    assert_state_store_conformance(&store, &2);
+}
+
+#[cfg(feature = "skiplist")]
+#[test]
+fn skiplist() {
+    let store = governor::state::keyed::SkipListStateStore::<u32>::default();
+    assert_state_store_conformance(&store, &1);
+    assert_state_store_conformance(&store, &2);
+}
+
+#[test]
+fn capped() {
+    for when_full in [WhenFull::Reject, WhenFull::EvictLeastRecentlyUsed] {
+        let store = CappedStateStore::<u32>::new(nonzero!(2usize), when_full);
+        assert_state_store_conformance(&store, &1);
+        assert_state_store_conformance(&store, &2);
+    }
+}
+
+#[test]
+fn fixed_capacity() {
+    let store = FixedCapacityStateStore::<u32>::with_capacity(16);
+    assert_state_store_conformance(&store, &1);
+    assert_state_store_conformance(&store, &2);
+}
+
+#[test]
+fn wrappers() {
+    assert_state_store_conformance(
+        &InstrumentedStateStore::new(HashMapStateStore::<u32>::default()),
+        &1,
+    );
+    assert_state_store_conformance(
+        &AdmissionLogStateStore::new(HashMapStateStore::<u32>::default(), nonzero!(4usize)),
+        &1,
+    );
+    assert_state_store_conformance(
+        &SkewedStateStore::new(
+            HashMapStateStore::<u32>::default(),
+            Quota::per_second(nonzero!(1u32)),
+        ),
+        &1,
+    );
+}
+
+/// A state store that only keeps the low 32 bits of states.
+#[derive(Default)]
+struct TruncatingStore(Mutex<Option<u32>>);
+
+impl StateStore for TruncatingStore {
+    type Key = NotKeyed;
+
+    fn measure_and_replace<T, F, E>(&self, _key: &Self::Key, f: F) -> Result<T, E>
+    where
+        F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+    {
+        let mut state = self.0.lock().unwrap();
+        let (result, new) = f(state.map(|n| Nanos::from(u64::from(n))))?;
+        *state = Some(new.as_u64() as u32);
+        Ok(result)
+    }
+}
+
+#[test]
+#[should_panic(expected = "didn't keep it")]
+fn truncating_store_violates_contract() {
+    assert_state_store_conformance(&TruncatingStore::default(), &NotKeyed::NonKey);
+}
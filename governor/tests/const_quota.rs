@@ -0,0 +1,62 @@
+use governor::{
+    clock::{Clock, FakeRelativeClock},
+    state::direct::ConstQuotaRateLimiter,
+    InsufficientCapacity, Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::num::NonZeroU32;
+use std::time::Duration;
+
+#[test]
+fn agrees_with_rate_limiter() {
+    let clock = FakeRelativeClock::default();
+    let lim = ConstQuotaRateLimiter::<3, 1_000_000_000, _>::with_clock(clock.clone());
+    let reference =
+        RateLimiter::direct_with_clock(Quota::per_second(nonzero!(3u32)), clock.clone());
+    assert_eq!(lim.quota(), Quota::per_second(nonzero!(3u32)));
+
+    let mut seed = 7u64;
+    for _ in 0..2000 {
+        seed = seed
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        let n = NonZeroU32::new((seed >> 33) as u32 % 4 + 1).unwrap();
+        assert_eq!(lim.check_n(n), reference.check_n(n), "{:?}", clock.now());
+        assert_eq!(lim.check(), reference.check(), "{:?}", clock.now());
+        clock.advance(Duration::from_millis((seed >> 40) % 500));
+    }
+}
+
+#[test]
+fn rejects_too_many() {
+    let clock = FakeRelativeClock::default();
+    let lim = ConstQuotaRateLimiter::<2, 60_000_000_000, _>::with_clock(clock.clone());
+    assert_eq!(Ok(()), lim.check());
+    assert_eq!(Ok(()), lim.check());
+    let denied = lim.check().unwrap_err();
+    assert_eq!(denied.wait_time_from(clock.now()), Duration::from_secs(30));
+
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(Ok(()), lim.check());
+    assert!(lim.check().is_err());
+}
+
+#[test]
+fn check_n_beyond_burst() {
+    let lim =
+        ConstQuotaRateLimiter::<5, 1_000_000_000, _>::with_clock(FakeRelativeClock::default());
+    assert_eq!(lim.check_n(nonzero!(6u32)), Err(InsufficientCapacity(5)));
+    assert_eq!(lim.check_n(nonzero!(5u32)), Ok(Ok(())));
+    assert!(lim.check().is_err());
+}
+
+#[test]
+fn smaller_than_rate_limiter() {
+    let lim =
+        ConstQuotaRateLimiter::<5, 1_000_000_000, _>::with_clock(FakeRelativeClock::default());
+    let reference = RateLimiter::direct_with_clock(
+        Quota::per_second(nonzero!(5u32)),
+        FakeRelativeClock::default(),
+    );
+    assert!(std::mem::size_of_val(&lim) < std::mem::size_of_val(&reference));
+}
@@ -0,0 +1,101 @@
+#![cfg(all(feature = "std", feature = "dashmap"))]
+
+use governor::nanos::Nanos;
+use governor::{
+    clock::FakeRelativeClock, middleware::NoOpMiddleware, state::keyed::DenialTrackingStateStore,
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::time::Duration;
+
+fn limiter(
+    window: Duration,
+    clock: &FakeRelativeClock,
+) -> RateLimiter<u32, DenialTrackingStateStore<u32>, FakeRelativeClock, NoOpMiddleware<Nanos>> {
+    RateLimiter::new(
+        Quota::per_second(nonzero!(1u32)),
+        DenialTrackingStateStore::new(window),
+        clock.clone(),
+    )
+}
+
+#[test]
+fn counts_denials_per_key() {
+    let clock = FakeRelativeClock::default();
+    let lim = limiter(Duration::from_secs(8), &clock);
+    assert_eq!(lim.recent_denials(&1), 0);
+
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_err());
+    assert!(lim.check_key(&1).is_err());
+    assert!(lim.check_key(&2).is_ok());
+    assert!(lim.check_key_n(&2, nonzero!(1u32)).unwrap().is_err());
+    assert!(lim.check_key_bare(&2).is_err());
+
+    assert_eq!(lim.recent_denials(&1), 2);
+    assert_eq!(lim.recent_denials(&2), 2);
+    assert_eq!(lim.recent_denials(&3), 0);
+}
+
+#[test]
+fn insufficient_capacity_is_not_a_denial() {
+    let clock = FakeRelativeClock::default();
+    let lim = limiter(Duration::from_secs(8), &clock);
+    assert!(lim.check_key_n(&1, nonzero!(2u32)).is_err());
+    assert_eq!(lim.recent_denials(&1), 0);
+}
+
+#[test]
+fn denials_expire_with_window() {
+    let clock = FakeRelativeClock::default();
+    let lim = limiter(Duration::from_secs(8), &clock);
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_err());
+
+    clock.advance(Duration::from_secs(4));
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_err());
+    assert_eq!(lim.recent_denials(&1), 2);
+
+    // The first denial slides out of the window:
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(lim.recent_denials(&1), 1);
+
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(lim.recent_denials(&1), 0);
+}
+
+#[test]
+fn retain_recent_keeps_denied_keys() {
+    let clock = FakeRelativeClock::default();
+    let lim = limiter(Duration::from_secs(60), &clock);
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_err());
+    assert!(lim.check_key(&2).is_ok());
+    assert_eq!(lim.len(), 2);
+
+    // Both keys' rate-limiting states are old, but key 1 was denied recently:
+    clock.advance(Duration::from_secs(10));
+    lim.retain_recent();
+    assert_eq!(lim.len(), 1);
+    assert_eq!(lim.recent_denials(&1), 1);
+
+    clock.advance(Duration::from_secs(60));
+    lim.retain_recent();
+    assert!(lim.is_empty());
+
+    lim.shrink_to_fit();
+    assert!(lim.approx_memory_bytes() > 0);
+}
+
+#[test]
+fn counts_consumed_denials() {
+    let clock = FakeRelativeClock::default();
+    let lim = limiter(Duration::from_secs(8), &clock);
+
+    assert!(lim.consume_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_err());
+    assert!(lim.consume_key(&1).is_err());
+    assert!(lim.consume_key(&1).is_err());
+    assert_eq!(lim.recent_denials(&1), 3);
+}
@@ -1,6 +1,8 @@
 use governor::{
-    clock::{Clock, FakeRelativeClock},
-    DefaultDirectRateLimiter, InsufficientCapacity, Quota, RateLimiter,
+    clock::{Clock, FakeRelativeClock, Reference},
+    nanos::Nanos,
+    state::FreshState,
+    CostError, DefaultDirectRateLimiter, InsufficientCapacity, Quota, RateLimiter,
 };
 use nonzero_ext::nonzero;
 use std::time::Duration;
@@ -211,3 +213,704 @@ fn stresstest_large_quotas() {
     });
     rlspin(rate_limiter);
 }
+
+#[test]
+fn bare_check_agrees_with_check() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(5u32));
+    let lim = RateLimiter::direct_with_clock(quota, clock.clone());
+    let bare = RateLimiter::direct_with_clock(quota, clock.clone());
+    let ms = Duration::from_millis(1);
+
+    for i in 0..100 {
+        assert_eq!(lim.check(), bare.check_bare(), "iteration {}", i);
+        clock.advance(ms * 50);
+    }
+}
+
+#[test]
+fn fast_check_agrees_with_check() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(5u32));
+    let lim = RateLimiter::direct_with_clock(quota, clock.clone());
+    let fast = RateLimiter::direct_with_clock(quota, clock.clone());
+    let ms = Duration::from_millis(1);
+
+    for i in 0..100 {
+        assert_eq!(lim.check().is_ok(), fast.check_fast(), "iteration {}", i);
+        clock.advance(ms * 50);
+    }
+}
+
+#[test]
+fn check_only_does_not_consume() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+
+    for _ in 0..10 {
+        assert_eq!(Ok(()), lb.check_only());
+    }
+    assert_eq!(Ok(Ok(())), lb.check_n_only(nonzero!(2u32)));
+    assert_eq!(
+        Err(InsufficientCapacity(2)),
+        lb.check_n_only(nonzero!(3u32))
+    );
+
+    assert_eq!(Ok(()), lb.check());
+    assert_eq!(Ok(()), lb.check_only());
+    assert!(lb.check_n_only(nonzero!(2u32)).unwrap().is_err());
+    assert_eq!(Ok(()), lb.check());
+    assert!(lb.check_only().is_err());
+    assert!(lb.check().is_err());
+}
+
+#[test]
+fn consume_counts_nonconforming_cells() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    let ms = Duration::from_millis(1);
+
+    assert_eq!(Ok(Ok(())), lb.consume_n(nonzero!(2u32)));
+    assert_eq!(Err(InsufficientCapacity(2)), lb.consume_n(nonzero!(3u32)));
+
+    // Overdraw the limiter by two cells:
+    let negative = lb.consume().unwrap_err();
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_millis(1000)
+    );
+    let negative = lb.consume().unwrap_err();
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_millis(1500)
+    );
+
+    // ...which delays the next conforming cell:
+    clock.advance(ms * 1499);
+    assert!(lb.check_only().is_err());
+    assert!(lb.check().is_err());
+    clock.advance(ms);
+    assert_eq!(Ok(()), lb.check_only());
+    assert_eq!(Ok(()), lb.consume());
+    assert!(lb.check().is_err());
+}
+
+#[test]
+fn check_only_then_consume() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(3u32)), clock.clone());
+
+    assert_eq!(Ok(Ok(())), lb.check_n_only(nonzero!(3u32)));
+    assert_eq!(Ok(Ok(())), lb.consume_n(nonzero!(2u32)));
+    assert!(lb.check_n_only(nonzero!(2u32)).unwrap().is_err());
+    assert_eq!(Ok(()), lb.check_only());
+    assert_eq!(Ok(()), lb.consume());
+    assert!(lb.check_only().is_err());
+}
+
+#[test]
+fn check_n_clamped_admits_burst_size() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(5u32)), clock.clone());
+    let ms = Duration::from_millis(1);
+
+    let clamped = lb.check_n_clamped(nonzero!(3u32)).unwrap();
+    assert_eq!(clamped.requested(), nonzero!(3u32));
+    assert_eq!(clamped.admitted(), nonzero!(3u32));
+    assert!(!clamped.is_clamped());
+    assert_eq!(&(), clamped.outcome());
+
+    // Only 2 cells are left, but a full burst is needed:
+    assert!(lb.check_n_clamped(nonzero!(10u32)).is_err());
+    clock.advance(ms * 600);
+    let clamped = lb.check_n_clamped(nonzero!(10u32)).unwrap();
+    assert_eq!(clamped.requested(), nonzero!(10u32));
+    assert_eq!(clamped.admitted(), nonzero!(5u32));
+    assert!(clamped.is_clamped());
+    clamped.into_outcome();
+    assert!(lb.check().is_err());
+}
+
+#[test]
+fn check_at_least_n_admits_available_cells() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(5u32)), clock.clone());
+    let ms = Duration::from_millis(1);
+
+    assert_eq!(
+        Err(InsufficientCapacity(5)),
+        lb.check_at_least_n(nonzero!(6u32), nonzero!(10u32))
+    );
+
+    let clamped = lb
+        .check_at_least_n(nonzero!(1u32), nonzero!(3u32))
+        .unwrap()
+        .unwrap();
+    assert_eq!(clamped.admitted(), nonzero!(3u32));
+    assert!(!clamped.is_clamped());
+
+    // 2 cells are left, which is enough for a minimum of 2 but not 3:
+    assert!(lb
+        .check_at_least_n(nonzero!(3u32), nonzero!(4u32))
+        .unwrap()
+        .is_err());
+    let clamped = lb
+        .check_at_least_n(nonzero!(2u32), nonzero!(4u32))
+        .unwrap()
+        .unwrap();
+    assert_eq!(clamped.requested(), nonzero!(4u32));
+    assert_eq!(clamped.admitted(), nonzero!(2u32));
+    assert!(clamped.is_clamped());
+    assert!(lb
+        .check_at_least_n(nonzero!(1u32), nonzero!(4u32))
+        .unwrap()
+        .is_err());
+
+    clock.advance(ms * 400);
+    let clamped = lb
+        .check_at_least_n(nonzero!(1u32), nonzero!(4u32))
+        .unwrap()
+        .unwrap();
+    assert_eq!(clamped.admitted(), nonzero!(2u32));
+    assert!(lb.check().is_err());
+}
+
+#[test]
+fn refundable_checks() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock);
+
+    for _ in 0..5 {
+        let guard = lb.check_refundable().unwrap();
+        assert_eq!(&(), guard.outcome());
+    }
+    lb.check_refundable().unwrap().commit();
+    assert_eq!(Ok(()), lb.check());
+    assert!(lb.check_refundable().is_err());
+
+    lb.refund();
+    lb.check_refundable().unwrap().refund();
+    assert_eq!(Ok(()), lb.check());
+    assert!(lb.check().is_err());
+}
+
+#[test]
+fn next_allowed_times_match_decisions() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    let ms = Duration::from_millis(1);
+
+    assert_eq!(Ok(()), lb.check());
+    clock.advance(ms * 100);
+    let times = lb.next_allowed_times(4);
+    assert_eq!(
+        times,
+        vec![
+            clock.now(),
+            clock.now() + ms * 400,
+            clock.now() + ms * 900,
+            clock.now() + ms * 1400
+        ]
+    );
+    for time in times {
+        let wait = time.duration_since(clock.now());
+        clock.advance(wait.into());
+        assert_eq!(Ok(()), lb.check(), "at {:?}", clock.now());
+        assert!(lb.check_only().is_err());
+    }
+}
+
+#[test]
+fn rebase_start_keeps_decisions() {
+    let clock = FakeRelativeClock::default();
+    let ms = Duration::from_millis(1);
+    clock.advance(ms * 10_000);
+    let mut lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+
+    assert_eq!(Ok(()), lb.check());
+    assert_eq!(Ok(()), lb.check());
+    let earliest = lb.check().unwrap_err().earliest_possible();
+
+    // Rebasing to an earlier start keeps the state:
+    let five_secs = Nanos::saturating_from(ms * 5_000);
+    lb.rebase_start(lb.start().saturating_sub(five_secs));
+    assert_eq!(lb.start(), five_secs);
+    assert_eq!(earliest, lb.check().unwrap_err().earliest_possible());
+
+    // ...and so does rebasing to a later one:
+    clock.advance(ms * 100);
+    lb.rebase_start(clock.now());
+    assert_eq!(earliest, lb.check().unwrap_err().earliest_possible());
+    clock.advance(ms * 400);
+    assert_eq!(Ok(()), lb.check());
+    assert!(lb.check().is_err());
+}
+
+#[test]
+fn deadline_duration_is_relative_to_decision() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    assert_eq!(Ok(()), lb.check());
+    assert_eq!(Ok(()), lb.check());
+    clock.advance(Duration::from_millis(100));
+    let negative = lb.check().unwrap_err();
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(negative.deadline_duration(), Duration::from_millis(400));
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_millis(300)
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn earliest_possible_unix_ms() {
+    use governor::clock::{MonotonicClock, SystemClock};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn unix_ms(t: SystemTime) -> u64 {
+        t.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+    }
+
+    let lb = RateLimiter::direct_with_clock(Quota::per_hour(nonzero!(1u32)), MonotonicClock);
+    assert_eq!(Ok(()), lb.check());
+    let expected = unix_ms(SystemTime::now() + Duration::from_secs(3600));
+    let earliest = lb
+        .check()
+        .unwrap_err()
+        .earliest_possible_unix_ms(lb.clock());
+    assert!(
+        earliest.abs_diff(expected) < 100,
+        "{} vs {}",
+        earliest,
+        expected
+    );
+
+    let lb = RateLimiter::direct_with_clock(Quota::per_hour(nonzero!(1u32)), SystemClock);
+    assert_eq!(Ok(()), lb.check());
+    let negative = lb.check().unwrap_err();
+    assert_eq!(
+        negative.earliest_possible_unix_ms(lb.clock()),
+        unix_ms(negative.earliest_possible())
+    );
+}
+
+#[test]
+fn try_check_reports_all_outcomes() {
+    use governor::{middleware::StateInformationMiddleware, state::TryCheck};
+
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(3u32)), clock.clone())
+        .with_middleware::<StateInformationMiddleware>();
+
+    let allowed = lim.try_check().allowed().unwrap();
+    assert_eq!(allowed.remaining_burst_capacity(), 2);
+    assert_eq!(
+        lim.try_check_n(nonzero!(4u32)),
+        TryCheck::InsufficientCapacity { max: 3 }
+    );
+    assert!(lim.try_check_n(nonzero!(2u32)).is_allowed());
+
+    let limited = lim.try_check();
+    assert_eq!(
+        limited.retry_after(),
+        Some(Duration::from_nanos(333_333_333))
+    );
+    assert_eq!(
+        lim.try_check_n(nonzero!(2u32)).retry_after(),
+        Some(Duration::from_nanos(666_666_666))
+    );
+
+    clock.advance(limited.retry_after().unwrap());
+    assert!(lim.try_check().is_allowed());
+}
+
+#[test]
+fn decide_reports_all_outcomes() {
+    use governor::state::{Decision, IntoDecision};
+
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(3u32)), clock.clone());
+
+    assert_eq!(lim.check_decide(), Decision::Allowed(()));
+    assert_eq!(
+        lim.check_n_decide(nonzero!(4u32)),
+        Decision::InsufficientCapacity { max: 3 }
+    );
+    assert!(lim.check_n(nonzero!(2u32)).into_decision().is_allowed());
+
+    let denied = lim.check_n_decide(nonzero!(2u32));
+    assert_eq!(denied.max_capacity(), None);
+    assert_eq!(
+        denied.retry_after(),
+        Some(Duration::from_nanos(666_666_666))
+    );
+    let not_until = denied.denied().unwrap();
+    assert_eq!(
+        lim.check_n_decide(nonzero!(2u32)).into_result(),
+        Ok(Err(not_until))
+    );
+}
+
+#[test]
+fn saturates_near_the_horizon() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(1u32)), clock.clone());
+    let horizon = Nanos::from(u64::MAX);
+
+    // The next cell would conform past the horizon, so the state saturates:
+    clock.advance(Duration::from_nanos(u64::MAX - 100));
+    assert_eq!(Ok(()), lim.check());
+    let not_until = lim.check().unwrap_err();
+    assert_eq!(not_until.earliest_possible(), horizon);
+    assert_eq!(
+        not_until.wait_time_from(clock.now()),
+        Duration::from_nanos(100)
+    );
+    assert_eq!(
+        lim.check_n(nonzero!(u32::MAX)),
+        Err(InsufficientCapacity(1))
+    );
+}
+
+#[test]
+fn huge_quotas_dont_overflow() {
+    let clock = FakeRelativeClock::default();
+    let century = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+    let lim = RateLimiter::direct_with_clock(Quota::with_period(century).unwrap(), clock.clone());
+    assert_eq!(
+        lim.check_n(nonzero!(u32::MAX)),
+        Err(InsufficientCapacity(1))
+    );
+    assert_eq!(lim.check(), Ok(()));
+
+    // Replenishing the next cell takes until past the horizon:
+    clock.advance(century * 5);
+    assert_eq!(lim.check(), Ok(()));
+    assert!(lim.check().is_err());
+    clock.advance(century / 2);
+    assert!(lim.check().is_err());
+}
+
+#[test]
+fn idle_time_doesnt_carry_over() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(10u32));
+    let lim = RateLimiter::direct_with_clock(quota, clock.clone());
+    clock.advance(Duration::from_secs(60 * 60));
+
+    // After an hour of idling, only the burst gets through at once:
+    let mut allowed = 0;
+    while lim.check().is_ok() {
+        allowed += 1;
+    }
+    assert_eq!(allowed, 10);
+
+    // ...and the rest of the interval only sees replenished cells:
+    for _ in 0..999 {
+        clock.advance(Duration::from_millis(1));
+        while lim.check().is_ok() {
+            allowed += 1;
+        }
+    }
+    assert_eq!(allowed, quota.max_cells_within(Duration::from_millis(999)));
+}
+
+#[test]
+fn cooldown_after_depletion() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(2u32)).with_cooldown(Duration::from_secs(5));
+    let lim = RateLimiter::direct_with_clock(quota, clock.clone());
+
+    // Using up the burst adds the cooldown to the wait for the next cell:
+    assert_eq!(Ok(()), lim.check());
+    assert_eq!(Ok(()), lim.check());
+    let wait = lim.check().unwrap_err().wait_time_from(clock.now());
+    assert_eq!(wait, Duration::from_millis(5500));
+
+    clock.advance(Duration::from_millis(5499));
+    assert!(lim.check().is_err());
+    clock.advance(Duration::from_millis(1));
+    assert_eq!(Ok(()), lim.check());
+
+    // That cell used up the burst again:
+    clock.advance(Duration::from_millis(500));
+    assert!(lim.check().is_err());
+
+    // Batches of cells that use up the burst get the cooldown too:
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(2u32)));
+    clock.advance(Duration::from_secs(1));
+    assert!(lim.check().is_err());
+}
+
+#[test]
+fn cooldown_spares_cells_within_burst() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(2u32)).with_cooldown(Duration::from_secs(5));
+    let lim = RateLimiter::direct_with_clock(quota, clock.clone());
+
+    // Cells at the replenishment rate never use up the second cell of the burst:
+    for _ in 0..10 {
+        assert_eq!(Ok(()), lim.check());
+        clock.advance(Duration::from_millis(500));
+    }
+    assert_eq!(lim.check_n(nonzero!(2u32)), Ok(Ok(())));
+}
+
+#[test]
+fn burst_recharge_delay_holds_burst_while_active() {
+    let clock = FakeRelativeClock::default();
+    // 333,333,333ns per cell don't divide evenly into any rounding:
+    let quota = Quota::per_second(nonzero!(3u32))
+        .allow_burst(nonzero!(4u32))
+        .with_burst_recharge_delay(Duration::from_secs(5));
+    let lim = RateLimiter::direct_with_clock(quota, clock.clone());
+    let interval = quota.replenish_interval();
+
+    // Half the burst (and the next cell) stays available through a long stretch at the
+    // sustained rate:
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(2u32)));
+    for _ in 0..10_000 {
+        clock.advance(interval);
+        assert_eq!(Ok(()), lim.check());
+    }
+    clock.advance(interval);
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(3u32)));
+    assert!(lim.check().is_err());
+
+    // Cells at the sustained rate get through exactly on time, without recharging the burst:
+    for _ in 0..10_000 {
+        clock.advance(interval - Duration::from_nanos(1));
+        assert!(lim.check().is_err());
+        clock.advance(Duration::from_nanos(1));
+        assert_eq!(Ok(()), lim.check());
+    }
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(Ok(()), lim.check());
+    assert!(lim.check().is_err());
+}
+
+#[test]
+fn burst_recharge_delay_recharges_after_quiet() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(2u32)).with_burst_recharge_delay(Duration::from_secs(5));
+    let lim = RateLimiter::direct_with_clock(quota, clock.clone());
+
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(2u32)));
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(Ok(()), lim.check());
+    assert!(lim.check().is_err());
+
+    // Denied cells don't hold the burst back:
+    clock.advance(Duration::from_millis(4999));
+    assert_eq!(Ok(()), lim.check());
+    assert!(lim.check().is_err());
+    clock.advance(Duration::from_millis(5501));
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(2u32)));
+
+    // Without a delay, the burst recharges right away:
+    let lim = RateLimiter::direct_with_clock(
+        quota.with_burst_recharge_delay(Duration::ZERO),
+        clock.clone(),
+    );
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(2u32)));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(2u32)));
+}
+
+#[test]
+fn burst_recharge_delay_at_unaligned_times() {
+    let quota = Quota::per_second(nonzero!(1u32))
+        .allow_burst(nonzero!(5u32))
+        .with_burst_recharge_delay(Duration::from_secs(10));
+
+    // A full burst of single cells gets through at any time, like a batch of them does:
+    for start in [
+        Duration::from_millis(1),
+        Duration::from_nanos(100_001_000_000),
+        Duration::from_nanos(123_456_789),
+    ] {
+        let clock = FakeRelativeClock::default();
+        let lim = RateLimiter::direct_with_clock(quota, clock.clone());
+        clock.advance(start);
+        for _ in 0..5 {
+            assert_eq!(Ok(()), lim.check(), "at {:?}", start);
+        }
+        assert!(lim.check().is_err());
+
+        // Once the burst is used up, a client sending exactly once per interval gets through:
+        for _ in 0..1000 {
+            clock.advance(quota.replenish_interval());
+            assert_eq!(Ok(()), lim.check(), "at {:?}", start);
+            assert!(lim.check().is_err());
+        }
+    }
+}
+
+#[test]
+fn idle_detection() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(lim.idle_for(), Duration::from_secs(1));
+
+    // A burst pushes the end of the idle time back by one cell's worth per cell:
+    lim.check_n(nonzero!(2u32)).unwrap().unwrap();
+    assert_eq!(lim.idle_for(), Duration::from_secs(0));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(lim.idle_for(), Duration::from_millis(500));
+    assert!(lim.is_idle(Duration::from_millis(500)));
+    assert!(!lim.is_idle(Duration::from_secs(1)));
+
+    // Denied cells don't count:
+    lim.check().unwrap();
+    lim.check().unwrap();
+    assert!(lim.check().is_err());
+    clock.advance(Duration::from_millis(750));
+    assert_eq!(lim.idle_for(), Duration::from_millis(250));
+}
+
+#[test]
+fn check_cost_accumulates_fractions() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    let ms = Duration::from_millis(1);
+
+    assert_eq!(
+        Err(CostError::InsufficientCapacity(InsufficientCapacity(2))),
+        lb.check_cost(2.5)
+    );
+    assert_eq!(
+        Err(CostError::InsufficientCapacity(InsufficientCapacity(2))),
+        lb.check_cost(f64::INFINITY)
+    );
+
+    // Six thirds of a cell make up the whole burst:
+    for _ in 0..6 {
+        assert_eq!(Ok(Ok(())), lb.check_cost(1.0 / 3.0));
+    }
+    assert!(lb.check_cost(0.1).unwrap().is_err());
+    assert_eq!(Ok(Ok(())), lb.check_cost(0.0));
+
+    // A quarter of a cell takes a quarter of the replenishment interval to come back:
+    clock.advance(ms * 124);
+    assert!(lb.check_cost(0.25).unwrap().is_err());
+    clock.advance(ms);
+    assert_eq!(Ok(Ok(())), lb.check_cost(0.25));
+
+    clock.advance(ms * 500);
+    assert_eq!(Ok(Ok(())), lb.check_cost(1.0));
+    assert!(lb.check().is_err());
+}
+
+#[test]
+fn check_cost_rejects_negative_costs() {
+    let lb = RateLimiter::direct_with_clock(
+        Quota::per_second(nonzero!(2u32)),
+        FakeRelativeClock::default(),
+    );
+    assert_eq!(Err(CostError::InvalidCost), lb.check_cost(-1.0));
+    assert_eq!(Err(CostError::InvalidCost), lb.check_cost(f64::NAN));
+    assert_eq!(
+        Err(CostError::InvalidCost),
+        lb.check_cost(f64::NEG_INFINITY)
+    );
+    assert_eq!(Ok(Ok(())), lb.check_cost(-0.0));
+    // Rejected costs consume nothing:
+    assert_eq!(Ok(Ok(())), lb.check_cost(2.0));
+}
+
+#[test]
+fn disabled_lets_everything_through() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    assert!(lb.is_enabled());
+    assert_eq!(Ok(()), lb.check());
+
+    lb.set_enabled(false);
+    assert!(!lb.is_enabled());
+    for _ in 0..10 {
+        assert_eq!(Ok(()), lb.check());
+        assert_eq!(Ok(()), lb.check_only());
+        assert_eq!(Ok(()), lb.consume());
+        assert!(lb.check_fast());
+    }
+    assert_eq!(Ok(Ok(())), lb.check_n(nonzero!(3u32)));
+    assert_eq!(Ok(Ok(())), lb.check_cost(10.0));
+    assert_eq!(
+        lb.check_n_clamped(nonzero!(5u32)).unwrap().admitted(),
+        nonzero!(5u32)
+    );
+    assert_eq!(
+        lb.check_or_reject_if_wait_exceeds(Duration::ZERO)
+            .unwrap()
+            .wait(),
+        Duration::ZERO
+    );
+    assert_eq!(lb.next_allowed_times(3), vec![clock.now(); 3]);
+    lb.refund();
+
+    // None of that counted against the rate limiter:
+    lb.set_enabled(true);
+    assert_eq!(Ok(()), lb.check());
+    assert_ne!(Ok(()), lb.check());
+}
+
+#[test]
+fn check_n_with_grace_borrows_capacity() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(4u32)), clock.clone());
+    let grace = Duration::from_millis(250);
+    assert_eq!(Ok(Ok(())), lb.check_n(nonzero!(3u32)));
+
+    // Two cells are missing a whole cell's worth of capacity, which replenishes within 250ms:
+    assert_eq!(Ok(Ok(())), lb.check_n_with_grace(nonzero!(2u32), grace));
+    // The borrowed capacity has to be paid back first:
+    assert!(lb.check().is_err());
+    clock.advance(Duration::from_millis(250));
+    assert!(lb.check().is_err());
+    let denied = lb
+        .check_n_with_grace(nonzero!(2u32), grace)
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(
+        denied.wait_time_from(clock.now()),
+        Duration::from_millis(250)
+    );
+
+    clock.advance(Duration::from_millis(250));
+    assert_eq!(Ok(Ok(())), lb.check_n_with_grace(nonzero!(2u32), grace));
+    assert_eq!(
+        Err(InsufficientCapacity(4)),
+        lb.check_n_with_grace(nonzero!(5u32), Duration::from_secs(10))
+    );
+
+    // Without a grace period, it behaves like `check_n`:
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(
+        Ok(Ok(())),
+        lb.check_n_with_grace(nonzero!(4u32), Duration::ZERO)
+    );
+    assert!(lb
+        .check_n_with_grace(nonzero!(1u32), Duration::ZERO)
+        .unwrap()
+        .is_err());
+}
+
+#[test]
+fn fresh_state_empty_with_recharge_delay() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(4u32)).with_burst_recharge_delay(Duration::from_secs(2));
+    let lb =
+        RateLimiter::direct_with_clock(quota, clock.clone()).with_fresh_state(FreshState::Empty);
+    assert_eq!(Ok(()), lb.check());
+    assert!(lb.check().is_err());
+
+    // The burst capacity only recharges once the delay has passed:
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(Ok(()), lb.check());
+    assert!(lb.check().is_err());
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(Ok(Ok(())), lb.check_n(nonzero!(4u32)));
+}
@@ -0,0 +1,41 @@
+#![cfg(feature = "std")]
+
+use futures_executor::block_on;
+use governor::{
+    flavors::{AsyncRateLimiter, BlockingRateLimiter},
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+fn flavors_share_the_limiter() {
+    let lim = Arc::new(RateLimiter::direct(Quota::per_hour(nonzero!(2u32))));
+    let immediate = BlockingRateLimiter::new(Arc::clone(&lim));
+    assert_eq!(Ok(()), immediate.check());
+
+    let paced = immediate.asynchronous();
+    assert!(Arc::ptr_eq(paced.limiter(), &lim));
+    assert_eq!(
+        Ok(()),
+        block_on(paced.until_ready_or_reject_if_wait_exceeds(Duration::ZERO))
+    );
+    assert!(paced.blocking().check().is_err());
+}
+
+#[test]
+fn keyed_flavors() {
+    let lim = Arc::new(RateLimiter::keyed(Quota::per_second(nonzero!(50u32))));
+    let paced = AsyncRateLimiter::from(Arc::clone(&lim));
+    let immediate = paced.blocking();
+
+    block_on(paced.until_key_ready(&"alice"));
+    assert!(block_on(paced.until_key_n_ready(&"alice", nonzero!(10u32))).is_ok());
+    assert!(immediate.check_key_n(&"alice", nonzero!(51u32)).is_err());
+    let clamped = immediate
+        .check_key_n_clamped(&"bob", nonzero!(100u32))
+        .unwrap();
+    assert_eq!(clamped.admitted(), nonzero!(50u32));
+    assert!(immediate.check_key(&"bob").is_err());
+}
@@ -2,7 +2,12 @@
 
 use all_asserts::*;
 use futures_executor::block_on;
-use governor::{Quota, RateLimiter};
+use governor::{
+    clock::{Clock, FakeRelativeClock},
+    flavors::AsyncRateLimiter,
+    nanos::Nanos,
+    DirectLimiter, Quota, RateLimiter,
+};
 use nonzero_ext::*;
 use std::sync::Arc;
 use std::thread;
@@ -39,6 +44,51 @@ fn pauses_n() {
     assert_ge!(i.elapsed(), Duration::from_millis(100));
 }
 
+#[test]
+fn pauses_n_clamped() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)));
+
+    lim.check().unwrap();
+    let i = Instant::now();
+    let clamped = block_on(lim.until_n_ready_clamped(nonzero!(15u32)));
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+    assert_eq!(clamped.requested().get(), 15);
+    assert_eq!(clamped.admitted().get(), 10);
+    assert!(clamped.is_clamped());
+}
+
+#[test]
+fn pauses_at_least_n() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)));
+
+    for _ in 0..9 {
+        lim.check().unwrap();
+    }
+    let i = Instant::now();
+    let clamped = block_on(lim.until_at_least_n_ready(nonzero!(2u32), nonzero!(5u32))).unwrap();
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+    assert_ge!(clamped.admitted().get(), 2);
+    assert_le!(clamped.admitted().get(), 5);
+
+    assert!(block_on(lim.until_at_least_n_ready(nonzero!(11u32), nonzero!(15u32))).is_err());
+}
+
+#[test]
+fn pauses_keyed_n_clamped() {
+    let lim = RateLimiter::hashmap(Quota::per_second(nonzero!(10u32)));
+
+    lim.check_key(&1u32).unwrap();
+    assert!(lim.check_key_n_clamped(&1u32, nonzero!(15u32)).is_err());
+    let clamped = lim.check_key_n_clamped(&2u32, nonzero!(15u32)).unwrap();
+    assert_eq!(clamped.admitted().get(), 10);
+
+    let i = Instant::now();
+    let clamped = block_on(lim.until_key_n_ready_clamped(&1u32, nonzero!(15u32)));
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+    assert_eq!(clamped.requested().get(), 15);
+    assert_eq!(clamped.admitted().get(), 10);
+}
+
 #[test]
 fn pauses_keyed() {
     let i = Instant::now();
@@ -55,6 +105,26 @@ fn pauses_keyed() {
     assert_ge!(i.elapsed(), Duration::from_millis(100));
 }
 
+#[cfg(feature = "jitter")]
+#[test]
+fn pauses_with_default_jitter() {
+    use governor::Jitter;
+
+    let quota = Quota::per_second(nonzero!(10u32)).allow_burst(nonzero!(1u32));
+    let lim = RateLimiter::keyed(quota)
+        .with_jitter(Jitter::new(Duration::from_millis(100), Duration::new(0, 0)));
+    lim.check_key(&1u32).unwrap();
+    let i = Instant::now();
+    block_on(lim.until_key_ready(&1u32));
+    assert_ge!(i.elapsed(), Duration::from_millis(200));
+
+    // Explicit jitter replaces the default:
+    lim.check_key(&2u32).unwrap();
+    let i = Instant::now();
+    block_on(lim.until_key_ready_with_jitter(&2u32, Jitter::up_to(Duration::new(0, 0))));
+    assert_lt!(i.elapsed(), Duration::from_millis(200));
+}
+
 #[test]
 fn pauses_keyed_n() {
     let lim = RateLimiter::keyed(Quota::per_second(nonzero!(10u32)));
@@ -150,3 +220,139 @@ fn errors_on_exceeded_capacity() {
 
     block_on(lim.until_key_n_ready(&1u32, nonzero!(11u32))).unwrap_err();
 }
+
+#[test]
+fn pauses_dyn() {
+    let lim: Arc<dyn DirectLimiter> =
+        Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(10u32))));
+
+    // exhaust the limiter:
+    loop {
+        if let Err(wait) = lim.check() {
+            assert_le!(wait, Duration::from_millis(100));
+            break;
+        }
+    }
+    assert!(lim.check_n(nonzero!(11u32)).is_err());
+    assert!(lim.check_n(nonzero!(2u32)).unwrap().is_err());
+    let i = Instant::now();
+    block_on(lim.until_ready());
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+    let lim = Arc::new(lim);
+    assert!(block_on(lim.until_n_ready(nonzero!(11u32))).is_err());
+    block_on(lim.until_n_ready(nonzero!(1u32))).unwrap();
+    assert_ge!(i.elapsed(), Duration::from_millis(200));
+}
+
+#[test]
+fn rejects_long_waits() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)));
+
+    // exhaust the limiter:
+    while lim.check().is_ok() {}
+    let i = Instant::now();
+    assert!(
+        block_on(lim.until_ready_or_reject_if_wait_exceeds(Duration::from_millis(50))).is_err()
+    );
+    assert_lt!(i.elapsed(), Duration::from_millis(50));
+
+    block_on(lim.until_ready_or_reject_if_wait_exceeds(Duration::from_millis(200))).unwrap();
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+    // The cell's capacity was reserved, the next one needs to wait again:
+    assert!(lim.check().is_err());
+}
+
+#[test]
+fn rejects_long_waits_keyed() {
+    let lim = RateLimiter::keyed(Quota::per_second(nonzero!(10u32)));
+
+    while lim.check_key(&1u32).is_ok() {}
+    let i = Instant::now();
+    assert!(block_on(
+        lim.until_key_ready_or_reject_if_wait_exceeds(&1u32, Duration::from_millis(50))
+    )
+    .is_err());
+    block_on(lim.until_key_ready_or_reject_if_wait_exceeds(&2u32, Duration::ZERO)).unwrap();
+    assert_lt!(i.elapsed(), Duration::from_millis(50));
+
+    block_on(lim.until_key_ready_or_reject_if_wait_exceeds(&1u32, Duration::from_millis(200)))
+        .unwrap();
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+}
+
+#[test]
+fn fake_clock_advances_instead_of_waiting() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(10u32)), clock.clone());
+
+    // exhaust the limiter:
+    while lim.check().is_ok() {}
+    let i = Instant::now();
+    block_on(lim.until_ready());
+    assert_eq!(Duration::from(clock.now()), Duration::from_millis(100));
+    block_on(lim.until_n_ready(nonzero!(5u32))).unwrap();
+    assert_eq!(Duration::from(clock.now()), Duration::from_millis(600));
+    block_on(lim.until_ready_or_reject_if_wait_exceeds(Duration::from_millis(200))).unwrap();
+    assert_eq!(Duration::from(clock.now()), Duration::from_millis(700));
+    assert_lt!(i.elapsed(), Duration::from_millis(100));
+}
+
+#[test]
+fn fake_clock_advances_instead_of_waiting_keyed() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(10u32)), clock.clone());
+
+    while lim.check_key(&1u32).is_ok() {}
+    block_on(lim.until_key_ready(&2u32));
+    assert_eq!(clock.now(), Nanos::from(0));
+    block_on(lim.until_key_ready(&1u32));
+    assert_eq!(Duration::from(clock.now()), Duration::from_millis(100));
+    block_on(lim.until_key_n_ready_clamped(&1u32, nonzero!(20u32)));
+    assert_eq!(Duration::from(clock.now()), Duration::from_millis(1100));
+
+    let lim = AsyncRateLimiter::new(Arc::new(lim));
+    block_on(lim.until_key_ready(&1u32));
+    assert_eq!(Duration::from(clock.now()), Duration::from_millis(1200));
+}
+
+#[test]
+fn proceeds_while_disabled() {
+    let lim = RateLimiter::direct(Quota::per_hour(nonzero!(1u32)));
+    lim.check().unwrap();
+    lim.set_enabled(false);
+    let i = Instant::now();
+    for _ in 0..10 {
+        block_on(lim.until_ready());
+        block_on(lim.until_n_ready(nonzero!(2u32))).unwrap();
+    }
+    assert_le!(i.elapsed(), MAX_TEST_RUN_DURATION * 10);
+}
+
+#[test]
+fn sleeps_until_instant() {
+    let clock = governor::clock::MonotonicClock;
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(20u32)), clock.clone());
+    while lim.check().is_ok() {}
+    let i = Instant::now();
+    let deadline = lim.check().unwrap_err().sleep_until_instant(&clock);
+    assert_le!(deadline, i + Duration::from_millis(50));
+    // Allow for the two clocks disagreeing a little:
+    thread::sleep(deadline.saturating_duration_since(Instant::now()) + Duration::from_millis(1));
+    assert_eq!(Ok(()), lim.check());
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn sleeps_until_tokio_instant() {
+    let clock = governor::clock::MonotonicClock;
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(20u32)), clock.clone());
+    while lim.check().is_ok() {}
+    let i = tokio::time::Instant::now();
+    let deadline = lim.check().unwrap_err().sleep_until_tokio_instant(&clock);
+    assert_le!(deadline, i + Duration::from_millis(50));
+    // Allow for the two clocks disagreeing a little:
+    thread::sleep(
+        deadline.saturating_duration_since(tokio::time::Instant::now()) + Duration::from_millis(1),
+    );
+    assert_eq!(Ok(()), lim.check());
+}
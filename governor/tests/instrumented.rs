@@ -0,0 +1,69 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    clock::FakeRelativeClock,
+    middleware::NoOpMiddleware,
+    state::{keyed::HashMapStateStore, ChaosStateStore, InMemoryState, InstrumentedStateStore},
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[test]
+fn counts_operations_and_retries() {
+    let chaos =
+        ChaosStateStore::new(InMemoryState::default()).with_spurious_retries(nonzero!(2u64));
+    let store = InstrumentedStateStore::new(chaos);
+    let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+        Quota::per_second(nonzero!(2u32)),
+        store,
+        FakeRelativeClock::default(),
+    );
+    for _ in 0..4 {
+        let _ = lim.check();
+    }
+    assert!(lim.check_only().is_err());
+
+    let store = lim.into_state_store();
+    assert_eq!(store.operations(), 5);
+    assert_eq!(store.retries(), 2);
+    assert_eq!(store.slow_operations(), 0);
+    assert_eq!(store.inner().accesses(), 5);
+}
+
+#[test]
+fn logs_slow_operations() {
+    let logged = Arc::new(Mutex::new(Vec::new()));
+    let store = InstrumentedStateStore::new(
+        ChaosStateStore::new(HashMapStateStore::default()).with_latency(Duration::from_millis(5)),
+    )
+    .with_slow_operation_log(Duration::from_millis(2), {
+        let logged = Arc::clone(&logged);
+        move |key: &u32, took, attempts| logged.lock().unwrap().push((*key, took, attempts))
+    });
+    let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+        Quota::per_second(nonzero!(1u32)),
+        store,
+        FakeRelativeClock::default(),
+    );
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&2).is_ok());
+
+    let logged = logged.lock().unwrap();
+    assert_eq!(
+        logged
+            .iter()
+            .map(|&(key, _, attempts)| (key, attempts))
+            .collect::<Vec<_>>(),
+        [(1, 1), (2, 1)]
+    );
+    assert!(logged
+        .iter()
+        .all(|&(_, took, _)| took >= Duration::from_millis(5)));
+    assert_eq!(lim.len(), 2);
+
+    let store = lim.into_state_store();
+    assert_eq!(store.slow_operations(), 2);
+    assert!(store.busy_time() >= Duration::from_millis(10));
+}
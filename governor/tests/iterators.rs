@@ -0,0 +1,70 @@
+#![cfg(feature = "std")]
+
+#[cfg(feature = "jitter")]
+use governor::Jitter;
+use governor::{prelude::*, Quota, RateLimiter};
+use nonzero_ext::*;
+use std::iter;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[test]
+fn iterator() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)));
+    let mut iter = iter::repeat(()).ratelimit(&lim);
+    let i = Instant::now();
+
+    for _ in 0..10 {
+        iter.next();
+    }
+    assert!(i.elapsed() <= Duration::from_millis(100));
+
+    iter.next();
+    assert!(i.elapsed() > Duration::from_millis(100));
+    assert!(i.elapsed() <= Duration::from_millis(200));
+
+    iter.next();
+    assert!(i.elapsed() > Duration::from_millis(200));
+    assert!(i.elapsed() <= Duration::from_millis(300));
+}
+
+#[test]
+fn passes_items_through() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(1000u32)));
+    let iter = (0..5).ratelimit(&lim);
+    assert_eq!(iter.size_hint(), (5, Some(5)));
+    assert_eq!(iter.collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+
+    // An exhausted iterator doesn't consume any capacity:
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(1u32)));
+    let mut iter = iter::empty::<()>().ratelimit(&lim);
+    assert_eq!(iter.next(), None);
+    assert!(lim.check().is_ok());
+}
+
+#[cfg(feature = "jitter")]
+#[test]
+fn iterator_with_jitter() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(1000u32)));
+    let jitter = Jitter::up_to(Duration::from_nanos(1));
+    let iter = (0..5).ratelimit_with_jitter(&lim, jitter.clone());
+    assert_eq!(iter.jitter(), &jitter);
+    assert_eq!(iter.collect::<Vec<_>>(), [0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn owned_iterator() {
+    let lim = Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(10u32))));
+    let mut iter = iter::repeat(()).ratelimit_owned(Arc::clone(&lim));
+    iter.set_jitter(Default::default());
+    assert!(iter.limiter().check().is_ok());
+
+    // The combinator doesn't borrow anything, so it can move to another thread:
+    let i = Instant::now();
+    let taken = std::thread::spawn(move || iter.take(10).count())
+        .join()
+        .unwrap();
+    assert_eq!(taken, 10);
+    assert!(i.elapsed() > Duration::from_millis(100));
+    assert!(lim.check().is_err());
+}
@@ -0,0 +1,23 @@
+#![cfg(feature = "jitter")]
+
+use governor::{nanos::Nanos, Jitter};
+use std::time::Duration;
+
+#[test]
+fn seeded_jitter_without_std() {
+    // Seeded jitter works the same with and without std:
+    let jitter = Jitter::new(Duration::from_millis(10), Duration::from_millis(10)).with_seed(7);
+    let waits: Vec<Duration> = (0..20)
+        .map(|_| jitter.clone() + Duration::from_secs(1))
+        .collect();
+    assert!(waits
+        .iter()
+        .all(|w| (Duration::from_millis(1010)..Duration::from_millis(1020)).contains(w)));
+    assert!(waits.iter().any(|w| *w != waits[0]));
+
+    let again = Jitter::new(Duration::from_millis(10), Duration::from_millis(10)).with_seed(7);
+    assert_eq!(
+        again + Nanos::from(0),
+        Nanos::saturating_from(waits[0] - Duration::from_secs(1))
+    );
+}
@@ -1,4 +1,4 @@
-use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use governor::{state::Decision, DefaultKeyedRateLimiter, Quota, RateLimiter};
 use nonzero_ext::nonzero;
 
 #[test]
@@ -7,3 +7,16 @@ fn default_keyed() {
         RateLimiter::keyed(Quota::per_second(nonzero!(20u32)));
     assert_eq!(Ok(()), limiter.check_key(&1));
 }
+
+#[test]
+fn keyed_decide() {
+    let limiter: DefaultKeyedRateLimiter<u32> =
+        RateLimiter::keyed(Quota::per_second(nonzero!(2u32)));
+    assert_eq!(limiter.check_key_decide(&1), Decision::Allowed(()));
+    assert_eq!(
+        limiter.check_key_n_decide(&1, nonzero!(3u32)),
+        Decision::InsufficientCapacity { max: 2 }
+    );
+    assert!(limiter.check_key_n_decide(&2, nonzero!(2u32)).is_allowed());
+    assert!(limiter.check_key_decide(&2).retry_after().is_some());
+}
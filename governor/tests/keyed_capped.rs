@@ -0,0 +1,155 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    clock::{Clock, FakeRelativeClock},
+    middleware::NoOpMiddleware,
+    nanos::Nanos,
+    state::{
+        keyed::{CappedStateStore, WhenFull},
+        FreshState,
+    },
+    InsufficientCapacity, Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::time::Duration;
+
+fn capped_limiter(
+    clock: &FakeRelativeClock,
+    when_full: WhenFull,
+) -> RateLimiter<u32, CappedStateStore<u32>, FakeRelativeClock, NoOpMiddleware<Nanos>> {
+    let store = CappedStateStore::new(nonzero!(2usize), when_full);
+    RateLimiter::new(Quota::per_second(nonzero!(1u32)), store, clock.clone())
+}
+
+#[test]
+fn rejects_new_keys_when_full() {
+    let clock = FakeRelativeClock::default();
+    let lim = capped_limiter(&clock, WhenFull::Reject);
+
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&2).is_ok());
+    let denied = lim.check_key(&3).unwrap_err();
+    assert!(denied.wait_time_from(clock.now()) > Duration::from_secs(365 * 24 * 60 * 60));
+    assert!(lim.check_key_only(&3).is_err());
+    assert_eq!(lim.rejected_keys(), 1);
+    assert_eq!(lim.evicted_keys(), 0);
+    assert_eq!(lim.len(), 2);
+    assert!(!lim.contains_key(&3));
+
+    // Keys in the store keep their states:
+    assert!(lim.check_key(&1).is_err());
+    clock.advance(Duration::from_secs(1));
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&3).is_err());
+    assert_eq!(lim.rejected_keys(), 2);
+
+    // Once keys get removed, there is room again:
+    clock.advance(Duration::from_secs(2));
+    lim.retain_recent();
+    assert!(lim.is_empty());
+    assert!(lim.check_key(&3).is_ok());
+    assert!(lim.check_key(&3).is_err());
+}
+
+#[test]
+fn evicts_least_recently_used() {
+    let clock = FakeRelativeClock::default();
+    let lim = capped_limiter(&clock, WhenFull::EvictLeastRecentlyUsed);
+
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&2).is_ok());
+    // Denials count as uses:
+    assert!(lim.check_key(&1).is_err());
+
+    assert!(lim.check_key(&3).is_ok());
+    assert_eq!(lim.evicted_keys(), 1);
+    assert!(lim.contains_key(&1));
+    assert!(!lim.contains_key(&2));
+    assert!(lim.check_key(&1).is_err());
+    assert!(lim.check_key(&3).is_err());
+    assert_eq!(lim.len(), 2);
+    assert_eq!(lim.rejected_keys(), 0);
+}
+
+#[test]
+fn evicts_stalest() {
+    let clock = FakeRelativeClock::default();
+    let lim = capped_limiter(&clock, WhenFull::EvictStalest);
+
+    assert!(lim.check_key(&1).is_ok());
+    clock.advance(Duration::from_millis(500));
+    assert!(lim.check_key(&2).is_ok());
+    // Key 1 was used more recently, but its state becomes fresh earlier:
+    assert!(lim.check_key(&1).is_err());
+
+    assert!(lim.check_key(&3).is_ok());
+    assert_eq!(lim.evicted_keys(), 1);
+    assert!(!lim.contains_key(&1));
+    assert!(lim.check_key(&2).is_err());
+    assert!(lim.check_key(&3).is_err());
+
+    // Once key 2 is checked again, key 3's state is the stalest:
+    clock.advance(Duration::from_secs(1));
+    assert!(lim.check_key(&2).is_ok());
+    assert!(lim.check_key(&1).is_ok());
+    assert!(!lim.contains_key(&3));
+    assert!(lim.check_key(&2).is_err());
+}
+
+#[test]
+fn denied_new_keys_take_no_room() {
+    let clock = FakeRelativeClock::default();
+    let lim = capped_limiter(&clock, WhenFull::EvictLeastRecentlyUsed);
+
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&2).is_ok());
+    assert_eq!(
+        lim.check_key_n(&3, nonzero!(2u32)),
+        Err(InsufficientCapacity(1))
+    );
+    assert_eq!(lim.evicted_keys(), 0);
+    assert!(lim.contains_key(&1));
+    assert!(lim.contains_key(&2));
+    assert!(!lim.contains_key(&3));
+}
+
+#[test]
+fn rebases_and_shrinks() {
+    let clock = FakeRelativeClock::default();
+    let mut lim = capped_limiter(&clock, WhenFull::EvictStalest);
+
+    assert!(lim.check_key(&1).is_ok());
+    clock.advance(Duration::from_millis(500));
+    assert!(lim.check_key(&2).is_ok());
+    lim.retain_recent_and_rebase();
+    assert!(lim.check_key(&1).is_err());
+    assert!(lim.check_key(&2).is_err());
+
+    // Rebasing keeps the eviction order:
+    clock.advance(Duration::from_millis(600));
+    assert!(lim.check_key(&3).is_ok());
+    assert!(!lim.contains_key(&1));
+
+    clock.advance(Duration::from_millis(1500));
+    lim.retain_recent();
+    assert_eq!(lim.len(), 1);
+    assert!(lim.contains_key(&3));
+    lim.shrink_to_fit();
+    assert!(lim.check_key(&4).is_ok());
+    assert!(lim.check_key(&5).is_ok());
+    assert!(!lim.contains_key(&3));
+}
+
+#[test]
+fn denied_batches_take_up_no_room() {
+    let clock = FakeRelativeClock::default();
+    let lim = capped_limiter(&clock, WhenFull::Reject).with_fresh_state(FreshState::Empty);
+
+    assert!(lim.check_key(&1).is_ok());
+    assert_eq!(lim.check_keys_all(&[&2, &1]).unwrap_err().0, &1);
+    assert_eq!(lim.len(), 1);
+
+    // Key 2 still starts out with a single cell:
+    assert!(lim.check_key(&2).is_ok());
+    assert!(lim.check_key(&2).is_err());
+}
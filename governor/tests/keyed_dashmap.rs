@@ -1,8 +1,9 @@
 #![cfg(all(feature = "std", feature = "dashmap"))]
 
+use all_asserts::{assert_gt, assert_lt};
 use governor::{
     clock::{Clock, FakeRelativeClock},
-    Quota, RateLimiter,
+    InsufficientCapacity, Quota, RateLimiter,
 };
 use governor::{middleware::NoOpMiddleware, state::keyed::DashMapStateStore};
 use nonzero_ext::nonzero;
@@ -127,6 +128,100 @@ fn actual_threadsafety() {
     }
 }
 
+#[test]
+fn check_key_only_and_consume_key() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::dashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+
+    for key in KEYS {
+        assert_eq!(Ok(()), lb.check_key_only(key));
+        assert_eq!(Ok(Ok(())), lb.check_key_n_only(key, nonzero!(2u32)));
+        assert_eq!(
+            Err(InsufficientCapacity(2)),
+            lb.check_key_n_only(key, nonzero!(3u32))
+        );
+        assert_eq!(Ok(Ok(())), lb.consume_key_n(key, nonzero!(2u32)));
+        assert!(lb.check_key_only(key).is_err());
+        assert!(lb.check_key_n_only(key, nonzero!(1u32)).unwrap().is_err());
+        assert!(lb.consume_key(key).is_err());
+        assert_eq!(
+            Err(InsufficientCapacity(2)),
+            lb.consume_key_n(key, nonzero!(3u32))
+        );
+    }
+
+    // The overdrawn cell needs to be replenished too:
+    clock.advance(Duration::from_millis(500));
+    assert!(lb.check_key_only(&KEYS[0]).is_err());
+    assert!(lb.check_key(&KEYS[0]).is_err());
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(Ok(()), lb.check_key_only(&KEYS[0]));
+    assert_eq!(Ok(()), lb.check_key(&KEYS[0]));
+}
+
+#[test]
+fn consume_key_counts_nonconforming_cells() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::dashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    let ms = Duration::from_millis(1);
+
+    assert_eq!(Ok(Ok(())), lb.consume_key_n(&1, nonzero!(2u32)));
+
+    // Overdraw the key by two cells:
+    let negative = lb.consume_key(&1).unwrap_err();
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_millis(1000)
+    );
+    let negative = lb.consume_key(&1).unwrap_err();
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_millis(1500)
+    );
+
+    // ...which delays the key's next conforming cell, but no other key's:
+    assert_eq!(Ok(()), lb.check_key(&2));
+    clock.advance(ms * 1499);
+    assert!(lb.check_key_only(&1).is_err());
+    assert!(lb.check_key(&1).is_err());
+    clock.advance(ms);
+    assert_eq!(Ok(()), lb.check_key_only(&1));
+    assert_eq!(Ok(()), lb.consume_key(&1));
+    assert!(lb.check_key(&1).is_err());
+}
+
+#[test]
+fn check_key_only_races_with_consume_key() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::dashmap_with_clock(Quota::per_second(nonzero!(20u32)), clock.clone());
+
+    // Peeks at keys that other threads insert and update concurrently never see a state that
+    // allows fewer cells than were consumed so far, and never insert keys themselves:
+    crossbeam::scope(|scope| {
+        for key in 0..8u32 {
+            let lim = &lim;
+            scope.spawn(move |_| {
+                for _ in 0..20 {
+                    assert_eq!(Ok(Ok(())), lim.consume_key_n(&key, nonzero!(1u32)));
+                }
+                assert!(lim.check_key_only(&key).is_err());
+            });
+            scope.spawn(move |_| {
+                for _ in 0..100 {
+                    let _ = lim.check_key_only(&key);
+                    let _ = lim.check_key_n_only(&(key + 100), nonzero!(2u32));
+                }
+            });
+        }
+    })
+    .unwrap();
+
+    assert_eq!(lim.len(), 8);
+    for key in 0..8u32 {
+        assert!(lim.check_key_only(&key).is_err());
+    }
+}
+
 #[test]
 fn dashmap_length() {
     let lim = RateLimiter::dashmap(Quota::per_second(nonzero!(1u32)));
@@ -166,3 +261,235 @@ fn dashmap_shrink_to_fit() {
 
     assert_eq!(lim.len(), 1);
 }
+
+#[test]
+fn dashmap_approx_memory_bytes() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::dashmap_with_clock(Quota::per_second(nonzero!(1u32)), clock.clone());
+    let empty = lim.approx_memory_bytes();
+
+    for key in 0..1000u32 {
+        lim.check_key(&key).unwrap();
+    }
+    let full = lim.approx_memory_bytes();
+    assert_gt!(full, empty + 1000 * std::mem::size_of::<u32>());
+
+    // Dropping all keys and shrinking the store releases the memory again:
+    clock.advance(Duration::from_secs(2));
+    lim.retain_recent();
+    lim.shrink_to_fit();
+    assert_lt!(lim.approx_memory_bytes(), full);
+}
+
+#[test]
+fn check_keys_batch_matches_sequential_checks() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(4u32));
+    let batched = RateLimiter::dashmap_with_clock(quota, clock.clone());
+    let sequential = RateLimiter::dashmap_with_clock(quota, clock.clone());
+    let items = vec![
+        (1u32, nonzero!(2u32)),
+        (2, nonzero!(4u32)),
+        (1, nonzero!(3u32)),
+        (1, nonzero!(1u32)),
+        (2, nonzero!(5u32)),
+        (3, nonzero!(1u32)),
+        (1, nonzero!(1u32)),
+    ];
+
+    for _ in 0..3 {
+        let decisions = batched.check_keys_batch(items.clone());
+        assert_eq!(decisions.len(), items.len());
+        for ((key, n), decision) in items.iter().zip(decisions) {
+            assert_eq!(
+                decision,
+                sequential.check_key_n(key, *n),
+                "key {:?}, n {:?}",
+                key,
+                n
+            );
+        }
+        clock.advance(Duration::from_millis(600));
+    }
+}
+
+#[test]
+fn check_keys_batch_accesses_each_key_once() {
+    use governor::{nanos::Nanos, state::StateStore};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingStore {
+        inner: DashMapStateStore<u32>,
+        accesses: AtomicUsize,
+    }
+
+    impl StateStore for CountingStore {
+        type Key = u32;
+
+        fn measure_and_replace<T, F, E>(&self, key: &u32, f: F) -> Result<T, E>
+        where
+            F: Fn(Option<Nanos>) -> Result<(T, Nanos), E>,
+        {
+            self.accesses.fetch_add(1, Ordering::Relaxed);
+            self.inner.measure_and_replace(key, f)
+        }
+    }
+
+    let lim: RateLimiter<u32, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+        Quota::per_second(nonzero!(100u32)),
+        CountingStore::default(),
+        FakeRelativeClock::default(),
+    );
+    let items = (0..100u32).map(|i| (i % 3, nonzero!(1u32)));
+    assert!(lim
+        .check_keys_batch(items)
+        .into_iter()
+        .all(|decision| decision == Ok(Ok(()))));
+    assert_eq!(lim.into_state_store().accesses.into_inner(), 3);
+}
+
+mod reentrant {
+    use super::*;
+    use governor::{
+        middleware::{RateLimitingMiddleware, StateSnapshot},
+        nanos::Nanos,
+        NotUntil,
+    };
+    use std::cell::Cell;
+    use std::sync::OnceLock;
+
+    type ReentrantLimiter =
+        RateLimiter<u32, DashMapStateStore<u32>, FakeRelativeClock, ChecksOtherKey>;
+
+    static LIMITER: OnceLock<ReentrantLimiter> = OnceLock::new();
+    const OTHER_KEY: u32 = 1000;
+
+    std::thread_local! {
+        static IN_HOOK: Cell<bool> = const { Cell::new(false) };
+        static OTHER_KEY_ALLOWED: Cell<u32> = const { Cell::new(0) };
+    }
+
+    /// Middleware that checks `OTHER_KEY` on the same rate limiter whenever a cell is let
+    /// through.
+    #[derive(Debug)]
+    struct ChecksOtherKey;
+
+    impl RateLimitingMiddleware<Nanos> for ChecksOtherKey {
+        type PositiveOutcome = ();
+        type NegativeOutcome = NotUntil<Nanos>;
+
+        fn allow<K>(_key: &K, _state: impl Into<StateSnapshot>) {
+            if IN_HOOK.with(|h| h.replace(true)) {
+                return;
+            }
+            if LIMITER.get().unwrap().check_key(&OTHER_KEY).is_ok() {
+                OTHER_KEY_ALLOWED.with(|n| n.set(n.get() + 1));
+            }
+            IN_HOOK.with(|h| h.set(false));
+        }
+
+        fn disallow<K: std::fmt::Display>(
+            key: &K,
+            state: impl Into<StateSnapshot>,
+            start_time: Nanos,
+        ) -> Self::NegativeOutcome {
+            NoOpMiddleware::disallow(key, state, start_time)
+        }
+    }
+
+    #[test]
+    fn middleware_can_check_other_keys() {
+        let lim = LIMITER.get_or_init(|| {
+            RateLimiter::dashmap_with_clock(
+                Quota::per_hour(nonzero!(2u32)),
+                FakeRelativeClock::default(),
+            )
+            .with_middleware::<ChecksOtherKey>()
+        });
+        // Some of these keys live in the same shard as `OTHER_KEY`, which must not deadlock:
+        for key in 0..64 {
+            assert_eq!(Ok(()), lim.check_key(&key));
+        }
+        assert_eq!(OTHER_KEY_ALLOWED.with(Cell::get), 2);
+    }
+}
+
+#[test]
+fn dashmap_with_capacity_and_pre_seed() {
+    let lim = RateLimiter::dashmap_with_capacity(Quota::per_second(nonzero!(2u32)), 100);
+    lim.pre_seed(0..100u32);
+    assert_eq!(lim.len(), 100);
+    assert_eq!(lim.check_key_n(&1, nonzero!(2u32)), Ok(Ok(())));
+
+    lim.pre_seed(vec![1]);
+    assert_ne!(lim.check_key(&1), Ok(()));
+
+    // Unused pre-seeded keys are as stale as fresh ones:
+    lim.retain_recent();
+    assert_eq!(lim.len(), 1);
+
+    let keyed = RateLimiter::keyed_with_capacity(Quota::per_second(nonzero!(2u32)), 10);
+    assert_eq!(keyed.check_key(&"a"), Ok(()));
+}
+
+#[test]
+fn queries_dont_insert_keys() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::dashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock);
+
+    assert!(!lim.contains_key(&1));
+    assert_eq!(lim.get_snapshot(&1), None);
+    assert_eq!(lim.check_key_only(&1), Ok(()));
+    assert!(lim.is_empty());
+
+    assert_eq!(lim.check_key(&1), Ok(()));
+    assert!(lim.contains_key(&1));
+    assert_eq!(lim.get_snapshot(&1).unwrap().remaining_burst_capacity(), 1);
+    assert_eq!(lim.len(), 1);
+
+    lim.pre_seed(vec![2]);
+    assert!(lim.contains_key(&2));
+    assert_eq!(lim.get_snapshot(&2), None);
+}
+
+#[test]
+fn checks_keys_all_or_nothing() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::dashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+
+    assert_eq!(lim.check_keys_all(&[&1, &2]), Ok(vec![(), ()]));
+    assert_eq!(lim.check_key(&2), Ok(()));
+
+    let (denied, negative) = lim.check_keys_all(&[&1, &3, &2]).unwrap_err();
+    assert_eq!(denied, &2);
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_millis(500)
+    );
+    assert_eq!(lim.get_snapshot(&1).unwrap().remaining_burst_capacity(), 1);
+    assert_eq!(lim.check_key_n(&3, nonzero!(2u32)), Ok(Ok(())));
+
+    // Keys that occur twice get two cells:
+    assert!(lim.check_keys_all(&[&1, &1]).is_err());
+    assert_eq!(lim.check_keys_all(&[&1]), Ok(vec![()]));
+}
+
+#[test]
+fn custom_hasher() {
+    use governor::state::keyed::SeededHashBuilder;
+
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::dashmap_with_hasher_and_clock(
+        Quota::per_second(nonzero!(1u32)),
+        SeededHashBuilder::new([7; 16]),
+        clock.clone(),
+    );
+    for key in KEYS {
+        assert_eq!(Ok(()), lb.check_key(key));
+        assert_ne!(Ok(()), lb.check_key(key));
+    }
+    clock.advance(Duration::from_secs(2));
+    lb.retain_recent();
+    assert!(lb.is_empty());
+}
@@ -0,0 +1,197 @@
+use governor::{
+    clock::FakeRelativeClock,
+    middleware::NoOpMiddleware,
+    state::keyed::{FixedCapacityStateStore, StoreFull},
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+type FixedLimiter<K> = RateLimiter<
+    K,
+    FixedCapacityStateStore<K>,
+    FakeRelativeClock,
+    NoOpMiddleware<governor::nanos::Nanos>,
+>;
+
+fn limiter<K: std::hash::Hash + Eq + Clone>(
+    capacity: usize,
+    quota: Quota,
+    clock: &FakeRelativeClock,
+) -> FixedLimiter<K> {
+    RateLimiter::new(
+        quota,
+        FixedCapacityStateStore::with_capacity(capacity),
+        clock.clone(),
+    )
+}
+
+#[test]
+fn rejects_too_many() {
+    let clock = FakeRelativeClock::default();
+    let lim = limiter(10, Quota::per_second(nonzero!(2u32)), &clock);
+    let ms = Duration::from_millis(1);
+
+    for key in 0..10u32 {
+        assert_eq!(Ok(()), lim.check_key(&key));
+        assert_eq!(Ok(()), lim.check_key(&key));
+        assert_ne!(Ok(()), lim.check_key(&key));
+    }
+    clock.advance(ms * 1000);
+    for key in 0..10u32 {
+        assert_eq!(Ok(()), lim.check_key(&key));
+    }
+    assert_eq!(lim.len(), 10);
+}
+
+#[test]
+fn full_store() {
+    let clock = FakeRelativeClock::default();
+    let lim = limiter(2, Quota::per_second(nonzero!(1u32)), &clock);
+
+    assert_eq!(Ok(Ok(())), lim.check_key_or_full(&"foo"));
+    assert_eq!(Ok(Ok(())), lim.check_key_or_full(&"bar"));
+    assert!(lim.check_key_or_full(&"foo").unwrap().is_err());
+    assert_eq!(Err(StoreFull), lim.check_key_or_full(&"baz"));
+    assert_eq!(lim.len(), 2);
+
+    // Keys that don't fit share one state:
+    assert_eq!(Ok(()), lim.check_key(&"baz"));
+    assert_ne!(Ok(()), lim.check_key(&"quux"));
+    assert_eq!(lim.len(), 2);
+}
+
+#[test]
+fn check_key_only_does_not_insert() {
+    let clock = FakeRelativeClock::default();
+    let lim = limiter(2, Quota::per_second(nonzero!(1u32)), &clock);
+    assert_eq!(Ok(()), lim.check_key_only(&"foo"));
+    assert!(lim.is_empty());
+}
+
+#[test]
+fn expiration() {
+    let clock = FakeRelativeClock::default();
+    let ms = Duration::from_millis(1);
+    let lim = limiter(64, Quota::per_second(nonzero!(1u32)), &clock);
+
+    for key in 0..32u32 {
+        lim.check_key(&key).unwrap();
+    }
+    clock.advance(ms * 500);
+    for key in 32..64u32 {
+        lim.check_key(&key).unwrap();
+    }
+    assert_eq!(Err(StoreFull), lim.check_key_or_full(&64));
+
+    clock.advance(ms * 1600);
+    lim.retain_recent();
+    assert_eq!(lim.len(), 32);
+
+    // The remaining keys can still be found after the table was rearranged:
+    for key in 32..64u32 {
+        assert_eq!(Ok(Ok(())), lim.check_key_or_full(&key), "key {}", key);
+    }
+    assert_eq!(lim.len(), 32);
+    for key in 64..96u32 {
+        assert_eq!(Ok(Ok(())), lim.check_key_or_full(&key));
+    }
+    assert_eq!(lim.len(), 64);
+
+    clock.advance(ms * 2000);
+    lim.retain_recent();
+    assert!(lim.is_empty());
+}
+
+#[test]
+fn actual_threadsafety() {
+    let clock = FakeRelativeClock::default();
+    let lim = Arc::new(limiter(100, Quota::per_second(nonzero!(20u32)), &clock));
+
+    let mut children = vec![];
+    for _i in 0..20 {
+        let lim = Arc::clone(&lim);
+        children.push(thread::spawn(move || {
+            for key in 0..100u32 {
+                lim.check_key(&key).unwrap();
+            }
+        }));
+    }
+    for child in children {
+        child.join().unwrap();
+    }
+    assert_eq!(lim.len(), 100);
+    for key in 0..100u32 {
+        assert_ne!(Ok(()), lim.check_key(&key));
+    }
+}
+
+#[test]
+fn concurrent_claims_of_one_key() {
+    // With room for just one key, every thread claiming it must find the others' slot instead
+    // of reporting a full store:
+    for _round in 0..100 {
+        let clock = FakeRelativeClock::default();
+        let lim = Arc::new(limiter(1, Quota::per_second(nonzero!(4u32)), &clock));
+        let barrier = Arc::new(std::sync::Barrier::new(8));
+
+        let children: Vec<_> = (0..8)
+            .map(|_| {
+                let lim = Arc::clone(&lim);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    lim.check_key_or_full(&"key")
+                })
+            })
+            .collect();
+        let decisions: Vec<_> = children.into_iter().map(|c| c.join().unwrap()).collect();
+        assert!(decisions.iter().all(Result::is_ok), "{:?}", decisions);
+        assert_eq!(decisions.iter().filter(|d| **d == Ok(Ok(()))).count(), 4);
+        assert_eq!(lim.len(), 1);
+    }
+}
+
+#[test]
+fn approx_memory_bytes_is_fixed() {
+    let clock = FakeRelativeClock::default();
+    let lim = limiter(100, Quota::per_second(nonzero!(1u32)), &clock);
+    let empty = lim.approx_memory_bytes();
+    for key in 0..100u32 {
+        lim.check_key(&key).unwrap();
+    }
+    assert_eq!(lim.approx_memory_bytes(), empty);
+}
+
+/// A key that panics when it's cloned, and that collides with every other key.
+#[derive(PartialEq, Eq)]
+struct Unclonable(u32, bool);
+
+impl std::hash::Hash for Unclonable {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
+
+impl Clone for Unclonable {
+    fn clone(&self) -> Self {
+        assert!(!self.1, "can't clone {}", self.0);
+        Unclonable(self.0, self.1)
+    }
+}
+
+#[test]
+fn panicking_clone_gives_the_slot_back() {
+    let clock = FakeRelativeClock::default();
+    let lim = limiter(1, Quota::per_second(nonzero!(1u32)), &clock);
+
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        lim.check_key(&Unclonable(1, true))
+    }));
+    assert!(panicked.is_err());
+    assert!(lim.is_empty());
+
+    // The next key probes past the slot, and fits into the room the first one reserved:
+    assert_eq!(Ok(Ok(())), lim.check_key_or_full(&Unclonable(2, false)));
+    assert_eq!(lim.len(), 1);
+}
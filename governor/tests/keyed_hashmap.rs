@@ -1,8 +1,13 @@
+use all_asserts::{assert_gt, assert_lt};
 use governor::{
     clock::{Clock, FakeRelativeClock},
-    Quota, RateLimiter,
+    nanos::Nanos,
+    CostError, InsufficientCapacity, Quota, RateLimiter,
+};
+use governor::{
+    middleware::NoOpMiddleware,
+    state::{keyed::HashMapStateStore, FreshState},
 };
-use governor::{middleware::NoOpMiddleware, state::keyed::HashMapStateStore};
 use nonzero_ext::nonzero;
 use std::hash::Hash;
 use std::time::Duration;
@@ -53,6 +58,10 @@ fn retained_keys<T: Clone + Hash + Eq + Copy + Ord>(
     >,
 ) -> Vec<T> {
     let state = limiter.into_state_store();
+    // Only the std mutex's lock can fail:
+    #[cfg(all(feature = "std", not(feature = "parking_lot")))]
+    let map = state.lock().unwrap();
+    #[cfg(not(all(feature = "std", not(feature = "parking_lot"))))]
     let map = state.lock();
     let mut keys: Vec<T> = map.keys().copied().collect();
     keys.sort();
@@ -160,3 +169,487 @@ fn hashmap_shrink_to_fit() {
 
     assert_eq!(lim.len(), 1);
 }
+
+#[test]
+fn hashmap_approx_memory_bytes() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(1u32)), clock.clone());
+    let empty = lim.approx_memory_bytes();
+
+    for key in 0..1000u32 {
+        lim.check_key(&key).unwrap();
+    }
+    let full = lim.approx_memory_bytes();
+    assert_gt!(full, empty + 1000 * std::mem::size_of::<u32>());
+
+    // Dropping all keys and shrinking the store releases the memory again:
+    clock.advance(Duration::from_secs(2));
+    lim.retain_recent();
+    lim.shrink_to_fit();
+    assert_lt!(lim.approx_memory_bytes(), full);
+}
+
+#[test]
+fn bare_check_agrees_with_check() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(5u32));
+    let lim = RateLimiter::hashmap_with_clock(quota, clock.clone());
+    let bare = RateLimiter::hashmap_with_clock(quota, clock.clone());
+    let ms = Duration::from_millis(1);
+
+    for i in 0..100 {
+        for key in KEYS {
+            assert_eq!(
+                lim.check_key(key),
+                bare.check_key_bare(key),
+                "iteration {}",
+                i
+            );
+        }
+        clock.advance(ms * 50);
+    }
+}
+
+#[test]
+fn fast_check_agrees_with_check() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(5u32));
+    let lim = RateLimiter::hashmap_with_clock(quota, clock.clone());
+    let fast = RateLimiter::hashmap_with_clock(quota, clock.clone());
+    let ms = Duration::from_millis(1);
+
+    for i in 0..100 {
+        for key in KEYS {
+            assert_eq!(
+                lim.check_key(key).is_ok(),
+                fast.check_key_fast(key),
+                "iteration {}",
+                i
+            );
+        }
+        clock.advance(ms * 50);
+    }
+}
+
+#[test]
+fn check_key_only_and_consume_key() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+
+    for key in KEYS {
+        assert_eq!(Ok(()), lb.check_key_only(key));
+        assert_eq!(Ok(Ok(())), lb.check_key_n_only(key, nonzero!(2u32)));
+        assert_eq!(Ok(Ok(())), lb.consume_key_n(key, nonzero!(2u32)));
+        assert!(lb.check_key_only(key).is_err());
+        assert!(lb.consume_key(key).is_err());
+        assert_eq!(
+            Err(InsufficientCapacity(2)),
+            lb.consume_key_n(key, nonzero!(3u32))
+        );
+    }
+
+    // The overdrawn cell needs to be replenished too:
+    clock.advance(Duration::from_millis(500));
+    assert!(lb.check_key(&KEYS[0]).is_err());
+    clock.advance(Duration::from_millis(500));
+    assert_eq!(Ok(()), lb.check_key(&KEYS[0]));
+}
+
+#[test]
+fn consume_key_counts_nonconforming_cells() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    let ms = Duration::from_millis(1);
+
+    assert_eq!(Ok(Ok(())), lb.consume_key_n(&1, nonzero!(2u32)));
+
+    // Overdraw the key by two cells:
+    let negative = lb.consume_key(&1).unwrap_err();
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_millis(1000)
+    );
+    let negative = lb.consume_key(&1).unwrap_err();
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_millis(1500)
+    );
+
+    // ...which delays the key's next conforming cell, but no other key's:
+    assert_eq!(Ok(()), lb.check_key(&2));
+    clock.advance(ms * 1499);
+    assert!(lb.check_key_only(&1).is_err());
+    assert!(lb.check_key_n_only(&1, nonzero!(1u32)).unwrap().is_err());
+    assert!(lb.check_key(&1).is_err());
+    clock.advance(ms);
+    assert_eq!(Ok(()), lb.check_key_only(&1));
+    assert_eq!(Ok(()), lb.consume_key(&1));
+    assert!(lb.check_key(&1).is_err());
+}
+
+#[test]
+fn refund_key() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(1u32)), clock.clone());
+
+    // Refunding an unknown key doesn't grant it any extra capacity:
+    lb.refund_key(&KEYS[0]);
+    assert_eq!(Ok(()), lb.check_key(&KEYS[0]));
+    assert!(lb.check_key(&KEYS[0]).is_err());
+    clock.advance(Duration::from_secs(1));
+
+    for key in KEYS {
+        drop(lb.check_key_refundable(key).unwrap());
+        lb.check_key_refundable(key).unwrap().commit();
+        assert!(lb.check_key_refundable(key).is_err());
+        lb.refund_key(key);
+        assert_eq!(Ok(()), lb.check_key(key));
+        assert!(lb.check_key(key).is_err());
+    }
+}
+
+#[test]
+fn next_allowed_times_for_key() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    let ms = Duration::from_millis(1);
+
+    assert_eq!(Ok(()), lb.check_key(&KEYS[0]));
+    assert_eq!(Ok(()), lb.check_key(&KEYS[0]));
+    assert_eq!(
+        lb.next_allowed_times_for_key(&KEYS[0], 2),
+        vec![clock.now() + ms * 500, clock.now() + ms * 1000]
+    );
+    assert_eq!(
+        lb.next_allowed_times_for_key(&KEYS[1], 2),
+        vec![clock.now(), clock.now()]
+    );
+}
+
+#[test]
+fn rebase_start() {
+    let clock = FakeRelativeClock::default();
+    let ms = Duration::from_millis(1);
+    let mut lb = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(1u32)), clock.clone());
+
+    assert_eq!(Ok(()), lb.check_key(&KEYS[0]));
+    clock.advance(ms * 1500);
+    assert_eq!(Ok(()), lb.check_key(&KEYS[1]));
+
+    // The state for the first key lies before the new start and becomes fresh:
+    lb.rebase_start(clock.now());
+    assert_eq!(lb.start(), clock.now());
+    assert!(lb.check_key(&KEYS[1]).is_err());
+    assert_eq!(Ok(()), lb.check_key(&KEYS[0]));
+}
+
+#[test]
+fn retain_recent_and_rebase() {
+    let clock = FakeRelativeClock::default();
+    let ms = Duration::from_millis(1);
+    let mut lb = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(1u32)), clock.clone());
+    let start = lb.start();
+
+    assert_eq!(Ok(()), lb.check_key(&KEYS[0]));
+    clock.advance(ms * 10_000);
+    assert_eq!(Ok(()), lb.check_key(&KEYS[1]));
+    clock.advance(ms * 500);
+
+    lb.retain_recent_and_rebase();
+    assert_eq!(lb.len(), 1);
+    // The start moves to one replenishment interval before now:
+    assert_eq!(lb.start(), start + Nanos::saturating_from(ms * 9_500));
+    assert!(lb.check_key(&KEYS[1]).is_err());
+    clock.advance(ms * 500);
+    assert_eq!(Ok(()), lb.check_key(&KEYS[1]));
+}
+
+#[test]
+fn retain_recent_keeps_held_bursts() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(2u32)).with_burst_recharge_delay(Duration::from_secs(5));
+    let mut lb = RateLimiter::hashmap_with_clock(quota, clock.clone());
+
+    assert_eq!(Ok(Ok(())), lb.check_key_n(&KEYS[0], nonzero!(2u32)));
+    clock.advance(Duration::from_secs(2));
+    lb.retain_recent_and_rebase();
+    assert_eq!(lb.len(), 1);
+    assert_eq!(Ok(()), lb.check_key(&KEYS[0]));
+    assert!(lb.check_key(&KEYS[0]).is_err());
+
+    // Once the burst has recharged, the key's state is fresh:
+    clock.advance(Duration::from_secs(7));
+    lb.retain_recent();
+    assert_eq!(lb.len(), 0);
+}
+
+#[test]
+fn hashmap_with_capacity_and_pre_seed() {
+    let lim = RateLimiter::hashmap_with_capacity(Quota::per_second(nonzero!(2u32)), 100);
+    let presized = lim.approx_memory_bytes();
+
+    lim.pre_seed(0..100u32);
+    assert_eq!(lim.len(), 100);
+    assert_eq!(lim.approx_memory_bytes(), presized);
+
+    // Pre-seeded keys have their whole burst capacity:
+    assert_eq!(lim.check_key_n(&1, nonzero!(2u32)), Ok(Ok(())));
+    assert_ne!(lim.check_key(&1), Ok(()));
+
+    // Pre-seeding doesn't reset keys that are already in use:
+    lim.pre_seed(vec![1]);
+    assert_ne!(lim.check_key(&1), Ok(()));
+    assert_eq!(lim.len(), 100);
+}
+
+#[test]
+fn try_check_key_reports_all_outcomes() {
+    use governor::state::TryCheck;
+
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+
+    assert_eq!(lim.try_check_key(&1), TryCheck::Allowed(()));
+    assert_eq!(
+        lim.try_check_key_n(&1, nonzero!(3u32)),
+        TryCheck::InsufficientCapacity { max: 2 }
+    );
+    assert_eq!(
+        lim.try_check_key_n(&1, nonzero!(2u32)),
+        TryCheck::Limited {
+            retry_after: Duration::from_millis(500)
+        }
+    );
+    assert_eq!(
+        lim.try_check_key_n(&2, nonzero!(2u32)),
+        TryCheck::Allowed(())
+    );
+    assert_eq!(
+        lim.try_check_key(&2).retry_after(),
+        Some(Duration::from_millis(500))
+    );
+}
+
+#[test]
+fn queries_dont_insert_keys() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+
+    assert!(!lim.contains_key(&1));
+    assert_eq!(lim.get_snapshot(&1), None);
+    assert_eq!(lim.check_key_only(&1), Ok(()));
+    assert!(lim.is_empty());
+
+    assert_eq!(lim.check_key(&1), Ok(()));
+    assert!(lim.contains_key(&1));
+    let snapshot = lim.get_snapshot(&1).unwrap();
+    assert_eq!(snapshot.remaining_burst_capacity(), 1);
+    assert_eq!(snapshot.tat(), Nanos::from(500_000_000));
+
+    clock.advance(Duration::from_millis(250));
+    let snapshot = lim.get_snapshot(&1).unwrap();
+    assert_eq!(snapshot.time_of_measurement(), Nanos::from(250_000_000));
+    assert_eq!(lim.len(), 1);
+}
+
+#[test]
+fn checks_keys_all_or_nothing() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+
+    assert_eq!(lim.check_keys_all(&[&1, &2]), Ok(vec![(), ()]));
+    assert_eq!(lim.check_key(&2), Ok(()));
+
+    // Key 2 is used up, so neither key gets its cell, and fresh keys get no state:
+    let (denied, negative) = lim.check_keys_all(&[&1, &3, &2]).unwrap_err();
+    assert_eq!(denied, &2);
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_millis(500)
+    );
+    assert!(!lim.contains_key(&3));
+    assert_eq!(lim.get_snapshot(&1).unwrap().remaining_burst_capacity(), 1);
+
+    // Keys that occur twice get two cells:
+    assert!(lim.check_keys_all(&[&1, &1]).is_err());
+    assert_eq!(lim.check_keys_all(&[&3, &3]), Ok(vec![(), ()]));
+}
+
+#[test]
+fn checks_keys_all_reports_most_restrictive_key() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+
+    lim.check_key(&1).unwrap();
+    lim.check_key(&1).unwrap();
+    clock.advance(Duration::from_millis(100));
+    lim.check_key(&2).unwrap();
+    lim.check_key(&2).unwrap();
+
+    let (denied, negative) = lim.check_keys_all(&[&1, &2]).unwrap_err();
+    assert_eq!(denied, &2);
+    assert_eq!(
+        negative.wait_time_from(clock.now()),
+        Duration::from_millis(500)
+    );
+}
+
+#[test]
+fn idle_keys() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    clock.advance(Duration::from_secs(2));
+    lim.check_key(&1).unwrap();
+    clock.advance(Duration::from_secs(1));
+
+    assert_eq!(lim.key_idle_for(&1), Duration::from_secs(1));
+    assert_eq!(lim.key_idle_for(&2), Duration::from_secs(3));
+    assert!(lim.is_key_idle(&2, Duration::from_secs(3)));
+    assert!(!lim.is_key_idle(&1, Duration::from_secs(3)));
+    assert!(!lim.contains_key(&2));
+}
+
+#[test]
+fn seeded_hasher() {
+    use governor::state::keyed::SeededHashBuilder;
+    use std::hash::BuildHasher;
+
+    let seeded = SeededHashBuilder::new([7; 16]);
+    assert_eq!(seeded.hash_one("key"), seeded.clone().hash_one("key"));
+    assert_ne!(
+        seeded.hash_one("key"),
+        SeededHashBuilder::new([8; 16]).hash_one("key")
+    );
+    assert_eq!(format!("{:?}", seeded), "SeededHashBuilder { .. }");
+
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::hashmap_with_hasher_and_clock(
+        Quota::per_second(nonzero!(1u32)),
+        seeded,
+        clock,
+    );
+    for key in KEYS {
+        assert_eq!(Ok(()), lb.check_key(key));
+        assert_ne!(Ok(()), lb.check_key(key));
+    }
+    assert_eq!(lb.len(), 2);
+}
+
+#[test]
+fn check_key_cost() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(1u32)), clock);
+    for _ in 0..4 {
+        assert_eq!(Ok(Ok(())), lim.check_key_cost(&1u32, 0.25));
+    }
+    assert!(lim.check_key_cost(&1u32, 0.25).unwrap().is_err());
+    assert_eq!(Ok(Ok(())), lim.check_key_cost(&2u32, 0.75));
+    assert_eq!(
+        Err(CostError::InsufficientCapacity(InsufficientCapacity(1))),
+        lim.check_key_cost(&2u32, 1.5)
+    );
+    assert_eq!(Err(CostError::InvalidCost), lim.check_key_cost(&3u32, -0.5));
+    assert_eq!(
+        Err(CostError::InvalidCost),
+        lim.check_key_cost(&3u32, f64::NAN)
+    );
+    assert_eq!(Ok(Ok(())), lim.check_key_cost(&3u32, 1.0));
+}
+
+#[test]
+fn available_cells_per_key() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    assert_eq!(lim.key_available_cells_f64(&1u32), 2.0);
+    lim.check_key_n(&1u32, nonzero!(2u32)).unwrap().unwrap();
+    assert_eq!(lim.key_available_cells_f64(&1u32), 0.0);
+    assert_eq!(lim.key_available_cells_f64(&2u32), 2.0);
+
+    clock.advance(Duration::from_millis(125));
+    assert_eq!(lim.key_available_cells_f64(&1u32), 0.25);
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(lim.key_available_cells_f64(&1u32), 2.0);
+}
+
+#[test]
+fn check_key_n_with_grace() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock);
+    let grace = Duration::from_millis(500);
+    assert_eq!(Ok(Ok(())), lim.check_key_n(&1u32, nonzero!(2u32)));
+    assert!(lim.check_key_n(&1u32, nonzero!(1u32)).unwrap().is_err());
+    assert_eq!(
+        Ok(Ok(())),
+        lim.check_key_n_with_grace(&1u32, nonzero!(1u32), grace)
+    );
+    assert!(lim
+        .check_key_n_with_grace(&1u32, nonzero!(1u32), grace)
+        .unwrap()
+        .is_err());
+    assert_eq!(
+        Ok(Ok(())),
+        lim.check_key_n_with_grace(&2u32, nonzero!(2u32), grace)
+    );
+}
+
+#[test]
+fn fresh_keys_start_empty() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(4u32)), clock.clone())
+        .with_fresh_state(FreshState::Empty);
+    assert_eq!(lim.fresh_state(), FreshState::Empty);
+    assert_eq!(lim.key_available_cells_f64(&1u32), 1.0);
+
+    assert_eq!(Ok(()), lim.check_key(&1u32));
+    let denied = lim.check_key(&1u32).unwrap_err();
+    assert_eq!(
+        denied.wait_time_from(clock.now()),
+        Duration::from_millis(250)
+    );
+    assert_eq!(
+        lim.check_key_n(&2u32, nonzero!(2u32))
+            .unwrap()
+            .unwrap_err()
+            .wait_time_from(clock.now()),
+        Duration::from_millis(250)
+    );
+
+    // Established keys accumulate their burst capacity while idle:
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(Ok(Ok(())), lim.check_key_n(&1u32, nonzero!(4u32)));
+    assert_eq!(Ok(()), lim.check_key(&3u32));
+    assert!(lim.check_key(&3u32).is_err());
+}
+
+#[test]
+fn fresh_keys_start_full_by_default() {
+    let lim = RateLimiter::hashmap_with_clock(
+        Quota::per_second(nonzero!(4u32)),
+        FakeRelativeClock::default(),
+    );
+    assert_eq!(lim.fresh_state(), FreshState::Full);
+    assert_eq!(Ok(Ok(())), lim.check_key_n(&1u32, nonzero!(4u32)));
+}
+
+#[cfg(feature = "spin")]
+#[test]
+fn spin_hashmap_rejects_too_many() {
+    use governor::state::keyed::SpinHashMapStateStore;
+
+    let clock = FakeRelativeClock::default();
+    let lim: RateLimiter<u32, SpinHashMapStateStore<u32>, _, NoOpMiddleware<Nanos>> =
+        RateLimiter::new(
+            Quota::per_second(nonzero!(1u32)),
+            SpinHashMapStateStore::default(),
+            clock.clone(),
+        );
+    for key in KEYS {
+        assert_eq!(Ok(()), lim.check_key(key));
+        assert_ne!(Ok(()), lim.check_key(key));
+    }
+    assert_eq!(lim.len(), 2);
+
+    // The spinlock is the store's type, whatever the crate's other features:
+    let map = lim.into_state_store().into_inner();
+    assert!(map.contains_key(&1));
+}
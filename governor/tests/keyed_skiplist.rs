@@ -0,0 +1,79 @@
+#![cfg(all(feature = "std", feature = "skiplist"))]
+
+use governor::{
+    clock::{Clock, FakeRelativeClock},
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::time::Duration;
+
+const KEYS: &[u32] = &[1u32, 2u32];
+
+#[test]
+fn rejects_too_many() {
+    let clock = FakeRelativeClock::default();
+    let lb = RateLimiter::skiplist_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    let ms = Duration::from_millis(1);
+
+    for key in KEYS {
+        // use up our burst capacity (2 in the first second):
+        assert_eq!(Ok(()), lb.check_key(key), "Now: {:?}", clock.now());
+        clock.advance(ms);
+        assert_eq!(Ok(()), lb.check_key(key), "Now: {:?}", clock.now());
+
+        clock.advance(ms);
+        assert_ne!(Ok(()), lb.check_key(key), "Now: {:?}", clock.now());
+
+        // should be ok again in 1s:
+        clock.advance(ms * 1000);
+        assert_eq!(Ok(()), lb.check_key(key), "Now: {:?}", clock.now());
+    }
+}
+
+#[test]
+fn skiplist_retain_recent() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::skiplist_with_clock(Quota::per_second(nonzero!(20u32)), clock.clone());
+    let ms = Duration::from_millis(1);
+    assert!(lim.is_empty());
+
+    assert_eq!(lim.check_key_n(&"long-lived", nonzero!(10_u32)), Ok(Ok(())));
+    assert_eq!(lim.check_key(&"short-lived"), Ok(()));
+    assert_eq!(lim.len(), 2);
+
+    // Move the clock forward far enough that the short-lived key gets dropped:
+    clock.advance(ms * 300);
+    lim.retain_recent();
+    assert_eq!(lim.len(), 1);
+    assert!(lim.check_key_only(&"long-lived").is_ok());
+}
+
+#[test]
+fn concurrent_checks_admit_burst() {
+    let lim = RateLimiter::skiplist_with_clock(
+        Quota::per_hour(nonzero!(20u32)),
+        FakeRelativeClock::default(),
+    );
+
+    // Threads racing to insert and update the same keys admit exactly each key's burst:
+    let admitted = crossbeam::scope(|scope| {
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let lim = &lim;
+                scope.spawn(move |_| {
+                    (0..100)
+                        .filter(|i| lim.check_key(&(i % 4u32)).is_ok())
+                        .count()
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|t| t.join().unwrap())
+            .sum::<usize>()
+    })
+    .unwrap();
+
+    assert_eq!(admitted, 4 * 20);
+    assert_eq!(lim.len(), 4);
+}
@@ -0,0 +1,109 @@
+#![cfg(all(loom, feature = "std"))]
+
+//! Model-checks the keyed state stores with every interleaving of a few threads.
+//!
+//! Run with `RUSTFLAGS="--cfg loom" cargo test --release --test loom`. Loom can only switch
+//! between threads at its own synchronization primitives, which the `HashMapStateStore` uses
+//! under the `loom` cfg. `DashMap`'s locks park threads where loom can't see it, so the tests
+//! of the `DashMapStateStore` only use keys that are already in the map, which it only ever
+//! takes shared locks for.
+
+use governor::{clock::FakeRelativeClock, Quota, RateLimiter};
+use loom::sync::Arc;
+use loom::thread;
+use nonzero_ext::nonzero;
+
+/// Returns how many of the threads running `f` were let through.
+fn admitted<F>(threads: usize, f: F) -> usize
+where
+    F: Fn() -> bool + Send + Sync + 'static,
+{
+    let f = Arc::new(f);
+    let threads: Vec<_> = (0..threads)
+        .map(|_| {
+            let f = Arc::clone(&f);
+            thread::spawn(move || f())
+        })
+        .collect();
+    threads
+        .into_iter()
+        .map(|t| t.join().unwrap())
+        .filter(|admitted| *admitted)
+        .count()
+}
+
+#[test]
+fn hashmap_lets_burst_through() {
+    loom::model(|| {
+        let lim = Arc::new(RateLimiter::hashmap_with_clock(
+            Quota::per_second(nonzero!(1u32)),
+            FakeRelativeClock::default(),
+        ));
+        let lim2 = Arc::clone(&lim);
+        assert_eq!(admitted(2, move || lim2.check_key(&1u32).is_ok()), 1);
+        assert!(lim.check_key(&1u32).is_err());
+    });
+}
+
+#[test]
+fn hashmap_checks_keys_atomically() {
+    loom::model(|| {
+        let lim = Arc::new(RateLimiter::hashmap_with_clock(
+            Quota::per_second(nonzero!(1u32)),
+            FakeRelativeClock::default(),
+        ));
+        let all = {
+            let lim = Arc::clone(&lim);
+            thread::spawn(move || lim.check_keys_all(&[&1u32, &2u32]).is_ok())
+        };
+        let single = {
+            let lim = Arc::clone(&lim);
+            thread::spawn(move || lim.check_key(&2u32).is_ok())
+        };
+        let (all, single) = (all.join().unwrap(), single.join().unwrap());
+        // Key 2 lets only one cell through; if that wasn't the one for both keys, key 1 kept
+        // its capacity:
+        assert!(all != single);
+        assert_eq!(lim.check_key(&1u32).is_ok(), !all);
+    });
+}
+
+#[cfg(feature = "dashmap")]
+#[test]
+fn dashmap_lets_burst_through() {
+    loom::model(|| {
+        let lim = Arc::new(RateLimiter::dashmap_with_clock(
+            Quota::per_second(nonzero!(1u32)),
+            FakeRelativeClock::default(),
+        ));
+        lim.pre_seed([1u32]);
+        let lim2 = Arc::clone(&lim);
+        assert_eq!(admitted(2, move || lim2.check_key(&1u32).is_ok()), 1);
+        assert!(lim.check_key(&1u32).is_err());
+    });
+}
+
+/// The DashMap store checks several keys by updating them one at a time, and restoring the
+/// earlier ones if a later key rate-limits the cell.
+#[cfg(feature = "dashmap")]
+#[test]
+fn dashmap_restores_earlier_keys() {
+    loom::model(|| {
+        let lim = Arc::new(RateLimiter::dashmap_with_clock(
+            Quota::per_second(nonzero!(1u32)),
+            FakeRelativeClock::default(),
+        ));
+        lim.pre_seed([1u32, 2u32]);
+        let all = {
+            let lim = Arc::clone(&lim);
+            thread::spawn(move || lim.check_keys_all(&[&1u32, &2u32]).is_ok())
+        };
+        let single = {
+            let lim = Arc::clone(&lim);
+            thread::spawn(move || lim.check_key(&2u32).is_ok())
+        };
+        let (all, single) = (all.join().unwrap(), single.join().unwrap());
+        assert!(all != single);
+        assert_eq!(lim.check_key(&1u32).is_ok(), !all);
+    });
+}
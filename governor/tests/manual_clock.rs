@@ -0,0 +1,73 @@
+use governor::{
+    clock::{Clock, ManualClock},
+    nanos::Nanos,
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::time::Duration;
+
+/// A `Date.now()` time, in milliseconds since the UNIX epoch.
+const EPOCH_MS: f64 = 1_700_000_000_000.0;
+
+#[test]
+fn only_moves_forward() {
+    let clock = ManualClock::default();
+    assert_eq!(clock.now(), Nanos::from(0));
+    assert_eq!(clock.set_now(Nanos::from(10)), Nanos::from(10));
+    assert_eq!(clock.set_now(Nanos::from(5)), Nanos::from(10));
+    assert_eq!(clock.now(), Nanos::from(10));
+
+    // Clones share their time:
+    let clone = clock.clone();
+    clone.set_now(Nanos::from(20));
+    assert_eq!(clock.now(), Nanos::from(20));
+}
+
+#[test]
+fn sets_time_from_millis() {
+    let clock = ManualClock::new(Nanos::from(1_000));
+    assert_eq!(clock.set_now_millis(-1.0), Nanos::from(1_000));
+    assert_eq!(clock.set_now_millis(f64::NAN), Nanos::from(1_000));
+    assert_eq!(
+        clock.set_now_millis(EPOCH_MS + 0.5),
+        Nanos::from(1_700_000_000_000_500_000)
+    );
+}
+
+#[test]
+fn limiter_constructed_before_first_request() {
+    // The clock shows 0 until the first request sets it, so the limiter starts measuring at 0;
+    // the first request's time since the epoch must not count as one long burst of history:
+    let clock = ManualClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+
+    clock.set_now_millis(EPOCH_MS);
+    assert_eq!(Ok(()), lim.check_key(&"alice"));
+    assert_eq!(Ok(()), lim.check_key(&"alice"));
+    let denied = lim.check_key(&"alice").unwrap_err();
+    assert_eq!(
+        denied.wait_time_from(clock.now()),
+        Duration::from_millis(500)
+    );
+
+    clock.set_now_millis(EPOCH_MS + 500.0);
+    assert_eq!(Ok(()), lim.check_key(&"alice"));
+    assert_eq!(Ok(()), lim.check_key(&"bob"));
+}
+
+#[test]
+fn clock_adjustments_do_not_replenish() {
+    let clock = ManualClock::default();
+    clock.set_now_millis(EPOCH_MS);
+    let lim = RateLimiter::direct_with_clock(Quota::per_minute(nonzero!(1u32)), clock.clone());
+    assert_eq!(Ok(()), lim.check());
+
+    // The host's clock jumps back a minute and then returns to where it was: no capacity
+    // replenishes in the meantime.
+    clock.set_now_millis(EPOCH_MS - 60_000.0);
+    assert!(lim.check().is_err());
+    clock.set_now_millis(EPOCH_MS + 1_000.0);
+    assert!(lim.check().is_err());
+    clock.set_now_millis(EPOCH_MS + 60_000.0);
+    assert_eq!(Ok(()), lim.check());
+}
@@ -95,6 +95,60 @@ fn state_snapshot_tracks_quota_accurately() {
     assert_eq!(lim.check().map_err(|_| ()), Err(()), "should rate limit");
 }
 
+#[test]
+fn state_snapshot_projects_capacity() {
+    use governor::nanos::Nanos;
+
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(4u32)), clock.clone())
+        .with_middleware::<StateInformationMiddleware>();
+    let snapshot = lim.check_n(nonzero!(3u32)).unwrap().unwrap();
+    let ms = |ms: u64| Nanos::from(ms * 1_000_000);
+
+    assert_eq!(snapshot.capacity_at(ms(0)), 1);
+    assert_eq!(snapshot.capacity_at(ms(249)), 1);
+    assert_eq!(snapshot.capacity_at(ms(250)), 2);
+    assert_eq!(snapshot.capacity_at(ms(750)), 4);
+    assert_eq!(snapshot.capacity_at(ms(10_000)), 4);
+
+    assert_eq!(snapshot.time_with_capacity(nonzero!(1u32)), Some(ms(0)));
+    assert_eq!(snapshot.time_with_capacity(nonzero!(3u32)), Some(ms(500)));
+    assert_eq!(snapshot.time_with_capacity(nonzero!(5u32)), None);
+
+    // Times before the measurement count as the time of measurement:
+    clock.advance(std::time::Duration::from_secs(1));
+    let snapshot = lim.check().unwrap();
+    assert_eq!(snapshot.capacity_at(ms(0)), 3);
+    assert_eq!(snapshot.time_with_capacity(nonzero!(3u32)), Some(ms(1000)));
+}
+
+#[test]
+fn state_snapshot_quota_keeps_the_cooldown() {
+    use std::time::Duration;
+
+    let quota = Quota::per_second(nonzero!(2u32)).with_cooldown(Duration::from_secs(3));
+    let lim = RateLimiter::direct_with_clock(quota, FakeRelativeClock::default())
+        .with_middleware::<StateInformationMiddleware>();
+
+    assert_eq!(lim.check().unwrap().quota(), quota);
+    assert_eq!(lim.check().unwrap().quota(), quota);
+    assert_eq!(lim.check().unwrap_err().quota(), quota);
+}
+
+#[test]
+fn state_snapshot_quota_keeps_the_burst_recharge_delay() {
+    use std::time::Duration;
+
+    let quota = Quota::per_second(nonzero!(2u32))
+        .allow_burst(nonzero!(3u32))
+        .with_burst_recharge_delay(Duration::from_secs(10));
+    let lim = RateLimiter::direct_with_clock(quota, FakeRelativeClock::default())
+        .with_middleware::<StateInformationMiddleware>();
+
+    assert_eq!(lim.check_n(nonzero!(3u32)).unwrap().unwrap().quota(), quota);
+    assert_eq!(lim.check().unwrap_err().quota(), quota);
+}
+
 #[test]
 #[cfg(feature = "std")]
 fn state_snapshot_tracks_quota_accurately_with_real_clock() {
@@ -112,3 +166,117 @@ fn state_snapshot_tracks_quota_accurately_with_real_clock() {
     assert_eq!(lim.check().unwrap().remaining_burst_capacity(), 0);
     assert_eq!(lim.check().map_err(|_| ()), Err(()), "should rate limit");
 }
+
+#[test]
+fn state_information_check_only() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(4u32)), clock.clone())
+        .with_middleware::<StateInformationMiddleware>();
+    assert_eq!(
+        Ok(3),
+        lim.check_only()
+            .map(|outcome| outcome.remaining_burst_capacity())
+    );
+    assert_eq!(
+        Ok(3),
+        lim.check_only()
+            .map(|outcome| outcome.remaining_burst_capacity())
+    );
+    assert_eq!(
+        Ok(1),
+        lim.consume_n(nonzero!(3u32))
+            .unwrap()
+            .map(|outcome| outcome.remaining_burst_capacity())
+    );
+    assert_eq!(
+        Ok(0),
+        lim.check_only()
+            .map(|outcome| outcome.remaining_burst_capacity())
+    );
+}
+
+#[test]
+fn state_snapshot_raw_parameters() {
+    use governor::nanos::Nanos;
+    use std::time::Duration;
+
+    let clock = FakeRelativeClock::default();
+    // 3 cells per second don't divide a second evenly:
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(3u32)), clock.clone())
+        .with_middleware::<StateInformationMiddleware>();
+
+    clock.advance(Duration::from_millis(10));
+    let snapshot = lim.check().unwrap();
+    assert_eq!(snapshot.t(), Nanos::from(333_333_333));
+    assert_eq!(snapshot.tau(), Nanos::from(666_666_666));
+    assert_eq!(snapshot.time_of_measurement(), Nanos::from(10_000_000));
+    assert_eq!(snapshot.tat(), Nanos::from(343_333_333));
+}
+
+#[test]
+fn http_headers() {
+    use governor::middleware::{HttpHeadersMiddleware, RateLimitHeaders};
+    use std::time::Duration;
+
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(3u32)), clock.clone())
+        .with_middleware::<HttpHeadersMiddleware>();
+
+    let headers = lim.check().unwrap();
+    assert_eq!(headers.limit(), 3);
+    assert_eq!(headers.remaining(), 2);
+    assert_eq!(headers.reset(), Duration::from_nanos(333_333_333));
+    assert_eq!(headers.retry_after(), None);
+    // Times are rounded up to whole seconds:
+    assert_eq!(headers.reset_header(), "1");
+    assert_eq!(headers.to_headers().len(), 3);
+
+    assert!(lim.check_n(nonzero!(2u32)).unwrap().is_ok());
+    clock.advance(Duration::from_millis(100));
+    let headers = lim.check().unwrap_err();
+    assert_eq!(headers.remaining(), 0);
+    assert_eq!(
+        headers.retry_after(),
+        Some(Duration::from_nanos(333_333_333 - 100_000_000))
+    );
+    assert_eq!(
+        headers.to_headers(),
+        [
+            (RateLimitHeaders::LIMIT, "3".to_string()),
+            (RateLimitHeaders::REMAINING, "0".to_string()),
+            (RateLimitHeaders::RESET, "1".to_string()),
+            (RateLimitHeaders::RETRY_AFTER, "1".to_string()),
+        ]
+    );
+
+    // Once replenished, checking without updating reports the headers for letting a cell
+    // through:
+    clock.advance(Duration::from_secs(1));
+    let headers = lim.check_only().unwrap();
+    assert_eq!(headers.remaining(), 2);
+    assert_eq!(headers.reset(), Duration::from_nanos(333_333_333));
+}
+
+#[test]
+fn state_information_while_disabled() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(4u32)), clock)
+        .with_middleware::<StateInformationMiddleware>();
+    lim.set_enabled(false);
+    for _ in 0..10 {
+        assert_eq!(
+            Ok(4),
+            lim.check_key(&"a")
+                .map(|outcome| outcome.remaining_burst_capacity())
+        );
+    }
+    let outcomes = lim.check_keys_all(&[&"a", &"b"]).unwrap();
+    assert!(outcomes
+        .iter()
+        .all(|outcome| outcome.remaining_burst_capacity() == 4));
+    assert!(lim
+        .check_keys_batch(vec![("a", nonzero!(10u32)), ("b", nonzero!(1u32))])
+        .iter()
+        .all(|decision| matches!(decision, Ok(Ok(_)))));
+    assert!(lim.get_snapshot(&"a").is_none());
+}
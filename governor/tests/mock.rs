@@ -0,0 +1,70 @@
+use governor::{
+    clock::{Clock, FakeRelativeClock},
+    mock::{AlwaysAllowLimiter, AlwaysAllowStore, AlwaysDenyLimiter, AlwaysDenyStore},
+    state::NotKeyed,
+    InsufficientCapacity, Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::time::Duration;
+
+#[test]
+fn always_allows() {
+    let clock = FakeRelativeClock::default();
+    let lim: AlwaysAllowLimiter<NotKeyed, FakeRelativeClock> = RateLimiter::new(
+        Quota::per_second(nonzero!(2u32)),
+        AlwaysAllowStore::new(),
+        clock,
+    );
+    for _ in 0..10 {
+        assert_eq!(Ok(()), lim.check());
+        assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(2u32)));
+    }
+    assert_eq!(Ok(()), lim.check_only());
+    assert_eq!(Err(InsufficientCapacity(2)), lim.check_n(nonzero!(3u32)));
+}
+
+#[test]
+fn always_denies() {
+    let clock = FakeRelativeClock::default();
+    clock.advance(Duration::from_secs(30));
+    let lim: AlwaysDenyLimiter<NotKeyed, FakeRelativeClock> = RateLimiter::new(
+        Quota::per_second(nonzero!(5u32)),
+        AlwaysDenyStore::new(Duration::from_millis(1500)),
+        clock.clone(),
+    );
+    for _ in 0..3 {
+        clock.advance(Duration::from_secs(7));
+        let now = clock.now();
+        let wait = lim.check().unwrap_err().wait_time_from(now);
+        assert_eq!(wait, Duration::from_millis(1500));
+        let wait = lim.check_n(nonzero!(3u32)).unwrap().unwrap_err();
+        assert_eq!(wait.wait_time_from(now), Duration::from_millis(1500));
+        let wait = lim.check_only().unwrap_err();
+        assert_eq!(wait.wait_time_from(now), Duration::from_millis(1500));
+    }
+}
+
+#[test]
+fn keyed_mocks() {
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(1u32));
+    let allow: AlwaysAllowLimiter<u32, FakeRelativeClock> =
+        RateLimiter::new(quota, AlwaysAllowStore::default(), clock.clone());
+    let deny: AlwaysDenyLimiter<u32, FakeRelativeClock> =
+        RateLimiter::new(quota, AlwaysDenyStore::new(Duration::ZERO), clock.clone());
+
+    assert!(allow.check_key(&1).is_ok());
+    assert!(allow.check_key(&1).is_ok());
+    assert!(allow.check_keys_all(&[&1, &2]).is_ok());
+
+    let not_until = deny.check_key(&1).unwrap_err();
+    assert_eq!(
+        not_until.wait_time_from(clock.now()),
+        Duration::from_nanos(1)
+    );
+    assert_eq!(deny.check_keys_all(&[&1, &2]).unwrap_err().0, &1);
+    assert_eq!(
+        format!("{:?}", deny.into_state_store()),
+        "AlwaysDenyStore { retry_after: 1ns }"
+    );
+}
@@ -0,0 +1,74 @@
+//! Checks that the hot paths of direct rate limiters can't panic.
+//!
+//! Each checked expression runs with a guard whose destructor calls a function that doesn't
+//! exist. The destructor only runs if the expression unwinds, so the test only links if the
+//! optimizer could prove that it can't (the technique of the
+//! [`no-panic`](https://docs.rs/no-panic) crate). That takes optimizations, so the checks only
+//! run in release builds. The optimizer also has to see that the rate limiter's code can't unwind,
+//! which it doesn't always across codegen units, so they run with a single one:
+//! `CARGO_PROFILE_RELEASE_CODEGEN_UNITS=1 cargo test --release --test no_panic`.
+
+#![cfg(all(feature = "std", not(debug_assertions)))]
+
+use governor::{clock, middleware::NoOpMiddleware, nanos::Nanos, Quota, RateLimiter};
+use nonzero_ext::nonzero;
+use std::cell::Cell;
+use std::rc::Rc;
+
+struct MayPanic;
+
+impl Drop for MayPanic {
+    fn drop(&mut self) {
+        extern "C" {
+            /// Undefined, so that linking fails if a destructor calling it is reachable.
+            fn governor_hot_path_may_panic() -> !;
+        }
+        unsafe { governor_hot_path_may_panic() }
+    }
+}
+
+macro_rules! no_panic {
+    ($e:expr) => {{
+        let guard = MayPanic;
+        let result = $e;
+        std::mem::forget(guard);
+        result
+    }};
+}
+
+/// A clock that doesn't call into anything that could panic.
+#[derive(Clone, Default)]
+struct CellClock(Rc<Cell<u64>>);
+
+impl clock::Clock for CellClock {
+    type Instant = Nanos;
+
+    fn now(&self) -> Nanos {
+        Nanos::new(self.0.get())
+    }
+}
+
+#[test]
+fn direct_checks() {
+    let clock = CellClock::default();
+    let lim: RateLimiter<_, _, _, NoOpMiddleware<Nanos>> =
+        RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    assert!(no_panic!(lim.check_only()).is_ok());
+    assert!(no_panic!(lim.check()).is_ok());
+    assert!(no_panic!(lim.check_n(nonzero!(1u32))).is_ok());
+    assert!(no_panic!(lim.check()).is_err());
+    assert!(no_panic!(lim.check_n(nonzero!(3u32))).is_err());
+    assert!(no_panic!(lim.check_n_only(nonzero!(1u32)))
+        .unwrap()
+        .is_err());
+    assert!(!no_panic!(lim.check_fast()));
+    assert!(no_panic!(lim.check_bare()).is_err());
+    assert!(no_panic!(lim.consume()).is_err());
+    assert!(no_panic!(lim.check_cost(0.5)).unwrap().is_err());
+    assert!(no_panic!(lim.check_cost(-1.0)).is_err());
+    assert!(no_panic!(lim.check_cost(f64::NAN)).is_err());
+
+    clock.0.set(u64::MAX);
+    let clamped = no_panic!(lim.check_n_clamped(nonzero!(5u32))).unwrap();
+    assert_eq!(clamped.admitted(), nonzero!(2u32));
+}
@@ -0,0 +1,45 @@
+#![cfg(feature = "std")]
+
+use futures_executor::block_on;
+use futures_util::{Stream, StreamExt};
+use governor::{middleware::StateInformationMiddleware, Quota, RateLimiter};
+use nonzero_ext::*;
+use std::time::{Duration, Instant};
+
+#[test]
+fn permits_at_the_quota_pace() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)));
+    let mut permits = lim.permits();
+    let i = Instant::now();
+
+    // The burst comes through right away:
+    for _ in 0..10 {
+        block_on(permits.next());
+    }
+    assert!(i.elapsed() <= Duration::from_millis(100));
+
+    block_on(permits.next());
+    assert!(i.elapsed() > Duration::from_millis(100));
+    assert!(i.elapsed() <= Duration::from_millis(200));
+
+    // Other users of the limiter take capacity from the stream:
+    assert!(permits.limiter().check().is_err());
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(lim.check().is_ok());
+    block_on(permits.next());
+    assert!(i.elapsed() > Duration::from_millis(300));
+}
+
+#[test]
+fn permits_yield_positive_outcomes() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(3u32)))
+        .with_middleware::<StateInformationMiddleware>();
+    let remaining: Vec<u32> = block_on(
+        lim.permits()
+            .take(3)
+            .map(|snapshot| snapshot.remaining_burst_capacity())
+            .collect(),
+    );
+    assert_eq!(remaining, [2, 1, 0]);
+    assert_eq!(lim.permits().size_hint(), (usize::MAX, None));
+}
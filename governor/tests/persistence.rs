@@ -0,0 +1,227 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    state::keyed::{DefaultKeyedStateStore, HashMapStateStore, StateJournal},
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn per_day(max_burst: NonZeroU32) -> Quota {
+    Quota::with_period(Duration::from_secs(24 * 60 * 60) / max_burst.get())
+        .unwrap()
+        .allow_burst(max_burst)
+}
+
+/// A journal path that no other test uses, with no file at it yet.
+fn journal_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "governor-test-{}-{}.journal",
+        std::process::id(),
+        name
+    ));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn recovers_daily_quota_after_restart() {
+    let path = journal_path("daily");
+    let quota = per_day(nonzero!(2u32));
+
+    let journal = StateJournal::open(&path).unwrap();
+    assert_eq!(journal.recovered_len(), 0);
+    let lim = RateLimiter::persistent(quota, HashMapStateStore::default(), journal);
+    assert!(lim.check_key(&"alice").is_ok());
+    assert!(lim.check_key(&"alice").is_ok());
+    assert!(lim.check_key(&"alice").is_err());
+    assert!(lim.check_key(&"bob").is_ok());
+    lim.checkpoint().unwrap();
+    drop(lim);
+
+    let journal = StateJournal::open(&path).unwrap();
+    assert_eq!(journal.recovered_len(), 2);
+    let lim = RateLimiter::persistent(quota, HashMapStateStore::default(), journal);
+    assert_eq!(lim.len(), 0);
+    assert_eq!(lim.pending_recovered_len(), 2);
+
+    // Peeking doesn't apply the recovered state, but takes it into account:
+    assert!(lim.check_key_only(&"alice").is_err());
+    assert!(lim.check_key(&"alice").is_err());
+    assert!(lim.check_key(&"bob").is_ok());
+    assert!(lim.check_key(&"bob").is_err());
+    assert!(lim.check_key(&"carol").is_ok());
+    assert_eq!(lim.pending_recovered_len(), 1);
+
+    // Applied states get checkpointed from the wrapped store, so the next restart still
+    // remembers them:
+    lim.checkpoint().unwrap();
+    drop(lim);
+    let lim = RateLimiter::persistent(
+        quota,
+        DefaultKeyedStateStore::default(),
+        StateJournal::open(&path).unwrap(),
+    );
+    assert!(lim.check_key(&"alice").is_err());
+    assert!(lim.check_key(&"bob").is_err());
+    assert!(lim.check_key(&"carol").is_ok());
+    assert!(lim.check_key(&"carol").is_err());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn recovers_last_intact_checkpoint() {
+    let path = journal_path("torn");
+    let quota = per_day(nonzero!(1u32));
+
+    let lim = RateLimiter::persistent(
+        quota,
+        HashMapStateStore::default(),
+        StateJournal::open(&path).unwrap(),
+    );
+    assert!(lim.check_key(&"alice").is_ok());
+    lim.checkpoint().unwrap();
+    let intact = fs::metadata(&path).unwrap().len();
+    assert!(lim.check_key(&"bob").is_ok());
+    lim.checkpoint().unwrap();
+    drop(lim);
+
+    // Simulate a crash in the middle of writing the second checkpoint:
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(intact + 20).unwrap();
+    drop(file);
+
+    let journal = StateJournal::open(&path).unwrap();
+    assert_eq!(journal.recovered_len(), 1);
+    assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+    let lim = RateLimiter::persistent(quota, HashMapStateStore::default(), journal);
+    assert!(lim.check_key(&"alice").is_err());
+    assert!(lim.check_key(&"bob").is_ok());
+
+    // New checkpoints follow the intact ones:
+    lim.checkpoint().unwrap();
+    drop(lim);
+    let lim = RateLimiter::persistent(
+        quota,
+        HashMapStateStore::default(),
+        StateJournal::open(&path).unwrap(),
+    );
+    assert!(lim.check_key(&"alice").is_err());
+    assert!(lim.check_key(&"bob").is_err());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn ignores_corrupted_checkpoint() {
+    let path = journal_path("corrupted");
+    let quota = per_day(nonzero!(1u32));
+
+    let lim = RateLimiter::persistent(
+        quota,
+        HashMapStateStore::default(),
+        StateJournal::open(&path).unwrap(),
+    );
+    assert!(lim.check_key(&"alice").is_ok());
+    lim.checkpoint().unwrap();
+    let intact = fs::metadata(&path).unwrap().len();
+    assert!(lim.check_key(&"bob").is_ok());
+    lim.checkpoint().unwrap();
+    drop(lim);
+
+    // Flip a bit in the second checkpoint's entries:
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[intact as usize + 12] ^= 1;
+    fs::write(&path, bytes).unwrap();
+
+    let journal = StateJournal::open(&path).unwrap();
+    assert_eq!(journal.recovered_len(), 1);
+    let lim = RateLimiter::persistent(quota, HashMapStateStore::default(), journal);
+    assert!(lim.check_key(&"alice").is_err());
+    assert!(lim.check_key(&"bob").is_ok());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn compacts_journal() {
+    let path = journal_path("compaction");
+    let quota = per_day(nonzero!(1u32));
+
+    let journal = StateJournal::open(&path)
+        .unwrap()
+        .with_max_checkpoints(nonzero!(2usize));
+    let lim = RateLimiter::persistent(quota, HashMapStateStore::default(), journal);
+    assert!(lim.check_key(&"alice").is_ok());
+    lim.checkpoint().unwrap();
+    let one_checkpoint = fs::metadata(&path).unwrap().len();
+    lim.checkpoint().unwrap();
+    assert_eq!(
+        fs::metadata(&path).unwrap().len(),
+        2 * one_checkpoint - 12,
+        "the header is only written once"
+    );
+    for _ in 0..5 {
+        lim.checkpoint().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() <= 2 * one_checkpoint);
+    }
+    drop(lim);
+
+    let lim = RateLimiter::persistent(
+        quota,
+        HashMapStateStore::default(),
+        StateJournal::open(&path).unwrap(),
+    );
+    assert!(lim.check_key(&"alice").is_err());
+    assert!(lim.check_key(&"bob").is_ok());
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn leaves_out_fresh_states() {
+    let path = journal_path("fresh");
+    // States are fresh again after a nanosecond:
+    let quota = Quota::with_period(Duration::from_nanos(1)).unwrap();
+
+    let lim = RateLimiter::persistent(
+        quota,
+        HashMapStateStore::default(),
+        StateJournal::open(&path).unwrap(),
+    );
+    assert!(lim.check_key(&"alice").is_ok());
+    std::thread::sleep(Duration::from_millis(1));
+    lim.checkpoint().unwrap();
+    drop(lim);
+
+    assert_eq!(StateJournal::open(&path).unwrap().recovered_len(), 0);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn refuses_to_open_other_files() {
+    let path = journal_path("foreign");
+    fs::write(&path, b"these are not the states you're looking for").unwrap();
+
+    let err = StateJournal::open(&path).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert_eq!(
+        fs::read(&path).unwrap(),
+        b"these are not the states you're looking for"
+    );
+
+    // A journal whose header was only partially written is new:
+    let mut file = fs::File::create(&path).unwrap();
+    file.write_all(b"GOVST").unwrap();
+    drop(file);
+    assert_eq!(StateJournal::open(&path).unwrap().recovered_len(), 0);
+    assert_eq!(fs::metadata(&path).unwrap().len(), 12);
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,121 @@
+#![cfg(all(feature = "std", feature = "dashmap"))]
+
+use governor::{
+    clock::FakeRelativeClock,
+    middleware::NoOpMiddleware,
+    nanos::Nanos,
+    state::keyed::{DashMapStateStore, ProbationStateStore},
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::time::Duration;
+
+/// Hashes `u32` keys to themselves (and other keys to their last eight bytes), so that tests
+/// know which keys share a sighting counter.
+#[derive(Default)]
+struct IdentityHasher(u64);
+
+impl Hasher for IdentityHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 << 8) | u64::from(*byte);
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = n.into();
+    }
+}
+
+type IdentityProbationStore =
+    ProbationStateStore<DashMapStateStore<u32>, BuildHasherDefault<IdentityHasher>>;
+
+fn probation_limiter(
+    clock: &FakeRelativeClock,
+) -> RateLimiter<u32, IdentityProbationStore, FakeRelativeClock, NoOpMiddleware<Nanos>> {
+    let store = ProbationStateStore::with_hasher(
+        DashMapStateStore::default(),
+        nonzero!(3u8),
+        BuildHasherDefault::default(),
+    )
+    .with_slots(nonzero!(1024usize));
+    RateLimiter::new(Quota::per_second(nonzero!(1u32)), store, clock.clone())
+}
+
+#[test]
+fn sprayed_keys_get_no_state() {
+    let clock = FakeRelativeClock::default();
+    let lim = probation_limiter(&clock);
+    for key in 0..1024 {
+        assert!(lim.check_key(&key).is_ok());
+    }
+    assert!(lim.is_empty());
+    assert_eq!(lim.probation_decisions(), 1024);
+}
+
+#[test]
+fn colliding_keys_leave_probation_early() {
+    let clock = FakeRelativeClock::default();
+    let lim = probation_limiter(&clock);
+
+    // Keys 1, 1025 and 2049 share a sighting counter:
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1025).is_ok());
+    assert!(lim.check_key(&2049).is_ok());
+    assert!(lim.contains_key(&2049));
+    assert!(lim.check_key(&2049).is_err());
+    assert_eq!(lim.len(), 1);
+}
+
+#[test]
+fn keys_leave_probation_after_sightings() {
+    let clock = FakeRelativeClock::default();
+    let lim = probation_limiter(&clock);
+
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_ok());
+    assert!(!lim.contains_key(&1));
+
+    // The third sighting gets stored, and uses up the burst:
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.contains_key(&1));
+    assert!(lim.check_key(&1).is_err());
+    assert_eq!(lim.probation_decisions(), 2);
+
+    clock.advance(Duration::from_secs(1));
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_err());
+    assert_eq!(lim.probation_decisions(), 2);
+}
+
+#[test]
+fn retain_recent_resets_sightings() {
+    let clock = FakeRelativeClock::default();
+    let lim = probation_limiter(&clock);
+
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_ok());
+    lim.retain_recent();
+
+    assert!(lim.check_key(&1).is_ok());
+    assert!(!lim.contains_key(&1));
+    assert_eq!(lim.probation_decisions(), 3);
+}
+
+#[test]
+fn single_sighting_disables_probation() {
+    let clock = FakeRelativeClock::default();
+    let store = ProbationStateStore::new(DashMapStateStore::default(), nonzero!(1u8));
+    let lim: RateLimiter<u32, _, _, NoOpMiddleware<Nanos>> =
+        RateLimiter::new(Quota::per_second(nonzero!(1u32)), store, clock);
+
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&1).is_err());
+    assert_eq!(lim.len(), 1);
+    assert_eq!(lim.probation_decisions(), 0);
+}
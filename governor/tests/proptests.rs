@@ -79,3 +79,67 @@ fn accurate_not_until() {
         }
     });
 }
+
+#[cfg(feature = "proptest")]
+#[test]
+fn gcra_invariants_hold_for_builtin_stores() {
+    use governor::state::{
+        keyed::{FixedCapacityStateStore, HashMapStateStore},
+        InMemoryState, NotKeyed,
+    };
+    use governor::testing::{self, check_invariants};
+
+    proptest!(test_config(), |(quota in testing::quotas(), ops in testing::op_sequences(1..100))| {
+        check_invariants(quota, &InMemoryState::default(), &NotKeyed::NonKey, &ops)?;
+        check_invariants(quota, &HashMapStateStore::<u32>::default(), &1, &ops)?;
+        check_invariants(quota, &FixedCapacityStateStore::<u32>::with_capacity(4), &1, &ops)?;
+        #[cfg(feature = "dashmap")]
+        check_invariants(quota, &governor::state::keyed::DashMapStateStore::<u32>::default(), &1, &ops)?;
+    });
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn merged_usage_summaries_converge() {
+    use governor::{middleware::NoOpMiddleware, nanos::Nanos, state::keyed::HashMapStateStore};
+    use nonzero_ext::nonzero;
+
+    type Replica =
+        RateLimiter<u32, HashMapStateStore<u32>, FakeRelativeClock, NoOpMiddleware<Nanos>>;
+
+    fn sorted(mut summary: Vec<(u32, Nanos)>) -> Vec<(u32, Nanos)> {
+        summary.sort();
+        summary
+    }
+
+    // Each step checks a key on one of the replicas, then advances the clock by some ms:
+    let steps = prop::collection::vec((any::<bool>(), 0..8u32, 0..300u64), 1..100);
+    proptest!(test_config(), |(steps in steps)| {
+        let clock = FakeRelativeClock::default();
+        let quota = Quota::per_second(nonzero!(5u32));
+        let a: Replica = RateLimiter::hashmap_with_clock(quota, clock.clone());
+        let b: Replica = RateLimiter::hashmap_with_clock(quota, clock.clone());
+        for (on_a, key, advance) in steps {
+            let _ = if on_a { a.check_key(&key) } else { b.check_key(&key) };
+            clock.advance(Duration::from_millis(advance));
+        }
+
+        let (from_a, from_b) = (sorted(a.usage_summary()), sorted(b.usage_summary()));
+        a.merge_usage_summary(from_b.clone());
+        b.merge_usage_summary(from_a.clone());
+        let merged = sorted(a.usage_summary());
+        prop_assert_eq!(&merged, &sorted(b.usage_summary()));
+
+        // Each key has the later of the two states:
+        for (key, tat) in &merged {
+            let in_a = from_a.iter().find(|(k, _)| k == key).map(|(_, tat)| *tat);
+            let in_b = from_b.iter().find(|(k, _)| k == key).map(|(_, tat)| *tat);
+            prop_assert_eq!(Some(*tat), in_a.max(in_b));
+        }
+        prop_assert_eq!(merged.len(), from_a.iter().chain(&from_b).map(|(k, _)| k).collect::<std::collections::HashSet<_>>().len());
+
+        // Merging again doesn't change anything:
+        a.merge_usage_summary(merged.clone());
+        prop_assert_eq!(sorted(a.usage_summary()), merged);
+    });
+}
@@ -0,0 +1,134 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    clock::FakeRelativeClock,
+    ramp::{QuotaRamp, RampingRateLimiter},
+    InsufficientCapacity, Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::time::Duration;
+
+#[test]
+fn set_quota_keeps_debt_in_cells() {
+    let clock = FakeRelativeClock::default();
+    let mut lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone());
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(2u32)));
+
+    // Two cells of debt leave two of ten cells' capacity, replenishing at the new rate:
+    lim.set_quota(Quota::per_second(nonzero!(10u32)));
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(8u32)));
+    assert!(lim.check().is_err());
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(Ok(()), lim.check());
+
+    // Ten cells of debt exceed a burst size of two, and get paid off at one cell per second:
+    lim.set_quota(Quota::per_second(nonzero!(1u32)).allow_burst(nonzero!(2u32)));
+    clock.advance(Duration::from_secs(8));
+    assert!(lim.check().is_err());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(Ok(()), lim.check());
+}
+
+#[test]
+fn set_quota_with_recharge_delay() {
+    let clock = FakeRelativeClock::default();
+    let mut lim = RateLimiter::hashmap_with_clock(Quota::per_second(nonzero!(4u32)), clock.clone());
+    assert_eq!(Ok(Ok(())), lim.check_key_n(&1u32, nonzero!(4u32)));
+
+    lim.set_quota(
+        Quota::per_second(nonzero!(4u32)).with_burst_recharge_delay(Duration::from_secs(5)),
+    );
+    assert!(lim.check_key(&1u32).is_err());
+    assert_eq!(Ok(Ok(())), lim.check_key_n(&2u32, nonzero!(4u32)));
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(Ok(()), lim.check_key(&1u32));
+    assert!(lim.check_key(&1u32).is_err());
+
+    lim.set_quota(Quota::per_second(nonzero!(4u32)));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(Ok(Ok(())), lim.check_key_n(&1u32, nonzero!(4u32)));
+}
+
+#[test]
+fn interpolates_rates() {
+    let ramp = QuotaRamp::new(
+        Quota::per_second(nonzero!(1u32)),
+        Quota::per_second(nonzero!(3u32)),
+        Duration::from_secs(3),
+    )
+    .with_steps(nonzero!(2u32));
+    let schedule: Vec<_> = ramp
+        .schedule()
+        .map(|(start, quota)| (start, quota.replenish_interval(), quota.burst_size().get()))
+        .collect();
+    assert_eq!(
+        schedule,
+        vec![
+            (Duration::ZERO, Duration::from_secs(1), 1),
+            (Duration::from_millis(1500), Duration::from_millis(500), 2),
+            (Duration::from_secs(3), Duration::from_nanos(333_333_333), 3),
+        ]
+    );
+    assert_eq!(ramp.step_at(Duration::from_millis(1499)), 0);
+    assert_eq!(ramp.step_at(Duration::from_secs(100)), 2);
+}
+
+#[test]
+fn ramps_up_without_bursts() {
+    let clock = FakeRelativeClock::default();
+    let from = Quota::per_second(nonzero!(10u32));
+    let to = Quota::per_second(nonzero!(1000u32));
+    let lim = RampingRateLimiter::new(
+        RateLimiter::hashmap_with_clock(from, clock.clone()),
+        QuotaRamp::new(from, to, Duration::from_secs(10)),
+    );
+    assert_eq!(
+        lim.check_key_n(&1u32, nonzero!(11u32)),
+        Err(InsufficientCapacity(10))
+    );
+
+    // The idle key only gets the first step's burst capacity:
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(lim.quota().burst_size().get(), 109);
+    assert_eq!(
+        lim.check_key_n(&1u32, nonzero!(1000u32)),
+        Err(InsufficientCapacity(109))
+    );
+    assert_eq!(Ok(Ok(())), lim.check_key_n(&1u32, nonzero!(109u32)));
+    assert!(lim.check_key(&1u32).is_err());
+
+    clock.advance(Duration::from_secs(9));
+    assert!(lim.is_finished());
+    assert_eq!(Ok(Ok(())), lim.check_key_n(&1u32, nonzero!(1000u32)));
+    assert!(lim.check_key(&1u32).is_err());
+}
+
+#[test]
+fn into_inner_applies_current_step() {
+    let clock = FakeRelativeClock::default();
+    let from = Quota::per_second(nonzero!(1u32));
+    let to = Quota::per_second(nonzero!(5u32));
+    let lim = RampingRateLimiter::new(
+        RateLimiter::direct_with_clock(from, clock.clone()),
+        QuotaRamp::new(from, to, Duration::from_secs(10)),
+    );
+    assert_eq!(Ok(()), lim.check());
+    assert!(lim.check().is_err());
+
+    clock.advance(Duration::from_secs(20));
+    let lim = lim.into_inner();
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(5u32)));
+}
+
+#[test]
+fn zero_duration_ramp_switches_at_once() {
+    let from = Quota::per_second(nonzero!(1u32));
+    let to = Quota::per_second(nonzero!(5u32));
+    let lim = RampingRateLimiter::new(
+        RateLimiter::direct_with_clock(from, FakeRelativeClock::default()),
+        QuotaRamp::new(from, to, Duration::ZERO),
+    );
+    assert!(lim.is_finished());
+    assert_eq!(lim.quota(), to);
+    assert_eq!(Ok(Ok(())), lim.check_n(nonzero!(5u32)));
+}
@@ -0,0 +1,128 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    clock::FakeRelativeClock,
+    middleware::NoOpMiddleware,
+    nanos::Nanos,
+    registry::LimiterRegistry,
+    state::{keyed::HashMapStateStore, InMemoryState},
+    Quota,
+};
+use nonzero_ext::nonzero;
+use std::sync::Arc;
+use std::time::Duration;
+
+type DirectRegistry = LimiterRegistry<InMemoryState, FakeRelativeClock, NoOpMiddleware<Nanos>>;
+
+#[test]
+fn insert_and_get() {
+    let registry = DirectRegistry::with_clock(FakeRelativeClock::default());
+    assert!(registry.is_empty());
+    let login = registry.insert("login", Quota::per_minute(nonzero!(2u32)));
+    assert_eq!(registry.len(), 1);
+    assert_eq!(
+        registry.quota("login"),
+        Some(Quota::per_minute(nonzero!(2u32)))
+    );
+    assert!(Arc::ptr_eq(&login, &registry.get("login").unwrap()));
+    assert!(registry.get("search").is_none());
+
+    assert_eq!(Ok(()), login.check());
+    assert_eq!(Ok(()), registry.get("login").unwrap().check());
+    assert!(registry.get("login").unwrap().check().is_err());
+}
+
+#[test]
+fn reinserting_keeps_or_replaces() {
+    let registry = DirectRegistry::with_clock(FakeRelativeClock::default());
+    let quota = Quota::per_second(nonzero!(1u32));
+    let first = registry.insert("search", quota);
+    assert_eq!(Ok(()), first.check());
+
+    // The same quota keeps the rate limiter and its state:
+    let same = registry.insert("search", quota);
+    assert!(Arc::ptr_eq(&first, &same));
+    assert!(same.check().is_err());
+
+    // A different quota replaces it with a fresh one:
+    let replaced = registry.insert("search", Quota::per_second(nonzero!(2u32)));
+    assert!(!Arc::ptr_eq(&first, &replaced));
+    assert_eq!(Ok(()), replaced.check());
+    assert!(first.check().is_err());
+
+    assert!(registry.remove("search").is_some());
+    assert!(registry.get("search").is_none());
+}
+
+#[test]
+fn reload() {
+    let clock = FakeRelativeClock::default();
+    let registry = DirectRegistry::with_clock(clock.clone());
+    let login = registry.insert("login", Quota::per_minute(nonzero!(5u32)));
+    registry.insert("search", Quota::per_second(nonzero!(50u32)));
+    registry.insert("upload", Quota::per_hour(nonzero!(1u32)));
+
+    registry.reload(vec![
+        ("login", Quota::per_minute(nonzero!(5u32))),
+        ("search", Quota::per_second(nonzero!(100u32))),
+        ("export", Quota::per_hour(nonzero!(1u32))),
+    ]);
+    let snapshot = registry.snapshot();
+    let names: Vec<&str> = snapshot.iter().map(|(name, _, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["export", "login", "search"]);
+    assert_eq!(snapshot[2].1, Quota::per_second(nonzero!(100u32)));
+    assert!(Arc::ptr_eq(&login, &snapshot[1].2));
+
+    clock.advance(Duration::from_secs(1));
+    assert!(format!("{:?}", registry).contains(r#"["export", "login", "search"]"#));
+}
+
+#[test]
+fn keyed_limiters() {
+    let registry: LimiterRegistry<
+        HashMapStateStore<&str>,
+        FakeRelativeClock,
+        NoOpMiddleware<Nanos>,
+    > = LimiterRegistry::with_clock(FakeRelativeClock::default());
+    let api = registry.insert("api", Quota::per_second(nonzero!(1u32)));
+    assert_eq!(Ok(()), api.check_key(&"alice"));
+    assert_eq!(Ok(()), api.check_key(&"bob"));
+    assert!(registry.get("api").unwrap().check_key(&"alice").is_err());
+    assert_eq!(registry.snapshot()[0].2.len(), 2);
+}
+
+#[test]
+fn default_clock() {
+    let registry: LimiterRegistry = LimiterRegistry::default();
+    registry.insert("login", Quota::per_second(nonzero!(1u32)));
+    assert_eq!(Ok(()), registry.get("login").unwrap().check());
+}
+
+governor::static_rate_limiter!(STATIC_LOGIN: "2/minute");
+governor::static_rate_limiter!(STATIC_UNUSED: direct "5/second");
+governor::static_rate_limiter!(STATIC_OUTBOUND: direct "3/hour");
+
+#[test]
+fn static_limiters() {
+    use governor::registry::static_limiters;
+
+    assert_eq!(STATIC_LOGIN.name(), "STATIC_LOGIN");
+    assert_eq!(STATIC_LOGIN.quota(), Quota::per_minute(nonzero!(2u32)));
+    assert!(!static_limiters()
+        .iter()
+        .any(|(name, _)| *name == "STATIC_LOGIN"));
+
+    let alice = "alice".to_string();
+    assert!(STATIC_LOGIN.check_key(&alice).is_ok());
+    assert!(STATIC_LOGIN.check_key(&alice).is_ok());
+    assert!(STATIC_LOGIN.check_key(&alice).is_err());
+    assert!(STATIC_LOGIN.check_key(&"bob".to_string()).is_ok());
+    assert!(STATIC_OUTBOUND.check_n(nonzero!(3u32)).unwrap().is_ok());
+    assert!(STATIC_OUTBOUND.check().is_err());
+
+    let used = static_limiters();
+    assert!(used.contains(&("STATIC_LOGIN", Quota::per_minute(nonzero!(2u32)))));
+    assert!(used.contains(&("STATIC_OUTBOUND", Quota::per_hour(nonzero!(3u32)))));
+    assert!(!used.iter().any(|(name, _)| *name == "STATIC_UNUSED"));
+    assert_eq!(STATIC_UNUSED.quota(), Quota::per_second(nonzero!(5u32)));
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    clock::FakeRelativeClock,
+    middleware::NoOpMiddleware,
+    nanos::Nanos,
+    state::keyed::{HashMapStateStore, ReplicatingStateStore},
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+type Batches = Arc<Mutex<Vec<Vec<(u32, Nanos)>>>>;
+
+/// Returns a sink that appends each batch to `batches`.
+fn record_into(batches: &Batches) -> impl Fn(Vec<(u32, Nanos)>) {
+    let batches = Arc::clone(batches);
+    move |batch| batches.lock().unwrap().push(batch)
+}
+
+#[test]
+fn reports_positive_decisions_in_batches() {
+    let batches = Batches::default();
+    let store = ReplicatingStateStore::new(
+        HashMapStateStore::default(),
+        nonzero!(3usize),
+        record_into(&batches),
+    );
+    let clock = FakeRelativeClock::default();
+    let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> =
+        RateLimiter::new(Quota::per_second(nonzero!(2u32)), store, clock.clone());
+
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&2).is_ok());
+    assert!(batches.lock().unwrap().is_empty());
+    assert!(lim.check_key(&1).is_ok());
+    assert_eq!(
+        *batches.lock().unwrap(),
+        vec![vec![
+            (1, Nanos::from(500_000_000)),
+            (2, Nanos::from(500_000_000)),
+            (1, Nanos::from(1_000_000_000)),
+        ]]
+    );
+
+    // Negative decisions and peeks don't change any state:
+    assert!(lim.check_key(&1).is_err());
+    assert!(lim.check_key_only(&2).is_ok());
+    clock.advance(Duration::from_millis(500));
+    assert!(lim.check_key(&1).is_ok());
+    lim.into_state_store().flush();
+    assert_eq!(
+        batches.lock().unwrap()[1],
+        vec![(1, Nanos::from(1_500_000_000))]
+    );
+}
+
+#[test]
+fn flushes_on_drop() {
+    let batches = Batches::default();
+    let store = ReplicatingStateStore::new(
+        HashMapStateStore::default(),
+        nonzero!(10usize),
+        record_into(&batches),
+    );
+    let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+        Quota::per_second(nonzero!(1u32)),
+        store,
+        FakeRelativeClock::default(),
+    );
+    assert!(lim.check_key(&1).is_ok());
+    drop(lim);
+    assert_eq!(
+        *batches.lock().unwrap(),
+        vec![vec![(1, Nanos::from(1_000_000_000))]]
+    );
+}
+
+#[test]
+fn standby_follows_primary() {
+    let batches = Batches::default();
+    let store = ReplicatingStateStore::new(
+        HashMapStateStore::default(),
+        nonzero!(1usize),
+        record_into(&batches),
+    );
+    let clock = FakeRelativeClock::default();
+    let quota = Quota::per_second(nonzero!(3u32));
+    let primary: RateLimiter<_, _, _, NoOpMiddleware<_>> =
+        RateLimiter::new(quota, store, clock.clone());
+    let standby = RateLimiter::hashmap_with_clock(quota, clock);
+
+    for _ in 0..2 {
+        assert!(primary.check_key(&1).is_ok());
+    }
+    // The standby has been ahead for key 1 on its own; it keeps its state:
+    for _ in 0..3 {
+        assert!(standby.check_key(&1).is_ok());
+    }
+    assert!(primary.check_key(&2).is_ok());
+    for batch in batches.lock().unwrap().drain(..) {
+        standby.apply_replicated_states(batch);
+    }
+    assert!(standby.check_key(&1).is_err());
+    assert!(standby.check_key(&2).is_ok());
+    assert!(standby.check_key(&2).is_ok());
+    assert!(standby.check_key(&2).is_err());
+}
@@ -0,0 +1,93 @@
+#![cfg(feature = "std")]
+
+use governor::{clock::FakeRelativeClock, retry::RateLimitedBackoff, Quota, RateLimiter};
+use nonzero_ext::nonzero;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn backon_backoff<B: backon::BackoffBuilder>(builder: B) -> B::Backoff {
+    builder.build()
+}
+
+#[test]
+fn backon_policies_share_capacity() {
+    let clock = FakeRelativeClock::default();
+    let lim = Arc::new(RateLimiter::direct_with_clock(
+        Quota::per_second(nonzero!(4u32)),
+        clock.clone(),
+    ));
+    let mut first = backon_backoff(RateLimitedBackoff::new(Arc::clone(&lim)));
+    let mut second = backon_backoff(RateLimitedBackoff::new(Arc::clone(&lim)));
+    assert_eq!(first.next(), Some(Duration::ZERO));
+    assert_eq!(second.next(), Some(Duration::ZERO));
+    assert_eq!(first.next(), Some(Duration::ZERO));
+    assert_eq!(second.next(), Some(Duration::ZERO));
+    assert_eq!(first.next(), Some(Duration::from_millis(250)));
+    assert_eq!(second.next(), Some(Duration::from_millis(500)));
+
+    // Waiting retries hold on to their cells:
+    assert!(lim.check().is_err());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(first.next(), Some(Duration::ZERO));
+}
+
+#[test]
+fn gives_up_after_max_wait() {
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_minute(nonzero!(1u32)), clock.clone());
+    let mut policy = RateLimitedBackoff::new(&lim).with_max_wait(Duration::from_secs(30));
+    assert_eq!(policy.max_wait(), Duration::from_secs(30));
+    assert_eq!(policy.next(), Some(Duration::ZERO));
+    assert_eq!(policy.next(), None);
+
+    // Giving up doesn't use any capacity:
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(policy.next(), Some(Duration::from_secs(30)));
+}
+
+#[cfg(feature = "backoff")]
+#[test]
+fn backoff_retries_as_allowed() {
+    use std::cell::Cell;
+
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(3u32)), clock);
+    let attempts = Cell::new(0);
+    let result = backoff::retry(RateLimitedBackoff::new(&lim), || {
+        attempts.set(attempts.get() + 1);
+        if attempts.get() < 3 {
+            Err(backoff::Error::transient("not yet"))
+        } else {
+            Ok(attempts.get())
+        }
+    });
+    assert_eq!(result, Ok(3));
+    // Two retries took two cells:
+    assert_eq!(Ok(()), lim.check());
+    assert!(lim.check().is_err());
+
+    let result: Result<(), _> = backoff::retry(
+        RateLimitedBackoff::new(&lim).with_max_wait(Duration::ZERO),
+        || Err(backoff::Error::transient("never")),
+    );
+    assert!(matches!(result, Err(backoff::Error::Transient { .. })));
+}
+
+#[cfg(feature = "tryhard")]
+#[test]
+fn tryhard_policy() {
+    use tryhard::{backoff_strategies::BackoffStrategy, RetryPolicy};
+
+    let lim = RateLimiter::direct_with_clock(
+        Quota::per_second(nonzero!(2u32)),
+        FakeRelativeClock::default(),
+    );
+    let mut policy = RateLimitedBackoff::new(&lim).with_max_wait(Duration::from_millis(500));
+    let error = std::io::Error::other("failed");
+    let mut delay =
+        |attempt| BackoffStrategy::<std::io::Error>::delay(&mut policy, attempt, &error);
+    assert_eq!(delay(1), RetryPolicy::Delay(Duration::ZERO));
+    assert_eq!(delay(2), RetryPolicy::Delay(Duration::ZERO));
+    assert_eq!(delay(3), RetryPolicy::Delay(Duration::from_millis(500)));
+    assert_eq!(delay(4), RetryPolicy::Break);
+}
@@ -0,0 +1,69 @@
+use governor::{
+    clock::{Clock, FakeRelativeClock},
+    retry::RetryBudget,
+    Quota,
+};
+use nonzero_ext::nonzero;
+use std::time::Duration;
+
+#[test]
+fn reserve_allows_retries() {
+    let clock = FakeRelativeClock::default();
+    let reserve = Quota::per_second(nonzero!(2u32)).allow_burst(nonzero!(4u32));
+    let budget = RetryBudget::with_clock(reserve, 10, clock.clone());
+    assert_eq!(budget.balance(), 4);
+    for _ in 0..4 {
+        assert_eq!(Ok(()), budget.withdraw());
+    }
+    assert_eq!(budget.balance(), 0);
+    let denied = budget.withdraw().unwrap_err();
+    assert_eq!(
+        denied.wait_time_from(clock.now()),
+        Duration::from_millis(500)
+    );
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(budget.balance(), 2);
+}
+
+#[test]
+fn deposits_pay_for_retries() {
+    let clock = FakeRelativeClock::default();
+    let reserve = Quota::per_second(nonzero!(1u32));
+    let budget = RetryBudget::with_clock(reserve, 25, clock);
+    assert_eq!(Ok(()), budget.withdraw());
+    assert!(budget.withdraw().is_err());
+
+    for _ in 0..3 {
+        budget.deposit();
+        assert!(budget.withdraw().is_err());
+    }
+    budget.deposit();
+    assert_eq!(budget.balance(), 1);
+    assert_eq!(Ok(()), budget.withdraw());
+    assert!(budget.withdraw().is_err());
+}
+
+#[test]
+fn deposits_dont_exceed_reserve_burst() {
+    let clock = FakeRelativeClock::default();
+    let reserve = Quota::per_second(nonzero!(1u32)).allow_burst(nonzero!(3u32));
+    let budget = RetryBudget::with_clock(reserve, 100, clock);
+    for _ in 0..100 {
+        budget.deposit();
+    }
+    assert_eq!(budget.balance(), 3);
+    for _ in 0..3 {
+        assert_eq!(Ok(()), budget.withdraw());
+    }
+    assert!(budget.withdraw().is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn default_clock() {
+    let budget = RetryBudget::new(Quota::per_second(nonzero!(10u32)), 20);
+    assert_eq!(Ok(()), budget.withdraw());
+    budget.deposit();
+    assert!(format!("{:?}", budget).contains("RetryBudget"));
+}
@@ -0,0 +1,114 @@
+#![cfg(feature = "std")]
+
+use governor::{
+    clock::{Clock, FakeRelativeClock},
+    shadow::{Divergence, ShadowRateLimiter},
+    state::NotKeyed,
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::num::NonZeroU32;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A deterministic stream of pseudo-random numbers, for mixing up checks.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 33) % bound
+    }
+}
+
+fn quotas() -> Vec<Quota> {
+    vec![
+        Quota::per_second(nonzero!(1u32)),
+        Quota::per_second(nonzero!(5u32)),
+        Quota::per_second(nonzero!(3u32)).allow_burst(nonzero!(7u32)),
+        Quota::with_period(Duration::from_millis(333))
+            .unwrap()
+            .allow_burst(nonzero!(2u32)),
+        Quota::per_minute(nonzero!(100u32)),
+    ]
+}
+
+#[test]
+fn direct_agrees_with_reference() {
+    for quota in quotas() {
+        let clock = FakeRelativeClock::default();
+        let divergences = Mutex::new(vec![]);
+        let lim = ShadowRateLimiter::new(
+            RateLimiter::direct_with_clock(quota, clock.clone()),
+            |divergence| divergences.lock().unwrap().push(divergence),
+        );
+        let mut rng = Lcg(u64::from(quota.burst_size().get()));
+        let mut admitted = 0;
+        for _ in 0..5000 {
+            let n = NonZeroU32::new(rng.next(4) as u32 + 1).unwrap();
+            if matches!(lim.check_n(n), Ok(Ok(_))) {
+                admitted += 1;
+            }
+            if lim.check().is_ok() {
+                admitted += 1;
+            }
+            clock.advance(Duration::from_millis(rng.next(400)));
+        }
+        assert!(admitted > 0, "{:?}", quota);
+        assert_eq!(divergences.into_inner().unwrap(), vec![], "{:?}", quota);
+    }
+}
+
+#[test]
+fn keyed_agrees_with_reference() {
+    for quota in quotas() {
+        let clock = FakeRelativeClock::default();
+        let divergences = Mutex::new(vec![]);
+        let lim = ShadowRateLimiter::new(
+            RateLimiter::hashmap_with_clock(quota, clock.clone()),
+            |divergence| divergences.lock().unwrap().push(divergence),
+        );
+        let mut rng = Lcg(17);
+        for _ in 0..5000 {
+            let key = rng.next(5);
+            let n = NonZeroU32::new(rng.next(3) as u32 + 1).unwrap();
+            let _ = lim.check_key_n(&key, n);
+            let _ = lim.check_key(&key);
+            clock.advance(Duration::from_millis(rng.next(150)));
+        }
+        assert_eq!(divergences.into_inner().unwrap(), vec![], "{:?}", quota);
+    }
+}
+
+#[test]
+fn reports_divergences() {
+    let clock = FakeRelativeClock::default();
+    let divergences = Mutex::new(vec![]);
+    let lim = ShadowRateLimiter::new(
+        RateLimiter::direct_with_clock(
+            Quota::per_second(nonzero!(2u32)).with_cooldown(Duration::from_secs(10)),
+            clock.clone(),
+        ),
+        |divergence: Divergence<NotKeyed, _>| divergences.lock().unwrap().push(divergence),
+    );
+    let start = clock.now();
+    assert_eq!(Ok(()), lim.check());
+    assert_eq!(Ok(()), lim.check());
+    assert_ne!(Ok(()), lim.check());
+    assert!(divergences.lock().unwrap().is_empty());
+
+    // The reference implementation doesn't know about the cooldown:
+    clock.advance(Duration::from_millis(600));
+    assert_ne!(Ok(()), lim.check());
+    let divergences = divergences.into_inner().unwrap();
+    assert_eq!(divergences.len(), 1);
+    let divergence = &divergences[0];
+    assert_eq!(divergence.key(), &NotKeyed::NonKey);
+    assert_eq!(divergence.cells(), nonzero!(1u32));
+    assert!(!divergence.is_admitted());
+    assert_eq!(divergence.at(), start + Duration::from_millis(600));
+    assert_eq!(divergence.log(), &[(start, nonzero!(1u32))]);
+}
@@ -70,3 +70,86 @@ fn sink_with_jitter() {
     assert_eq!(result.len(), 12);
     assert!(result.into_iter().all(|elt| elt == ()));
 }
+
+#[test]
+fn owned_sink() {
+    let lim = Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(10u32))));
+    let mut sink = Vec::new().ratelimit_sink_owned(Arc::clone(&lim));
+    assert_eq!(sink.jitter(), &Default::default());
+    assert!(sink.limiter().check().is_ok());
+
+    let i = Instant::now();
+    let sink = std::thread::spawn(move || {
+        for n in 0..10 {
+            block_on(sink.send(n)).unwrap();
+        }
+        sink
+    })
+    .join()
+    .unwrap();
+    assert_eq!(sink.get_ref().len(), 10);
+    assert_range!((100..=200), i.elapsed().as_millis());
+}
+
+#[test]
+fn sinks_sharing_a_limiter() {
+    // Two cells at once, then one every 50ms:
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(20u32)).allow_burst(nonzero!(2u32)));
+    let mut left = Vec::new().ratelimit_sink(&lim);
+    let mut right = Vec::new().ratelimit_sink(&lim);
+    let i = Instant::now();
+
+    // Both combinators wait for the same cells; only one of them gets each:
+    block_on(futures_util::future::join(
+        async {
+            for n in 0..4u8 {
+                left.send(n).await.unwrap();
+            }
+        },
+        async {
+            for n in 0..4u8 {
+                right.send(n).await.unwrap();
+            }
+        },
+    ));
+    assert_ge!(i.elapsed(), Duration::from_millis(300));
+    assert_eq!(left.get_ref().len() + right.get_ref().len(), 8);
+}
+
+#[test]
+fn close_completes_pending_wait() {
+    use futures_util::task::noop_waker_ref;
+    use std::task::{Context, Poll};
+
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)).allow_burst(nonzero!(1u32)));
+    let mut sink = Vec::new().ratelimit_sink(&lim);
+    let i = Instant::now();
+    block_on(sink.send(1u8)).unwrap();
+
+    // Start waiting for the next item's admission, then close instead:
+    let mut cx = Context::from_waker(noop_waker_ref());
+    assert!(matches!(sink.poll_ready_unpin(&mut cx), Poll::Pending));
+    block_on(sink.close()).unwrap();
+    assert_range!((100..=200), i.elapsed().as_millis());
+    assert_eq!(sink.get_ref(), &[1]);
+
+    // Closing without a pending wait doesn't wait:
+    let i = Instant::now();
+    block_on(sink.close()).unwrap();
+    assert_lt!(i.elapsed(), Duration::from_millis(50));
+}
+
+#[test]
+fn start_send_without_poll_ready() {
+    let lim = RateLimiter::direct(Quota::per_hour(nonzero!(1u32)));
+    let mut sink = Vec::new().ratelimit_sink(&lim);
+
+    // Misusing the sink passes the item on, and counts it against the rate limiter:
+    sink.start_send_unpin(1u8).unwrap();
+    assert_eq!(sink.get_ref(), &[1]);
+    assert!(lim.check().is_err());
+
+    // Even once the quota is used up:
+    sink.start_send_unpin(2u8).unwrap();
+    assert_eq!(sink.get_ref(), &[1, 2]);
+}
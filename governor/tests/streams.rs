@@ -26,3 +26,46 @@ fn stream() {
     assert!(i.elapsed() > Duration::from_millis(200));
     assert!(i.elapsed() <= Duration::from_millis(300));
 }
+
+#[test]
+fn owned_stream() {
+    let lim = Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(10u32))));
+    let mut stream = stream::repeat(()).ratelimit_stream_owned(Arc::clone(&lim));
+    stream.set_jitter(Default::default());
+    assert!(stream.limiter().check().is_ok());
+
+    // The combinator doesn't borrow anything, so it can move to another thread:
+    let i = Instant::now();
+    let taken = std::thread::spawn(move || block_on(stream.take(10).count()))
+        .join()
+        .unwrap();
+    assert_eq!(taken, 10);
+    assert!(i.elapsed() > Duration::from_millis(100));
+    assert!(i.elapsed() <= Duration::from_millis(200));
+}
+
+#[test]
+fn streams_sharing_a_limiter() {
+    // Two cells at once, then one every 50ms:
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(20u32)).allow_burst(nonzero!(2u32)));
+    let left = stream::repeat("left").ratelimit_stream(&lim);
+    let right = stream::repeat("right").ratelimit_stream(&lim);
+    let i = Instant::now();
+
+    // Both combinators wait for the same cells; only one of them gets each:
+    let times: Vec<_> = block_on(
+        stream::select(left, right)
+            .map(|_| i.elapsed())
+            .take(8)
+            .collect(),
+    );
+    assert!(times[1] <= Duration::from_millis(50));
+    for window in times.windows(3) {
+        assert!(
+            window[2] - window[0] >= Duration::from_millis(45),
+            "times: {:?}",
+            times
+        );
+    }
+    assert!(i.elapsed() >= Duration::from_millis(300));
+}
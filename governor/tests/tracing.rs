@@ -0,0 +1,189 @@
+#![cfg(all(feature = "std", feature = "tracing"))]
+
+use governor::{
+    clock::{Clock, FakeRelativeClock},
+    middleware::{KeyFormat, NoOpMiddleware, StateInformationMiddleware, TracingMiddleware},
+    nanos::Nanos,
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{
+    field::{Empty, Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+/// A subscriber that remembers the last value recorded for each span field, treating the last
+/// span created as the current one.
+#[derive(Clone, Default)]
+struct FieldRecorder {
+    fields: Arc<Mutex<HashMap<String, String>>>,
+    current: Arc<Mutex<Option<&'static Metadata<'static>>>>,
+}
+
+impl FieldRecorder {
+    fn get(&self, name: &str) -> Option<String> {
+        self.fields.lock().unwrap().get(name).cloned()
+    }
+}
+
+impl Visit for FieldRecorder {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.fields
+            .lock()
+            .unwrap()
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+impl Subscriber for FieldRecorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        span.record(&mut self.clone());
+        *self.current.lock().unwrap() = Some(span.metadata());
+        span::Id::from_u64(1)
+    }
+
+    fn current_span(&self) -> tracing_core::span::Current {
+        match *self.current.lock().unwrap() {
+            Some(metadata) => tracing_core::span::Current::new(span::Id::from_u64(1), metadata),
+            None => tracing_core::span::Current::none(),
+        }
+    }
+
+    fn record(&self, _span: &span::Id, values: &span::Record<'_>) {
+        values.record(&mut self.clone());
+    }
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, _event: &Event<'_>) {}
+
+    fn enter(&self, _span: &span::Id) {}
+
+    fn exit(&self, _span: &span::Id) {}
+}
+
+#[test]
+fn records_decisions_on_current_span() {
+    let recorder = FieldRecorder::default();
+    let _default = tracing::subscriber::set_default(recorder.clone());
+
+    let clock = FakeRelativeClock::default();
+    let lim = RateLimiter::direct_with_clock(Quota::per_second(nonzero!(2u32)), clock.clone())
+        .with_middleware::<TracingMiddleware<StateInformationMiddleware>>();
+    let span = tracing::info_span!(
+        "request",
+        governor.allowed = Empty,
+        governor.remaining_burst_capacity = Empty,
+        governor.wait_time_ms = Empty,
+    );
+    let _entered = span.enter();
+
+    // The inner middleware's outcome is passed through:
+    assert_eq!(lim.check().unwrap().remaining_burst_capacity(), 1);
+    assert_eq!(recorder.get("governor.allowed").as_deref(), Some("true"));
+    assert_eq!(
+        recorder.get("governor.remaining_burst_capacity").as_deref(),
+        Some("1")
+    );
+    assert_eq!(recorder.get("governor.wait_time_ms").as_deref(), Some("0"));
+
+    assert!(lim.check().is_ok());
+    assert_eq!(
+        recorder.get("governor.remaining_burst_capacity").as_deref(),
+        Some("0")
+    );
+    assert_eq!(
+        recorder.get("governor.wait_time_ms").as_deref(),
+        Some("500")
+    );
+
+    clock.advance(Duration::from_millis(100));
+    let denied = lim.check().unwrap_err();
+    assert_eq!(
+        denied.wait_time_from(clock.now()),
+        Duration::from_millis(400)
+    );
+    assert_eq!(recorder.get("governor.allowed").as_deref(), Some("false"));
+    assert_eq!(
+        recorder.get("governor.remaining_burst_capacity").as_deref(),
+        Some("0")
+    );
+    assert_eq!(
+        recorder.get("governor.wait_time_ms").as_deref(),
+        Some("400")
+    );
+}
+
+#[test]
+fn skips_spans_without_fields() {
+    let recorder = FieldRecorder::default();
+    let _default = tracing::subscriber::set_default(recorder.clone());
+
+    let lim = RateLimiter::hashmap_with_clock(
+        Quota::per_second(nonzero!(1u32)),
+        FakeRelativeClock::default(),
+    )
+    .with_middleware::<TracingMiddleware<NoOpMiddleware<Nanos>>>();
+    let span = tracing::info_span!("request");
+    let _entered = span.enter();
+    assert_eq!(Ok(()), lim.check_key(&"alice"));
+    assert!(lim.check_key(&"alice").is_err());
+    assert_eq!(recorder.get("governor.allowed"), None);
+}
+
+#[test]
+fn records_redacted_keys() {
+    let recorder = FieldRecorder::default();
+    let _default = tracing::subscriber::set_default(recorder.clone());
+
+    let quota = Quota::per_second(nonzero!(1u32));
+    let span = tracing::info_span!("request", governor.key = Empty);
+    let _entered = span.enter();
+
+    // Keys are hashed by default:
+    let lim = RateLimiter::hashmap_with_clock(quota, FakeRelativeClock::default())
+        .with_middleware::<TracingMiddleware<NoOpMiddleware<Nanos>>>();
+    assert_eq!(Ok(()), lim.check_key(&"alice@example.com"));
+    let hashed = recorder.get("governor.key").unwrap();
+    assert!(hashed.starts_with('#'), "{:?}", hashed);
+    assert!(!hashed.contains("alice"), "{:?}", hashed);
+    assert!(lim.check_key(&"alice@example.com").is_err());
+    assert_eq!(recorder.get("governor.key"), Some(hashed.clone()));
+    assert_eq!(Ok(()), lim.check_key(&"bob@example.com"));
+    assert_ne!(recorder.get("governor.key"), Some(hashed));
+
+    let lim = RateLimiter::hashmap_with_clock(quota, FakeRelativeClock::default())
+        .with_key_format(KeyFormat::masked())
+        .with_middleware::<TracingMiddleware<NoOpMiddleware<Nanos>>>();
+    assert_eq!(Ok(()), lim.check_key(&"alice@example.com"));
+    assert_eq!(recorder.get("governor.key").as_deref(), Some("alic***"));
+
+    let lim = RateLimiter::hashmap_with_clock(quota, FakeRelativeClock::default())
+        .with_key_format(KeyFormat::full())
+        .with_middleware::<TracingMiddleware<NoOpMiddleware<Nanos>>>();
+    assert!(lim.check_key(&"alice@example.com").is_ok());
+    assert!(lim.check_key(&"alice@example.com").is_err());
+    assert_eq!(
+        recorder.get("governor.key").as_deref(),
+        Some("alice@example.com")
+    );
+
+    // Direct rate limiters have no key to show, unless they are given a format:
+    let lim = RateLimiter::direct_with_clock(quota, FakeRelativeClock::default())
+        .with_middleware::<TracingMiddleware<NoOpMiddleware<Nanos>>>();
+    assert_eq!(Ok(()), lim.check());
+    assert_eq!(recorder.get("governor.key").as_deref(), Some("[redacted]"));
+    let lim = RateLimiter::direct_with_clock(quota, FakeRelativeClock::default())
+        .with_key_format(KeyFormat::custom(|_, f| f.write_str("uploads")))
+        .with_middleware::<TracingMiddleware<NoOpMiddleware<Nanos>>>();
+    assert_eq!(Ok(()), lim.check());
+    assert_eq!(recorder.get("governor.key").as_deref(), Some("uploads"));
+}
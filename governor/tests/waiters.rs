@@ -0,0 +1,71 @@
+#![cfg(feature = "std")]
+
+use futures_executor::block_on;
+use futures_util::{future, FutureExt};
+use governor::{
+    clock::DefaultClock,
+    middleware::NoOpMiddleware,
+    state::{keyed::HashMapStateStore, InMemoryState, WaiterCountingStateStore},
+    Quota, RateLimiter,
+};
+use nonzero_ext::nonzero;
+use std::time::Duration;
+
+#[test]
+fn counts_direct_waiters() {
+    let store = WaiterCountingStateStore::new(InMemoryState::default());
+    let lim: RateLimiter<_, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+        Quota::per_second(nonzero!(20u32)),
+        store,
+        DefaultClock::default(),
+    );
+
+    // Cells that are allowed through right away don't wait:
+    block_on(lim.until_n_ready(nonzero!(19u32))).unwrap();
+    assert_eq!(lim.pending_waiters(), 0);
+
+    let mut first = lim.until_n_ready(nonzero!(2u32)).boxed_local();
+    let mut second = lim.until_ready().boxed_local();
+    assert!(block_on(future::poll_immediate(&mut first)).is_none());
+    assert_eq!(lim.pending_waiters(), 1);
+    assert!(block_on(future::poll_immediate(&mut second)).is_some());
+    assert_eq!(lim.pending_waiters(), 1);
+
+    // Waiting tasks stop being counted once they are done:
+    block_on(first).unwrap();
+    assert_eq!(lim.pending_waiters(), 0);
+}
+
+#[test]
+fn counts_waiters_per_key() {
+    let store = WaiterCountingStateStore::new(HashMapStateStore::default());
+    let lim: RateLimiter<u32, _, _, NoOpMiddleware<_>> = RateLimiter::new(
+        Quota::per_second(nonzero!(1u32)),
+        store,
+        DefaultClock::default(),
+    );
+    assert!(lim.check_key(&1).is_ok());
+    assert!(lim.check_key(&2).is_ok());
+
+    let mut waiting: Vec<_> = [1, 1, 2]
+        .iter()
+        .map(|key| lim.until_key_ready(key).boxed_local())
+        .collect();
+    for w in waiting.iter_mut() {
+        assert!(block_on(future::poll_immediate(w)).is_none());
+    }
+    let mut ready = lim
+        .until_key_ready_or_reject_if_wait_exceeds(&3, Duration::from_secs(1))
+        .boxed_local();
+    assert!(block_on(future::poll_immediate(&mut ready)).is_some());
+    assert_eq!(lim.pending_waiters(), 3);
+    assert_eq!(lim.pending_waiters_for_key(&1), 2);
+    assert_eq!(lim.pending_waiters_for_key(&2), 1);
+    assert_eq!(lim.pending_waiters_for_key(&3), 0);
+
+    // Tasks that give up waiting aren't counted anymore:
+    waiting.truncate(1);
+    assert_eq!(lim.pending_waiters(), 1);
+    assert_eq!(lim.pending_waiters_for_key(&1), 1);
+    assert_eq!(lim.pending_waiters_for_key(&2), 0);
+}
@@ -0,0 +1,137 @@
+#![cfg(all(feature = "wasm", target_arch = "wasm32"))]
+
+//! The asynchronous tests from `future.rs`, `streams.rs` and `sinks.rs`, in a browser.
+//!
+//! Run them with `wasm-pack test --headless --firefox -- --features wasm`.
+
+use all_asserts::*;
+use futures_util::{sink::SinkExt, stream, StreamExt};
+use governor::{
+    clock::{Clock, WebClock},
+    prelude::*,
+    Quota, RateLimiter,
+};
+use nonzero_ext::*;
+use std::sync::Arc;
+use std::time::Duration;
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[wasm_bindgen_test]
+async fn pauses() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)));
+
+    // exhaust the limiter:
+    loop {
+        if lim.check().is_err() {
+            break;
+        }
+    }
+    let i = WebClock.now();
+    lim.until_ready().await;
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+}
+
+#[wasm_bindgen_test]
+async fn pauses_n() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)));
+
+    for _ in 0..6 {
+        lim.check().unwrap();
+    }
+    let i = WebClock.now();
+    lim.until_n_ready(nonzero!(5u32)).await.unwrap();
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+}
+
+#[wasm_bindgen_test]
+async fn pauses_n_clamped() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)));
+
+    lim.check().unwrap();
+    let i = WebClock.now();
+    let clamped = lim.until_n_ready_clamped(nonzero!(15u32)).await;
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+    assert_eq!(clamped.admitted().get(), 10);
+}
+
+#[wasm_bindgen_test]
+async fn pauses_at_least_n() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)));
+
+    for _ in 0..9 {
+        lim.check().unwrap();
+    }
+    let i = WebClock.now();
+    let clamped = lim
+        .until_at_least_n_ready(nonzero!(2u32), nonzero!(5u32))
+        .await
+        .unwrap();
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+    assert_ge!(clamped.admitted().get(), 2);
+}
+
+#[wasm_bindgen_test]
+async fn pauses_keyed() {
+    let lim = RateLimiter::keyed(Quota::per_second(nonzero!(10u32)));
+
+    // exhaust the limiter:
+    loop {
+        if lim.check_key(&1u32).is_err() {
+            break;
+        }
+    }
+    let i = WebClock.now();
+    lim.until_key_ready(&1u32).await;
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+}
+
+#[wasm_bindgen_test]
+async fn proceeds() {
+    let lim = RateLimiter::direct(Quota::per_second(nonzero!(10u32)));
+    let i = WebClock.now();
+    lim.until_ready().await;
+    assert_lt!(i.elapsed(), Duration::from_millis(100));
+}
+
+#[wasm_bindgen_test]
+async fn stream() {
+    let lim = Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(10u32))));
+    let mut stream = stream::repeat(()).ratelimit_stream(&lim);
+    let i = WebClock.now();
+
+    for _ in 0..10 {
+        stream.next().await;
+    }
+    assert_le!(i.elapsed(), Duration::from_millis(100));
+
+    stream.next().await;
+    assert_gt!(i.elapsed(), Duration::from_millis(100));
+
+    stream.next().await;
+    assert_gt!(i.elapsed(), Duration::from_millis(200));
+}
+
+#[wasm_bindgen_test]
+#[allow(clippy::unit_cmp)]
+async fn sink() {
+    let lim = Arc::new(RateLimiter::direct(Quota::per_second(nonzero!(10u32))));
+    let mut sink = Vec::new().ratelimit_sink(&lim);
+    let i = WebClock.now();
+
+    for _ in 0..10 {
+        sink.send(()).await.unwrap();
+    }
+    assert_lt!(i.elapsed(), Duration::from_millis(100));
+
+    sink.send(()).await.unwrap();
+    assert_ge!(i.elapsed(), Duration::from_millis(100));
+
+    sink.send(()).await.unwrap();
+    assert_ge!(i.elapsed(), Duration::from_millis(200));
+
+    let result = sink.get_ref();
+    assert_eq!(result.len(), 12);
+    assert!(result.iter().all(|&elt| elt == ()));
+}